@@ -0,0 +1,165 @@
+//! How well the bitrate math predicted the output size, for tuning the
+//! overhead and margin defaults (`--verbose`).
+
+use crate::sizefmt::SizeFormat;
+
+/// The size predicted for an encode next to the size it came out at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Prediction {
+    /// Audio and video payload from bitrate times duration.
+    pub payload_bytes: u64,
+    /// The allowance set aside for container overhead.
+    pub overhead_bytes: u64,
+    pub actual_bytes: u64,
+}
+
+impl Prediction {
+    pub fn predicted_bytes(&self) -> u64 {
+        self.payload_bytes + self.overhead_bytes
+    }
+
+    /// How far the actual size is off the prediction, in percent; positive
+    /// when the output came out larger.
+    pub fn error_percent(&self) -> f64 {
+        let predicted = self.predicted_bytes();
+        if predicted == 0 {
+            return 0.0;
+        }
+        (self.actual_bytes as f64 - predicted as f64) / predicted as f64 * 100.0
+    }
+
+    /// Adds up the predictions of the parts of a split output.
+    pub fn combine(self, other: Prediction) -> Prediction {
+        Prediction {
+            payload_bytes: self.payload_bytes + other.payload_bytes,
+            overhead_bytes: self.overhead_bytes + other.overhead_bytes,
+            actual_bytes: self.actual_bytes + other.actual_bytes,
+        }
+    }
+
+    /// One line for the verbose output.
+    pub fn describe(&self, sizes: SizeFormat) -> String {
+        format!(
+            "predicted {} (payload {} + overhead {}), actual {} ({:+.1}%)",
+            sizes.size(self.predicted_bytes()),
+            sizes.size(self.payload_bytes),
+            sizes.size(self.overhead_bytes),
+            sizes.size(self.actual_bytes),
+            self.error_percent()
+        )
+    }
+}
+
+/// The spread of prediction errors over several encodes, in percent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorStats {
+    pub count: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// The error furthest from zero, with its sign.
+    pub max: f64,
+}
+
+impl ErrorStats {
+    /// `None` without any errors to summarize.
+    pub fn of(errors: &[f64]) -> Option<Self> {
+        if errors.is_empty() {
+            return None;
+        }
+        let mut sorted = errors.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let n = sorted.len();
+        let median = if n % 2 == 1 {
+            sorted[n / 2]
+        } else {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+        };
+        let max = sorted
+            .iter()
+            .copied()
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or_default();
+        Some(Self {
+            count: n,
+            mean: sorted.iter().sum::<f64>() / n as f64,
+            median,
+            max,
+        })
+    }
+}
+
+impl std::fmt::Display for ErrorStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "mean {:+.1}%, median {:+.1}%, max {:+.1}% over {} file{}",
+            self.mean,
+            self.median,
+            self.max,
+            self.count,
+            if self.count == 1 { "" } else { "s" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::size::SizeUnits;
+
+    #[test]
+    fn test_error_percent_is_relative_to_prediction() {
+        let p = Prediction {
+            payload_bytes: 9_500_000,
+            overhead_bytes: 500_000,
+            actual_bytes: 10_200_000,
+        };
+        assert_eq!(p.predicted_bytes(), 10_000_000);
+        assert!((p.error_percent() - 2.0).abs() < 1e-9);
+        let under = Prediction {
+            actual_bytes: 9_000_000,
+            ..p
+        };
+        assert!((under.error_percent() + 10.0).abs() < 1e-9);
+        assert_eq!(Prediction::default().error_percent(), 0.0);
+        assert_eq!(
+            p.describe(SizeUnits::Si.into()),
+            "predicted 10 MB (payload 9.5 MB + overhead 0.5 MB), actual 10.2 MB (+2.0%)"
+        );
+    }
+
+    #[test]
+    fn test_parts_combine() {
+        let part = Prediction {
+            payload_bytes: 100,
+            overhead_bytes: 10,
+            actual_bytes: 99,
+        };
+        assert_eq!(
+            part.combine(part),
+            Prediction {
+                payload_bytes: 200,
+                overhead_bytes: 20,
+                actual_bytes: 198,
+            }
+        );
+    }
+
+    #[test]
+    fn test_error_stats() {
+        assert_eq!(ErrorStats::of(&[]), None);
+        let stats = ErrorStats::of(&[2.0, -6.0, 1.0]).unwrap();
+        assert_eq!(stats.count, 3);
+        assert!((stats.mean + 1.0).abs() < 1e-9);
+        assert_eq!(stats.median, 1.0);
+        assert_eq!(stats.max, -6.0);
+
+        let stats = ErrorStats::of(&[4.0, 1.0, 3.0, 2.0]).unwrap();
+        assert_eq!(stats.median, 2.5);
+        assert_eq!(stats.max, 4.0);
+        assert_eq!(
+            stats.to_string(),
+            "mean +2.5%, median +2.5%, max +4.0% over 4 files"
+        );
+    }
+}
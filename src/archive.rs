@@ -0,0 +1,281 @@
+//! `--archive-layout` and `--archive-tz`: filing outputs by when they were
+//! recorded.
+//!
+//! The recording date is the container's `creation_time`, which cameras
+//! and phones stamp when they start recording. Many files have none, or
+//! one from a camera whose clock was never set (1904 and 1970 are the zero
+//! dates of MP4 and Unix), so a date that can't be right gives way to the
+//! input's modification time. Dates are taken in UTC unless `--archive-tz
+//! local` asks for this machine's time zone, which moves a recording made
+//! shortly after midnight to the day its owner remembers.
+
+use crate::history;
+use clap::ValueEnum;
+use std::path::PathBuf;
+
+/// How `--archive-layout` arranges the outputs under the output directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// `YYYY/MM/` of the recording date.
+    Date,
+}
+
+impl Layout {
+    /// The directories under the output directory for a file recorded on
+    /// `date`.
+    pub fn dir(self, date: (i64, u32, u32)) -> PathBuf {
+        let (year, month, _) = date;
+        match self {
+            Layout::Date => PathBuf::from(format!("{:04}", year)).join(format!("{:02}", month)),
+        }
+    }
+}
+
+/// The time zone dates are taken in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Zone {
+    #[default]
+    Utc,
+    /// This machine's time zone, with its daylight saving time.
+    Local,
+}
+
+impl Zone {
+    /// The (year, month, day) of seconds since the Unix epoch.
+    pub fn date(self, secs: u64) -> (i64, u32, u32) {
+        let offset = match self {
+            Zone::Utc => 0,
+            Zone::Local => local_offset(secs),
+        };
+        history::utc_date(secs.saturating_add_signed(offset))
+    }
+}
+
+/// Recordings dated before this (1980-01-01) are taken for a clock
+/// that was never set.
+const EARLIEST: u64 = 315_532_800;
+
+/// How far past `now` a recording may be dated, for clocks a little fast
+/// and time zones written as UTC.
+const LATEST_AHEAD: u64 = 86_400;
+
+/// When the input was recorded, in seconds since the Unix epoch: its
+/// `creation_time` when that parses and falls between 1980 and `now`,
+/// otherwise `modified`.
+pub fn recorded(creation_time: Option<&str>, modified: u64, now: u64) -> u64 {
+    creation_time
+        .and_then(parse_creation_time)
+        .filter(|&secs| (EARLIEST..=now + LATEST_AHEAD).contains(&secs))
+        .unwrap_or(modified)
+}
+
+/// Parses a `creation_time` as ffprobe prints it, such as
+/// `2024-05-07T12:34:56.000000Z`, into seconds since the Unix epoch. The
+/// date and time may be separated by a space and the zone written as
+/// `+02:00` or `+0200`; a time without a zone is UTC, as ffmpeg takes it.
+/// `None` for anything else and for times before the epoch.
+pub fn parse_creation_time(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (date, time) = text.split_once(['T', ' '])?;
+    let mut date = date.splitn(3, '-');
+    let year: i64 = number(date.next()?, 4)?;
+    let month: u32 = number(date.next()?, 2)?;
+    let day: u32 = number(date.next()?, 2)?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let zone_at = time.find(['Z', 'z', '+', '-']).unwrap_or(time.len());
+    let (clock, zone) = time.split_at(zone_at);
+    let clock = clock.split_once('.').map_or(clock, |(whole, fraction)| {
+        if fraction.bytes().all(|b| b.is_ascii_digit()) {
+            whole
+        } else {
+            ""
+        }
+    });
+    let mut clock = clock.splitn(3, ':');
+    let hour: i64 = number(clock.next()?, 2)?;
+    let minute: i64 = number(clock.next()?, 2)?;
+    let second: i64 = number(clock.next()?, 2)?;
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let offset = match zone {
+        "" | "Z" | "z" => 0,
+        _ => {
+            let sign = if zone.starts_with('-') { -1 } else { 1 };
+            let digits = zone[1..].replace(':', "");
+            if digits.len() != 4 {
+                return None;
+            }
+            let hours: i64 = number(&digits[..2], 2)?;
+            let minutes: i64 = number(&digits[2..], 2)?;
+            if hours > 14 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+    let secs =
+        days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    u64::try_from(secs).ok()
+}
+
+/// `text` as a number of exactly `digits` digits.
+fn number<N: std::str::FromStr>(text: &str, digits: usize) -> Option<N> {
+    (text.len() == digits && text.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| text.parse().ok())
+        .flatten()
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since the Unix epoch of a date, the inverse of
+/// [`history::utc_date`].
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Seconds this machine's time zone is ahead of UTC at `secs`.
+#[cfg(unix)]
+// `tm_gmtoff` is a C long, 32 bits on some targets.
+#[allow(clippy::useless_conversion)]
+fn local_offset(secs: u64) -> i64 {
+    let time = secs as libc::time_t;
+    let mut tm = std::mem::MaybeUninit::<libc::tm>::zeroed();
+    // SAFETY: localtime_r reads the time given and only writes the struct
+    // it is given, which is zeroed and of the right type.
+    if unsafe { libc::localtime_r(&time, tm.as_mut_ptr()) }.is_null() {
+        return 0;
+    }
+    // SAFETY: the call succeeded, so it filled the struct in.
+    i64::from(unsafe { tm.assume_init() }.tm_gmtoff)
+}
+
+/// Elsewhere there is no time zone to ask for, so local time is UTC.
+#[cfg(not(unix))]
+fn local_offset(_secs: u64) -> i64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-06-01T00:00:00Z, the "now" of these tests.
+    const NOW: u64 = 1_717_200_000;
+    /// 2023-03-10T09:00:00Z, the modification time of these tests.
+    const MODIFIED: u64 = 1_678_438_800;
+
+    #[test]
+    fn test_creation_times_parse_in_the_forms_ffprobe_prints() {
+        let cases = [
+            ("2024-05-07T12:34:56.000000Z", Some(1_715_085_296)),
+            ("2024-05-07T12:34:56Z", Some(1_715_085_296)),
+            ("2024-05-07 12:34:56", Some(1_715_085_296)),
+            ("2024-05-07T14:34:56+02:00", Some(1_715_085_296)),
+            ("2024-05-07T07:04:56-0530", Some(1_715_085_296)),
+            ("2024-02-29T00:00:00Z", Some(1_709_164_800)),
+            ("1970-01-01T00:00:00.000000Z", Some(0)),
+            ("1904-01-01T00:00:00.000000Z", None),
+            ("2023-02-29T00:00:00Z", None),
+            ("2024-13-01T00:00:00Z", None),
+            ("2024-05-07T24:00:00Z", None),
+            ("2024-05-07T12:34:56+2", None),
+            ("2024-05-07T12:34:56.abcZ", None),
+            ("2024-5-7T12:34:56Z", None),
+            ("2024-05-07", None),
+            ("yesterday", None),
+            ("", None),
+        ];
+        for (text, secs) in cases {
+            assert_eq!(parse_creation_time(text), secs, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_a_missing_or_bogus_creation_time_falls_back_to_the_mtime() {
+        let cases = [
+            (Some("2024-05-07T12:34:56.000000Z"), 1_715_085_296),
+            (None, MODIFIED),
+            (Some("not a date"), MODIFIED),
+            // The zero dates of MP4 and of Unix: a clock that was never set.
+            (Some("1904-01-01T00:00:00.000000Z"), MODIFIED),
+            (Some("1970-01-01T00:00:00.000000Z"), MODIFIED),
+            // A year ahead of now.
+            (Some("2025-06-01T00:00:00Z"), MODIFIED),
+            // An hour ahead is a fast clock, and kept.
+            (Some("2024-06-01T01:00:00Z"), NOW + 3600),
+        ];
+        for (creation_time, secs) in cases {
+            assert_eq!(
+                recorded(creation_time, MODIFIED, NOW),
+                secs,
+                "{:?}",
+                creation_time
+            );
+        }
+    }
+
+    #[test]
+    fn test_recording_dates_of_probed_files() {
+        let probe = |format: &str| {
+            let output = format!(
+                r#"{{"streams": [{{"width": 1920, "height": 1080}}], "format": {}}}"#,
+                format
+            );
+            let info = crate::probe::parse_video_info(&output).unwrap();
+            Zone::Utc.date(recorded(info.creation_time.as_deref(), MODIFIED, NOW))
+        };
+        // An iPhone MOV, with its zone in the tag.
+        assert_eq!(
+            probe(r#"{"tags": {"creation_time": "2024-05-31T23:30:00-0700"}}"#),
+            (2024, 6, 1)
+        );
+        // A GoPro whose clock was reset with its battery.
+        assert_eq!(
+            probe(r#"{"tags": {"creation_time": "1904-01-01T00:00:00.000000Z"}}"#),
+            (2023, 3, 10)
+        );
+        // A screen recording with no tags at all.
+        assert_eq!(probe("{}"), (2023, 3, 10));
+        assert_eq!(
+            probe(r#"{"tags": {"creation_time": "0000-00-00 00:00:00"}}"#),
+            (2023, 3, 10)
+        );
+    }
+
+    #[test]
+    fn test_days_from_civil_inverts_utc_date() {
+        for days in (0..800_000).step_by(997) {
+            let (year, month, day) = history::utc_date(86_400 * days + 1);
+            assert_eq!(days_from_civil(year, month, day), days as i64);
+        }
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+    }
+
+    #[test]
+    fn test_date_layout_files_by_year_and_month() {
+        let date = Zone::Utc.date(1_715_085_296);
+        assert_eq!(date, (2024, 5, 7));
+        assert_eq!(Layout::Date.dir(date), PathBuf::from("2024").join("05"));
+        // Just before midnight UTC on New Year's Eve.
+        let date = Zone::Utc.date(1_704_067_199);
+        assert_eq!(Layout::Date.dir(date), PathBuf::from("2023").join("12"));
+    }
+}
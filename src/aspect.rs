@@ -0,0 +1,120 @@
+//! Non-square pixels, as on DVDs and in some broadcast captures.
+//!
+//! A 720x480 DVD frame is shown at 16:9 or 4:3 depending on its sample
+//! aspect ratio (SAR). Scaling such a frame as if its pixels were square
+//! squashes the picture, so every size limit is applied to the square-pixel
+//! size the frame is displayed at.
+
+use std::fmt;
+
+/// An aspect ratio as ffprobe reports it, e.g. `32:27`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ratio {
+    pub num: u32,
+    pub den: u32,
+}
+
+impl Ratio {
+    pub const SQUARE: Ratio = Ratio { num: 1, den: 1 };
+
+    /// Parses `num:den` (or `num/den`); `None` for `0:1`, `N/A` and
+    /// anything else that leaves the ratio unknown.
+    pub fn parse(text: &str) -> Option<Ratio> {
+        let (num, den) = text.split_once([':', '/'])?;
+        let ratio = Ratio {
+            num: num.trim().parse().ok()?,
+            den: den.trim().parse().ok()?,
+        };
+        (ratio.num > 0 && ratio.den > 0).then(|| ratio.reduced())
+    }
+
+    pub fn is_square(self) -> bool {
+        self.num == self.den
+    }
+
+    fn reduced(self) -> Ratio {
+        reduce(self.num as u64, self.den as u64)
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.num, self.den)
+    }
+}
+
+fn reduce(num: u64, den: u64) -> Ratio {
+    let divisor = gcd(num, den);
+    Ratio {
+        num: (num / divisor) as u32,
+        den: (den / divisor) as u32,
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a.max(1)
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The size `width`x`height` is displayed at with square pixels: the width
+/// stretched (or narrowed) by `sar` and rounded to even, the height kept.
+pub fn square_pixel_size(width: u32, height: u32, sar: Ratio) -> (u32, u32) {
+    if sar.is_square() {
+        return (width, height);
+    }
+    let display = width as f64 * sar.num as f64 / sar.den as f64;
+    (((display / 2.0).round() * 2.0) as u32, height)
+}
+
+/// The display aspect ratio of `width`x`height` with `sar`, reduced.
+pub fn display_aspect(width: u32, height: u32, sar: Ratio) -> Ratio {
+    reduce(
+        width as u64 * sar.num as u64,
+        height as u64 * sar.den as u64,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Ratio::parse("32:27"), Some(Ratio { num: 32, den: 27 }));
+        assert_eq!(Ratio::parse("64/45"), Some(Ratio { num: 64, den: 45 }));
+        assert_eq!(Ratio::parse("2:2"), Some(Ratio::SQUARE));
+        assert_eq!(Ratio::parse("0:1"), None);
+        assert_eq!(Ratio::parse("N/A"), None);
+        assert_eq!(Ratio::parse(""), None);
+    }
+
+    #[test]
+    fn test_dvd_frames_display_at_their_aspect_ratio() {
+        // (frame, SAR) -> (square-pixel size, DAR)
+        let cases = [
+            ((720, 480), (32, 27), (854, 480), (16, 9)),
+            ((720, 480), (8, 9), (640, 480), (4, 3)),
+            ((720, 576), (64, 45), (1024, 576), (16, 9)),
+            ((720, 576), (16, 15), (768, 576), (4, 3)),
+            // HDV: 1440x1080 shown as 1920x1080.
+            ((1440, 1080), (4, 3), (1920, 1080), (16, 9)),
+            ((1920, 1080), (1, 1), (1920, 1080), (16, 9)),
+        ];
+        for ((width, height), (num, den), square, (dar_num, dar_den)) in cases {
+            let sar = Ratio { num, den };
+            assert_eq!(square_pixel_size(width, height, sar), square, "{}", sar);
+            assert_eq!(
+                display_aspect(width, height, sar),
+                Ratio {
+                    num: dar_num,
+                    den: dar_den
+                },
+                "{}",
+                sar
+            );
+        }
+    }
+}
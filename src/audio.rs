@@ -0,0 +1,500 @@
+//! Which audio tracks are kept, and how much of the size budget each gets.
+
+use crate::probe::{AudioStream, VideoInfo};
+use std::fmt;
+
+/// AAC bitrate per channel of a kept track.
+pub const AUDIO_BITRATE_PER_CHANNEL: u64 = 64_000;
+
+/// Highest bitrate given to a single track, however many channels it has.
+pub const MAX_TRACK_BITRATE: u64 = 256_000;
+
+/// Channels assumed when ffprobe doesn't say.
+const DEFAULT_CHANNELS: u32 = 2;
+
+/// The audio format an output container is written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioCodec {
+    Aac,
+    Opus,
+    Mp3,
+}
+
+/// An ffmpeg encoder for an [`AudioCodec`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioEncoder {
+    /// The name for `-c:a`, as listed by `ffmpeg -encoders`.
+    pub name: &'static str,
+    /// Needs `-strict experimental`, as ffmpeg's own Opus encoder does.
+    pub experimental: bool,
+}
+
+impl AudioEncoder {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            experimental: false,
+        }
+    }
+
+    /// `-c:a` and any flags the encoder needs to be used at all.
+    pub fn args(self) -> Vec<String> {
+        let mut args = vec!["-c:a".to_string(), self.name.to_string()];
+        if self.experimental {
+            args.extend(["-strict".to_string(), "experimental".to_string()]);
+        }
+        args
+    }
+}
+
+impl fmt::Display for AudioEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl AudioCodec {
+    /// The encoders for the codec, best first: the external libraries
+    /// sound better at the same bitrate than ffmpeg's own encoders.
+    pub fn encoders(self) -> &'static [AudioEncoder] {
+        const AAC: &[AudioEncoder] = &[AudioEncoder::new("libfdk_aac"), AudioEncoder::new("aac")];
+        const OPUS: &[AudioEncoder] = &[
+            AudioEncoder::new("libopus"),
+            AudioEncoder {
+                name: "opus",
+                experimental: true,
+            },
+        ];
+        const MP3: &[AudioEncoder] = &[AudioEncoder::new("libmp3lame")];
+        match self {
+            AudioCodec::Aac => AAC,
+            AudioCodec::Opus => OPUS,
+            AudioCodec::Mp3 => MP3,
+        }
+    }
+
+    /// How to get an ffmpeg that has an encoder for the codec.
+    fn install_hint(self) -> &'static str {
+        match self {
+            AudioCodec::Aac => {
+                "every regular ffmpeg build has aac; install one from your package manager or ffmpeg.org instead of a minimal build"
+            }
+            AudioCodec::Opus => {
+                "install an ffmpeg built with --enable-libopus, as most distribution packages are"
+            }
+            AudioCodec::Mp3 => {
+                "install an ffmpeg built with --enable-libmp3lame, or write a .mp4 instead"
+            }
+        }
+    }
+}
+
+impl fmt::Display for AudioCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AudioCodec::Aac => "AAC",
+            AudioCodec::Opus => "Opus",
+            AudioCodec::Mp3 => "MP3",
+        })
+    }
+}
+
+/// The best encoder for `codec` among the `available` ones (what `ffmpeg
+/// -encoders` lists), or why there is none.
+pub fn select_encoder(codec: AudioCodec, available: &[String]) -> Result<AudioEncoder, String> {
+    let candidates = codec.encoders();
+    candidates
+        .iter()
+        .find(|encoder| available.iter().any(|name| name == encoder.name))
+        .copied()
+        .ok_or_else(|| {
+            let names: Vec<&str> = candidates.iter().map(|e| e.name).collect();
+            format!(
+                "this ffmpeg has no {} encoder (looked for {}); {}",
+                codec,
+                names.join(", "),
+                codec.install_hint()
+            )
+        })
+}
+
+/// Which of the input's audio tracks to keep (`--audio-track`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioSelection {
+    /// One track, counting from 1.
+    Track(u32),
+    All,
+}
+
+impl Default for AudioSelection {
+    fn default() -> Self {
+        AudioSelection::Track(1)
+    }
+}
+
+/// Parses `--audio-track`: a track number from 1, or `all`.
+pub fn parse_audio_selection(text: &str) -> Result<AudioSelection, String> {
+    if text.eq_ignore_ascii_case("all") {
+        return Ok(AudioSelection::All);
+    }
+    match text.parse::<u32>() {
+        Ok(n) if n >= 1 => Ok(AudioSelection::Track(n)),
+        _ => Err(format!(
+            "invalid audio track '{}': expected a track number from 1, or 'all'",
+            text
+        )),
+    }
+}
+
+/// One audio track that goes into the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeptTrack {
+    /// Position among the input's audio streams, from 0 (for `-map 0:a:N`).
+    pub input_position: u32,
+    /// AAC bitrate in bits per second.
+    pub bitrate: u64,
+    /// Channel count, assumed stereo when ffprobe doesn't say.
+    pub channels: u32,
+    /// The input stream's language tag; `und` counts as none.
+    pub language: Option<String>,
+    /// Whether players should pick this track first.
+    pub default: bool,
+}
+
+impl KeptTrack {
+    /// The tags a track carries, e.g. `eng, default`, or `no tags`.
+    pub fn describe_tags(&self) -> String {
+        describe_tags(self.language.as_deref(), self.default)
+    }
+}
+
+/// How the tags of a track read in verbose output.
+pub fn describe_tags(language: Option<&str>, default: bool) -> String {
+    let mut tags: Vec<&str> = language.into_iter().collect();
+    if default {
+        tags.push("default");
+    }
+    if tags.is_empty() {
+        "no tags".to_string()
+    } else {
+        tags.join(", ")
+    }
+}
+
+/// The language of a probed stream, leaving out empty and `und` tags.
+pub fn stream_language(stream: &AudioStream) -> Option<&str> {
+    stream
+        .tags
+        .language
+        .as_deref()
+        .filter(|language| !language.is_empty() && *language != "und")
+}
+
+/// Bitrate for a track with `channels` channels: 64k mono, 128k stereo, up
+/// to 256k for surround.
+pub fn track_bitrate(channels: Option<u32>) -> u64 {
+    (channel_count(channels) as u64 * AUDIO_BITRATE_PER_CHANNEL).min(MAX_TRACK_BITRATE)
+}
+
+fn channel_count(channels: Option<u32>) -> u32 {
+    channels.filter(|&c| c > 0).unwrap_or(DEFAULT_CHANNELS)
+}
+
+/// The tracks `selection` keeps from the probed streams.
+///
+/// Unprobed streams are assumed to be a single stereo track. A selected
+/// track the input doesn't have keeps nothing; see [`check_selection`].
+///
+/// Each track keeps its language and default flag. When none of the kept
+/// tracks was the default, as with `--audio-track 2`, the first becomes it.
+pub fn kept_tracks(info: &VideoInfo, selection: AudioSelection) -> Vec<KeptTrack> {
+    let assumed = [AudioStream::default()];
+    let streams = info.audio_streams.as_deref().unwrap_or(&assumed);
+    let kept = |(position, stream): (usize, &AudioStream)| KeptTrack {
+        input_position: position as u32,
+        bitrate: track_bitrate(stream.channels),
+        channels: channel_count(stream.channels),
+        language: stream_language(stream).map(str::to_string),
+        default: stream.disposition.default != 0,
+    };
+    let mut tracks: Vec<KeptTrack> = match selection {
+        AudioSelection::Track(n) => streams
+            .iter()
+            .enumerate()
+            .skip(n.saturating_sub(1) as usize)
+            .take(1)
+            .map(kept)
+            .collect(),
+        AudioSelection::All => streams.iter().enumerate().map(kept).collect(),
+    };
+    if !tracks.iter().any(|t| t.default) {
+        if let Some(first) = tracks.first_mut() {
+            first.default = true;
+        }
+    }
+    tracks
+}
+
+/// The language and default flag of each kept track, numbered as in the
+/// output. Every track gets a disposition, so that one the input marked
+/// as default but that now comes second is no longer picked first.
+pub fn stream_args(tracks: &[KeptTrack]) -> Vec<String> {
+    let mut args = Vec::new();
+    for (i, track) in tracks.iter().enumerate() {
+        if let Some(language) = &track.language {
+            args.extend([
+                format!("-metadata:s:a:{}", i),
+                format!("language={}", language),
+            ]);
+        }
+        args.extend([
+            format!("-disposition:a:{}", i),
+            if track.default { "default" } else { "0" }.to_string(),
+        ]);
+    }
+    args
+}
+
+/// Rejects a track number beyond the probed streams. Track 1 (the
+/// default) is fine on an input without audio, which then simply has none.
+pub fn check_selection(info: &VideoInfo, selection: AudioSelection) -> Result<(), String> {
+    let (AudioSelection::Track(n), Some(streams)) = (selection, &info.audio_streams) else {
+        return Ok(());
+    };
+    if n as usize > streams.len().max(1) {
+        return Err(format!(
+            "--audio-track {}: the input has {} audio track{}",
+            n,
+            streams.len(),
+            if streams.len() == 1 { "" } else { "s" }
+        ));
+    }
+    Ok(())
+}
+
+/// Total bitrate of the kept tracks.
+pub fn total_bitrate(tracks: &[KeptTrack]) -> u64 {
+    tracks.iter().map(|t| t.bitrate).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_streams(channels: &[Option<u32>]) -> VideoInfo {
+        VideoInfo {
+            audio_streams: Some(
+                channels
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &channels)| AudioStream {
+                        index: i as u32 + 1,
+                        codec_name: Some("pcm_s16le".into()),
+                        channels,
+                        ..AudioStream::default()
+                    })
+                    .collect(),
+            ),
+            ..VideoInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_track_bitrate_follows_channels() {
+        assert_eq!(track_bitrate(Some(1)), 64_000);
+        assert_eq!(track_bitrate(Some(2)), 128_000);
+        assert_eq!(track_bitrate(None), 128_000);
+        assert_eq!(track_bitrate(Some(0)), 128_000);
+        assert_eq!(track_bitrate(Some(6)), MAX_TRACK_BITRATE);
+    }
+
+    #[test]
+    fn test_multi_track_budget() {
+        let dual = with_streams(&[Some(2), Some(1)]);
+        let all = kept_tracks(&dual, AudioSelection::All);
+        assert_eq!(all.len(), 2);
+        assert_eq!(total_bitrate(&all), 192_000);
+        assert_eq!(all[1].input_position, 1);
+
+        let second = kept_tracks(&dual, AudioSelection::Track(2));
+        assert_eq!(
+            second,
+            vec![KeptTrack {
+                input_position: 1,
+                bitrate: 64_000,
+                channels: 1,
+                language: None,
+                default: true,
+            }]
+        );
+        assert_eq!(
+            total_bitrate(&kept_tracks(&dual, AudioSelection::default())),
+            128_000
+        );
+
+        let surround = with_streams(&[Some(6), Some(2), Some(2)]);
+        assert_eq!(
+            total_bitrate(&kept_tracks(&surround, AudioSelection::All)),
+            512_000
+        );
+    }
+
+    #[test]
+    fn test_missing_or_unprobed_audio() {
+        let silent = with_streams(&[]);
+        assert!(kept_tracks(&silent, AudioSelection::All).is_empty());
+        assert!(kept_tracks(&silent, AudioSelection::default()).is_empty());
+        // Not probed at all: one stereo track, as before track selection.
+        let unknown = VideoInfo::default();
+        assert_eq!(
+            total_bitrate(&kept_tracks(&unknown, AudioSelection::All)),
+            128_000
+        );
+    }
+
+    #[test]
+    fn test_selection_is_checked_against_probe() {
+        let dual = with_streams(&[Some(2), Some(2)]);
+        assert!(check_selection(&dual, AudioSelection::Track(2)).is_ok());
+        let err = check_selection(&dual, AudioSelection::Track(3)).unwrap_err();
+        assert_eq!(err, "--audio-track 3: the input has 2 audio tracks");
+        assert!(check_selection(&VideoInfo::default(), AudioSelection::Track(3)).is_ok());
+        let silent = with_streams(&[]);
+        assert!(check_selection(&silent, AudioSelection::default()).is_ok());
+        assert!(check_selection(&silent, AudioSelection::Track(2)).is_err());
+    }
+
+    fn tagged(tags: &[(Option<&str>, bool)]) -> VideoInfo {
+        let mut info = with_streams(&vec![Some(2); tags.len()]);
+        for (stream, &(language, default)) in
+            info.audio_streams.as_mut().unwrap().iter_mut().zip(tags)
+        {
+            stream.tags.language = language.map(str::to_string);
+            stream.disposition.default = default.into();
+        }
+        info
+    }
+
+    #[test]
+    fn test_tracks_keep_their_language_and_default() {
+        let info = tagged(&[
+            (Some("eng"), true),
+            (Some("und"), false),
+            (Some("jpn"), false),
+        ]);
+        let all = kept_tracks(&info, AudioSelection::All);
+        let tags: Vec<String> = all.iter().map(KeptTrack::describe_tags).collect();
+        assert_eq!(tags, ["eng, default", "no tags", "jpn"]);
+        assert_eq!(
+            stream_args(&all),
+            [
+                "-metadata:s:a:0",
+                "language=eng",
+                "-disposition:a:0",
+                "default",
+                "-disposition:a:1",
+                "0",
+                "-metadata:s:a:2",
+                "language=jpn",
+                "-disposition:a:2",
+                "0",
+            ]
+        );
+
+        // A lone track that wasn't the default becomes it, numbered 0.
+        let third = kept_tracks(&info, AudioSelection::Track(3));
+        assert_eq!(third[0].input_position, 2);
+        assert_eq!(
+            stream_args(&third),
+            [
+                "-metadata:s:a:0",
+                "language=jpn",
+                "-disposition:a:0",
+                "default"
+            ]
+        );
+
+        // The input's default stays the only one.
+        let info = tagged(&[(Some("eng"), false), (Some("fra"), true)]);
+        let all = kept_tracks(&info, AudioSelection::All);
+        assert_eq!(
+            all.iter().map(|t| t.default).collect::<Vec<_>>(),
+            [false, true]
+        );
+    }
+
+    /// What `ffmpeg -encoders` lists for audio on some common builds.
+    const BUILDS: &[(&str, &[&str])] = &[
+        (
+            "distribution package",
+            &[
+                "aac",
+                "libmp3lame",
+                "libopus",
+                "libvorbis",
+                "opus",
+                "vorbis",
+            ],
+        ),
+        ("static build", &["aac", "libmp3lame", "libopus", "opus"]),
+        ("nonfree build", &["aac", "libfdk_aac", "libopus", "opus"]),
+        ("without libopus", &["aac", "libmp3lame", "opus"]),
+        ("minimal", &["aac"]),
+        ("decoding only", &[]),
+    ];
+
+    fn select(codec: AudioCodec, build: &str) -> Result<AudioEncoder, String> {
+        let (_, encoders) = BUILDS.iter().find(|(name, _)| *name == build).unwrap();
+        let available: Vec<String> = encoders.iter().map(|s| s.to_string()).collect();
+        select_encoder(codec, &available)
+    }
+
+    #[test]
+    fn test_best_available_audio_encoder_is_picked() {
+        // (codec, build, encoder or None)
+        let cases = [
+            (AudioCodec::Aac, "distribution package", Some("aac")),
+            (AudioCodec::Aac, "nonfree build", Some("libfdk_aac")),
+            (AudioCodec::Aac, "minimal", Some("aac")),
+            (AudioCodec::Aac, "decoding only", None),
+            (AudioCodec::Opus, "static build", Some("libopus")),
+            (AudioCodec::Opus, "without libopus", Some("opus")),
+            (AudioCodec::Opus, "minimal", None),
+            (AudioCodec::Mp3, "distribution package", Some("libmp3lame")),
+            (AudioCodec::Mp3, "nonfree build", None),
+        ];
+        for (codec, build, expected) in cases {
+            let picked = select(codec, build);
+            assert_eq!(
+                picked.as_ref().ok().map(|e| e.name),
+                expected,
+                "{} on {}: {:?}",
+                codec,
+                build,
+                picked
+            );
+        }
+    }
+
+    #[test]
+    fn test_native_opus_is_marked_experimental() {
+        let opus = select(AudioCodec::Opus, "without libopus").unwrap();
+        assert_eq!(opus.args(), ["-c:a", "opus", "-strict", "experimental"]);
+        let libopus = select(AudioCodec::Opus, "static build").unwrap();
+        assert_eq!(libopus.args(), ["-c:a", "libopus"]);
+
+        let err = select(AudioCodec::Opus, "minimal").unwrap_err();
+        assert_eq!(
+            err,
+            "this ffmpeg has no Opus encoder (looked for libopus, opus); install an ffmpeg built with --enable-libopus, as most distribution packages are"
+        );
+    }
+
+    #[test]
+    fn test_parse_audio_selection() {
+        assert_eq!(parse_audio_selection("all"), Ok(AudioSelection::All));
+        assert_eq!(parse_audio_selection("2"), Ok(AudioSelection::Track(2)));
+        assert!(parse_audio_selection("0").is_err());
+        assert!(parse_audio_selection("two").is_err());
+    }
+}
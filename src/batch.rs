@@ -0,0 +1,1643 @@
+//! `mdviqure batch`: reduce several files with the same options, or the
+//! jobs of a [manifest](crate::manifest) with their own, then print a
+//! summary table.
+//!
+//! Up to `--jobs` files are reduced at once, each on a thread of its own
+//! that sends its events and outcome back. Everything else is done on the
+//! batch's thread as each file is started or over: its share of
+//! `--max-total-size` (a running file counts with all it may take), the
+//! saved state, the history, and the warnings and report events.
+
+use crate::accuracy::ErrorStats;
+use crate::archive::{self, Layout, Zone};
+use crate::checksum::Checksums;
+use crate::console::Console;
+use crate::error::ReduceError;
+use crate::events::{self, Event, Report};
+use crate::filename::{self, NameRules};
+use crate::filetarget::{self, TargetFrom};
+use crate::history::{self, Entry, Fingerprint, History};
+use crate::hls;
+use crate::notify::Notice;
+use crate::outdir;
+use crate::presenter::{Align, Column, Presenter, Style, Table};
+use crate::probecache::ProbeCache;
+use crate::reduce::{
+    part_output_path, reduce_video, sample_output_path, ReduceOptions, ReduceReport,
+};
+use crate::resume::{BatchState, Status};
+use crate::size::SizeUnits;
+use crate::sizefmt::SizeFormat;
+use crate::tempdir::RunTempDir;
+use crate::template::{Field, Template, Values};
+use crate::tool::VideoTool;
+use crate::usage::Usage;
+use crate::warning::{self, Warning};
+use crate::STDIO_PATH;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// How one file of a batch went.
+#[derive(Debug)]
+pub enum Outcome {
+    /// Reduced in this run, writing this many bytes.
+    Reduced(u64),
+    /// Skipped: an earlier run (per the history, or the interrupted run
+    /// being resumed) already wrote these bytes.
+    AlreadyDone(u64),
+    Failed(ReduceError),
+    /// Not started because it could have taken the batch past
+    /// `--max-total-size`.
+    OverBudget,
+}
+
+impl Outcome {
+    /// The output bytes on disk, reduced now or before.
+    pub fn bytes(&self) -> Option<u64> {
+        match self {
+            Outcome::Reduced(bytes) | Outcome::AlreadyDone(bytes) => Some(*bytes),
+            Outcome::Failed(_) | Outcome::OverBudget => None,
+        }
+    }
+}
+
+/// One file of a batch.
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// What the progress lines and the summary call it: the input, or the
+    /// name of its manifest entry.
+    pub label: String,
+    pub input: String,
+    pub output: String,
+    pub opts: ReduceOptions,
+    /// Why the job fails without being started, found while the batch
+    /// was put together.
+    pub invalid: Option<String>,
+}
+
+/// Batch settings beyond the per-file [`ReduceOptions`].
+#[derive(Debug, Clone, Default)]
+pub struct BatchOptions {
+    /// The history to consult and record into.
+    pub history: Option<History>,
+    /// Record the files without their overhead samples (`--no-learn`).
+    pub no_samples: bool,
+    /// Reduce every file, even those the history or a saved batch state
+    /// shows as done.
+    pub redo: bool,
+    /// State file to resume from. Without one, the state a batch keeps in
+    /// its output directory is resumed when it is for the same invocation.
+    pub resume: Option<PathBuf>,
+    /// Cap on the bytes all outputs may add up to (`--max-total-size`).
+    pub max_total_bytes: Option<u64>,
+    /// Rather than stopping at the cap, lower each file's target so the
+    /// files left share what remains of it (`--fit-remaining`).
+    pub fit_remaining: bool,
+    /// Output file names (`--name-template`); without one each output
+    /// keeps its input's file name.
+    pub name_template: Option<Template>,
+    /// What the output file names have to keep to.
+    pub names: NameRules,
+    /// Directories under the output directory to file each output in by
+    /// its recording date (`--archive-layout`).
+    pub archive_layout: Option<Layout>,
+    /// The time zone of the dates in names and the layout (`--archive-tz`).
+    pub archive_tz: Zone,
+    /// Where each file's target is read (`--target-from`).
+    pub target_from: TargetFrom,
+    /// How many files are reduced at once (`--jobs`); none counts as one.
+    pub jobs: u32,
+}
+
+/// A file of the batch being reduced.
+struct Running {
+    /// What it counts with against `--max-total-size` until it is over.
+    reserved: u64,
+    fingerprint: Option<Fingerprint>,
+    /// The options it was started with.
+    opts: ReduceOptions,
+}
+
+/// What the thread reducing a file tells the batch.
+enum Message {
+    /// A line of the file's events, for the batch's sink.
+    Event(Vec<u8>),
+    /// The file at this index is over, with the warnings recorded if it
+    /// failed.
+    Finished(usize, Result<Box<ReduceReport>, ReduceError>, Vec<Warning>),
+    /// Its thread panicked, for the batch to pass on.
+    Panicked(Box<dyn Any + Send>),
+}
+
+/// The event sink of a file's thread: each line goes to the batch's.
+struct Relay(Sender<Message>);
+
+impl Write for Relay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The batch only stops listening once every file is over.
+        let _ = self.0.send(Message::Event(buf.to_vec()));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reduces each of `inputs` into `output_dir` under its own file name (or
+/// the rendered name template; the directory may have placeholders too),
+/// skipping the ones already done for this target: finished by the
+/// interrupted run being resumed, or recorded in the history. Each file
+/// has the target of `opts` unless `batch` reads its own; see
+/// [`crate::filetarget`].
+///
+/// Progress is saved after every file. A failed file doesn't stop the
+/// batch, but Ctrl-C does, and so does reaching `max_total_bytes` unless
+/// the remaining files are fitted into it. The error of the first failure
+/// is returned after the summary, so the exit code reflects it.
+pub fn reduce_all<T: VideoTool + Sync>(
+    tool: &T,
+    inputs: &[String],
+    output_dir: &Path,
+    opts: &ReduceOptions,
+    batch: &BatchOptions,
+) -> Result<(), ReduceError> {
+    let (output_dir, outputs) = output_paths(tool, inputs, output_dir, opts, batch)?;
+    let jobs: Vec<Job> = inputs
+        .iter()
+        .zip(outputs)
+        .map(|(input, output)| {
+            let mut job = Job {
+                label: input.clone(),
+                input: input.clone(),
+                output,
+                opts: opts.clone(),
+                invalid: None,
+            };
+            if batch.target_from != TargetFrom::Fixed {
+                let read = filetarget::resolve(
+                    input,
+                    batch.target_from,
+                    opts.target_bytes,
+                    opts.size_units,
+                );
+                match read {
+                    Ok((bytes, source)) => {
+                        job.opts.target_bytes = bytes;
+                        job.opts.target_source = Some(source);
+                    }
+                    Err(e) => job.invalid = Some(e),
+                }
+            }
+            job
+        })
+        .collect();
+    run_jobs(tool, &jobs, &output_dir, opts, batch)
+}
+
+/// Reduces each of `jobs` with its own options, as [`reduce_all`] does,
+/// keeping the batch's state in `state_dir`. `opts` are the batch's own:
+/// its temp directory, split, output and the target jobs are compared
+/// with.
+pub fn run_jobs<T: VideoTool + Sync>(
+    tool: &T,
+    jobs: &[Job],
+    state_dir: &Path,
+    opts: &ReduceOptions,
+    batch: &BatchOptions,
+) -> Result<(), ReduceError> {
+    let dirs = std::iter::once(state_dir).chain(
+        jobs.iter()
+            .filter_map(|job| Path::new(&job.output).parent()),
+    );
+    for dir in dirs {
+        outdir::ensure_dir(dir, opts.create_dirs)?;
+    }
+    let inputs: Vec<String> = jobs.iter().map(|job| job.input.clone()).collect();
+    let outputs: Vec<String> = jobs.iter().map(|job| job.output.clone()).collect();
+    let labels: Vec<String> = jobs.iter().map(|job| job.label.clone()).collect();
+
+    let out = Presenter::new(Console::stdout(), opts.output_mode);
+    let started = Instant::now();
+    let mut fresh = BatchState::new(&inputs, &outputs, opts.target_bytes, opts.parts);
+    for (item, job) in fresh.items.iter_mut().zip(jobs) {
+        item.target_bytes =
+            (job.opts.target_bytes != opts.target_bytes).then_some(job.opts.target_bytes);
+    }
+    let (state_path, mut state) = starting_state(fresh, state_dir, batch, out)?;
+    // Every item's intermediate files go under one directory the state
+    // knows about, so a resumed run can clear what a crash left behind.
+    let temp = RunTempDir::create(opts.temp_dir.as_deref(), opts.keep_temp)
+        .map_err(|e| ReduceError::Encode(e.to_string()))?;
+    state.temp_dir = Some(temp.path().to_path_buf());
+    let probes = tool.probe_cache();
+    if let Some(cache) = probes {
+        cache.load(&state.probes);
+    }
+    save_state(&mut state, &state_path, probes, out);
+
+    let history = batch.history.as_ref();
+    let done = match history {
+        Some(history) if !batch.redo => load_history(history, out),
+        _ => Vec::new(),
+    };
+    let durations = match batch.max_total_bytes {
+        Some(_) if batch.fit_remaining => input_durations(tool, &inputs, opts),
+        _ => Vec::new(),
+    };
+    // Each file's outcome, once it is skipped, over, or not to be started.
+    let mut outcomes: Vec<Option<Outcome>> = jobs.iter().map(|_| None).collect();
+    // What `--checksum` made of each file's output, for the summary.
+    let mut hashes = vec![None; jobs.len()];
+    // Prediction error of each file reduced in this run, in percent.
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let mut usage: Option<Usage> = None;
+    let at_once = batch.jobs.max(1) as usize;
+    let relayed = events::is_enabled();
+    thread::scope(|scope| {
+        let (sender, messages) = mpsc::channel();
+        let mut running: HashMap<usize, Running> = HashMap::new();
+        let mut next = 0;
+        let mut stopped = false;
+        loop {
+            while !stopped && next < jobs.len() && running.len() < at_once {
+                let (i, job) = (next, &jobs[next]);
+                let (input, output) = (&job.input, &job.output);
+                let announce = || out.info(&format!("[{}/{}] {}", i + 1, jobs.len(), job.label));
+                // The most the file may take: its target, for each part.
+                let file_max = job.opts.target_bytes * u64::from(opts.parts.max(1));
+                if let Some(bytes) = state.completed(i).filter(|_| !batch.redo) {
+                    announce();
+                    out.info("Finished by the interrupted run; skipping");
+                    outcomes[i] = Some(Outcome::AlreadyDone(bytes));
+                    next += 1;
+                    continue;
+                }
+                let fingerprint = history.and_then(|_| Fingerprint::of(input));
+                let on_disk = written_bytes(output, opts.parts);
+                if let Some(fp) = fingerprint.as_ref().filter(|_| job.invalid.is_none()) {
+                    if history::already_done(&done, fp, job.opts.target_bytes, output, on_disk) {
+                        announce();
+                        out.info(
+                            "Already reduced for this target; skipping (use --redo to reduce it again)",
+                        );
+                        outcomes[i] = Some(Outcome::AlreadyDone(on_disk));
+                        next += 1;
+                        continue;
+                    }
+                }
+                // A running file counts with all it may take until it is over.
+                let used: u64 = outcomes
+                    .iter()
+                    .flatten()
+                    .filter_map(Outcome::bytes)
+                    .sum::<u64>()
+                    + running.values().map(|r| r.reserved).sum::<u64>();
+                let file_target = match batch.max_total_bytes {
+                    Some(cap) if batch.fit_remaining => {
+                        let left = cap.saturating_sub(used);
+                        if durations[i] > 0.0 {
+                            share_budget(left, &durations[i..], file_max)[0]
+                        } else {
+                            // Unprobed; it most likely fails before using any.
+                            file_max.min(left)
+                        }
+                    }
+                    Some(cap) if used + file_max > cap => 0,
+                    _ => file_max,
+                };
+                if file_target == 0 {
+                    // The running files may leave room, writing less than
+                    // they could.
+                    if !running.is_empty() {
+                        break;
+                    }
+                    announce();
+                    let cap = batch.max_total_bytes.unwrap_or_default();
+                    out.warn(&format!(
+                        "stopping: {} of --max-total-size {} written, too little for another file{}",
+                        opts.sizes.size(used),
+                        opts.sizes.size(cap),
+                        if batch.fit_remaining {
+                            ""
+                        } else {
+                            " (--fit-remaining would shrink the rest to fit)"
+                        }
+                    ));
+                    for outcome in &mut outcomes[i..] {
+                        *outcome = Some(Outcome::OverBudget);
+                    }
+                    stopped = true;
+                    break;
+                }
+                let mut file_opts = ReduceOptions {
+                    target_bytes: file_target / u64::from(opts.parts.max(1)),
+                    temp_dir: Some(temp.path().to_path_buf()),
+                    ..job.opts.clone()
+                };
+                // The progress of files side by side can't share one line.
+                if at_once > 1 {
+                    file_opts.output_mode.plain = true;
+                }
+                announce();
+                if file_target < file_max {
+                    out.info(&format!(
+                        "Target lowered to {} to fit the rest of --max-total-size",
+                        opts.sizes.size(file_opts.target_bytes)
+                    ));
+                }
+                running.insert(
+                    i,
+                    Running {
+                        reserved: file_target,
+                        fingerprint,
+                        opts: file_opts.clone(),
+                    },
+                );
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    if relayed {
+                        events::install(Box::new(Relay(sender.clone())));
+                    }
+                    let reduce = || match &job.invalid {
+                        Some(problem) => Err(ReduceError::Usage(problem.clone())),
+                        None => {
+                            reduce_video(tool, &job.input, &job.output, &file_opts).map(Box::new)
+                        }
+                    };
+                    let message = match panic::catch_unwind(AssertUnwindSafe(reduce)) {
+                        Ok(result) => Message::Finished(i, result, warning::take()),
+                        Err(payload) => Message::Panicked(payload),
+                    };
+                    let _ = sender.send(message);
+                });
+                next += 1;
+            }
+            if running.is_empty() {
+                break;
+            }
+            let (i, result, taken) = match messages.recv().expect("the batch keeps a sender") {
+                Message::Event(line) => {
+                    events::relay(&line);
+                    continue;
+                }
+                Message::Panicked(payload) => panic::resume_unwind(payload),
+                Message::Finished(i, result, taken) => (i, result, taken),
+            };
+            let Running {
+                fingerprint,
+                opts: file_opts,
+                ..
+            } = running.remove(&i).expect("a job finishes once");
+            let job = &jobs[i];
+            let (input, output) = (&job.input, &job.output);
+            let mut sample = None;
+            let outcome = match result {
+                Ok(report) => {
+                    if events::is_enabled() {
+                        let report = Report::reduced(input, output, &file_opts, &report);
+                        events::emit(&Event::Report(Box::new(report)));
+                    }
+                    errors.extend(report.prediction.map(|p| p.error_percent()));
+                    hashes[i] = report.checksums.as_ref().map(output_hash);
+                    sample = report.sample;
+                    if let Some(used) = report.usage {
+                        usage = Some(usage.map_or(used, |total| total.plus(used)));
+                    }
+                    warnings.extend(report.warnings.into_iter().map(|w| (&job.label, w)));
+                    Outcome::Reduced(written_bytes(output, opts.parts))
+                }
+                Err(e) => {
+                    if events::is_enabled() {
+                        let report = Report::failed(input, output, &file_opts, &e, taken.clone());
+                        events::emit(&Event::Report(Box::new(report)));
+                    }
+                    warnings.extend(taken.into_iter().map(|w| (&job.label, w)));
+                    out.error(&e.to_string());
+                    Outcome::Failed(e)
+                }
+            };
+            let interrupted = matches!(outcome, Outcome::Failed(ReduceError::Interrupted));
+            if !interrupted {
+                let status = match outcome {
+                    Outcome::Failed(_) => Status::Failed,
+                    _ => Status::Done,
+                };
+                state.record(i, status, outcome.bytes());
+                save_state(&mut state, &state_path, probes, out);
+            }
+            if let (Some(history), Some(fp), false) = (history, &fingerprint, interrupted) {
+                let entry = Entry::new(
+                    fp,
+                    job.opts.target_bytes,
+                    output,
+                    label(&outcome),
+                    outcome.bytes(),
+                )
+                .with_sample(sample.filter(|_| !batch.no_samples));
+                if let Err(e) = history.append(&entry) {
+                    out.warn(&format!(
+                        "cannot record {} in {}: {}",
+                        input,
+                        history.path().display(),
+                        e
+                    ));
+                }
+            }
+            outcomes[i] = Some(outcome);
+            // The files still running are stopped by the same Ctrl-C.
+            stopped |= interrupted;
+        }
+    });
+    // Files are started in order and all that are run finish, so those
+    // without an outcome are the ones after the rest.
+    let outcomes: Vec<Outcome> = outcomes.into_iter().flatten().collect();
+
+    out.info("");
+    if !warnings.is_empty() {
+        print_warnings(out, &warnings);
+        out.info("");
+    }
+    print_summary(out, &labels, &outcomes, &hashes, opts.sizes);
+    if let Some(usage) = usage {
+        out.info(&format!("In total: {}", usage.describe(opts.sizes)));
+    }
+    if opts.verbose {
+        if let Some(stats) = ErrorStats::of(&errors) {
+            out.info(&format!("Size prediction error: {}", stats));
+        }
+    }
+    let finished = outcomes.iter().filter(|o| o.bytes().is_some()).count();
+    if finished == jobs.len() {
+        let _ = std::fs::remove_file(&state_path);
+    } else {
+        out.info(&format!(
+            "Progress saved in {}; run the same command again to continue",
+            state_path.display()
+        ));
+    }
+    let interrupted = outcomes
+        .iter()
+        .any(|o| matches!(o, Outcome::Failed(ReduceError::Interrupted)));
+    // Whoever pressed Ctrl-C is at the terminal already.
+    if opts.notify && !interrupted {
+        let reduced = outcomes.iter().filter(|o| o.bytes().is_some()).count();
+        let bytes = outcomes.iter().filter_map(Outcome::bytes).sum();
+        Notice::batch(reduced, jobs.len(), bytes, started.elapsed(), opts.sizes)
+            .send(out, opts.verbose);
+    }
+    if interrupted {
+        return Err(ReduceError::Interrupted);
+    }
+    match outcomes.into_iter().find_map(|o| match o {
+        Outcome::Failed(e) => Some(e),
+        _ => None,
+    }) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Repeats the warnings of every file above the summary, where they are not
+/// lost among the progress output.
+fn print_warnings(out: Presenter, warnings: &[(&String, Warning)]) {
+    out.line(Style::Warning, "Warnings:");
+    for (input, warning) in warnings {
+        out.info(&format!("  {}: {}", input, warning.message));
+    }
+}
+
+/// Length of each input for sharing out a total budget; inputs that can't
+/// be probed count as empty (and will fail when their turn comes).
+fn input_durations<T: VideoTool>(tool: &T, inputs: &[String], opts: &ReduceOptions) -> Vec<f64> {
+    inputs
+        .iter()
+        .map(|input| {
+            opts.duration
+                .or_else(|| tool.get_video_duration(input).ok())
+                .unwrap_or(0.0)
+        })
+        .collect()
+}
+
+/// Shares `budget` bytes among files of the given `durations` in
+/// proportion to their length, giving none more than `cap`. What a capped
+/// file can't take goes to the others.
+pub fn share_budget(budget: u64, durations: &[f64], cap: u64) -> Vec<u64> {
+    let mut shares = vec![0; durations.len()];
+    let mut left = budget;
+    let mut open: Vec<usize> = (0..durations.len())
+        .filter(|&i| durations[i] > 0.0)
+        .collect();
+    while !open.is_empty() {
+        let total: f64 = open.iter().map(|&i| durations[i]).sum();
+        let share = |i: usize| left as f64 * durations[i] / total;
+        let (capped, uncapped): (Vec<usize>, Vec<usize>) =
+            open.iter().partition(|&&i| share(i) >= cap as f64);
+        if capped.is_empty() {
+            for i in uncapped {
+                shares[i] = share(i) as u64;
+            }
+            break;
+        }
+        for i in capped {
+            shares[i] = cap;
+            left -= cap;
+        }
+        open = uncapped;
+    }
+    shares
+}
+
+/// Where to save progress, and the state to start from: the saved one when
+/// resuming, otherwise `fresh`.
+fn starting_state(
+    fresh: BatchState,
+    output_dir: &Path,
+    batch: &BatchOptions,
+    out: Presenter,
+) -> Result<(PathBuf, BatchState), ReduceError> {
+    let explicit = batch.resume.is_some();
+    let path = batch
+        .resume
+        .clone()
+        .unwrap_or_else(|| BatchState::default_path(output_dir));
+    let saved = match BatchState::load(&path) {
+        Ok(saved) => saved,
+        Err(e) if explicit => {
+            return Err(ReduceError::Usage(format!(
+                "cannot read batch state {}: {}",
+                path.display(),
+                e
+            )))
+        }
+        Err(e) => {
+            out.warn(&format!(
+                "ignoring unreadable batch state {}: {}",
+                path.display(),
+                e
+            ));
+            None
+        }
+    };
+    match saved {
+        Some(saved) if saved.is_same_batch(&fresh) => {
+            if let Err(e) = saved.remove_leftovers() {
+                out.warn(&format!("cannot remove partial files: {}", e));
+            }
+            if batch.redo {
+                return Ok((path, fresh));
+            }
+            out.info(&format!(
+                "Resuming the batch from {} ({} of {} files done)",
+                path.display(),
+                saved.done_count(),
+                saved.items.len()
+            ));
+            Ok((path, saved))
+        }
+        Some(_) if explicit => Err(ReduceError::Usage(format!(
+            "{} is the state of a different batch (its inputs, outputs or target differ)",
+            path.display()
+        ))),
+        None if explicit => Err(ReduceError::Usage(format!(
+            "no batch state at {}",
+            path.display()
+        ))),
+        _ => Ok((path, fresh)),
+    }
+}
+
+/// Saves progress, along with what `probes` know about the inputs; failing
+/// to only costs the ability to resume.
+fn save_state(state: &mut BatchState, path: &Path, probes: Option<&ProbeCache>, out: Presenter) {
+    if let Some(cache) = probes {
+        let inputs: Vec<String> = state.items.iter().map(|i| i.input.clone()).collect();
+        state.probes = cache.saved(&inputs);
+    }
+    if let Err(e) = state.save(path) {
+        out.warn(&format!(
+            "cannot save batch progress to {}: {}",
+            path.display(),
+            e
+        ));
+    }
+}
+
+/// The ledger's entries; a ledger that can't be read only costs the skips.
+fn load_history(history: &History, out: Presenter) -> Vec<Entry> {
+    history.load().unwrap_or_else(|e| {
+        out.warn(&format!(
+            "cannot read history {}: {}",
+            history.path().display(),
+            e
+        ));
+        Vec::new()
+    })
+}
+
+/// The batch's own directory (where its state is kept: `output_dir` up to
+/// its first placeholder) and the output path for each input, rejecting
+/// inputs that can't be batched or whose outputs would collide.
+///
+/// Each file name is sanitized under `batch.names`, leaving room for the
+/// part numbers of `--split`. Names that only become the same through
+/// that are numbered apart. With `--archive-layout`, so are outputs of the
+/// same name filed into the same directory, and names of files already
+/// there that the history doesn't show were written from the same input.
+fn output_paths<T: VideoTool>(
+    tool: &T,
+    inputs: &[String],
+    output_dir: &Path,
+    opts: &ReduceOptions,
+    batch: &BatchOptions,
+) -> Result<(PathBuf, Vec<String>), ReduceError> {
+    let name_template = batch.name_template.as_ref();
+    let dir_template = Template::parse(&output_dir.to_string_lossy())
+        .map_err(|e| ReduceError::Usage(format!("--output-dir: {}", e)))?;
+    let root = if dir_template.is_literal() {
+        output_dir.to_path_buf()
+    } else {
+        let prefix = dir_template.fixed_prefix();
+        match prefix.rfind(std::path::is_separator) {
+            Some(end) => PathBuf::from(&prefix[..end.max(1)]),
+            None => PathBuf::from("."),
+        }
+    };
+    let templates = [Some(&dir_template), name_template];
+    let uses = |fields: &[Field]| {
+        templates
+            .iter()
+            .flatten()
+            .any(|t| fields.iter().any(|&f| t.uses(f)))
+    };
+    let layout = batch.archive_layout;
+    let (needs_date, needs_recorded, needs_frame) = (
+        uses(&[Field::Date, Field::Year, Field::Month, Field::Day]),
+        layout.is_some() || uses(&[Field::Recorded]),
+        uses(&[Field::Width, Field::Height]),
+    );
+    let earlier = match &batch.history {
+        // run_jobs warns when it can't be read.
+        Some(history) if layout.is_some() => history.load().unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    let part_room = if opts.parts > 1 {
+        format!(".part{}", opts.parts).len()
+    } else {
+        0
+    };
+    let rules = batch.names.leaving(part_room);
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    let mut taken: HashSet<PathBuf> = HashSet::new();
+    let mut outputs = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        if input == STDIO_PATH {
+            return Err(ReduceError::Usage(
+                "batch mode cannot read from stdin".into(),
+            ));
+        }
+        let path = Path::new(input);
+        let Some(name) = path.file_name() else {
+            return Err(ReduceError::Usage(format!("'{}' is not a file", input)));
+        };
+        let mut values = Values {
+            stem: path
+                .file_stem()
+                .map_or_else(String::new, |s| s.to_string_lossy().into_owned()),
+            ext: path
+                .extension()
+                .map_or_else(String::new, |s| s.to_string_lossy().into_owned()),
+            size: size_label(opts.target_bytes, opts.size_units),
+            ..Values::default()
+        };
+        let info = if needs_frame || needs_recorded {
+            match tool.get_video_info(input) {
+                Ok(info) => Some(info),
+                // Dated by its mtime; the run says what is wrong with it.
+                Err(_) if !needs_frame => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+        if needs_date || needs_recorded {
+            let modified = std::fs::metadata(input)
+                .and_then(|m| m.modified())
+                .map_err(|e| {
+                    ReduceError::Probe(format!("cannot read the date of {}: {}", input, e))
+                })?;
+            let secs = modified
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            values.date = batch.archive_tz.date(secs);
+            let creation_time = info.as_ref().and_then(|i| i.creation_time.as_deref());
+            values.recorded = batch
+                .archive_tz
+                .date(archive::recorded(creation_time, secs, now));
+        }
+        if let Some(info) = info.filter(|_| needs_frame) {
+            values.width = info.width;
+            values.height = info.height;
+        }
+        let mut dir = PathBuf::from(dir_template.render(&values));
+        if let Some(layout) = layout {
+            dir.push(layout.dir(values.recorded));
+        }
+        let output = match name_template {
+            Some(template) => dir.join(template.render(&values)),
+            None => dir.join(name),
+        };
+        let mut output = output.to_string_lossy().into_owned();
+        if opts.sample.is_some() {
+            output = sample_output_path(&output);
+        }
+        match seen.insert(output.clone(), input) {
+            Some(other) if layout.is_none() => {
+                return Err(ReduceError::Usage(format!(
+                    "{} and {} would both be written to {}",
+                    other, input, output
+                )));
+            }
+            _ => {}
+        }
+        let path = Path::new(&output);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let name = path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        let name = filename::sanitize(&name, rules);
+        let fingerprint = layout.and_then(|_| Fingerprint::of(input));
+        let someone_elses = |path: &Path| {
+            let path = path.to_string_lossy();
+            let first = if opts.parts > 1 {
+                part_output_path(&path, 1)
+            } else {
+                path.to_string()
+            };
+            Path::new(&first).exists()
+                && !fingerprint
+                    .as_ref()
+                    .is_some_and(|fp| history::wrote(&earlier, fp, &path))
+        };
+        let name = filename::disambiguate(&name, rules, |name| {
+            let path = dir.join(name);
+            taken.contains(&path) || (layout.is_some() && someone_elses(&path))
+        });
+        let path = dir.join(name);
+        outputs.push(path.to_string_lossy().into_owned());
+        taken.insert(path);
+    }
+    Ok((root, outputs))
+}
+
+/// The target for `{size}`: whole megabytes when it is one, else one decimal.
+fn size_label(bytes: u64, units: SizeUnits) -> String {
+    let mb = bytes as f64 / units.megabyte() as f64;
+    if (mb - mb.round()).abs() < 0.05 {
+        format!("{}", mb.round())
+    } else {
+        format!("{:.1}", mb)
+    }
+}
+
+/// Total size of what a successful run wrote to `output`.
+pub fn written_bytes(output: &str, parts: u32) -> u64 {
+    // A directory is an HLS output.
+    let size = |path: &str| match Path::new(path) {
+        dir if dir.is_dir() => hls::total_bytes(dir).unwrap_or(0),
+        file => std::fs::metadata(file).map_or(0, |m| m.len()),
+    };
+    if parts > 1 {
+        (1..=parts)
+            .map(|n| size(&part_output_path(output, n)))
+            .sum()
+    } else {
+        size(output)
+    }
+}
+
+/// Short result label for the summary and the history; the full error was
+/// printed already.
+fn label(outcome: &Outcome) -> &'static str {
+    match outcome {
+        Outcome::Reduced(_) => history::RESULT_OK,
+        Outcome::AlreadyDone(_) => "already done",
+        Outcome::Failed(e) => e.label(),
+        Outcome::OverBudget => "over total size",
+    }
+}
+
+/// The summary's checksum of a file: the hash of its output, or where to
+/// find those of its parts.
+fn output_hash(checksums: &Checksums) -> String {
+    match checksums.outputs.as_slice() {
+        [output] => output.hash.clone(),
+        parts => format!("{} parts, listed above", parts.len()),
+    }
+}
+
+/// The summary table, with a column of each output's checksum when there
+/// are any; inputs after an interruption are listed as skipped.
+pub fn summary_table(
+    inputs: &[String],
+    outcomes: &[Outcome],
+    hashes: &[Option<String>],
+    sizes: SizeFormat,
+) -> Table {
+    let checksums = hashes.iter().any(Option::is_some);
+    let mut columns = vec![
+        Column::new("Input", Align::Left).shrinking(),
+        Column::new("Result", Align::Left),
+        Column::new("Size", Align::Right),
+    ];
+    if checksums {
+        columns.push(Column::new("Checksum", Align::Left));
+    }
+    let mut table = Table::new(columns);
+    for (i, input) in inputs.iter().enumerate() {
+        let (result, size) = match outcomes.get(i) {
+            Some(outcome) => (
+                label(outcome),
+                outcome
+                    .bytes()
+                    .map_or_else(|| "-".to_string(), |b| sizes.size(b)),
+            ),
+            None => ("skipped", "-".to_string()),
+        };
+        let mut row = vec![input.clone(), result.to_string(), size];
+        if checksums {
+            let hash = hashes.get(i).cloned().flatten();
+            row.push(hash.unwrap_or_else(|| "-".to_string()));
+        }
+        table.push(row);
+    }
+    table
+}
+
+fn print_summary(
+    out: Presenter,
+    inputs: &[String],
+    outcomes: &[Outcome],
+    hashes: &[Option<String>],
+    sizes: SizeFormat,
+) {
+    let lines = out.table(&summary_table(inputs, outcomes, hashes, sizes));
+    let mut lines = lines.iter();
+    if let Some(header) = lines.next() {
+        out.info(header);
+    }
+    for (i, line) in lines.enumerate() {
+        let style = match outcomes.get(i) {
+            Some(Outcome::Failed(_)) => Style::Error,
+            Some(Outcome::OverBudget) | None => Style::Warning,
+            Some(_) => Style::Success,
+        };
+        out.line(style, line);
+    }
+    let reduced = outcomes.iter().filter(|o| o.bytes().is_some()).count();
+    let earlier = outcomes
+        .iter()
+        .filter(|o| matches!(o, Outcome::AlreadyDone(_)))
+        .count();
+    let style = if reduced == inputs.len() {
+        Style::Success
+    } else {
+        Style::Error
+    };
+    let mut summary = format!("Reduced {} of {} files", reduced, inputs.len());
+    if earlier > 0 {
+        summary.push_str(&format!(" ({} already done)", earlier));
+    }
+    out.line(style, &summary);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetarget::TargetSource;
+    use crate::probecache::Cached;
+    use crate::testing::{arg_value, mib, EventLog, MockVideoTool, TestDir};
+    use std::time::Duration;
+
+    fn names(inputs: &[&str]) -> Vec<String> {
+        inputs.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn opts_in(dir: &TestDir) -> ReduceOptions {
+        let mut opts = ReduceOptions::new(mib(50));
+        opts.temp_dir = Some(dir.path().to_path_buf());
+        opts
+    }
+
+    #[test]
+    fn test_reduces_each_input_into_output_dir() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(60.0);
+        let output_dir = dir.path().join("reduced");
+        let inputs = names(&["clips/a.mp4", "other/b.mp4"]);
+        reduce_all(
+            &tool,
+            &inputs,
+            &output_dir,
+            &opts_in(&dir),
+            &BatchOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 2);
+        assert!(output_dir.join("a.mp4").exists());
+        assert!(output_dir.join("b.mp4").exists());
+    }
+
+    #[test]
+    fn test_failures_continue_and_are_reported() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(60.0);
+        tool.output_bytes = vec![mib(60)];
+        let inputs = names(&["a.mp4", "b.mp4"]);
+        let mut opts = opts_in(&dir);
+        opts.max_retries = 0;
+        let err =
+            reduce_all(&tool, &inputs, dir.path(), &opts, &BatchOptions::default()).unwrap_err();
+
+        assert!(matches!(err, ReduceError::OverTarget { .. }));
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_reports_point_at_the_sidecars() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(60.0);
+        let inputs = names(&["a.mp4", "b.mp4"]);
+        let mut opts = opts_in(&dir);
+        opts.sidecar = true;
+        let log = EventLog::install();
+        reduce_all(&tool, &inputs, dir.path(), &opts, &BatchOptions::default()).unwrap();
+
+        let sidecars: Vec<String> = log
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Report(report) => report.sidecar,
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sidecars.len(), 2);
+        for (sidecar, input) in sidecars.iter().zip(&inputs) {
+            assert!(sidecar.ends_with(".mp4.mdviqure.json"), "{}", sidecar);
+            let json = std::fs::read_to_string(sidecar).unwrap();
+            assert!(json.contains(input.as_str()), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_targets_from_file_names_fail_only_their_file() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(60.0);
+        let inputs = names(&["a__5MiB.mp4", "b__25XB.mp4", "c.mp4"]);
+        let batch = BatchOptions {
+            target_from: TargetFrom::FilenameSuffix,
+            ..BatchOptions::default()
+        };
+        let log = EventLog::install();
+        let err = reduce_all(&tool, &inputs, dir.path(), &opts_in(&dir), &batch).unwrap_err();
+        assert!(
+            err.to_string().starts_with("the target in the file name"),
+            "{}",
+            err
+        );
+
+        // The malformed one is never started.
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 2);
+        let reports: Vec<(u64, Option<TargetSource>, bool)> = log
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Report(report) => Some((
+                    report.target_bytes,
+                    report.target_source,
+                    report.error.is_some(),
+                )),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            reports,
+            [
+                (mib(5), Some(TargetSource::Filename), false),
+                (mib(50), None, true),
+                (mib(50), Some(TargetSource::Fixed), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_budget_is_shared_by_duration() {
+        assert_eq!(
+            share_budget(900, &[10.0, 20.0, 60.0], 1000),
+            [100, 200, 600]
+        );
+        // A file held at the cap leaves the rest to the others.
+        assert_eq!(share_budget(900, &[10.0, 10.0, 70.0], 400), [250, 250, 400]);
+        assert_eq!(share_budget(900, &[10.0, 10.0], 400), [400, 400]);
+        // Files of unknown length get nothing.
+        assert_eq!(share_budget(900, &[0.0, 30.0], 1000), [0, 900]);
+        assert_eq!(share_budget(0, &[10.0], 1000), [0]);
+        assert!(share_budget(500, &[], 1000).is_empty());
+    }
+
+    #[test]
+    fn test_total_size_cap_stops_the_batch() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(60.0);
+        tool.output_bytes = vec![mib(40)];
+        let inputs = names(&["a.mp4", "b.mp4", "c.mp4"]);
+        let batch = BatchOptions {
+            max_total_bytes: Some(mib(120)),
+            ..BatchOptions::default()
+        };
+        reduce_all(&tool, &inputs, dir.path(), &opts_in(&dir), &batch).unwrap();
+
+        // 80 MiB written, and the third could take another 50.
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 2);
+        assert!(!dir.path().join("c.mp4").exists());
+        let state = BatchState::load(&BatchState::default_path(dir.path()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.items[2].status, Status::Pending);
+    }
+
+    #[test]
+    fn test_jobs_reduce_that_many_files_at_once() {
+        let dir = TestDir::new();
+        let inputs = names(&["a.mp4", "b.mp4", "c.mp4", "d.mp4"]);
+        for (jobs, at_once) in [(1, 1), (2, 2), (8, 4)] {
+            let mut tool = MockVideoTool::new(60.0);
+            tool.ffmpeg_delay = Duration::from_millis(50);
+            let output_dir = dir.path().join(format!("jobs{}", jobs));
+            let batch = BatchOptions {
+                jobs,
+                ..BatchOptions::default()
+            };
+            let log = EventLog::install();
+            reduce_all(&tool, &inputs, &output_dir, &opts_in(&dir), &batch).unwrap();
+
+            assert_eq!(tool.most_at_once.get(), at_once, "--jobs {}", jobs);
+            for input in &inputs {
+                assert!(output_dir.join(input).exists(), "{}", input);
+            }
+            // The events of every file reach the batch's sink.
+            let reports = log
+                .events()
+                .into_iter()
+                .filter(|event| matches!(event, Event::Report(_)))
+                .count();
+            assert_eq!(reports, inputs.len());
+            assert!(!BatchState::default_path(&output_dir).exists());
+        }
+    }
+
+    #[test]
+    fn test_files_at_once_keep_to_the_total_size() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(60.0);
+        tool.output_bytes = vec![mib(40)];
+        tool.ffmpeg_delay = Duration::from_millis(20);
+        let inputs = names(&["a.mp4", "b.mp4", "c.mp4"]);
+        let batch = BatchOptions {
+            max_total_bytes: Some(mib(120)),
+            jobs: 3,
+            ..BatchOptions::default()
+        };
+        reduce_all(&tool, &inputs, dir.path(), &opts_in(&dir), &batch).unwrap();
+
+        // Two running may take 100 MiB, too much for a third to start
+        // beside them, and once they are over 80 MiB is.
+        assert_eq!(tool.most_at_once.get(), 2);
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 2);
+        assert!(!dir.path().join("c.mp4").exists());
+        let state = BatchState::load(&BatchState::default_path(dir.path()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.items[2].status, Status::Pending);
+    }
+
+    #[test]
+    fn test_fit_remaining_shrinks_the_last_targets() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(60.0);
+        tool.output_bytes = vec![mib(30)];
+        let inputs = names(&["a.mp4", "b.mp4", "c.mp4"]);
+        let batch = BatchOptions {
+            max_total_bytes: Some(mib(100)),
+            fit_remaining: true,
+            ..BatchOptions::default()
+        };
+        reduce_all(&tool, &inputs, dir.path(), &opts_in(&dir), &batch).unwrap();
+        let unlimited = MockVideoTool::new(60.0);
+        reduce_all(
+            &unlimited,
+            &names(&["a.mp4"]),
+            &dir.path().join("unlimited"),
+            &opts_in(&dir),
+            &BatchOptions::default(),
+        )
+        .unwrap();
+
+        let bitrate = |call: &[String]| {
+            arg_value(call, "-b:v")
+                .unwrap()
+                .trim_end_matches('k')
+                .parse::<u64>()
+                .unwrap()
+        };
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        // Targets of 33, 35 and 40 MiB: each file gets a third of the cap
+        // at first, plus what those before it left unused.
+        let full = bitrate(&unlimited.single_call());
+        assert!(bitrate(&calls[0]) < bitrate(&calls[1]));
+        assert!(bitrate(&calls[1]) < bitrate(&calls[2]));
+        assert!(bitrate(&calls[2]) < full);
+        assert!(dir.path().join("c.mp4").exists());
+    }
+
+    #[test]
+    fn test_name_and_directory_templates() {
+        let dir = TestDir::new();
+        let input = dir.join("holiday.mov");
+        std::fs::write(&input, b"video").unwrap();
+        let tool = MockVideoTool::new(60.0).with_dimensions(1280, 720);
+        let batch = BatchOptions {
+            name_template: Some(Template::parse("{stem}_{width}x{height}_{size}MB.mp4").unwrap()),
+            ..BatchOptions::default()
+        };
+        let output_dir = dir.path().join("archive").join("{year}");
+        reduce_all(
+            &tool,
+            std::slice::from_ref(&input),
+            &output_dir,
+            &opts_in(&dir),
+            &batch,
+        )
+        .unwrap();
+
+        let year = std::fs::read_dir(dir.path().join("archive"))
+            .unwrap()
+            .filter_map(Result::ok)
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .find(|name| !name.starts_with('.'))
+            .unwrap();
+        assert_eq!(year.len(), 4);
+        let output = dir
+            .path()
+            .join("archive")
+            .join(&year)
+            .join("holiday_1280x720_50MB.mp4");
+        assert!(output.exists());
+        // The state of the batch lives above the placeholders.
+        assert!(!dir.path().join("archive").join("{year}").exists());
+    }
+
+    #[test]
+    fn test_template_collisions_are_rejected_before_encoding() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(60.0);
+        let batch = BatchOptions {
+            name_template: Some(Template::parse("{size}MB.{ext}").unwrap()),
+            ..BatchOptions::default()
+        };
+        let inputs = names(&["a.mp4", "b.mp4"]);
+        let err = reduce_all(&tool, &inputs, dir.path(), &opts_in(&dir), &batch).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("a.mp4 and b.mp4 would both be written to"),
+            "{}",
+            err
+        );
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+        let err = reduce_all(
+            &tool,
+            &inputs,
+            &dir.path().join("{resolution}"),
+            &opts_in(&dir),
+            &BatchOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)), "{:?}", err);
+    }
+
+    /// `name` under `dir`, last modified on 2023-03-10.
+    fn dated_input(dir: &TestDir, name: &str) -> String {
+        let path = dir.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1_678_438_800))
+            .unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_archive_layout_files_by_recording_date() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(60.0);
+        let archive = dir.path().join("archive");
+        let batch = BatchOptions {
+            archive_layout: Some(Layout::Date),
+            name_template: Some(Template::parse("{recorded}_{stem}.mp4").unwrap()),
+            ..BatchOptions::default()
+        };
+        let inputs = [dated_input(&dir, "GOPR0001.MP4")];
+        let cases = [
+            (
+                Some("2024-05-07T12:34:56.000000Z"),
+                "2024/05/2024-05-07_GOPR0001.mp4",
+            ),
+            // No creation time, or one that can't be right: the mtime.
+            (None, "2023/03/2023-03-10_GOPR0001.mp4"),
+            (
+                Some("1970-01-01T00:00:00.000000Z"),
+                "2023/03/2023-03-10_GOPR0001.mp4",
+            ),
+            (Some("garbage"), "2023/03/2023-03-10_GOPR0001.mp4"),
+        ];
+        for (creation_time, output) in cases {
+            tool.info.creation_time = creation_time.map(str::to_string);
+            let (_, outputs) =
+                output_paths(&tool, &inputs, &archive, &opts_in(&dir), &batch).unwrap();
+            assert_eq!(
+                outputs,
+                [archive.join(output).to_string_lossy()],
+                "{:?}",
+                creation_time
+            );
+        }
+
+        tool.info.creation_time = Some("2024-05-07T12:34:56.000000Z".into());
+        reduce_all(&tool, &inputs, &archive, &opts_in(&dir), &batch).unwrap();
+        assert!(archive.join("2024/05/2024-05-07_GOPR0001.mp4").exists());
+    }
+
+    #[test]
+    fn test_archive_layout_numbers_apart_what_would_collide() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(60.0);
+        tool.info.creation_time = Some("2024-05-07T12:34:56.000000Z".into());
+        let archive = dir.path().join("archive");
+        let batch = BatchOptions {
+            archive_layout: Some(Layout::Date),
+            history: Some(History::at(dir.join("history.jsonl"))),
+            ..BatchOptions::default()
+        };
+        // Two cameras both start counting at GOPR0001.
+        let inputs = [
+            dated_input(&dir, "front/GOPR0001.MP4"),
+            dated_input(&dir, "rear/GOPR0001.MP4"),
+        ];
+        // And last month's run filed another camera's first clip.
+        let month = archive.join("2024").join("05");
+        std::fs::create_dir_all(&month).unwrap();
+        std::fs::write(month.join("GOPR0001.MP4"), b"earlier").unwrap();
+
+        let expected: Vec<String> = ["GOPR0001-2.MP4", "GOPR0001-3.MP4"]
+            .map(|name| month.join(name).to_string_lossy().into_owned())
+            .to_vec();
+        let (_, outputs) = output_paths(&tool, &inputs, &archive, &opts_in(&dir), &batch).unwrap();
+        assert_eq!(outputs, expected);
+        reduce_all(&tool, &inputs, &archive, &opts_in(&dir), &batch).unwrap();
+        assert_eq!(
+            std::fs::read(month.join("GOPR0001.MP4")).unwrap(),
+            b"earlier"
+        );
+
+        // Running again finds the same names, its own outputs by then.
+        let (_, again) = output_paths(&tool, &inputs, &archive, &opts_in(&dir), &batch).unwrap();
+        assert_eq!(again, expected);
+    }
+
+    #[test]
+    fn test_portable_names_are_cleaned_and_numbered() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(60.0);
+        let batch = BatchOptions {
+            names: NameRules {
+                portable: true,
+                max_bytes: 20,
+            },
+            ..BatchOptions::default()
+        };
+        let inputs = names(&["a:b.mp4", "a?b.mp4", "CON.mp4", "a-very-long-recording.mp4"]);
+        let (_, outputs) =
+            output_paths(&tool, &inputs, dir.path(), &opts_in(&dir), &batch).unwrap();
+        let names: Vec<String> = outputs
+            .iter()
+            .map(|p| {
+                Path::new(p)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            ["a_b.mp4", "a_b-2.mp4", "CON_.mp4", "a-very-long-reco.mp4"]
+        );
+    }
+
+    #[test]
+    fn test_size_label() {
+        assert_eq!(size_label(mib(25), SizeUnits::Binary), "25");
+        assert_eq!(size_label(1_500_000, SizeUnits::Si), "1.5");
+    }
+
+    #[test]
+    fn test_colliding_names_and_stdin_are_rejected() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(60.0);
+        for inputs in [names(&["x/a.mp4", "y/a.mp4"]), names(&["a.mp4", "-"])] {
+            let err = reduce_all(
+                &tool,
+                &inputs,
+                dir.path(),
+                &opts_in(&dir),
+                &BatchOptions::default(),
+            )
+            .unwrap_err();
+            assert!(matches!(err, ReduceError::Usage(_)), "{:?}", err);
+        }
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_history_skips_files_already_done() {
+        let dir = TestDir::new();
+        let input = dir.join("a.mp4");
+        std::fs::write(&input, b"source").unwrap();
+        let inputs = vec![input];
+        let output_dir = dir.path().join("reduced");
+        let mut ledger = BatchOptions {
+            history: Some(History::at(dir.path().join("history.jsonl"))),
+            ..BatchOptions::default()
+        };
+        let tool = MockVideoTool::new(60.0);
+        let mut opts = opts_in(&dir);
+        for _ in 0..2 {
+            reduce_all(&tool, &inputs, &output_dir, &opts, &ledger).unwrap();
+        }
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 1);
+        // The entry also keeps what the encode showed about MP4 overhead.
+        let entries = ledger.history.as_ref().unwrap().load().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].sample.is_some());
+
+        // A new target, or --redo, reduces it again.
+        opts.target_bytes = mib(25);
+        reduce_all(&tool, &inputs, &output_dir, &opts, &ledger).unwrap();
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 2);
+        ledger.redo = true;
+        reduce_all(&tool, &inputs, &output_dir, &opts, &ledger).unwrap();
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_history_ignores_failed_or_missing_results() {
+        let dir = TestDir::new();
+        let input = dir.join("a.mp4");
+        std::fs::write(&input, b"source").unwrap();
+        let inputs = vec![input];
+        let output_dir = dir.path().join("reduced");
+        let ledger = BatchOptions {
+            history: Some(History::at(dir.path().join("history.jsonl"))),
+            ..BatchOptions::default()
+        };
+        let mut tool = MockVideoTool::new(60.0);
+        tool.fail_ffmpeg = true;
+        let opts = opts_in(&dir);
+        assert!(reduce_all(&tool, &inputs, &output_dir, &opts, &ledger).is_err());
+        tool.fail_ffmpeg = false;
+        reduce_all(&tool, &inputs, &output_dir, &opts, &ledger).unwrap();
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 2);
+
+        // The recorded output was deleted since.
+        std::fs::remove_file(output_dir.join("a.mp4")).unwrap();
+        reduce_all(&tool, &inputs, &output_dir, &opts, &ledger).unwrap();
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_interrupted_batch_resumes_after_finished_items() {
+        let dir = TestDir::new();
+        let inputs = names(&["a.mp4", "b.mp4"]);
+        let output_dir = dir.path().join("reduced");
+        let mut opts = opts_in(&dir);
+        opts.max_retries = 0;
+        // b comes out over the target, so the batch doesn't finish.
+        let mut tool = MockVideoTool::new(60.0);
+        tool.output_bytes = vec![1024, mib(60)];
+        let batch = BatchOptions::default();
+        assert!(reduce_all(&tool, &inputs, &output_dir, &opts, &batch).is_err());
+        let state_path = BatchState::default_path(&output_dir);
+        assert!(state_path.exists());
+
+        let tool = MockVideoTool::new(60.0);
+        reduce_all(&tool, &inputs, &output_dir, &opts, &batch).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert!(calls[0].iter().any(|a| a == "b.mp4"));
+        assert!(!state_path.exists());
+    }
+
+    /// Input files in `dir`, which the probe cache only keeps answers of.
+    fn files_in(dir: &TestDir, names: &[&str]) -> Vec<String> {
+        names
+            .iter()
+            .map(|name| {
+                let path = dir.join(name);
+                std::fs::write(&path, b"video").unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_each_input_is_probed_once() {
+        let dir = TestDir::new();
+        let inputs = files_in(&dir, &["a.mp4", "b.mp4"]);
+        // Naming wants the frame and the budget the durations, before the
+        // encodes probe the same files.
+        let batch = BatchOptions {
+            name_template: Some(Template::parse("{stem}_{width}x{height}.mp4").unwrap()),
+            max_total_bytes: Some(mib(200)),
+            fit_remaining: true,
+            ..BatchOptions::default()
+        };
+        let mock = MockVideoTool::new(60.0);
+        let tool = Cached::new(&mock);
+        reduce_all(&tool, &inputs, dir.path(), &opts_in(&dir), &batch).unwrap();
+        assert_eq!(mock.ffmpeg_calls.lock().unwrap().len(), 2);
+        let probes = mock.probes();
+        let mut unique = probes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(probes.len(), unique.len(), "{:#?}", probes);
+        for input in &inputs {
+            assert!(probes.contains(&format!("info {}", input)));
+            assert!(probes.contains(&format!("duration {}", input)));
+        }
+    }
+
+    #[test]
+    fn test_a_resumed_batch_reuses_the_saved_probes() {
+        let dir = TestDir::new();
+        let inputs = files_in(&dir, &["a.mp4", "b.mp4"]);
+        let output_dir = dir.path().join("reduced");
+        let mut opts = opts_in(&dir);
+        opts.max_retries = 0;
+        let batch = BatchOptions {
+            max_total_bytes: Some(mib(200)),
+            fit_remaining: true,
+            ..BatchOptions::default()
+        };
+        // b comes out over the target, so the batch doesn't finish.
+        let mut mock = MockVideoTool::new(60.0);
+        mock.output_bytes = vec![1024, mib(60)];
+        let tool = Cached::new(&mock);
+        assert!(reduce_all(&tool, &inputs, &output_dir, &opts, &batch).is_err());
+        let state = BatchState::load(&BatchState::default_path(&output_dir))
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.probes.len(), 2);
+
+        let mock = MockVideoTool::new(60.0);
+        let tool = Cached::new(&mock);
+        reduce_all(&tool, &inputs, &output_dir, &opts, &batch).unwrap();
+        assert_eq!(mock.ffmpeg_calls.lock().unwrap().len(), 1);
+        let probes = mock.probes();
+        assert!(
+            probes
+                .iter()
+                .all(|p| !p.starts_with("info ") && !p.starts_with("duration ")),
+            "{:#?}",
+            probes
+        );
+    }
+
+    #[test]
+    fn test_resume_clears_partial_files_and_redoes_the_cut_off_item() {
+        let dir = TestDir::new();
+        let inputs = names(&["a.mp4", "b.mp4"]);
+        let output_dir = dir.path().join("reduced");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let opts = opts_in(&dir);
+        let tool = MockVideoTool::new(60.0);
+        let (_, outputs) =
+            output_paths(&tool, &inputs, &output_dir, &opts, &BatchOptions::default()).unwrap();
+        // As left by a crash while b was encoding.
+        let mut state = BatchState::new(&inputs, &outputs, opts.target_bytes, 1);
+        std::fs::write(&outputs[0], vec![0; 1024]).unwrap();
+        state.record(0, Status::Done, Some(1024));
+        let crashed = dir.path().join("crashed-run");
+        std::fs::create_dir_all(&crashed).unwrap();
+        std::fs::write(crashed.join("partial.mp4"), b"half").unwrap();
+        state.temp_dir = Some(crashed.clone());
+        let state_path = dir.path().join("state.json");
+        state.save(&state_path).unwrap();
+
+        let tool = MockVideoTool::new(60.0);
+        let batch = BatchOptions {
+            resume: Some(state_path),
+            ..BatchOptions::default()
+        };
+        reduce_all(&tool, &inputs, &output_dir, &opts, &batch).unwrap();
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 1);
+        assert!(!crashed.exists());
+    }
+
+    #[test]
+    fn test_resume_rejects_missing_or_foreign_state() {
+        let dir = TestDir::new();
+        let inputs = names(&["a.mp4"]);
+        let output_dir = dir.path().join("reduced");
+        let state_path = dir.path().join("state.json");
+        let tool = MockVideoTool::new(60.0);
+        let batch = BatchOptions {
+            resume: Some(state_path.clone()),
+            ..BatchOptions::default()
+        };
+        let err = reduce_all(&tool, &inputs, &output_dir, &opts_in(&dir), &batch).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)), "{:?}", err);
+
+        let other = BatchState::new(&names(&["z.mp4"]), &[dir.join("z.mp4")], mib(50), 1);
+        other.save(&state_path).unwrap();
+        let err = reduce_all(&tool, &inputs, &output_dir, &opts_in(&dir), &batch).unwrap_err();
+        assert!(err.to_string().contains("different batch"), "{}", err);
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_summary_table_rows() {
+        let outcomes = vec![
+            Outcome::Reduced(25_000_000),
+            Outcome::AlreadyDone(20_000_000),
+            Outcome::Failed(ReduceError::Interrupted),
+        ];
+        let inputs = names(&["a.mp4", "b.mp4", "c.mp4", "d.mp4"]);
+        let lines = summary_table(&inputs, &outcomes, &[], SizeUnits::Si.into()).render(usize::MAX);
+        assert_eq!(
+            lines,
+            vec![
+                "Input  Result         Size",
+                "a.mp4  ok            25 MB",
+                "b.mp4  already done  20 MB",
+                "c.mp4  interrupted       -",
+                "d.mp4  skipped           -",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_summary_table_lists_checksums() {
+        let outcomes = vec![
+            Outcome::Reduced(25_000_000),
+            Outcome::Failed(ReduceError::Interrupted),
+        ];
+        let inputs = names(&["a.mp4", "b.mp4"]);
+        let hashes = [Some("e3b0c442".to_string()), None];
+        let lines =
+            summary_table(&inputs, &outcomes, &hashes, SizeUnits::Si.into()).render(usize::MAX);
+        assert_eq!(
+            lines,
+            vec![
+                "Input  Result        Size  Checksum",
+                "a.mp4  ok           25 MB  e3b0c442",
+                "b.mp4  interrupted      -  -",
+            ]
+        );
+
+        let split = Checksums {
+            algorithm: crate::checksum::Algorithm::Blake3,
+            input: None,
+            outputs: vec![
+                crate::checksum::FileChecksum {
+                    path: "a-part1.mp4".to_string(),
+                    hash: "ab".to_string(),
+                };
+                3
+            ],
+        };
+        assert_eq!(output_hash(&split), "3 parts, listed above");
+    }
+}
@@ -0,0 +1,523 @@
+//! `probe --breakdown`: how a file's bytes split between its streams and
+//! the container.
+//!
+//! Stream sizes come from each stream's bit rate times its duration, which
+//! ffprobe reads from the headers. Where a container doesn't record a bit
+//! rate (MKV, TS), `--exact` sums the packet sizes instead, which reads the
+//! whole file.
+
+use crate::estimate::format_duration;
+use crate::presenter::{Align, Column, Table};
+use crate::probe::StreamTags;
+use crate::provenance;
+use crate::sizefmt::SizeFormat;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// What a stream carries, from ffprobe's `codec_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    /// Files stored in the container, such as Matroska fonts.
+    Attachment,
+    Data,
+}
+
+impl StreamKind {
+    fn parse(codec_type: Option<&str>) -> Self {
+        match codec_type {
+            Some("video") => StreamKind::Video,
+            Some("audio") => StreamKind::Audio,
+            Some("subtitle") => StreamKind::Subtitle,
+            Some("attachment") => StreamKind::Attachment,
+            _ => StreamKind::Data,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            StreamKind::Video => "video",
+            StreamKind::Audio => "audio",
+            StreamKind::Subtitle => "subtitle",
+            StreamKind::Attachment => "attachment",
+            StreamKind::Data => "data",
+        }
+    }
+}
+
+/// One stream as `ffprobe -show_entries stream=...` reports it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamEntry {
+    pub index: u32,
+    pub kind: StreamKind,
+    pub codec: String,
+    /// Bits per second, when the container records it.
+    pub bit_rate: Option<u64>,
+    /// Seconds, when the stream reports its own duration.
+    pub duration: Option<f64>,
+    /// Size of the codec's extra data, which is where an attachment's
+    /// content lives.
+    pub extradata_bytes: u64,
+    /// The stream's language tag, such as `eng`.
+    pub language: Option<String>,
+}
+
+/// The streams of a file and its container-level size and duration.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileStreams {
+    pub size_bytes: Option<u64>,
+    pub duration: Option<f64>,
+    pub bit_rate: Option<u64>,
+    /// The container's `comment` metadata.
+    pub comment: Option<String>,
+    pub streams: Vec<StreamEntry>,
+}
+
+#[derive(Deserialize)]
+struct RawProbe {
+    #[serde(default)]
+    format: RawFormat,
+    #[serde(default)]
+    streams: Vec<RawStream>,
+}
+
+#[derive(Default, Deserialize)]
+struct RawFormat {
+    size: Option<String>,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+    /// Matroska spells its tags in capitals, MP4 in lower case.
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct RawStream {
+    #[serde(default)]
+    index: u32,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    bit_rate: Option<String>,
+    duration: Option<String>,
+    #[serde(default)]
+    extradata_size: u64,
+    #[serde(default)]
+    tags: StreamTags,
+}
+
+/// ffprobe prints numbers as strings, and `N/A` for unknown ones.
+fn number<T: std::str::FromStr>(value: Option<&str>) -> Option<T> {
+    value.and_then(|v| v.parse().ok())
+}
+
+/// Parses `ffprobe -show_entries format=size,duration,bit_rate:
+/// format_tags=comment:stream=index,codec_type,codec_name,bit_rate,duration,
+/// extradata_size:stream_tags=language -of json` output.
+pub fn parse_streams(stdout: &str) -> Result<FileStreams, Box<dyn Error>> {
+    let probe: RawProbe = serde_json::from_str(stdout)?;
+    Ok(FileStreams {
+        size_bytes: number(probe.format.size.as_deref()),
+        duration: number(probe.format.duration.as_deref()),
+        bit_rate: number(probe.format.bit_rate.as_deref()),
+        comment: probe
+            .format
+            .tags
+            .into_iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("comment"))
+            .map(|(_, value)| value),
+        streams: probe
+            .streams
+            .into_iter()
+            .map(|s| StreamEntry {
+                index: s.index,
+                kind: StreamKind::parse(s.codec_type.as_deref()),
+                codec: s.codec_name.unwrap_or_else(|| "unknown".to_string()),
+                bit_rate: number(s.bit_rate.as_deref()),
+                duration: number(s.duration.as_deref()),
+                extradata_bytes: s.extradata_size,
+                language: s.tags.language,
+            })
+            .collect(),
+    })
+}
+
+/// Sums `ffprobe -show_entries packet=stream_index,size -of csv=p=0`
+/// output (one `index,size` line per packet) into bytes per stream.
+pub fn parse_packet_sizes(stdout: &str) -> HashMap<u32, u64> {
+    let mut sizes = HashMap::new();
+    for line in stdout.lines() {
+        let mut fields = line.trim().split(',');
+        let (Some(index), Some(size)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        if let (Ok(index), Ok(size)) = (index.parse::<u32>(), size.parse::<u64>()) {
+            *sizes.entry(index).or_insert(0) += size;
+        }
+    }
+    sizes
+}
+
+/// How the stream sizes of a [`Breakdown`] were worked out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Bit rate times duration.
+    Bitrate,
+    /// Packet sizes added up (`--exact`).
+    Packets,
+}
+
+/// Bits per second as kb/s, `-` when unknown.
+fn format_bit_rate(bit_rate: Option<u64>) -> String {
+    match bit_rate {
+        Some(rate) if rate < 1000 => "<1k".to_string(),
+        Some(rate) => format!("{}k", rate / 1000),
+        None => "-".to_string(),
+    }
+}
+
+/// Plain `probe` output: the file's duration, size and bit rate, then one
+/// row per stream. A [`provenance`] tag follows when the file has one.
+pub fn render_streams(
+    file: &FileStreams,
+    total_bytes: Option<u64>,
+    sizes: SizeFormat,
+    max_width: usize,
+) -> Vec<String> {
+    let mut lines = vec![format!(
+        "Duration {}, size {}, bitrate {}",
+        file.duration
+            .map_or_else(|| "-".to_string(), format_duration),
+        total_bytes.map_or_else(|| "-".to_string(), |b| sizes.size(b)),
+        format_bit_rate(file.bit_rate)
+    )];
+    let mut table = Table::new(vec![
+        Column::new("Stream", Align::Left),
+        Column::new("Codec", Align::Left),
+        Column::new("Bitrate", Align::Right),
+        Column::new("Duration", Align::Right),
+    ]);
+    for stream in &file.streams {
+        table.push(vec![
+            format!("#{} {}", stream.index, stream.kind.label()),
+            stream.codec.clone(),
+            format_bit_rate(stream.bit_rate),
+            stream
+                .duration
+                .map_or_else(|| "-".to_string(), format_duration),
+        ]);
+    }
+    lines.extend(table.render(max_width));
+    if let Some(tag) = file.comment.as_deref().filter(|c| provenance::is_tag(c)) {
+        lines.push(format!("Made with {}", tag));
+    }
+    lines
+}
+
+/// The bytes one stream takes up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Share {
+    pub stream: StreamEntry,
+    /// `None` when neither the bit rate nor (without `--exact`) the packets
+    /// are known.
+    pub bytes: Option<u64>,
+}
+
+/// A file's size split by stream, with the rest counted as container
+/// overhead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakdown {
+    pub total_bytes: u64,
+    pub shares: Vec<Share>,
+    pub method: Method,
+}
+
+impl Breakdown {
+    /// Sizes from bit rate times duration (the stream's own, else the
+    /// file's). Attachments count with their stored size.
+    pub fn from_bitrates(file: &FileStreams, total_bytes: u64) -> Self {
+        let shares = file
+            .streams
+            .iter()
+            .map(|stream| {
+                let bytes = if stream.kind == StreamKind::Attachment {
+                    Some(stream.extradata_bytes)
+                } else {
+                    let duration = stream.duration.or(file.duration);
+                    stream
+                        .bit_rate
+                        .zip(duration)
+                        .map(|(rate, duration)| (rate as f64 * duration / 8.0).round() as u64)
+                };
+                Share {
+                    stream: stream.clone(),
+                    bytes,
+                }
+            })
+            .collect();
+        Self {
+            total_bytes,
+            shares,
+            method: Method::Bitrate,
+        }
+    }
+
+    /// Sizes from the summed packets of each stream. Streams without
+    /// packets (attachments) count with their stored size.
+    pub fn from_packets(file: &FileStreams, total_bytes: u64, packets: &HashMap<u32, u64>) -> Self {
+        let shares = file
+            .streams
+            .iter()
+            .map(|stream| {
+                let bytes = match packets.get(&stream.index) {
+                    Some(&bytes) => bytes,
+                    None if stream.kind == StreamKind::Attachment => stream.extradata_bytes,
+                    None => 0,
+                };
+                Share {
+                    stream: stream.clone(),
+                    bytes: Some(bytes),
+                }
+            })
+            .collect();
+        Self {
+            total_bytes,
+            shares,
+            method: Method::Packets,
+        }
+    }
+
+    /// What the streams don't account for: headers, indexes and
+    /// interleaving. `None` while any stream's size is unknown.
+    pub fn overhead_bytes(&self) -> Option<u64> {
+        let streams: Option<u64> = self.shares.iter().map(|s| s.bytes).sum();
+        streams.map(|sum| self.total_bytes.saturating_sub(sum))
+    }
+
+    /// Number of streams whose size is unknown.
+    pub fn unknown_count(&self) -> usize {
+        self.shares.iter().filter(|s| s.bytes.is_none()).count()
+    }
+
+    /// The breakdown as a table, one row per stream plus overhead and
+    /// total, followed by notes on how it was worked out.
+    pub fn render(&self, sizes: SizeFormat, max_width: usize) -> Vec<String> {
+        let percent = |bytes: u64| {
+            if self.total_bytes == 0 {
+                "-".to_string()
+            } else {
+                format!("{:.1}%", bytes as f64 / self.total_bytes as f64 * 100.0)
+            }
+        };
+        let mut table = Table::new(vec![
+            Column::new("Stream", Align::Left),
+            Column::new("Codec", Align::Left),
+            Column::new("Bitrate", Align::Right),
+            Column::new("Size", Align::Right),
+            Column::new("Share", Align::Right),
+        ]);
+        for share in &self.shares {
+            let stream = &share.stream;
+            table.push(vec![
+                format!("#{} {}", stream.index, stream.kind.label()),
+                stream.codec.clone(),
+                format_bit_rate(stream.bit_rate),
+                share
+                    .bytes
+                    .map_or_else(|| "?".to_string(), |b| sizes.size(b)),
+                share.bytes.map_or_else(|| "?".to_string(), percent),
+            ]);
+        }
+        let overhead = self.overhead_bytes();
+        table.push(vec![
+            "container overhead".into(),
+            String::new(),
+            String::new(),
+            overhead.map_or_else(|| "?".to_string(), |b| sizes.size(b)),
+            overhead.map_or_else(|| "?".to_string(), percent),
+        ]);
+        table.push(vec![
+            "total".into(),
+            String::new(),
+            String::new(),
+            sizes.size(self.total_bytes),
+            percent(self.total_bytes),
+        ]);
+
+        let mut lines = table.render(max_width);
+        match self.method {
+            Method::Bitrate => {
+                lines.push("Sizes are bit rate times duration.".into());
+                let unknown = self.unknown_count();
+                if unknown > 0 {
+                    lines.push(format!(
+                        "{} stream{} record{} no bit rate; --exact sums the packets instead (reads the whole file).",
+                        unknown,
+                        if unknown == 1 { "" } else { "s" },
+                        if unknown == 1 { "s" } else { "" }
+                    ));
+                }
+            }
+            Method::Packets => lines.push("Sizes are summed packet sizes.".into()),
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::size::SizeUnits;
+
+    /// An MP4 with its bit rates recorded: 100 s of 2 Mb/s video and two
+    /// 128 kb/s audio tracks, plus a subtitle track.
+    const MP4_FIXTURE: &str = r#"{
+        "programs": [],
+        "streams": [
+            {"index": 0, "codec_name": "h264", "codec_type": "video",
+             "duration": "100.000000", "bit_rate": "2000000", "extradata_size": 45},
+            {"index": 1, "codec_name": "aac", "codec_type": "audio",
+             "duration": "100.000000", "bit_rate": "128000", "extradata_size": 2},
+            {"index": 2, "codec_name": "aac", "codec_type": "audio",
+             "duration": "100.000000", "bit_rate": "128000", "extradata_size": 2},
+            {"index": 3, "codec_name": "mov_text", "codec_type": "subtitle",
+             "duration": "98.500000", "bit_rate": "80", "extradata_size": 48}
+        ],
+        "format": {"duration": "100.000000", "size": "28500000", "bit_rate": "2280000"}
+    }"#;
+
+    /// A Matroska file: no per-stream bit rates or durations, and a font
+    /// attachment.
+    const MKV_FIXTURE: &str = r#"{
+        "programs": [],
+        "streams": [
+            {"index": 0, "codec_name": "hevc", "codec_type": "video", "extradata_size": 120},
+            {"index": 1, "codec_name": "opus", "codec_type": "audio", "extradata_size": 19},
+            {"index": 2, "codec_name": "ass", "codec_type": "subtitle", "extradata_size": 900,
+             "tags": {"language": "jpn"}},
+            {"index": 3, "codec_name": "ttf", "codec_type": "attachment", "extradata_size": 250000}
+        ],
+        "format": {"duration": "60.000000", "size": "16000000", "bit_rate": "2133333",
+                   "tags": {"COMMENT": "mdviqure 0.1.0: target=16MB codec=h264 br=copy audio=none"}}
+    }"#;
+
+    /// `-show_packets` of the MKV, summarized to a few packets per stream.
+    const MKV_PACKETS: &str =
+        "0,5000000\n1,300000\n0,9000000\n2,1200\n1,200000\n0,700000,\n\nbogus\n";
+
+    #[test]
+    fn test_streams_parsing() {
+        let file = parse_streams(MP4_FIXTURE).unwrap();
+        assert_eq!(file.size_bytes, Some(28_500_000));
+        assert_eq!(file.duration, Some(100.0));
+        assert_eq!(file.streams.len(), 4);
+        assert_eq!(file.streams[1].kind, StreamKind::Audio);
+        assert_eq!(file.streams[3].bit_rate, Some(80));
+
+        let file = parse_streams(MKV_FIXTURE).unwrap();
+        assert_eq!(file.streams[0].bit_rate, None);
+        assert_eq!(file.streams[3].kind, StreamKind::Attachment);
+        assert_eq!(file.streams[2].language.as_deref(), Some("jpn"));
+        assert_eq!(file.streams[3].language, None);
+        assert!(file.comment.unwrap().starts_with("mdviqure 0.1.0:"));
+        let empty = parse_streams(r#"{"format": {"size": "N/A"}}"#).unwrap();
+        assert_eq!(empty, FileStreams::default());
+    }
+
+    #[test]
+    fn test_packet_sizes_parsing() {
+        let sizes = parse_packet_sizes(MKV_PACKETS);
+        assert_eq!(sizes.get(&0), Some(&14_700_000));
+        assert_eq!(sizes.get(&1), Some(&500_000));
+        assert_eq!(sizes.get(&2), Some(&1200));
+        assert_eq!(sizes.get(&3), None);
+    }
+
+    #[test]
+    fn test_breakdown_from_bitrates() {
+        let file = parse_streams(MP4_FIXTURE).unwrap();
+        let breakdown = Breakdown::from_bitrates(&file, 28_500_000);
+        let bytes: Vec<Option<u64>> = breakdown.shares.iter().map(|s| s.bytes).collect();
+        // 80 b/s over the subtitle's own 98.5 s.
+        assert_eq!(
+            bytes,
+            [
+                Some(25_000_000),
+                Some(1_600_000),
+                Some(1_600_000),
+                Some(985)
+            ]
+        );
+        assert_eq!(breakdown.overhead_bytes(), Some(299_015));
+        // Estimates above the file size leave no overhead rather than a
+        // negative one.
+        let tight = Breakdown::from_bitrates(&file, 28_000_000);
+        assert_eq!(tight.overhead_bytes(), Some(0));
+        assert_eq!(breakdown.unknown_count(), 0);
+    }
+
+    #[test]
+    fn test_missing_bitrates_leave_overhead_unknown() {
+        let file = parse_streams(MKV_FIXTURE).unwrap();
+        let breakdown = Breakdown::from_bitrates(&file, 16_000_000);
+        assert_eq!(breakdown.shares[0].bytes, None);
+        assert_eq!(breakdown.shares[3].bytes, Some(250_000));
+        assert_eq!(breakdown.unknown_count(), 3);
+        assert_eq!(breakdown.overhead_bytes(), None);
+    }
+
+    #[test]
+    fn test_breakdown_from_packets() {
+        let file = parse_streams(MKV_FIXTURE).unwrap();
+        let packets = parse_packet_sizes(MKV_PACKETS);
+        let breakdown = Breakdown::from_packets(&file, 16_000_000, &packets);
+        let bytes: Vec<Option<u64>> = breakdown.shares.iter().map(|s| s.bytes).collect();
+        assert_eq!(
+            bytes,
+            [Some(14_700_000), Some(500_000), Some(1200), Some(250_000)]
+        );
+        assert_eq!(breakdown.overhead_bytes(), Some(548_800));
+    }
+
+    #[test]
+    fn test_render_bitrate_breakdown() {
+        let file = parse_streams(MP4_FIXTURE).unwrap();
+        let lines = Breakdown::from_bitrates(&file, 28_500_000).render(SizeUnits::Si.into(), 200);
+        assert_eq!(
+            lines,
+            [
+                "Stream              Codec     Bitrate     Size   Share",
+                "#0 video            h264        2000k    25 MB   87.7%",
+                "#1 audio            aac          128k   1.6 MB    5.6%",
+                "#2 audio            aac          128k   1.6 MB    5.6%",
+                "#3 subtitle         mov_text      <1k  0.99 KB    0.0%",
+                "container overhead                      0.3 MB    1.0%",
+                "total                                  28.5 MB  100.0%",
+                "Sizes are bit rate times duration.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_marks_unknown_sizes() {
+        let file = parse_streams(MKV_FIXTURE).unwrap();
+        let lines = Breakdown::from_bitrates(&file, 16_000_000).render(SizeUnits::Si.into(), 200);
+        assert!(lines[1].starts_with("#0 video"), "{}", lines[1]);
+        assert!(lines[1].ends_with("-        ?       ?"), "{}", lines[1]);
+        assert!(lines[5].starts_with("container overhead"), "{}", lines[5]);
+        assert!(lines[5].ends_with('?'), "{}", lines[5]);
+        assert_eq!(
+            lines.last().unwrap(),
+            "3 streams record no bit rate; --exact sums the packets instead (reads the whole file)."
+        );
+
+        let packets = parse_packet_sizes(MKV_PACKETS);
+        let lines =
+            Breakdown::from_packets(&file, 16_000_000, &packets).render(SizeUnits::Si.into(), 200);
+        assert!(lines[1].ends_with("14.7 MB   91.9%"), "{}", lines[1]);
+        assert!(lines[5].ends_with("0.55 MB    3.4%"), "{}", lines[5]);
+        assert_eq!(lines.last().unwrap(), "Sizes are summed packet sizes.");
+    }
+}
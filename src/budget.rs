@@ -0,0 +1,375 @@
+//! `--max-attempts` and `--max-total-time`: how often, and for how long, a
+//! run may try to write its output.
+//!
+//! Several things run ffmpeg again: a remux that comes out over the target
+//! is re-encoded, an encode that does is retried at a lower bitrate
+//! (`--max-retries`), one that fails to start with an error that may pass
+//! is started again (`--retries`), and one whose GPU decoder fails to
+//! start runs again decoding in software. Each of those is an [`Attempt`],
+//! and every attempt of a run goes into its [`Ledger`], which asks
+//! [`exhausted`] before starting the next one. The first attempt always
+//! runs. Both passes of a two-pass encode, and all the chunks of a chunked
+//! one, are one attempt; probing the input isn't one.
+//!
+//! The time counts from the start of the run, probing included. An attempt
+//! under way is never cut short for it, as that would throw away an encode
+//! that may be about to fit; `--timeout` is what bounds a single ffmpeg run.
+//!
+//! An attempt that comes in under the target is kept as soon as it is
+//! checked, so when the budget runs out there is nothing better to finish
+//! with: the run fails with [`ReduceError::BudgetExhausted`], which lists
+//! every attempt. The parts of a `--split` written by then stay in place.
+
+use crate::error::ReduceError;
+use crate::estimate::format_duration;
+use crate::sizefmt::SizeFormat;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The limits of a run; none by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Budget {
+    /// `--max-attempts`: attempts in all, the first one included.
+    pub max_attempts: Option<u32>,
+    /// `--max-total-time`: after this long, no attempt is started.
+    pub max_total_time: Option<Duration>,
+}
+
+/// Which limit of a [`Budget`] ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exhausted {
+    Attempts(u32),
+    Time(Duration),
+}
+
+impl fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exhausted::Attempts(1) => write!(f, "the one attempt --max-attempts allows is used"),
+            Exhausted::Attempts(max) => {
+                write!(f, "the {} attempts --max-attempts allows are used", max)
+            }
+            Exhausted::Time(max) => write!(
+                f,
+                "the {} --max-total-time allows is up",
+                format_duration(max.as_secs_f64())
+            ),
+        }
+    }
+}
+
+/// Whether another attempt may start after `attempts` of them, `elapsed`
+/// into the run; when not, the limit that ran out. The first attempt
+/// always may.
+pub fn exhausted(budget: &Budget, attempts: u32, elapsed: Duration) -> Option<Exhausted> {
+    if attempts == 0 {
+        return None;
+    }
+    if let Some(max) = budget.max_attempts.filter(|&max| attempts >= max) {
+        return Some(Exhausted::Attempts(max));
+    }
+    budget
+        .max_total_time
+        .filter(|&max| elapsed >= max)
+        .map(Exhausted::Time)
+}
+
+/// Why an attempt was made. The `snake_case` names are part of the JSON
+/// output, so existing ones must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttemptKind {
+    /// `--remux-only`'s copy of the streams.
+    Remux,
+    /// The first encode.
+    Encode,
+    /// An encode at a lower bitrate, after one came out over the target.
+    OverTargetRetry,
+    /// The same encode again, after it failed to start with an error that
+    /// may pass.
+    TransientRetry,
+    /// The same encode again decoding in software, after the GPU decoder
+    /// failed to start.
+    SoftwareDecode,
+}
+
+impl AttemptKind {
+    fn describe(self) -> &'static str {
+        match self {
+            AttemptKind::Remux => "remux",
+            AttemptKind::Encode => "encode",
+            AttemptKind::OverTargetRetry => "retry over the target",
+            AttemptKind::TransientRetry => "retry after a transient failure",
+            AttemptKind::SoftwareDecode => "retry decoding in software",
+        }
+    }
+}
+
+/// How an attempt ended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    /// Within the target, and kept.
+    Kept {
+        bytes: u64,
+    },
+    /// Written to stdout, where its size is never known.
+    Streamed,
+    OverTarget {
+        bytes: u64,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// One try at writing the output, as the report lists it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attempt {
+    pub kind: AttemptKind,
+    /// The video bitrate asked for, in bits per second; absent when the
+    /// video was copied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video_bitrate: Option<u64>,
+    /// The size it had to come within: the target, or a part's share of it.
+    pub target_bytes: u64,
+    /// How long it took.
+    pub seconds: f64,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+impl Attempt {
+    /// The attempt in a few words, with sizes in `sizes`.
+    pub fn describe(&self, sizes: SizeFormat) -> String {
+        let what = match self.video_bitrate {
+            Some(bitrate) => format!("{} at {}k", self.kind.describe(), bitrate / 1000),
+            None => self.kind.describe().to_string(),
+        };
+        match &self.outcome {
+            Outcome::Kept { bytes } => format!("{}: {}, kept", what, sizes.size(*bytes)),
+            Outcome::Streamed => format!("{}: streamed", what),
+            Outcome::OverTarget { bytes } => format!(
+                "{}: {}, over the target",
+                what,
+                sizes.size_against(*bytes, self.target_bytes)
+            ),
+            // ffmpeg's errors end with what it printed, which is too much
+            // for a list.
+            Outcome::Failed { error } => {
+                format!("{}: {}", what, error.lines().next().unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// The attempt under way: why, at what bitrate, within what size and since
+/// when.
+#[derive(Debug, Clone, Copy)]
+struct Current {
+    kind: AttemptKind,
+    video_bitrate: Option<u64>,
+    target_bytes: u64,
+    started: Instant,
+}
+
+/// The attempts of one run, and the budget they are held to.
+#[derive(Debug)]
+pub struct Ledger {
+    budget: Budget,
+    sizes: SizeFormat,
+    started: Instant,
+    attempts: RefCell<Vec<Attempt>>,
+    current: Cell<Option<Current>>,
+}
+
+impl Ledger {
+    /// The ledger of a run starting now, describing sizes in `sizes`.
+    pub fn new(budget: Budget, sizes: SizeFormat) -> Self {
+        Self {
+            budget,
+            sizes,
+            started: Instant::now(),
+            attempts: RefCell::new(Vec::new()),
+            current: Cell::new(None),
+        }
+    }
+
+    /// Fails when the budget has run out, so no attempt may start.
+    pub fn check(&self) -> Result<(), ReduceError> {
+        let count = self.attempts.borrow().len() as u32;
+        match exhausted(&self.budget, count, self.started.elapsed()) {
+            Some(limit) => Err(self.exhausted(limit)),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts an attempt that has to come within `target_bytes`, unless the
+    /// budget has run out.
+    pub fn begin(
+        &self,
+        kind: AttemptKind,
+        video_bitrate: Option<u64>,
+        target_bytes: u64,
+    ) -> Result<(), ReduceError> {
+        self.check()?;
+        self.current.set(Some(Current {
+            kind,
+            video_bitrate,
+            target_bytes,
+            started: Instant::now(),
+        }));
+        Ok(())
+    }
+
+    /// Records how the attempt under way ended.
+    pub fn end(&self, outcome: Outcome) {
+        if let Some(current) = self.current.take() {
+            self.attempts.borrow_mut().push(Attempt {
+                kind: current.kind,
+                video_bitrate: current.video_bitrate,
+                target_bytes: current.target_bytes,
+                seconds: current.started.elapsed().as_secs_f64(),
+                outcome,
+            });
+        }
+    }
+
+    /// Records that the attempt under way failed with `err`.
+    pub fn fail(&self, err: &ReduceError) {
+        self.end(Outcome::Failed {
+            error: err.to_string(),
+        });
+    }
+
+    /// The attempts so far, oldest first.
+    pub fn attempts(&self) -> Vec<Attempt> {
+        self.attempts.borrow().clone()
+    }
+
+    fn exhausted(&self, limit: Exhausted) -> ReduceError {
+        let attempts = self.attempts();
+        let tried: Vec<String> = attempts
+            .iter()
+            .enumerate()
+            .map(|(i, attempt)| format!("{}. {}", i + 1, attempt.describe(self.sizes)))
+            .collect();
+        ReduceError::BudgetExhausted {
+            message: format!(
+                "stopped {} into the run, as {}; tried {}",
+                format_duration(self.started.elapsed().as_secs_f64()),
+                limit,
+                tried.join("; ")
+            ),
+            attempts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::size::SizeUnits;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_the_budget_allows_the_first_attempt_and_stops_later_ones() {
+        let none = Budget::default();
+        let both = Budget {
+            max_attempts: Some(3),
+            max_total_time: Some(10 * MINUTE),
+        };
+        let once = Budget {
+            max_attempts: Some(1),
+            ..Budget::default()
+        };
+        // (budget, attempts so far, elapsed, exhausted)
+        let cases = [
+            (none, 40, 600 * MINUTE, None),
+            (both, 0, 20 * MINUTE, None),
+            (once, 0, Duration::ZERO, None),
+            (once, 1, Duration::ZERO, Some(Exhausted::Attempts(1))),
+            (both, 2, 9 * MINUTE, None),
+            (both, 3, MINUTE, Some(Exhausted::Attempts(3))),
+            (both, 1, 10 * MINUTE, Some(Exhausted::Time(10 * MINUTE))),
+            // The count is checked first, as it doesn't depend on timing.
+            (both, 3, 20 * MINUTE, Some(Exhausted::Attempts(3))),
+        ];
+        for (budget, attempts, elapsed, expected) in cases {
+            assert_eq!(
+                exhausted(&budget, attempts, elapsed),
+                expected,
+                "{:?} after {} in {:?}",
+                budget,
+                attempts,
+                elapsed
+            );
+        }
+    }
+
+    #[test]
+    fn test_an_exhausted_budget_lists_every_attempt() {
+        let budget = Budget {
+            max_attempts: Some(3),
+            ..Budget::default()
+        };
+        let sizes = SizeFormat {
+            units: SizeUnits::Si,
+            ..SizeFormat::default()
+        };
+        let ledger = Ledger::new(budget, sizes);
+        ledger.begin(AttemptKind::Remux, None, 50_000_000).unwrap();
+        ledger.end(Outcome::OverTarget { bytes: 60_000_000 });
+        ledger
+            .begin(AttemptKind::Encode, Some(800_000), 50_000_000)
+            .unwrap();
+        ledger.fail(&ReduceError::Encode(
+            "ffmpeg failed: Input/output error\n[last lines of ffmpeg]".into(),
+        ));
+        ledger
+            .begin(AttemptKind::TransientRetry, Some(800_000), 50_000_000)
+            .unwrap();
+        // Just over the target, which it must not read as.
+        ledger.end(Outcome::OverTarget { bytes: 50_000_001 });
+
+        let err = ledger
+            .begin(AttemptKind::OverTargetRetry, Some(760_000), 50_000_000)
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 12);
+        let message = err.to_string();
+        assert!(
+            message.ends_with(
+                "as the 3 attempts --max-attempts allows are used; tried \
+                 1. remux: 60 MB, over the target; \
+                 2. encode at 800k: ffmpeg failed: Input/output error; \
+                 3. retry after a transient failure at 800k: 50.01 MB, over the target"
+            ),
+            "{}",
+            message
+        );
+        let ReduceError::BudgetExhausted { attempts, .. } = err else {
+            unreachable!()
+        };
+        assert_eq!(attempts, ledger.attempts());
+        assert_eq!(
+            attempts.iter().map(|a| a.kind).collect::<Vec<_>>(),
+            [
+                AttemptKind::Remux,
+                AttemptKind::Encode,
+                AttemptKind::TransientRetry
+            ]
+        );
+
+        let json = serde_json::to_value(&attempts[2]).unwrap();
+        assert_eq!(json["kind"], "transient_retry");
+        assert_eq!(json["video_bitrate"], 800_000);
+        assert_eq!(json["outcome"], "over_target");
+        assert_eq!(json["target_bytes"], 50_000_000);
+        assert_eq!(json["bytes"], 50_000_001);
+        let back: Attempt = serde_json::from_value(json).unwrap();
+        assert_eq!(back, attempts[2]);
+    }
+}
@@ -0,0 +1,240 @@
+//! Reading what ffmpeg prints with bounded memory.
+//!
+//! A long encode at a chatty log level can print hundreds of megabytes to
+//! stderr, of which only the end explains a failure. [`Tail`] keeps the
+//! last bytes of a stream and nothing more, and [`Lines`] cuts a stream
+//! read in arbitrary chunks back into lines, however the reads split them,
+//! keeping at most one line in memory and only so much of that.
+
+use std::collections::VecDeque;
+
+/// The last `capacity` bytes pushed into it.
+#[derive(Debug, Clone)]
+pub struct Tail {
+    bytes: VecDeque<u8>,
+    capacity: usize,
+    dropped: u64,
+}
+
+impl Tail {
+    pub fn new(capacity: usize) -> Self {
+        Tail {
+            bytes: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        let chunk = if chunk.len() > self.capacity {
+            self.dropped += (chunk.len() - self.capacity) as u64;
+            &chunk[chunk.len() - self.capacity..]
+        } else {
+            chunk
+        };
+        let overflow = (self.bytes.len() + chunk.len()).saturating_sub(self.capacity);
+        self.bytes.drain(..overflow);
+        self.dropped += overflow as u64;
+        self.bytes.extend(chunk);
+    }
+
+    /// How many bytes have been pushed out of it.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.bytes.iter().copied().collect()
+    }
+
+    /// Its last `n` lines that aren't blank, without the first one kept
+    /// when the start of that line was pushed out.
+    pub fn last_lines(&self, n: usize) -> Vec<String> {
+        let text = String::from_utf8_lossy(&self.bytes()).into_owned();
+        let mut lines: Vec<&str> = text.split(['\n', '\r']).collect();
+        if self.dropped > 0 {
+            lines.remove(0);
+        }
+        let lines: Vec<&str> = lines
+            .into_iter()
+            .map(str::trim_end)
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        lines[lines.len().saturating_sub(n)..]
+            .iter()
+            .map(|line| line.to_string())
+            .collect()
+    }
+}
+
+/// Lines out of a stream read in chunks. A line ends at `\n` or `\r`
+/// (ffmpeg rewrites its status line with `\r`), and empty lines are passed
+/// over, so `\r\n` ends one line. A line longer than `max_len` bytes is cut
+/// there; invalid UTF-8 becomes U+FFFD.
+#[derive(Debug, Clone)]
+pub struct Lines {
+    partial: Vec<u8>,
+    max_len: usize,
+}
+
+impl Lines {
+    pub fn new(max_len: usize) -> Self {
+        Lines {
+            partial: Vec::new(),
+            max_len,
+        }
+    }
+
+    /// The lines `chunk` completes.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut rest = chunk;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n' || b == b'\r') {
+            self.take(&rest[..end]);
+            lines.extend(self.finish());
+            rest = &rest[end + 1..];
+        }
+        self.take(rest);
+        lines
+    }
+
+    /// The line left unterminated at the end of the stream, if any.
+    pub fn finish(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.partial);
+        (!line.is_empty()).then(|| String::from_utf8_lossy(&line).into_owned())
+    }
+
+    fn take(&mut self, bytes: &[u8]) {
+        let room = self.max_len.saturating_sub(self.partial.len());
+        self.partial.extend(&bytes[..bytes.len().min(room)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A xorshift generator, so every run tries the same splits.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        /// `bytes` cut into chunks of 1 to `max` bytes.
+        fn chunks<'a>(&mut self, bytes: &'a [u8], max: usize) -> Vec<&'a [u8]> {
+            let mut chunks = Vec::new();
+            let mut rest = bytes;
+            while !rest.is_empty() {
+                let len = (1 + self.below(max)).min(rest.len());
+                let (chunk, after) = rest.split_at(len);
+                chunks.push(chunk);
+                rest = after;
+            }
+            chunks
+        }
+
+        /// Text of ffmpeg-like lines ending in `\n`, `\r` or `\r\n`, with
+        /// the odd blank line and invalid byte.
+        fn output(&mut self) -> Vec<u8> {
+            let words: [&[u8]; 6] = [
+                b"frame=  120",
+                b"out_time_us=4000000",
+                b"[h264 @ 0x55] error while decoding MB 12 34",
+                b"progress=continue",
+                "Ünïcode".as_bytes(),
+                b"\xff\xfe",
+            ];
+            let ends: [&[u8]; 4] = [b"\n", b"\r", b"\r\n", b"\n\n"];
+            let mut out = Vec::new();
+            for _ in 0..self.below(40) {
+                for _ in 0..1 + self.below(3) {
+                    out.extend(words[self.below(words.len())]);
+                    out.push(b' ');
+                }
+                out.extend(ends[self.below(ends.len())]);
+            }
+            if self.below(2) == 0 {
+                out.extend(b"speed=2x");
+            }
+            out
+        }
+    }
+
+    /// The lines of `bytes` read all at once, cut at `max_len`.
+    fn expected_lines(bytes: &[u8], max_len: usize) -> Vec<String> {
+        bytes
+            .split(|&b| b == b'\n' || b == b'\r')
+            .filter(|line| !line.is_empty())
+            .map(|line| String::from_utf8_lossy(&line[..line.len().min(max_len)]).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_lines_come_out_the_same_however_the_reads_split_them() {
+        for seed in 1..500 {
+            let mut rng = Rng(seed);
+            let output = rng.output();
+            let max_len = [8, 30, 4096][rng.below(3)];
+            let mut lines = Lines::new(max_len);
+            let mut got = Vec::new();
+            let max_chunk = 1 + rng.below(64);
+            for chunk in rng.chunks(&output, max_chunk) {
+                got.extend(lines.feed(chunk));
+            }
+            got.extend(lines.finish());
+            assert_eq!(got, expected_lines(&output, max_len), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_an_overlong_line_is_cut_and_the_next_one_whole() {
+        let mut lines = Lines::new(4);
+        assert!(lines.feed(b"abcdefgh").is_empty());
+        assert_eq!(lines.feed(b"ij\nxy\r"), ["abcd", "xy"]);
+        assert_eq!(lines.finish(), None);
+        // A multibyte character cut in two reads as a replacement.
+        let mut lines = Lines::new(2);
+        assert_eq!(lines.feed("aé\n".as_bytes()), ["a\u{fffd}"]);
+    }
+
+    #[test]
+    fn test_tail_keeps_the_last_bytes_however_they_are_pushed() {
+        for seed in 1..500 {
+            let mut rng = Rng(seed);
+            let output = rng.output();
+            let capacity = 1 + rng.below(200);
+            let mut tail = Tail::new(capacity);
+            let max_chunk = 1 + rng.below(300);
+            for chunk in rng.chunks(&output, max_chunk) {
+                tail.push(chunk);
+            }
+            let kept = output.len().min(capacity);
+            assert_eq!(
+                tail.bytes(),
+                &output[output.len() - kept..],
+                "seed {}",
+                seed
+            );
+            assert_eq!(tail.dropped(), (output.len() - kept) as u64);
+        }
+    }
+
+    #[test]
+    fn test_last_lines_leave_out_a_line_cut_at_the_start() {
+        let mut tail = Tail::new(1000);
+        tail.push(b"first\r\nsecond\n\nthird  \n");
+        assert_eq!(tail.last_lines(2), ["second", "third"]);
+        assert_eq!(tail.last_lines(20), ["first", "second", "third"]);
+
+        let mut tail = Tail::new(12);
+        tail.push(b"first\nsecond\nthird\n");
+        // "econd\nthird\n" is kept; the start of "second" is gone.
+        assert_eq!(tail.last_lines(20), ["third"]);
+        assert_eq!(Tail::new(4).last_lines(20), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,192 @@
+//! `--checksum`: hashes of the input and of what was written, for
+//! pipelines that check nothing was corrupted on the way to storage.
+//!
+//! Files are read in blocks, never whole. The input is hashed on a thread
+//! of its own while the encode runs ([`Pending`]), the outputs once they
+//! are in place.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread::JoinHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Blake3 => "blake3",
+        })
+    }
+}
+
+/// The hashes of a run, as lowercase hex.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checksums {
+    pub algorithm: Algorithm,
+    /// Absent for an input that isn't a file: a URL, or stdin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+    /// Each file written: the output, or each of its `--split` parts.
+    pub outputs: Vec<FileChecksum>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileChecksum {
+    pub path: String,
+    pub hash: String,
+}
+
+impl Checksums {
+    /// Each hash and the file it is of, `input` first, as `sha256sum`
+    /// lists them.
+    pub fn lines<'a>(&'a self, input: &'a str) -> Vec<(&'a str, &'a str)> {
+        let input = self.input.as_deref().map(|hash| (hash, input));
+        input
+            .into_iter()
+            .chain(
+                self.outputs
+                    .iter()
+                    .map(|f| (f.hash.as_str(), f.path.as_str())),
+            )
+            .collect()
+    }
+}
+
+/// The hash of everything `reader` yields.
+pub fn of_reader(algorithm: Algorithm, mut reader: impl Read) -> io::Result<String> {
+    let mut hasher = Hasher::new(algorithm);
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hex(&hasher.finish()))
+}
+
+/// The hash of the file at `path`.
+pub fn of_file(algorithm: Algorithm, path: &Path) -> io::Result<String> {
+    of_reader(algorithm, File::open(path)?)
+}
+
+/// A file being hashed in the background.
+pub struct Pending(JoinHandle<io::Result<String>>);
+
+impl Pending {
+    /// Starts hashing `path`. Dropping the result leaves the thread to
+    /// finish on its own.
+    pub fn start(algorithm: Algorithm, path: &Path) -> Self {
+        let path = path.to_path_buf();
+        Self(std::thread::spawn(move || of_file(algorithm, &path)))
+    }
+
+    /// Waits for the hash.
+    pub fn wait(self) -> io::Result<String> {
+        self.0
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("the hashing thread panicked")))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, input: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(input),
+            Hasher::Blake3(h) => {
+                h.update(input);
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(h) => h.finalize().to_vec(),
+            Hasher::Blake3(h) => h.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    fn hash(algorithm: Algorithm, input: &[u8]) -> String {
+        of_reader(algorithm, input).unwrap()
+    }
+
+    /// The input of the BLAKE3 test vectors: bytes counting up modulo 251.
+    fn counting(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_hashes_match_the_published_vectors() {
+        let cases = [
+            (
+                Algorithm::Sha256,
+                &b"abc"[..],
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                Algorithm::Blake3,
+                b"abc",
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+        ];
+        for (algorithm, input, expected) in cases {
+            assert_eq!(hash(algorithm, input), expected, "{}", algorithm);
+        }
+        // Longer than the buffer `of_reader` reads into, so it takes a few
+        // reads.
+        let long = [b'a'; 1_000_000];
+        assert_eq!(
+            hash(Algorithm::Sha256, &long),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn test_files_hash_in_the_background() {
+        let dir = TestDir::new();
+        let path = dir.path().join("in.bin");
+        std::fs::write(&path, counting(200_000)).unwrap();
+        let pending = Pending::start(Algorithm::Blake3, &path);
+        assert_eq!(
+            pending.wait().unwrap(),
+            hash(Algorithm::Blake3, &counting(200_000))
+        );
+        let missing = Pending::start(Algorithm::Sha256, &dir.path().join("missing"));
+        assert_eq!(missing.wait().unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,106 @@
+//! `--chunked-encode`: encoding one file as several segments at once.
+//!
+//! x264 at low resolutions leaves most cores idle, so the timeline is cut
+//! at frame boundaries into chunks that separate ffmpeg processes encode
+//! with the same settings and bitrate. Each encoder starts its chunk with a
+//! keyframe, which is what lets the concat demuxer join them without
+//! re-encoding. The audio is encoded once over the whole input and muxed in
+//! at the join, so chunk boundaries can't leave gaps or clicks in it.
+
+use crate::reduce::Segment;
+use std::path::Path;
+
+/// Chunks shorter than this aren't worth a process of their own.
+pub const MIN_CHUNK_SECONDS: f64 = 10.0;
+
+/// Chunks when `--chunked-encode` is given without a count: one per core.
+pub fn default_count() -> u32 {
+    std::thread::available_parallelism().map_or(1, |n| n.get() as u32)
+}
+
+/// How many chunks `requested` becomes for an input of `duration`
+/// seconds; 1 means encoding in one piece.
+pub fn chunk_count(requested: u32, duration: f64) -> u32 {
+    let most = (duration / MIN_CHUNK_SECONDS) as u32;
+    requested.min(most).max(1)
+}
+
+/// Splits `0..duration` into `count` chunks of about equal length.
+///
+/// Cuts fall halfway between two frames at `fps`, so the millisecond
+/// precision of `-ss` and `-t` can't put a frame in two chunks or in none.
+/// Seeking as an input option is frame-accurate, and each chunk's encoder
+/// opens with a keyframe, so no chunk depends on frames of another.
+pub fn chunk_bounds(duration: f64, count: u32, fps: f64) -> Vec<Segment> {
+    let count = count.max(1);
+    let frame = 1.0 / fps;
+    let boundary = |i: u32| {
+        if i == 0 {
+            0.0
+        } else if i == count {
+            duration
+        } else {
+            ((duration * i as f64 / count as f64 / frame).round() - 0.5) * frame
+        }
+    };
+    (0..count)
+        .map(|i| Segment {
+            start: boundary(i),
+            length: boundary(i + 1) - boundary(i),
+        })
+        .collect()
+}
+
+/// The concat demuxer's list of `chunks`, in order. Single quotes in paths
+/// are escaped the way the demuxer expects.
+pub fn concat_list(chunks: &[impl AsRef<Path>]) -> String {
+    chunks
+        .iter()
+        .map(|chunk| {
+            let path = chunk.as_ref().to_string_lossy().replace('\'', r"'\''");
+            format!("file '{}'\n", path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_inputs_get_fewer_chunks() {
+        assert_eq!(chunk_count(4, 7200.0), 4);
+        assert_eq!(chunk_count(8, 35.0), 3);
+        assert_eq!(chunk_count(4, 5.0), 1);
+        assert_eq!(chunk_count(0, 7200.0), 1);
+    }
+
+    #[test]
+    fn test_chunks_split_on_frames_and_cover_the_input() {
+        let chunks = chunk_bounds(100.0, 3, 30.0);
+        assert_eq!(chunks.len(), 3);
+        // Frame 1000 is at 33.333 s; the cut falls half a frame before it.
+        assert!((chunks[0].length - 999.5 / 30.0).abs() < 1e-9);
+        assert_eq!(chunks[1].start, chunks[0].start + chunks[0].length);
+        assert_eq!(chunks[2].start + chunks[2].length, 100.0);
+        for chunk in &chunks[1..] {
+            let frames = chunk.start * 30.0 + 0.5;
+            assert!((frames - frames.round()).abs() < 1e-6, "{}", chunk.start);
+        }
+        assert_eq!(
+            chunk_bounds(10.0, 1, 25.0),
+            [Segment {
+                start: 0.0,
+                length: 10.0
+            }]
+        );
+    }
+
+    #[test]
+    fn test_concat_list_escapes_quotes() {
+        assert_eq!(
+            concat_list(&["/tmp/a.mkv", "/tmp/it's.mkv"]),
+            "file '/tmp/a.mkv'\nfile '/tmp/it'\\''s.mkv'\n"
+        );
+    }
+}
@@ -0,0 +1,2846 @@
+//! Command-line parsing and the top-level application flow.
+
+use crate::archive::{Layout, Zone};
+use crate::audio::{parse_audio_selection, AudioSelection};
+use crate::batch::{self, BatchOptions};
+use crate::breakdown::{self, Breakdown};
+use crate::budget::Budget;
+use crate::checksum::Algorithm;
+use crate::chunked;
+use crate::compare;
+use crate::config::Config;
+use crate::console::{self, Console};
+use crate::device::{self, Compat, Constraints, Device};
+use crate::effort;
+use crate::encoder::{EncoderParams, Preset, VideoEncoder};
+use crate::error::ReduceError;
+use crate::estimate::{Quality, DEFAULT_FPS};
+use crate::events::{self, Event, Report};
+use crate::filename::{self, NameRules};
+use crate::filetarget::TargetFrom;
+use crate::filter::EvenMode;
+use crate::floor::Floor;
+use crate::history::{format_timestamp, Entry, Fingerprint, History, RESULT_OK};
+use crate::hls::{self, Format, Hls};
+use crate::hwdecode::HwDecode;
+use crate::images::ImageInput;
+use crate::interactive;
+use crate::ladder;
+use crate::launch::{self, Platform};
+use crate::limit;
+use crate::manifest::Manifest;
+use crate::notify::Notice;
+use crate::overhead::{self, Learned};
+use crate::planner::{self, Scenario, Summary};
+use crate::presenter::{Align, ColorChoice, Column, Presenter, Table};
+use crate::probecache::Cached;
+use crate::profile::{self, Profile};
+use crate::prompt::Prompter;
+use crate::ratecontrol::Mode;
+use crate::reduce::{
+    part_output_path, probe_source, reduce_video, sample_output_path, ReduceOptions, ReduceReport,
+    Source,
+};
+use crate::region::{self, Region};
+use crate::size::{parse_bitrate, parse_size, SizeUnits};
+use crate::sizefmt::{self, Grouping, Separators, SizeFormat};
+use crate::sleep;
+use crate::template::Template;
+use crate::terminal::OutputMode;
+use crate::timecode::parse_time;
+use crate::tool::{FfmpegTool, VideoTool};
+use crate::url;
+use crate::validate::{self, Capabilities};
+use crate::vertical;
+use crate::warning;
+use crate::STDIO_PATH;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Reduce MP4 video quality to fit within a target size using FFMPEG", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Reduce several files into one directory and print a summary table
+    Batch(Box<BatchArgs>),
+    /// List the files reduced so far, most recent first
+    History(HistoryArgs),
+    /// Show the container overhead learned from earlier runs
+    Stats(StatsArgs),
+    /// Show a file's streams, or with --breakdown where its bytes go
+    Probe(ProbeArgs),
+    /// Work out the bitrate and expected quality for a length, target size
+    /// and frame size, without an input file or ffmpeg
+    Plan(PlanArgs),
+}
+
+/// Reducing a single file.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Input video file (MP4), an http:// or https:// URL, or `-` to read
+    /// from stdin
+    ///
+    /// Stdin is copied into the run's temp directory before probing, so it
+    /// needs as much free space there as the input itself. A URL is read by
+    /// ffprobe and ffmpeg directly (see --download-first). With
+    /// --input-pattern, give only the output.
+    #[arg(required_unless_present_any = ["explain_effort", "list_devices"])]
+    pub input: Option<String>,
+
+    /// Output video file, or `-` to write to stdout
+    ///
+    /// Streamed output is always fragmented MP4, and all status messages go
+    /// to stderr instead. There is no progress display, and a failed encode
+    /// may already have written part of the stream.
+    #[arg(required_unless_present_any = ["input_pattern", "explain_effort", "list_devices"])]
+    pub output: Option<String>,
+
+    /// Print what each --effort level means for every codec, and exit
+    #[arg(long, exclusive = true)]
+    pub explain_effort: bool,
+
+    /// Print what each --device stands for, and exit
+    #[arg(long, exclusive = true)]
+    pub list_devices: bool,
+
+    /// Read numbered image frames such as frames/%05d.png instead of a video
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        requires = "input_fps",
+        conflicts_with = "loop_duration"
+    )]
+    pub input_pattern: Option<String>,
+
+    /// Frame rate of the --input-pattern frames
+    #[arg(long, value_name = "FPS", requires = "input_pattern")]
+    pub input_fps: Option<f64>,
+
+    /// Treat INPUT as a still image and loop it into a clip of this length
+    /// (seconds or hh:mm:ss)
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub loop_duration: Option<f64>,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+
+    /// Show the plan and ask for confirmation before encoding, offering
+    /// fixes when the expected quality is poor (needs a terminal)
+    #[arg(long)]
+    pub interactive: bool,
+
+    /// Probe the input and print the encoding plan (bitrate steps, filters,
+    /// predicted size) without encoding anything
+    #[arg(long, conflicts_with_all = ["interactive", "open", "reveal", "notify", "compare"])]
+    pub dry_run: bool,
+
+    /// Plan the input at several target sizes (100,50,25,10,8 MB unless
+    /// given) and print how each would come out, without encoding
+    #[arg(
+        long,
+        value_name = "SIZES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ladder::DEFAULT_LADDER
+    )]
+    pub size_ladder: Option<String>,
+
+    /// After --size-ladder, encode at the smallest size whose expected
+    /// quality is at least this: poor, fair or good
+    #[arg(long, value_enum, value_name = "QUALITY", requires = "size_ladder")]
+    pub pick_best_under: Option<Quality>,
+
+    /// human: status text on stdout; json: status on stderr, and one JSON
+    /// object with the result and any warnings on stdout when the run ends
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Human)]
+    pub output_format: OutputFormat,
+
+    /// Print how the run went as one line on stdout when it ends, e.g.
+    /// `demo.mp4: 412.3 MB → 49.6 MB (-88%) h264 1080p→720p 2:13 elapsed`,
+    /// and nothing else; only an error still goes to stderr
+    #[arg(long)]
+    pub summary_line: bool,
+
+    /// Copy a URL input into the temp directory before probing, for servers
+    /// that handle range requests badly; every encode attempt then reads the
+    /// local copy
+    #[arg(long)]
+    pub download_first: bool,
+
+    /// Open the result in the default player once it is done
+    #[arg(long)]
+    pub open: bool,
+
+    /// Show the result in the file manager once it is done (selected where
+    /// the platform supports it)
+    #[arg(long)]
+    pub reveal: bool,
+
+    /// Also write a 10-second clip of the input and the output side by
+    /// side, as <OUTPUT stem>_compare.mp4, from the window where the source
+    /// spends the most bits
+    #[arg(long, conflicts_with = "split")]
+    pub compare: bool,
+
+    /// Start the --compare clip at TIME (seconds or hh:mm:ss[.fff]) instead
+    #[arg(long, value_name = "TIME", value_parser = parse_time, requires = "compare")]
+    pub compare_at: Option<f64>,
+
+    /// file: OUTPUT is one video file; hls: OUTPUT is a directory that gets
+    /// an index.m3u8 playlist and fragmented MP4 segments, all of them
+    /// together within the target size
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = Format::File)]
+    pub format: Format,
+
+    /// Length of each --format hls segment, in seconds
+    #[arg(long, value_name = "SECONDS", value_parser = hls::parse_segment_seconds)]
+    pub hls_time: Option<f64>,
+
+    /// Keep the stats of the --two-pass first pass in FILE, with a header
+    /// beside it saying what they were made for, for --stats to reuse
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "two_pass",
+        conflicts_with = "stats"
+    )]
+    pub save_stats: Option<PathBuf>,
+
+    /// Skip the --two-pass first pass, reusing the stats --save-stats kept;
+    /// refused unless the input, encoder, frame size and rate are the same
+    #[arg(long, value_name = "FILE", requires = "two_pass")]
+    pub stats: Option<PathBuf>,
+}
+
+/// Reducing several files with the same settings.
+#[derive(clap::Args, Debug)]
+pub struct BatchArgs {
+    /// Input video files
+    #[arg(required_unless_present = "manifest")]
+    pub inputs: Vec<String>,
+
+    /// Directory to write the reduced files to, under their original names;
+    /// may use the --name-template placeholders (e.g. archive/{year}/{month})
+    #[arg(short, long, value_name = "DIR", required_unless_present = "manifest")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Reduce the jobs listed in this TOML file instead (JSON unless the
+    /// name ends in .toml), each with its own input, output and settings
+    /// over the defaults the file gives and the options given here
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["inputs", "output_dir", "name_template", "archive_layout"])]
+    pub manifest: Option<PathBuf>,
+
+    /// Output file name, from the placeholders {stem}, {ext}, {size}, {date},
+    /// {year}, {month}, {day}, {recorded}, {width} and {height}
+    /// (e.g. {date}_{stem}_{size}MB.{ext})
+    #[arg(long, value_name = "TEMPLATE")]
+    pub name_template: Option<String>,
+
+    /// File each output under the output directory by when it was
+    /// recorded: `date` writes into YYYY/MM/, creating them as needed
+    #[arg(long, value_enum, value_name = "LAYOUT")]
+    pub archive_layout: Option<Layout>,
+
+    /// Time zone of the recording and modification dates: utc or local
+    #[arg(long, value_enum, value_name = "ZONE", default_value_t = Zone::Utc)]
+    pub archive_tz: Zone,
+
+    /// Keep the output file names to what Windows allows (no : ? * and
+    /// the like, no CON or NUL), wherever the batch runs
+    #[arg(long)]
+    pub portable_names: bool,
+
+    /// Shorten output file names longer than this many bytes, keeping
+    /// their extension
+    #[arg(long, value_name = "BYTES", default_value_t = filename::MAX_BYTES,
+          value_parser = parse_name_bytes)]
+    pub max_name_bytes: usize,
+
+    /// Reduce every input, even those the history or a saved batch state
+    /// shows as already reduced for this target
+    #[arg(long)]
+    pub redo: bool,
+
+    /// Continue the batch saved in this state file (by default a batch picks
+    /// up its own interrupted run from <DIR>/.mdviqure-batch.json)
+    #[arg(long, value_name = "STATE_FILE")]
+    pub resume: Option<PathBuf>,
+
+    /// History file to consult and record into (defaults to history.jsonl
+    /// in the mdviqure config directory)
+    #[arg(long, value_name = "FILE")]
+    pub history_file: Option<PathBuf>,
+
+    /// Stop before a file could take the outputs' total past this size
+    /// (e.g. 2G), in the units of --size-units
+    #[arg(long, value_name = "SIZE")]
+    pub max_total_size: Option<String>,
+
+    /// Instead of stopping at --max-total-size, lower the targets of the
+    /// remaining files so they share what is left, by duration
+    #[arg(long, requires = "max_total_size")]
+    pub fit_remaining: bool,
+
+    /// Where each input's target is read: filename-suffix takes the size
+    /// after the last __ of its name (clip__25MB.mp4), sidecar the size in
+    /// <input>.target; inputs without one, and all with fixed, get --size
+    #[arg(long, value_enum, value_name = "SOURCE", default_value_t = TargetFrom::Fixed,
+          conflicts_with = "manifest")]
+    pub target_from: TargetFrom,
+
+    /// Reduce this many files at once, each with its own ffmpeg; their
+    /// progress is then printed as plain lines
+    #[arg(short, long, value_name = "N", default_value_t = 1,
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: u32,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Showing or clearing the batch history.
+#[derive(clap::Args, Debug)]
+pub struct HistoryArgs {
+    #[command(subcommand)]
+    pub action: Option<HistoryAction>,
+
+    /// History file to read (defaults to history.jsonl in the mdviqure
+    /// config directory)
+    #[arg(long, value_name = "FILE", global = true)]
+    pub history_file: Option<PathBuf>,
+
+    /// Show at most this many entries
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 20)]
+    pub limit: usize,
+
+    #[command(flatten)]
+    pub display: DisplayArgs,
+}
+
+/// Inspecting a file without reducing it.
+#[derive(clap::Args, Debug)]
+pub struct ProbeArgs {
+    /// Video file or http:// or https:// URL to inspect
+    pub input: String,
+
+    /// Split the file's size into video, audio, subtitle and attachment
+    /// bytes and container overhead
+    ///
+    /// Stream sizes are bit rate times duration as the headers record them.
+    /// Containers such as MKV and MPEG-TS often record no bit rate; use
+    /// --exact there.
+    #[arg(long)]
+    pub breakdown: bool,
+
+    /// Sum every packet's size instead of using bit rates (reads the whole
+    /// file)
+    #[arg(long, requires = "breakdown")]
+    pub exact: bool,
+
+    /// Whether MB means 1000 (si) or 1024 (binary) squared bytes
+    #[arg(long, value_enum, default_value_t = SizeUnits::Binary)]
+    pub size_units: SizeUnits,
+
+    #[command(flatten)]
+    pub display: DisplayArgs,
+}
+
+/// Planning an encode from numbers alone.
+#[derive(clap::Args, Debug)]
+pub struct PlanArgs {
+    /// Length of the video, in seconds or hh:mm:ss
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub duration: f64,
+
+    /// Target size, e.g. 50, 25MB, 500KB or 1.5GiB (a bare number is megabytes)
+    #[arg(short, long, default_value = "100")]
+    pub size: String,
+
+    /// Whether MB/KB/GB mean powers of 1000 (si) or 1024 (binary); KiB/MiB/GiB
+    /// are always binary
+    #[arg(long, value_enum, default_value_t = SizeUnits::Binary)]
+    pub size_units: SizeUnits,
+
+    #[command(flatten)]
+    pub display: DisplayArgs,
+
+    /// Frame size of the video, e.g. 1280x720
+    #[arg(long, value_name = "WxH", default_value = "1920x1080", value_parser = planner::parse_resolution)]
+    pub resolution: (u32, u32),
+
+    /// Frame rate of the video
+    #[arg(long, default_value_t = DEFAULT_FPS)]
+    pub fps: f64,
+
+    /// Bitrate of the audio track, e.g. 96k, instead of 128k for stereo
+    #[arg(long, value_name = "BITRATE", value_parser = parse_bitrate, conflicts_with = "no_audio")]
+    pub audio_bitrate: Option<u64>,
+
+    /// Plan without an audio track
+    #[arg(long)]
+    pub no_audio: bool,
+
+    /// Video encoder to plan for
+    #[arg(long, value_enum, default_value_t = VideoEncoder::H264)]
+    pub codec: VideoEncoder,
+
+    /// Share of the target set aside for container overhead, in percent
+    #[arg(long, value_name = "PERCENT", default_value_t = 0.0, value_parser = parse_overhead)]
+    pub overhead_percent: f64,
+
+    /// human: the plan as text; json: one JSON object with the numbers
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Human)]
+    pub output_format: OutputFormat,
+}
+
+/// Showing what the history taught about container overhead.
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// History file to learn from (defaults to history.jsonl in the
+    /// mdviqure config directory)
+    #[arg(long, value_name = "FILE")]
+    pub history_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryAction {
+    /// Delete every history entry
+    Clear,
+}
+
+/// The shortest `--max-name-bytes`: room for a stem, a part number and an
+/// extension.
+const MIN_NAME_BYTES: usize = 16;
+
+fn parse_name_bytes(text: &str) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(bytes) if (MIN_NAME_BYTES..=filename::MAX_BYTES).contains(&bytes) => Ok(bytes),
+        _ => Err(format!(
+            "invalid name length '{}': expected bytes from {} to {}",
+            text,
+            MIN_NAME_BYTES,
+            filename::MAX_BYTES
+        )),
+    }
+}
+
+fn parse_overhead(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(percent) if (0.0..50.0).contains(&percent) => Ok(percent),
+        _ => Err(format!(
+            "invalid overhead '{}': expected a percentage from 0 to below 50",
+            text
+        )),
+    }
+}
+
+fn parse_x264_params(text: &str) -> Result<EncoderParams, String> {
+    EncoderParams::parse(VideoEncoder::H264, text)
+}
+
+fn parse_svtav1_params(text: &str) -> Result<EncoderParams, String> {
+    EncoderParams::parse(VideoEncoder::SvtAv1, text)
+}
+
+/// What a single-file run prints on stdout (`--output-format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+/// The report of a single run, for `--output-format json` and the event
+/// stream.
+fn run_report(
+    input: &str,
+    output: &str,
+    opts: &ReduceOptions,
+    result: &Result<ReduceReport, ReduceError>,
+) -> Report {
+    match result {
+        Ok(report) => Report::reduced(input, output, opts, report),
+        // A failed run never reached its report.
+        Err(e) => Report::failed(input, output, opts, e, warning::take()),
+    }
+}
+
+/// How sizes are shown, by every command that shows them.
+#[derive(clap::Args, Debug)]
+pub struct DisplayArgs {
+    /// Show sizes in MB (si) or MiB (binary); defaults to --size-units
+    #[arg(long, value_enum, value_name = "UNITS")]
+    pub display_units: Option<SizeUnits>,
+
+    /// Show sizes in MB, as --display-units si does
+    #[arg(long, conflicts_with = "display_units")]
+    pub si: bool,
+
+    /// How thousands and decimals are separated: as the locale in LC_ALL,
+    /// LC_NUMERIC or LANG does, or in a fixed style
+    #[arg(long, value_enum, value_name = "STYLE", default_value_t = Grouping::Locale)]
+    pub digit_grouping: Grouping,
+}
+
+impl DisplayArgs {
+    /// The format sizes are shown in, in `size_units` unless
+    /// `--display-units` or `--si` say otherwise.
+    pub fn size_format(&self, size_units: SizeUnits) -> SizeFormat {
+        let units = if self.si {
+            SizeUnits::Si
+        } else {
+            self.display_units.unwrap_or(size_units)
+        };
+        let locale = sizefmt::system_locale();
+        SizeFormat::new(
+            units,
+            Separators::of(self.digit_grouping, locale.as_deref()),
+        )
+    }
+}
+
+/// Options shared by single-file and batch runs.
+#[derive(clap::Args, Debug)]
+pub struct CommonArgs {
+    /// Target size, e.g. 50, 25MB, 500KB or 1.5GiB (a bare number is
+    /// megabytes); 100 when neither it nor a profile is given
+    #[arg(short, long)]
+    pub size: Option<String>,
+
+    /// Target the upload limit of a destination: discord (10 MB), whatsapp
+    /// (16 MB) or telegram (2 GB); --size wins over it
+    #[arg(long, value_enum, value_name = "DESTINATION")]
+    pub profile: Option<Profile>,
+
+    /// Without --size or --profile, target the destination an output
+    /// folder is named after (e.g. Videos/Discord) instead of only
+    /// suggesting it
+    #[arg(long)]
+    pub auto_profile: bool,
+
+    /// Use the upload limit an http:// or https:// URL answers with as the
+    /// target: a plain number of bytes, or JSON (see --limit-json-path)
+    #[arg(long, value_name = "URL", conflicts_with = "size")]
+    pub probe_limit_url: Option<String>,
+
+    /// Where the limit is in the JSON answer of --probe-limit-url, e.g.
+    /// limits.max_bytes (array items by index: plans.0.max)
+    #[arg(long, value_name = "PATH", requires = "probe_limit_url")]
+    pub limit_json_path: Option<String>,
+
+    /// Whether MB/KB/GB mean powers of 1000 (si) or 1024 (binary); KiB/MiB/GiB
+    /// are always binary
+    #[arg(long, value_enum, default_value_t = SizeUnits::Binary)]
+    pub size_units: SizeUnits,
+
+    #[command(flatten)]
+    pub display: DisplayArgs,
+
+    /// Pad odd frame dimensions up to even ones instead of scaling them down
+    #[arg(long)]
+    pub pad_odd: bool,
+
+    /// Downscale to at most this width in pixels, keeping the aspect ratio
+    #[arg(long, value_name = "PIXELS")]
+    pub max_width: Option<u32>,
+
+    /// Downscale to at most this height in pixels, whatever the target size
+    /// would allow (with --max-width too, the smaller frame wins)
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(2..))]
+    pub max_height: Option<u32>,
+
+    /// Output a 9:16 portrait video of at most 1080x1920, for Shorts, Reels
+    /// and TikTok; landscape sources are letterboxed
+    #[arg(long, conflicts_with_all = ["max_width", "max_height"])]
+    pub vertical: bool,
+
+    /// Like --vertical, but center-crop landscape sources to 9:16 instead
+    /// of letterboxing them
+    #[arg(long, conflicts_with_all = ["max_width", "max_height"])]
+    pub vertical_crop: bool,
+
+    /// Encode only this rectangle of the frame as displayed, such as
+    /// 1280x720+2560+0 for a window of a 4K desktop capture; it is cropped
+    /// before any scaling
+    #[arg(long, value_name = "WxH+X+Y", value_parser = region::parse,
+          conflicts_with = "remux_only")]
+    pub region: Option<Region>,
+
+    /// Change the output frame rate
+    #[arg(long)]
+    pub fps: Option<f64>,
+
+    /// Lower the frame rate to at most this, whether it comes from the
+    /// source, --fps or --cfr
+    #[arg(long, value_name = "FPS")]
+    pub max_fps: Option<f64>,
+
+    /// Normalize a variable frame rate (typical of phone recordings) to a
+    /// constant one, by default the source's average rounded to whole fps
+    #[arg(long, value_name = "FPS", num_args = 0..=1, require_equals = true, conflicts_with = "fps")]
+    pub cfr: Option<Option<f64>>,
+
+    /// Print probe details and other diagnostics
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Write newline-delimited JSON events (phases, progress, warnings and
+    /// the report) to stderr, or with =FD to that file descriptor; status
+    /// text is kept off the stream
+    #[arg(
+        long,
+        value_name = "FD",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "2"
+    )]
+    pub progress_json: Option<u32>,
+
+    /// Stop the run, as Ctrl-C would, once the reader of the status or the
+    /// events goes away (exit code 11); without it the run finishes with
+    /// that output dropped
+    #[arg(long)]
+    pub abort_on_broken_pipe: bool,
+
+    /// Video encoder; svt-av1 needs an ffmpeg built with libsvtav1 and falls
+    /// back to h264 without it; a source in another codec is encoded even
+    /// when it would fit as it is [default: h264]
+    #[arg(long, value_enum)]
+    pub codec: Option<VideoEncoder>,
+
+    /// Make the output play on this kind of device: its codec, H.264
+    /// profile and level, frame size and rate caps and audio format (see
+    /// --list-devices); --codec, --max-width, --max-height and --max-fps
+    /// still win
+    #[arg(long, value_enum, value_name = "DEVICE")]
+    pub device: Option<Device>,
+
+    /// Encoder speed preset (slower presets give better quality per bit;
+    /// mapped onto SVT-AV1's numeric presets for svt-av1) [default: medium]
+    #[arg(long, value_enum)]
+    pub preset: Option<Preset>,
+
+    /// Encoder effort from 1 (fastest) to 9 (best quality per bit), mapped
+    /// onto each codec's own presets (see --explain-effort); --preset wins
+    /// over it
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u8).range(1..=9))]
+    pub effort: Option<u8>,
+
+    /// Options for libx264 as key=value pairs separated by ':', passed on
+    /// as ffmpeg's -x264-params (with --codec h264)
+    #[arg(long, value_name = "PARAMS", value_parser = parse_x264_params)]
+    pub x264_params: Option<EncoderParams>,
+
+    /// Options for libsvtav1 as key=value pairs separated by ':', passed on
+    /// as ffmpeg's -svtav1-params (with --codec svt-av1)
+    #[arg(long, value_name = "PARAMS", value_parser = parse_svtav1_params,
+          conflicts_with = "x264_params")]
+    pub svtav1_params: Option<EncoderParams>,
+
+    /// Refuse to start, or switch to a faster preset, when the estimated
+    /// encode time exceeds this many minutes
+    #[arg(long, value_name = "MINUTES")]
+    pub max_encode_time: Option<f64>,
+
+    /// Stop before encoding when the planned bitrate predicts heavy
+    /// artifacts, instead of only warning
+    #[arg(long)]
+    pub fail_on_poor_quality: bool,
+
+    /// Refuse to encode when the plan's quality is below this floor: `fair`,
+    /// `good` or bits per pixel as `bpp=0.04`
+    #[arg(long, value_name = "FLOOR", value_parser = Floor::parse)]
+    pub min_quality: Option<Floor>,
+
+    /// Only change the container (e.g. MKV to MP4) by copying the streams,
+    /// when they fit the output's container and the input is within the
+    /// target; otherwise re-encode as usual
+    #[arg(long, conflicts_with = "split")]
+    pub remux_only: bool,
+
+    /// With --remux-only, fail instead of re-encoding when remuxing isn't possible
+    #[arg(long, requires = "remux_only")]
+    pub strict_remux: bool,
+
+    /// Directory for intermediate files (defaults to $TMPDIR or the system temp dir)
+    #[arg(long, value_name = "DIR")]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Keep the per-run temp directory instead of deleting it, and print its path
+    #[arg(long)]
+    pub keep_temp: bool,
+
+    /// Fail when an output's directory doesn't exist, instead of creating it
+    #[arg(long)]
+    pub no_create_dirs: bool,
+
+    /// When the temp dir is on an SMB share, encode in the system temp dir
+    /// and copy the result over instead of writing fragmented MP4
+    #[arg(long)]
+    pub safe_remote_write: bool,
+
+    /// Record the settings (target, codec, preset, bitrate; never paths) in
+    /// the output's comment metadata, which `mdviqure probe` shows
+    #[arg(long, overrides_with = "no_tag_metadata")]
+    pub tag_metadata: bool,
+
+    /// Leave the output's comment alone (the default)
+    #[arg(long, overrides_with = "tag_metadata")]
+    pub no_tag_metadata: bool,
+
+    /// Write a <output>.mdviqure.json next to each output recording how it
+    /// was made: the command line, settings and every ffmpeg command
+    #[arg(long)]
+    pub sidecar: bool,
+
+    /// Write the same bytes for the same input and options on every run:
+    /// no input metadata or timestamps, and encoding on one thread
+    #[arg(long)]
+    pub deterministic: bool,
+
+    /// Make the output loop seamlessly, as a background: a keyframe at the
+    /// start, audio cut or padded to the video's length and, in MP4 and
+    /// MOV, no edit list; the output is checked afterwards
+    #[arg(long, conflicts_with = "remux_only")]
+    pub loop_safe: bool,
+
+    /// When an output replaces a file (the input itself, or an earlier
+    /// output), keep that file until the run has succeeded and put it back
+    /// if anything after the encode fails
+    #[arg(long)]
+    pub keep_original_on_failure: bool,
+
+    /// Once an MP4 or MOV output is within the target, pad it to exactly
+    /// the target size with a free box that players skip, for storage
+    /// that only takes files of one size
+    #[arg(long)]
+    pub pad_to_exact: bool,
+
+    /// Write each subtitle stream of the input next to the output, as
+    /// <output stem>.<language>.srt (or the stream's own format when it
+    /// can't become SubRip), since a re-encode doesn't carry them
+    #[arg(long)]
+    pub extract_subs: bool,
+
+    /// Hash the input (while it is encoded) and the output with ALGORITHM,
+    /// and list both in the report
+    #[arg(long, value_name = "ALGORITHM", value_enum)]
+    pub checksum: Option<Algorithm>,
+
+    /// Copy the input's cover art (a picture stored as a video stream)
+    /// into the output untouched instead of leaving it out
+    #[arg(long)]
+    pub keep_cover_art: bool,
+
+    /// Copy the input's data streams (such as GoPro telemetry) into MP4 or
+    /// MOV output instead of leaving them out
+    #[arg(long)]
+    pub keep_data_streams: bool,
+
+    /// Decode the input on the GPU (auto: the first one this machine has),
+    /// whatever the encoder; decodes in software, with a warning, when the
+    /// decoder fails to start
+    #[arg(long, value_enum, value_name = "DECODER", default_value_t = HwDecode::None)]
+    pub hwdecode: HwDecode,
+
+    /// When the audio runs on past the video, budget for the video's length
+    /// and end the output with it, with a warning (the default)
+    #[arg(long, overrides_with = "no_trim_to_video")]
+    pub trim_to_video: bool,
+
+    /// Keep audio that runs on past the video, over a frozen last frame
+    #[arg(long, overrides_with = "trim_to_video")]
+    pub no_trim_to_video: bool,
+
+    /// Allow an .avi output, written with H.264 and MP3 whatever --codec
+    /// says
+    #[arg(long)]
+    pub allow_legacy_container: bool,
+
+    /// Encode the video in two passes, for a size closer to the target;
+    /// clips under 15 seconds and SVT-AV1 get one pass with a capped rate
+    #[arg(long)]
+    pub two_pass: bool,
+
+    /// How the encoder is held to the bitrate: abr (an average bitrate),
+    /// crf-capped (a constant quality that never goes over it) or auto,
+    /// which gives clips of a minute or less with bits per pixel to spare
+    /// crf-capped and everything else abr; discord and whatsapp pin
+    /// crf-capped
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub mode: Option<Mode>,
+
+    /// Re-encode at a lower bitrate up to this many times when the output
+    /// exceeds the target size
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    pub max_retries: u32,
+
+    /// Retry probing the input or starting the encode up to this many times
+    /// when it fails with an I/O error that may pass (connection reset,
+    /// stale file handle), waiting 1s, 2s, 4s... in between
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub retries: u32,
+
+    /// Stop after this many attempts at writing the output in all, counting
+    /// remuxes, encodes and every kind of retry
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_attempts: Option<u32>,
+
+    /// Start no further attempt once the run has taken this long (seconds
+    /// or hh:mm:ss[.fff]); one under way is finished
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub max_total_time: Option<f64>,
+
+    /// Kill ffmpeg or ffprobe if a single run takes longer than this many
+    /// seconds
+    #[arg(long, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+
+    /// Write everything ffmpeg prints on stderr to this file, each run
+    /// after its command; otherwise only the end is kept, for errors
+    #[arg(long, value_name = "FILE")]
+    pub ffmpeg_log: Option<PathBuf>,
+
+    /// Drop the audio track and give its share of the size budget to the video
+    #[arg(long)]
+    pub no_audio: bool,
+
+    /// Audio track to keep, counting from 1, or `all`; each kept track gets
+    /// its own share of the size budget
+    #[arg(long, value_name = "N|all", default_value = "1", value_parser = parse_audio_selection)]
+    pub audio_track: AudioSelection,
+
+    /// Bitrate of each kept audio track, e.g. 96k, instead of 64k per
+    /// channel
+    #[arg(long, value_name = "BITRATE", value_parser = parse_bitrate, conflicts_with = "no_audio")]
+    pub audio_bitrate: Option<u64>,
+
+    /// Percent of the target size to set aside for container overhead (by
+    /// default what earlier runs into the same container needed, once
+    /// there are enough of them, otherwise 0)
+    #[arg(long, value_name = "PERCENT", value_parser = parse_overhead)]
+    pub overhead_percent: Option<f64>,
+
+    /// Neither use nor add to the overhead learned from earlier runs (see
+    /// `mdviqure stats`)
+    #[arg(long)]
+    pub no_learn: bool,
+
+    /// Split into this many equal-length parts, each within the target size
+    /// (written as <OUTPUT stem>.partN.<ext>)
+    #[arg(long, value_name = "PARTS", default_value_t = 1,
+          value_parser = clap::value_parser!(u32).range(1..))]
+    pub split: u32,
+
+    /// Encode the video as N chunks at once (by default one per core) and
+    /// join them, to use more cores on a single file; the audio is encoded
+    /// once for the whole file
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true,
+          value_parser = clap::value_parser!(u32).range(1..),
+          conflicts_with_all = ["split", "sample", "max_duration", "window_start"])]
+    pub chunked_encode: Option<Option<u32>>,
+
+    /// Allow a video bitrate above the source's own (by default the bitrate
+    /// is capped there, as more bits can't restore lost quality)
+    #[arg(long)]
+    pub no_source_cap: bool,
+
+    /// When the source bitrate is already below the planned one, copy the
+    /// video stream unchanged instead of re-encoding it
+    #[arg(long, conflicts_with = "no_source_cap")]
+    pub copy_if_larger: bool,
+
+    /// Re-encode the video even when copying it and re-encoding only the
+    /// audio would already fit the target
+    #[arg(long, conflicts_with = "copy_if_larger")]
+    pub force_video_reencode: bool,
+
+    /// Use this input duration (seconds or hh:mm:ss[.fff]) instead of the
+    /// one ffprobe reports, for captures whose headers are wrong
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub duration: Option<f64>,
+
+    /// Encode only the first TIME (seconds or hh:mm:ss[.fff]) with exactly
+    /// the settings of the full run, into <OUTPUT stem>.sample.<ext>
+    #[arg(long, value_name = "TIME", value_parser = parse_time,
+          conflicts_with_all = ["split", "remux_only"])]
+    pub sample: Option<f64>,
+
+    /// Encode at most TIME (seconds or hh:mm:ss[.fff]) of the input, cutting
+    /// the rest, with the bitrate planned for what is kept; with --split,
+    /// make no part longer instead
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub max_duration: Option<f64>,
+
+    /// Skip TIME (seconds or hh:mm:ss[.fff]) of the input before the
+    /// encoded window
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub window_start: Option<f64>,
+
+    /// Measure the duration by decoding the whole input, even when the
+    /// container reports one (slow, but exact)
+    #[arg(long, conflicts_with = "duration")]
+    pub trust_decode_duration: bool,
+
+    /// Color status output: auto (only on a terminal, and unless NO_COLOR
+    /// is set or CLICOLOR_FORCE asks for it anyway), always or never
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Plain status output for logs: no color, progress as a line every 10%
+    /// instead of one redrawn in place, and ASCII only
+    ///
+    /// This is the default when the status output isn't a terminal or TERM
+    /// is dumb.
+    #[arg(long, conflicts_with = "color")]
+    pub plain: bool,
+
+    /// Send a desktop notification when the run finishes (a terminal bell
+    /// where notifications aren't available)
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Keep the system from sleeping while encoding: always, never, or
+    /// auto for encodes estimated to take over 3 minutes (a bare
+    /// --inhibit-sleep means always)
+    #[arg(
+        long,
+        value_enum,
+        value_name = "WHEN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_value_t = sleep::Policy::Auto,
+        default_missing_value = "always"
+    )]
+    pub inhibit_sleep: sleep::Policy,
+
+    /// Answer every prompt with its default instead of asking, for scripts
+    /// and other runs without a terminal; it never lifts a refusal
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+impl CommonArgs {
+    /// `--mode`, else the one `profile` pins unless `--two-pass` asks for
+    /// an average bitrate, else auto.
+    fn rate_mode(&self, profile: Option<Profile>) -> Mode {
+        self.mode
+            .or(profile
+                .filter(|_| !self.two_pass)
+                .and_then(Profile::rate_mode))
+            .unwrap_or_default()
+    }
+
+    /// Whether the target was given as a size, not left to a profile.
+    fn explicit_target(&self) -> bool {
+        self.size.is_some() || self.probe_limit_url.is_some()
+    }
+
+    pub fn output_mode(&self) -> OutputMode {
+        OutputMode {
+            color: self.color,
+            plain: self.plain,
+        }
+    }
+
+    /// Validates the options and turns them into [`ReduceOptions`].
+    pub fn reduce_options(&self) -> Result<ReduceOptions, ReduceError> {
+        let opts = self.unvalidated_options()?;
+        validate::check(&opts.validate(None, &Capabilities::default()))?;
+        Ok(opts)
+    }
+
+    /// The options as [`ReduceOptions`], leaving
+    /// [`ReduceOptions::validate`] to the caller.
+    fn unvalidated_options(&self) -> Result<ReduceOptions, ReduceError> {
+        let target_bytes = match &self.probe_limit_url {
+            Some(limit_url) => limit::probe(
+                limit_url,
+                self.limit_json_path.as_deref(),
+                self.size_units,
+                limit::TIMEOUT,
+            )
+            .map_err(|e| ReduceError::Usage(format!("--probe-limit-url: {}", e)))?,
+            None => match (&self.size, self.profile) {
+                (Some(size), _) => parse_size(size, self.size_units).map_err(ReduceError::Usage)?,
+                (None, Some(profile)) => profile.target_bytes(),
+                (None, None) => parse_size(profile::DEFAULT_SIZE, self.size_units)
+                    .map_err(ReduceError::Usage)?,
+            },
+        };
+
+        let mut opts = ReduceOptions::new(target_bytes);
+        opts.limit_url = self.probe_limit_url.as_deref().map(url::redact);
+        opts.size_units = self.size_units;
+        opts.sizes = self.display.size_format(self.size_units);
+        if self.pad_odd {
+            opts.even_mode = EvenMode::Pad;
+        }
+        opts.vertical = if self.vertical_crop {
+            Some(vertical::Fit::Crop)
+        } else if self.vertical {
+            Some(vertical::Fit::Pad)
+        } else {
+            None
+        };
+        opts.region = self.region;
+        let mut bundle = self.device.map(Device::constraints).unwrap_or_default();
+        if opts.vertical.is_some() {
+            // The portrait canvas has its own size.
+            bundle.max_width = None;
+            bundle.max_height = None;
+        }
+        let constraints = Constraints {
+            encoder: self.codec,
+            max_width: self.max_width,
+            max_height: self.max_height,
+            max_fps: self.max_fps,
+            compat: Compat::default(),
+        }
+        .or(bundle);
+        opts.max_width = constraints.max_width;
+        opts.max_height = constraints.max_height;
+        opts.compat = constraints.compat;
+        opts.fps = self.fps;
+        opts.max_fps = constraints.max_fps;
+        opts.cfr = self.cfr;
+        opts.verbose = self.verbose;
+        opts.encoder = constraints.encoder.unwrap_or(VideoEncoder::H264);
+        opts.preset = self
+            .preset
+            .or(self.effort.map(effort::preset))
+            .unwrap_or(Preset::Medium);
+        opts.effort = self.effort;
+        opts.encoder_params = self.x264_params.clone().or(self.svtav1_params.clone());
+        opts.max_encode_minutes = self.max_encode_time;
+        opts.fail_on_poor_quality = self.fail_on_poor_quality;
+        opts.min_quality = self.min_quality;
+        opts.remux_only = self.remux_only;
+        opts.strict_remux = self.strict_remux;
+        opts.temp_dir = self.temp_dir.clone();
+        opts.keep_temp = self.keep_temp;
+        opts.create_dirs = !self.no_create_dirs;
+        opts.safe_remote_write = self.safe_remote_write;
+        opts.tag_metadata = self.tag_metadata;
+        opts.sidecar = self.sidecar;
+        opts.deterministic = self.deterministic;
+        opts.loop_safe = self.loop_safe;
+        opts.keep_original_on_failure = self.keep_original_on_failure;
+        opts.pad_to_exact = self.pad_to_exact;
+        opts.extract_subs = self.extract_subs;
+        opts.checksum = self.checksum;
+        opts.keep_cover_art = self.keep_cover_art;
+        opts.keep_data_streams = self.keep_data_streams;
+        opts.hwdecode = self.hwdecode;
+        opts.command_line = std::env::args().collect();
+        opts.trim_to_video = !self.no_trim_to_video;
+        opts.allow_legacy_container = self.allow_legacy_container;
+        opts.two_pass = self.two_pass;
+        if self.two_pass && self.mode == Some(Mode::CrfCapped) {
+            return Err(ReduceError::Usage(
+                "--two-pass needs an average bitrate, not --mode crf-capped".into(),
+            ));
+        }
+        opts.rate_mode = self.rate_mode(self.profile);
+        opts.inhibit_sleep = self.inhibit_sleep;
+        opts.max_retries = self.max_retries;
+        opts.transient_retries.count = self.retries;
+        opts.budget = Budget {
+            max_attempts: self.max_attempts,
+            max_total_time: self.max_total_time.map(Duration::from_secs_f64),
+        };
+        opts.no_audio = self.no_audio;
+        opts.parts = self.split;
+        opts.chunks = match self.chunked_encode {
+            Some(chunks) => chunks.unwrap_or_else(chunked::default_count),
+            None => 1,
+        };
+        opts.audio_tracks = self.audio_track;
+        opts.audio_bitrate = self.audio_bitrate;
+        opts.overhead_percent = self.overhead_percent.unwrap_or(0.0);
+        opts.output_mode = self.output_mode();
+        opts.duration = self.duration;
+        opts.sample = self.sample;
+        opts.max_duration = self.max_duration;
+        opts.window_start = self.window_start;
+        opts.trust_decode_duration = self.trust_decode_duration;
+        opts.source_cap = !self.no_source_cap;
+        opts.copy_if_larger = self.copy_if_larger;
+        opts.force_video_reencode = self.force_video_reencode;
+        opts.notify = self.notify;
+        Ok(opts)
+    }
+}
+
+impl Cli {
+    fn common(&self) -> Option<&CommonArgs> {
+        match &self.command {
+            Some(Command::Batch(batch)) => Some(&batch.common),
+            Some(Command::History(_))
+            | Some(Command::Stats(_))
+            | Some(Command::Probe(_))
+            | Some(Command::Plan(_)) => None,
+            None => Some(&self.args.common),
+        }
+    }
+}
+
+/// The history at `path`, or in the default location.
+/// `--size-ladder`: prints how `input` would come out at each of `sizes`.
+/// With a `bar`, the target becomes the smallest size that reaches it, and
+/// the answer is whether to go on and encode.
+fn size_ladder<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    sizes: &str,
+    bar: Option<Quality>,
+    opts: &mut ReduceOptions,
+    out: Presenter,
+) -> Result<bool, ReduceError> {
+    if input == STDIO_PATH {
+        return Err(ReduceError::Usage(
+            "--size-ladder needs an input file to probe, not stdin".into(),
+        ));
+    }
+    let targets = ladder::parse_ladder(sizes, opts.size_units)
+        .map_err(|e| ReduceError::Usage(format!("--size-ladder: {}", e)))?;
+    let Source { info, duration, .. } = probe_source(tool, input, opts, out)?;
+    let rungs = ladder::evaluate(duration, &info, opts, &targets);
+    for line in out.table(&ladder::table(&rungs, opts.sizes)) {
+        out.info(&line);
+    }
+    let Some(bar) = bar else {
+        return Ok(false);
+    };
+    let Some(rung) = ladder::pick(&rungs, bar) else {
+        return Err(ReduceError::Usage(format!(
+            "no size in the ladder reaches {} quality; try larger ones",
+            bar
+        )));
+    };
+    opts.target_bytes = rung.target_bytes;
+    out.info(&format!(
+        "Encoding at {}, the smallest size with {} quality or better",
+        opts.sizes.size(rung.target_bytes),
+        bar
+    ));
+    out.info("");
+    Ok(true)
+}
+
+/// The settings file, or its defaults after a warning when it is broken.
+fn load_config(out: Presenter) -> Config {
+    let (config, problem) = Config::load();
+    if let Some(problem) = problem {
+        out.warn(&format!("ignoring the settings file: {}", problem));
+    }
+    config
+}
+
+/// Applies, or merely suggests, the profile the folders of `dir` name,
+/// unless the target was given or the settings file turned inference off;
+/// `--auto-profile` asks for it either way.
+fn infer_profile(
+    common: &CommonArgs,
+    dir: Option<&Path>,
+    opts: &mut ReduceOptions,
+    config: &Config,
+    out: Presenter,
+) {
+    if !config.infer_profile && !common.auto_profile {
+        return;
+    }
+    let inferred = dir.and_then(profile::infer);
+    let (bytes, origin) = profile::choose(
+        common.explicit_target().then_some(opts.target_bytes),
+        common.profile,
+        inferred.clone(),
+        common.auto_profile,
+        opts.target_bytes,
+    );
+    opts.target_bytes = bytes;
+    if let profile::Origin::Inferred(inference) = &origin {
+        opts.rate_mode = common.rate_mode(Some(inference.profile));
+    }
+    if let Some(line) = profile::describe(&origin, inferred.as_ref(), opts.sizes) {
+        out.info(&line);
+    }
+}
+
+fn history_at(path: Option<&Path>) -> Option<History> {
+    match path {
+        Some(path) => Some(History::at(path)),
+        None => History::default_location(),
+    }
+}
+
+impl Args {
+    /// The input and output paths; with `--input-pattern` the only
+    /// positional argument is the output.
+    fn paths(&self) -> Result<(&str, &str), ReduceError> {
+        match (&self.input_pattern, &self.input, &self.output) {
+            (Some(pattern), Some(output), None) => Ok((pattern, output)),
+            (Some(_), Some(_), Some(_)) => Err(ReduceError::Usage(
+                "--input-pattern replaces the INPUT argument; give only the OUTPUT".into(),
+            )),
+            (None, Some(input), Some(output)) => Ok((input, output)),
+            _ => Err(ReduceError::Usage(
+                "an input and an output file are required".into(),
+            )),
+        }
+    }
+
+    /// How `--format hls` cuts the output, if it is asked for.
+    fn hls(&self) -> Result<Option<Hls>, ReduceError> {
+        match (self.format, self.hls_time) {
+            (Format::Hls, seconds) => Ok(Some(Hls {
+                segment_seconds: seconds.unwrap_or(hls::DEFAULT_SEGMENT_SECONDS),
+            })),
+            (Format::File, None) => Ok(None),
+            (Format::File, Some(_)) => Err(ReduceError::Usage(
+                "--hls-time only applies to --format hls".into(),
+            )),
+        }
+    }
+
+    /// The image input the flags ask for, if any.
+    fn image_input(&self) -> Result<Option<ImageInput>, ReduceError> {
+        let positive = |value: f64, flag: &str| {
+            if value.is_finite() && value > 0.0 {
+                Ok(value)
+            } else {
+                Err(ReduceError::Usage(format!(
+                    "{} must be greater than zero",
+                    flag
+                )))
+            }
+        };
+        if let Some(fps) = self.input_fps {
+            let fps = positive(fps, "--input-fps")?;
+            return Ok(Some(ImageInput::Sequence { fps }));
+        }
+        match self.loop_duration {
+            Some(seconds) => Ok(Some(ImageInput::Still {
+                seconds: positive(seconds, "--loop-duration")?,
+                fps: self.common.fps.unwrap_or(DEFAULT_FPS),
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+pub fn run_app<T: VideoTool>(args: Args, tool: &T) -> Result<(), ReduceError> {
+    let table = if args.explain_effort {
+        Some(effort::table())
+    } else if args.list_devices {
+        Some(device::table())
+    } else {
+        None
+    };
+    if let Some(table) = table {
+        let out = Presenter::new(Console::stdout(), args.common.output_mode());
+        for line in out.table(&table) {
+            out.info(&line);
+        }
+        return Ok(());
+    }
+    let (input, output) = args.paths()?;
+    let mut opts = args.common.unvalidated_options()?;
+    let sample_output;
+    let output = match opts.sample {
+        Some(_) if output != STDIO_PATH => {
+            sample_output = sample_output_path(output);
+            if Path::new(&sample_output) == Path::new(input) {
+                return Err(ReduceError::Usage(format!(
+                    "the sample would overwrite the input {}",
+                    input
+                )));
+            }
+            sample_output.as_str()
+        }
+        _ => output,
+    };
+    opts.image = args.image_input()?;
+    if opts.image.is_some() {
+        opts.no_audio = true;
+    }
+    if args.download_first && !url::is_url(input) {
+        return Err(ReduceError::Usage(
+            "--download-first only applies to http:// and https:// inputs".into(),
+        ));
+    }
+    opts.download_first = args.download_first;
+    opts.dry_run = args.dry_run;
+    opts.hls = args.hls()?;
+    opts.stats = args.stats.clone();
+    opts.save_stats = args.save_stats.clone();
+    let json = args.output_format == OutputFormat::Json;
+    if json && output == STDIO_PATH {
+        return Err(ReduceError::Usage(
+            "--output-format json needs an output file; stdout already carries the video".into(),
+        ));
+    }
+    if args.summary_line && (json || output == STDIO_PATH) {
+        return Err(ReduceError::Usage(format!(
+            "--summary-line needs stdout for its line, but {} already writes there",
+            if json {
+                "--output-format json, whose report has the line as summary_line,"
+            } else {
+                "the video"
+            }
+        )));
+    }
+    opts.status_on_stderr = json;
+    opts.quiet = args.summary_line;
+    if args.common.progress_json == Some(1) && (json || args.summary_line || output == STDIO_PATH) {
+        return Err(ReduceError::Usage(format!(
+            "--progress-json=1 needs stdout for its events, but {} already writes there",
+            if json {
+                "--output-format json"
+            } else if args.summary_line {
+                "--summary-line"
+            } else {
+                "the video"
+            }
+        )));
+    }
+    if args.compare && opts.hls.is_some() {
+        return Err(ReduceError::Usage(
+            "--compare needs a single output file, not --format hls".into(),
+        ));
+    }
+    if args.compare && (input == STDIO_PATH || output == STDIO_PATH) {
+        return Err(ReduceError::Usage(
+            "--compare needs an input and an output file to read back".into(),
+        ));
+    }
+    if opts.sidecar && output == STDIO_PATH {
+        return Err(ReduceError::Usage(
+            "--sidecar needs an output file to write next to, not stdout".into(),
+        ));
+    }
+    if opts.extract_subs && output == STDIO_PATH {
+        return Err(ReduceError::Usage(
+            "--extract-subs needs an output file to write next to, not stdout".into(),
+        ));
+    }
+    let out = opts.presenter(output);
+    infer_profile(
+        &args.common,
+        Path::new(output).parent().filter(|_| output != STDIO_PATH),
+        &mut opts,
+        &load_config(out),
+        out,
+    );
+    if args.interactive && !confirm_interactively(tool, input, output, args.common.yes, &mut opts)?
+    {
+        return Err(ReduceError::Interrupted);
+    }
+    validate_options(tool, Some(input), &opts)?;
+    let history = history_at(None).filter(|_| !args.common.no_learn);
+    opts.learned_overhead = learned_overhead(&args.common, history.as_ref());
+    warning::hold_back();
+    if let Some(sizes) = &args.size_ladder {
+        let out = opts.presenter(output);
+        if !size_ladder(tool, input, sizes, args.pick_best_under, &mut opts, out)? {
+            return Ok(());
+        }
+    }
+    let started = Instant::now();
+    let result = reduce_video(tool, input, output, &opts);
+    let out = opts.presenter(output);
+    // Those of a run that failed, or came after its `Done:` line.
+    warning::recap(out);
+    if let (Ok(report), Some(history)) = (&result, &history) {
+        record_run(history, input, output, &opts, report, out);
+    }
+    if opts.notify && !matches!(result, Err(ReduceError::Interrupted)) {
+        let written = result
+            .as_ref()
+            .map(|_| (output != STDIO_PATH).then(|| batch::written_bytes(output, opts.parts)));
+        Notice::single(output, written, started.elapsed(), opts.sizes).send(out, opts.verbose);
+    }
+    if json || args.summary_line || events::is_enabled() {
+        let report = run_report(input, output, &opts, &result);
+        if json {
+            Console::stdout().say(&report.to_line());
+        }
+        if args.summary_line {
+            Console::stdout().say(&report.summary_line);
+        }
+        events::emit(&Event::Report(Box::new(report)));
+    }
+    result?;
+    if args.compare {
+        // The output is done; a clip that can't be made is no reason to fail.
+        match compare::write_clip(tool, input, output, args.compare_at) {
+            Ok(clip) => out.info(&format!("Comparison clip: {}", clip)),
+            Err(e) => out.warn(&format!("could not write the comparison clip: {}", e)),
+        }
+    }
+    if output != STDIO_PATH {
+        let result = if opts.parts > 1 {
+            part_output_path(output, 1)
+        } else if opts.hls.is_some() {
+            Path::new(output).join(hls::PLAYLIST).display().to_string()
+        } else {
+            output.to_string()
+        };
+        launch_result(launch::current(), Path::new(&result), &args, out);
+    }
+    Ok(())
+}
+
+/// Fails with every problem [`ReduceOptions::validate`] finds that stops
+/// a run of `opts`, given what `tool` can do and a probe of `input` when it
+/// is a file (which the run then reads from the cache). Warnings are left
+/// to the run, which says them where they happen; `--progress-json` gets
+/// all of them up front.
+fn validate_options<T: VideoTool>(
+    tool: &T,
+    input: Option<&str>,
+    opts: &ReduceOptions,
+) -> Result<(), ReduceError> {
+    let probe = input
+        .filter(|input| opts.image.is_none() && Path::new(input).is_file())
+        .and_then(|input| tool.get_video_info(input).ok());
+    let issues = opts.validate(probe.as_ref(), &Capabilities::of(tool, opts));
+    if !issues.is_empty() {
+        events::emit(&Event::Issues {
+            issues: issues.clone(),
+        });
+    }
+    validate::check(&issues)
+}
+
+/// The overhead learned from `history`, unless `--overhead-percent` or
+/// `--no-learn` says otherwise. A history that can't be read teaches
+/// nothing.
+fn learned_overhead(common: &CommonArgs, history: Option<&History>) -> Option<Learned> {
+    if common.overhead_percent.is_some() || common.no_learn {
+        return None;
+    }
+    let entries = history?.load().ok()?;
+    Some(Learned::from_history(&entries))
+}
+
+/// Adds a single-file run to the history, as a batch does for each of its
+/// files, so later runs learn from its overhead.
+fn record_run(
+    history: &History,
+    input: &str,
+    output: &str,
+    opts: &ReduceOptions,
+    report: &ReduceReport,
+    out: Presenter,
+) {
+    if opts.dry_run || output == STDIO_PATH {
+        return;
+    }
+    // Only inputs on disk can be told apart later.
+    let Some(fingerprint) = Fingerprint::of(input) else {
+        return;
+    };
+    let written = batch::written_bytes(output, opts.parts);
+    let entry = Entry::new(
+        &fingerprint,
+        opts.target_bytes,
+        output,
+        RESULT_OK,
+        Some(written),
+    )
+    .with_sample(report.sample);
+    if let Err(e) = history.append(&entry) {
+        out.warn(&format!(
+            "cannot record {} in {}: {}",
+            input,
+            history.path().display(),
+            e
+        ));
+    }
+}
+
+/// Handles `--open` and `--reveal`. The encode already succeeded, so a
+/// launch failure is only a warning.
+fn launch_result(platform: &dyn Platform, result: &Path, args: &Args, out: Presenter) {
+    let mut commands = Vec::new();
+    if args.open {
+        commands.push(("open", platform.open(result)));
+    }
+    if args.reveal {
+        commands.push(("reveal", platform.reveal(result)));
+    }
+    for (what, command) in commands {
+        if let Err(e) = command.run() {
+            out.warn(&format!("could not {} {}: {}", what, result.display(), e));
+        }
+    }
+}
+
+pub fn run_batch<T: VideoTool + Sync>(args: BatchArgs, tool: &T) -> Result<(), ReduceError> {
+    let mut opts = args.common.reduce_options()?;
+    let max_total_bytes = match &args.max_total_size {
+        Some(size) => Some(
+            parse_size(size, opts.size_units)
+                .map_err(|e| ReduceError::Usage(format!("--max-total-size: {}", e)))?,
+        ),
+        None => None,
+    };
+    let name_template = match &args.name_template {
+        Some(text) => Some(
+            Template::parse(text)
+                .map_err(|e| ReduceError::Usage(format!("--name-template: {}", e)))?,
+        ),
+        None => None,
+    };
+    let batch = BatchOptions {
+        history: history_at(args.history_file.as_deref()),
+        no_samples: args.common.no_learn,
+        redo: args.redo,
+        resume: args.resume,
+        max_total_bytes,
+        fit_remaining: args.fit_remaining,
+        name_template,
+        names: NameRules {
+            portable: args.portable_names,
+            max_bytes: args.max_name_bytes,
+        },
+        archive_layout: args.archive_layout,
+        archive_tz: args.archive_tz,
+        target_from: args.target_from,
+        jobs: args.jobs,
+    };
+    opts.learned_overhead = learned_overhead(&args.common, batch.history.as_ref());
+    match (&args.manifest, &args.output_dir) {
+        (Some(path), _) => {
+            let manifest = Manifest::load(path)?;
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let jobs = manifest.jobs(&opts, dir).map_err(|problems| {
+                ReduceError::Usage(format!(
+                    "{} has mistakes:\n  {}",
+                    path.display(),
+                    problems.join("\n  ")
+                ))
+            })?;
+            batch::run_jobs(tool, &jobs, dir, &opts, &batch)
+        }
+        (None, Some(output_dir)) => {
+            let out = Presenter::new(Console::stdout(), opts.output_mode);
+            infer_profile(
+                &args.common,
+                Some(output_dir),
+                &mut opts,
+                &load_config(out),
+                out,
+            );
+            batch::reduce_all(tool, &args.inputs, output_dir, &opts, &batch)
+        }
+        (None, None) => unreachable!("clap requires --output-dir without --manifest"),
+    }
+}
+
+/// `mdviqure history`: prints the latest entries, or clears them.
+pub fn run_history(args: HistoryArgs) -> Result<(), ReduceError> {
+    let Some(history) = history_at(args.history_file.as_deref()) else {
+        return Err(ReduceError::Usage(
+            "no config directory found; pass --history-file".into(),
+        ));
+    };
+    let io_error = |e: io::Error| {
+        ReduceError::Encode(format!("cannot access {}: {}", history.path().display(), e))
+    };
+    let out = Presenter::stderr(ColorChoice::Auto);
+    if let Some(HistoryAction::Clear) = args.action {
+        history.clear().map_err(io_error)?;
+        out.info(&format!("Cleared {}", history.path().display()));
+        return Ok(());
+    }
+    let entries = history.load().map_err(io_error)?;
+    if entries.is_empty() {
+        out.info("No history yet");
+        return Ok(());
+    }
+    let mut table = Table::new(vec![
+        Column::new("Finished (UTC)", Align::Left),
+        Column::new("Input", Align::Left).shrinking(),
+        Column::new("Target", Align::Right),
+        Column::new("Result", Align::Left),
+        Column::new("Size", Align::Right),
+    ]);
+    let sizes = args.display.size_format(SizeUnits::Binary);
+    for entry in entries.iter().rev().take(args.limit) {
+        table.push(vec![
+            format_timestamp(entry.finished),
+            entry.input.clone(),
+            sizes.size(entry.target_bytes),
+            entry.result.clone(),
+            entry
+                .output_bytes
+                .map_or_else(|| "-".to_string(), |b| sizes.size(b)),
+        ]);
+    }
+    let out = Presenter::new(Console::stdout(), ColorChoice::Auto);
+    for line in out.table(&table) {
+        out.info(&line);
+    }
+    Ok(())
+}
+
+/// `mdviqure stats`: prints the overhead learned per container.
+pub fn run_stats(args: StatsArgs) -> Result<(), ReduceError> {
+    let Some(history) = history_at(args.history_file.as_deref()) else {
+        return Err(ReduceError::Usage(
+            "no config directory found; pass --history-file".into(),
+        ));
+    };
+    let entries = history.load().map_err(|e| {
+        ReduceError::Encode(format!("cannot access {}: {}", history.path().display(), e))
+    })?;
+    let learned = Learned::from_history(&entries);
+    if learned.estimates.is_empty() {
+        Presenter::stderr(ColorChoice::Auto)
+            .info("No samples yet; every reduce into a file adds one");
+        return Ok(());
+    }
+    let out = Presenter::new(Console::stdout(), ColorChoice::Auto);
+    for line in out.table(&stats_table(&learned)) {
+        out.info(&line);
+    }
+    Ok(())
+}
+
+fn stats_table(learned: &Learned) -> Table {
+    let mut table = Table::new(vec![
+        Column::new("Container", Align::Left),
+        Column::new("Samples", Align::Right),
+        Column::new("Median", Align::Right),
+        Column::new("Overhead used", Align::Left),
+    ]);
+    for (container, estimate) in &learned.estimates {
+        let used = match estimate.percent {
+            Some(percent) if percent != estimate.median => {
+                format!("{:.2}% (bounded to 0-{}%)", percent, overhead::MAX_PERCENT)
+            }
+            Some(percent) => format!("{:.2}%", percent),
+            None => format!(
+                "--overhead-percent until {} more",
+                overhead::MIN_SAMPLES - estimate.samples
+            ),
+        };
+        table.push(vec![
+            container.to_string(),
+            estimate.samples.to_string(),
+            format!("{:+.2}%", estimate.median),
+            used,
+        ]);
+    }
+    table
+}
+
+/// `mdviqure probe`: prints the input's streams, or its size breakdown.
+pub fn run_plan(args: PlanArgs) -> Result<(), ReduceError> {
+    let out = Presenter::new(Console::stdout(), ColorChoice::Auto);
+    for line in plan_report(&args)? {
+        out.info(&line);
+    }
+    Ok(())
+}
+
+/// The lines `plan` prints: the plan as `--dry-run` shows it and the
+/// verdict, or one JSON object.
+fn plan_report(args: &PlanArgs) -> Result<Vec<String>, ReduceError> {
+    if args.duration <= 0.0 {
+        return Err(ReduceError::Usage(
+            "--duration must be greater than zero".into(),
+        ));
+    }
+    if !(args.fps > 0.0 && args.fps.is_finite()) {
+        return Err(ReduceError::Usage("--fps must be greater than zero".into()));
+    }
+    let mut opts =
+        ReduceOptions::new(parse_size(&args.size, args.size_units).map_err(ReduceError::Usage)?);
+    opts.size_units = args.size_units;
+    opts.sizes = args.display.size_format(args.size_units);
+    opts.encoder = args.codec;
+    opts.audio_bitrate = args.audio_bitrate;
+    opts.no_audio = args.no_audio;
+    opts.overhead_percent = args.overhead_percent;
+    let (width, height) = args.resolution;
+    let scenario = Scenario {
+        duration: args.duration,
+        width,
+        height,
+        fps: args.fps,
+    };
+    let plan = scenario.plan(&opts);
+    let summary = Summary::new(scenario.duration, &plan, &opts);
+    if args.output_format == OutputFormat::Json {
+        let json = serde_json::to_string(&summary).expect("the summary always serializes");
+        return Ok(vec![json]);
+    }
+    let warnings = summary
+        .warnings
+        .iter()
+        .map(|warning| format!("Warning: {}", warning.message));
+    Ok(plan
+        .describe(&opts)
+        .into_iter()
+        .chain(summary.describe(opts.sizes))
+        .chain(warnings)
+        .collect())
+}
+
+pub fn run_probe<T: VideoTool>(args: ProbeArgs, tool: &T) -> Result<(), ReduceError> {
+    let out = Presenter::new(Console::stdout(), ColorChoice::Auto);
+    for line in probe_report(&args, tool, out.width())? {
+        out.info(&line);
+    }
+    Ok(())
+}
+
+/// The lines `probe` prints, fitted to `width` columns.
+fn probe_report<T: VideoTool>(
+    args: &ProbeArgs,
+    tool: &T,
+    width: usize,
+) -> Result<Vec<String>, ReduceError> {
+    let file = tool.get_streams(&args.input)?;
+    // The file on disk is the authority; a URL only has what the container
+    // reports.
+    let total_bytes = std::fs::metadata(&args.input)
+        .ok()
+        .map(|m| m.len())
+        .or(file.size_bytes);
+    if !args.breakdown {
+        return Ok(breakdown::render_streams(
+            &file,
+            total_bytes,
+            args.display.size_format(args.size_units),
+            width,
+        ));
+    }
+    let Some(total_bytes) = total_bytes else {
+        return Err(ReduceError::Probe(format!(
+            "cannot tell the size of {}",
+            args.input
+        )));
+    };
+    let breakdown = if args.exact {
+        let packets = tool.get_packet_sizes(&args.input)?;
+        Breakdown::from_packets(&file, total_bytes, &packets)
+    } else {
+        Breakdown::from_bitrates(&file, total_bytes)
+    };
+    Ok(breakdown.render(args.display.size_format(args.size_units), width))
+}
+
+/// Runs the `--interactive` dialog on the terminal, adjusting `opts`.
+fn confirm_interactively<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    output: &str,
+    assume_yes: bool,
+    opts: &mut ReduceOptions,
+) -> Result<bool, ReduceError> {
+    // Prompting on piped video would read garbage from it.
+    if input == STDIO_PATH && !assume_yes {
+        return Err(ReduceError::Usage(
+            "--interactive cannot be combined with input from stdin".into(),
+        ));
+    }
+    let out = opts.presenter(output);
+    let mut prompter = Prompter::stdin(out, assume_yes);
+    prompter.check("--interactive")?;
+    let Source { info, duration, .. } = probe_source(tool, input, opts, out)?;
+    let source_bytes = std::fs::metadata(input).ok().map(|m| m.len());
+    interactive::confirm(&mut prompter, out, duration, source_bytes, &info, opts)
+}
+
+/// Runs the tool and maps the outcome onto the documented exit codes (see
+/// [`crate::error`]). Argument errors are reported by clap with code 2.
+pub fn main() -> ExitCode {
+    let cli = Cli::parse();
+    crate::interrupt::install_handler();
+    let common = cli.common();
+    let errors = Presenter::stderr(common.map_or(OutputMode::default(), |c| c.output_mode()));
+    if common.is_some_and(|c| c.abort_on_broken_pipe) {
+        console::abort_on_broken_pipe();
+    }
+    if let Some(fd) = common.and_then(|c| c.progress_json) {
+        match progress_sink(fd) {
+            Ok(sink) => events::install(sink),
+            Err(e) => {
+                errors.error(&e.to_string());
+                return ExitCode::from(e.exit_code());
+            }
+        }
+    }
+    let ffmpeg_log = common.and_then(|c| c.ffmpeg_log.clone());
+    if let Some(path) = &ffmpeg_log {
+        // Each run appends; the log starts over with the invocation.
+        if let Err(e) = File::create(path) {
+            let e = ReduceError::Usage(format!(
+                "cannot write the ffmpeg log {}: {}",
+                path.display(),
+                e
+            ));
+            errors.error(&e.to_string());
+            return ExitCode::from(e.exit_code());
+        }
+    }
+    let tool = FfmpegTool {
+        timeout: common.and_then(|c| c.timeout).map(Duration::from_secs),
+        diagnostics: common.filter(|c| c.verbose).map(|_| errors),
+        ffmpeg_log,
+        read_mp4_headers: true,
+    };
+    // Every feature of the run asks its questions of the same cache.
+    let tool = Cached::new(&tool);
+    let result = match cli.command {
+        Some(Command::Batch(batch)) => run_batch(*batch, &tool),
+        Some(Command::History(history)) => run_history(history),
+        Some(Command::Stats(stats)) => run_stats(stats),
+        Some(Command::Probe(probe)) => run_probe(probe, &tool),
+        Some(Command::Plan(plan)) => run_plan(plan),
+        None => run_app(cli.args, &tool),
+    };
+    let result = result.map_err(|e| match e {
+        ReduceError::Interrupted if console::aborted_for_broken_pipe() => ReduceError::BrokenPipe,
+        e => e,
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            errors.error(&e.to_string());
+            events::emit(&Event::Error {
+                message: e.to_string(),
+                exit_code: e.exit_code(),
+            });
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+/// Where `--progress-json=FD` writes. Standard output and error are
+/// reserved for the events from then on.
+fn progress_sink(fd: u32) -> Result<Box<dyn Write>, ReduceError> {
+    match fd {
+        0 => Err(ReduceError::Usage(
+            "--progress-json cannot write to standard input (file descriptor 0)".into(),
+        )),
+        1 => {
+            Console::stdout().reserve();
+            Ok(Box::new(io::stdout()))
+        }
+        2 => {
+            Console::stderr().reserve();
+            Ok(Box::new(io::stderr()))
+        }
+        // A descriptor the wrapper opened for us, reached through /dev/fd.
+        fd => File::options()
+            .append(true)
+            .open(format!("/dev/fd/{}", fd))
+            .map(|file| Box::new(file) as Box<dyn Write>)
+            .map_err(|e| {
+                ReduceError::Usage(format!(
+                    "cannot write progress events to file descriptor {}: {}",
+                    fd, e
+                ))
+            }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Container;
+    use crate::overhead::Estimate;
+    use crate::probe::AudioStream;
+    use crate::reduce::Cap;
+    use crate::testing::{arg_value, MockVideoTool, TestDir};
+    use crate::usage::Usage;
+    use crate::warning::{Code, Warning};
+
+    fn parse(argv: &[&str]) -> Args {
+        Cli::parse_from(argv).args
+    }
+
+    fn args(size: u64) -> Args {
+        parse(&[
+            "mdviqure",
+            "in.mp4",
+            "out.mp4",
+            "--size",
+            &size.to_string(),
+            "--no-learn",
+        ])
+    }
+
+    /// Arguments whose output and temp files live in `dir`.
+    fn args_in(dir: &TestDir, size: u64) -> Args {
+        let mut args = args(size);
+        args.output = Some(dir.join("out.mp4"));
+        args.common.temp_dir = Some(dir.path().to_path_buf());
+        args
+    }
+
+    #[test]
+    fn test_run_app_pad_odd() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(60.0).with_dimensions(641, 480);
+        let mut args = args_in(&dir, 50);
+        args.common.pad_odd = true;
+        run_app(args, &tool).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert!(calls[0].contains(&"pad=ceil(iw/2)*2:ceil(ih/2)*2".to_string()));
+    }
+
+    #[test]
+    fn test_run_app_validation_success() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(60.0);
+        let args = args_in(&dir, 50);
+
+        let result = run_app(args, &tool);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_a_codec_asked_for_is_never_skipped_by_a_copy() {
+        let dir = TestDir::new();
+        // An H.264 source far inside the target, which is copied as it is
+        // when nothing else is asked for.
+        let source = || {
+            let mut tool = MockVideoTool::new(100.0);
+            tool.info.bit_rate = Some("2000000".into());
+            tool
+        };
+        let tool = source();
+        run_app(args_in(&dir, 50), &tool).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-c:v"), Some("copy"));
+
+        for copy_if_larger in [false, true] {
+            let tool = source();
+            let mut args = args_in(&dir, 50);
+            args.common.codec = Some(VideoEncoder::SvtAv1);
+            args.common.copy_if_larger = copy_if_larger;
+            run_app(args, &tool).unwrap();
+            let call = tool.single_call();
+            assert_eq!(arg_value(&call, "-c:v"), Some("libsvtav1"));
+            assert!(arg_value(&call, "-b:v").is_some());
+        }
+    }
+
+    #[test]
+    fn test_run_app_size_units() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut args = args_in(&dir, 25);
+        args.common.size_units = SizeUnits::Si;
+        run_app(args, &tool).unwrap();
+        // 25,000,000 bytes over 100 s is 2 Mb/s, minus 6,840 bps of muxing
+        // and 128k of audio.
+        assert_eq!(arg_value(&tool.single_call(), "-b:v"), Some("1865k"));
+
+        let tool = MockVideoTool::new(100.0);
+        let mut args = args_in(&dir, 25);
+        // What a size is read in unless said otherwise.
+        assert_eq!(args.common.size_units, SizeUnits::Binary);
+        args.common.size_units = SizeUnits::Binary;
+        run_app(args, &tool).unwrap();
+        // 25 MiB is 26,214,400 bytes, 2,097,152 bps over 100 s, less the
+        // same muxing and audio.
+        assert_eq!(arg_value(&tool.single_call(), "-b:v"), Some("1962k"));
+    }
+
+    #[test]
+    fn test_display_units_apart_from_size_units() {
+        let sizes = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4"];
+            argv.extend(extra);
+            let cli = Cli::try_parse_from(argv).unwrap();
+            cli.args
+                .common
+                .display
+                .size_format(cli.args.common.size_units)
+        };
+        let grouped = |units| SizeFormat::new(units, Separators::COMMA);
+        // Shown as read unless said otherwise.
+        let args = ["--digit-grouping", "comma"];
+        assert_eq!(sizes(&args), grouped(SizeUnits::Binary));
+        assert_eq!(
+            sizes(&["--size-units", "si", "--digit-grouping", "comma"]),
+            grouped(SizeUnits::Si)
+        );
+        assert_eq!(
+            sizes(&[
+                "--size-units",
+                "si",
+                "--display-units",
+                "binary",
+                "--digit-grouping",
+                "comma"
+            ]),
+            grouped(SizeUnits::Binary)
+        );
+        assert_eq!(
+            sizes(&["--si", "--digit-grouping", "period"]),
+            SizeFormat::new(SizeUnits::Si, Separators::PERIOD)
+        );
+        assert!(
+            Cli::try_parse_from(["mdviqure", "a", "b", "--si", "--display-units", "si"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_probe_limit_url_takes_the_place_of_size() {
+        let with = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4"];
+            argv.extend(extra);
+            Cli::try_parse_from(argv)
+        };
+        let url = "http://127.0.0.1:9/limit";
+        assert!(with(&["--probe-limit-url", url, "--size", "25"]).is_err());
+        assert!(with(&["--limit-json-path", "max"]).is_err());
+        let args = with(&["--probe-limit-url", url, "--limit-json-path", "max"])
+            .unwrap()
+            .args;
+        assert_eq!(args.common.limit_json_path.as_deref(), Some("max"));
+
+        // Nothing listens on the discard port.
+        let err = args.common.reduce_options().unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)), "{:?}", err);
+        assert!(err
+            .to_string()
+            .contains("--probe-limit-url: http://127.0.0.1:9/limit: "));
+    }
+
+    #[test]
+    fn test_duration_accepts_clock_time() {
+        let args = parse(&["mdviqure", "in.mp4", "out.mp4", "--duration", "1:02:03.5"]);
+        let opts = args.common.reduce_options().unwrap();
+        assert_eq!(opts.duration, Some(3723.5));
+
+        let args = parse(&["mdviqure", "in.mp4", "out.mp4", "--duration", "0"]);
+        assert!(args.common.reduce_options().is_err());
+        assert!(
+            Cli::try_parse_from(["mdviqure", "in.mp4", "out.mp4", "--duration", "soon"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_every_problem_is_reported_before_the_run() {
+        let args = parse(&[
+            "mdviqure",
+            "in.mp4",
+            "out.mp4",
+            "--sample",
+            "0",
+            "--duration",
+            "0",
+        ]);
+        let err = args.common.reduce_options().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2 problems stop the run:\n  --duration must be greater than zero\n  --sample must be greater than zero"
+        );
+
+        // An input on disk is probed for the problems of reducing it.
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.duration = Some("100".into());
+        tool.info.audio_streams = Some(vec![AudioStream::default()]);
+        std::fs::write(dir.join("in.mp4"), b"video").unwrap();
+        let mut args = args_in(&dir, 5);
+        args.input = Some(dir.join("in.mp4"));
+        args.common.audio_track = AudioSelection::Track(2);
+        args.common.fail_on_poor_quality = true;
+        let err = run_app(args, &tool).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+        assert!(
+            err.to_string().starts_with(
+                "2 problems stop the run:\n  --audio-track 2: the input has 1 audio track\n"
+            ),
+            "{}",
+            err
+        );
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sample_writes_next_to_the_output() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut args = args_in(&dir, 50);
+        args.common.sample = Some(30.0);
+        run_app(args, &tool).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-t"), Some("30.000"));
+        assert_eq!(dir.entries(), vec!["out.sample.mp4"]);
+
+        let args = parse(&["mdviqure", "in.mp4", "out.mp4", "--sample", "0"]);
+        assert!(args.common.reduce_options().is_err());
+        assert!(Cli::try_parse_from([
+            "mdviqure", "in.mp4", "out.mp4", "--sample", "10", "--split", "2"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_max_duration_and_window_start_are_read() {
+        let opts = parse(&[
+            "mdviqure",
+            "in.mp4",
+            "out.mp4",
+            "--max-duration",
+            "0:30",
+            "--window-start",
+            "1:15.5",
+        ])
+        .common
+        .reduce_options()
+        .unwrap();
+        assert_eq!(opts.max_duration, Some(30.0));
+        assert_eq!(opts.window_start, Some(75.5));
+
+        let args = parse(&["mdviqure", "in.mp4", "out.mp4", "--max-duration", "0"]);
+        assert!(args.common.reduce_options().is_err());
+        assert!(Cli::try_parse_from([
+            "mdviqure",
+            "in.mp4",
+            "out.mp4",
+            "--max-duration",
+            "30",
+            "--chunked-encode"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_download_first_needs_a_url() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut args = args_in(&dir, 50);
+        args.download_first = true;
+        assert!(matches!(run_app(args, &tool), Err(ReduceError::Usage(_))));
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sample_never_overwrites_the_input() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut args = args_in(&dir, 50);
+        args.input = Some(dir.join("out.sample.mp4"));
+        args.common.sample = Some(30.0);
+        let err = run_app(args, &tool).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)));
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_cfr_rate_is_optional() {
+        let opts = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4"];
+            argv.extend(extra);
+            parse(&argv).common.reduce_options()
+        };
+        assert_eq!(opts(&[]).unwrap().cfr, None);
+        assert_eq!(opts(&["--cfr"]).unwrap().cfr, Some(None));
+        assert_eq!(opts(&["--cfr=25"]).unwrap().cfr, Some(Some(25.0)));
+        assert!(opts(&["--cfr=0"]).is_err());
+        assert!(
+            Cli::try_parse_from(["mdviqure", "in.mp4", "out.mp4", "--cfr", "--fps", "30"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_output_folders_suggest_a_profile_unless_the_size_is_given() {
+        let target = |extra: &[&str], config: Config| {
+            let mut argv = vec!["mdviqure"];
+            argv.extend(extra);
+            argv.extend(["in.mp4", "Videos/Discord/out.mp4"]);
+            let args = parse(&argv);
+            let mut opts = args.common.reduce_options().unwrap();
+            infer_profile(
+                &args.common,
+                Some(Path::new("Videos/Discord")),
+                &mut opts,
+                &config,
+                Presenter::stderr(ColorChoice::Never),
+            );
+            opts.target_bytes
+        };
+        let off = Config {
+            infer_profile: false,
+        };
+        let discord = Profile::Discord.target_bytes();
+        // (flags, settings file, target)
+        let cases: [(&[&str], Config, u64); 7] = [
+            (&[], Config::default(), 100 << 20),
+            (&["--auto-profile"], Config::default(), discord),
+            (
+                &["--auto-profile", "--size", "50"],
+                Config::default(),
+                50 << 20,
+            ),
+            (&["--profile", "whatsapp"], Config::default(), 16_000_000),
+            (
+                &["--profile", "whatsapp", "--auto-profile"],
+                Config::default(),
+                16_000_000,
+            ),
+            (
+                &["--profile", "whatsapp", "-s", "5"],
+                Config::default(),
+                5 << 20,
+            ),
+            // The settings file turns the guessing off, not the flag.
+            (&["--auto-profile"], off, discord),
+        ];
+        for (extra, config, expected) in cases {
+            assert_eq!(target(extra, config), expected, "{:?}", extra);
+        }
+    }
+
+    #[test]
+    fn test_inhibit_sleep_defaults_to_auto() {
+        let policy = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure"];
+            argv.extend(extra);
+            argv.extend(["in.mp4", "out.mp4"]);
+            parse(&argv).common.reduce_options().unwrap().inhibit_sleep
+        };
+        assert_eq!(policy(&[]), sleep::Policy::Auto);
+        // A bare flag doesn't swallow the input.
+        assert_eq!(policy(&["--inhibit-sleep"]), sleep::Policy::Always);
+        assert_eq!(policy(&["--inhibit-sleep=never"]), sleep::Policy::Never);
+    }
+
+    #[test]
+    fn test_chunk_count_defaults_to_the_cores() {
+        let opts = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4"];
+            argv.extend(extra);
+            parse(&argv).common.reduce_options()
+        };
+        assert_eq!(opts(&[]).unwrap().chunks, 1);
+        assert_eq!(
+            opts(&["--chunked-encode"]).unwrap().chunks,
+            chunked::default_count()
+        );
+        assert_eq!(opts(&["--chunked-encode=3"]).unwrap().chunks, 3);
+        for conflict in [&["--split", "2"], &["--sample", "10"]] {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4", "--chunked-encode"];
+            argv.extend(conflict);
+            assert!(Cli::try_parse_from(argv).is_err());
+        }
+    }
+
+    #[test]
+    fn test_tag_metadata_is_off_unless_asked_for() {
+        let opts = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4"];
+            argv.extend(extra);
+            parse(&argv).common.reduce_options().unwrap()
+        };
+        assert!(!opts(&[]).tag_metadata);
+        assert!(opts(&["--tag-metadata"]).tag_metadata);
+        // The last one given wins, so an alias can be overridden.
+        assert!(!opts(&["--tag-metadata", "--no-tag-metadata"]).tag_metadata);
+        assert!(opts(&["--no-tag-metadata", "--tag-metadata"]).tag_metadata);
+    }
+
+    #[test]
+    fn test_effort_picks_the_preset_unless_one_is_given() {
+        let opts = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4"];
+            argv.extend(extra);
+            let opts = parse(&argv).common.reduce_options().unwrap();
+            (opts.preset, opts.effort)
+        };
+        assert_eq!(opts(&[]), (Preset::Medium, None));
+        assert_eq!(opts(&["--effort", "8"]), (Preset::Slower, Some(8)));
+        assert_eq!(
+            opts(&["--effort", "8", "--preset", "fast"]),
+            (Preset::Fast, Some(8))
+        );
+        for level in ["0", "10"] {
+            assert!(
+                Cli::try_parse_from(["mdviqure", "in.mp4", "out.mp4", "--effort", level]).is_err()
+            );
+        }
+        assert!(parse(&["mdviqure", "--explain-effort"]).explain_effort);
+    }
+
+    #[test]
+    fn test_device_bundles_give_way_to_explicit_flags() {
+        let opts = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4"];
+            argv.extend(extra);
+            parse(&argv).common.reduce_options().unwrap()
+        };
+        let plain = opts(&[]);
+        assert_eq!(plain.compat, Compat::default());
+        assert_eq!((plain.max_height, plain.max_fps), (None, None));
+
+        let tv = opts(&["--device", "old-tv"]);
+        assert_eq!(tv.compat.h264_profile, Some("main"));
+        assert_eq!((tv.max_width, tv.max_height), (Some(1280), Some(720)));
+        assert_eq!(tv.max_fps, Some(30.0));
+
+        let tv = opts(&[
+            "--device",
+            "old-tv",
+            "--max-height",
+            "480",
+            "--codec",
+            "svt-av1",
+        ]);
+        assert_eq!((tv.max_width, tv.max_height), (Some(1280), Some(480)));
+        assert_eq!(tv.encoder, VideoEncoder::SvtAv1);
+        assert_eq!(tv.compat.audio_channels, Some(2));
+
+        let phone = opts(&["--device", "ios", "--vertical"]);
+        assert_eq!((phone.max_width, phone.max_height), (None, None));
+
+        let err = Cli::try_parse_from(["mdviqure", "in.mp4", "out.mp4", "--device", "vcr"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("old-tv, ios, android, web, ps4"), "{}", err);
+        assert!(parse(&["mdviqure", "--list-devices"]).list_devices);
+    }
+
+    #[test]
+    fn test_vertical_flags_pick_the_fit() {
+        let opts = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4"];
+            argv.extend(extra);
+            parse(&argv).common.reduce_options().unwrap().vertical
+        };
+        assert_eq!(opts(&[]), None);
+        assert_eq!(opts(&["--vertical"]), Some(vertical::Fit::Pad));
+        assert_eq!(opts(&["--vertical-crop"]), Some(vertical::Fit::Crop));
+        assert_eq!(
+            opts(&["--vertical", "--vertical-crop"]),
+            Some(vertical::Fit::Crop)
+        );
+        assert!(Cli::try_parse_from([
+            "mdviqure",
+            "in.mp4",
+            "out.mp4",
+            "--vertical",
+            "--max-height",
+            "720"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_region_is_parsed_as_geometry() {
+        let args = |region: &str| {
+            Cli::try_parse_from(["mdviqure", "in.mp4", "out.mp4", "--region", region])
+        };
+        let opts = parse(&[
+            "mdviqure",
+            "in.mp4",
+            "out.mp4",
+            "--region",
+            "1280x720+2560+0",
+        ])
+        .common
+        .reduce_options()
+        .unwrap();
+        assert_eq!(opts.region.unwrap().to_string(), "1280x720+2560+0");
+        let error = args("1280x720-10+0").unwrap_err().to_string();
+        assert!(error.contains("can't be negative"), "{}", error);
+        assert!(Cli::try_parse_from([
+            "mdviqure",
+            "in.mp4",
+            "out.mp4",
+            "--region",
+            "1280x720+0+0",
+            "--remux-only"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_encoder_params_need_their_codec() {
+        let opts = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4"];
+            argv.extend(extra);
+            parse(&argv).common.reduce_options()
+        };
+        let params = opts(&["--x264-params", "aq-mode=3:psy-rd=1.0,0.15"])
+            .unwrap()
+            .encoder_params
+            .unwrap();
+        assert_eq!(params.args(), ["-x264-params", "aq-mode=3:psy-rd=1.0,0.15"]);
+        let err = opts(&["--svtav1-params", "tune=0"]).unwrap_err();
+        assert_eq!(err.to_string(), "--svtav1-params needs --codec svt-av1");
+        assert!(opts(&["--codec", "svt-av1", "--svtav1-params", "tune=0"]).is_ok());
+        // Malformed lists are rejected while parsing.
+        assert!(
+            Cli::try_parse_from(["mdviqure", "in.mp4", "out.mp4", "--x264-params", "aq-mode"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_mode_is_auto_unless_given_or_pinned() {
+        let mode = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "out.mp4"];
+            argv.extend(extra);
+            parse(&argv)
+                .common
+                .reduce_options()
+                .map(|opts| opts.rate_mode)
+        };
+        assert_eq!(mode(&[]).unwrap(), Mode::Auto);
+        assert_eq!(mode(&["--mode", "abr"]).unwrap(), Mode::Abr);
+        assert_eq!(mode(&["--profile", "discord"]).unwrap(), Mode::CrfCapped);
+        assert_eq!(mode(&["--profile", "telegram"]).unwrap(), Mode::Auto);
+        assert_eq!(
+            mode(&["--profile", "whatsapp", "--mode", "abr"]).unwrap(),
+            Mode::Abr
+        );
+        // --two-pass needs an average bitrate, which a pin gives way to.
+        assert_eq!(
+            mode(&["--profile", "discord", "--two-pass"]).unwrap(),
+            Mode::Auto
+        );
+        let err = mode(&["--mode", "crf-capped", "--two-pass"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--two-pass needs an average bitrate, not --mode crf-capped"
+        );
+    }
+
+    #[test]
+    fn test_hls_time_needs_format_hls() {
+        let hls = |extra: &[&str]| {
+            let mut argv = vec!["mdviqure", "in.mp4", "stream"];
+            argv.extend(extra);
+            parse(&argv).hls()
+        };
+        assert_eq!(hls(&[]).unwrap(), None);
+        assert_eq!(
+            hls(&["--format", "hls"]).unwrap(),
+            Some(Hls {
+                segment_seconds: 6.0
+            })
+        );
+        assert_eq!(
+            hls(&["--format", "hls", "--hls-time", "4"]).unwrap(),
+            Some(Hls {
+                segment_seconds: 4.0
+            })
+        );
+        let err = hls(&["--hls-time", "4"]).unwrap_err();
+        assert_eq!(err.to_string(), "--hls-time only applies to --format hls");
+        assert!(Cli::try_parse_from(["mdviqure", "in.mp4", "stream", "--hls-time", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_input_pattern_takes_the_place_of_input() {
+        let args = parse(&[
+            "mdviqure",
+            "--input-pattern",
+            "frames/%05d.png",
+            "--input-fps",
+            "30",
+            "out.mp4",
+        ]);
+        assert_eq!(args.paths().unwrap(), ("frames/%05d.png", "out.mp4"));
+        assert_eq!(
+            args.image_input().unwrap(),
+            Some(ImageInput::Sequence { fps: 30.0 })
+        );
+
+        let args = parse(&[
+            "mdviqure",
+            "a.mp4",
+            "out.mp4",
+            "--input-pattern",
+            "frames/%05d.png",
+            "--input-fps",
+            "30",
+        ]);
+        assert!(args.paths().is_err());
+        assert!(Cli::try_parse_from(["mdviqure", "--input-pattern", "%d.png", "out.mp4"]).is_err());
+    }
+
+    #[test]
+    fn test_launch_failure_is_only_a_warning() {
+        struct Broken;
+        impl Platform for Broken {
+            fn open(&self, _path: &Path) -> launch::LaunchCommand {
+                launch::LaunchCommand {
+                    program: "mdviqure-no-such-player".into(),
+                    args: Vec::new(),
+                    check_status: true,
+                }
+            }
+            fn reveal(&self, path: &Path) -> launch::LaunchCommand {
+                self.open(path)
+            }
+        }
+        let args = parse(&["mdviqure", "in.mp4", "out.mp4", "--open", "--reveal"]);
+        // Returns nothing to fail with; the warning goes to the console.
+        launch_result(
+            &Broken,
+            Path::new("out.mp4"),
+            &args,
+            Presenter::stderr(ColorChoice::Never),
+        );
+    }
+
+    #[test]
+    fn test_json_report() {
+        let dir = TestDir::new();
+        let output = dir.join("out.mp4");
+        std::fs::write(&output, vec![0; 1000]).unwrap();
+        let opts = ReduceOptions::new(2000);
+        let report = ReduceReport {
+            prediction: None,
+            caps: vec![Cap::MaxHeight],
+            sample: None,
+            warnings: vec![Warning::new(Code::BitrateClamped, "too small")],
+            usage: Some(Usage {
+                wall_s: 12.5,
+                cpu_s: Some(40.0),
+                peak_rss_bytes: None,
+            }),
+            recipe: None,
+            sidecar: None,
+            subtitles: Vec::new(),
+            checksums: None,
+            attempts: Vec::new(),
+        };
+        let line = run_report("https://x/in.mp4?sig=1", &output, &opts, &Ok(report)).to_line();
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["input"], "https://x/in.mp4?sig=***");
+        assert_eq!(json["output_bytes"], 1000);
+        assert_eq!(json["target_bytes"], 2000);
+        assert_eq!(json["warnings"][0]["code"], "bitrate_clamped");
+        assert_eq!(json["caps"][0], "max_height");
+        assert_eq!(json["usage"]["cpu_s"], 40.0);
+        assert!(json["usage"].get("peak_rss_bytes").is_none());
+        assert!(json.get("error").is_none());
+
+        // A failure carries the error and what was warned before it.
+        warning::take();
+        warning::emit(
+            Presenter::stderr(ColorChoice::Never),
+            Warning::new(Code::EncoderFallback, "no svt-av1"),
+        );
+        let failed = Err(ReduceError::Encode("ffmpeg failed".into()));
+        let json: serde_json::Value =
+            serde_json::from_str(&run_report("in.mp4", &output, &opts, &failed).to_line()).unwrap();
+        assert_eq!(json["error"], "ffmpeg failed");
+        assert!(json.get("output_bytes").is_none());
+        assert_eq!(json["warnings"][0]["code"], "encoder_fallback");
+    }
+
+    #[test]
+    fn test_size_ladder_encodes_only_what_it_picks() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(60.0);
+        let mut args = args_in(&dir, 50);
+        args.size_ladder = Some(ladder::DEFAULT_LADDER.to_string());
+        run_app(args, &tool).unwrap();
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+        assert!(dir.entries().is_empty());
+
+        // A minute of 1080p reaches fair quality at 25 MiB, not at 10.
+        let mut args = args_in(&dir, 50);
+        args.size_ladder = Some(ladder::DEFAULT_LADDER.to_string());
+        args.pick_best_under = Some(Quality::Fair);
+        run_app(args, &tool).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        let expected = ReduceOptions::new(25 << 20);
+        let plan = ladder::evaluate(
+            60.0,
+            &tool.get_video_info("in.mp4").unwrap(),
+            &expected,
+            &[25 << 20],
+        );
+        assert_eq!(
+            arg_value(&calls[0], "-b:v"),
+            Some(format!("{}k", plan[0].summary.video_bitrate / 1000).as_str())
+        );
+        drop(calls);
+
+        let mut args = args_in(&dir, 50);
+        args.size_ladder = Some("2,1".to_string());
+        args.pick_best_under = Some(Quality::Fair);
+        let err = run_app(args, &tool).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(m) if m.contains("reaches fair quality")));
+
+        // The quality bar needs a ladder to pick from.
+        let err =
+            Cli::try_parse_from(["mdviqure", "in.mp4", "out.mp4", "--pick-best-under", "good"])
+                .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn test_compare_clip_follows_the_encode_and_never_fails_it() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut args = args_in(&dir, 50);
+        args.compare = true;
+        run_app(args, &tool).unwrap();
+        {
+            let calls = tool.ffmpeg_calls.lock().unwrap();
+            assert_eq!(calls.len(), 2);
+            assert!(arg_value(&calls[1], "-filter_complex")
+                .unwrap()
+                .contains("hstack"));
+        }
+        assert_eq!(dir.entries(), vec!["out.mp4", "out_compare.mp4"]);
+
+        // Past the end of the output: only a warning.
+        let tool = MockVideoTool::new(100.0);
+        let mut args = args_in(&dir, 50);
+        args.compare = true;
+        args.compare_at = Some(500.0);
+        run_app(args, &tool).unwrap();
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 1);
+
+        let args = parse(&["mdviqure", "in.mp4", "-", "--compare"]);
+        let err = run_app(args, &MockVideoTool::new(10.0)).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(m) if m.contains("--compare")));
+        assert!(
+            Cli::try_parse_from(["mdviqure", "in.mp4", "out.mp4", "--compare-at", "5"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_stats_show_bounded_and_pending_estimates() {
+        let mut learned = Learned::default();
+        learned.estimates.insert(
+            Container::Mkv,
+            Estimate::of(&[8.0, 9.0, 8.5, 9.5, 9.0]).unwrap(),
+        );
+        learned
+            .estimates
+            .insert(Container::Webm, Estimate::of(&[0.4, 0.6]).unwrap());
+        let lines = stats_table(&learned).render(100);
+        assert!(
+            lines[1].ends_with("+9.00%  5.00% (bounded to 0-5%)"),
+            "{}",
+            lines[1]
+        );
+        assert!(
+            lines[2].ends_with("--overhead-percent until 3 more"),
+            "{}",
+            lines[2]
+        );
+    }
+
+    #[test]
+    fn test_json_output_needs_stdout_free() {
+        let args = parse(&["mdviqure", "in.mp4", "-", "--output-format", "json"]);
+        let err = run_app(args, &MockVideoTool::new(10.0)).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(m) if m.contains("--output-format json")));
+    }
+
+    #[test]
+    fn test_run_app_validation_failure() {
+        let tool = MockVideoTool::new(60.0);
+        let args = parse(&["mdviqure", "in.mp4", "out.mp4", "--size", "lots"]);
+
+        let result = run_app(args, &tool);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "invalid size 'lots': expected e.g. 25, 25MB, 500KB or 1.5GiB"
+        );
+    }
+
+    fn probe_args(argv: &[&str]) -> ProbeArgs {
+        match Cli::parse_from(argv).command {
+            Some(Command::Probe(args)) => args,
+            other => panic!("not a probe command: {:?}", other),
+        }
+    }
+
+    /// A mock whose 10 s MKV holds 1 MB of video and 100 KB of audio, with
+    /// only the audio's bit rate recorded.
+    fn probe_tool() -> MockVideoTool {
+        use crate::breakdown::{FileStreams, StreamEntry, StreamKind};
+        let mut tool = MockVideoTool::new(10.0);
+        let stream = |index, kind, codec: &str, bit_rate| StreamEntry {
+            index,
+            kind,
+            codec: codec.into(),
+            bit_rate,
+            duration: None,
+            extradata_bytes: 0,
+            language: None,
+        };
+        tool.streams = FileStreams {
+            size_bytes: Some(1_200_000),
+            duration: Some(10.0),
+            bit_rate: Some(960_000),
+            comment: None,
+            streams: vec![
+                stream(0, StreamKind::Video, "h264", None),
+                stream(1, StreamKind::Audio, "opus", Some(80_000)),
+            ],
+        };
+        tool.packet_sizes = [(0, 1_000_000), (1, 100_000)].into();
+        tool
+    }
+
+    #[test]
+    fn test_probe_lists_streams() {
+        let tool = probe_tool();
+        let args = probe_args(&["mdviqure", "probe", "in.mkv", "--size-units", "si"]);
+        let lines = probe_report(&args, &tool, 80).unwrap();
+        assert_eq!(lines[0], "Duration 0:10, size 1.2 MB, bitrate 960k");
+        assert!(lines[2].starts_with("#0 video"), "{:?}", lines);
+        assert!(lines[3].contains("opus"), "{:?}", lines);
+        assert_eq!(tool.packet_calls.get(), 0);
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn test_probe_shows_how_the_file_was_made() {
+        let mut tool = probe_tool();
+        tool.streams.comment = Some("Encoded with HandBrake".into());
+        let args = probe_args(&["mdviqure", "probe", "in.mkv"]);
+        assert_eq!(probe_report(&args, &tool, 80).unwrap().len(), 4);
+
+        let tag = "mdviqure 0.1.0: target=1MiB codec=h264 preset=medium br=800k audio=96k";
+        tool.streams.comment = Some(tag.into());
+        let lines = probe_report(&args, &tool, 80).unwrap();
+        assert_eq!(lines.last().unwrap(), &format!("Made with {}", tag));
+    }
+
+    #[test]
+    fn test_probe_breakdown_uses_bitrates_unless_exact() {
+        let tool = probe_tool();
+        let args = probe_args(&["mdviqure", "probe", "in.mkv", "--breakdown"]);
+        let lines = probe_report(&args, &tool, 80).unwrap();
+        assert_eq!(tool.packet_calls.get(), 0);
+        assert!(lines
+            .iter()
+            .any(|l| l.contains("1 stream records no bit rate")));
+
+        let args = probe_args(&["mdviqure", "probe", "in.mkv", "--breakdown", "--exact"]);
+        let lines = probe_report(&args, &tool, 80).unwrap();
+        assert_eq!(tool.packet_calls.get(), 1);
+        let overhead = lines
+            .iter()
+            .find(|l| l.starts_with("container overhead"))
+            .unwrap();
+        // 1,200,000 - 1,100,000 bytes.
+        assert!(overhead.ends_with("8.3%"), "{}", overhead);
+        assert_eq!(lines.last().unwrap(), "Sizes are summed packet sizes.");
+    }
+
+    #[test]
+    fn test_probe_breakdown_needs_a_size() {
+        let mut tool = probe_tool();
+        tool.streams.size_bytes = None;
+        let args = probe_args(&["mdviqure", "probe", "in.mkv", "--breakdown"]);
+        let err = probe_report(&args, &tool, 80).unwrap_err();
+        assert!(matches!(err, ReduceError::Probe(_)), "{:?}", err);
+        assert!(Cli::try_parse_from(["mdviqure", "probe", "in.mkv", "--exact"]).is_err());
+    }
+
+    fn plan_args(argv: &[&str]) -> PlanArgs {
+        match Cli::parse_from(argv).command {
+            Some(Command::Plan(args)) => args,
+            other => panic!("not a plan command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_plan_matches_the_plan_of_a_real_run() {
+        let argv = [
+            "mdviqure",
+            "plan",
+            "--duration",
+            "1:23:45",
+            "--size",
+            "500",
+            "--audio-bitrate",
+            "96k",
+            "--output-format",
+            "json",
+        ];
+        let lines = plan_report(&plan_args(&argv)).unwrap();
+        assert_eq!(lines.len(), 1);
+        let json: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+
+        // A probed 1080p30 file of the same length, reduced the same way.
+        let tool = MockVideoTool::new(5025.0);
+        let mut opts = ReduceOptions::new(500 * 1024 * 1024);
+        opts.audio_bitrate = Some(96_000);
+        let plan = crate::reduce::plan_encoding(5025.0, &tool.info, &opts);
+        assert_eq!(json["video_bitrate"], plan.video_bitrate);
+        assert_eq!(json["audio_bitrate"], 96_000);
+        assert_eq!(json["predicted_bytes"], plan.predicted_bytes);
+        assert_eq!(json["duration_s"], 5025.0);
+        assert_eq!(json["verdict"], "poor_quality");
+        assert_eq!(json["quality"], "poor");
+    }
+
+    #[test]
+    fn test_plan_prints_the_steps_and_the_verdict() {
+        let args = plan_args(&["mdviqure", "plan", "--duration", "3600", "--size", "10"]);
+        let lines = plan_report(&args).unwrap();
+        assert_eq!(lines[0], "Target 10 MiB");
+        assert!(
+            lines.iter().any(|l| l.starts_with("  = video 100k")),
+            "{:?}",
+            lines
+        );
+        assert!(
+            lines.contains(
+                &"Verdict for 10 MiB: does not fit: even the minimum bitrate is over the target"
+                    .to_string()
+            ),
+            "{:?}",
+            lines
+        );
+        assert!(
+            lines.last().unwrap().starts_with("Warning: "),
+            "{:?}",
+            lines
+        );
+
+        let args = plan_args(&["mdviqure", "plan", "--duration", "0"]);
+        assert!(matches!(plan_report(&args), Err(ReduceError::Usage(_))));
+        for bad in [
+            &["--resolution", "1080p"][..],
+            &["--audio-bitrate", "96k", "--no-audio"],
+            &["--size", "25", "--duration", "soon"],
+        ] {
+            let mut argv = vec!["mdviqure", "plan", "--duration", "60"];
+            argv.extend(bad);
+            assert!(Cli::try_parse_from(argv).is_err(), "{:?}", bad);
+        }
+    }
+}
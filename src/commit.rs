@@ -0,0 +1,250 @@
+//! Putting a finished file at its output path, the last step of every flow
+//! that writes one.
+//!
+//! Everything is written in the run's temp directory first, so a run that
+//! fails before [`commit`] (ffmpeg failing, an output over the target, a
+//! full disk, Ctrl-C) leaves the output path as it was: an input reduced in
+//! place, or an earlier output a batch writes over, is untouched. [`commit`]
+//! checks the finished file, flushes it to disk and renames it over the
+//! output in one step, so the output path never names a partial file, not
+//! even after a power loss. From a temp directory on another disk the file
+//! is first copied to a `.part` file beside the output, which is removed if
+//! anything fails.
+//!
+//! A run can also fail after its output is in place, computing checksums
+//! or writing the sidecar. With `--keep-original-on-failure` ([`hold`]) the
+//! file an output replaces is kept beside it until the run has succeeded,
+//! and such a failure puts it back.
+
+use crate::tempdir;
+use crate::tool::VideoTool;
+use std::cell::RefCell;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+thread_local! {
+    /// The replaced files kept by the [`Held`] of this thread, if any.
+    static HELD: RefCell<Option<Vec<Replaced>>> = const { RefCell::new(None) };
+}
+
+/// An output that replaced a file, and where that file is kept.
+#[derive(Debug)]
+struct Replaced {
+    output: PathBuf,
+    original: PathBuf,
+}
+
+/// Moves `finished`, which was measured at `bytes` when that is given, to
+/// `output`: verify, flush, rename, and keep or drop what it replaces.
+/// Whatever fails, `output` is either what it was or the whole file,
+/// and no `.part` file is left beside it.
+pub fn commit<T: VideoTool>(
+    tool: &T,
+    finished: &Path,
+    output: &Path,
+    bytes: Option<u64>,
+) -> io::Result<()> {
+    verify(finished, bytes)?;
+    sync(finished)?;
+    let original = keep_original(output)?;
+    let result = tool
+        .rename_file(finished, output)
+        .or_else(|_| copy_over(tool, finished, output));
+    if let Err(e) = result {
+        if let Some(original) = original {
+            let _ = fs::remove_file(original);
+        }
+        return Err(e);
+    }
+    sync_dir(output);
+    if let Some(original) = original {
+        HELD.with(|held| {
+            if let Some(held) = held.borrow_mut().as_mut() {
+                held.push(Replaced {
+                    output: output.to_path_buf(),
+                    original,
+                });
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Fails when `finished` is gone or no longer has the `bytes` it was
+/// measured at.
+fn verify(finished: &Path, bytes: Option<u64>) -> io::Result<()> {
+    let len = fs::metadata(finished)?.len();
+    match bytes {
+        Some(bytes) if len != bytes => Err(io::Error::other(format!(
+            "it was {} bytes when checked, but is {} now",
+            bytes, len
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn sync(path: &Path) -> io::Result<()> {
+    OpenOptions::new().write(true).open(path)?.sync_all()
+}
+
+/// Flushes the directory entry of a rename. Not every platform can open a
+/// directory for that, and the file itself is safe either way.
+fn sync_dir(output: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = output.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let _ = fs::File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    #[cfg(not(unix))]
+    let _ = output;
+}
+
+/// A path beside `output` for a file of the run, hidden where a leading
+/// dot hides it.
+fn beside(output: &Path, suffix: &str) -> PathBuf {
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!(".{}.{}.{}", name, tempdir::unique_token(), suffix))
+}
+
+/// Copies `finished` to a `.part` file beside `output` and renames that
+/// over it, for a `finished` on another disk.
+fn copy_over<T: VideoTool>(tool: &T, finished: &Path, output: &Path) -> io::Result<()> {
+    let part = beside(output, "part");
+    let copied = fs::copy(finished, &part)
+        .and_then(|_| sync(&part))
+        .and_then(|()| tool.rename_file(&part, output));
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&part);
+        return Err(e);
+    }
+    let _ = fs::remove_file(finished);
+    Ok(())
+}
+
+/// With a [`Held`] on this thread, keeps the file at `output`, if there is
+/// one, beside it: a hard link where the filesystem has them, a copy
+/// elsewhere.
+fn keep_original(output: &Path) -> io::Result<Option<PathBuf>> {
+    let holding = HELD.with(|held| held.borrow().is_some());
+    if !holding || !output.is_file() {
+        return Ok(None);
+    }
+    let original = beside(output, "orig");
+    if fs::hard_link(output, &original).is_err() {
+        if let Err(e) = fs::copy(output, &original).and_then(|_| sync(&original)) {
+            let _ = fs::remove_file(&original);
+            return Err(e);
+        }
+    }
+    Ok(Some(original))
+}
+
+/// Keeps the files that commits on this thread replace until
+/// [`Held::release`]; dropped unreleased, as when the run returns an
+/// error, it puts them back.
+#[derive(Debug)]
+pub struct Held(());
+
+/// Starts keeping the files that outputs replace; see [`Held`].
+pub fn hold() -> Held {
+    HELD.with(|held| *held.borrow_mut() = Some(Vec::new()));
+    Held(())
+}
+
+impl Held {
+    /// The run succeeded: the replaced files are deleted.
+    pub fn release(self) {
+        for replaced in HELD.with(|held| held.take()).unwrap_or_default() {
+            let _ = fs::remove_file(replaced.original);
+        }
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        // Last replaced first, in case one output was written twice.
+        for replaced in HELD
+            .with(|held| held.take())
+            .unwrap_or_default()
+            .iter()
+            .rev()
+        {
+            let _ = fs::rename(&replaced.original, &replaced.output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockVideoTool, TestDir};
+
+    #[test]
+    fn test_a_commit_replaces_the_output_whole() {
+        let dir = TestDir::new();
+        let (finished, output) = (dir.path().join("partial"), dir.path().join("out.mp4"));
+        fs::write(&finished, b"new").unwrap();
+        fs::write(&output, b"old").unwrap();
+        commit(&MockVideoTool::new(1.0), &finished, &output, Some(3)).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"new");
+        assert_eq!(dir.entries(), ["out.mp4"]);
+    }
+
+    #[test]
+    fn test_a_finished_file_that_changed_is_not_committed() {
+        let dir = TestDir::new();
+        let (finished, output) = (dir.path().join("partial"), dir.path().join("out.mp4"));
+        fs::write(&finished, b"new").unwrap();
+        fs::write(&output, b"old").unwrap();
+        let e = commit(&MockVideoTool::new(1.0), &finished, &output, Some(4)).unwrap_err();
+        assert_eq!(e.to_string(), "it was 4 bytes when checked, but is 3 now");
+        assert_eq!(fs::read(&output).unwrap(), b"old");
+    }
+
+    #[test]
+    fn test_a_failed_rename_leaves_no_part_file() {
+        let dir = TestDir::new();
+        let (finished, output) = (dir.path().join("partial"), dir.path().join("out.mp4"));
+        fs::write(&finished, b"new").unwrap();
+        fs::write(&output, b"old").unwrap();
+        let mut tool = MockVideoTool::new(1.0);
+        tool.rename_error = Some(io::ErrorKind::PermissionDenied);
+        let _held = hold();
+        let e = commit(&tool, &finished, &output, None).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(fs::read(&output).unwrap(), b"old");
+        assert_eq!(dir.entries(), ["out.mp4", "partial"]);
+    }
+
+    #[test]
+    fn test_held_originals_come_back_unless_released() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(1.0);
+        let output = dir.path().join("out.mp4");
+        for release in [false, true] {
+            fs::write(&output, b"old").unwrap();
+            let finished = dir.path().join("partial");
+            fs::write(&finished, b"new").unwrap();
+            let held = hold();
+            commit(&tool, &finished, &output, None).unwrap();
+            assert_eq!(fs::read(&output).unwrap(), b"new");
+            if release {
+                held.release();
+                assert_eq!(fs::read(&output).unwrap(), b"new");
+            } else {
+                drop(held);
+                assert_eq!(fs::read(&output).unwrap(), b"old");
+            }
+            assert_eq!(dir.entries(), ["out.mp4"]);
+        }
+        // Nothing is kept without a hold.
+        fs::write(dir.path().join("partial"), b"new").unwrap();
+        commit(&tool, &dir.path().join("partial"), &output, None).unwrap();
+        assert_eq!(dir.entries(), ["out.mp4"]);
+    }
+}
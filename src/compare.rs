@@ -0,0 +1,201 @@
+//! `--compare`: a short clip of the input and the reduced output side by
+//! side, for judging what the reduction cost.
+//!
+//! Artifacts show most where the picture changes most, which is also where
+//! the source spends the most bits, so the clip covers the window of the
+//! source's largest video packets unless `--compare-at` picks one.
+
+use crate::error::ReduceError;
+use crate::filename;
+use crate::filter;
+use crate::longpath;
+use crate::tool::VideoTool;
+use std::path::Path;
+
+/// Length of the comparison clip in seconds.
+pub const CLIP_SECONDS: f64 = 10.0;
+
+/// Where the comparison clip of `output` goes: `out/clip.mkv` becomes
+/// `out/clip_compare.mp4`.
+pub fn clip_path(output: &str) -> String {
+    let path = Path::new(output);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = filename::fit_length(&format!("{}_compare.mp4", stem), filename::MAX_BYTES);
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Parses `ffprobe -show_entries packet=pts_time,size -of csv=p=0` output
+/// into (time, bytes) pairs; packets without a timestamp are skipped.
+pub fn parse_packet_times(stdout: &str) -> Vec<(f64, u64)> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let (time, size) = line.trim().split_once(',')?;
+            Some((time.parse().ok()?, size.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Start of the `length`-second window within `0..duration` whose
+/// `packets` (time, bytes) add up to the most bytes. Without any packets
+/// the middle of the input is as good a guess as any.
+pub fn busiest_window(packets: &[(f64, u64)], length: f64, duration: f64) -> f64 {
+    let latest = (duration - length).max(0.0);
+    let mut packets: Vec<(f64, u64)> = packets
+        .iter()
+        .copied()
+        .filter(|&(time, _)| time >= 0.0 && time < duration)
+        .collect();
+    if packets.is_empty() {
+        return latest / 2.0;
+    }
+    packets.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (mut best_start, mut best_bytes) = (0.0, 0);
+    let (mut end, mut bytes) = (0, 0);
+    for (first, &(start, _)) in packets.iter().enumerate() {
+        let start = start.min(latest);
+        while end < packets.len() && packets[end].0 < start + length {
+            bytes += packets[end].1;
+            end += 1;
+        }
+        if bytes > best_bytes {
+            (best_start, best_bytes) = (start, bytes);
+        }
+        if end > first {
+            bytes -= packets[first].1;
+        }
+    }
+    best_start
+}
+
+/// The ffmpeg arguments rendering `length` seconds from `start` of `input`
+/// and `output` side by side at `height` into `destination`.
+pub fn clip_args(
+    input: &str,
+    output: &str,
+    start: f64,
+    length: f64,
+    height: u32,
+    destination: &str,
+) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    for file in [input, output] {
+        args.extend([
+            "-ss".to_string(),
+            format!("{:.3}", start),
+            "-t".to_string(),
+            format!("{:.3}", length),
+            "-i".to_string(),
+            longpath::for_tool(file),
+        ]);
+    }
+    args.extend([
+        "-filter_complex".to_string(),
+        filter::side_by_side(height, ["Original", "Reduced"]),
+        "-map".to_string(),
+        "[v]".to_string(),
+        "-an".to_string(),
+    ]);
+    // Near-lossless, so the clip shows the output's artifacts, not its own.
+    args.extend(
+        [
+            "-c:v", "libx264", "-preset", "veryfast", "-crf", "16", "-pix_fmt", "yuv420p",
+        ]
+        .iter()
+        .map(|s| s.to_string()),
+    );
+    args.push(longpath::for_tool(destination));
+    args
+}
+
+/// Writes the comparison clip of `input` and its reduced `output`, at
+/// `at` seconds or the busiest window, and returns the clip's path.
+pub fn write_clip<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    output: &str,
+    at: Option<f64>,
+) -> Result<String, ReduceError> {
+    let reduced = tool.get_video_info(output)?;
+    let duration = tool.get_video_duration(output)?;
+    let length = CLIP_SECONDS.min(duration);
+    let start = match at {
+        Some(at) if at >= duration => {
+            return Err(ReduceError::Usage(format!(
+                "--compare-at {:.3} is past the end of the {:.3}-second output",
+                at, duration
+            )))
+        }
+        Some(at) => at.min(duration - length),
+        None => busiest_window(&tool.get_packet_times(input)?, length, duration),
+    };
+    let destination = clip_path(output);
+    let args = clip_args(input, output, start, length, reduced.height, &destination);
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    tool.run_ffmpeg(&args)?;
+    Ok(destination)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{arg_value, MockVideoTool, TestDir};
+
+    #[test]
+    fn test_clip_goes_next_to_the_output_as_mp4() {
+        assert_eq!(clip_path("out/clip.mkv"), "out/clip_compare.mp4");
+        assert_eq!(clip_path("clip"), "clip_compare.mp4");
+    }
+
+    #[test]
+    fn test_parse_packet_times() {
+        let stdout = "0.000000,5000\n0.033367,1200\nN/A,300\n\n0.066733,900\n";
+        assert_eq!(
+            parse_packet_times(stdout),
+            [(0.0, 5000), (0.033367, 1200), (0.066733, 900)]
+        );
+    }
+
+    #[test]
+    fn test_busiest_window_follows_the_bytes() {
+        // One packet a second; seconds 40..50 carry ten times the rest.
+        let packets: Vec<(f64, u64)> = (0..100)
+            .map(|s| (s as f64, if (40..50).contains(&s) { 10_000 } else { 1_000 }))
+            .collect();
+        assert_eq!(busiest_window(&packets, 10.0, 100.0), 40.0);
+        // A burst at the very end still leaves room for the whole window.
+        let mut packets = vec![(0.0, 1); 5];
+        packets.push((99.5, 50_000));
+        assert_eq!(busiest_window(&packets, 10.0, 100.0), 90.0);
+        assert_eq!(busiest_window(&[], 10.0, 100.0), 45.0);
+        assert_eq!(busiest_window(&[], 10.0, 6.0), 0.0);
+    }
+
+    #[test]
+    fn test_write_clip_at_a_chosen_time() {
+        let dir = TestDir::new();
+        let output = dir.join("out.mp4");
+        let mut tool = MockVideoTool::new(60.0).with_dimensions(1280, 720);
+        tool.packet_times = vec![(20.0, 10), (30.0, 90_000)];
+
+        let clip = write_clip(&tool, "in.mp4", &output, Some(55.0)).unwrap();
+        assert_eq!(clip, dir.join("out_compare.mp4"));
+        let args = tool.single_call();
+        // Pulled back so the whole window fits.
+        assert_eq!(arg_value(&args, "-ss"), Some("50.000"));
+        assert_eq!(arg_value(&args, "-t"), Some("10.000"));
+        assert!(arg_value(&args, "-filter_complex")
+            .unwrap()
+            .contains("scale=trunc(iw*sar*720/ih/2)*2:720"));
+
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        write_clip(&tool, "in.mp4", &output, None).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-ss"), Some("30.000"));
+
+        let err = write_clip(&tool, "in.mp4", &output, Some(60.0)).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)));
+    }
+}
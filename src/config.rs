@@ -0,0 +1,102 @@
+//! The tool's config directory and the settings file in it.
+//!
+//! `config.json` holds the defaults that are a matter of taste rather than
+//! of one run, next to the history ledger. A missing file leaves every
+//! setting at its default; one that can't be read is warned about and
+//! ignored, so a typo never stops a run.
+
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// The settings file's name in [`dir`].
+pub const FILE_NAME: &str = "config.json";
+
+/// The tool's directory in the platform's config directory.
+pub fn dir() -> Option<PathBuf> {
+    let env_dir = |name: &str| {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    let base = if let Some(dir) = env_dir("XDG_CONFIG_HOME") {
+        Some(dir)
+    } else if cfg!(windows) {
+        env_dir("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env_dir("HOME").map(|home| home.join(".config"))
+    };
+    base.map(|dir| dir.join("mdviqure"))
+}
+
+/// What `config.json` sets.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Look at the output's folders for a destination profile; see
+    /// [`crate::profile`].
+    pub infer_profile: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            infer_profile: true,
+        }
+    }
+}
+
+impl Config {
+    /// The settings in `path`; a missing file is the defaults.
+    pub fn at(path: &Path) -> Result<Config, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+        };
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// The settings in the config directory, with the error when the file
+    /// is there but unusable.
+    pub fn load() -> (Config, Option<String>) {
+        match dir().map(|dir| Config::at(&dir.join(FILE_NAME))) {
+            Some(Ok(config)) => (config, None),
+            Some(Err(e)) => (Config::default(), Some(e)),
+            None => (Config::default(), None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_missing_settings_are_defaults() {
+        let dir = TestDir::new();
+        let path = dir.path().join(FILE_NAME);
+        assert_eq!(Config::at(&path), Ok(Config::default()));
+        std::fs::write(&path, "{}").unwrap();
+        assert_eq!(Config::at(&path), Ok(Config::default()));
+        std::fs::write(&path, r#"{"infer_profile": false}"#).unwrap();
+        assert!(!Config::at(&path).unwrap().infer_profile);
+    }
+
+    #[test]
+    fn test_bad_settings_say_where() {
+        let dir = TestDir::new();
+        let path = dir.path().join(FILE_NAME);
+        for bad in [
+            r#"{"infer_profiles": false}"#,
+            "not json",
+            r#"{"infer_profile": 1}"#,
+        ] {
+            std::fs::write(&path, bad).unwrap();
+            let err = Config::at(&path).unwrap_err();
+            assert!(err.starts_with(&path.display().to_string()), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Destination for human-readable status output.
+//!
+//! Status normally goes to stdout, but when stdout carries the encoded video
+//! (`-` as the output path) every message has to move to stderr instead.
+//! A stream that `--progress-json` writes its events to is reserved, and
+//! status meant for it is dropped.
+//!
+//! A reader that goes away, such as `head` closing the pipe, fails the
+//! next write with a broken pipe. The stream is then left alone and the run
+//! carries on, unless `--abort-on-broken-pipe` asked for it to stop
+//! ([`broken_pipe`]), which it does as if Ctrl-C had been pressed.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// Bit per [`Stream`] set once it is reserved.
+static RESERVED: AtomicU8 = AtomicU8::new(0);
+
+/// Bit per [`Stream`] set once its reader went away.
+static CLOSED: AtomicU8 = AtomicU8::new(0);
+
+/// Whether a reader going away stops the run.
+static ABORT_ON_BROKEN_PIPE: AtomicBool = AtomicBool::new(false);
+
+/// Whether one went away since.
+static ABORTED: AtomicBool = AtomicBool::new(false);
+
+/// Stops the run once a reader of status or events goes away, from now on.
+pub fn abort_on_broken_pipe() {
+    ABORT_ON_BROKEN_PIPE.store(true, Ordering::SeqCst);
+}
+
+/// Whether the run was stopped because a reader went away.
+pub fn aborted_for_broken_pipe() -> bool {
+    ABORTED.load(Ordering::SeqCst)
+}
+
+/// Whether a write failed with `error` because its reader went away, in
+/// which case the run is stopped when [`abort_on_broken_pipe`] asked for
+/// it.
+pub fn broken_pipe(error: &io::Error) -> bool {
+    if error.kind() != io::ErrorKind::BrokenPipe {
+        return false;
+    }
+    if ABORT_ON_BROKEN_PIPE.load(Ordering::SeqCst) && !ABORTED.swap(true, Ordering::SeqCst) {
+        crate::interrupt::request();
+    }
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream {
+    Stdout,
+    Stderr,
+    /// Nowhere: status a run keeps to itself.
+    Null,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Console {
+    stream: Stream,
+}
+
+impl Console {
+    pub fn stdout() -> Self {
+        Self {
+            stream: Stream::Stdout,
+        }
+    }
+
+    pub fn stderr() -> Self {
+        Self {
+            stream: Stream::Stderr,
+        }
+    }
+
+    /// A console that drops everything, for a run asked for no status.
+    pub fn none() -> Self {
+        Self {
+            stream: Stream::Null,
+        }
+    }
+
+    /// Picks stderr when `output` means standard output.
+    pub fn for_output(output: &str) -> Self {
+        if output == crate::STDIO_PATH {
+            Self::stderr()
+        } else {
+            Self::stdout()
+        }
+    }
+
+    /// Keeps status off this stream for the rest of the process, because
+    /// machine-readable output took it over.
+    pub fn reserve(&self) {
+        RESERVED.fetch_or(self.bit(), Ordering::Relaxed);
+    }
+
+    fn is_reserved(&self) -> bool {
+        RESERVED.load(Ordering::Relaxed) & self.bit() != 0
+    }
+
+    fn is_closed(&self) -> bool {
+        CLOSED.load(Ordering::Relaxed) & self.bit() != 0
+    }
+
+    fn bit(&self) -> u8 {
+        match self.stream {
+            Stream::Stdout => 1,
+            Stream::Stderr => 2,
+            Stream::Null => 0,
+        }
+    }
+
+    /// Whether the stream is attached to a terminal.
+    pub fn is_terminal(&self) -> bool {
+        match self.stream {
+            Stream::Stdout => io::stdout().is_terminal(),
+            Stream::Stderr => io::stderr().is_terminal(),
+            Stream::Null => false,
+        }
+    }
+
+    /// Prints one status line.
+    pub fn say(&self, line: &str) {
+        self.write(&format!("{}\n", line));
+    }
+
+    /// Writes `text` as-is and flushes, for in-place redraws (`\r`). Other
+    /// write errors are ignored, as status is no reason to fail the run.
+    pub fn write(&self, text: &str) {
+        if self.is_reserved() || self.is_closed() {
+            return;
+        }
+        let written = match self.stream {
+            Stream::Stdout => {
+                let mut out = io::stdout().lock();
+                out.write_all(text.as_bytes()).and_then(|_| out.flush())
+            }
+            Stream::Stderr => {
+                let mut err = io::stderr().lock();
+                err.write_all(text.as_bytes()).and_then(|_| err.flush())
+            }
+            Stream::Null => return,
+        };
+        if written.is_err_and(|e| broken_pipe(&e)) {
+            CLOSED.fetch_or(self.bit(), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_moves_to_stderr_for_streamed_output() {
+        assert_eq!(Console::for_output("-"), Console::stderr());
+        assert_eq!(Console::for_output("out.mp4"), Console::stdout());
+    }
+}
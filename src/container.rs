@@ -0,0 +1,360 @@
+//! Output containers, inferred from the output's extension, which codecs
+//! each can carry as they are (for `--remux-only`), and which encoders a
+//! run may write into it. An output whose extension isn't listed in
+//! [`EXTENSIONS`] is refused before anything runs.
+
+use crate::audio::AudioCodec;
+use crate::encoder::VideoEncoder;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Container {
+    /// `.mp4` and `.m4v`.
+    Mp4,
+    Mov,
+    Mkv,
+    Webm,
+    /// Legacy; only with `--allow-legacy-container`, and with H.264 and MP3.
+    Avi,
+}
+
+/// The output extensions the tool writes, and their containers.
+pub const EXTENSIONS: &[(&str, Container)] = &[
+    ("mp4", Container::Mp4),
+    ("m4v", Container::Mp4),
+    ("mov", Container::Mov),
+    ("mkv", Container::Mkv),
+    ("webm", Container::Webm),
+    ("avi", Container::Avi),
+];
+
+/// The codec changes a stream copy into a container needs, once
+/// [`Container::check`] found the streams compatible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remux {
+    pub container: Container,
+    /// Text subtitles are converted to this codec (MP4's `mov_text`);
+    /// `None` when they can be copied.
+    pub subtitle_codec: Option<&'static str>,
+}
+
+/// Text subtitle formats ffmpeg can convert into one another.
+const TEXT_SUBTITLES: &[&str] = &["subrip", "ass", "ssa", "webvtt", "mov_text", "text"];
+
+impl Container {
+    /// The container the extension of `output` asks for.
+    pub fn from_path(output: &str) -> Option<Self> {
+        let ext = Path::new(output)
+            .extension()?
+            .to_string_lossy()
+            .to_ascii_lowercase();
+        EXTENSIONS
+            .iter()
+            .find(|(known, _)| *known == ext)
+            .map(|&(_, container)| container)
+    }
+
+    /// The container to write `output` as, or why the tool won't: its
+    /// extension is unknown, or names a legacy container without
+    /// `allow_legacy`.
+    pub fn for_output(output: &str, allow_legacy: bool) -> Result<Self, String> {
+        let supported = || {
+            EXTENSIONS
+                .iter()
+                .filter(|(_, container)| !container.is_legacy())
+                .map(|(ext, _)| format!(".{}", ext))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let Some(container) = Self::from_path(output) else {
+            let ext = Path::new(output)
+                .extension()
+                .map_or("no extension".to_string(), |ext| {
+                    format!(".{}", ext.to_string_lossy())
+                });
+            return Err(format!(
+                "can't write {}: {} isn't a supported output format (supported: {})",
+                output,
+                ext,
+                supported()
+            ));
+        };
+        if container.is_legacy() && !allow_legacy {
+            return Err(format!(
+                "{} is a legacy container with poor support for modern codecs; pass --allow-legacy-container to write it anyway, or use one of {}",
+                container,
+                supported()
+            ));
+        }
+        Ok(container)
+    }
+
+    /// Whether writing it takes `--allow-legacy-container`.
+    pub fn is_legacy(self) -> bool {
+        self == Container::Avi
+    }
+
+    /// The video encoders whose output it holds, preferred first.
+    pub fn video_encoders(self) -> &'static [VideoEncoder] {
+        match self {
+            Container::Mp4 | Container::Mov | Container::Mkv => {
+                &[VideoEncoder::H264, VideoEncoder::SvtAv1]
+            }
+            Container::Webm => &[VideoEncoder::SvtAv1],
+            Container::Avi => &[VideoEncoder::H264],
+        }
+    }
+
+    /// The audio format it is written with.
+    pub fn audio_codec(self) -> AudioCodec {
+        match self {
+            Container::Mp4 | Container::Mov | Container::Mkv => AudioCodec::Aac,
+            Container::Webm => AudioCodec::Opus,
+            Container::Avi => AudioCodec::Mp3,
+        }
+    }
+
+    pub fn carries_video(self, codec: &str) -> bool {
+        match self {
+            Container::Mp4 | Container::Mov => {
+                matches!(codec, "h264" | "hevc" | "av1" | "vp9" | "mpeg4" | "mjpeg")
+                    || (self == Container::Mov && codec == "prores")
+            }
+            Container::Mkv => true,
+            Container::Webm => matches!(codec, "vp8" | "vp9" | "av1"),
+            Container::Avi => matches!(codec, "h264" | "mpeg4" | "mjpeg" | "msmpeg4v3"),
+        }
+    }
+
+    pub fn carries_audio(self, codec: &str) -> bool {
+        match self {
+            Container::Mp4 => matches!(
+                codec,
+                "aac" | "mp3" | "ac3" | "eac3" | "opus" | "alac" | "flac"
+            ),
+            Container::Mov => matches!(
+                codec,
+                "aac" | "mp3" | "ac3" | "eac3" | "alac" | "pcm_s16le" | "pcm_s24le"
+            ),
+            Container::Mkv => true,
+            Container::Webm => matches!(codec, "opus" | "vorbis"),
+            Container::Avi => matches!(codec, "mp3" | "ac3" | "pcm_s16le"),
+        }
+    }
+
+    /// How subtitles of `codec` get into this container: `Some(None)` as
+    /// they are, `Some(Some(target))` converted, `None` not at all (bitmap
+    /// subtitles can't become text).
+    pub fn subtitle_codec(self, codec: &str) -> Option<Option<&'static str>> {
+        let text = TEXT_SUBTITLES.contains(&codec);
+        match self {
+            Container::Mp4 | Container::Mov if codec == "mov_text" => Some(None),
+            Container::Mp4 | Container::Mov => text.then_some(Some("mov_text")),
+            Container::Mkv => Some(None),
+            Container::Webm if codec == "webvtt" => Some(None),
+            Container::Webm => text.then_some(Some("webvtt")),
+            Container::Avi => None,
+        }
+    }
+
+    /// Whether the streams with these codecs can be copied into this
+    /// container; the error names the first one that can't.
+    pub fn check(self, video: &str, audio: &[&str], subtitles: &[&str]) -> Result<Remux, String> {
+        if !self.carries_video(video) {
+            return Err(format!("{} video can't go into {} as it is", video, self));
+        }
+        if let Some(codec) = audio.iter().find(|c| !self.carries_audio(c)) {
+            return Err(format!("{} audio can't go into {} as it is", codec, self));
+        }
+        let mut subtitle_codec = None;
+        for codec in subtitles {
+            match self.subtitle_codec(codec) {
+                Some(target) => subtitle_codec = subtitle_codec.or(target),
+                None => return Err(format!("{} subtitles can't go into {}", codec, self)),
+            }
+        }
+        Ok(Remux {
+            container: self,
+            subtitle_codec,
+        })
+    }
+
+    /// Whether attachments such as subtitle fonts can be copied in; only
+    /// Matroska stores files alongside the streams.
+    pub fn carries_attachments(self) -> bool {
+        self == Container::Mkv
+    }
+
+    /// Whether cover art can be copied in as an attached picture.
+    pub fn carries_cover_art(self) -> bool {
+        matches!(self, Container::Mp4 | Container::Mov | Container::Mkv)
+    }
+
+    /// Whether data streams such as camera telemetry can be copied in; only
+    /// the QuickTime family keeps timed metadata tracks.
+    pub fn carries_data_streams(self) -> bool {
+        matches!(self, Container::Mp4 | Container::Mov)
+    }
+
+    /// Whether the index should be moved to the front (`-movflags +faststart`)
+    /// so playback can start before the whole file has downloaded.
+    pub fn wants_faststart(self) -> bool {
+        matches!(self, Container::Mp4 | Container::Mov)
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Container::Mp4 => "MP4",
+            Container::Mov => "MOV",
+            Container::Mkv => "Matroska",
+            Container::Webm => "WebM",
+            Container::Avi => "AVI",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_from_extension() {
+        assert_eq!(Container::from_path("out/clip.MP4"), Some(Container::Mp4));
+        assert_eq!(Container::from_path("clip.m4v"), Some(Container::Mp4));
+        assert_eq!(Container::from_path("clip.webm"), Some(Container::Webm));
+        assert_eq!(Container::from_path("clip.avi"), Some(Container::Avi));
+        assert_eq!(Container::from_path("clip.mp3"), None);
+        assert_eq!(Container::from_path("clip"), None);
+    }
+
+    #[test]
+    fn test_output_formats_and_their_encoders() {
+        // (output, allowed without --allow-legacy-container, with it,
+        // video encoders, audio codec)
+        let cases: &[(&str, bool, bool, &[VideoEncoder], &str)] = &[
+            (
+                "a.mp4",
+                true,
+                true,
+                &[VideoEncoder::H264, VideoEncoder::SvtAv1],
+                "AAC",
+            ),
+            (
+                "a.m4v",
+                true,
+                true,
+                &[VideoEncoder::H264, VideoEncoder::SvtAv1],
+                "AAC",
+            ),
+            (
+                "a.mov",
+                true,
+                true,
+                &[VideoEncoder::H264, VideoEncoder::SvtAv1],
+                "AAC",
+            ),
+            (
+                "a.MKV",
+                true,
+                true,
+                &[VideoEncoder::H264, VideoEncoder::SvtAv1],
+                "AAC",
+            ),
+            ("a.webm", true, true, &[VideoEncoder::SvtAv1], "Opus"),
+            ("a.avi", false, true, &[VideoEncoder::H264], "MP3"),
+            ("a.mp3", false, false, &[], ""),
+            ("a.flv", false, false, &[], ""),
+            ("a", false, false, &[], ""),
+        ];
+        for &(output, allowed, legacy_allowed, video, audio) in cases {
+            assert_eq!(
+                Container::for_output(output, false).is_ok(),
+                allowed,
+                "{}",
+                output
+            );
+            let container = Container::for_output(output, true);
+            assert_eq!(container.is_ok(), legacy_allowed, "{}", output);
+            if let Ok(container) = container {
+                assert_eq!(container.video_encoders(), video, "{}", output);
+                assert_eq!(container.audio_codec().to_string(), audio, "{}", output);
+                // Every encoder it takes writes a codec it carries.
+                for encoder in video {
+                    let codec = match encoder {
+                        VideoEncoder::H264 => "h264",
+                        VideoEncoder::SvtAv1 => "av1",
+                    };
+                    assert!(container.carries_video(codec), "{} {}", output, codec);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_refusals_list_the_supported_formats() {
+        assert_eq!(
+            Container::for_output("song.mp3", false).unwrap_err(),
+            "can't write song.mp3: .mp3 isn't a supported output format (supported: .mp4, .m4v, .mov, .mkv, .webm)"
+        );
+        assert!(Container::for_output("clip", false)
+            .unwrap_err()
+            .contains("no extension"));
+        let avi = Container::for_output("clip.avi", false).unwrap_err();
+        assert!(avi.contains("--allow-legacy-container"), "{}", avi);
+    }
+
+    #[test]
+    fn test_mkv_to_mp4_matrix() {
+        let mp4 = Container::Mp4;
+        assert_eq!(
+            mp4.check("h264", &["aac"], &[]),
+            Ok(Remux {
+                container: mp4,
+                subtitle_codec: None
+            })
+        );
+        assert_eq!(
+            mp4.check("hevc", &["aac", "ac3"], &["subrip"])
+                .unwrap()
+                .subtitle_codec,
+            Some("mov_text")
+        );
+        assert_eq!(
+            mp4.check("h264", &["aac"], &["mov_text"])
+                .unwrap()
+                .subtitle_codec,
+            None
+        );
+        assert_eq!(
+            mp4.check("h264", &["vorbis"], &[]).unwrap_err(),
+            "vorbis audio can't go into MP4 as it is"
+        );
+        assert!(mp4.check("vp8", &[], &[]).is_err());
+        assert!(mp4.check("h264", &[], &["hdmv_pgs_subtitle"]).is_err());
+        assert!(Container::Mov.carries_video("prores"));
+        assert!(!mp4.carries_video("prores"));
+    }
+
+    #[test]
+    fn test_mkv_and_webm_matrix() {
+        assert!(Container::Mkv
+            .check("prores", &["pcm_s16le"], &["hdmv_pgs_subtitle"])
+            .is_ok());
+        let webm = Container::Webm;
+        assert!(webm.check("vp9", &["opus"], &[]).is_ok());
+        assert_eq!(
+            webm.check("av1", &["opus"], &["subrip"])
+                .unwrap()
+                .subtitle_codec,
+            Some("webvtt")
+        );
+        assert!(webm.check("h264", &["opus"], &[]).is_err());
+        assert!(webm.check("vp9", &["aac"], &[]).is_err());
+        assert!(!webm.wants_faststart() && Container::Mp4.wants_faststart());
+    }
+}
@@ -0,0 +1,118 @@
+//! Cover art: a picture stored as a one-frame video stream and flagged
+//! `attached_pic`, as MP4 and M4V files from stores and taggers often carry
+//! next to the film.
+//!
+//! It is never the video that is reduced: [`crate::probe::parse_video_info`]
+//! passes over it, and the encode maps [`VIDEO`], the first video stream
+//! that isn't a picture, even when a picture comes first. The cover art is
+//! left out of the output unless `--keep-cover-art` asks for it to be
+//! copied through untouched, which only an output with a place for it
+//! allows.
+
+use crate::container::Container;
+use crate::presenter::Presenter;
+use crate::warning::{self, Code, Warning};
+
+/// The stream specifier for the input's video: the first video stream
+/// that is not a picture.
+pub const VIDEO: &str = "0:V:0";
+
+/// Why `container` can't hold cover art, if it can't; `fragmented` is MP4
+/// or MOV written in fragments, as it is to stdout.
+pub fn no_room(container: Container, fragmented: bool) -> Option<String> {
+    if !container.carries_cover_art() {
+        Some(format!("{} has no place for it", container))
+    } else if fragmented && container != Container::Mkv {
+        Some("fragmented MP4 has no place for it".to_string())
+    } else {
+        None
+    }
+}
+
+/// The cover art of the input, by its absolute stream indices in
+/// `streams`, that goes into an output of `container`: all of it when
+/// `keep` and the container has room, none otherwise. Leaving it out is
+/// mentioned, and warned about when `--keep-cover-art` asked for it.
+pub fn kept(
+    streams: &[u32],
+    keep: bool,
+    container: Container,
+    fragmented: bool,
+    out: Presenter,
+) -> Vec<u32> {
+    if streams.is_empty() {
+        return Vec::new();
+    }
+    if !keep {
+        out.info("Leaving out the input's cover art; --keep-cover-art copies it");
+        return Vec::new();
+    }
+    if let Some(reason) = no_room(container, fragmented) {
+        let message = format!("the input's cover art is left out: {}", reason);
+        warning::emit(out, Warning::new(Code::CoverArtDropped, message));
+        return Vec::new();
+    }
+    streams.to_vec()
+}
+
+/// The arguments copying `streams` of input `input` into the output after
+/// the one video stream that is encoded, so they become `v:1` and on.
+pub fn args(streams: &[u32], input: usize) -> Vec<String> {
+    streams
+        .iter()
+        .enumerate()
+        .flat_map(|(i, index)| {
+            let output = i + 1;
+            [
+                "-map".to_string(),
+                format!("{}:{}", input, index),
+                format!("-c:v:{}", output),
+                "copy".to_string(),
+                format!("-disposition:v:{}", output),
+                "attached_pic".to_string(),
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presenter::ColorChoice;
+
+    #[test]
+    fn test_args_copy_each_picture() {
+        assert!(args(&[], 0).is_empty());
+        assert_eq!(
+            args(&[0, 3], 2).join(" "),
+            "-map 2:0 -c:v:1 copy -disposition:v:1 attached_pic \
+             -map 2:3 -c:v:2 copy -disposition:v:2 attached_pic"
+        );
+    }
+
+    #[test]
+    fn test_kept_only_where_asked_and_possible() {
+        let out = Presenter::stderr(ColorChoice::Never);
+        // (keep, container, fragmented) and whether it is kept.
+        let cases = [
+            (false, Container::Mp4, false, false),
+            (true, Container::Mp4, false, true),
+            (true, Container::Mov, false, true),
+            (true, Container::Mkv, false, true),
+            (true, Container::Mkv, true, true),
+            (true, Container::Mp4, true, false),
+            (true, Container::Webm, false, false),
+            (true, Container::Avi, false, false),
+        ];
+        for (keep, container, fragmented, expected) in cases {
+            warning::take();
+            let kept = kept(&[2], keep, container, fragmented, out);
+            assert_eq!(!kept.is_empty(), expected, "{:?}", (keep, container));
+            let warnings = warning::take();
+            // Only a --keep-cover-art that can't be honored warns.
+            assert_eq!(warnings.len(), usize::from(keep && !expected));
+            assert!(warnings.iter().all(|w| w.code == Code::CoverArtDropped));
+        }
+        assert!(kept(&[], true, Container::Mp4, false, out).is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+//! Data streams: timed metadata next to the picture and sound, such as the
+//! GPMF telemetry (GPS, gyro, accelerometer) GoPro cameras record as a
+//! `gpmd` track.
+//!
+//! They take no part in the encode: without `--keep-data-streams` they are
+//! left out, and with it those ffmpeg can copy go through untouched, their
+//! bitrates set aside from the budget like the audio's. ffmpeg can't copy
+//! a track it has no codec for, such as a `tmcd` timecode (it writes its
+//! own) or GoPro's `fdsc` recovery data, so those are always left out.
+//! Only MP4 and MOV hold data streams.
+
+use crate::container::Container;
+use crate::presenter::Presenter;
+use crate::probe::DataStream;
+use crate::warning::{self, Code, Warning};
+
+/// What a kept stream whose container doesn't record its bitrate is
+/// budgeted at: more than GoPro telemetry takes.
+pub const UNKNOWN_BITRATE: u64 = 64_000;
+
+/// Whether ffmpeg knows the codec of `stream`, so it can be copied.
+pub fn copyable(stream: &DataStream) -> bool {
+    stream
+        .codec_name
+        .as_deref()
+        .is_some_and(|codec| codec != "unknown" && codec != "none")
+}
+
+/// The absolute indices of those of `streams` that can be copied.
+pub fn copied(streams: &[DataStream]) -> Vec<u32> {
+    streams
+        .iter()
+        .filter(|stream| copyable(stream))
+        .map(|stream| stream.index)
+        .collect()
+}
+
+/// `#3 (gpmd, GoPro MET)`, for messages.
+pub fn describe(stream: &DataStream) -> String {
+    let codec = stream
+        .codec_tag_string
+        .as_deref()
+        .or(stream.codec_name.as_deref())
+        .unwrap_or("unknown");
+    match &stream.tags.handler_name {
+        Some(name) => format!("#{} ({}, {})", stream.index, codec, name),
+        None => format!("#{} ({})", stream.index, codec),
+    }
+}
+
+/// The data streams of the input, by their absolute indices, that go into
+/// an output of `container`: those ffmpeg can copy when `keep` and the
+/// container has room, none otherwise. Leaving them out is mentioned, and
+/// warned about when `--keep-data-streams` asked for them.
+pub fn kept(streams: &[DataStream], keep: bool, container: Container, out: Presenter) -> Vec<u32> {
+    if streams.is_empty() {
+        return Vec::new();
+    }
+    if !keep {
+        out.info(&format!(
+            "Leaving out the input's data stream{} (such as camera telemetry); --keep-data-streams copies them",
+            if streams.len() == 1 { "" } else { "s" }
+        ));
+        return Vec::new();
+    }
+    if !container.carries_data_streams() {
+        let message = format!(
+            "the input's data streams are left out: {} has no place for them",
+            container
+        );
+        warning::emit(out, Warning::new(Code::DataStreamsDropped, message));
+        return Vec::new();
+    }
+    let names: Vec<String> = streams
+        .iter()
+        .filter(|stream| !copyable(stream))
+        .map(describe)
+        .collect();
+    if !names.is_empty() {
+        out.info(&format!(
+            "Leaving out data stream{} {}: ffmpeg can't copy {}",
+            if names.len() == 1 { "" } else { "s" },
+            names.join(", "),
+            if names.len() == 1 { "it" } else { "them" }
+        ));
+    }
+    copied(streams)
+}
+
+/// Bits per second the `kept` streams of `streams` take together.
+pub fn bitrate(streams: &[DataStream], kept: &[u32]) -> u64 {
+    streams
+        .iter()
+        .filter(|stream| kept.contains(&stream.index))
+        .map(|stream| {
+            stream
+                .bit_rate
+                .as_deref()
+                .and_then(|b| b.parse().ok())
+                .filter(|&b: &u64| b > 0)
+                .unwrap_or(UNKNOWN_BITRATE)
+        })
+        .sum()
+}
+
+/// The arguments copying the `kept` streams of input `input`.
+pub fn args(kept: &[u32], input: usize) -> Vec<String> {
+    if kept.is_empty() {
+        return Vec::new();
+    }
+    let mut args: Vec<String> = kept
+        .iter()
+        .flat_map(|index| ["-map".to_string(), format!("{}:{}", input, index)])
+        .collect();
+    args.extend(["-c:d".to_string(), "copy".to_string()]);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presenter::ColorChoice;
+    use crate::probe::parse_data_streams;
+    use crate::testing::GOPRO_DATA_STREAMS;
+
+    #[test]
+    fn test_only_the_telemetry_of_a_gopro_clip_is_copyable() {
+        let streams = parse_data_streams(GOPRO_DATA_STREAMS).unwrap();
+        assert_eq!(copied(&streams), [3]);
+        assert_eq!(describe(&streams[1]), "#3 (gpmd, GoPro MET)");
+        assert_eq!(describe(&DataStream::default()), "#0 (unknown)");
+    }
+
+    #[test]
+    fn test_kept_only_where_asked_and_possible() {
+        let out = Presenter::stderr(ColorChoice::Never);
+        let streams = parse_data_streams(GOPRO_DATA_STREAMS).unwrap();
+        // (keep, container) and what is kept.
+        let cases: [(bool, Container, &[u32]); 5] = [
+            (false, Container::Mp4, &[]),
+            (true, Container::Mp4, &[3]),
+            (true, Container::Mov, &[3]),
+            (true, Container::Mkv, &[]),
+            (true, Container::Webm, &[]),
+        ];
+        for (keep, container, expected) in cases {
+            warning::take();
+            assert_eq!(
+                kept(&streams, keep, container, out),
+                expected,
+                "{:?}",
+                (keep, container)
+            );
+            let warnings = warning::take();
+            // Only a --keep-data-streams that can't be honored warns.
+            let refused = keep && expected.is_empty();
+            assert_eq!(warnings.len(), usize::from(refused));
+            assert!(warnings.iter().all(|w| w.code == Code::DataStreamsDropped));
+        }
+        assert!(kept(&[], true, Container::Mp4, out).is_empty());
+    }
+
+    #[test]
+    fn test_bitrate_and_args() {
+        let streams = parse_data_streams(GOPRO_DATA_STREAMS).unwrap();
+        assert_eq!(bitrate(&streams, &[3]), 48_063);
+        assert_eq!(bitrate(&streams, &[]), 0);
+        let unrated = DataStream {
+            index: 5,
+            codec_name: Some("bin_data".into()),
+            ..DataStream::default()
+        };
+        assert_eq!(bitrate(&[unrated], &[5]), UNKNOWN_BITRATE);
+
+        assert!(args(&[], 0).is_empty());
+        assert_eq!(args(&[3], 0).join(" "), "-map 0:3 -c:d copy");
+        assert_eq!(args(&[3, 5], 2).join(" "), "-map 2:3 -map 2:5 -c:d copy");
+    }
+}
@@ -0,0 +1,84 @@
+//! `--deterministic`: two runs on the same input with the same options
+//! write the same bytes.
+//!
+//! Left to themselves, outputs differ from run to run in a few places:
+//! the muxer stamps `creation_time` and its own version into the
+//! container, metadata is carried over from the input, and encoders that
+//! split the frame between threads may decide differently depending on
+//! which thread finishes first. Every command that writes a file then
+//! gets [`muxer_args`], and every one that encodes also [`encoder_args`].
+//! The `--tag-metadata` note has no timestamp, so it stays as it is.
+//!
+//! That is enough for libx264, whose single-threaded output depends only
+//! on its input and options, and for every audio encoder the tool uses.
+//! SVT-AV1 makes no such promise ([`VideoEncoder::bit_exact`]), so it is
+//! warned about. Either way the bytes only repeat with the same ffmpeg
+//! build: another version of ffmpeg or the encoder library encodes
+//! differently.
+
+use crate::encoder::VideoEncoder;
+
+/// The `creation_time` written into every output.
+pub const CREATION_TIME: &str = "1970-01-01T00:00:00.000000Z";
+
+/// Options for a command writing an output: no metadata of the input, a
+/// fixed creation time and no version strings from the muxer.
+pub fn muxer_args() -> Vec<String> {
+    [
+        "-map_metadata",
+        "-1",
+        "-metadata",
+        &format!("creation_time={}", CREATION_TIME),
+        "-fflags",
+        "+bitexact",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+/// Options for a command encoding a stream: one thread, and no version
+/// strings from the encoders.
+pub fn encoder_args() -> Vec<String> {
+    ["-threads", "1", "-flags", "+bitexact"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+/// Why `encoder` may still write different bytes each run, if it may.
+pub fn caveat(encoder: VideoEncoder) -> Option<String> {
+    (!encoder.bit_exact()).then(|| {
+        format!(
+            "{} is not guaranteed to encode the same bytes twice, so --deterministic outputs may still differ ({} is)",
+            encoder,
+            VideoEncoder::H264
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_svt_av1_is_warned_about() {
+        assert_eq!(caveat(VideoEncoder::H264), None);
+        let caveat = caveat(VideoEncoder::SvtAv1).unwrap();
+        assert!(
+            caveat.starts_with("libsvtav1 is not guaranteed"),
+            "{}",
+            caveat
+        );
+    }
+
+    #[test]
+    fn test_args_pin_what_varies() {
+        let muxer = muxer_args().join(" ");
+        assert_eq!(
+            muxer,
+            "-map_metadata -1 -metadata creation_time=1970-01-01T00:00:00.000000Z -fflags +bitexact"
+        );
+        assert_eq!(encoder_args().join(" "), "-threads 1 -flags +bitexact");
+    }
+}
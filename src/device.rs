@@ -0,0 +1,278 @@
+//! `--device`: the output constraints known to play on a kind of device.
+//!
+//! Each device stands for a [`Constraints`] bundle: the codec, the largest
+//! frame and frame rate, and the stream details ([`Compat`]) that players
+//! are picky about, such as the H.264 profile and level, 4:2:0 chroma and
+//! 48 kHz stereo audio. A flag given on the command line wins over the
+//! device's value for the same constraint, so `--device ios --max-height
+//! 720` keeps everything else the bundle says.
+
+use crate::encoder::VideoEncoder;
+use crate::presenter::{Align, Column, Table};
+use crate::probe::VideoInfo;
+use clap::ValueEnum;
+use std::fmt;
+
+/// A device to make the output play on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Device {
+    /// Smart TVs, set-top boxes and media players from the early 2010s.
+    OldTv,
+    Ios,
+    Android,
+    /// Every current browser's `<video>`.
+    Web,
+    Ps4,
+}
+
+/// Stream details of the output that don't change the bitrate math.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Compat {
+    /// H.264 profile (`-profile:v`), ignored for other encoders.
+    pub h264_profile: Option<&'static str>,
+    /// H.264 level (`-level:v`), ignored for other encoders.
+    pub h264_level: Option<&'static str>,
+    /// Pixel format of the encoded video.
+    pub pix_fmt: Option<&'static str>,
+    /// Audio sample rate in Hz.
+    pub audio_sample_rate: Option<u32>,
+    /// Most audio channels per track; more are downmixed.
+    pub audio_channels: Option<u32>,
+    /// Move an MP4 or MOV index to the front, so playback can start before
+    /// the whole file has arrived.
+    pub faststart: bool,
+}
+
+impl Compat {
+    /// Each of `self`'s values, or else `fallback`'s.
+    pub fn or(self, fallback: Compat) -> Compat {
+        Compat {
+            h264_profile: self.h264_profile.or(fallback.h264_profile),
+            h264_level: self.h264_level.or(fallback.h264_level),
+            pix_fmt: self.pix_fmt.or(fallback.pix_fmt),
+            audio_sample_rate: self.audio_sample_rate.or(fallback.audio_sample_rate),
+            audio_channels: self.audio_channels.or(fallback.audio_channels),
+            faststart: self.faststart || fallback.faststart,
+        }
+    }
+
+    /// The first of the video's limits that the probed stream `info` isn't
+    /// known to keep to, so that copying it would break them; `None` when
+    /// it keeps to all. The H.264 ones only apply to an H.264 stream.
+    pub fn unmet_by(&self, info: &VideoInfo) -> Option<String> {
+        if let Some(pix_fmt) = self.pix_fmt {
+            if info.pix_fmt.as_deref() != Some(pix_fmt) {
+                return Some(format!(
+                    "it is {}, not {}",
+                    info.pix_fmt.as_deref().unwrap_or("of unknown pixel format"),
+                    pix_fmt
+                ));
+            }
+        }
+        if info.codec_name.as_deref() != Some("h264") {
+            return None;
+        }
+        let profile = info.profile.as_deref().unwrap_or("unknown");
+        if let Some(wanted) = self.h264_profile {
+            let fits = h264_profile_rank(profile)
+                .zip(h264_profile_rank(wanted))
+                .is_some_and(|(rank, most)| rank <= most);
+            if !fits {
+                return Some(format!("its profile is {}, not {}", profile, wanted));
+            }
+        }
+        if let Some(wanted) = self.h264_level {
+            let most = wanted
+                .parse::<f64>()
+                .map_or(0, |level| (level * 10.0).round() as i64);
+            let fits = info.level.is_some_and(|level| level > 0 && level <= most);
+            if !fits {
+                let level = info
+                    .level
+                    .filter(|&level| level > 0)
+                    .map_or("unknown".to_string(), |level| {
+                        format!("{}.{}", level / 10, level % 10)
+                    });
+                return Some(format!("its level is {}, over {}", level, wanted));
+            }
+        }
+        None
+    }
+}
+
+/// Where an H.264 profile stands among those players ask for, each of
+/// which a player of a later one also plays; `None` for the rest, such as
+/// High 10.
+fn h264_profile_rank(profile: &str) -> Option<u8> {
+    match profile.to_ascii_lowercase().as_str() {
+        "baseline" | "constrained baseline" => Some(0),
+        "main" => Some(1),
+        "high" => Some(2),
+        _ => None,
+    }
+}
+
+/// What the output has to stick to. Unset values leave the usual choice.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Constraints {
+    pub encoder: Option<VideoEncoder>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub max_fps: Option<f64>,
+    pub compat: Compat,
+}
+
+impl Constraints {
+    /// Each of `self`'s values, or else `fallback`'s: the explicit flags
+    /// over a device's bundle.
+    pub fn or(self, fallback: Constraints) -> Constraints {
+        Constraints {
+            encoder: self.encoder.or(fallback.encoder),
+            max_width: self.max_width.or(fallback.max_width),
+            max_height: self.max_height.or(fallback.max_height),
+            max_fps: self.max_fps.or(fallback.max_fps),
+            compat: self.compat.or(fallback.compat),
+        }
+    }
+}
+
+/// The bundle of a device, from one table row.
+const fn bundle(
+    profile: &'static str,
+    level: &'static str,
+    (max_width, max_height): (u32, u32),
+    max_fps: f64,
+) -> Constraints {
+    Constraints {
+        encoder: Some(VideoEncoder::H264),
+        max_width: Some(max_width),
+        max_height: Some(max_height),
+        max_fps: Some(max_fps),
+        compat: Compat {
+            h264_profile: Some(profile),
+            h264_level: Some(level),
+            pix_fmt: Some("yuv420p"),
+            audio_sample_rate: Some(48_000),
+            audio_channels: Some(2),
+            faststart: true,
+        },
+    }
+}
+
+impl Device {
+    pub fn constraints(self) -> Constraints {
+        match self {
+            Device::OldTv => bundle("main", "3.1", (1280, 720), 30.0),
+            Device::Ios => bundle("high", "4.2", (1920, 1080), 60.0),
+            Device::Android => bundle("high", "4.1", (1920, 1080), 30.0),
+            Device::Web => bundle("high", "4.1", (1920, 1080), 60.0),
+            Device::Ps4 => bundle("high", "4.2", (1920, 1080), 60.0),
+        }
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no skipped devices");
+        f.write_str(value.get_name())
+    }
+}
+
+/// `--list-devices`: every device with its bundle.
+pub fn table() -> Table {
+    let mut table = Table::new(vec![
+        Column::new("Device", Align::Left),
+        Column::new("Video", Align::Left),
+        Column::new("Max", Align::Right),
+        Column::new("Audio", Align::Left),
+    ]);
+    for &device in Device::value_variants() {
+        let c = device.constraints();
+        let encoder = c.encoder.map_or("-".to_string(), |encoder| {
+            let value = encoder.to_possible_value().expect("no skipped encoders");
+            value.get_name().to_string()
+        });
+        let profile = match (c.compat.h264_profile, c.compat.h264_level) {
+            (Some(profile), Some(level)) => format!(" {}@{}", profile, level),
+            _ => String::new(),
+        };
+        let pix_fmt = c
+            .compat
+            .pix_fmt
+            .map_or(String::new(), |p| format!(" {}", p));
+        let size = match (c.max_width, c.max_height, c.max_fps) {
+            (Some(w), Some(h), Some(fps)) => format!("{}x{}@{}", w, h, fps),
+            _ => "-".to_string(),
+        };
+        let channels = match c.compat.audio_channels {
+            Some(1) => " mono",
+            Some(2) => " stereo",
+            _ => "",
+        };
+        let rate = c
+            .compat
+            .audio_sample_rate
+            .map_or(String::new(), |hz| format!(" {}kHz", hz / 1000));
+        table.push(vec![
+            device.to_string(),
+            format!("{}{}{}", encoder, profile, pix_fmt),
+            size,
+            format!("AAC{}{}", rate, channels),
+        ]);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_device_has_a_playable_bundle() {
+        for &device in Device::value_variants() {
+            let c = device.constraints();
+            assert_eq!(c.encoder, Some(VideoEncoder::H264), "{}", device);
+            assert_eq!(c.compat.pix_fmt, Some("yuv420p"), "{}", device);
+            assert_eq!(c.compat.audio_sample_rate, Some(48_000), "{}", device);
+            assert_eq!(c.compat.audio_channels, Some(2), "{}", device);
+            assert!(c.compat.faststart, "{}", device);
+            assert!(c.max_width.zip(c.max_height).is_some(), "{}", device);
+        }
+        let old = Device::OldTv.constraints();
+        assert_eq!(
+            (old.compat.h264_profile, old.compat.h264_level),
+            (Some("main"), Some("3.1"))
+        );
+        assert_eq!((old.max_width, old.max_height), (Some(1280), Some(720)));
+        assert_eq!(Device::Android.constraints().max_fps, Some(30.0));
+    }
+
+    #[test]
+    fn test_explicit_values_win_one_by_one() {
+        let explicit = Constraints {
+            max_height: Some(720),
+            encoder: Some(VideoEncoder::SvtAv1),
+            ..Constraints::default()
+        };
+        let merged = explicit.or(Device::Ios.constraints());
+        assert_eq!(merged.max_height, Some(720));
+        assert_eq!(merged.encoder, Some(VideoEncoder::SvtAv1));
+        // The rest comes from the bundle.
+        assert_eq!(merged.max_width, Some(1920));
+        assert_eq!(merged.max_fps, Some(60.0));
+        assert_eq!(merged.compat, Device::Ios.constraints().compat);
+
+        // Without a device, only the flags count.
+        assert_eq!(explicit.or(Constraints::default()), explicit);
+    }
+
+    #[test]
+    fn test_table_lists_every_device() {
+        let lines = table().render(usize::MAX);
+        assert_eq!(lines.len(), 1 + Device::value_variants().len());
+        assert_eq!(
+            lines[1],
+            "old-tv   h264 main@3.1 yuv420p   1280x720@30  AAC 48kHz stereo"
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Running out of disk space, before and during a run.
+//!
+//! ffmpeg reports a full disk as one more muxer error, and a copy across
+//! filesystems as an `io::Error`; either way the run fails with
+//! [`ReduceError::DiskFull`], naming the directory and what was free then,
+//! instead of a generic encode failure. Before encoding, a run checks that
+//! the disks it writes to have room for the target and warns when they are
+//! close.
+
+use crate::error::ReduceError;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What ffmpeg prints when a write runs out of space: the texts of ENOSPC
+/// and EDQUOT on Unix, and of ERROR_DISK_FULL on Windows.
+const SIGNATURES: &[&str] = &[
+    "No space left on device",
+    "Disk quota exceeded",
+    "There is not enough space on the disk",
+];
+
+/// Free space below this share over what a run needs counts as tight.
+pub const HEADROOM_PERCENT: u64 = 10;
+
+/// Whether ffmpeg's `stderr` says it ran out of space.
+pub fn is_out_of_space(stderr: &str) -> bool {
+    SIGNATURES
+        .iter()
+        .any(|signature| stderr.contains(signature))
+}
+
+/// Whether one of our own writes failed for lack of space.
+pub fn is_storage_full(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+    )
+}
+
+/// The error for running out of space in `dir`, with what is free there
+/// now.
+pub fn full(dir: &Path) -> ReduceError {
+    ReduceError::DiskFull {
+        dir: dir.to_path_buf(),
+        free_bytes: free_bytes(dir),
+    }
+}
+
+/// `err` as [`ReduceError::DiskFull`] for `dir` when it is an ffmpeg
+/// failure for lack of space; otherwise unchanged.
+pub fn classify(err: ReduceError, dir: &Path) -> ReduceError {
+    match err {
+        ReduceError::Encode(message) if is_out_of_space(&message) => full(dir),
+        other => other,
+    }
+}
+
+/// Bytes free to unprivileged users on the filesystem holding `path`, when
+/// the platform can tell.
+pub fn free_bytes(path: &Path) -> Option<u64> {
+    if cfg!(unix) {
+        let output = std::process::Command::new("df")
+            .arg("-Pk")
+            .arg(path)
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()?;
+        parse_df(&String::from_utf8_lossy(&output.stdout))
+    } else {
+        None
+    }
+}
+
+/// Parses `df -Pk` output into bytes available. The mount point may hold
+/// spaces, so the field before the capacity percentage is taken.
+pub fn parse_df(stdout: &str) -> Option<u64> {
+    let line = stdout.lines().nth(1)?;
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let capacity = fields.iter().position(|f| f.ends_with('%'))?;
+    let kib: u64 = fields.get(capacity.checked_sub(1)?)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Whether `a` and `b` are on the same filesystem, so a file moves between
+/// them by renaming. Assumed when it can't be told.
+pub fn same_disk(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (std::fs::metadata(a), std::fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev(),
+            _ => true,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        true
+    }
+}
+
+/// What a run needs free, at most, on the disks it writes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Needs {
+    /// Per directory, the bytes it may hold at once.
+    pub dirs: Vec<(PathBuf, u64)>,
+}
+
+impl Needs {
+    /// A run writing `parts` outputs of up to `target_bytes` each into
+    /// `output_dir` by way of `temp_dir`. Each part is encoded in the temp
+    /// directory, retries overwriting the same file, and then moved out;
+    /// a chunked encode also holds its chunks until they are joined.
+    pub fn of_run(
+        target_bytes: u64,
+        parts: u32,
+        chunked: bool,
+        temp_dir: &Path,
+        output_dir: &Path,
+        same_disk: bool,
+    ) -> Self {
+        let in_progress = if chunked {
+            target_bytes * 2
+        } else {
+            target_bytes
+        };
+        let outputs = target_bytes * parts.max(1) as u64;
+        let dirs = if same_disk {
+            // The last part is renamed into place, not copied.
+            vec![(temp_dir.to_path_buf(), outputs - target_bytes + in_progress)]
+        } else {
+            vec![
+                (temp_dir.to_path_buf(), in_progress),
+                (output_dir.to_path_buf(), outputs),
+            ]
+        };
+        Self { dirs }
+    }
+}
+
+/// Whether `free` bytes leave less than [`HEADROOM_PERCENT`] over
+/// `needed`.
+pub fn is_tight(needed: u64, free: u64) -> bool {
+    free < needed + needed * HEADROOM_PERCENT / 100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffmpeg_out_of_space_messages() {
+        let unix = "[mp4 @ 0x55d] Error writing trailer of out.mp4: No space left on device";
+        assert!(is_out_of_space(unix));
+        assert!(is_out_of_space(
+            "av_interleaved_write_frame(): There is not enough space on the disk."
+        ));
+        assert!(is_out_of_space("out.mkv: Disk quota exceeded"));
+        assert!(!is_out_of_space("Conversion failed!"));
+
+        let dir = Path::new("/tmp");
+        let err = classify(
+            ReduceError::Encode(format!("ffmpeg failed:\n{}", unix)),
+            dir,
+        );
+        assert!(matches!(&err, ReduceError::DiskFull { dir, .. } if dir == Path::new("/tmp")));
+        let err = classify(ReduceError::Encode("Conversion failed!".into()), dir);
+        assert!(matches!(err, ReduceError::Encode(_)));
+        assert!(matches!(
+            classify(ReduceError::Interrupted, dir),
+            ReduceError::Interrupted
+        ));
+    }
+
+    #[test]
+    fn test_storage_full_io_errors() {
+        assert!(is_storage_full(&io::Error::from(
+            io::ErrorKind::StorageFull
+        )));
+        assert!(!is_storage_full(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+    }
+
+    #[test]
+    fn test_parse_df() {
+        let stdout = "Filesystem     1024-blocks     Used Available Capacity Mounted on\n\
+                      /dev/vda         264212084 15210896  80846512      16% /\n";
+        assert_eq!(parse_df(stdout), Some(80_846_512 * 1024));
+        let spaced = "Filesystem 1024-blocks Used Available Capacity Mounted on\n\
+                      //nas/My Share 1000 900 100 90% /mnt/My Share\n";
+        assert_eq!(parse_df(spaced), Some(100 * 1024));
+        assert_eq!(parse_df(""), None);
+        assert_eq!(parse_df("df: /nowhere: No such file or directory\n"), None);
+    }
+
+    #[test]
+    fn test_needs_of_a_run() {
+        let (temp, out) = (Path::new("/tmp/run"), Path::new("/videos"));
+        let mb = 1_000_000;
+        assert_eq!(
+            Needs::of_run(50 * mb, 1, false, temp, out, true).dirs,
+            [(temp.to_path_buf(), 50 * mb)]
+        );
+        // The chunks and the file they are joined into.
+        assert_eq!(
+            Needs::of_run(50 * mb, 1, true, temp, out, true).dirs,
+            [(temp.to_path_buf(), 100 * mb)]
+        );
+        // Two finished parts next to the third one in progress.
+        assert_eq!(
+            Needs::of_run(50 * mb, 3, false, temp, out, true).dirs,
+            [(temp.to_path_buf(), 150 * mb)]
+        );
+        assert_eq!(
+            Needs::of_run(50 * mb, 3, false, temp, out, false).dirs,
+            [(temp.to_path_buf(), 50 * mb), (out.to_path_buf(), 150 * mb)]
+        );
+    }
+
+    #[test]
+    fn test_tight_below_the_headroom() {
+        assert!(is_tight(100, 50));
+        assert!(is_tight(100, 109));
+        assert!(!is_tight(100, 110));
+        assert!(!is_tight(0, 0));
+    }
+}
@@ -0,0 +1,124 @@
+//! `--effort`: one speed/quality scale for every encoder.
+//!
+//! Effort 1 is the fastest encode and 9 the slowest, which gives the best
+//! quality for the size. Each level stands for one of the x264-style
+//! [`Preset`]s, which each encoder turns into its own `-preset` value, so
+//! this table is the one place the levels are defined.
+
+use crate::encoder::{Preset, VideoEncoder};
+use crate::presenter::{Align, Column, Table};
+use clap::ValueEnum;
+
+/// The lowest effort.
+pub const MIN: u8 = 1;
+
+/// The highest effort.
+pub const MAX: u8 = 9;
+
+/// The preset of each effort level, from [`MIN`].
+const LEVELS: [Preset; 9] = [
+    Preset::Ultrafast,
+    Preset::Superfast,
+    Preset::Veryfast,
+    Preset::Faster,
+    Preset::Fast,
+    Preset::Medium,
+    Preset::Slow,
+    Preset::Slower,
+    Preset::Veryslow,
+];
+
+/// The preset `effort` stands for.
+///
+/// # Panics
+///
+/// When `effort` is outside [`MIN`]..=[`MAX`], which the command line
+/// rules out.
+pub fn preset(effort: u8) -> Preset {
+    LEVELS[usize::from(effort - MIN)]
+}
+
+/// What `encoder` is given for `effort`, as its `-preset` value.
+pub fn native(encoder: VideoEncoder, effort: u8) -> String {
+    encoder.preset_value(preset(effort))
+}
+
+/// `--explain-effort`: each level with its preset and every encoder's value.
+pub fn table() -> Table {
+    let mut columns = vec![
+        Column::new("Effort", Align::Right),
+        Column::new("Preset", Align::Left),
+    ];
+    for encoder in VideoEncoder::value_variants() {
+        let name = encoder.to_possible_value().expect("no skipped encoders");
+        columns.push(Column::new(name.get_name(), Align::Right));
+    }
+    let mut table = Table::new(columns);
+    for effort in MIN..=MAX {
+        let mut row = vec![effort.to_string(), preset(effort).to_string()];
+        row.extend(
+            VideoEncoder::value_variants()
+                .iter()
+                .map(|&encoder| native(encoder, effort)),
+        );
+        table.push(row);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_encoder_has_a_value_for_every_level() {
+        let expected: &[(VideoEncoder, [&str; 9])] = &[
+            (
+                VideoEncoder::H264,
+                [
+                    "ultrafast",
+                    "superfast",
+                    "veryfast",
+                    "faster",
+                    "fast",
+                    "medium",
+                    "slow",
+                    "slower",
+                    "veryslow",
+                ],
+            ),
+            (
+                VideoEncoder::SvtAv1,
+                ["12", "11", "10", "10", "9", "8", "6", "5", "4"],
+            ),
+        ];
+        for encoder in VideoEncoder::value_variants() {
+            let (_, values) = expected
+                .iter()
+                .find(|(e, _)| e == encoder)
+                .unwrap_or_else(|| panic!("no expected effort values for {}", encoder));
+            let mapped: Vec<String> = (MIN..=MAX).map(|e| native(*encoder, e)).collect();
+            assert_eq!(mapped, values, "{}", encoder);
+        }
+    }
+
+    #[test]
+    fn test_more_effort_is_never_faster() {
+        for effort in MIN..MAX {
+            assert!(preset(effort) < preset(effort + 1));
+            // SVT-AV1 counts down as it gets slower.
+            let svt = |e| native(VideoEncoder::SvtAv1, e).parse::<u8>().unwrap();
+            assert!(svt(effort) >= svt(effort + 1));
+        }
+        assert_eq!(preset(6), Preset::Medium);
+    }
+
+    #[test]
+    fn test_table_lists_every_level_and_encoder() {
+        let lines = table().render(usize::MAX);
+        assert_eq!(lines.len(), 1 + usize::from(MAX));
+        assert_eq!(lines[0], "Effort  Preset          h264  svt-av1");
+        assert_eq!(lines[1], "     1  ultrafast  ultrafast       12");
+        assert_eq!(lines[9], "     9  veryslow    veryslow        4");
+    }
+}
@@ -0,0 +1,333 @@
+//! Encoder settings exposed on the command line.
+
+use crate::estimate::Codec;
+use clap::ValueEnum;
+use std::fmt;
+
+/// x264-style speed presets, fastest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Preset {
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    Medium,
+    Slow,
+    Slower,
+    Veryslow,
+}
+
+impl Preset {
+    /// All presets, fastest first.
+    pub const ALL: [Preset; 9] = [
+        Preset::Ultrafast,
+        Preset::Superfast,
+        Preset::Veryfast,
+        Preset::Faster,
+        Preset::Fast,
+        Preset::Medium,
+        Preset::Slow,
+        Preset::Slower,
+        Preset::Veryslow,
+    ];
+
+    /// The name ffmpeg expects for `-preset`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Ultrafast => "ultrafast",
+            Preset::Superfast => "superfast",
+            Preset::Veryfast => "veryfast",
+            Preset::Faster => "faster",
+            Preset::Fast => "fast",
+            Preset::Medium => "medium",
+            Preset::Slow => "slow",
+            Preset::Slower => "slower",
+            Preset::Veryslow => "veryslow",
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Video encoders the tool can drive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VideoEncoder {
+    /// H.264 with libx264
+    H264,
+    /// AV1 with SVT-AV1 (libsvtav1); much faster than libaom
+    #[value(name = "svt-av1")]
+    SvtAv1,
+}
+
+impl VideoEncoder {
+    /// The ffmpeg encoder name, as listed by `ffmpeg -encoders`.
+    pub fn ffmpeg_name(self) -> &'static str {
+        match self {
+            VideoEncoder::H264 => "libx264",
+            VideoEncoder::SvtAv1 => "libsvtav1",
+        }
+    }
+
+    /// The codec of what it writes, as ffprobe names a stream's.
+    pub fn stream_codec(self) -> &'static str {
+        match self {
+            VideoEncoder::H264 => "h264",
+            VideoEncoder::SvtAv1 => "av1",
+        }
+    }
+
+    pub fn codec(self) -> Codec {
+        match self {
+            VideoEncoder::H264 => Codec::H264,
+            VideoEncoder::SvtAv1 => Codec::Av1,
+        }
+    }
+
+    /// Whether the encoder reorders frames (B-frames), which costs the
+    /// container a presentation offset per frame.
+    pub fn reorders_frames(self) -> bool {
+        match self {
+            VideoEncoder::H264 => true,
+            VideoEncoder::SvtAv1 => false,
+        }
+    }
+
+    /// Whether ffmpeg can run the encoder in two passes (`-pass 1` and
+    /// `-pass 2`); its libsvtav1 wrapper only does one.
+    pub fn supports_two_pass(self) -> bool {
+        match self {
+            VideoEncoder::H264 => true,
+            VideoEncoder::SvtAv1 => false,
+        }
+    }
+
+    /// Whether the encoder, on one thread, always encodes the same input
+    /// and options into the same bytes. SVT-AV1 doesn't promise to.
+    pub fn bit_exact(self) -> bool {
+        match self {
+            VideoEncoder::H264 => true,
+            VideoEncoder::SvtAv1 => false,
+        }
+    }
+
+    /// The `-preset` value for `preset`. SVT-AV1 uses a numeric scale where
+    /// lower is slower; 4 is about as slow as is practical and 12 is fastest.
+    pub fn preset_value(self, preset: Preset) -> String {
+        match self {
+            VideoEncoder::H264 => preset.name().to_string(),
+            VideoEncoder::SvtAv1 => svt_preset(preset).to_string(),
+        }
+    }
+
+    /// The name of the encoder's pass-through option, for ffmpeg's
+    /// `-x264-params` and the matching `--x264-params` flag.
+    pub fn params_option(self) -> &'static str {
+        match self {
+            VideoEncoder::H264 => "x264-params",
+            VideoEncoder::SvtAv1 => "svtav1-params",
+        }
+    }
+
+    /// Whether the pass-through option `key` sets the bitrate or the rate
+    /// control, which the tool sets itself to hit the target size.
+    fn controls_rate(self, key: &str) -> bool {
+        let key = key.replace('_', "-");
+        match self {
+            VideoEncoder::H264 => {
+                matches!(
+                    key.as_str(),
+                    "bitrate" | "crf" | "qp" | "pass" | "stats" | "ratetol"
+                ) || key.starts_with("vbv-")
+            }
+            VideoEncoder::SvtAv1 => {
+                matches!(
+                    key.as_str(),
+                    "rc" | "tbr" | "mbr" | "crf" | "qp" | "pass" | "stats"
+                ) || key.starts_with("buf-")
+            }
+        }
+    }
+}
+
+/// Encoder library options passed through as they are (`--x264-params`,
+/// `--svtav1-params`): `key=value` pairs separated by `:`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderParams {
+    /// The encoder they are for; any other encoder leaves them out.
+    pub encoder: VideoEncoder,
+    pairs: Vec<(String, String)>,
+}
+
+impl EncoderParams {
+    /// Checks that `text` is a list of `key=value` pairs separated by `:`.
+    /// Values may hold anything else, such as x264's `psy-rd=1.0,0.15`.
+    pub fn parse(encoder: VideoEncoder, text: &str) -> Result<Self, String> {
+        if text.is_empty() {
+            return Err("expected key=value pairs separated by ':'".into());
+        }
+        let mut pairs: Vec<(String, String)> = Vec::new();
+        for pair in text.split(':') {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not a key=value pair", pair))?;
+            let is_name = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !is_name {
+                return Err(format!("'{}' is not an option name", key));
+            }
+            if value.is_empty() {
+                return Err(format!("{} has no value", key));
+            }
+            if pairs.iter().any(|(seen, _)| seen == key) {
+                return Err(format!("{} is given twice", key));
+            }
+            pairs.push((key.to_string(), value.to_string()));
+        }
+        Ok(Self { encoder, pairs })
+    }
+
+    /// The keys that fight the size targeting by setting the bitrate or
+    /// the rate control.
+    pub fn rate_control_keys(&self) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .filter(|key| self.encoder.controls_rate(key))
+            .collect()
+    }
+
+    /// The value given for `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The ffmpeg option and value passing these on.
+    pub fn args(&self) -> [String; 2] {
+        [
+            format!("-{}", self.encoder.params_option()),
+            self.to_string(),
+        ]
+    }
+}
+
+impl fmt::Display for EncoderParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.pairs.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for VideoEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.ffmpeg_name())
+    }
+}
+
+fn svt_preset(preset: Preset) -> u8 {
+    match preset {
+        Preset::Ultrafast => 12,
+        Preset::Superfast => 11,
+        Preset::Veryfast => 10,
+        Preset::Faster => 10,
+        Preset::Fast => 9,
+        Preset::Medium => 8,
+        Preset::Slow => 6,
+        Preset::Slower => 5,
+        Preset::Veryslow => 4,
+    }
+}
+
+/// Encoder names from `ffmpeg -encoders` output: after the legend, each
+/// line is flags, name and description.
+pub fn parse_encoders(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("------"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svt_presets_follow_speed_order() {
+        assert_eq!(VideoEncoder::SvtAv1.preset_value(Preset::Veryslow), "4");
+        assert_eq!(VideoEncoder::SvtAv1.preset_value(Preset::Medium), "8");
+        assert_eq!(VideoEncoder::SvtAv1.preset_value(Preset::Ultrafast), "12");
+        let values: Vec<u8> = Preset::ALL.iter().map(|&p| svt_preset(p)).collect();
+        assert!(values.windows(2).all(|w| w[0] >= w[1]), "{:?}", values);
+        assert_eq!(VideoEncoder::H264.preset_value(Preset::Slow), "slow");
+    }
+
+    #[test]
+    fn test_encoder_params_are_key_value_pairs() {
+        let params = EncoderParams::parse(VideoEncoder::H264, "aq-mode=3:psy-rd=1.0,0.15").unwrap();
+        assert_eq!(params.to_string(), "aq-mode=3:psy-rd=1.0,0.15");
+        assert_eq!(params.args(), ["-x264-params", "aq-mode=3:psy-rd=1.0,0.15"]);
+        assert!(params.rate_control_keys().is_empty());
+
+        for (text, error) in [
+            ("", "expected key=value pairs separated by ':'"),
+            ("aq-mode", "'aq-mode' is not a key=value pair"),
+            ("aq-mode=3:", "'' is not a key=value pair"),
+            ("=3", "'' is not an option name"),
+            ("aq mode=3", "'aq mode' is not an option name"),
+            ("aq-mode=", "aq-mode has no value"),
+            ("ref=4:ref=5", "ref is given twice"),
+        ] {
+            assert_eq!(
+                EncoderParams::parse(VideoEncoder::H264, text).unwrap_err(),
+                error,
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_rate_control_keys_per_encoder() {
+        let params =
+            EncoderParams::parse(VideoEncoder::H264, "bitrate=900:vbv_maxrate=1200:me=umh")
+                .unwrap();
+        assert_eq!(params.rate_control_keys(), ["bitrate", "vbv_maxrate"]);
+        // tbr is only SVT-AV1's target bitrate.
+        let params = EncoderParams::parse(VideoEncoder::SvtAv1, "tune=0:tbr=900").unwrap();
+        assert_eq!(params.rate_control_keys(), ["tbr"]);
+        assert_eq!(params.args()[0], "-svtav1-params");
+        let params = EncoderParams::parse(VideoEncoder::H264, "tbr=900").unwrap();
+        assert!(params.rate_control_keys().is_empty());
+    }
+
+    #[test]
+    fn test_parse_encoders() {
+        let stdout = "Encoders:
+ V..... = Video
+ A..... = Audio
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC (codec h264)
+ V....D libsvtav1            SVT-AV1(Scalable Video Technology for AV1) encoder (codec av1)
+ A....D aac                  AAC (Advanced Audio Coding)
+";
+        assert_eq!(parse_encoders(stdout), ["libx264", "libsvtav1", "aac"]);
+        assert!(parse_encoders("").is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+//! The error type shared by every step, and the exit codes it maps to.
+//!
+//! Exit codes are part of the command-line interface that scripts rely on,
+//! so existing values must never change meaning:
+//!
+//! | Code | Meaning                                         |
+//! |------|-------------------------------------------------|
+//! | 0    | Success                                         |
+//! | 2    | Bad arguments                                   |
+//! | 3    | ffmpeg or ffprobe not found                     |
+//! | 4    | Probe failed or the input is unsupported        |
+//! | 5    | Encode failed                                   |
+//! | 6    | Output still exceeded the target after retries  |
+//! | 7    | Interrupted (Ctrl-C)                            |
+//! | 8    | Timed out                                       |
+//! | 9    | Out of disk space                               |
+//! | 10   | The plan is below the `--min-quality` floor     |
+//! | 11   | Output closed (`--abort-on-broken-pipe`)        |
+//! | 12   | `--max-attempts` or `--max-total-time` ran out  |
+
+use crate::budget::Attempt;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum ReduceError {
+    /// Invalid or unsatisfiable arguments.
+    Usage(String),
+    /// The named executable could not be started because it isn't installed.
+    ToolNotFound(&'static str),
+    /// ffprobe failed, or its output didn't describe a usable video.
+    Probe(String),
+    /// ffmpeg failed, or the encoded file couldn't be put in place.
+    Encode(String),
+    /// Every attempt produced a file larger than the target.
+    OverTarget {
+        actual_bytes: u64,
+        target_bytes: u64,
+        attempts: u32,
+    },
+    /// The user pressed Ctrl-C.
+    Interrupted,
+    /// ffmpeg or ffprobe ran longer than `--timeout` and was killed.
+    Timeout(Duration),
+    /// A write into `dir` ran out of space; `free_bytes` is what was free
+    /// when it failed, if known.
+    DiskFull {
+        dir: PathBuf,
+        free_bytes: Option<u64>,
+    },
+    /// The planned bitrate is below the `--min-quality` floor; the message
+    /// says what would reach it.
+    BelowQualityFloor(String),
+    /// The reader of the status or the events went away, and
+    /// `--abort-on-broken-pipe` stopped the run for it.
+    BrokenPipe,
+    /// `--max-attempts` or `--max-total-time` ran out before an attempt
+    /// came in under the target; the message says which, and what was
+    /// tried.
+    BudgetExhausted {
+        message: String,
+        attempts: Vec<Attempt>,
+    },
+}
+
+impl ReduceError {
+    /// What went wrong in a few words, for the batch summary and
+    /// `--summary-line`; the full error is printed as well.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ReduceError::Usage(_) => "invalid options",
+            ReduceError::ToolNotFound(_) => "missing tool",
+            ReduceError::Probe(_) => "probe failed",
+            ReduceError::Encode(_) => "encode failed",
+            ReduceError::OverTarget { .. } => "over target",
+            ReduceError::Interrupted => "interrupted",
+            ReduceError::Timeout(_) => "timed out",
+            ReduceError::DiskFull { .. } => "disk full",
+            ReduceError::BelowQualityFloor(_) => "below quality floor",
+            ReduceError::BrokenPipe => "output closed",
+            ReduceError::BudgetExhausted { .. } => "budget exhausted",
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ReduceError::Usage(_) => 2,
+            ReduceError::ToolNotFound(_) => 3,
+            ReduceError::Probe(_) => 4,
+            ReduceError::Encode(_) => 5,
+            ReduceError::OverTarget { .. } => 6,
+            ReduceError::Interrupted => 7,
+            ReduceError::Timeout(_) => 8,
+            ReduceError::DiskFull { .. } => 9,
+            ReduceError::BelowQualityFloor(_) => 10,
+            ReduceError::BrokenPipe => 11,
+            ReduceError::BudgetExhausted { .. } => 12,
+        }
+    }
+}
+
+impl fmt::Display for ReduceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReduceError::Usage(msg)
+            | ReduceError::Probe(msg)
+            | ReduceError::Encode(msg)
+            | ReduceError::BelowQualityFloor(msg)
+            | ReduceError::BudgetExhausted { message: msg, .. } => {
+                write!(f, "{}", msg)
+            }
+            ReduceError::ToolNotFound(tool) => {
+                write!(
+                    f,
+                    "{} not found; make sure it is installed and on PATH",
+                    tool
+                )
+            }
+            ReduceError::OverTarget {
+                actual_bytes,
+                target_bytes,
+                attempts,
+            } => write!(
+                f,
+                "output is {} bytes, over the {}-byte target after {} attempts",
+                actual_bytes, target_bytes, attempts
+            ),
+            ReduceError::Interrupted => write!(f, "Interrupted"),
+            ReduceError::BrokenPipe => write!(
+                f,
+                "stopped because the reader of the output went away (--abort-on-broken-pipe)"
+            ),
+            ReduceError::Timeout(limit) => {
+                write!(f, "timed out after {} seconds", limit.as_secs())
+            }
+            ReduceError::DiskFull { dir, free_bytes } => {
+                write!(f, "out of disk space writing to {}", dir.display())?;
+                match free_bytes {
+                    Some(free) => write!(f, " ({} bytes free)", free),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReduceError {}
+
+impl From<crate::filter::FilterError> for ReduceError {
+    fn from(err: crate::filter::FilterError) -> Self {
+        ReduceError::Usage(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_are_distinct_and_stable() {
+        let errors = [
+            ReduceError::Usage(String::new()),
+            ReduceError::ToolNotFound("ffmpeg"),
+            ReduceError::Probe(String::new()),
+            ReduceError::Encode(String::new()),
+            ReduceError::OverTarget {
+                actual_bytes: 2,
+                target_bytes: 1,
+                attempts: 3,
+            },
+            ReduceError::Interrupted,
+            ReduceError::Timeout(Duration::from_secs(1)),
+            ReduceError::DiskFull {
+                dir: PathBuf::new(),
+                free_bytes: None,
+            },
+            ReduceError::BelowQualityFloor(String::new()),
+            ReduceError::BrokenPipe,
+            ReduceError::BudgetExhausted {
+                message: String::new(),
+                attempts: Vec::new(),
+            },
+        ];
+        let codes: Vec<u8> = errors.iter().map(ReduceError::exit_code).collect();
+        assert_eq!(codes, vec![2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+    }
+}
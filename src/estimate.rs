@@ -0,0 +1,321 @@
+//! Up-front encode time estimates and ETA refinement from progress samples.
+
+use crate::encoder::Preset;
+use clap::ValueEnum;
+
+/// Frame rate assumed when the source doesn't report one.
+pub const DEFAULT_FPS: f64 = 30.0;
+
+/// Approximate libx264 throughput in frames per second for 1080p content on a
+/// typical desktop CPU. Used when no calibration data is available.
+fn frames_per_second_1080p(preset: Preset) -> f64 {
+    match preset {
+        Preset::Ultrafast => 250.0,
+        Preset::Superfast => 180.0,
+        Preset::Veryfast => 120.0,
+        Preset::Faster => 80.0,
+        Preset::Fast => 60.0,
+        Preset::Medium => 45.0,
+        Preset::Slow => 25.0,
+        Preset::Slower => 12.0,
+        Preset::Veryslow => 6.0,
+    }
+}
+
+/// Estimates the wall time of an encode in seconds.
+///
+/// Throughput is assumed to scale inversely with the pixel count relative to 1080p.
+pub fn estimate_encode_seconds(
+    duration: f64,
+    fps: f64,
+    width: u32,
+    height: u32,
+    preset: Preset,
+) -> f64 {
+    let frames = duration * fps;
+    let pixel_ratio = (width as f64 * height as f64) / (1920.0 * 1080.0);
+    let throughput = frames_per_second_1080p(preset) / pixel_ratio.max(0.01);
+    frames / throughput
+}
+
+/// Picks the slowest preset, no slower than `preferred`, whose estimate fits `budget_seconds`.
+///
+/// Returns `None` when even the fastest preset is over budget.
+pub fn fit_preset(
+    duration: f64,
+    fps: f64,
+    width: u32,
+    height: u32,
+    preferred: Preset,
+    budget_seconds: f64,
+) -> Option<Preset> {
+    Preset::ALL
+        .iter()
+        .rev()
+        .filter(|&&p| p <= preferred)
+        .find(|&&p| estimate_encode_seconds(duration, fps, width, height, p) <= budget_seconds)
+        .copied()
+}
+
+/// Remaining wall time in seconds given the encoded position and ffmpeg's reported speed.
+///
+/// `speed` is the media-time-per-wall-time factor from ffmpeg's `speed=` field
+/// (e.g. 2.0 means two seconds of video are encoded per second).
+pub fn eta_seconds(out_time: f64, total: f64, speed: f64) -> Option<f64> {
+    if speed <= 0.0 || !speed.is_finite() || total <= 0.0 {
+        return None;
+    }
+    let remaining = (total - out_time).max(0.0);
+    Some(remaining / speed)
+}
+
+/// Video bits spent on each pixel of each frame: the usual rule-of-thumb
+/// measure of how starved an encode is, independent of resolution.
+pub fn bits_per_pixel(video_bitrate: u64, width: u32, height: u32, fps: f64) -> f64 {
+    let pixels_per_second = width as f64 * height as f64 * fps;
+    if pixels_per_second <= 0.0 {
+        return 0.0;
+    }
+    video_bitrate as f64 / pixels_per_second
+}
+
+/// Rough expected picture quality of an encode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Quality {
+    Poor,
+    Fair,
+    Good,
+}
+
+impl Quality {
+    /// The verdict for an H.264 encode (see [`Codec::quality`]).
+    pub fn from_bits_per_pixel(bpp: f64) -> Self {
+        Codec::H264.quality(bpp)
+    }
+}
+
+/// Video codecs whose bits-per-pixel needs differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    H264,
+    H265,
+    Av1,
+}
+
+impl Codec {
+    /// Bits per pixel this codec needs relative to H.264 for a similar
+    /// picture.
+    fn bpp_factor(self) -> f64 {
+        match self {
+            Codec::H264 => 1.0,
+            Codec::H265 => 0.6,
+            Codec::Av1 => 0.5,
+        }
+    }
+
+    /// Thresholds follow the common guidance for H.264: around 0.1 bpp looks
+    /// clean for typical content, and below about half that, blocking and
+    /// smearing become obvious. Newer codecs get there with fewer bits.
+    pub fn quality(self, bpp: f64) -> Quality {
+        let bpp = bpp / self.bpp_factor();
+        if bpp >= 0.08 {
+            Quality::Good
+        } else if bpp >= 0.04 {
+            Quality::Fair
+        } else {
+            Quality::Poor
+        }
+    }
+
+    /// The fewest bits per pixel that get the codec to `quality`.
+    pub fn min_bpp(self, quality: Quality) -> f64 {
+        let h264 = match quality {
+            Quality::Good => 0.08,
+            Quality::Fair => 0.04,
+            Quality::Poor => 0.0,
+        };
+        h264 * self.bpp_factor()
+    }
+}
+
+/// The warning for an encode whose bits per pixel predict a poor result,
+/// with up to two flags that would help; `None` when quality is acceptable.
+pub fn poor_quality_warning(
+    video_bitrate: u64,
+    width: u32,
+    height: u32,
+    fps: f64,
+    codec: Codec,
+) -> Option<String> {
+    let bpp = bits_per_pixel(video_bitrate, width, height, fps);
+    if codec.quality(bpp) != Quality::Poor {
+        return None;
+    }
+    let mut suggestions = Vec::new();
+    // One step down the usual ladder, at the current aspect ratio.
+    if let Some(&lower) = [720u32, 480].iter().find(|&&h| h < height) {
+        let scaled = (width as u64 * lower as u64 / height.max(1) as u64) as u32;
+        suggestions.push(format!("--max-width {}", (scaled + 1) & !1));
+    }
+    if fps > 30.5 {
+        suggestions.push("--fps 30".to_string());
+    }
+    if suggestions.is_empty() {
+        suggestions.push("a larger --size".to_string());
+        suggestions.push("--split 2".to_string());
+    }
+    suggestions.truncate(2);
+    Some(format!(
+        "{}p{} at {} kb/s \u{2248} {:.3} bpp \u{2014} expect heavy artifacts; consider {}",
+        height,
+        format_fps(fps),
+        video_bitrate / 1000,
+        bpp,
+        suggestions.join(" or ")
+    ))
+}
+
+/// `60`, or `29.97` for fractional rates.
+pub fn format_fps(fps: f64) -> String {
+    if (fps - fps.round()).abs() < 0.01 {
+        format!("{}", fps.round() as u64)
+    } else {
+        format!("{:.2}", fps)
+    }
+}
+
+impl std::fmt::Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Quality::Poor => "poor",
+            Quality::Fair => "fair",
+            Quality::Good => "good",
+        })
+    }
+}
+
+/// Formats a number of seconds as `m:ss` or `h:mm:ss`.
+pub fn format_duration(seconds: f64) -> String {
+    let total = seconds.max(0.0).round() as u64;
+    let (h, m, s) = (total / 3600, (total % 3600) / 60, total % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quality_verdict_from_bits_per_pixel() {
+        // 1080p30 at 5 Mb/s is about 0.08 bpp.
+        let bpp = bits_per_pixel(5_000_000, 1920, 1080, 30.0);
+        assert!((bpp - 0.0804).abs() < 0.001);
+        assert_eq!(Quality::from_bits_per_pixel(bpp), Quality::Good);
+        assert_eq!(Quality::from_bits_per_pixel(0.05), Quality::Fair);
+        assert_eq!(Quality::from_bits_per_pixel(0.01), Quality::Poor);
+        assert_eq!(bits_per_pixel(1_000, 0, 0, 30.0), 0.0);
+    }
+
+    #[test]
+    fn test_newer_codecs_tolerate_fewer_bits() {
+        assert_eq!(Codec::H264.quality(0.03), Quality::Poor);
+        assert_eq!(Codec::H265.quality(0.03), Quality::Fair);
+        assert_eq!(Codec::Av1.quality(0.02), Quality::Fair);
+        assert_eq!(Codec::Av1.quality(0.015), Quality::Poor);
+    }
+
+    #[test]
+    fn test_poor_quality_warning_suggests_fixes() {
+        let warning = poor_quality_warning(850_000, 1280, 720, 60.0, Codec::H264).unwrap();
+        assert_eq!(
+            warning,
+            "720p60 at 850 kb/s \u{2248} 0.015 bpp \u{2014} expect heavy artifacts; \
+             consider --max-width 854 or --fps 30"
+        );
+        let warning = poor_quality_warning(1_000_000, 1920, 1080, 30.0, Codec::H264).unwrap();
+        assert!(
+            warning.ends_with("consider --max-width 1280"),
+            "{}",
+            warning
+        );
+        // Nothing left to scale down or slow down.
+        let warning = poor_quality_warning(100_000, 640, 360, 29.97, Codec::H264).unwrap();
+        assert!(warning.starts_with("360p29.97 at 100 kb/s"), "{}", warning);
+        assert!(warning.ends_with("consider a larger --size or --split 2"));
+    }
+
+    #[test]
+    fn test_no_warning_when_quality_is_acceptable() {
+        assert_eq!(
+            poor_quality_warning(5_000_000, 1920, 1080, 30.0, Codec::H264),
+            None
+        );
+        // What starves H.264 is only fair for H.265.
+        assert!(poor_quality_warning(2_000_000, 1920, 1080, 30.0, Codec::H264).is_some());
+        assert_eq!(
+            poor_quality_warning(2_000_000, 1920, 1080, 30.0, Codec::H265),
+            None
+        );
+    }
+
+    #[test]
+    fn test_estimate_scales_with_resolution_and_preset() {
+        // 60 s at 30 fps = 1800 frames; medium 1080p runs at 45 fps -> 40 s.
+        let medium = estimate_encode_seconds(60.0, 30.0, 1920, 1080, Preset::Medium);
+        assert!((medium - 40.0).abs() < 1e-9);
+
+        // A quarter of the pixels encodes four times as fast.
+        let small = estimate_encode_seconds(60.0, 30.0, 960, 540, Preset::Medium);
+        assert!((small - 10.0).abs() < 1e-9);
+
+        let slow = estimate_encode_seconds(60.0, 30.0, 1920, 1080, Preset::Veryslow);
+        assert!(slow > medium);
+    }
+
+    #[test]
+    fn test_fit_preset_prefers_requested_then_faster() {
+        // 1 hour of 1080p30 at medium is ~40 minutes.
+        let hour = 3600.0;
+        assert_eq!(
+            fit_preset(hour, 30.0, 1920, 1080, Preset::Medium, 3600.0),
+            Some(Preset::Medium)
+        );
+        // With a 20 minute budget "faster" (22.5 min) is still too slow.
+        assert_eq!(
+            fit_preset(hour, 30.0, 1920, 1080, Preset::Medium, 1200.0),
+            Some(Preset::Veryfast)
+        );
+        // Never picks something slower than requested.
+        assert_eq!(
+            fit_preset(10.0, 30.0, 1920, 1080, Preset::Fast, 3600.0),
+            Some(Preset::Fast)
+        );
+        assert_eq!(
+            fit_preset(hour, 30.0, 3840, 2160, Preset::Medium, 60.0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_eta_from_progress_samples() {
+        // Half way through a 100 s clip at 2x: 50 s of media left -> 25 s.
+        assert_eq!(eta_seconds(50.0, 100.0, 2.0), Some(25.0));
+        // Overshooting the nominal duration never yields a negative ETA.
+        assert_eq!(eta_seconds(101.0, 100.0, 1.0), Some(0.0));
+        // ffmpeg reports speed=N/A until the first frames are out.
+        assert_eq!(eta_seconds(0.0, 100.0, 0.0), None);
+        assert_eq!(eta_seconds(10.0, 0.0, 1.0), None);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0.0), "0:00");
+        assert_eq!(format_duration(75.4), "1:15");
+        assert_eq!(format_duration(3723.0), "1:02:03");
+        assert_eq!(format_duration(-5.0), "0:00");
+    }
+}
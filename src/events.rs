@@ -0,0 +1,295 @@
+//! `--progress-json`: what a run is doing, as newline-delimited JSON for
+//! programs wrapping the tool.
+//!
+//! Each [`Event`] is written as one line and flushed at once, so a GUI
+//! reading the other end of a pipe sees it as it happens. The types here
+//! are the schema: they deserialize from the stream as well as they
+//! serialize into it. Like warnings, events go to a per-thread sink that
+//! the command line installs; without one, emitting does nothing.
+
+use crate::batch;
+use crate::budget::Attempt;
+use crate::checksum::Checksums;
+use crate::console;
+use crate::error::ReduceError;
+use crate::filetarget::TargetSource;
+use crate::reduce::{Cap, ReduceOptions, ReduceReport};
+use crate::summary;
+use crate::url;
+use crate::usage::Usage;
+use crate::validate::ValidationIssue;
+use crate::warning::Warning;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::io::Write;
+
+/// One line of the event stream. The `event` field names the kind; the
+/// `snake_case` names are part of the format, so existing ones must not
+/// change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The run moved on to another step.
+    Phase {
+        phase: Phase,
+        /// Which retry this is, from 1, for [`Phase::Retry`].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retry: Option<u32>,
+    },
+    /// ffmpeg reported how far the current encode has come.
+    Progress {
+        /// Milliseconds of output encoded.
+        out_time_ms: u64,
+        /// Share of the current encode done, from 0 to 100.
+        percent: f64,
+        /// Encoding speed relative to real time, if ffmpeg reported one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        speed: Option<f64>,
+        /// Seconds left at the current speed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        eta_s: Option<f64>,
+    },
+    /// What checking the options found before the run, errors first; with
+    /// any error, an [`Event::Error`] follows.
+    Issues { issues: Vec<ValidationIssue> },
+    /// A warning, as printed and as collected into the report.
+    Warning(Warning),
+    /// How a file came out; the last event of its run.
+    Report(Box<Report>),
+    /// The command failed; nothing follows.
+    Error { message: String, exit_code: u8 },
+}
+
+/// The steps of a run, in the order they happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Reading the input's duration and streams.
+    Probing,
+    /// Copying the streams into a new container, for `--remux-only`.
+    Remuxing,
+    /// The first encode attempt.
+    Encoding,
+    /// Encoding again at a lower bitrate after an attempt came out over
+    /// the target.
+    Retry,
+    /// Checking the size of what was written.
+    Verifying,
+}
+
+/// How a run ended. `--output-format json` prints the same object when the
+/// run ends.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub input: String,
+    pub output: String,
+    pub target_bytes: u64,
+    /// Where `batch --target-from` found the target.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_source: Option<TargetSource>,
+    /// Bytes written, with every part counted; absent after a failure or a
+    /// dry run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub predicted_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The `--max-*` limits that changed the output.
+    pub caps: Vec<Cap>,
+    pub warnings: Vec<Warning>,
+    /// CPU, memory and wall time the run took; absent after a failure or a
+    /// dry run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// The `--sidecar` file written next to the output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sidecar: Option<String>,
+    /// The subtitle files `--extract-subs` wrote next to the output.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtitles: Vec<String>,
+    /// The hashes `--checksum` computed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<Checksums>,
+    /// How the run went in one line, as `--summary-line` prints it.
+    #[serde(default)]
+    pub summary_line: String,
+    /// Every try at writing the output, in order; after a failure, only
+    /// when `--max-attempts` or `--max-total-time` stopped the run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<Attempt>,
+}
+
+impl Report {
+    /// The report of a run of `input` into `output` that succeeded.
+    pub fn reduced(input: &str, output: &str, opts: &ReduceOptions, report: &ReduceReport) -> Self {
+        let output_bytes = (!opts.dry_run).then(|| batch::written_bytes(output, opts.parts));
+        // The input may be the output by now, so its size is the one it
+        // started with; a run that wrote nothing has no recipe.
+        let source_bytes = match &report.recipe {
+            Some(recipe) => recipe.source.bytes,
+            None => std::fs::metadata(input).ok().map(|m| m.len()),
+        };
+        Self {
+            input: input.to_string(),
+            output: output.to_string(),
+            target_bytes: opts.target_bytes,
+            target_source: opts.target_source,
+            output_bytes,
+            predicted_bytes: report.prediction.as_ref().map(|p| p.predicted_bytes()),
+            error: None,
+            caps: report.caps.clone(),
+            warnings: report.warnings.clone(),
+            usage: report.usage,
+            sidecar: report.sidecar.clone(),
+            subtitles: report.subtitles.clone(),
+            checksums: report.checksums.clone(),
+            summary_line: summary::line(
+                input,
+                source_bytes,
+                output_bytes,
+                Ok(report),
+                opts.sizes,
+                opts.target_bytes * u64::from(opts.parts.max(1)),
+            ),
+            attempts: report.attempts.clone(),
+        }
+    }
+
+    /// The report of a run that failed with `error` after `warnings`.
+    pub fn failed(
+        input: &str,
+        output: &str,
+        opts: &ReduceOptions,
+        error: &ReduceError,
+        warnings: Vec<Warning>,
+    ) -> Self {
+        Self {
+            input: input.to_string(),
+            output: output.to_string(),
+            target_bytes: opts.target_bytes,
+            target_source: opts.target_source,
+            output_bytes: None,
+            predicted_bytes: None,
+            error: Some(error.to_string()),
+            caps: Vec::new(),
+            warnings,
+            usage: None,
+            sidecar: None,
+            subtitles: Vec::new(),
+            checksums: None,
+            summary_line: summary::line(
+                input,
+                std::fs::metadata(input).ok().map(|m| m.len()),
+                None,
+                Err(error),
+                opts.sizes,
+                opts.target_bytes * u64::from(opts.parts.max(1)),
+            ),
+            attempts: match error {
+                ReduceError::BudgetExhausted { attempts, .. } => attempts.clone(),
+                _ => Vec::new(),
+            },
+        }
+    }
+
+    /// One line of JSON, with credentials in URLs masked as in the status
+    /// output.
+    pub fn to_line(&self) -> String {
+        let json = serde_json::to_string(self).expect("the report always serializes");
+        url::redact_urls(&json)
+    }
+}
+
+thread_local! {
+    static SINK: RefCell<Option<Box<dyn Write>>> = const { RefCell::new(None) };
+}
+
+/// Sends the events of this thread to `sink` from now on.
+pub fn install(sink: Box<dyn Write>) {
+    SINK.with(|current| *current.borrow_mut() = Some(sink));
+}
+
+/// Stops sending events from this thread, returning the sink.
+pub fn uninstall() -> Option<Box<dyn Write>> {
+    SINK.with(|current| current.borrow_mut().take())
+}
+
+/// Whether this thread has a sink, so events are worth building.
+pub fn is_enabled() -> bool {
+    SINK.with(|current| current.borrow().is_some())
+}
+
+/// Writes `event` to this thread's sink as one line and flushes it. A
+/// reader that went away is no reason to stop the encode, so write errors
+/// are ignored; once the pipe is broken the sink is dropped, and the run
+/// only stops for it with `--abort-on-broken-pipe` (see
+/// [`console::broken_pipe`]).
+pub fn emit(event: &Event) {
+    if is_enabled() {
+        let json = serde_json::to_string(event).expect("events always serialize");
+        relay(format!("{}\n", url::redact_urls(&json)).as_bytes());
+    }
+}
+
+/// Writes `line`, an event as [`emit`] puts it, to this thread's sink, as
+/// a batch does with the events of the threads running its jobs.
+pub fn relay(line: &[u8]) {
+    SINK.with(|current| {
+        let mut current = current.borrow_mut();
+        if let Some(sink) = current.as_mut() {
+            let written = sink.write_all(line).and_then(|_| sink.flush());
+            if written.is_err_and(|e| console::broken_pipe(&e)) {
+                *current = None;
+            }
+        }
+    });
+}
+
+/// Emits a change to `phase`.
+pub fn phase(phase: Phase) {
+    emit(&Event::Phase { phase, retry: None });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::warning::Code;
+
+    #[test]
+    fn test_events_round_trip_in_their_wire_form() {
+        let events = [
+            (
+                Event::Phase {
+                    phase: Phase::Retry,
+                    retry: Some(2),
+                },
+                r#"{"event":"phase","phase":"retry","retry":2}"#,
+            ),
+            (
+                Event::Progress {
+                    out_time_ms: 1500,
+                    percent: 25.0,
+                    speed: None,
+                    eta_s: None,
+                },
+                r#"{"event":"progress","out_time_ms":1500,"percent":25.0}"#,
+            ),
+            (
+                Event::Warning(Warning::new(Code::SampleOnly, "only a sample")),
+                r#"{"event":"warning","code":"sample_only","message":"only a sample"}"#,
+            ),
+        ];
+        for (event, line) in events {
+            assert_eq!(serde_json::to_string(&event).unwrap(), line);
+            assert_eq!(serde_json::from_str::<Event>(line).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn test_nothing_is_written_without_a_sink() {
+        uninstall();
+        assert!(!is_enabled());
+        phase(Phase::Probing);
+    }
+}
@@ -0,0 +1,238 @@
+//! File names the tool makes up itself: a batch's outputs, named after
+//! their inputs or by `--name-template`, and the part, sample and compare
+//! names derived from an output.
+//!
+//! A name that was fine where the input came from may not be where the
+//! output goes: `:` and `?` are common in Linux file names and invalid on
+//! Windows, `NUL.mp4` can't be created there at all, and a long stem with
+//! `.sample` and `_1920x1080_25MB` added can pass the 255 bytes most
+//! filesystems allow. [`sanitize`] replaces what the target can't hold and
+//! shortens the stem, never the extension, which picks the container.
+
+/// The longest file name most filesystems take, in bytes (ext4, APFS,
+/// and near enough NTFS's 255 UTF-16 units).
+pub const MAX_BYTES: usize = 255;
+
+/// Characters Windows doesn't allow in file names, besides control
+/// characters.
+const WINDOWS_INVALID: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names Windows reserves, whatever the extension and case.
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// What a made-up file name has to keep to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NameRules {
+    /// Keep to what Windows allows, wherever the tool runs, so the files
+    /// can be shared (`--portable-names`). On Windows this always holds.
+    pub portable: bool,
+    /// The longest name in bytes (`--max-name-bytes`).
+    pub max_bytes: usize,
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        Self {
+            portable: false,
+            max_bytes: MAX_BYTES,
+        }
+    }
+}
+
+impl NameRules {
+    fn windows(self) -> bool {
+        self.portable || cfg!(windows)
+    }
+
+    /// These rules with `bytes` less room, for what is added to the name
+    /// later.
+    pub fn leaving(self, bytes: usize) -> Self {
+        Self {
+            max_bytes: self.max_bytes.saturating_sub(bytes),
+            ..self
+        }
+    }
+}
+
+/// `name` made valid under `rules`: invalid characters become `_`, a
+/// reserved name gets a `_` after it, and a name too long loses the end of
+/// its stem. An empty stem becomes `_`.
+pub fn sanitize(name: &str, rules: NameRules) -> String {
+    let windows = rules.windows();
+    let invalid = |c: char| {
+        c == '/' || c == '\0' || (windows && (c.is_control() || WINDOWS_INVALID.contains(&c)))
+    };
+    let mut clean: String = name
+        .chars()
+        .map(|c| if invalid(c) { '_' } else { c })
+        .collect();
+    if windows {
+        // Windows drops them, so `a.mp4.` would be written as `a.mp4`.
+        let kept = clean.trim_end_matches(['.', ' ']).len();
+        clean.truncate(kept);
+    }
+    let (stem, extension) = split_extension(&clean);
+    let mut stem = if stem.is_empty() {
+        "_".to_string()
+    } else {
+        stem.to_string()
+    };
+    if windows {
+        // `CON.backup.mp4` is as reserved as `CON.mp4`.
+        let device = stem.split('.').next().unwrap_or_default();
+        if WINDOWS_RESERVED
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(device.trim_end()))
+        {
+            stem.insert(device.len(), '_');
+        }
+    }
+    fit(&stem, extension, rules.max_bytes)
+}
+
+/// `name` cut to `max_bytes` at the end of its stem, keeping the extension.
+pub fn fit_length(name: &str, max_bytes: usize) -> String {
+    let (stem, extension) = split_extension(name);
+    fit(stem, extension, max_bytes)
+}
+
+/// `name`, or when `taken` says it is, the first of `name-2`, `name-3` and
+/// so on that isn't, each cut to the rules' length.
+pub fn disambiguate(name: &str, rules: NameRules, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(name) {
+        return name.to_string();
+    }
+    let (stem, extension) = split_extension(name);
+    (2..)
+        .map(|n| {
+            let suffix = format!("-{}", n);
+            let stem = fit(
+                stem,
+                "",
+                rules
+                    .max_bytes
+                    .saturating_sub(extension.len() + suffix.len()),
+            );
+            format!("{}{}{}", stem, suffix, extension)
+        })
+        .find(|candidate| !taken(candidate))
+        .expect("the numbers run out after the names")
+}
+
+/// The stem and the extension with its dot; a leading dot starts the
+/// stem, as in `.hidden`.
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    }
+}
+
+/// `stem` and `extension` in at most `max_bytes`, shortening the stem on
+/// a character boundary but never below one character.
+fn fit(stem: &str, extension: &str, max_bytes: usize) -> String {
+    let room = max_bytes.saturating_sub(extension.len());
+    if stem.len() <= room {
+        return format!("{}{}", stem, extension);
+    }
+    let mut end = room;
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    if end == 0 {
+        end = stem.chars().next().map_or(0, char::len_utf8);
+    }
+    format!("{}{}", &stem[..end], extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const PORTABLE: NameRules = NameRules {
+        portable: true,
+        max_bytes: MAX_BYTES,
+    };
+
+    #[test]
+    fn test_portable_names_keep_to_windows() {
+        let cases = [
+            ("clip.mp4", "clip.mp4"),
+            ("2024-05-01 12:30:00.mp4", "2024-05-01 12_30_00.mp4"),
+            ("what?<now>|\"x\"*.mkv", "what__now___x__.mkv"),
+            ("back\\slash.mp4", "back_slash.mp4"),
+            ("tab\there.mp4", "tab_here.mp4"),
+            ("trailing.mp4. .", "trailing.mp4"),
+            ("CON.mp4", "CON_.mp4"),
+            ("nul.mp4", "nul_.mp4"),
+            ("Com1.backup.mp4", "Com1_.backup.mp4"),
+            ("LPT9", "LPT9_"),
+            ("CONSOLE.mp4", "CONSOLE.mp4"),
+            ("COM10.mp4", "COM10.mp4"),
+            (".mp4", ".mp4"),
+            ("", "_"),
+            ("...", "_"),
+            ("café ☕.mp4", "café ☕.mp4"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(sanitize(name, PORTABLE), expected, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_native_names_only_lose_what_no_system_allows() {
+        let native = NameRules {
+            portable: false,
+            ..NameRules::default()
+        };
+        if cfg!(windows) {
+            assert_eq!(sanitize("a:b.mp4", native), "a_b.mp4");
+        } else {
+            assert_eq!(sanitize("a:b?.mp4", native), "a:b?.mp4");
+            assert_eq!(sanitize("CON.mp4", native), "CON.mp4");
+        }
+        assert_eq!(sanitize("a/b\0c.mp4", native), "a_b_c.mp4");
+    }
+
+    #[test]
+    fn test_long_names_keep_their_extension() {
+        let long = format!("{}.mp4", "a".repeat(300));
+        let fitted = sanitize(&long, NameRules::default());
+        assert_eq!(fitted.len(), MAX_BYTES);
+        assert!(fitted.ends_with("a.mp4"));
+
+        let rules = NameRules {
+            portable: false,
+            max_bytes: 12,
+        };
+        assert_eq!(sanitize("holiday-video.mkv", rules), "holiday-.mkv");
+        // Multi-byte characters are never split.
+        assert_eq!(sanitize("ééééé.mp4", rules), "éééé.mp4");
+        assert_eq!(sanitize("€€€€.webm", rules.leaving(5)), "€.webm");
+        // The stem keeps a character even when the extension takes it all.
+        assert_eq!(fit_length("abc.mp4", 2), "a.mp4");
+        assert_eq!(fit_length("short.mp4", 255), "short.mp4");
+    }
+
+    #[test]
+    fn test_collisions_get_a_number() {
+        let taken: HashSet<&str> = ["a.mp4", "a-2.mp4", "b"].into_iter().collect();
+        let is_taken = |name: &str| taken.contains(name);
+        let rules = NameRules::default();
+        assert_eq!(disambiguate("c.mp4", rules, is_taken), "c.mp4");
+        assert_eq!(disambiguate("a.mp4", rules, is_taken), "a-3.mp4");
+        assert_eq!(disambiguate("b", rules, is_taken), "b-2");
+
+        // The number still fits.
+        let rules = NameRules {
+            portable: false,
+            max_bytes: 10,
+        };
+        let full = "abcdef.mp4";
+        assert_eq!(disambiguate(full, rules, |name| name == full), "abcd-2.mp4");
+    }
+}
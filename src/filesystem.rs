@@ -0,0 +1,193 @@
+//! The filesystems a run writes to, and what they can't take.
+//!
+//! A FAT32 USB stick fails a write at 4 GiB with a bare "File too large",
+//! and SMB shares handle the seeks MP4 muxing makes to patch its index
+//! badly enough to corrupt the file or stall. The filesystem type comes
+//! from the mount table on Linux and from `mount` on macOS, through
+//! [`VideoTool::filesystem_of`](crate::tool::VideoTool::filesystem_of) so
+//! tests can pretend.
+
+use std::path::{Component, Path, PathBuf};
+
+/// The largest file FAT32 can hold.
+pub const FAT32_MAX_FILE_BYTES: u64 = 4 * 1024 * 1024 * 1024 - 1;
+
+/// A filesystem, by the type name the platform reports for it (`vfat`,
+/// `cifs`, `ext4`, ...).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filesystem {
+    pub kind: String,
+}
+
+impl Filesystem {
+    pub fn new(kind: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+        }
+    }
+
+    fn is_fat32(&self) -> bool {
+        matches!(self.kind.as_str(), "vfat" | "msdos" | "fat")
+    }
+
+    /// The largest file it can hold, where that is less than any output.
+    pub fn max_file_bytes(&self) -> Option<u64> {
+        self.is_fat32().then_some(FAT32_MAX_FILE_BYTES)
+    }
+
+    /// Whether files on it shouldn't be muxed in place, because seeking
+    /// back into them is unreliable.
+    pub fn seeks_poorly(&self) -> bool {
+        matches!(self.kind.as_str(), "cifs" | "smb3" | "smbfs")
+    }
+
+    /// The name to show in messages.
+    pub fn name(&self) -> &str {
+        if self.is_fat32() {
+            "FAT32"
+        } else if self.seeks_poorly() {
+            "SMB"
+        } else {
+            &self.kind
+        }
+    }
+}
+
+/// The filesystem holding `dir`, when the platform can tell.
+pub fn detect(dir: &Path) -> Option<Filesystem> {
+    let dir = std::fs::canonicalize(dir).ok()?;
+    let mounts = if cfg!(target_os = "linux") {
+        parse_proc_mounts(&std::fs::read_to_string("/proc/self/mounts").ok()?)
+    } else if cfg!(target_os = "macos") {
+        let output = std::process::Command::new("mount")
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()?;
+        parse_mount_output(&String::from_utf8_lossy(&output.stdout))
+    } else {
+        return None;
+    };
+    mount_of(&mounts, &dir)
+}
+
+/// (mount point, type) pairs from `/proc/self/mounts`, which escapes
+/// spaces and other odd characters in paths as octal (`\040`).
+pub fn parse_proc_mounts(text: &str) -> Vec<(PathBuf, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let _device = fields.next()?;
+            let point = unescape_octal(fields.next()?);
+            let kind = fields.next()?;
+            Some((PathBuf::from(point), kind.to_string()))
+        })
+        .collect()
+}
+
+/// (mount point, type) pairs from the macOS `mount` listing, whose lines
+/// read `<device> on <mount point> (<type>, <flags>...)`.
+pub fn parse_mount_output(text: &str) -> Vec<(PathBuf, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let (_device, rest) = line.split_once(" on ")?;
+            let (point, options) = rest.rsplit_once(" (")?;
+            let kind = options.split([',', ')']).next()?.trim();
+            Some((PathBuf::from(point), kind.to_string()))
+        })
+        .collect()
+}
+
+/// The filesystem of the innermost of `mounts` holding `dir`. A later
+/// mount over the same point hides the earlier one.
+pub fn mount_of(mounts: &[(PathBuf, String)], dir: &Path) -> Option<Filesystem> {
+    mounts
+        .iter()
+        .filter(|(point, _)| dir.starts_with(point))
+        .max_by_key(|(point, _)| {
+            point
+                .components()
+                .filter(|c| *c != Component::RootDir)
+                .count()
+        })
+        .map(|(_, kind)| Filesystem::new(kind))
+}
+
+/// Undoes the `\ooo` escapes of the mount table.
+fn unescape_octal(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&first, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..3)
+            .filter(|digits| first == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d)))
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                rest = &tail[3..];
+            }
+            None => {
+                out.push(first);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROC_MOUNTS: &str = "\
+/dev/nvme0n1p2 / ext4 rw,relatime 0 0
+tmpfs /tmp tmpfs rw,nosuid,nodev 0 0
+/dev/sdb1 /media/me/USB\\040STICK vfat rw,nosuid,nodev,uid=1000 0 0
+//nas/videos /mnt/nas cifs rw,vers=3.1.1 0 0
+";
+
+    #[test]
+    fn test_linux_mount_table() {
+        let mounts = parse_proc_mounts(PROC_MOUNTS);
+        assert_eq!(mounts.len(), 4);
+        assert_eq!(
+            mounts[2],
+            (PathBuf::from("/media/me/USB STICK"), "vfat".to_string())
+        );
+        let of = |dir: &str| mount_of(&mounts, Path::new(dir)).unwrap().kind;
+        assert_eq!(of("/media/me/USB STICK/films"), "vfat");
+        assert_eq!(of("/mnt/nas/2024"), "cifs");
+        assert_eq!(of("/tmp"), "tmpfs");
+        // A path merely sharing a prefix is on the parent mount.
+        assert_eq!(of("/mnt/nas-backup"), "ext4");
+        assert_eq!(of("/home/me"), "ext4");
+    }
+
+    #[test]
+    fn test_macos_mount_listing() {
+        let text = "\
+/dev/disk3s1s1 on / (apfs, sealed, local, read-only, journaled)
+/dev/disk4s1 on /Volumes/NO NAME (msdos, local, nodev, nosuid, noowners)
+//me@nas._smb._tcp.local/videos on /Volumes/videos (smbfs, nodev, nosuid, mounted by me)
+";
+        let mounts = parse_mount_output(text);
+        let of = |dir: &str| mount_of(&mounts, Path::new(dir)).unwrap();
+        assert_eq!(of("/Volumes/NO NAME/clip").name(), "FAT32");
+        assert_eq!(of("/Volumes/videos").name(), "SMB");
+        assert_eq!(of("/Users/me").kind, "apfs");
+    }
+
+    #[test]
+    fn test_what_filesystems_cannot_take() {
+        let fat = Filesystem::new("vfat");
+        assert_eq!(fat.max_file_bytes(), Some(FAT32_MAX_FILE_BYTES));
+        assert!(!fat.seeks_poorly());
+        let smb = Filesystem::new("cifs");
+        assert!(smb.seeks_poorly());
+        assert_eq!(smb.max_file_bytes(), None);
+        // exFAT is what large USB sticks come with, and has no such limit.
+        assert_eq!(Filesystem::new("exfat").max_file_bytes(), None);
+        assert_eq!(Filesystem::new("exfat").name(), "exfat");
+    }
+}
@@ -0,0 +1,196 @@
+//! `batch --target-from`: a target for each file of a batch, read from the
+//! file's name or from a file beside it, for upload tools that say how big
+//! each video may be.
+//!
+//! `filename-suffix` reads the size after the last `__` of the file stem,
+//! as in `clip__25MB.mp4`; `sidecar` reads the contents of
+//! `clip.mp4.target`, such as `25M`. Both are size strings as `--size`
+//! takes them, in `--size-units`. A file without one gets the batch's
+//! target (`--size`, a profile or the default), and `fixed` gives every
+//! file that. One that is there but isn't a size fails that file, not the
+//! batch ([`resolve`]).
+
+use crate::size::{parse_size, SizeUnits};
+use crate::url;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+/// What comes before the size in a file stem.
+pub const SUFFIX_SEPARATOR: &str = "__";
+
+/// The extension added to an input's name for the file holding its target.
+pub const SIDECAR_EXTENSION: &str = "target";
+
+/// Where `--target-from` reads each file's target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum TargetFrom {
+    /// The batch's target for every file.
+    #[default]
+    Fixed,
+    /// The size after the last `__` of the file stem.
+    FilenameSuffix,
+    /// `<input>.target` beside the file.
+    Sidecar,
+}
+
+/// Where a file's target came from, as its report records it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetSource {
+    /// The batch's target.
+    Fixed,
+    Filename,
+    Sidecar,
+}
+
+impl TargetSource {
+    /// How the status output names it, after the target.
+    pub fn describe(self) -> &'static str {
+        match self {
+            TargetSource::Fixed => "the batch's, with none given for the file",
+            TargetSource::Filename => "from the file name",
+            TargetSource::Sidecar => "from the .target file beside the input",
+        }
+    }
+}
+
+/// The size string after the last `__` of `stem`, when that starts like a
+/// size does, with a digit. Earlier parts are part of the name, whatever
+/// they look like, so `4K_60fps__25MB` gives `25MB`.
+pub fn suffix_of(stem: &str) -> Option<&str> {
+    let (_, suffix) = stem.rsplit_once(SUFFIX_SEPARATOR)?;
+    suffix
+        .starts_with(|c: char| c.is_ascii_digit())
+        .then_some(suffix)
+}
+
+/// The size a `.target` file holds: its only non-blank text.
+pub fn parse_sidecar(text: &str, units: SizeUnits) -> Result<u64, String> {
+    match text.trim() {
+        "" => Err("it is empty".to_string()),
+        size => parse_size(size, units),
+    }
+}
+
+/// Where the target of `input` is kept with `--target-from sidecar`.
+pub fn sidecar_path(input: &str) -> String {
+    format!("{}.{}", input, SIDECAR_EXTENSION)
+}
+
+/// The target of `input` read as `from` says, in `units`, and where it came
+/// from; `fixed` when there is none to read. Fails with why when there is
+/// one but it isn't a size.
+pub fn resolve(
+    input: &str,
+    from: TargetFrom,
+    fixed: u64,
+    units: SizeUnits,
+) -> Result<(u64, TargetSource), String> {
+    match from {
+        TargetFrom::Fixed => {}
+        TargetFrom::FilenameSuffix => {
+            let stem = Path::new(input).file_stem().map(|s| s.to_string_lossy());
+            if let Some(suffix) = stem.as_deref().and_then(suffix_of) {
+                let bytes = parse_size(suffix, units)
+                    .map_err(|e| format!("the target in the file name: {}", e))?;
+                return Ok((bytes, TargetSource::Filename));
+            }
+        }
+        // A download has nothing beside it.
+        TargetFrom::Sidecar if url::is_url(input) => {}
+        TargetFrom::Sidecar => {
+            let path = sidecar_path(input);
+            match std::fs::read_to_string(&path) {
+                Ok(text) => {
+                    let bytes =
+                        parse_sidecar(&text, units).map_err(|e| format!("{}: {}", path, e))?;
+                    return Ok((bytes, TargetSource::Sidecar));
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("cannot read {}: {}", path, e)),
+            }
+        }
+    }
+    Ok((fixed, TargetSource::Fixed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_only_the_last_suffix_of_a_stem_is_a_target() {
+        // (stem, suffix)
+        let cases = [
+            ("clip__25MB", Some("25MB")),
+            ("clip__1.5GiB", Some("1.5GiB")),
+            ("holiday_4K_60fps_1080p__25MB", Some("25MB")),
+            ("clip__10MB__25MB", Some("25MB")),
+            // A size earlier in the name, with something after it.
+            ("clip__25MB__final", None),
+            ("clip_25MB", None),
+            ("clip__", None),
+            ("__8M", Some("8M")),
+            ("clip__25XB", Some("25XB")),
+        ];
+        for (stem, suffix) in cases {
+            assert_eq!(suffix_of(stem), suffix, "{}", stem);
+        }
+    }
+
+    #[test]
+    fn test_names_give_sizes_as_size_does() {
+        let units = SizeUnits::Si;
+        let from = TargetFrom::FilenameSuffix;
+        assert_eq!(
+            resolve("in/clip__25MB.mp4", from, 1, units),
+            Ok((25_000_000, TargetSource::Filename))
+        );
+        assert_eq!(
+            resolve("in/clip__25.mp4", from, 1, SizeUnits::Binary),
+            Ok((25 << 20, TargetSource::Filename))
+        );
+        assert_eq!(
+            resolve("in/clip.mp4", from, 7, units),
+            Ok((7, TargetSource::Fixed))
+        );
+        let e = resolve("in/clip__25XB.mp4", from, 7, units).unwrap_err();
+        assert!(
+            e.starts_with("the target in the file name: invalid size '25XB'"),
+            "{}",
+            e
+        );
+        // Unless asked to, names are only names.
+        assert_eq!(
+            resolve("in/clip__25MB.mp4", TargetFrom::Fixed, 7, units),
+            Ok((7, TargetSource::Fixed))
+        );
+    }
+
+    #[test]
+    fn test_target_files_hold_one_size() {
+        let dir = TestDir::new();
+        let input = dir.join("clip.mp4");
+        let units = SizeUnits::Si;
+        let from = TargetFrom::Sidecar;
+        assert_eq!(
+            resolve(&input, from, 7, units),
+            Ok((7, TargetSource::Fixed))
+        );
+
+        std::fs::write(sidecar_path(&input), "25M\n").unwrap();
+        assert_eq!(
+            resolve(&input, from, 7, units),
+            Ok((25_000_000, TargetSource::Sidecar))
+        );
+        for text in ["", " \n", "25M 30M", "big"] {
+            std::fs::write(sidecar_path(&input), text).unwrap();
+            let e = resolve(&input, from, 7, units).unwrap_err();
+            assert!(e.starts_with(&sidecar_path(&input)), "{:?}: {}", text, e);
+        }
+        assert_eq!(parse_sidecar("  1.5GiB\r\n", units), Ok(3 << 29));
+    }
+}
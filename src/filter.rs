@@ -0,0 +1,845 @@
+//! Construction of the video filter graph passed to ffmpeg.
+//!
+//! Every feature that wants to alter the video frames goes through
+//! [`FilterChain`] so that the final command line carries a single, coherent
+//! `-vf` (or `-filter_complex`) argument instead of several conflicting ones.
+//! Stages are rendered in a fixed order regardless of the order in which they
+//! were added:
+//!
+//! hwdownload → crop → deinterlace → denoise → scale → fps → rotate → overlay/drawtext → subtitles
+
+use crate::coverart;
+use crate::longpath;
+use std::fmt;
+
+/// How odd frame dimensions are made even (libx264 rejects odd sizes for yuv420p).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvenMode {
+    /// Scale down to the nearest even size (drops at most one row/column).
+    Scale,
+    /// Pad up to the nearest even size with black pixels.
+    Pad,
+}
+
+/// The position of a stage in the chain. Variants are declared in render order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StageKind {
+    Download,
+    Crop,
+    Deinterlace,
+    Denoise,
+    Scale,
+    Fps,
+    Rotate,
+    Overlay,
+    DrawText,
+    Subtitles,
+}
+
+impl StageKind {
+    /// Whether adding a second stage of this kind replaces the first.
+    fn is_unique(self) -> bool {
+        !matches!(self, StageKind::Overlay | StageKind::DrawText)
+    }
+}
+
+/// A single ffmpeg filter with positional and/or named options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    name: String,
+    options: Vec<(Option<String>, String)>,
+}
+
+impl Filter {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            options: Vec::new(),
+        }
+    }
+
+    /// Appends a positional option value.
+    pub fn value(mut self, value: &str) -> Self {
+        self.options.push((None, value.to_string()));
+        self
+    }
+
+    /// Appends a `key=value` option.
+    pub fn option(mut self, key: &str, value: &str) -> Self {
+        self.options
+            .push((Some(key.to_string()), value.to_string()));
+        self
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        for (i, (key, value)) in self.options.iter().enumerate() {
+            f.write_str(if i == 0 { "=" } else { ":" })?;
+            if let Some(key) = key {
+                write!(f, "{}=", key)?;
+            }
+            f.write_str(&escape_value(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes an option value for use inside a filtergraph description.
+///
+/// ffmpeg unescapes twice: once when splitting the graph into filters
+/// (`\ ' [ ] , ;`) and once when splitting a filter's options (`\ ' :`), so
+/// the value is escaped for the inner level first and the outer level second.
+pub fn escape_value(value: &str) -> String {
+    let inner = escape_chars(value, &['\\', '\'', ':']);
+    escape_chars(&inner, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+fn escape_chars(value: &str, special: &[char]) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Problems detected while assembling a filter chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterError {
+    /// A scale stage where neither dimension is fixed.
+    UnboundedScale,
+    /// A frame rate that ffmpeg would reject.
+    InvalidFps(String),
+    /// A rotation that is not a multiple of 90 degrees.
+    InvalidRotation(u32),
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::UnboundedScale => {
+                write!(f, "scale filter needs at least one fixed dimension")
+            }
+            FilterError::InvalidFps(fps) => write!(f, "invalid frame rate: {}", fps),
+            FilterError::InvalidRotation(deg) => {
+                write!(f, "rotation must be 90, 180 or 270 degrees, got {}", deg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stage {
+    kind: StageKind,
+    filters: Vec<Filter>,
+    /// Extra input (index into [`FilterChain::inputs`]) consumed by an overlay stage.
+    input: Option<usize>,
+}
+
+/// The rendered form of a [`FilterChain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterGraph {
+    /// No filtering is required.
+    None,
+    /// A linear chain, passed with `-vf`.
+    Simple(String),
+    /// A graph with additional inputs, passed with `-filter_complex`. The
+    /// filtered video is exposed under the `output` label and must be mapped
+    /// explicitly, which also disables ffmpeg's automatic audio selection.
+    Complex {
+        graph: String,
+        inputs: Vec<String>,
+        output: String,
+    },
+}
+
+impl FilterGraph {
+    /// Arguments that must precede the output options (extra `-i` inputs).
+    pub fn input_args(&self) -> Vec<String> {
+        match self {
+            FilterGraph::Complex { inputs, .. } => inputs
+                .iter()
+                .flat_map(|i| ["-i".to_string(), longpath::for_tool(i)])
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The `-vf`/`-filter_complex` arguments, including the video `-map` for complex graphs.
+    pub fn filter_args(&self) -> Vec<String> {
+        match self {
+            FilterGraph::None => Vec::new(),
+            FilterGraph::Simple(graph) => vec!["-vf".to_string(), graph.clone()],
+            FilterGraph::Complex { graph, output, .. } => vec![
+                "-filter_complex".to_string(),
+                graph.clone(),
+                "-map".to_string(),
+                format!("[{}]", output),
+            ],
+        }
+    }
+
+    /// Whether the graph maps the video stream itself (so other streams need explicit maps).
+    pub fn maps_video(&self) -> bool {
+        matches!(self, FilterGraph::Complex { .. })
+    }
+}
+
+/// A `-filter_complex` graph putting the video of inputs 0 and 1 (not
+/// their cover art) next to each other as `[v]`, both scaled to `height`
+/// with square pixels and labeled in their top-left corner.
+pub fn side_by_side(height: u32, labels: [&str; 2]) -> String {
+    let font_size = (height / 20).max(12).to_string();
+    let sides = ["left", "right"];
+    let mut chains: Vec<String> = labels
+        .iter()
+        .zip(sides)
+        .enumerate()
+        .map(|(input, (label, side))| {
+            let filters = [
+                Filter::new("scale")
+                    .value(&format!("trunc(iw*sar*{}/ih/2)*2", height))
+                    .value(&height.to_string()),
+                Filter::new("setsar").value("1"),
+                Filter::new("drawtext")
+                    .option("text", label)
+                    .option("x", "10")
+                    .option("y", "10")
+                    .option("fontsize", &font_size)
+                    .option("fontcolor", "white")
+                    .option("box", "1")
+                    .option("boxcolor", "black@0.5"),
+            ];
+            let filters: Vec<String> = filters.iter().map(Filter::to_string).collect();
+            format!("[{}:V:0]{}[{}]", input, filters.join(","), side)
+        })
+        .collect();
+    chains.push(format!("[{}][{}]hstack=inputs=2[v]", sides[0], sides[1]));
+    chains.join(";")
+}
+
+/// Collects video filter stages and renders them into a single filter graph.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterChain {
+    stages: Vec<Stage>,
+    inputs: Vec<String>,
+    even: Option<EvenMode>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a stage. For unique kinds (everything except overlay and drawtext)
+    /// a previous stage of the same kind is replaced.
+    pub fn push(&mut self, kind: StageKind, filters: Vec<Filter>) -> &mut Self {
+        self.insert(Stage {
+            kind,
+            filters,
+            input: None,
+        })
+    }
+
+    fn insert(&mut self, stage: Stage) -> &mut Self {
+        if stage.kind.is_unique() {
+            self.stages.retain(|s| s.kind != stage.kind);
+        }
+        self.stages.push(stage);
+        self
+    }
+
+    /// Crops to a `width`x`height` rectangle at (`x`, `y`); values may be ffmpeg expressions.
+    pub fn crop(&mut self, width: &str, height: &str, x: &str, y: &str) -> &mut Self {
+        let filter = Filter::new("crop")
+            .value(width)
+            .value(height)
+            .value(x)
+            .value(y);
+        self.push(StageKind::Crop, vec![filter])
+    }
+
+    /// Brings frames decoded on the GPU (`--hwdecode`) back into memory,
+    /// in the 8- or 10-bit layout they were decoded in, for the filters
+    /// after it and the encoder.
+    pub fn hwdownload(&mut self) -> &mut Self {
+        let filters = vec![
+            Filter::new("hwdownload"),
+            Filter::new("format").value("nv12|p010le"),
+        ];
+        self.push(StageKind::Download, filters)
+    }
+
+    pub fn deinterlace(&mut self) -> &mut Self {
+        self.push(StageKind::Deinterlace, vec![Filter::new("yadif")])
+    }
+
+    pub fn denoise(&mut self) -> &mut Self {
+        self.push(StageKind::Denoise, vec![Filter::new("hqdn3d")])
+    }
+
+    /// Scales to `width`x`height`; either may be `-1`/`-2` to keep the aspect ratio.
+    pub fn scale(&mut self, width: &str, height: &str) -> &mut Self {
+        let filter = Filter::new("scale").value(width).value(height);
+        self.push(StageKind::Scale, vec![filter])
+    }
+
+    /// Scales a frame with non-square pixels to exactly `width`x`height` and
+    /// marks the result as square-pixeled.
+    pub fn scale_to_square_pixels(&mut self, width: u32, height: u32) -> &mut Self {
+        let scale = Filter::new("scale")
+            .value(&width.to_string())
+            .value(&height.to_string());
+        let setsar = Filter::new("setsar").value("1");
+        self.push(StageKind::Scale, vec![scale, setsar])
+    }
+
+    /// Scales the frame to `picture` with square pixels and centers it on a
+    /// black `canvas`, when that is larger.
+    pub fn scale_onto_canvas(&mut self, picture: (u32, u32), canvas: (u32, u32)) -> &mut Self {
+        let scale = Filter::new("scale")
+            .value(&picture.0.to_string())
+            .value(&picture.1.to_string());
+        let mut filters = vec![scale, Filter::new("setsar").value("1")];
+        if picture != canvas {
+            filters.push(
+                Filter::new("pad")
+                    .value(&canvas.0.to_string())
+                    .value(&canvas.1.to_string())
+                    .value("(ow-iw)/2")
+                    .value("(oh-ih)/2"),
+            );
+        }
+        self.push(StageKind::Scale, filters)
+    }
+
+    pub fn fps(&mut self, rate: &str) -> &mut Self {
+        self.push(StageKind::Fps, vec![Filter::new("fps").value(rate)])
+    }
+
+    /// Rotates clockwise by `degrees` (90, 180 or 270).
+    pub fn rotate(&mut self, degrees: u32) -> &mut Self {
+        let filters = match degrees {
+            90 => vec![Filter::new("transpose").value("clock")],
+            180 => vec![Filter::new("hflip"), Filter::new("vflip")],
+            270 => vec![Filter::new("transpose").value("cclock")],
+            // Rejected at render time so the builder stays infallible.
+            other => vec![Filter::new("rotate").value(&other.to_string())],
+        };
+        self.push(StageKind::Rotate, filters)
+    }
+
+    /// Overlays the first video stream of the file at `path` at position (`x`, `y`).
+    pub fn overlay(&mut self, path: &str, x: &str, y: &str) -> &mut Self {
+        self.inputs.push(path.to_string());
+        let filter = Filter::new("overlay").value(x).value(y);
+        self.insert(Stage {
+            kind: StageKind::Overlay,
+            filters: vec![filter],
+            input: Some(self.inputs.len() - 1),
+        })
+    }
+
+    pub fn drawtext(&mut self, text: &str, x: &str, y: &str, size: u32) -> &mut Self {
+        let filter = Filter::new("drawtext")
+            .option("text", text)
+            .option("x", x)
+            .option("y", y)
+            .option("fontsize", &size.to_string());
+        self.push(StageKind::DrawText, vec![filter])
+    }
+
+    /// Burns in the subtitles from `path`.
+    pub fn subtitles(&mut self, path: &str) -> &mut Self {
+        let filter = Filter::new("subtitles").option("filename", path);
+        self.push(StageKind::Subtitles, vec![filter])
+    }
+
+    /// Burns in the subtitles from `path`, with libass looking for fonts in
+    /// `fonts_dir` (see [`crate::fonts::extract`]) before the system's.
+    pub fn subtitles_with_fonts(&mut self, path: &str, fonts_dir: &str) -> &mut Self {
+        let filter = Filter::new("subtitles")
+            .option("filename", path)
+            .option("fontsdir", fonts_dir);
+        self.push(StageKind::Subtitles, vec![filter])
+    }
+
+    /// Requires the final frame dimensions to be even.
+    ///
+    /// In [`EvenMode::Scale`] mode the constraint is folded into an existing
+    /// scale stage rather than appending a second `scale` filter.
+    pub fn force_even(&mut self, mode: EvenMode) -> &mut Self {
+        self.even = Some(mode);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty() && self.even.is_none()
+    }
+
+    /// Files that must be added as extra ffmpeg inputs, in input-index order starting at 1.
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    /// Returns the stages in render order with the even-dimension fix applied.
+    fn ordered_stages(&self) -> Result<Vec<Stage>, FilterError> {
+        let mut stages = self.stages.clone();
+        // Stable sort keeps insertion order among repeated overlay/drawtext stages.
+        stages.sort_by_key(|s| s.kind);
+
+        for stage in &stages {
+            validate(stage)?;
+        }
+
+        match self.even {
+            Some(EvenMode::Scale) => {
+                if let Some(scale) = stages.iter_mut().find(|s| s.kind == StageKind::Scale) {
+                    // Only the sizes; a `setsar` ratio next to them stays.
+                    for filter in scale.filters.iter_mut().filter(|f| f.name == "scale") {
+                        for (_, value) in &mut filter.options {
+                            *value = even_expr(value);
+                        }
+                    }
+                } else {
+                    let filter = Filter::new("scale")
+                        .value("trunc(iw/2)*2")
+                        .value("trunc(ih/2)*2");
+                    insert_sorted(&mut stages, StageKind::Scale, filter);
+                }
+            }
+            Some(EvenMode::Pad) => {
+                let filter = Filter::new("pad")
+                    .value("ceil(iw/2)*2")
+                    .value("ceil(ih/2)*2");
+                // Padding belongs with the geometry stages so later overlays see the final size.
+                match stages.iter_mut().find(|s| s.kind == StageKind::Scale) {
+                    Some(scale) => scale.filters.push(filter),
+                    None => insert_sorted(&mut stages, StageKind::Scale, filter),
+                }
+            }
+            None => {}
+        }
+        Ok(stages)
+    }
+
+    /// Renders the chain, choosing `-filter_complex` when overlay inputs exist.
+    pub fn render(&self) -> Result<FilterGraph, FilterError> {
+        if self.is_empty() {
+            return Ok(FilterGraph::None);
+        }
+        let stages = self.ordered_stages()?;
+        if self.inputs.is_empty() {
+            let graph = stages
+                .iter()
+                .flat_map(|s| s.filters.iter().map(|f| f.to_string()))
+                .collect::<Vec<_>>()
+                .join(",");
+            return Ok(FilterGraph::Simple(graph));
+        }
+
+        // Split the linear chain at each overlay, wiring the extra input in by label.
+        let output = "vout".to_string();
+        let mut segments = Vec::new();
+        let mut pending: Vec<String> = Vec::new();
+        let mut current = coverart::VIDEO.to_string();
+        let mut next_label = 0;
+        for stage in &stages {
+            match stage.input {
+                Some(input) => {
+                    if !pending.is_empty() {
+                        let label = format!("v{}", next_label);
+                        next_label += 1;
+                        segments.push(format!("[{}]{}[{}]", current, pending.join(","), label));
+                        pending.clear();
+                        current = label;
+                    }
+                    let label = format!("v{}", next_label);
+                    next_label += 1;
+                    segments.push(format!(
+                        "[{}][{}:v]{}[{}]",
+                        current,
+                        input + 1,
+                        stage.filters[0],
+                        label
+                    ));
+                    current = label;
+                }
+                None => pending.extend(stage.filters.iter().map(|f| f.to_string())),
+            }
+        }
+        if pending.is_empty() {
+            // Rename the final overlay's output to the public label.
+            if let Some(last) = segments.last_mut() {
+                let suffix = format!("[{}]", current);
+                last.truncate(last.len() - suffix.len());
+                last.push_str(&format!("[{}]", output));
+            }
+        } else {
+            segments.push(format!("[{}]{}[{}]", current, pending.join(","), output));
+        }
+
+        Ok(FilterGraph::Complex {
+            graph: segments.join(";"),
+            inputs: self.inputs.clone(),
+            output,
+        })
+    }
+}
+
+fn insert_sorted(stages: &mut Vec<Stage>, kind: StageKind, filter: Filter) {
+    let at = stages
+        .iter()
+        .position(|s| s.kind > kind)
+        .unwrap_or(stages.len());
+    stages.insert(
+        at,
+        Stage {
+            kind,
+            filters: vec![filter],
+            input: None,
+        },
+    );
+}
+
+fn validate(stage: &Stage) -> Result<(), FilterError> {
+    let first = &stage.filters[0];
+    match stage.kind {
+        StageKind::Scale if first.name == "scale" => {
+            let keeps_aspect = |v: &str| v == "-1" || v == "-2";
+            if first.options.iter().all(|(_, v)| keeps_aspect(v)) {
+                return Err(FilterError::UnboundedScale);
+            }
+        }
+        StageKind::Fps => {
+            let rate = &first.options[0].1;
+            let valid = match rate.split_once('/') {
+                Some((num, den)) => {
+                    num.parse::<u32>().is_ok_and(|n| n > 0)
+                        && den.parse::<u32>().is_ok_and(|d| d > 0)
+                }
+                None => rate.parse::<f64>().is_ok_and(|r| r > 0.0 && r.is_finite()),
+            };
+            if !valid {
+                return Err(FilterError::InvalidFps(rate.clone()));
+            }
+        }
+        StageKind::Rotate if first.name == "rotate" => {
+            let degrees = first.options[0].1.parse().unwrap_or(0);
+            return Err(FilterError::InvalidRotation(degrees));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Rewrites a scale dimension expression so that it always evaluates to an even number.
+fn even_expr(expr: &str) -> String {
+    match expr {
+        // ffmpeg's "-2" keeps the aspect ratio like "-1" but rounds to a multiple of 2.
+        "-1" | "-2" => "-2".to_string(),
+        _ => match expr.parse::<u32>() {
+            Ok(n) => (n - n % 2).to_string(),
+            Err(_) => format!("trunc(({})/2)*2", expr),
+        },
+    }
+}
+
+/// Returns the dimensions produced by making `width`x`height` even with `mode`.
+pub fn even_dimensions(width: u32, height: u32, mode: EvenMode) -> (u32, u32) {
+    match mode {
+        EvenMode::Scale => (width - width % 2, height - height % 2),
+        EvenMode::Pad => (width + width % 2, height + height % 2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simple(chain: &FilterChain) -> String {
+        match chain.render().unwrap() {
+            FilterGraph::Simple(graph) => graph,
+            other => panic!("expected a simple graph, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_empty_chain_has_no_args() {
+        let chain = FilterChain::new();
+        assert!(chain.is_empty());
+        assert_eq!(chain.render().unwrap(), FilterGraph::None);
+        assert!(chain.render().unwrap().filter_args().is_empty());
+    }
+
+    #[test]
+    fn test_force_even_scale_alone() {
+        let mut chain = FilterChain::new();
+        chain.force_even(EvenMode::Scale);
+        assert_eq!(
+            chain.render().unwrap().filter_args(),
+            vec!["-vf", "scale=trunc(iw/2)*2:trunc(ih/2)*2"]
+        );
+    }
+
+    #[test]
+    fn test_force_even_pad_alone() {
+        let mut chain = FilterChain::new();
+        chain.force_even(EvenMode::Pad);
+        assert_eq!(simple(&chain), "pad=ceil(iw/2)*2:ceil(ih/2)*2");
+    }
+
+    #[test]
+    fn test_even_scale_merges_into_existing_scale() {
+        // A user scale plus the even fix must yield one scale filter, not two.
+        let mut chain = FilterChain::new();
+        chain.scale("1281", "-1").force_even(EvenMode::Scale);
+        assert_eq!(simple(&chain), "scale=1280:-2");
+
+        let mut chain = FilterChain::new();
+        chain.force_even(EvenMode::Scale).scale("iw/3", "ih/3");
+        assert_eq!(simple(&chain), "scale=trunc((iw/3)/2)*2:trunc((ih/3)/2)*2");
+    }
+
+    #[test]
+    fn test_even_scale_leaves_setsar_alone() {
+        let mut chain = FilterChain::new();
+        chain
+            .scale_to_square_pixels(853, 480)
+            .force_even(EvenMode::Scale);
+        assert_eq!(
+            chain.render().unwrap(),
+            FilterGraph::Simple("scale=852:480,setsar=1".into())
+        );
+    }
+
+    #[test]
+    fn test_scale_onto_canvas_pads_only_when_smaller() {
+        let mut chain = FilterChain::new();
+        chain.scale_onto_canvas((1080, 608), (1080, 1920));
+        assert_eq!(
+            simple(&chain),
+            "scale=1080:608,setsar=1,pad=1080:1920:(ow-iw)/2:(oh-ih)/2"
+        );
+        chain.scale_onto_canvas((1080, 1920), (1080, 1920));
+        assert_eq!(simple(&chain), "scale=1080:1920,setsar=1");
+    }
+
+    #[test]
+    fn test_even_pad_follows_user_scale() {
+        let mut chain = FilterChain::new();
+        chain
+            .force_even(EvenMode::Pad)
+            .fps("30")
+            .scale("1281", "-1");
+        assert_eq!(
+            simple(&chain),
+            "scale=1281:-1,pad=ceil(iw/2)*2:ceil(ih/2)*2,fps=30"
+        );
+    }
+
+    #[test]
+    fn test_crop_renders_before_even_fix() {
+        let mut chain = FilterChain::new();
+        chain.force_even(EvenMode::Pad).crop("641", "361", "0", "0");
+        assert_eq!(
+            simple(&chain),
+            "crop=641:361:0:0,pad=ceil(iw/2)*2:ceil(ih/2)*2"
+        );
+
+        let mut chain = FilterChain::new();
+        chain
+            .force_even(EvenMode::Scale)
+            .crop("641", "361", "0", "0");
+        assert_eq!(
+            simple(&chain),
+            "crop=641:361:0:0,scale=trunc(iw/2)*2:trunc(ih/2)*2"
+        );
+    }
+
+    #[test]
+    fn test_stages_render_in_defined_order() {
+        let mut chain = FilterChain::new();
+        chain
+            .subtitles("subs.srt")
+            .drawtext("hi", "10", "10", 24)
+            .rotate(90)
+            .fps("24")
+            .scale("1280", "-2")
+            .denoise()
+            .deinterlace()
+            .crop("iw", "ih-100", "0", "50");
+        assert_eq!(
+            simple(&chain),
+            "crop=iw:ih-100:0:50,yadif,hqdn3d,scale=1280:-2,fps=24,transpose=clock,\
+             drawtext=text=hi:x=10:y=10:fontsize=24,subtitles=filename=subs.srt"
+        );
+    }
+
+    #[test]
+    fn test_unique_stages_are_replaced() {
+        let mut chain = FilterChain::new();
+        chain
+            .scale("1920", "-2")
+            .scale("1280", "-2")
+            .fps("60")
+            .fps("30");
+        assert_eq!(simple(&chain), "scale=1280:-2,fps=30");
+    }
+
+    #[test]
+    fn test_repeatable_stages_keep_insertion_order() {
+        let mut chain = FilterChain::new();
+        chain
+            .drawtext("first", "0", "0", 12)
+            .drawtext("second", "0", "20", 12);
+        let graph = simple(&chain);
+        assert!(graph.find("first").unwrap() < graph.find("second").unwrap());
+        assert_eq!(graph.matches("drawtext").count(), 2);
+    }
+
+    #[test]
+    fn test_rotate_180_uses_flips() {
+        let mut chain = FilterChain::new();
+        chain.rotate(180);
+        assert_eq!(simple(&chain), "hflip,vflip");
+    }
+
+    #[test]
+    fn test_escaping_of_special_characters() {
+        assert_eq!(escape_value("plain"), "plain");
+        assert_eq!(escape_value("min(1280,iw)"), "min(1280\\,iw)");
+        assert_eq!(escape_value("a:b"), "a\\\\:b");
+        assert_eq!(escape_value("it's"), "it\\\\\\'s");
+        assert_eq!(escape_value("[x];y"), "\\[x\\]\\;y");
+
+        let mut chain = FilterChain::new();
+        chain.subtitles("C:\\subs\\a,b.srt");
+        assert_eq!(
+            simple(&chain),
+            "subtitles=filename=C\\\\:\\\\\\\\subs\\\\\\\\a\\,b.srt"
+        );
+    }
+
+    #[test]
+    fn test_subtitles_fontsdir_is_escaped_like_the_filename() {
+        let mut chain = FilterChain::new();
+        chain
+            .subtitles("first.ass")
+            .subtitles_with_fonts("in.mkv", "/tmp/run:1/fonts");
+        assert_eq!(
+            simple(&chain),
+            "subtitles=filename=in.mkv:fontsdir=/tmp/run\\\\:1/fonts"
+        );
+    }
+
+    #[test]
+    fn test_side_by_side_scales_both_to_one_height_and_labels_them() {
+        assert_eq!(
+            side_by_side(720, ["Original", "Reduced: 8 MiB"]),
+            "[0:V:0]scale=trunc(iw*sar*720/ih/2)*2:720,setsar=1,\
+             drawtext=text=Original:x=10:y=10:fontsize=36:fontcolor=white:box=1:boxcolor=black@0.5[left];\
+             [1:V:0]scale=trunc(iw*sar*720/ih/2)*2:720,setsar=1,\
+             drawtext=text=Reduced\\\\: 8 MiB:x=10:y=10:fontsize=36:fontcolor=white:box=1:boxcolor=black@0.5[right];\
+             [left][right]hstack=inputs=2[v]"
+        );
+        // Small outputs still get readable labels.
+        assert!(side_by_side(144, ["a", "b"]).contains("fontsize=12"));
+    }
+
+    #[test]
+    fn test_overlay_switches_to_filter_complex() {
+        let mut chain = FilterChain::new();
+        chain
+            .scale("1280", "-2")
+            .overlay("logo.png", "10", "10")
+            .drawtext("hi", "0", "0", 12);
+        let graph = chain.render().unwrap();
+        assert!(graph.maps_video());
+        assert_eq!(graph.input_args(), vec!["-i", "logo.png"]);
+        assert_eq!(
+            graph.filter_args(),
+            vec![
+                "-filter_complex",
+                "[0:V:0]scale=1280:-2[v0];[v0][1:v]overlay=10:10[v1];\
+                 [v1]drawtext=text=hi:x=0:y=0:fontsize=12[vout]",
+                "-map",
+                "[vout]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hwdownload_comes_before_everything() {
+        let mut chain = FilterChain::new();
+        chain.hwdownload();
+        assert_eq!(simple(&chain), "hwdownload,format=nv12|p010le");
+        chain.scale("1280", "-2").crop("iw", "ih-100", "0", "50");
+        assert_eq!(
+            simple(&chain),
+            "hwdownload,format=nv12|p010le,crop=iw:ih-100:0:50,scale=1280:-2"
+        );
+        chain.overlay("logo.png", "10", "10");
+        match chain.render().unwrap() {
+            FilterGraph::Complex { graph, .. } => assert!(
+                graph.starts_with("[0:V:0]hwdownload,format=nv12|p010le,crop="),
+                "{}",
+                graph
+            ),
+            other => panic!("expected a complex graph, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_overlay_as_last_stage_outputs_public_label() {
+        let mut chain = FilterChain::new();
+        chain
+            .overlay("a.png", "0", "0")
+            .overlay("b.png", "W-w", "H-h");
+        match chain.render().unwrap() {
+            FilterGraph::Complex { graph, inputs, .. } => {
+                assert_eq!(
+                    graph,
+                    "[0:V:0][1:v]overlay=0:0[v0];[v0][2:v]overlay=W-w:H-h[vout]"
+                );
+                assert_eq!(inputs, vec!["a.png", "b.png"]);
+            }
+            other => panic!("expected a complex graph, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_combinations_are_rejected() {
+        let mut chain = FilterChain::new();
+        chain.scale("-1", "-2");
+        assert_eq!(chain.render(), Err(FilterError::UnboundedScale));
+
+        let mut chain = FilterChain::new();
+        chain.fps("0");
+        assert_eq!(
+            chain.render(),
+            Err(FilterError::InvalidFps("0".to_string()))
+        );
+
+        let mut chain = FilterChain::new();
+        chain.fps("30000/1001");
+        assert!(chain.render().is_ok());
+
+        let mut chain = FilterChain::new();
+        chain.rotate(45);
+        assert_eq!(chain.render(), Err(FilterError::InvalidRotation(45)));
+    }
+
+    #[test]
+    fn test_even_dimensions() {
+        assert_eq!(even_dimensions(1281, 721, EvenMode::Scale), (1280, 720));
+        assert_eq!(even_dimensions(1281, 721, EvenMode::Pad), (1282, 722));
+        assert_eq!(even_dimensions(1280, 720, EvenMode::Scale), (1280, 720));
+    }
+}
@@ -0,0 +1,394 @@
+//! `--min-quality`: a floor the planned picture may not fall below.
+//!
+//! `--fail-on-poor-quality` stops at the tool's own idea of poor; a floor
+//! is the user's, given as bits per pixel (`bpp=0.04`) or as a [`Quality`]
+//! level, which needs as many bits per pixel as the codec takes for it.
+//! A plan below the floor after `--max-width`, `--max-height` and the
+//! frame rate options have had their say is refused before encoding,
+//! with exit code 10. The message names the mildest smaller frame size
+//! and frame rate that would reach the floor within the same target,
+//! which [`search`] finds, or says that none does.
+
+use crate::estimate::{bits_per_pixel, format_fps, Codec, Quality};
+use std::fmt;
+
+/// The shorter side of the frame sizes tried below the planned one,
+/// largest first.
+pub const HEIGHTS: [u32; 7] = [1440, 1080, 720, 540, 480, 360, 240];
+
+/// The frame rates tried below the planned one, highest first.
+pub const RATES: [f64; 3] = [30.0, 24.0, 15.0];
+
+/// Below this rate motion looks choppy, so a lower rate is only suggested
+/// when no frame size reaches the floor otherwise.
+const SMOOTH_FPS: f64 = 24.0;
+
+/// The lowest quality an output may plan for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Floor {
+    /// Bits per pixel, whatever the codec.
+    Bpp(f64),
+    /// The bits per pixel the codec needs for this verdict.
+    Level(Quality),
+}
+
+impl Floor {
+    /// Parses `bpp=<number>`, `fair` or `good`.
+    pub fn parse(text: &str) -> Result<Floor, String> {
+        if let Some(value) = text.strip_prefix("bpp=") {
+            return match value.parse::<f64>() {
+                Ok(bpp) if bpp > 0.0 && bpp.is_finite() => Ok(Floor::Bpp(bpp)),
+                _ => Err(format!(
+                    "'{}' is not a positive number of bits per pixel",
+                    value
+                )),
+            };
+        }
+        match text {
+            "fair" => Ok(Floor::Level(Quality::Fair)),
+            "good" => Ok(Floor::Level(Quality::Good)),
+            "poor" => Err("every plan is at least poor; use fair, good or bpp=<number>".into()),
+            _ => Err(format!(
+                "'{}' is not a quality floor; use fair, good or bpp=<number>",
+                text
+            )),
+        }
+    }
+
+    /// The fewest bits per pixel that reach the floor with `codec`.
+    pub fn min_bpp(self, codec: Codec) -> f64 {
+        match self {
+            Floor::Bpp(bpp) => bpp,
+            Floor::Level(quality) => codec.min_bpp(quality),
+        }
+    }
+}
+
+impl fmt::Display for Floor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Floor::Bpp(bpp) => write!(f, "bpp={}", bpp),
+            Floor::Level(quality) => write!(f, "{}", quality),
+        }
+    }
+}
+
+/// A frame size and rate, and the bits per pixel the video bitrate gives
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Choice {
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+    pub bpp: f64,
+}
+
+impl Choice {
+    fn at(video_bitrate: u64, width: u32, height: u32, fps: f64) -> Self {
+        Self {
+            width,
+            height,
+            fps,
+            bpp: bits_per_pixel(video_bitrate, width, height, fps),
+        }
+    }
+
+    /// The options that turn a `width`x`height` plan at `fps` into this.
+    pub fn flags(&self, width: u32, height: u32, fps: f64) -> Vec<String> {
+        let mut flags = Vec::new();
+        if (self.width, self.height) != (width, height) {
+            if width >= height {
+                flags.push(format!("--max-height {}", self.height));
+            } else {
+                flags.push(format!("--max-width {}", self.width));
+            }
+        }
+        if self.fps < fps {
+            flags.push(format!("--max-fps {}", format_fps(self.fps)));
+        }
+        flags
+    }
+}
+
+/// The frame sizes to try for a `width`x`height` frame: itself, then each
+/// of [`HEIGHTS`] below its shorter side at the same shape, the other side
+/// rounded to even.
+fn sizes(width: u32, height: u32) -> Vec<(u32, u32)> {
+    let even = |side: f64| (((side / 2.0).round() * 2.0) as u32).max(2);
+    let short = width.min(height);
+    let mut sizes = vec![(width, height)];
+    for side in HEIGHTS.into_iter().filter(|&side| side < short) {
+        let scale = side as f64 / short as f64;
+        sizes.push(if width >= height {
+            (even(width as f64 * scale), side)
+        } else {
+            (side, even(height as f64 * scale))
+        });
+    }
+    sizes
+}
+
+/// The frame rates to try for `fps`: itself, then each of [`RATES`]
+/// clearly below it.
+fn rates(fps: f64) -> Vec<f64> {
+    let mut rates = vec![fps];
+    rates.extend(RATES.into_iter().filter(|&rate| rate < fps - 0.5));
+    rates
+}
+
+/// The mildest way to reach `min_bpp` at `video_bitrate` from a
+/// `width`x`height` plan at `fps`: of every frame size and rate tried, the
+/// one keeping the most pixels per second, the larger frame when two keep
+/// as many. Rates below 24 fps that the plan didn't have already come
+/// last. `None` when even the smallest misses the floor.
+pub fn search(
+    video_bitrate: u64,
+    width: u32,
+    height: u32,
+    fps: f64,
+    min_bpp: f64,
+) -> Option<Choice> {
+    let rates = rates(fps);
+    sizes(width, height)
+        .into_iter()
+        .flat_map(|(w, h)| {
+            rates
+                .iter()
+                .map(move |&rate| Choice::at(video_bitrate, w, h, rate))
+        })
+        .filter(|choice| choice.bpp >= min_bpp)
+        .max_by(|a, b| {
+            let smooth = |c: &Choice| c.fps >= SMOOTH_FPS || c.fps == fps;
+            let rate = |c: &Choice| c.width as f64 * c.height as f64 * c.fps;
+            smooth(a)
+                .cmp(&smooth(b))
+                .then(rate(a).total_cmp(&rate(b)))
+                .then((a.width * a.height).cmp(&(b.width * b.height)))
+        })
+}
+
+/// Why a `width`x`height` plan at `fps` and `video_bitrate` doesn't meet
+/// `floor`, with what would; `None` when it does.
+pub fn refusal(
+    floor: Floor,
+    codec: Codec,
+    video_bitrate: u64,
+    width: u32,
+    height: u32,
+    fps: f64,
+) -> Option<String> {
+    let min_bpp = floor.min_bpp(codec);
+    let planned = Choice::at(video_bitrate, width, height, fps);
+    if planned.bpp >= min_bpp {
+        return None;
+    }
+    let below = format!(
+        "{}x{} at {} fps and {} kb/s gives {:.3} bpp, below --min-quality {} ({:.3} bpp)",
+        width,
+        height,
+        format_fps(fps),
+        video_bitrate / 1000,
+        planned.bpp,
+        floor,
+        min_bpp
+    );
+    Some(match search(video_bitrate, width, height, fps, min_bpp) {
+        Some(choice) => format!(
+            "{}; {}x{} at {} fps would reach {:.3} bpp within the target ({})",
+            below,
+            choice.width,
+            choice.height,
+            format_fps(choice.fps),
+            choice.bpp,
+            choice.flags(width, height, fps).join(" ")
+        ),
+        None => format!(
+            "{}; no frame size down to {}p and frame rate down to {} fps reaches it within the target, so it needs a larger --size or --split",
+            below,
+            HEIGHTS[HEIGHTS.len() - 1],
+            format_fps(RATES[RATES.len() - 1])
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Floor::parse("bpp=0.04"), Ok(Floor::Bpp(0.04)));
+        assert_eq!(Floor::parse("fair"), Ok(Floor::Level(Quality::Fair)));
+        assert_eq!(Floor::parse("good"), Ok(Floor::Level(Quality::Good)));
+        for bad in [
+            "bpp=0", "bpp=-1", "bpp=abc", "bpp=", "poor", "great", "0.04",
+        ] {
+            assert!(Floor::parse(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(Floor::Bpp(0.04).to_string(), "bpp=0.04");
+        assert_eq!(Floor::Level(Quality::Good).to_string(), "good");
+    }
+
+    #[test]
+    fn test_levels_follow_the_codec() {
+        let fair = Floor::Level(Quality::Fair);
+        assert_eq!(fair.min_bpp(Codec::H264), 0.04);
+        assert!(fair.min_bpp(Codec::Av1) < fair.min_bpp(Codec::H264));
+        assert_eq!(Floor::Bpp(0.05).min_bpp(Codec::Av1), 0.05);
+        // The level's threshold is where the verdict starts.
+        for codec in [Codec::H264, Codec::H265, Codec::Av1] {
+            for quality in [Quality::Fair, Quality::Good] {
+                let bpp = Floor::Level(quality).min_bpp(codec);
+                assert_eq!(codec.quality(bpp * 1.001), quality);
+                assert!(codec.quality(bpp * 0.999) < quality);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sizes_keep_the_shape() {
+        assert_eq!(
+            sizes(1920, 1080),
+            [
+                (1920, 1080),
+                (1280, 720),
+                (960, 540),
+                (854, 480),
+                (640, 360),
+                (426, 240)
+            ]
+        );
+        // Portrait frames step down their width.
+        assert_eq!(sizes(1080, 1920)[1], (720, 1280));
+        // Below the smallest step there is nothing to try.
+        assert_eq!(sizes(320, 200), [(320, 200)]);
+        assert_eq!(rates(60.0), [60.0, 30.0, 24.0, 15.0]);
+        // 29.97 isn't lowered to 30.
+        assert_eq!(rates(29.97), [29.97, 24.0, 15.0]);
+        assert_eq!(rates(12.0), [12.0]);
+    }
+
+    #[test]
+    fn test_search_takes_the_mildest_reduction() {
+        // (bitrate, frame, fps, floor) -> (frame, fps) found
+        let cases = [
+            // Already there: the plan itself.
+            (
+                4_000_000,
+                (1920, 1080),
+                30.0,
+                0.04,
+                Some(((1920, 1080), 30.0)),
+            ),
+            // 1080p30 at 2 Mb/s is 0.032 bpp; 30 to 24 fps is a smaller
+            // step than 1080p to 720p.
+            (
+                2_000_000,
+                (1920, 1080),
+                30.0,
+                0.04,
+                Some(((1920, 1080), 24.0)),
+            ),
+            // At 60 fps halving the rate is the mildest.
+            (
+                2_600_000,
+                (1920, 1080),
+                60.0,
+                0.04,
+                Some(((1920, 1080), 30.0)),
+            ),
+            // 1.2 Mb/s needs 720p: 0.043 bpp at 30 fps.
+            (
+                1_200_000,
+                (1920, 1080),
+                30.0,
+                0.04,
+                Some(((1280, 720), 30.0)),
+            ),
+            // 1080p15 keeps more pixels per second than 720p30 at 1.3
+            // Mb/s, but looks choppy.
+            (
+                1_300_000,
+                (1920, 1080),
+                30.0,
+                0.04,
+                Some(((1280, 720), 30.0)),
+            ),
+            // A rate that was below 24 fps already is kept.
+            (
+                1_500_000,
+                (1920, 1080),
+                20.0,
+                0.04,
+                Some(((1280, 720), 20.0)),
+            ),
+            // Portrait frames shrink as well.
+            (
+                1_200_000,
+                (1080, 1920),
+                30.0,
+                0.04,
+                Some(((720, 1280), 30.0)),
+            ),
+            // Only the smallest frame at the lowest rate gets there.
+            (65_000, (1920, 1080), 30.0, 0.04, Some(((426, 240), 15.0))),
+            // Nothing does.
+            (50_000, (1920, 1080), 30.0, 0.04, None),
+            (100_000, (320, 200), 12.0, 0.2, None),
+        ];
+        for (bitrate, (w, h), fps, floor, expected) in cases {
+            let found = search(bitrate, w, h, fps, floor);
+            assert_eq!(
+                found.map(|c| ((c.width, c.height), c.fps)),
+                expected,
+                "{} b/s at {}x{} {} fps",
+                bitrate,
+                w,
+                h,
+                fps
+            );
+            if let Some(choice) = found {
+                assert!(choice.bpp >= floor);
+            }
+        }
+    }
+
+    #[test]
+    fn test_flags_name_what_changes() {
+        let choice = Choice::at(1_000_000, 1280, 720, 24.0);
+        assert_eq!(
+            choice.flags(1920, 1080, 30.0),
+            ["--max-height 720", "--max-fps 24"]
+        );
+        assert_eq!(choice.flags(1280, 720, 24.0), Vec::<String>::new());
+        let portrait = Choice::at(1_000_000, 720, 1280, 30.0);
+        assert_eq!(portrait.flags(1080, 1920, 30.0), ["--max-width 720"]);
+    }
+
+    #[test]
+    fn test_refusal_says_what_would_do() {
+        let floor = Floor::Bpp(0.04);
+        assert_eq!(
+            refusal(floor, Codec::H264, 4_000_000, 1920, 1080, 30.0),
+            None
+        );
+        let message = refusal(floor, Codec::H264, 1_200_000, 1920, 1080, 30.0).unwrap();
+        assert_eq!(
+            message,
+            "1920x1080 at 30 fps and 1200 kb/s gives 0.019 bpp, below --min-quality bpp=0.04 (0.040 bpp); \
+             1280x720 at 30 fps would reach 0.043 bpp within the target (--max-height 720)"
+        );
+        let message = refusal(floor, Codec::H264, 50_000, 1920, 1080, 30.0).unwrap();
+        assert!(
+            message.ends_with(
+                "no frame size down to 240p and frame rate down to 15 fps reaches it within the target, so it needs a larger --size or --split"
+            ),
+            "{}",
+            message
+        );
+        // A level is judged for the codec: AV1 gets there with fewer bits.
+        let fair = Floor::Level(Quality::Fair);
+        assert!(refusal(fair, Codec::H264, 2_000_000, 1920, 1080, 30.0).is_some());
+        assert!(refusal(fair, Codec::Av1, 2_000_000, 1920, 1080, 30.0).is_none());
+    }
+}
@@ -0,0 +1,147 @@
+//! Fonts attached to Matroska files, which ASS subtitles are styled with.
+//!
+//! Fansubs and anime releases ship the fonts their subtitles use as
+//! attachment streams. A remux into Matroska copies them along; burning the
+//! subtitles in needs them as files, so they are extracted into the run
+//! directory and handed to the `subtitles` filter as its `fontsdir`.
+
+use crate::breakdown::{FileStreams, StreamEntry, StreamKind};
+use crate::error::ReduceError;
+use crate::longpath;
+use crate::tempdir::RunTempDir;
+use crate::tool::VideoTool;
+use std::path::{Path, PathBuf};
+
+/// The attachment streams of a file, fonts or not.
+pub fn attachments(streams: &FileStreams) -> Vec<&StreamEntry> {
+    streams
+        .streams
+        .iter()
+        .filter(|s| s.kind == StreamKind::Attachment)
+        .collect()
+}
+
+/// Whether an attachment of `codec` is a font libass can load.
+pub fn is_font(codec: &str) -> bool {
+    matches!(codec, "ttf" | "otf")
+}
+
+/// The ffmpeg arguments writing each of `fonts` into `dir` as
+/// `<stream index>.<codec>`. libass finds fonts by the family names inside
+/// them, so the original file names aren't needed.
+pub fn dump_args(input: &str, fonts: &[&StreamEntry], dir: &Path) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    for font in fonts {
+        let path = dir.join(format!("{}.{}", font.index, font.codec));
+        args.extend([
+            format!("-dump_attachment:{}", font.index),
+            longpath::for_tool(&path.to_string_lossy()),
+        ]);
+    }
+    // Attachments are dumped while the input is opened; the output just
+    // has to exist and end at once.
+    args.extend(["-i".to_string(), longpath::for_tool(input)]);
+    args.extend(["-t", "0", "-f", "null", "-"].iter().map(|s| s.to_string()));
+    args
+}
+
+/// Extracts the fonts attached to `input` into a `fonts` directory of
+/// `run_dir` and returns it; `None` when the input has none.
+pub fn extract<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    run_dir: &RunTempDir,
+) -> Result<Option<PathBuf>, ReduceError> {
+    let streams = tool.get_streams(input)?;
+    let fonts: Vec<&StreamEntry> = attachments(&streams)
+        .into_iter()
+        .filter(|s| is_font(&s.codec))
+        .collect();
+    if fonts.is_empty() {
+        return Ok(None);
+    }
+    let dir = run_dir.file("fonts");
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| ReduceError::Encode(format!("cannot create {}: {}", dir.display(), e)))?;
+    let args = dump_args(input, &fonts, &dir);
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    tool.run_ffmpeg(&args).map_err(|e| match e {
+        ReduceError::Encode(message) => ReduceError::Encode(format!(
+            "cannot extract the fonts of {}: {}",
+            input, message
+        )),
+        other => other,
+    })?;
+    Ok(Some(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::breakdown::parse_streams;
+    use crate::testing::{arg_value, MockVideoTool, TestDir};
+
+    /// An MKV with ASS subtitles, two fonts and a cover image.
+    const FANSUB_MKV: &str = r#"{
+        "format": {"size": "50000000", "duration": "1440.0"},
+        "streams": [
+            {"index": 0, "codec_name": "h264", "codec_type": "video"},
+            {"index": 1, "codec_name": "aac", "codec_type": "audio"},
+            {"index": 2, "codec_name": "ass", "codec_type": "subtitle"},
+            {"index": 3, "codec_name": "ttf", "codec_type": "attachment", "extradata_size": 250000},
+            {"index": 4, "codec_name": "otf", "codec_type": "attachment", "extradata_size": 90000},
+            {"index": 5, "codec_name": "png", "codec_type": "attachment", "extradata_size": 4000}
+        ]
+    }"#;
+
+    #[test]
+    fn test_fonts_are_dumped_by_stream_index() {
+        let streams = parse_streams(FANSUB_MKV).unwrap();
+        assert_eq!(attachments(&streams).len(), 3);
+        let fonts: Vec<&StreamEntry> = attachments(&streams)
+            .into_iter()
+            .filter(|s| is_font(&s.codec))
+            .collect();
+        let args = dump_args("in.mkv", &fonts, Path::new("/tmp/run/fonts"));
+        assert_eq!(
+            args,
+            [
+                "-y",
+                "-dump_attachment:3",
+                "/tmp/run/fonts/3.ttf",
+                "-dump_attachment:4",
+                "/tmp/run/fonts/4.otf",
+                "-i",
+                "in.mkv",
+                "-t",
+                "0",
+                "-f",
+                "null",
+                "-"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_makes_a_fonts_dir_in_the_run_dir() {
+        let dir = TestDir::new();
+        let run_dir = RunTempDir::create(Some(dir.path()), false).unwrap();
+        let mut tool = MockVideoTool::new(1440.0);
+        tool.streams = parse_streams(FANSUB_MKV).unwrap();
+
+        let fonts = extract(&tool, "in.mkv", &run_dir).unwrap().unwrap();
+        assert!(fonts.is_dir());
+        assert!(fonts.starts_with(run_dir.path()));
+        let args = tool.single_call();
+        assert_eq!(
+            arg_value(&args, "-dump_attachment:3"),
+            Some(fonts.join("3.ttf").to_str().unwrap())
+        );
+        assert_eq!(arg_value(&args, "-dump_attachment:5"), None);
+
+        // Without fonts there is nothing to run.
+        let tool = MockVideoTool::new(1440.0);
+        assert_eq!(extract(&tool, "in.mkv", &run_dir).unwrap(), None);
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+//! The history ledger: which files a batch already reduced, so a restarted
+//! batch can skip them.
+//!
+//! The ledger is an append-only file of JSON lines under the config
+//! directory. Each finished file appends one line in a single write, so
+//! concurrent batches interleave whole entries rather than corrupting each
+//! other; a line torn by a crash is skipped on load. Once the file grows
+//! past [`COMPACT_AFTER`] lines, loading rewrites it (through a temp file
+//! and a rename) with only the latest entry per file and target.
+
+use crate::config;
+use crate::outdir;
+use crate::overhead::Sample;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File name of the ledger inside the config directory.
+const FILE_NAME: &str = "history.jsonl";
+
+/// Number of lines after which loading compacts the ledger.
+pub const COMPACT_AFTER: usize = 1000;
+
+/// Result recorded for a file that was reduced successfully.
+pub const RESULT_OK: &str = "ok";
+
+/// Identifies an input by where it is and what it looks like on disk, so an
+/// edited file counts as new even under the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// Canonical path of the input.
+    pub input: String,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch.
+    pub modified: u64,
+}
+
+impl Fingerprint {
+    /// Fingerprints `input`; `None` when it can't be read.
+    pub fn of(input: &str) -> Option<Self> {
+        let path = std::fs::canonicalize(input).ok()?;
+        let meta = std::fs::metadata(&path).ok()?;
+        let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            input: path.to_string_lossy().into_owned(),
+            size: meta.len(),
+            modified: modified.as_secs(),
+        })
+    }
+}
+
+/// One processed file.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Entry {
+    pub input: String,
+    pub input_size: u64,
+    pub input_modified: u64,
+    pub target_bytes: u64,
+    pub output: String,
+    /// [`RESULT_OK`], or a short description of the failure.
+    pub result: String,
+    /// Total size written, for successful runs.
+    pub output_bytes: Option<u64>,
+    /// When the run finished, in seconds since the Unix epoch.
+    pub finished: u64,
+    /// What the run showed about its container's overhead; absent for
+    /// failures, samples and entries written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<Sample>,
+}
+
+impl Entry {
+    pub fn new(
+        fingerprint: &Fingerprint,
+        target_bytes: u64,
+        output: &str,
+        result: &str,
+        output_bytes: Option<u64>,
+    ) -> Self {
+        Self {
+            input: fingerprint.input.clone(),
+            input_size: fingerprint.size,
+            input_modified: fingerprint.modified,
+            target_bytes,
+            output: absolute(output),
+            result: result.to_string(),
+            output_bytes,
+            finished: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            sample: None,
+        }
+    }
+
+    /// The entry with what the run showed about its container.
+    pub fn with_sample(mut self, sample: Option<Sample>) -> Self {
+        self.sample = sample;
+        self
+    }
+
+    fn key(&self) -> (&str, u64, &str) {
+        (&self.input, self.target_bytes, &self.output)
+    }
+
+    fn is_for(&self, fingerprint: &Fingerprint) -> bool {
+        self.input == fingerprint.input
+            && self.input_size == fingerprint.size
+            && self.input_modified == fingerprint.modified
+    }
+}
+
+/// The ledger file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn at(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The ledger in the default config directory: `$XDG_CONFIG_HOME` when
+    /// set, otherwise the platform's own. `None` without a home directory.
+    pub fn default_location() -> Option<Self> {
+        config::dir().map(|dir| Self::at(dir.join(FILE_NAME)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// All entries, oldest first. A missing ledger is empty.
+    pub fn load(&self) -> io::Result<Vec<Entry>> {
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let lines = text.lines().filter(|l| !l.trim().is_empty()).count();
+        let entries: Vec<Entry> = text
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        if lines <= COMPACT_AFTER {
+            return Ok(entries);
+        }
+        let entries = latest_per_key(entries);
+        self.rewrite(&entries)?;
+        Ok(entries)
+    }
+
+    /// Appends one entry, creating the ledger (and its directory) on first use.
+    pub fn append(&self, entry: &Entry) -> io::Result<()> {
+        outdir::ensure_parent(&self.path, true).map_err(io::Error::other)?;
+        let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+        line.push('\n');
+        // One write of the whole line: with O_APPEND, concurrent writers
+        // can't interleave within it.
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+
+    /// Deletes every entry.
+    pub fn clear(&self) -> io::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces the ledger with `entries` in one rename, so readers see
+    /// either the old file or the new one.
+    fn rewrite(&self, entries: &[Entry]) -> io::Result<()> {
+        let mut temp = self.path.as_os_str().to_owned();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        let temp = PathBuf::from(temp);
+        let mut text = String::new();
+        for entry in entries {
+            text.push_str(&serde_json::to_string(entry).map_err(io::Error::other)?);
+            text.push('\n');
+        }
+        std::fs::write(&temp, text)?;
+        std::fs::rename(&temp, &self.path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&temp);
+        })
+    }
+}
+
+/// Whether `entries` show the input already reduced to `target_bytes` at
+/// `output`, with the result still there at the recorded size
+/// (`written_bytes`).
+pub fn already_done(
+    entries: &[Entry],
+    fingerprint: &Fingerprint,
+    target_bytes: u64,
+    output: &str,
+    written_bytes: u64,
+) -> bool {
+    let output = absolute(output);
+    entries
+        .iter()
+        .rev()
+        .find(|e| e.is_for(fingerprint) && e.target_bytes == target_bytes && e.output == output)
+        .is_some_and(|e| e.result == RESULT_OK && e.output_bytes == Some(written_bytes))
+}
+
+/// Whether `entries` record `output` as written from the input of
+/// `fingerprint`, whatever the target, so writing it again replaces
+/// nothing of another input's.
+pub fn wrote(entries: &[Entry], fingerprint: &Fingerprint, output: &str) -> bool {
+    let output = absolute(output);
+    entries
+        .iter()
+        .any(|e| e.is_for(fingerprint) && e.output == output)
+}
+
+/// Keeps the last entry for each input, target and output, in order.
+fn latest_per_key(entries: Vec<Entry>) -> Vec<Entry> {
+    let mut seen = HashSet::new();
+    let keep: Vec<bool> = entries.iter().rev().map(|e| seen.insert(e.key())).collect();
+    let mut keep = keep.into_iter().rev();
+    entries
+        .iter()
+        .filter(|_| keep.next().unwrap_or(false))
+        .cloned()
+        .collect()
+}
+
+fn absolute(path: &str) -> String {
+    std::path::absolute(path)
+        .map_or_else(|_| path.to_string(), |p| p.to_string_lossy().into_owned())
+}
+
+/// Formats seconds since the Unix epoch as a UTC `YYYY-MM-DD HH:MM`.
+pub fn format_timestamp(secs: u64) -> String {
+    let (year, month, day) = utc_date(secs);
+    let (hour, minute) = ((secs % 86_400) / 3600, (secs % 3600) / 60);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year, month, day, hour, minute
+    )
+}
+
+/// The UTC (year, month, day) of seconds since the Unix epoch.
+pub fn utc_date(secs: u64) -> (i64, u32, u32) {
+    let days = (secs / 86_400) as i64;
+    // Howard Hinnant's days-to-civil conversion.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    fn fingerprint(input: &str) -> Fingerprint {
+        Fingerprint {
+            input: input.to_string(),
+            size: 1000,
+            modified: 1_700_000_000,
+        }
+    }
+
+    fn ok_entry(input: &str, output: &str, bytes: u64) -> Entry {
+        Entry::new(&fingerprint(input), 50, output, RESULT_OK, Some(bytes))
+    }
+
+    #[test]
+    fn test_append_and_load_round_trip() {
+        let dir = TestDir::new();
+        let history = History::at(dir.path().join("config").join(FILE_NAME));
+        assert!(history.load().unwrap().is_empty());
+
+        let entry = ok_entry("/v/a.mp4", "/out/a.mp4", 10);
+        history.append(&entry).unwrap();
+        assert_eq!(history.load().unwrap(), vec![entry]);
+        history.clear().unwrap();
+        assert!(history.load().unwrap().is_empty());
+        history.clear().unwrap();
+    }
+
+    #[test]
+    fn test_torn_lines_are_skipped() {
+        let dir = TestDir::new();
+        let history = History::at(dir.path().join(FILE_NAME));
+        let entry = ok_entry("/v/a.mp4", "/out/a.mp4", 10);
+        history.append(&entry).unwrap();
+        let mut text = std::fs::read_to_string(history.path()).unwrap();
+        text.push_str("{\"input\":\"/v/b.mp4\",\"inp\n");
+        std::fs::write(history.path(), text).unwrap();
+        history.append(&entry).unwrap();
+
+        assert_eq!(history.load().unwrap(), vec![entry.clone(), entry]);
+    }
+
+    #[test]
+    fn test_concurrent_appends_stay_whole() {
+        let dir = TestDir::new();
+        let history = History::at(dir.path().join(FILE_NAME));
+        std::thread::scope(|scope| {
+            for t in 0..8 {
+                let history = &history;
+                scope.spawn(move || {
+                    for i in 0..50 {
+                        let input = format!("/v/{}-{}.mp4", t, i);
+                        history.append(&ok_entry(&input, "/out/x.mp4", 1)).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(history.load().unwrap().len(), 400);
+    }
+
+    #[test]
+    fn test_large_ledger_is_compacted_to_latest_entries() {
+        let dir = TestDir::new();
+        let history = History::at(dir.path().join(FILE_NAME));
+        for i in 0..=COMPACT_AFTER as u64 {
+            history
+                .append(&ok_entry("/v/a.mp4", "/out/a.mp4", i))
+                .unwrap();
+        }
+        history
+            .append(&ok_entry("/v/b.mp4", "/out/b.mp4", 7))
+            .unwrap();
+
+        let entries = history.load().unwrap();
+        let sizes: Vec<_> = entries.iter().map(|e| e.output_bytes).collect();
+        assert_eq!(sizes, vec![Some(COMPACT_AFTER as u64), Some(7)]);
+        let lines = std::fs::read_to_string(history.path()).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_already_done_needs_same_file_target_and_output() {
+        let fp = fingerprint("/v/a.mp4");
+        let entries = vec![ok_entry("/v/a.mp4", "/out/a.mp4", 10)];
+        assert!(already_done(&entries, &fp, 50, "/out/a.mp4", 10));
+        // Another target, output, or a changed or missing result.
+        assert!(!already_done(&entries, &fp, 25, "/out/a.mp4", 10));
+        assert!(!already_done(&entries, &fp, 50, "/elsewhere/a.mp4", 10));
+        assert!(!already_done(&entries, &fp, 50, "/out/a.mp4", 0));
+        // The input was edited since.
+        let edited = Fingerprint {
+            modified: fp.modified + 60,
+            ..fp.clone()
+        };
+        assert!(!already_done(&entries, &edited, 50, "/out/a.mp4", 10));
+    }
+
+    #[test]
+    fn test_latest_failure_means_not_done() {
+        let fp = fingerprint("/v/a.mp4");
+        let mut entries = vec![ok_entry("/v/a.mp4", "/out/a.mp4", 10)];
+        entries.push(Entry::new(&fp, 50, "/out/a.mp4", "encode failed", None));
+        assert!(!already_done(&entries, &fp, 50, "/out/a.mp4", 10));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13");
+    }
+}
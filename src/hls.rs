@@ -0,0 +1,283 @@
+//! `--format hls`: a playlist and its segments in an output directory, for
+//! players that stream.
+//!
+//! The plan is the same as for a single file; only the muxing changes.
+//! ffmpeg's `hls` muxer writes fragmented MP4 segments of about
+//! [`Hls::segment_seconds`] each, cut at keyframes forced on that grid,
+//! into a directory of the run ([`muxer_args`]). The target is for all of
+//! it together: [`total_bytes`] adds up the playlist and every file it
+//! lists, which is what an attempt is verified and retried against. Only a
+//! set within the target is moved to the output directory ([`commit`]),
+//! the playlist last, so a failed run leaves no partial set behind.
+
+use crate::commit;
+use crate::longpath;
+use crate::tool::VideoTool;
+use clap::ValueEnum;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The playlist's name in the output directory.
+pub const PLAYLIST: &str = "index.m3u8";
+
+/// The fragmented MP4 header every segment starts from.
+const INIT: &str = "init.mp4";
+
+const SEGMENT_PREFIX: &str = "segment";
+const SEGMENT_EXTENSION: &str = ".m4s";
+
+/// `--hls-time` when not given.
+pub const DEFAULT_SEGMENT_SECONDS: f64 = 6.0;
+
+/// What container the output is written as: a single file, or an HLS
+/// playlist with its segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Format {
+    #[default]
+    File,
+    Hls,
+}
+
+/// How an HLS output is cut.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hls {
+    /// The length each segment aims for; the last one may be shorter.
+    pub segment_seconds: f64,
+}
+
+/// Parses `--hls-time`, in seconds.
+pub fn parse_segment_seconds(text: &str) -> Result<f64, String> {
+    match text.trim().parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds > 0.0 => Ok(seconds),
+        _ => Err(format!(
+            "invalid segment length '{}': expected seconds greater than zero, e.g. 6",
+            text
+        )),
+    }
+}
+
+/// A keyframe at every segment boundary, so segments come out the length
+/// asked for rather than that of the encoder's GOP.
+pub fn keyframe_args(hls: Hls) -> Vec<String> {
+    vec![
+        "-force_key_frames".to_string(),
+        format!("expr:gte(t,n_forced*{})", hls.segment_seconds),
+    ]
+}
+
+/// The muxer options for writing the playlist `playlist`, with the
+/// segments beside it.
+pub fn muxer_args(hls: Hls, playlist: &str) -> Vec<String> {
+    let segments =
+        Path::new(playlist).with_file_name(format!("{}%05d{}", SEGMENT_PREFIX, SEGMENT_EXTENSION));
+    [
+        "-f",
+        "hls",
+        "-hls_time",
+        &hls.segment_seconds.to_string(),
+        "-hls_playlist_type",
+        "vod",
+        "-hls_segment_type",
+        "fmp4",
+        "-hls_fmp4_init_filename",
+        INIT,
+        "-hls_segment_filename",
+        &longpath::for_tool(&segments.to_string_lossy()),
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+/// The files the playlist text lists: its segments, and the header they
+/// start from. Each must be a plain name beside the playlist.
+fn listed(playlist: &str) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for line in playlist.lines().map(str::trim) {
+        let name = match line.strip_prefix("#EXT-X-MAP:") {
+            Some(attributes) => attributes
+                .split(',')
+                .find_map(|a| a.strip_prefix("URI="))
+                .map(|uri| uri.trim_matches('"')),
+            None if line.is_empty() || line.starts_with('#') => None,
+            None => Some(line),
+        };
+        let Some(name) = name else {
+            continue;
+        };
+        if name.contains(['/', '\\']) || name == ".." {
+            return Err(io::Error::other(format!(
+                "{} lists {}, which isn't beside it",
+                PLAYLIST, name
+            )));
+        }
+        names.push(name.to_string());
+    }
+    Ok(names)
+}
+
+/// The files listed in the playlist in `dir`, which must all be there.
+fn members(dir: &Path) -> io::Result<Vec<String>> {
+    let names = listed(&fs::read_to_string(dir.join(PLAYLIST))?)?;
+    if let Some(missing) = names.iter().find(|name| !dir.join(name).is_file()) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} lists {}, which isn't there", PLAYLIST, missing),
+        ));
+    }
+    Ok(names)
+}
+
+/// The bytes of the playlist in `dir` and every file it lists, the size
+/// the target is for.
+pub fn total_bytes(dir: &Path) -> io::Result<u64> {
+    let size = |name: &str| fs::metadata(dir.join(name)).map(|m| m.len());
+    members(dir)?
+        .iter()
+        .try_fold(size(PLAYLIST)?, |total, name| Ok(total + size(name)?))
+}
+
+/// Empties `dir` for an attempt, so nothing an earlier one wrote is
+/// counted.
+pub fn clear(dir: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    fs::create_dir_all(dir)
+}
+
+/// Moves the playlist in `staging`, and what it lists, into the directory
+/// `output`, creating it; see [`commit::commit`]. The playlist goes last,
+/// and segments an earlier run left there are removed once it is in
+/// place. When a file can't be moved, those already moved are removed
+/// again.
+pub fn commit<T: VideoTool>(tool: &T, staging: &Path, output: &Path) -> io::Result<()> {
+    let names = members(staging)?;
+    fs::create_dir_all(output)?;
+    let mut moved = Vec::new();
+    for name in names.iter().map(String::as_str).chain([PLAYLIST]) {
+        if let Err(e) = commit::commit(tool, &staging.join(name), &output.join(name), None) {
+            for name in moved {
+                let _ = fs::remove_file(output.join(name));
+            }
+            return Err(e);
+        }
+        moved.push(name);
+    }
+    let kept: HashSet<&str> = moved.into_iter().collect();
+    for entry in fs::read_dir(output)?.filter_map(Result::ok) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let segment = name.starts_with(SEGMENT_PREFIX) && name.ends_with(SEGMENT_EXTENSION);
+        if segment && !kept.contains(name.as_str()) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockVideoTool, TestDir};
+
+    /// Writes a playlist of `segments` segments of `bytes` each into
+    /// `dir`, as the muxer would.
+    fn write_set(dir: &Path, segments: u32, bytes: usize) -> String {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(INIT), vec![0; 100]).unwrap();
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:6\n#EXT-X-MAP:URI=\"{}\"\n",
+            INIT
+        );
+        for n in 0..segments {
+            let name = format!("segment{:05}.m4s", n);
+            fs::write(dir.join(&name), vec![0; bytes]).unwrap();
+            playlist += &format!("#EXTINF:6.000000,\n{}\n", name);
+        }
+        playlist += "#EXT-X-ENDLIST\n";
+        fs::write(dir.join(PLAYLIST), &playlist).unwrap();
+        playlist
+    }
+
+    #[test]
+    fn test_segments_are_fragmented_mp4_on_a_keyframe_grid() {
+        let hls = Hls {
+            segment_seconds: 4.0,
+        };
+        let args = muxer_args(hls, "/tmp/run/hls/index.m3u8");
+        assert_eq!(
+            args.join(" "),
+            "-f hls -hls_time 4 -hls_playlist_type vod -hls_segment_type fmp4 -hls_fmp4_init_filename init.mp4 -hls_segment_filename /tmp/run/hls/segment%05d.m4s"
+        );
+        assert_eq!(
+            keyframe_args(hls),
+            ["-force_key_frames", "expr:gte(t,n_forced*4)"]
+        );
+        assert_eq!(parse_segment_seconds("2.5"), Ok(2.5));
+        for text in ["0", "-6", "six", "inf"] {
+            assert!(parse_segment_seconds(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_the_total_counts_the_playlist_and_what_it_lists() {
+        let dir = TestDir::new();
+        let playlist = write_set(dir.path(), 3, 1000);
+        // Not listed, so not part of the output.
+        fs::write(dir.path().join("notes.txt"), b"unrelated").unwrap();
+        assert_eq!(
+            total_bytes(dir.path()).unwrap(),
+            playlist.len() as u64 + 100 + 3 * 1000
+        );
+
+        fs::remove_file(dir.path().join("segment00001.m4s")).unwrap();
+        let e = total_bytes(dir.path()).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "index.m3u8 lists segment00001.m4s, which isn't there"
+        );
+        assert!(listed("#EXTM3U\n../elsewhere.m4s\n").is_err());
+    }
+
+    #[test]
+    fn test_a_committed_set_replaces_an_earlier_one() {
+        let dir = TestDir::new();
+        let output = dir.path().join("stream");
+        write_set(&output, 5, 10);
+        let staging = dir.path().join("staging");
+        write_set(&staging, 2, 20);
+        commit(&MockVideoTool::new(1.0), &staging, &output).unwrap();
+        let mut names: Vec<String> = fs::read_dir(&output)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "index.m3u8",
+                "init.mp4",
+                "segment00000.m4s",
+                "segment00001.m4s"
+            ]
+        );
+        assert_eq!(
+            fs::metadata(output.join("segment00001.m4s")).unwrap().len(),
+            20
+        );
+    }
+
+    #[test]
+    fn test_a_set_that_cant_be_moved_leaves_nothing() {
+        let dir = TestDir::new();
+        let (staging, output) = (dir.path().join("staging"), dir.path().join("stream"));
+        write_set(&staging, 2, 20);
+        let mut tool = MockVideoTool::new(1.0);
+        tool.rename_error = Some(io::ErrorKind::PermissionDenied);
+        assert!(commit(&tool, &staging, &output).is_err());
+        assert_eq!(fs::read_dir(&output).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,286 @@
+//! `--hwdecode`: decoding the input on the GPU, for machines where
+//! decoding (4K HEVC, say) holds the encode back rather than the encoder.
+//!
+//! Only the decoding moves: the encoder stays the software one asked for.
+//! Frames are decoded into GPU memory and brought back by the filter
+//! graph's download stage ([`crate::filter::FilterChain::hwdownload`])
+//! before any other filter sees them. Which decoders this ffmpeg has comes
+//! from `ffmpeg -hwaccels`, and `auto` also wants the device to be there.
+//! A listed decoder can still fail to start (no driver, no permission on
+//! the device), so an encode that fails with one of [`SIGNATURES`] runs
+//! once more decoding in software, with a warning, and so does the rest of
+//! the run.
+
+use crate::error::ReduceError;
+use crate::presenter::Presenter;
+use crate::warning::{self, Code, Warning};
+use clap::ValueEnum;
+use std::fmt;
+use std::path::Path;
+
+/// What `--hwdecode` asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum HwDecode {
+    /// The first decoder this machine has, or software if none.
+    Auto,
+    Cuda,
+    Vaapi,
+    Videotoolbox,
+    /// Decode in software.
+    #[default]
+    None,
+}
+
+impl HwDecode {
+    /// The decoder named, for all but `auto` and `none`.
+    pub fn accel(self) -> Option<Accel> {
+        match self {
+            HwDecode::Cuda => Some(Accel::Cuda),
+            HwDecode::Vaapi => Some(Accel::Vaapi),
+            HwDecode::Videotoolbox => Some(Accel::Videotoolbox),
+            HwDecode::Auto | HwDecode::None => None,
+        }
+    }
+}
+
+/// A hardware decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accel {
+    Cuda,
+    Vaapi,
+    Videotoolbox,
+}
+
+/// The render node VAAPI decodes on: the first GPU's.
+pub const VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+impl Accel {
+    /// The decoders `auto` tries on this platform, in order.
+    pub fn preferred() -> &'static [Accel] {
+        if cfg!(target_os = "macos") {
+            &[Accel::Videotoolbox]
+        } else {
+            &[Accel::Cuda, Accel::Vaapi]
+        }
+    }
+
+    /// The name `ffmpeg -hwaccels` lists and `-hwaccel` takes.
+    pub fn name(self) -> &'static str {
+        match self {
+            Accel::Cuda => "cuda",
+            Accel::Vaapi => "vaapi",
+            Accel::Videotoolbox => "videotoolbox",
+        }
+    }
+
+    /// Whether the device it decodes on is there. Static ffmpeg builds list
+    /// `cuda` whatever the GPU, so `auto` checks; a decoder named outright
+    /// is left to ffmpeg to refuse.
+    pub fn device_present(self) -> bool {
+        match self {
+            Accel::Cuda => Path::new("/dev/nvidiactl").exists(),
+            Accel::Vaapi => Path::new(VAAPI_DEVICE).exists(),
+            Accel::Videotoolbox => true,
+        }
+    }
+
+    /// The input options that decode on it, keeping the frames in GPU
+    /// memory; they go before the input's `-i`.
+    pub fn input_args(self) -> Vec<String> {
+        let args: &[&str] = match self {
+            Accel::Cuda => &["-hwaccel", "cuda", "-hwaccel_output_format", "cuda"],
+            Accel::Vaapi => &[
+                "-hwaccel",
+                "vaapi",
+                "-hwaccel_device",
+                VAAPI_DEVICE,
+                "-hwaccel_output_format",
+                "vaapi",
+            ],
+            Accel::Videotoolbox => &[
+                "-hwaccel",
+                "videotoolbox",
+                "-hwaccel_output_format",
+                "videotoolbox_vld",
+            ],
+        };
+        args.iter().map(|s| s.to_string()).collect()
+    }
+}
+
+impl fmt::Display for Accel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Decoder names from `ffmpeg -hwaccels` output: one per line after the
+/// heading.
+pub fn parse_hwaccels(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .skip_while(|line| !line.trim_end().ends_with(':'))
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The decoder `requested` comes to, given the `available` ones and
+/// `present`, which says whether a decoder's device is there. A decoder
+/// named outright that this ffmpeg lacks is warned about.
+pub fn choose(
+    requested: HwDecode,
+    available: &[String],
+    present: impl Fn(Accel) -> bool,
+    out: Presenter,
+) -> Option<Accel> {
+    let listed = |accel: Accel| available.iter().any(|name| name == accel.name());
+    if requested == HwDecode::Auto {
+        let found = Accel::preferred()
+            .iter()
+            .copied()
+            .find(|&accel| listed(accel) && present(accel));
+        if found.is_none() {
+            out.info("No hardware decoder found; decoding in software");
+        }
+        return found;
+    }
+    let accel = requested.accel()?;
+    if !listed(accel) {
+        let message = format!(
+            "this ffmpeg has no {} decoding (check `ffmpeg -hwaccels`); decoding in software",
+            accel
+        );
+        warning::emit(out, Warning::new(Code::HwDecodeFallback, message));
+        return None;
+    }
+    Some(accel)
+}
+
+/// What ffmpeg says, in lower case, when a hardware decoder fails to
+/// start.
+pub const SIGNATURES: &[&str] = &[
+    "hwaccel initialisation returned error",
+    "failed setup for format",
+    "device creation failed",
+    "no device available for decoder",
+    "failed to initialise vaapi connection",
+    "cannot load libcuda",
+    "cuinit(0) failed",
+];
+
+/// Whether `err` is an encode that failed because the hardware decoder
+/// didn't start.
+pub fn failed_to_start(err: &ReduceError) -> bool {
+    let ReduceError::Encode(message) = err else {
+        return false;
+    };
+    let message = message.to_lowercase();
+    SIGNATURES
+        .iter()
+        .any(|signature| message.contains(signature))
+}
+
+/// Says that `accel` failed to start and the encode runs again in
+/// software.
+pub fn warn_fallback(accel: Accel, out: Presenter) {
+    let message = format!(
+        "{} decoding failed to start; decoding in software instead",
+        accel
+    );
+    warning::emit(out, Warning::new(Code::HwDecodeFallback, message));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presenter::ColorChoice;
+
+    #[test]
+    fn test_parse_hwaccels() {
+        let stdout = "Hardware acceleration methods:\nvdpau\ncuda\nvaapi\n\n";
+        assert_eq!(parse_hwaccels(stdout), ["vdpau", "cuda", "vaapi"]);
+        assert!(parse_hwaccels("Hardware acceleration methods:\n").is_empty());
+    }
+
+    #[test]
+    fn test_choose() {
+        let out = Presenter::stderr(ColorChoice::Never);
+        let all: Vec<String> = ["cuda", "vaapi", "videotoolbox"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let everywhere = |_| true;
+        let nowhere = |_| false;
+
+        warning::take();
+        assert_eq!(choose(HwDecode::None, &all, everywhere, out), None);
+        assert_eq!(
+            choose(HwDecode::Vaapi, &all, everywhere, out),
+            Some(Accel::Vaapi)
+        );
+        // A decoder named outright doesn't wait for its device.
+        assert_eq!(
+            choose(HwDecode::Cuda, &all, nowhere, out),
+            Some(Accel::Cuda)
+        );
+        assert!(warning::take().is_empty());
+
+        // Auto takes the platform's first decoder that is all there...
+        let first = Accel::preferred()[0];
+        assert_eq!(choose(HwDecode::Auto, &all, everywhere, out), Some(first));
+        let others = |accel| accel != first;
+        assert_eq!(
+            choose(HwDecode::Auto, &all, others, out),
+            Accel::preferred().get(1).copied()
+        );
+        // ...and quietly none when nothing is.
+        assert_eq!(choose(HwDecode::Auto, &all, nowhere, out), None);
+        assert_eq!(choose(HwDecode::Auto, &[], everywhere, out), None);
+        assert!(warning::take().is_empty());
+
+        // Named but missing from this ffmpeg says so.
+        let cuda_only = vec!["cuda".to_string()];
+        assert_eq!(choose(HwDecode::Vaapi, &cuda_only, everywhere, out), None);
+        let warnings = warning::take();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, Code::HwDecodeFallback);
+        assert!(warnings[0].message.contains("no vaapi decoding"));
+    }
+
+    #[test]
+    fn test_failed_to_start() {
+        let failures = [
+            "[AVHWDeviceContext @ 0x5581] Failed to initialise VAAPI connection: -1 (unknown libva error).\nDevice creation failed: -5.",
+            "[h264 @ 0x55] No device available for decoder: device type cuda needed for codec h264.",
+            "[hevc @ 0x55] Failed setup for format cuda: hwaccel initialisation returned error.",
+            "Cannot load libcuda.so.1",
+        ];
+        for failure in failures {
+            let err = ReduceError::Encode(format!("ffmpeg failed during encoding:\n{}", failure));
+            assert!(failed_to_start(&err), "{}", failure);
+        }
+        let others = [
+            ReduceError::Encode("ffmpeg failed during encoding:\nConnection reset by peer".into()),
+            ReduceError::Encode("ffmpeg failed during encoding".into()),
+            ReduceError::Probe("Device creation failed".into()),
+        ];
+        for err in others {
+            assert!(!failed_to_start(&err), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_input_args_keep_frames_on_the_gpu() {
+        assert_eq!(
+            Accel::Cuda.input_args().join(" "),
+            "-hwaccel cuda -hwaccel_output_format cuda"
+        );
+        assert_eq!(
+            Accel::Vaapi.input_args().join(" "),
+            "-hwaccel vaapi -hwaccel_device /dev/dri/renderD128 -hwaccel_output_format vaapi"
+        );
+    }
+}
@@ -0,0 +1,330 @@
+//! Image inputs: numbered frame sequences (`--input-pattern`) and single
+//! stills looped into a clip (`--loop-duration`).
+//!
+//! Both are fed to ffmpeg's image2 demuxer; their duration comes from the
+//! frame count or the requested length instead of ffprobe.
+
+use crate::error::ReduceError;
+use crate::longpath;
+use crate::reduce::Segment;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How an image input becomes video.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageInput {
+    /// Frames named by a printf-style pattern, shown at `fps`.
+    Sequence { fps: f64 },
+    /// One image repeated for `seconds` at `fps`.
+    Still { seconds: f64, fps: f64 },
+}
+
+/// The numbered part of a frame pattern such as `frames/%05d.png`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FramePattern {
+    dir: PathBuf,
+    prefix: String,
+    /// Zero-padded width of the number, if fixed.
+    width: Option<usize>,
+    suffix: String,
+}
+
+impl FramePattern {
+    /// Parses a pattern containing exactly one `%d` or `%0Nd` in its file name.
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "invalid input pattern '{}': expected one %d or %0Nd in the file name, e.g. frames/%05d.png",
+                pattern
+            )
+        };
+        let path = Path::new(pattern);
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .ok_or_else(invalid)?;
+        let start = name.find('%').ok_or_else(invalid)?;
+        let rest = &name[start + 1..];
+        let end = rest.find('d').ok_or_else(invalid)?;
+        let spec = &rest[..end];
+        let width = match spec {
+            "" => None,
+            _ if spec.starts_with('0') && spec.len() > 1 => {
+                Some(spec[1..].parse().map_err(|_| invalid())?)
+            }
+            _ => return Err(invalid()),
+        };
+        let suffix = rest[end + 1..].to_string();
+        if suffix.contains('%') {
+            return Err(invalid());
+        }
+        Ok(Self {
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            prefix: name[..start].to_string(),
+            width,
+            suffix,
+        })
+    }
+
+    /// The frame number in `name`, if it belongs to this sequence.
+    fn frame_number(&self, name: &str) -> Option<u64> {
+        let digits = name
+            .strip_prefix(&self.prefix)?
+            .strip_suffix(&self.suffix)?;
+        let well_formed = !digits.is_empty()
+            && digits.chars().all(|c| c.is_ascii_digit())
+            && self.width.is_none_or(|w| digits.len() == w);
+        well_formed.then(|| digits.parse().ok()).flatten()
+    }
+
+    fn path_of(&self, number: u64) -> PathBuf {
+        let digits = match self.width {
+            Some(width) => format!("{:0width$}", number, width = width),
+            None => number.to_string(),
+        };
+        self.dir
+            .join(format!("{}{}{}", self.prefix, digits, self.suffix))
+    }
+
+    /// Finds the sequence on disk: its first frame number and how many
+    /// consecutive frames follow from there (ffmpeg stops at the first gap).
+    pub fn scan(&self) -> io::Result<Option<(u64, u64)>> {
+        let dir = if self.dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            self.dir.as_path()
+        };
+        let mut numbers: Vec<u64> = std::fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .filter_map(|e| self.frame_number(&e.file_name().to_string_lossy()))
+            .collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+        let Some(&first) = numbers.first() else {
+            return Ok(None);
+        };
+        let count = numbers
+            .iter()
+            .zip(first..)
+            .take_while(|(n, expected)| *n == expected)
+            .count() as u64;
+        Ok(Some((first, count)))
+    }
+}
+
+/// An image input checked against the filesystem and ready to encode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageSource {
+    pub kind: ImageInput,
+    /// What ffmpeg reads: the pattern or the still image.
+    pub input: String,
+    /// One actual image, for ffprobe (which can't expand patterns).
+    pub probe_path: String,
+    /// First frame number of a sequence.
+    pub start_number: u64,
+    /// Frames in a sequence (1 for a still).
+    pub frames: u64,
+}
+
+impl ImageSource {
+    /// Validates `input` for `kind` before anything is handed to ffmpeg.
+    pub fn open(input: &str, kind: ImageInput) -> Result<Self, ReduceError> {
+        match kind {
+            ImageInput::Sequence { .. } => {
+                let pattern = FramePattern::parse(input).map_err(ReduceError::Usage)?;
+                let scan = pattern.scan().map_err(|e| {
+                    ReduceError::Probe(format!("cannot list frames for {}: {}", input, e))
+                })?;
+                let Some((start_number, frames)) = scan else {
+                    return Err(ReduceError::Usage(format!(
+                        "input pattern '{}' matches no files",
+                        input
+                    )));
+                };
+                Ok(Self {
+                    kind,
+                    input: input.to_string(),
+                    probe_path: pattern.path_of(start_number).to_string_lossy().into_owned(),
+                    start_number,
+                    frames,
+                })
+            }
+            ImageInput::Still { .. } => {
+                if !Path::new(input).is_file() {
+                    return Err(ReduceError::Probe(format!("{}: No such file", input)));
+                }
+                Ok(Self {
+                    kind,
+                    input: input.to_string(),
+                    probe_path: input.to_string(),
+                    start_number: 0,
+                    frames: 1,
+                })
+            }
+        }
+    }
+
+    /// Length of the resulting clip in seconds.
+    pub fn duration(&self) -> f64 {
+        match self.kind {
+            ImageInput::Sequence { fps } => self.frames as f64 / fps,
+            ImageInput::Still { seconds, .. } => seconds,
+        }
+    }
+
+    pub fn fps(&self) -> f64 {
+        match self.kind {
+            ImageInput::Sequence { fps } | ImageInput::Still { fps, .. } => fps,
+        }
+    }
+
+    /// ffmpeg input options up to and including `-i`, limited to `segment`.
+    pub fn input_args(&self, segment: Option<Segment>) -> Vec<String> {
+        let mut args = match self.kind {
+            ImageInput::Sequence { .. } => vec![
+                "-framerate".to_string(),
+                self.fps().to_string(),
+                "-start_number".to_string(),
+                self.start_number.to_string(),
+            ],
+            // Every frame of a looped still is the same, so a segment only
+            // needs its length.
+            ImageInput::Still { .. } => vec![
+                "-loop".to_string(),
+                "1".to_string(),
+                "-framerate".to_string(),
+                self.fps().to_string(),
+            ],
+        };
+        let length = match (self.kind, segment) {
+            (ImageInput::Sequence { .. }, Some(segment)) => {
+                if segment.start > 0.0 {
+                    args.extend(["-ss".to_string(), format!("{:.3}", segment.start)]);
+                }
+                Some(segment.length)
+            }
+            (ImageInput::Sequence { .. }, None) => None,
+            (ImageInput::Still { seconds, .. }, segment) => {
+                Some(segment.map_or(seconds, |s| s.length))
+            }
+        };
+        if let Some(length) = length {
+            args.extend(["-t".to_string(), format!("{:.3}", length)]);
+        }
+        args.extend(["-i".to_string(), longpath::for_tool(&self.input)]);
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    fn touch(dir: &TestDir, names: &[&str]) {
+        for name in names {
+            std::fs::write(dir.join(name), b"png").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_pattern() {
+        let pattern = FramePattern::parse("frames/shot_%05d.png").unwrap();
+        assert_eq!(pattern.prefix, "shot_");
+        assert_eq!(pattern.width, Some(5));
+        assert_eq!(pattern.suffix, ".png");
+        assert_eq!(pattern.path_of(7), Path::new("frames/shot_00007.png"));
+        assert_eq!(FramePattern::parse("%d.png").unwrap().width, None);
+
+        for bad in [
+            "frames/001.png",
+            "%5d.png",
+            "%05x.png",
+            "%d_%d.png",
+            "%0xd.png",
+        ] {
+            assert!(FramePattern::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_scan_counts_consecutive_frames() {
+        let dir = TestDir::new();
+        touch(
+            &dir,
+            &[
+                "f0001.png",
+                "f0002.png",
+                "f0003.png",
+                "f0005.png",
+                "f01.png",
+                "other.png",
+            ],
+        );
+        let pattern = FramePattern::parse(&dir.join("f%04d.png")).unwrap();
+        assert_eq!(pattern.scan().unwrap(), Some((1, 3)));
+        let unpadded = FramePattern::parse(&dir.join("f%d.png")).unwrap();
+        assert_eq!(unpadded.scan().unwrap(), Some((1, 3)));
+    }
+
+    #[test]
+    fn test_open_sequence_validates_before_encoding() {
+        let dir = TestDir::new();
+        let kind = ImageInput::Sequence { fps: 30.0 };
+        let err = ImageSource::open(&dir.join("%05d.png"), kind).unwrap_err();
+        assert!(err.to_string().contains("matches no files"), "{}", err);
+
+        touch(&dir, &["00010.png", "00011.png", "00012.png"]);
+        let source = ImageSource::open(&dir.join("%05d.png"), kind).unwrap();
+        assert_eq!((source.start_number, source.frames), (10, 3));
+        assert_eq!(source.probe_path, dir.join("00010.png"));
+        assert!((source.duration() - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_input_args() {
+        let sequence = ImageSource {
+            kind: ImageInput::Sequence { fps: 24.0 },
+            input: "f/%04d.png".into(),
+            probe_path: "f/0001.png".into(),
+            start_number: 1,
+            frames: 480,
+        };
+        assert_eq!(
+            sequence.input_args(None),
+            ["-framerate", "24", "-start_number", "1", "-i", "f/%04d.png"]
+        );
+        let second_half = Segment {
+            start: 10.0,
+            length: 10.0,
+        };
+        assert_eq!(
+            sequence.input_args(Some(second_half))[4..],
+            ["-ss", "10.000", "-t", "10.000", "-i", "f/%04d.png"]
+        );
+
+        let still = ImageSource {
+            kind: ImageInput::Still {
+                seconds: 10.0,
+                fps: 30.0,
+            },
+            input: "title.png".into(),
+            probe_path: "title.png".into(),
+            start_number: 0,
+            frames: 1,
+        };
+        assert_eq!(
+            still.input_args(Some(second_half)),
+            [
+                "-loop",
+                "1",
+                "-framerate",
+                "30",
+                "-t",
+                "10.000",
+                "-i",
+                "title.png"
+            ]
+        );
+    }
+}
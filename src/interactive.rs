@@ -0,0 +1,300 @@
+//! `--interactive`: show the plan before encoding and, when the expected
+//! quality is poor, offer ways out.
+//!
+//! Prompt I/O goes through a [`Prompter`] and is kept apart from
+//! [`Choice::apply`], which only adjusts [`ReduceOptions`] the same way the
+//! equivalent command-line flags would.
+
+use crate::error::ReduceError;
+use crate::estimate::{format_duration, Quality};
+use crate::presenter::Presenter;
+use crate::probe::VideoInfo;
+use crate::prompt::Prompter;
+use crate::reduce::{describe_budget, output_size, plan_encoding, ReduceOptions};
+use std::io::BufRead;
+
+/// Height targeted by the "downscale to 720p" choice.
+const HD_HEIGHT: u32 = 720;
+
+/// A fix offered when the planned quality is poor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Choice {
+    Downscale720,
+    DropAudio,
+    Split,
+    Continue,
+}
+
+impl Choice {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Choice::Downscale720 => "Downscale to 720p",
+            Choice::DropAudio => "Drop audio",
+            Choice::Split => "Split into 2 parts",
+            Choice::Continue => "Continue anyway",
+        }
+    }
+
+    /// The choices that would still change something for this source.
+    pub fn available(info: &VideoInfo, opts: &ReduceOptions) -> Vec<Choice> {
+        let mut choices = Vec::new();
+        // The vertical canvas has its own size, which --max-width can't change.
+        if output_size(info, opts).1 > HD_HEIGHT && opts.vertical.is_none() {
+            choices.push(Choice::Downscale720);
+        }
+        if !opts.no_audio {
+            choices.push(Choice::DropAudio);
+        }
+        if opts.parts <= 1 {
+            choices.push(Choice::Split);
+        }
+        choices.push(Choice::Continue);
+        choices
+    }
+
+    /// Applies the choice as `--max-width`, `--no-audio` or `--split` would.
+    pub fn apply(self, info: &VideoInfo, opts: &mut ReduceOptions) {
+        match self {
+            Choice::Downscale720 => {
+                let width = info.width as u64 * HD_HEIGHT as u64 / info.height.max(1) as u64;
+                // libx264 needs an even width too.
+                opts.max_width = Some((width as u32 + 1) & !1);
+            }
+            Choice::DropAudio => opts.no_audio = true,
+            Choice::Split => opts.parts = 2,
+            Choice::Continue => {}
+        }
+    }
+}
+
+/// Describes what an encode with `opts` would do, one line per item.
+pub fn describe_plan(
+    duration: f64,
+    source_bytes: Option<u64>,
+    info: &VideoInfo,
+    opts: &ReduceOptions,
+) -> Vec<String> {
+    let plan = plan_encoding(duration, info, opts);
+    let mut lines = vec![format!("Duration:       {}", format_duration(duration))];
+    match source_bytes {
+        Some(bytes) => lines.push(format!(
+            "Source:         {}x{}, {}",
+            info.width,
+            info.height,
+            opts.sizes.size(bytes)
+        )),
+        None => lines.push(format!("Source:         {}x{}", info.width, info.height)),
+    }
+    lines.push(format!("Output:         {}x{}", plan.width, plan.height));
+    lines.push(format!(
+        "Target:         {} ({} bytes){}",
+        opts.sizes.size(opts.target_bytes),
+        opts.sizes.count(opts.target_bytes),
+        if opts.parts > 1 {
+            format!(" per part, {} parts", opts.parts)
+        } else {
+            String::new()
+        }
+    ));
+    lines.push(format!(
+        "Video bitrate:  {}k{}{}",
+        plan.video_bitrate / 1000,
+        if plan.source_capped() {
+            " (capped at the source's)"
+        } else {
+            ""
+        },
+        if opts.no_audio { ", no audio" } else { "" }
+    ));
+    lines.push(format!("Budget:         {}", describe_budget(&plan, opts)));
+    lines.push(format!("Quality:        {}", plan.quality));
+    lines
+}
+
+/// Walks the user through the plan until they confirm or cancel. With
+/// `--yes`, the plan is shown and the defaults ("continue anyway", then
+/// "proceed") are taken.
+///
+/// Returns `Ok(true)` to go ahead with the (possibly adjusted) `opts`, and
+/// `Ok(false)` when the user declined or closed the input.
+pub fn confirm<R: BufRead>(
+    prompter: &mut Prompter<R>,
+    out: Presenter,
+    duration: f64,
+    source_bytes: Option<u64>,
+    info: &VideoInfo,
+    opts: &mut ReduceOptions,
+) -> Result<bool, ReduceError> {
+    loop {
+        out.info("");
+        for line in describe_plan(duration, source_bytes, info, opts) {
+            out.info(&line);
+        }
+        if plan_encoding(duration, info, opts).quality != Quality::Poor {
+            break;
+        }
+        let choices = Choice::available(info, opts);
+        out.info("");
+        out.warn("the result is likely to look poor. What would you like to do?");
+        for (i, choice) in choices.iter().enumerate() {
+            out.info(&format!("  {}) {}", i + 1, choice.label()));
+        }
+        // "Continue anyway" is always last, and the default.
+        let Some(index) = prompter.choose(choices.len(), choices.len() - 1, false)? else {
+            return Ok(false);
+        };
+        let choice = choices[index];
+        if choice == Choice::Continue {
+            break;
+        }
+        choice.apply(info, opts);
+    }
+
+    // The encode replaces whatever is at the output path.
+    prompter.confirm("Proceed?", true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presenter::ColorChoice;
+    use crate::testing::mib;
+    use std::io::Cursor;
+
+    fn prompter(input: &str, assume_yes: bool) -> Prompter<Cursor<String>> {
+        Prompter::new(
+            Cursor::new(input.to_string()),
+            Presenter::stderr(ColorChoice::Never),
+            assume_yes,
+            true,
+        )
+    }
+
+    fn info(width: u32, height: u32) -> VideoInfo {
+        VideoInfo {
+            width,
+            height,
+            avg_frame_rate: Some("30/1".into()),
+            ..VideoInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_choices_depend_on_source_and_options() {
+        let mut opts = ReduceOptions::new(mib(50));
+        assert_eq!(
+            Choice::available(&info(1920, 1080), &opts),
+            vec![
+                Choice::Downscale720,
+                Choice::DropAudio,
+                Choice::Split,
+                Choice::Continue
+            ]
+        );
+        opts.no_audio = true;
+        opts.parts = 2;
+        assert_eq!(
+            Choice::available(&info(1280, 720), &opts),
+            vec![Choice::Continue]
+        );
+    }
+
+    #[test]
+    fn test_apply_maps_to_existing_options() {
+        let source = info(1920, 1080);
+        let mut opts = ReduceOptions::new(mib(50));
+        Choice::Downscale720.apply(&source, &mut opts);
+        assert_eq!(opts.max_width, Some(1280));
+        Choice::DropAudio.apply(&source, &mut opts);
+        assert!(opts.no_audio);
+        Choice::Split.apply(&source, &mut opts);
+        assert_eq!(opts.parts, 2);
+
+        let before = opts.clone();
+        Choice::Continue.apply(&source, &mut opts);
+        assert_eq!(opts, before);
+    }
+
+    #[test]
+    fn test_downscale_keeps_even_width_for_odd_aspect_ratios() {
+        let mut opts = ReduceOptions::new(mib(50));
+        Choice::Downscale720.apply(&info(1440, 1080), &mut opts);
+        assert_eq!(opts.max_width, Some(960));
+        Choice::Downscale720.apply(&info(2000, 1001), &mut opts);
+        assert_eq!(opts.max_width.map(|w| w % 2), Some(0));
+    }
+
+    #[test]
+    fn test_choices_improve_the_verdict() {
+        // Four minutes of 1080p in 50 MB is starved; 720p halves are not.
+        let source = info(1920, 1080);
+        let mut opts = ReduceOptions::new(mib(50));
+        let duration = 240.0;
+        assert_eq!(
+            plan_encoding(duration, &source, &opts).quality,
+            Quality::Poor
+        );
+        Choice::Downscale720.apply(&source, &mut opts);
+        Choice::Split.apply(&source, &mut opts);
+        assert!(plan_encoding(duration, &source, &opts).quality > Quality::Poor);
+    }
+
+    #[test]
+    fn test_confirm_applies_selection_then_asks() {
+        let source = info(1920, 1080);
+        let mut opts = ReduceOptions::new(mib(50));
+        // Invalid input is asked again; "1" downscales, which is still poor
+        // at this length, so "3" (now "continue") and an empty line accept.
+        let go = confirm(
+            &mut prompter("9\n1\n3\n\n", false),
+            Presenter::stderr(ColorChoice::Never),
+            1200.0,
+            None,
+            &source,
+            &mut opts,
+        );
+        assert!(go.unwrap());
+        assert_eq!(opts.max_width, Some(1280));
+    }
+
+    #[test]
+    fn test_confirm_with_yes_continues_without_reading() {
+        let source = info(1920, 1080);
+        let mut opts = ReduceOptions::new(mib(50));
+        let before = opts.clone();
+        let go = confirm(
+            &mut prompter("1\nn\n", true),
+            Presenter::stderr(ColorChoice::Never),
+            1200.0,
+            None,
+            &source,
+            &mut opts,
+        );
+        assert!(go.unwrap());
+        assert_eq!(opts, before);
+    }
+
+    #[test]
+    fn test_confirm_declined_or_closed_input_cancels() {
+        let source = info(640, 360);
+        let mut opts = ReduceOptions::new(mib(50));
+        assert!(!confirm(
+            &mut prompter("n\n", false),
+            Presenter::stderr(ColorChoice::Never),
+            60.0,
+            None,
+            &source,
+            &mut opts
+        )
+        .unwrap());
+        assert!(!confirm(
+            &mut prompter("", false),
+            Presenter::stderr(ColorChoice::Never),
+            60.0,
+            None,
+            &source,
+            &mut opts
+        )
+        .unwrap());
+    }
+}
@@ -0,0 +1,69 @@
+//! Ctrl-C handling shared by every long-running step.
+//!
+//! The handler only records the request; the process loops poll
+//! [`is_interrupted`], kill their child and unwind normally so that temp
+//! directories and partial outputs are cleaned up by their owners.
+//!
+//! Ctrl-C stops everything in the process. A [`CancelToken`] stops only the
+//! thread watching it, which is how an [`EncodeSession`] is cancelled.
+//!
+//! [`EncodeSession`]: crate::session::EncodeSession
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static WATCHED: RefCell<Option<CancelToken>> = const { RefCell::new(None) };
+}
+
+/// A cancel request shared between the thread doing the work and whoever
+/// may want it stopped.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the watching thread to stop; repeating it does nothing more.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Makes [`is_interrupted`] on the current thread also report `token`.
+pub fn watch(token: CancelToken) {
+    WATCHED.with(|watched| *watched.borrow_mut() = Some(token));
+}
+
+/// Installs the Ctrl-C handler. Safe to call more than once.
+pub fn install_handler() {
+    // An error means a handler is already installed, which is all we need.
+    let _ = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst));
+}
+
+/// Stops everything in the process as Ctrl-C does, for a reason found
+/// inside it.
+pub fn request() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether Ctrl-C was pressed, or the token this thread watches was
+/// cancelled.
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+        || WATCHED.with(|watched| {
+            watched
+                .borrow()
+                .as_ref()
+                .is_some_and(CancelToken::is_cancelled)
+        })
+}
@@ -0,0 +1,220 @@
+//! `--size-ladder`: how the same input would come out at several target
+//! sizes, to see how small it can go before it looks bad.
+//!
+//! Each rung is planned as the run would plan that target, with
+//! [`plan_encoding`], and judged by its [`Verdict`]. Nothing is encoded;
+//! `--pick-best-under` then [`pick`]s the smallest target that still
+//! reaches a quality and goes on to encode at it.
+
+use crate::estimate::Quality;
+use crate::planner::{Summary, Verdict};
+use crate::presenter::{Align, Column, Table};
+use crate::probe::VideoInfo;
+use crate::reduce::{plan_encoding, ReduceOptions};
+use crate::size::{parse_size, SizeUnits};
+use crate::sizefmt::SizeFormat;
+
+/// The targets tried by a bare `--size-ladder`, in megabytes.
+pub const DEFAULT_LADDER: &str = "100,50,25,10,8";
+
+/// Parses comma-separated sizes, each as `--size` reads them, into targets
+/// from the largest down with duplicates dropped.
+pub fn parse_ladder(text: &str, units: SizeUnits) -> Result<Vec<u64>, String> {
+    let mut targets = text
+        .split(',')
+        .map(|size| parse_size(size.trim(), units))
+        .collect::<Result<Vec<u64>, String>>()?;
+    if targets.contains(&0) {
+        return Err(format!("'{}' has a size of zero", text));
+    }
+    targets.sort_unstable_by(|a, b| b.cmp(a));
+    targets.dedup();
+    Ok(targets)
+}
+
+/// One target of the ladder and how it would come out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rung {
+    pub target_bytes: u64,
+    pub quality: Quality,
+    /// Bits per second of each kept audio track.
+    pub audio_bitrates: Vec<u64>,
+    pub summary: Summary,
+}
+
+impl Rung {
+    /// Whether an encode at this target reaches `bar`.
+    pub fn reaches(&self, bar: Quality) -> bool {
+        self.summary.verdict != Verdict::OverTarget && self.quality >= bar
+    }
+}
+
+/// Plans `input` of `duration` seconds at each of `targets`, otherwise
+/// with `opts`.
+pub fn evaluate(
+    duration: f64,
+    info: &VideoInfo,
+    opts: &ReduceOptions,
+    targets: &[u64],
+) -> Vec<Rung> {
+    targets
+        .iter()
+        .map(|&target_bytes| {
+            let opts = ReduceOptions {
+                target_bytes,
+                ..opts.clone()
+            };
+            let plan = plan_encoding(duration, info, &opts);
+            Rung {
+                target_bytes,
+                quality: plan.quality,
+                audio_bitrates: plan.audio_tracks.iter().map(|t| t.bitrate).collect(),
+                summary: Summary::new(duration, &plan, &opts),
+            }
+        })
+        .collect()
+}
+
+/// The smallest target of `rungs` that reaches `bar`, if any does.
+pub fn pick(rungs: &[Rung], bar: Quality) -> Option<&Rung> {
+    rungs
+        .iter()
+        .filter(|rung| rung.reaches(bar))
+        .min_by_key(|rung| rung.target_bytes)
+}
+
+/// The ladder as a table, one rung per row.
+pub fn table(rungs: &[Rung], sizes: SizeFormat) -> Table {
+    let mut table = Table::new(vec![
+        Column::new("Target", Align::Right),
+        Column::new("Video", Align::Right),
+        Column::new("Audio", Align::Right),
+        Column::new("Frame", Align::Right),
+        Column::new("Bits/pixel", Align::Right),
+        Column::new("Quality", Align::Left),
+        Column::new("Verdict", Align::Left).shrinking(),
+    ]);
+    for rung in rungs {
+        let s = &rung.summary;
+        table.push(vec![
+            sizes.size(rung.target_bytes),
+            format!("{}k", s.video_bitrate / 1000),
+            describe_audio(&rung.audio_bitrates),
+            format!("{}x{}", s.width, s.height),
+            format!("{:.3}", s.bits_per_pixel),
+            s.quality.clone(),
+            s.verdict.describe().to_string(),
+        ]);
+    }
+    table
+}
+
+/// The kept audio tracks, e.g. `128k` or `2x96k`.
+fn describe_audio(bitrates: &[u64]) -> String {
+    match bitrates {
+        [] => "none".to_string(),
+        [only] => format!("{}k", only / 1000),
+        [first, rest @ ..] if rest.iter().all(|b| b == first) => {
+            format!("{}x{}k", bitrates.len(), first / 1000)
+        }
+        _ => format!("{}k", bitrates.iter().sum::<u64>() / 1000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mib;
+
+    fn source() -> VideoInfo {
+        VideoInfo {
+            width: 1920,
+            height: 1080,
+            avg_frame_rate: Some("30".to_string()),
+            r_frame_rate: Some("30".to_string()),
+            ..VideoInfo::default()
+        }
+    }
+
+    fn ladder(targets: &[u64]) -> Vec<Rung> {
+        evaluate(60.0, &source(), &ReduceOptions::new(mib(100)), targets)
+    }
+
+    #[test]
+    fn test_parse_ladder() {
+        let units = SizeUnits::Binary;
+        assert_eq!(
+            parse_ladder(DEFAULT_LADDER, units),
+            Ok(vec![mib(100), mib(50), mib(25), mib(10), mib(8)])
+        );
+        assert_eq!(
+            parse_ladder("8, 25MB,8,1GiB", units),
+            Ok(vec![mib(1024), mib(25), mib(8)])
+        );
+        assert!(parse_ladder("50,,10", units).is_err());
+        assert!(parse_ladder("abc", units).is_err());
+        assert!(parse_ladder("0,10", units).is_err());
+    }
+
+    #[test]
+    fn test_rungs_follow_the_planner() {
+        let rungs = ladder(&[mib(100), mib(25), mib(1)]);
+        assert_eq!(rungs.len(), 3);
+        assert!(rungs[0].summary.video_bitrate > rungs[1].summary.video_bitrate);
+        assert_eq!(rungs[0].audio_bitrates, [128_000]);
+        assert_eq!(rungs[2].summary.verdict, Verdict::OverTarget);
+        for rung in &rungs {
+            assert_eq!(rung.summary.target_bytes, rung.target_bytes);
+            assert_eq!(rung.summary.quality, rung.quality.to_string());
+        }
+    }
+
+    #[test]
+    fn test_pick_takes_the_smallest_that_qualifies() {
+        let rungs = ladder(&[mib(100), mib(25), mib(10), mib(4), mib(1)]);
+        let qualities: Vec<Quality> = rungs.iter().map(|r| r.quality).collect();
+        assert_eq!(
+            qualities,
+            [
+                Quality::Good,
+                Quality::Fair,
+                Quality::Poor,
+                Quality::Poor,
+                Quality::Poor
+            ]
+        );
+        let picked = |bar| pick(&rungs, bar).map(|rung| rung.target_bytes);
+        assert_eq!(picked(Quality::Good), Some(mib(100)));
+        assert_eq!(picked(Quality::Fair), Some(mib(25)));
+        // The smallest rung is over the target, so it never qualifies.
+        assert_eq!(rungs[4].summary.verdict, Verdict::OverTarget);
+        assert_eq!(picked(Quality::Poor), Some(mib(4)));
+
+        // Nothing qualifies.
+        let small = ladder(&[mib(2), mib(1)]);
+        assert_eq!(pick(&small, Quality::Fair), None);
+        assert_eq!(pick(&[], Quality::Poor), None);
+
+        // Everything does: the order of the ladder doesn't matter.
+        let large = ladder(&[mib(500), mib(2000), mib(1000)]);
+        assert!(large.iter().all(|rung| rung.reaches(Quality::Good)));
+        assert_eq!(
+            pick(&large, Quality::Good).map(|rung| rung.target_bytes),
+            Some(mib(500))
+        );
+    }
+
+    #[test]
+    fn test_table_has_a_row_per_rung() {
+        let rungs = ladder(&[mib(100), mib(1)]);
+        let lines = table(&rungs, SizeUnits::Binary.into()).render(200);
+        assert_eq!(lines.len(), 3, "{:#?}", lines);
+        assert!(lines[0].trim_start().starts_with("Target"), "{}", lines[0]);
+        assert!(lines[1].contains("100 MiB"), "{}", lines[1]);
+        assert!(lines[1].contains("fits"), "{}", lines[1]);
+        assert!(lines[2].contains("does not fit"), "{}", lines[2]);
+        assert_eq!(describe_audio(&[]), "none");
+        assert_eq!(describe_audio(&[96_000, 96_000]), "2x96k");
+        assert_eq!(describe_audio(&[128_000, 64_000]), "192k");
+    }
+}
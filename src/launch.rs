@@ -0,0 +1,158 @@
+//! `--open` and `--reveal`: hand the finished output to the desktop.
+//!
+//! Each platform knows the command that opens a file or shows it in the
+//! file manager; running it is separate so the choice can be tested
+//! anywhere.
+
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// A command line to launch, plus whether its exit status means anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Some launchers (Windows Explorer) exit non-zero even on success.
+    pub check_status: bool,
+}
+
+impl LaunchCommand {
+    fn new(program: &str, args: &[&str]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            check_status: true,
+        }
+    }
+
+    /// Runs the command to completion with its output discarded.
+    pub fn run(&self) -> io::Result<()> {
+        let status = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+        if self.check_status && !status.success() {
+            return Err(io::Error::other(format!(
+                "{} exited with {}",
+                self.program, status
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// How a desktop platform opens files.
+pub trait Platform {
+    /// Opens `path` in its default application.
+    fn open(&self, path: &Path) -> LaunchCommand;
+    /// Shows `path` in the file manager, selected where supported.
+    fn reveal(&self, path: &Path) -> LaunchCommand;
+}
+
+/// Linux and other freedesktop systems.
+pub struct Freedesktop;
+
+impl Platform for Freedesktop {
+    fn open(&self, path: &Path) -> LaunchCommand {
+        LaunchCommand::new("xdg-open", &[&path.to_string_lossy()])
+    }
+
+    /// There is no portable way to select a file, so this opens its folder.
+    fn reveal(&self, path: &Path) -> LaunchCommand {
+        LaunchCommand::new("xdg-open", &[&parent_dir(path)])
+    }
+}
+
+pub struct MacOs;
+
+impl Platform for MacOs {
+    fn open(&self, path: &Path) -> LaunchCommand {
+        LaunchCommand::new("open", &[&path.to_string_lossy()])
+    }
+
+    fn reveal(&self, path: &Path) -> LaunchCommand {
+        LaunchCommand::new("open", &["-R", &path.to_string_lossy()])
+    }
+}
+
+pub struct Windows;
+
+impl Platform for Windows {
+    fn open(&self, path: &Path) -> LaunchCommand {
+        // `start` treats its first quoted argument as the window title.
+        LaunchCommand::new("cmd", &["/C", "start", "", &path.to_string_lossy()])
+    }
+
+    fn reveal(&self, path: &Path) -> LaunchCommand {
+        LaunchCommand {
+            check_status: false,
+            ..LaunchCommand::new(
+                "explorer",
+                &[&format!("/select,{}", path.to_string_lossy())],
+            )
+        }
+    }
+}
+
+/// The platform this binary was built for.
+pub fn current() -> &'static dyn Platform {
+    if cfg!(target_os = "macos") {
+        &MacOs
+    } else if cfg!(windows) {
+        &Windows
+    } else {
+        &Freedesktop
+    }
+}
+
+fn parent_dir(path: &Path) -> String {
+    match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(parent) => parent.to_string_lossy().into_owned(),
+        None => ".".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &LaunchCommand) -> Vec<&str> {
+        std::iter::once(cmd.program.as_str())
+            .chain(cmd.args.iter().map(String::as_str))
+            .collect()
+    }
+
+    #[test]
+    fn test_open_commands() {
+        let path = Path::new("out/clip.mp4");
+        assert_eq!(args(&Freedesktop.open(path)), ["xdg-open", "out/clip.mp4"]);
+        assert_eq!(args(&MacOs.open(path)), ["open", "out/clip.mp4"]);
+        assert_eq!(
+            args(&Windows.open(path)),
+            ["cmd", "/C", "start", "", "out/clip.mp4"]
+        );
+    }
+
+    #[test]
+    fn test_reveal_commands() {
+        let path = Path::new("out/clip.mp4");
+        assert_eq!(args(&Freedesktop.reveal(path)), ["xdg-open", "out"]);
+        assert_eq!(
+            args(&Freedesktop.reveal(Path::new("clip.mp4"))),
+            ["xdg-open", "."]
+        );
+        assert_eq!(args(&MacOs.reveal(path)), ["open", "-R", "out/clip.mp4"]);
+        let explorer = Windows.reveal(path);
+        assert_eq!(args(&explorer), ["explorer", "/select,out/clip.mp4"]);
+        assert!(!explorer.check_status);
+    }
+
+    #[test]
+    fn test_launch_failures_are_errors() {
+        let missing = LaunchCommand::new("mdviqure-no-such-launcher", &[]);
+        assert!(missing.run().is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Core library behind the `mdviqure` command-line tool.
+//!
+//! The binary is a thin wrapper around [`cli::main`]; everything else lives in
+//! the modules below so it can be unit-tested without ffmpeg installed.
+
+pub mod accuracy;
+pub mod archive;
+pub mod aspect;
+pub mod audio;
+pub mod batch;
+pub mod breakdown;
+pub mod budget;
+pub mod capture;
+pub mod checksum;
+pub mod chunked;
+pub mod cli;
+pub mod commit;
+pub mod compare;
+pub mod config;
+pub mod console;
+pub mod container;
+pub mod coverart;
+pub mod datastream;
+pub mod deterministic;
+pub mod device;
+pub mod diskspace;
+pub mod effort;
+pub mod encoder;
+pub mod error;
+pub mod estimate;
+pub mod events;
+pub mod filename;
+pub mod filesystem;
+pub mod filetarget;
+pub mod filter;
+pub mod floor;
+pub mod fonts;
+pub mod history;
+pub mod hls;
+pub mod hwdecode;
+pub mod images;
+pub mod interactive;
+pub mod interrupt;
+pub mod ladder;
+pub mod launch;
+pub mod limit;
+pub mod longpath;
+pub mod looping;
+pub mod manifest;
+pub mod mp4box;
+pub mod notify;
+pub mod outdir;
+pub mod overhead;
+pub mod padding;
+pub mod passstats;
+pub mod planner;
+pub mod presenter;
+pub mod probe;
+pub mod probecache;
+pub mod process;
+pub mod profile;
+pub mod progress;
+pub mod prompt;
+pub mod provenance;
+pub mod ratecontrol;
+pub mod reduce;
+pub mod region;
+pub mod resume;
+pub mod session;
+pub mod sidecar;
+pub mod size;
+pub mod sizefmt;
+pub mod sleep;
+pub mod subtitles;
+pub mod summary;
+pub mod tempdir;
+pub mod template;
+pub mod terminal;
+pub mod timecode;
+pub mod tool;
+pub mod transient;
+pub mod twopass;
+pub mod unsupported;
+pub mod url;
+pub mod usage;
+pub mod validate;
+pub mod vertical;
+pub mod warning;
+pub mod window;
+
+#[cfg(test)]
+pub(crate) mod testing;
+
+/// Input/output path meaning stdin or stdout.
+pub const STDIO_PATH: &str = "-";
@@ -0,0 +1,173 @@
+//! Windows' `MAX_PATH` limit for the paths handed to ffmpeg and ffprobe.
+//!
+//! Rust's file APIs switch to extended-length (`\\?\`) paths by themselves
+//! when a path is too long, but the paths in a command line reach ffmpeg as
+//! plain strings, and it opens them with the classic 260-character limit.
+//! [`for_tool`] makes such paths absolute and extended before they go into
+//! an argument list. Everywhere else it is the identity.
+//!
+//! The names this tool derives itself stay short: run directories are
+//! `mdviqure-<pid>-<token>` and the artifacts in them `partial-<token>.<ext>`
+//! and the like, so only a long temp or output directory can push a path past
+//! the limit.
+
+/// The classic Windows path limit, in UTF-16 units.
+pub const MAX_PATH: usize = 260;
+
+/// Paths from this length on are extended: directories must leave room for
+/// an 8.3 file name, so `CreateDirectoryW` already gives up 12 units earlier.
+const LIMIT: usize = MAX_PATH - 12;
+
+/// The extended-length form of the absolute Windows path `path` when it is
+/// too long for the classic APIs: `\\?\C:\...` for drive paths and
+/// `\\?\UNC\server\share\...` for network shares. `.` and `..` are resolved
+/// and `/` turned into `\`, as the extended form takes names literally.
+///
+/// Short, relative and already extended paths come back unchanged.
+pub fn extended(path: &str) -> String {
+    if path.encode_utf16().count() < LIMIT || path.starts_with(r"\\?\") || path.starts_with(r"\\.\")
+    {
+        return path.to_string();
+    }
+    let is_separator = |c: char| c == '\\' || c == '/';
+    let bytes = path.as_bytes();
+    let (prefix, rest) = if bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && is_separator(bytes[2] as char)
+    {
+        (format!(r"\\?\{}\", &path[..2]), &path[3..])
+    } else if path.starts_with(r"\\") || path.starts_with("//") {
+        let mut parts = path[2..].splitn(3, is_separator);
+        match (parts.next(), parts.next()) {
+            (Some(server), Some(share)) if !server.is_empty() && !share.is_empty() => (
+                format!(r"\\?\UNC\{}\{}\", server, share),
+                parts.next().unwrap_or(""),
+            ),
+            _ => return path.to_string(),
+        }
+    } else {
+        return path.to_string();
+    };
+
+    let mut names: Vec<&str> = Vec::new();
+    for name in rest.split(is_separator) {
+        match name {
+            "" | "." => {}
+            ".." => {
+                names.pop();
+            }
+            name => names.push(name),
+        }
+    }
+    prefix + &names.join(r"\")
+}
+
+/// Whether `arg` is an ffmpeg protocol URL (`pipe:1`, `https://...`)
+/// rather than a file path. A drive letter is one character, so `C:\...`
+/// doesn't count.
+pub fn is_protocol(arg: &str) -> bool {
+    match arg.split_once(':') {
+        Some((scheme, _)) => scheme.len() > 1 && scheme.chars().all(|c| c.is_ascii_alphanumeric()),
+        None => false,
+    }
+}
+
+/// `path` as it should appear in an ffmpeg or ffprobe command line: on
+/// Windows, absolute and extended when it would be too long otherwise.
+#[cfg(windows)]
+pub fn for_tool(path: &str) -> String {
+    if path == crate::STDIO_PATH || is_protocol(path) {
+        return path.to_string();
+    }
+    match std::path::absolute(path) {
+        Ok(absolute) if absolute.as_os_str().len() >= LIMIT => {
+            extended(&absolute.to_string_lossy())
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// `path` as it should appear in an ffmpeg or ffprobe command line; only
+/// Windows needs any changes.
+#[cfg(not(windows))]
+pub fn for_tool(path: &str) -> String {
+    path.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A drive path of at least `len` characters.
+    fn long_path(root: &str, len: usize) -> String {
+        let mut path = root.to_string();
+        while path.len() < len {
+            path.push_str(r"\0123456789");
+        }
+        path + r"\clip.mp4"
+    }
+
+    #[test]
+    fn test_short_and_relative_paths_are_unchanged() {
+        assert_eq!(extended(r"C:\videos\clip.mp4"), r"C:\videos\clip.mp4");
+        let relative = long_path("videos", 300);
+        assert_eq!(extended(&relative), relative);
+        let verbatim = format!(r"\\?\{}", long_path("C:", 300));
+        assert_eq!(extended(&verbatim), verbatim);
+    }
+
+    #[test]
+    fn test_long_drive_paths_are_extended() {
+        let path = long_path("C:", 300);
+        let extended_path = extended(&path);
+        assert_eq!(extended_path, format!(r"\\?\{}", path));
+
+        let messy = format!(r"D:/work/./skip/..\{}", &long_path("x", 300)[2..]);
+        let clean = extended(&messy);
+        assert!(clean.starts_with(r"\\?\D:\work\"), "{}", clean);
+        assert!(!clean.contains('/') && !clean.contains(r"\.\") && !clean.contains("skip"));
+        assert!(clean.ends_with(r"\clip.mp4"));
+    }
+
+    #[test]
+    fn test_long_unc_paths_are_extended() {
+        let path = long_path(r"\\nas\media", 300);
+        assert_eq!(
+            extended(&path),
+            format!(r"\\?\UNC\nas\media{}", &path[r"\\nas\media".len()..])
+        );
+        let no_share = format!(r"\\nas{}", "x".repeat(300));
+        assert_eq!(extended(&no_share), no_share);
+    }
+
+    #[test]
+    fn test_protocols_are_not_paths() {
+        assert!(is_protocol("pipe:1"));
+        assert!(is_protocol("https://example.com/a.mp4"));
+        assert!(!is_protocol(r"C:\videos\clip.mp4"));
+        assert!(!is_protocol("clip.mp4"));
+        assert!(!is_protocol("-"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_for_tool_extends_long_relative_paths() {
+        let cwd = std::env::current_dir().unwrap();
+        let relative = "a".repeat(MAX_PATH) + ".mp4";
+        let arg = for_tool(&relative);
+        assert!(arg.starts_with(r"\\?\"), "{}", arg);
+        assert!(arg.ends_with(&relative));
+        assert!(arg.contains(&*cwd.file_name().unwrap().to_string_lossy()));
+        assert_eq!(for_tool("clip.mp4"), "clip.mp4");
+        assert_eq!(for_tool("pipe:1"), "pipe:1");
+        assert_eq!(for_tool("-"), "-");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_for_tool_is_the_identity_elsewhere() {
+        let long = "/".to_string() + &"a/".repeat(200);
+        assert_eq!(for_tool(&long), long);
+    }
+}
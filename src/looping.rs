@@ -0,0 +1,114 @@
+//! `--loop-safe`: outputs that play back seamlessly on repeat, as
+//! backgrounds do.
+//!
+//! A player looping a clip jumps from its end back to its first frame.
+//! That only goes unnoticed when the first frame is a keyframe, the audio
+//! ends with the video instead of running on or stopping short, and the
+//! container doesn't ask for part of the start to be skipped. MP4 muxers
+//! write such an edit list to hide the delay of B-frames and of the audio
+//! encoder, and players differ in whether they honor it on a loop. The
+//! encode gets [`video_args`], [`audio_args`] and, into MP4 and MOV,
+//! [`muxer_args`] and [`MOVFLAGS`]; [`verify`] then checks what came out.
+
+use crate::presenter::Presenter;
+use crate::tool::VideoTool;
+use crate::warning::{self, Code, Warning};
+
+/// Video timestamps that may go below zero instead of an edit list.
+pub const MOVFLAGS: &str = "negative_cts_offsets";
+
+/// A keyframe at the first frame, whatever the encoder's GOP would do.
+pub fn video_args() -> Vec<String> {
+    vec!["-force_key_frames".to_string(), "0".to_string()]
+}
+
+/// Audio padded with silence or cut so it lasts exactly `length`
+/// seconds, the length of the video; `-shortest` stops it there too.
+pub fn audio_args(length: f64) -> Vec<String> {
+    vec![
+        "-filter:a".to_string(),
+        format!("apad,atrim=end={:.6}", length),
+    ]
+}
+
+/// MP4 and MOV muxer options: no edit list.
+pub fn muxer_args() -> Vec<String> {
+    vec!["-use_editlist".to_string(), "0".to_string()]
+}
+
+/// Why an output whose video lasts `video` seconds, the whole file
+/// `total` and its audio tracks `audio` won't loop cleanly at `fps`, or
+/// `None` when every track ends within a frame of the video.
+pub fn mismatch(video: f64, total: f64, audio: &[f64], fps: f64) -> Option<String> {
+    let frame = 1.0 / fps;
+    let off = |length: f64| (length - video).abs() > frame;
+    if off(total) {
+        return Some(format!(
+            "the output lasts {:.3}s but its video {:.3}s",
+            total, video
+        ));
+    }
+    let (track, length) = audio.iter().enumerate().find(|&(_, &length)| off(length))?;
+    Some(format!(
+        "audio track {} lasts {:.3}s but the video {:.3}s",
+        track + 1,
+        length,
+        video
+    ))
+}
+
+/// Probes `output`, a `--loop-safe` encode at `fps`, and warns when its
+/// tracks don't end together. An output that can't be probed, or whose
+/// video length isn't known, isn't checked.
+pub fn verify<T: VideoTool>(tool: &T, output: &str, fps: f64, out: Presenter) {
+    let (Ok(info), Ok(total)) = (tool.get_video_info(output), tool.get_video_duration(output))
+    else {
+        return;
+    };
+    let Some(video) = info.stream_duration() else {
+        return;
+    };
+    let audio: Vec<f64> = info
+        .audio_streams
+        .iter()
+        .flatten()
+        .filter_map(|a| a.duration.as_deref()?.parse().ok())
+        .collect();
+    if let Some(problem) = mismatch(video, total, &audio, fps) {
+        warning::emit(
+            out,
+            Warning::new(
+                Code::LoopNotSeamless,
+                format!("{}, so it won't loop seamlessly", problem),
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracks_within_a_frame_of_the_video_loop() {
+        // 10 s at 30 fps, with audio a little long from AAC priming.
+        assert_eq!(mismatch(10.0, 10.021, &[10.021], 30.0), None);
+        assert_eq!(mismatch(10.0, 10.0, &[], 30.0), None);
+        assert_eq!(
+            mismatch(10.0, 10.5, &[10.5], 30.0).as_deref(),
+            Some("the output lasts 10.500s but its video 10.000s")
+        );
+        // Audio that stops short doesn't show in the file's length.
+        assert_eq!(
+            mismatch(10.0, 10.0, &[10.0, 9.9], 30.0).as_deref(),
+            Some("audio track 2 lasts 9.900s but the video 10.000s")
+        );
+        // A frame at 60 fps is shorter than one at 30.
+        assert!(mismatch(10.0, 10.03, &[], 60.0).is_some());
+    }
+
+    #[test]
+    fn test_audio_is_cut_to_the_video() {
+        assert_eq!(audio_args(12.5), ["-filter:a", "apad,atrim=end=12.500000"]);
+    }
+}
@@ -0,0 +1,493 @@
+//! `mdviqure batch --manifest`: a batch whose files each have their own
+//! output and settings, read from a TOML file, or from a JSON one.
+//!
+//! ```toml
+//! [defaults]
+//! target = "25MB"
+//! codec = "svt-av1"
+//!
+//! [[jobs]]
+//! name = "intro"
+//! input = "raw/intro.mov"
+//! output = "out/intro.mp4"
+//!
+//! [[jobs]]
+//! input = "raw/talk.mov"
+//! output = "out/talk.mp4"
+//! target = "100MB"
+//! preset = "slow"
+//! ```
+//!
+//! A file is read as TOML when its name ends in `.toml`, and as JSON
+//! otherwise ([`Format::of`]); both have the same fields. A job's settings fall back to the manifest's `defaults`, and those to the
+//! command line. Relative paths are taken from the manifest's directory.
+//! Every entry is checked before anything is encoded, and each problem
+//! names the entry it is in.
+
+use crate::batch::Job;
+use crate::effort;
+use crate::encoder::{Preset, VideoEncoder};
+use crate::error::ReduceError;
+use crate::reduce::{sample_output_path, ReduceOptions};
+use crate::size::parse_size;
+use crate::url;
+use crate::STDIO_PATH;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Settings a job may give, each overriding the command line's.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Target size, as for `--size`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Video encoder, by its `--codec` name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// Encoder preset, by its `--preset` name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    /// `--effort` level, which the preset wins over.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effort: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_audio: Option<bool>,
+}
+
+impl Settings {
+    /// `self` with the settings `over` gives replaced.
+    fn merged(&self, over: &Settings) -> Settings {
+        Settings {
+            target: over.target.clone().or_else(|| self.target.clone()),
+            codec: over.codec.clone().or_else(|| self.codec.clone()),
+            preset: over.preset.clone().or_else(|| self.preset.clone()),
+            effort: over.effort.or(self.effort),
+            max_width: over.max_width.or(self.max_width),
+            max_height: over.max_height.or(self.max_height),
+            no_audio: over.no_audio.or(self.no_audio),
+        }
+    }
+
+    /// `base` with these settings applied.
+    fn apply(&self, base: &ReduceOptions) -> Result<ReduceOptions, String> {
+        let mut opts = base.clone();
+        if let Some(target) = &self.target {
+            opts.target_bytes =
+                parse_size(target, opts.size_units).map_err(|e| format!("target: {}", e))?;
+        }
+        if let Some(codec) = &self.codec {
+            opts.encoder = VideoEncoder::from_str(codec, true)
+                .map_err(|_| format!("unknown codec '{}'", codec))?;
+            if opts
+                .encoder_params
+                .as_ref()
+                .is_some_and(|p| p.encoder != opts.encoder)
+            {
+                opts.encoder_params = None;
+            }
+        }
+        if let Some(level) = self.effort {
+            if !(effort::MIN..=effort::MAX).contains(&level) {
+                return Err(format!(
+                    "effort must be from {} to {}",
+                    effort::MIN,
+                    effort::MAX
+                ));
+            }
+            opts.effort = Some(level);
+            opts.preset = effort::preset(level);
+        }
+        if let Some(preset) = &self.preset {
+            opts.preset = Preset::from_str(preset, true)
+                .map_err(|_| format!("unknown preset '{}'", preset))?;
+        }
+        if self.max_width.is_some() {
+            opts.max_width = self.max_width;
+        }
+        if self.max_height.is_some() {
+            opts.max_height = self.max_height;
+        }
+        if let Some(no_audio) = self.no_audio {
+            opts.no_audio = no_audio;
+        }
+        Ok(opts)
+    }
+}
+
+/// One job of a manifest.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    /// What the progress and the summary call the job; its input without.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub input: String,
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effort: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_audio: Option<bool>,
+}
+
+impl Entry {
+    fn settings(&self) -> Settings {
+        Settings {
+            target: self.target.clone(),
+            codec: self.codec.clone(),
+            preset: self.preset.clone(),
+            effort: self.effort,
+            max_width: self.max_width,
+            max_height: self.max_height,
+            no_audio: self.no_audio,
+        }
+    }
+}
+
+/// How a manifest is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    /// The format of the manifest at `path`, by its extension: TOML for
+    /// `.toml`, JSON for anything else, as manifests were before TOML.
+    pub fn of(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => Format::Toml,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// A manifest file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub defaults: Settings,
+    pub jobs: Vec<Entry>,
+}
+
+impl Manifest {
+    /// Parses a manifest written in `format`. The jobs are read one by
+    /// one, so a schema error says which of them it is in.
+    pub fn parse(text: &str, format: Format) -> Result<Self, String> {
+        let value: serde_json::Value = match format {
+            Format::Json => serde_json::from_str(text).map_err(|e| e.to_string())?,
+            // TOML's errors quote the line they are on, after a line of
+            // their own.
+            Format::Toml => toml::from_str(text).map_err(|e| e.to_string().trim().to_string())?,
+        };
+        let serde_json::Value::Object(mut fields) = value else {
+            return Err("expected an object with \"jobs\"".into());
+        };
+        let jobs = match fields.remove("jobs") {
+            Some(serde_json::Value::Array(jobs)) => jobs,
+            Some(_) => return Err("\"jobs\" must be a list".into()),
+            None => return Err("missing field `jobs`".into()),
+        };
+        let top: Manifest = serde_json::from_value(serde_json::json!({
+            "defaults": fields.remove("defaults").unwrap_or_else(|| serde_json::json!({})),
+            "jobs": [],
+        }))
+        .map_err(|e| format!("defaults: {}", e))?;
+        if let Some(unknown) = fields.keys().next() {
+            return Err(format!(
+                "unknown field `{}`, expected `defaults` or `jobs`",
+                unknown
+            ));
+        }
+        let jobs = jobs
+            .into_iter()
+            .enumerate()
+            .map(|(i, job)| {
+                let name = job.get("name").and_then(|n| n.as_str()).map(str::to_string);
+                serde_json::from_value(job)
+                    .map_err(|e| format!("{}: {}", describe(i, name.as_deref()), e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            defaults: top.defaults,
+            jobs,
+        })
+    }
+
+    /// Reads and parses the manifest at `path`, in the format its name
+    /// gives.
+    pub fn load(path: &Path) -> Result<Self, ReduceError> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            ReduceError::Usage(format!("cannot read manifest {}: {}", path.display(), e))
+        })?;
+        Self::parse(&text, Format::of(path))
+            .map_err(|e| ReduceError::Usage(format!("{}: {}", path.display(), e)))
+    }
+
+    /// The batch's jobs, with their options on top of `base` and their
+    /// paths taken from `dir`. Fails listing every problem found: settings
+    /// that don't parse, inputs that don't exist, and outputs or names
+    /// given twice.
+    pub fn jobs(&self, base: &ReduceOptions, dir: &Path) -> Result<Vec<Job>, Vec<String>> {
+        let mut problems = Vec::new();
+        let mut jobs = Vec::with_capacity(self.jobs.len());
+        let mut outputs: HashMap<String, usize> = HashMap::new();
+        let mut names: HashMap<&str, usize> = HashMap::new();
+        for (i, entry) in self.jobs.iter().enumerate() {
+            let which = describe(i, entry.name.as_deref());
+            let mut problem = |text: String| problems.push(format!("{}: {}", which, text));
+            if let Some(name) = &entry.name {
+                if let Some(first) = names.insert(name.as_str(), i) {
+                    problem(format!("the name is taken by job {}", first + 1));
+                }
+            }
+            let input = resolve(dir, &entry.input);
+            if entry.input == STDIO_PATH {
+                problem("batch mode cannot read from stdin".into());
+            } else if entry.input.is_empty() {
+                problem("no input".into());
+            } else if !url::is_url(&input) && !Path::new(&input).exists() {
+                problem(format!("no such input {}", input));
+            }
+            let mut output = resolve(dir, &entry.output);
+            if entry.output.is_empty() || entry.output == STDIO_PATH {
+                problem("the output must be a file".into());
+            }
+            if base.sample.is_some() {
+                output = sample_output_path(&output);
+            }
+            if let Some(first) = outputs.insert(output.clone(), i) {
+                problem(format!("job {} writes to {} already", first + 1, output));
+            }
+            let opts = match self.defaults.merged(&entry.settings()).apply(base) {
+                Ok(opts) => opts,
+                Err(e) => {
+                    problem(e);
+                    continue;
+                }
+            };
+            jobs.push(Job {
+                label: entry.name.clone().unwrap_or_else(|| entry.input.clone()),
+                input,
+                output,
+                opts,
+                invalid: None,
+            });
+        }
+        if problems.is_empty() {
+            Ok(jobs)
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+/// How messages name job `index`: by its number from 1, and its name.
+fn describe(index: usize, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("job {} ({})", index + 1, name),
+        None => format!("job {}", index + 1),
+    }
+}
+
+/// `path` from the manifest, taken from `dir` when it is relative.
+fn resolve(dir: &Path, path: &str) -> String {
+    if path.is_empty() || path == STDIO_PATH || url::is_url(path) || Path::new(path).is_absolute() {
+        path.to_string()
+    } else {
+        dir.join(path).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{mib, TestDir};
+
+    const MANIFEST: &str = r#"{
+        "defaults": { "target": "25MiB", "codec": "svt-av1", "effort": 8 },
+        "jobs": [
+            { "name": "intro", "input": "intro.mov", "output": "out/intro.mp4" },
+            { "input": "talk.mov", "output": "out/talk.mp4", "target": "100MiB",
+              "codec": "h264", "preset": "slow", "no_audio": true }
+        ]
+    }"#;
+
+    /// [`MANIFEST`] in TOML.
+    const MANIFEST_TOML: &str = r#"
+        [defaults]
+        target = "25MiB"
+        codec = "svt-av1"
+        effort = 8
+
+        [[jobs]]
+        name = "intro"
+        input = "intro.mov"
+        output = "out/intro.mp4"
+
+        [[jobs]]
+        input = "talk.mov"
+        output = "out/talk.mp4"
+        target = "100MiB"
+        codec = "h264"
+        preset = "slow"
+        no_audio = true
+    "#;
+
+    #[test]
+    fn test_manifest_round_trips() {
+        let manifest = Manifest::parse(MANIFEST, Format::Json).unwrap();
+        assert_eq!(manifest.defaults.target.as_deref(), Some("25MiB"));
+        assert_eq!(manifest.jobs.len(), 2);
+        assert_eq!(manifest.jobs[0].name.as_deref(), Some("intro"));
+        assert_eq!(manifest.jobs[1].preset.as_deref(), Some("slow"));
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert_eq!(Manifest::parse(&json, Format::Json).unwrap(), manifest);
+        // Unset settings are left out, not written as null.
+        assert!(!json.contains("null"), "{}", json);
+
+        assert_eq!(
+            Manifest::parse(MANIFEST_TOML, Format::Toml).unwrap(),
+            manifest
+        );
+        let toml = toml::to_string(&manifest).unwrap();
+        assert_eq!(Manifest::parse(&toml, Format::Toml).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_the_extension_picks_the_format() {
+        // (file name, format)
+        let cases = [
+            ("jobs.toml", Format::Toml),
+            ("JOBS.TOML", Format::Toml),
+            ("jobs.json", Format::Json),
+            ("jobs", Format::Json),
+            ("toml", Format::Json),
+        ];
+        for (name, format) in cases {
+            assert_eq!(Format::of(Path::new(name)), format, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_jobs_fall_back_to_the_defaults_and_the_command_line() {
+        let dir = TestDir::new();
+        for name in ["intro.mov", "talk.mov"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let manifest = Manifest::parse(MANIFEST, Format::Json).unwrap();
+        let mut base = ReduceOptions::new(mib(50));
+        base.max_height = Some(720);
+        let jobs = manifest.jobs(&base, dir.path()).unwrap();
+
+        assert_eq!(jobs[0].label, "intro");
+        assert_eq!(jobs[0].input, dir.join("intro.mov"));
+        assert_eq!(jobs[0].output, dir.join("out/intro.mp4"));
+        assert_eq!(jobs[0].opts.target_bytes, mib(25));
+        assert_eq!(jobs[0].opts.encoder, VideoEncoder::SvtAv1);
+        assert_eq!(jobs[0].opts.max_height, Some(720));
+        assert_eq!(jobs[0].opts.preset, Preset::Slower);
+
+        assert_eq!(jobs[1].label, "talk.mov");
+        assert_eq!(jobs[1].opts.target_bytes, mib(100));
+        assert_eq!(jobs[1].opts.encoder, VideoEncoder::H264);
+        assert_eq!(jobs[1].opts.preset, Preset::Slow);
+        assert!(jobs[1].opts.no_audio);
+    }
+
+    #[test]
+    fn test_schema_errors_name_the_entry() {
+        let err = |text: &str| Manifest::parse(text, Format::Json).unwrap_err();
+        let typo = r#"{"jobs": [{"input": "a.mp4", "output": "b.mp4"},
+                                {"name": "talk", "input": "t.mp4", "output": "o.mp4", "codex": "h265"}]}"#;
+        let message = err(typo);
+        assert!(
+            message.starts_with("job 2 (talk): unknown field `codex`"),
+            "{}",
+            message
+        );
+        let message = err(r#"{"jobs": [{"input": "a.mp4"}]}"#);
+        assert!(
+            message.starts_with("job 1: missing field `output`"),
+            "{}",
+            message
+        );
+        let message = err(r#"{"defaults": {"target": 25}, "jobs": []}"#);
+        assert!(message.starts_with("defaults: invalid type"), "{}", message);
+        assert!(err(r#"{"job": []}"#).contains("missing field `jobs`"));
+        assert!(err(r#"{"jobs": [], "default": {}}"#).contains("unknown field `default`"));
+        assert!(err("[]").contains("expected an object"));
+
+        // TOML is held to the same schema.
+        let err = |text: &str| Manifest::parse(text, Format::Toml).unwrap_err();
+        let typo = "[[jobs]]\ninput = \"a.mp4\"\noutput = \"b.mp4\"\n\n\
+                    [[jobs]]\nname = \"talk\"\ninput = \"t.mp4\"\noutput = \"o.mp4\"\ncodex = \"h265\"\n";
+        let message = err(typo);
+        assert!(
+            message.starts_with("job 2 (talk): unknown field `codex`"),
+            "{}",
+            message
+        );
+        let message =
+            err("[defaults]\ntarget = 25\n\n[[jobs]]\ninput = \"a.mp4\"\noutput = \"b.mp4\"");
+        assert!(message.starts_with("defaults: invalid type"), "{}", message);
+        assert!(err("jobs = []\ndefault = {}").contains("unknown field `default`"));
+        assert!(err("job = []").contains("missing field `jobs`"));
+        // A syntax error says where it is.
+        let message = err("[[jobs]]\ninput = \"a.mp4");
+        assert!(message.contains("line 2"), "{}", message);
+    }
+
+    #[test]
+    fn test_every_problem_is_found_before_encoding() {
+        let dir = TestDir::new();
+        std::fs::write(dir.join("a.mov"), b"").unwrap();
+        let manifest = Manifest::parse(
+            r#"{"jobs": [
+                {"name": "a", "input": "a.mov", "output": "same.mp4"},
+                {"name": "a", "input": "missing.mov", "output": "same.mp4"},
+                {"input": "a.mov", "output": "c.mp4", "target": "lots", "codec": "vp9"}
+            ]}"#,
+            Format::Json,
+        )
+        .unwrap();
+        let problems = manifest
+            .jobs(&ReduceOptions::new(mib(50)), dir.path())
+            .unwrap_err();
+        assert_eq!(
+            problems[..3],
+            [
+                "job 2 (a): the name is taken by job 1".to_string(),
+                format!("job 2 (a): no such input {}", dir.join("missing.mov")),
+                format!(
+                    "job 2 (a): job 1 writes to {} already",
+                    dir.join("same.mp4")
+                ),
+            ]
+        );
+        // The first bad setting of a job is reported.
+        assert_eq!(problems.len(), 4);
+        assert!(
+            problems[3].starts_with("job 3: target: invalid size 'lots'"),
+            "{}",
+            problems[3]
+        );
+    }
+}
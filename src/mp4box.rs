@@ -0,0 +1,347 @@
+//! Reading an MP4's durations from its own boxes, for when ffprobe can't
+//! be run.
+//!
+//! Some minimal installs ship ffmpeg without ffprobe. For the common MP4
+//! and MOV input the length, which the bitrate math can't do without, is
+//! written in the movie header (`moov/mvhd`) and that of each track in its
+//! media header (`trak/mdia/mdhd`), so [`read_file`] finds them without
+//! decoding anything. What else ffprobe would tell, the frame size, rate
+//! and audio layout, is assumed ([`Movie::video_info`]). A fragmented MP4
+//! keeps its durations in the fragments instead and is refused, as is any
+//! file without an MP4 extension ([`reads`]), whatever its contents.
+
+use crate::probe::{AudioStream, VideoInfo};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Extensions of the files read here.
+const EXTENSIONS: &[&str] = &["mp4", "m4v", "mov"];
+
+/// The frame size taken for a file read here, the most common one.
+pub const ASSUMED_SIZE: (u32, u32) = (1920, 1080);
+
+/// A larger `moov` than this isn't read into memory; hours of video take a
+/// few megabytes.
+const MAX_MOOV_BYTES: u64 = 256 << 20;
+
+/// What kind of media a track holds, from its handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Video,
+    Audio,
+    Other,
+}
+
+/// One `trak` of a movie.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    pub kind: Kind,
+    /// Seconds; `None` when the header leaves it unknown.
+    pub duration: Option<f64>,
+}
+
+/// The durations a movie's headers record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    /// Seconds, from `mvhd`: the longest track's.
+    pub duration: f64,
+    /// In file order, which is ffprobe's stream order.
+    pub tracks: Vec<Track>,
+}
+
+/// Whether `path` has an extension of the files read here.
+pub fn reads(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+/// Reads the durations of the MP4 at `path`.
+pub fn read_file(path: &Path) -> Result<Movie, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    read(&mut file)
+}
+
+/// Reads the durations of an MP4 from its top-level boxes, skipping over
+/// the media data to the `moov` box wherever it is.
+pub fn read(file: &mut (impl Read + Seek)) -> Result<Movie, String> {
+    let end = file.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+    let mut at = file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+    while at < end {
+        let (kind, header, size) = read_header(file, end - at)?;
+        match &kind {
+            b"moof" => return Err(FRAGMENTED.to_string()),
+            b"moov" => {
+                if size > MAX_MOOV_BYTES {
+                    return Err(format!("its moov box is too large ({} bytes)", size));
+                }
+                let mut moov = vec![0; (size - header) as usize];
+                file.read_exact(&mut moov).map_err(|e| e.to_string())?;
+                return parse_moov(&moov);
+            }
+            _ => {}
+        }
+        at = file
+            .seek(SeekFrom::Start(at + size))
+            .map_err(|e| e.to_string())?;
+    }
+    Err("it has no moov box; it may not be an MP4".to_string())
+}
+
+const FRAGMENTED: &str = "it is a fragmented MP4, whose durations are only in its fragments";
+
+/// Reads a box header at the current position, with at most `left` bytes
+/// of the file left: the box type, the header's length and the box's
+/// whole length. A size of 1 is followed by a 64-bit one; 0 runs to the
+/// end of the file.
+fn read_header(file: &mut impl Read, left: u64) -> Result<([u8; 4], u64, u64), String> {
+    let truncated = |e: io::Error| format!("a box header is cut off: {}", e);
+    let mut header = [0; 8];
+    file.read_exact(&mut header).map_err(truncated)?;
+    let kind = [header[4], header[5], header[6], header[7]];
+    let (size, length) = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+        0 => (left, 8),
+        1 => {
+            let mut large = [0; 8];
+            file.read_exact(&mut large).map_err(truncated)?;
+            (u64::from_be_bytes(large), 16)
+        }
+        size => (u64::from(size), 8),
+    };
+    if size < length || size > left {
+        return Err(format!(
+            "its {} box claims {} bytes, but {} are left",
+            String::from_utf8_lossy(&kind),
+            size,
+            left
+        ));
+    }
+    Ok((kind, length, size))
+}
+
+/// A box read into memory, as (type, body).
+type Boxed<'a> = ([u8; 4], &'a [u8]);
+
+/// The child boxes of a box body.
+fn children(mut data: &[u8]) -> Result<Vec<Boxed<'_>>, String> {
+    let mut boxes = Vec::new();
+    while !data.is_empty() {
+        let mut reader = data;
+        let (kind, header, size) = read_header(&mut reader, data.len() as u64)?;
+        boxes.push((kind, &data[header as usize..size as usize]));
+        data = &data[size as usize..];
+    }
+    Ok(boxes)
+}
+
+fn child<'a>(boxes: &[Boxed<'a>], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes.iter().find(|(k, _)| k == kind).map(|&(_, body)| body)
+}
+
+fn parse_moov(moov: &[u8]) -> Result<Movie, String> {
+    let boxes = children(moov)?;
+    if child(&boxes, b"mvex").is_some() {
+        return Err(FRAGMENTED.to_string());
+    }
+    let mvhd = child(&boxes, b"mvhd").ok_or("its moov box has no mvhd")?;
+    let duration = header_duration(mvhd)
+        .ok_or("its mvhd box is cut off")?
+        .ok_or("its mvhd box leaves the duration unknown")?;
+    let tracks = boxes
+        .iter()
+        .filter(|(kind, _)| kind == b"trak")
+        .map(|&(_, trak)| parse_trak(trak))
+        .collect::<Result<_, _>>()?;
+    Ok(Movie { duration, tracks })
+}
+
+fn parse_trak(trak: &[u8]) -> Result<Track, String> {
+    let mdia = child(&children(trak)?, b"mdia").ok_or("a trak box has no mdia")?;
+    let boxes = children(mdia)?;
+    let kind = match child(&boxes, b"hdlr").and_then(|hdlr| hdlr.get(8..12)) {
+        Some(b"vide") => Kind::Video,
+        Some(b"soun") => Kind::Audio,
+        _ => Kind::Other,
+    };
+    let duration = child(&boxes, b"mdhd").and_then(header_duration).flatten();
+    Ok(Track { kind, duration })
+}
+
+/// The duration in seconds of an `mvhd` or `mdhd` box body, which lay out
+/// their timescale and duration alike: `None` when the body is cut off,
+/// `Some(None)` when the duration is unknown (all ones) or the timescale
+/// zero. Version 1 has 64-bit times and duration.
+fn header_duration(body: &[u8]) -> Option<Option<f64>> {
+    let field = |at: usize, len: usize| -> Option<u64> {
+        let bytes = body.get(at..at + len)?;
+        Some(bytes.iter().fold(0, |n, &b| n << 8 | u64::from(b)))
+    };
+    let (timescale, duration, unknown) = match body.first()? {
+        1 => (field(20, 4)?, field(24, 8)?, u64::MAX),
+        _ => (field(12, 4)?, field(16, 4)?, u64::from(u32::MAX)),
+    };
+    Some((timescale > 0 && duration != unknown).then(|| duration as f64 / timescale as f64))
+}
+
+impl Movie {
+    /// What ffprobe would report of the first video track, with the
+    /// durations from the headers and the rest assumed: a frame of
+    /// [`ASSUMED_SIZE`], no frame rate (so the default is used) and audio
+    /// tracks of unknown layout, taken as stereo.
+    pub fn video_info(&self) -> Result<VideoInfo, String> {
+        let (index, video) = self
+            .tracks
+            .iter()
+            .enumerate()
+            .find(|(_, track)| track.kind == Kind::Video)
+            .ok_or("it has no video track")?;
+        let audio = self
+            .tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, track)| track.kind == Kind::Audio)
+            .map(|(index, track)| AudioStream {
+                index: index as u32,
+                duration: track.duration.map(|d| d.to_string()),
+                ..AudioStream::default()
+            })
+            .collect();
+        Ok(VideoInfo {
+            index: index as u32,
+            width: ASSUMED_SIZE.0,
+            height: ASSUMED_SIZE.1,
+            duration: video.duration.map(|d| d.to_string()),
+            audio_streams: Some(audio),
+            from_headers: true,
+            ..VideoInfo::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mp4::{boxed, header, header_v1, trak};
+    use std::io::Cursor;
+
+    /// A movie of 12.5 s of video at a 90 kHz timescale and 12.48 s of
+    /// AAC at 48 kHz, its moov after the media data, as cameras write it.
+    fn camera_file() -> Vec<u8> {
+        let moov = [
+            boxed(b"mvhd", &header(1000, 12_500)),
+            trak(b"vide", &header(90_000, 1_125_000)),
+            trak(b"soun", &header(48_000, 599_040)),
+        ]
+        .concat();
+        [
+            boxed(b"ftyp", b"isom\0\0\x02\0isomiso2avc1mp41"),
+            boxed(b"mdat", &[0xAB; 4096]),
+            boxed(b"moov", &moov),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_durations_come_from_mvhd_and_each_mdhd() {
+        let movie = read(&mut Cursor::new(camera_file())).unwrap();
+        assert_eq!(movie.duration, 12.5);
+        assert_eq!(
+            movie.tracks,
+            [
+                Track {
+                    kind: Kind::Video,
+                    duration: Some(12.5)
+                },
+                Track {
+                    kind: Kind::Audio,
+                    duration: Some(12.48)
+                },
+            ]
+        );
+        let info = movie.video_info().unwrap();
+        assert_eq!((info.index, info.width, info.height), (0, 1920, 1080));
+        assert!(info.from_headers);
+        assert_eq!(info.stream_duration(), Some(12.5));
+        let audio = info.audio_streams.unwrap();
+        assert_eq!(audio.len(), 1);
+        assert_eq!((audio[0].index, audio[0].channels), (1, None));
+    }
+
+    #[test]
+    fn test_64_bit_durations_and_box_sizes() {
+        // Version 1 headers, for a 30-hour recording at a 1 GHz timescale.
+        let moov = [
+            boxed(b"mvhd", &header_v1(1_000_000_000, 108_000_000_000_000)),
+            trak(b"vide", &header_v1(1_000_000_000, 108_000_000_000_000)),
+        ]
+        .concat();
+        // An mdat with a 64-bit size, and a moov running to the end of the
+        // file (size 0).
+        let mut mdat = 1u32.to_be_bytes().to_vec();
+        mdat.extend(b"mdat");
+        mdat.extend(24u64.to_be_bytes());
+        mdat.extend([0; 8]);
+        let mut moov_box = 0u32.to_be_bytes().to_vec();
+        moov_box.extend(b"moov");
+        moov_box.extend(&moov);
+        let movie = read(&mut Cursor::new([mdat, moov_box].concat())).unwrap();
+        assert_eq!(movie.duration, 108_000.0);
+        assert_eq!(movie.tracks[0].duration, Some(108_000.0));
+    }
+
+    #[test]
+    fn test_fragmented_and_broken_files_are_refused() {
+        let fragmented = [
+            boxed(b"ftyp", b"iso5"),
+            boxed(
+                b"moov",
+                &[boxed(b"mvhd", &header(1000, 0)), boxed(b"mvex", &[])].concat(),
+            ),
+            boxed(b"moof", &[]),
+        ]
+        .concat();
+        let refused = |data: Vec<u8>| read(&mut Cursor::new(data)).unwrap_err();
+        assert!(refused(fragmented).contains("fragmented"));
+        assert!(refused([boxed(b"moof", &[]), boxed(b"mdat", &[])].concat()).contains("fragmented"));
+
+        assert!(refused(boxed(b"mdat", &[0; 64])).contains("no moov"));
+        assert!(refused(b"not really a video".to_vec()).contains("claims"));
+        let mut cut = camera_file();
+        cut.truncate(cut.len() - 10);
+        assert!(refused(cut).contains("moov box claims"));
+        assert!(refused(boxed(b"moov", &boxed(b"mvhd", &[0; 10]))).contains("cut off"));
+
+        let unknown = boxed(b"moov", &boxed(b"mvhd", &header(1000, u32::MAX)));
+        assert!(refused(unknown).contains("unknown"));
+        let movie = read(&mut Cursor::new(boxed(
+            b"moov",
+            &[
+                boxed(b"mvhd", &header(1000, 5000)),
+                trak(b"soun", &header(0, 5000)),
+            ]
+            .concat(),
+        )))
+        .unwrap();
+        assert_eq!(movie.tracks[0].duration, None);
+        assert!(movie.video_info().unwrap_err().contains("no video"));
+    }
+
+    #[test]
+    fn test_only_mp4_extensions_are_read() {
+        for path in ["clip.mp4", "clip.MOV", "/videos/a.b/clip.m4v"] {
+            assert!(reads(path), "{}", path);
+        }
+        for path in [
+            "clip.mkv",
+            "clip.webm",
+            "clip.ts",
+            "mp4",
+            "clip.mp4.part",
+            "",
+        ] {
+            assert!(!reads(path), "{}", path);
+        }
+    }
+}
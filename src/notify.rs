@@ -0,0 +1,176 @@
+//! `--notify`: tell the desktop when a run has finished.
+//!
+//! Desktop notifications need the `notify` cargo feature, which pulls in
+//! `notify-rust` (D-Bus on Linux, the notification center on macOS, toasts
+//! on Windows). Without it, or when no notification service answers, the
+//! terminal bell rings instead.
+
+use crate::error::ReduceError;
+use crate::estimate::format_duration;
+use crate::presenter::Presenter;
+use crate::sizefmt::SizeFormat;
+use crate::STDIO_PATH;
+use std::path::Path;
+use std::time::Duration;
+
+/// Name the notifications are sent under.
+#[cfg(feature = "notify")]
+const APP_NAME: &str = "mdviqure";
+
+/// A finished-run notification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notice {
+    pub title: String,
+    pub body: String,
+}
+
+impl Notice {
+    /// The notice for a single file written to `output`; the size is
+    /// unknown when streaming to stdout.
+    pub fn single(
+        output: &str,
+        result: Result<Option<u64>, &ReduceError>,
+        elapsed: Duration,
+        sizes: SizeFormat,
+    ) -> Self {
+        let name = if output == STDIO_PATH {
+            "stdout".to_string()
+        } else {
+            Path::new(output)
+                .file_name()
+                .map_or_else(|| output.to_string(), |n| n.to_string_lossy().into_owned())
+        };
+        let took = format_duration(elapsed.as_secs_f64());
+        match result {
+            Ok(bytes) => Notice {
+                title: "Reduction finished".into(),
+                body: match bytes {
+                    Some(bytes) => {
+                        format!("{} is {} (took {})", name, sizes.size(bytes), took)
+                    }
+                    None => format!("{} is done (took {})", name, took),
+                },
+            },
+            Err(e) => Notice {
+                title: "Reduction failed".into(),
+                body: format!("{}: {} (after {})", name, first_line(&e.to_string()), took),
+            },
+        }
+    }
+
+    /// The one notice for a whole batch.
+    pub fn batch(
+        reduced: usize,
+        total: usize,
+        bytes: u64,
+        elapsed: Duration,
+        sizes: SizeFormat,
+    ) -> Self {
+        let title = if reduced == total {
+            "Batch finished"
+        } else {
+            "Batch finished with errors"
+        };
+        Notice {
+            title: title.into(),
+            body: format!(
+                "Reduced {} of {} files to {} in total (took {})",
+                reduced,
+                total,
+                sizes.size(bytes),
+                format_duration(elapsed.as_secs_f64())
+            ),
+        }
+    }
+
+    /// Shows the notice on the desktop, ringing the terminal bell on `out`
+    /// when that isn't possible (saying why if `verbose`).
+    pub fn send(&self, out: Presenter, verbose: bool) {
+        if let Err(e) = self.show() {
+            if verbose {
+                out.info(&format!("Desktop notification unavailable ({})", e));
+            }
+            out.raw("\x07");
+        }
+    }
+
+    #[cfg(feature = "notify")]
+    fn show(&self) -> Result<(), String> {
+        notify_rust::Notification::new()
+            .appname(APP_NAME)
+            .summary(&self.title)
+            .body(&self.body)
+            .show()
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    #[cfg(not(feature = "notify"))]
+    fn show(&self) -> Result<(), String> {
+        Err("built without the notify feature".into())
+    }
+}
+
+/// ffmpeg errors carry a stderr tail; a notification only has room for the
+/// summary.
+fn first_line(text: &str) -> &str {
+    text.lines().next().unwrap_or(text).trim_end_matches(':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::size::SizeUnits;
+
+    #[test]
+    fn test_single_notice_names_the_output_and_size() {
+        let notice = Notice::single(
+            "clips/demo.mp4",
+            Ok(Some(48_700_000)),
+            Duration::from_secs(751),
+            SizeUnits::Si.into(),
+        );
+        assert_eq!(notice.title, "Reduction finished");
+        assert_eq!(notice.body, "demo.mp4 is 48.7 MB (took 12:31)");
+
+        let notice = Notice::single(
+            "-",
+            Ok(None),
+            Duration::from_secs(751),
+            SizeUnits::Si.into(),
+        );
+        assert_eq!(notice.body, "stdout is done (took 12:31)");
+    }
+
+    #[test]
+    fn test_failed_notice_keeps_only_the_summary() {
+        let err = ReduceError::Encode("ffmpeg failed during encoding:\nline 1\nline 2".into());
+        let notice = Notice::single(
+            "demo.mp4",
+            Err(&err),
+            Duration::from_secs(5),
+            SizeUnits::Si.into(),
+        );
+        assert_eq!(notice.title, "Reduction failed");
+        assert_eq!(
+            notice.body,
+            "demo.mp4: ffmpeg failed during encoding (after 0:05)"
+        );
+    }
+
+    #[test]
+    fn test_batch_notice_aggregates() {
+        let notice = Notice::batch(
+            2,
+            3,
+            60_000_000,
+            Duration::from_secs(3700),
+            SizeUnits::Si.into(),
+        );
+        assert_eq!(notice.title, "Batch finished with errors");
+        assert_eq!(
+            notice.body,
+            "Reduced 2 of 3 files to 60 MB in total (took 1:01:40)"
+        );
+    }
+}
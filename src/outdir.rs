@@ -0,0 +1,109 @@
+//! Directories that outputs are written into.
+//!
+//! ffmpeg only opens its output once the encode has started, and a missing
+//! directory then shows up as a muxer error that doesn't name it. Every
+//! file the user points the tool at is checked here first: its directory
+//! is created, or with `--no-create-dirs` reported missing.
+
+use crate::error::ReduceError;
+use std::path::{Path, MAIN_SEPARATOR};
+
+/// Makes sure `dir` exists, creating it and its parents when `create` is
+/// set. The empty path is the current directory.
+pub fn ensure_dir(dir: &Path, create: bool) -> Result<(), ReduceError> {
+    if dir.as_os_str().is_empty() || dir.is_dir() {
+        return Ok(());
+    }
+    if dir.exists() {
+        return Err(ReduceError::Usage(format!(
+            "output directory {} is not a directory",
+            shown(dir)
+        )));
+    }
+    if !create {
+        return Err(ReduceError::Usage(format!(
+            "output directory does not exist: {}",
+            shown(dir)
+        )));
+    }
+    std::fs::create_dir_all(dir).map_err(|e| {
+        ReduceError::Encode(format!(
+            "cannot create output directory {}: {}",
+            shown(dir),
+            e
+        ))
+    })
+}
+
+/// Makes sure the directory of the file `path` exists; see [`ensure_dir`].
+pub fn ensure_parent(path: &Path, create: bool) -> Result<(), ReduceError> {
+    match path.parent() {
+        Some(dir) => ensure_dir(dir, create),
+        // The root itself, which always exists.
+        None => Ok(()),
+    }
+}
+
+/// `dir` ending in a separator, so messages read as directories.
+fn shown(dir: &Path) -> String {
+    let text = dir.to_string_lossy();
+    if text.ends_with(MAIN_SEPARATOR) || text.ends_with('/') {
+        text.into_owned()
+    } else {
+        format!("{}{}", text, MAIN_SEPARATOR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_missing_directories_fail_unless_created() {
+        let err = ensure_parent(Path::new("no-such-dir/result.mp4"), false).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "output directory does not exist: no-such-dir{}",
+                MAIN_SEPARATOR
+            )
+        );
+
+        let dir = TestDir::new();
+        let nested = dir.path().join("out").join("2024");
+        ensure_parent(&nested.join("result.mp4"), true).unwrap();
+        assert!(nested.is_dir());
+        // Existing directories are fine either way.
+        ensure_parent(&nested.join("result.mp4"), false).unwrap();
+    }
+
+    #[test]
+    fn test_outputs_without_a_directory_to_check() {
+        // The current directory, and the root.
+        ensure_parent(Path::new("result.mp4"), false).unwrap();
+        ensure_parent(Path::new("/result.mp4"), false).unwrap();
+        ensure_parent(Path::new("/"), false).unwrap();
+    }
+
+    #[test]
+    fn test_trailing_separators() {
+        let dir = TestDir::new();
+        let batch = format!("{}/reduced/", dir.path().display());
+        let err = ensure_dir(Path::new(&batch), false).unwrap_err();
+        assert!(err.to_string().ends_with("/reduced/"), "{}", err);
+        ensure_dir(Path::new(&batch), true).unwrap();
+        assert!(dir.path().join("reduced").is_dir());
+    }
+
+    #[test]
+    fn test_a_file_in_the_way_is_no_directory() {
+        let dir = TestDir::new();
+        let file = dir.join("taken");
+        std::fs::write(&file, "").unwrap();
+        let err = ensure_parent(&Path::new(&file).join("result.mp4"), true).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)));
+        assert!(err.to_string().contains("is not a directory"), "{}", err);
+    }
+}
@@ -0,0 +1,212 @@
+//! Container overhead learned from earlier runs.
+//!
+//! The muxing model in [`crate::reduce::muxing_bytes`] covers packet
+//! headers and the index, but containers and muxer versions add bytes of
+//! their own that no fixed `--overhead-percent` gets right. Each encode
+//! records how far its output came out over the payload and the model; once
+//! the history holds [`MIN_SAMPLES`] of them for a container, their median
+//! becomes that container's default overhead. The median and the
+//! [`MAX_PERCENT`] bound keep an odd file (a wrong duration, a muxer bug)
+//! from moving it far.
+
+use crate::container::Container;
+use crate::history::Entry;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Samples a container needs before its overhead is learned.
+pub const MIN_SAMPLES: usize = 5;
+
+/// Only the latest samples of a container count, so a new ffmpeg version
+/// is picked up.
+pub const RECENT_SAMPLES: usize = 50;
+
+/// Overhead above this share of the output stays at it.
+pub const MAX_PERCENT: f64 = 5.0;
+
+/// Samples further off than this are broken measurements, not overhead.
+const PLAUSIBLE_PERCENT: f64 = 25.0;
+
+/// What one encode into a file showed about its container.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub container: Container,
+    /// Input length in seconds.
+    pub duration: f64,
+    /// Audio and video streams written.
+    pub streams: u32,
+    /// Audio and video bytes the bitrates predicted.
+    pub payload_bytes: u64,
+    /// Bytes the muxing model added on top.
+    pub muxing_bytes: u64,
+    pub actual_bytes: u64,
+}
+
+impl Sample {
+    /// The share of the output, in percent, that neither the payload nor
+    /// the muxing model accounts for; negative when the encoder undershot.
+    pub fn overhead_percent(&self) -> f64 {
+        if self.actual_bytes == 0 {
+            return 0.0;
+        }
+        let modelled = (self.payload_bytes + self.muxing_bytes) as f64;
+        (self.actual_bytes as f64 - modelled) / self.actual_bytes as f64 * 100.0
+    }
+}
+
+/// The overhead learned for one container.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// Plausible samples the history holds for the container.
+    pub samples: usize,
+    /// Median overhead of the latest [`RECENT_SAMPLES`] of them.
+    pub median: f64,
+    /// `median` within `0..=`[`MAX_PERCENT`]; `None` until there are
+    /// [`MIN_SAMPLES`].
+    pub percent: Option<f64>,
+}
+
+impl Estimate {
+    /// Learns from `samples` (overhead percents, oldest first); `None`
+    /// without any plausible one.
+    pub fn of(samples: &[f64]) -> Option<Self> {
+        let plausible: Vec<f64> = samples
+            .iter()
+            .copied()
+            .filter(|p| p.is_finite() && p.abs() <= PLAUSIBLE_PERCENT)
+            .collect();
+        let mut recent = plausible[plausible.len().saturating_sub(RECENT_SAMPLES)..].to_vec();
+        if recent.is_empty() {
+            return None;
+        }
+        recent.sort_by(f64::total_cmp);
+        let n = recent.len();
+        let median = if n % 2 == 1 {
+            recent[n / 2]
+        } else {
+            (recent[n / 2 - 1] + recent[n / 2]) / 2.0
+        };
+        Some(Self {
+            samples: plausible.len(),
+            median,
+            percent: (plausible.len() >= MIN_SAMPLES).then(|| median.clamp(0.0, MAX_PERCENT)),
+        })
+    }
+}
+
+/// Learned overhead per container.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Learned {
+    pub estimates: BTreeMap<Container, Estimate>,
+}
+
+impl Learned {
+    /// Learns from the samples of successful runs in `entries`, oldest
+    /// first.
+    pub fn from_history(entries: &[Entry]) -> Self {
+        let mut samples: BTreeMap<Container, Vec<f64>> = BTreeMap::new();
+        for sample in entries.iter().filter_map(|entry| entry.sample) {
+            samples
+                .entry(sample.container)
+                .or_default()
+                .push(sample.overhead_percent());
+        }
+        Self {
+            estimates: samples
+                .into_iter()
+                .filter_map(|(container, samples)| Some((container, Estimate::of(&samples)?)))
+                .collect(),
+        }
+    }
+
+    /// The overhead percent to use for `container`, once learned.
+    pub fn percent_for(&self, container: Container) -> Option<(f64, usize)> {
+        let estimate = self.estimates.get(&container)?;
+        Some((estimate.percent?, estimate.samples))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(container: Container, actual_bytes: u64) -> Sample {
+        Sample {
+            container,
+            duration: 60.0,
+            streams: 2,
+            payload_bytes: 9_800_000,
+            muxing_bytes: 200_000,
+            actual_bytes,
+        }
+    }
+
+    #[test]
+    fn test_sample_overhead_is_a_share_of_the_output() {
+        assert!((sample(Container::Mp4, 10_100_000).overhead_percent() - 0.990).abs() < 0.001);
+        assert!(sample(Container::Mp4, 9_900_000).overhead_percent() < 0.0);
+        assert_eq!(sample(Container::Mp4, 0).overhead_percent(), 0.0);
+    }
+
+    #[test]
+    fn test_nothing_is_learned_from_too_few_samples() {
+        let estimate = Estimate::of(&[1.0, 1.2, 0.8, 1.1]).unwrap();
+        assert_eq!(estimate.samples, 4);
+        assert_eq!(estimate.percent, None);
+        let estimate = Estimate::of(&[1.0, 1.2, 0.8, 1.1, 0.9]).unwrap();
+        assert_eq!(estimate.percent, Some(1.0));
+        assert_eq!(Estimate::of(&[]), None);
+    }
+
+    #[test]
+    fn test_one_odd_file_barely_moves_the_estimate() {
+        let steady = [1.0, 1.1, 0.9, 1.0, 1.2, 0.8];
+        let mut poisoned = steady.to_vec();
+        poisoned.push(20.0);
+        let estimate = Estimate::of(&poisoned).unwrap();
+        assert_eq!(estimate.percent, Some(1.0));
+        // Beyond plausibility a sample doesn't count at all.
+        poisoned.push(400.0);
+        poisoned.push(f64::NAN);
+        assert_eq!(Estimate::of(&poisoned).unwrap().samples, 7);
+    }
+
+    #[test]
+    fn test_estimates_stay_within_bounds() {
+        let high = Estimate::of(&[9.0, 8.0, 12.0, 10.0, 11.0]).unwrap();
+        assert_eq!(high.median, 10.0);
+        assert_eq!(high.percent, Some(MAX_PERCENT));
+        // Encoders undershooting their bitrate don't make overhead negative.
+        let low = Estimate::of(&[-2.0, -1.5, -3.0, -2.5, -1.0]).unwrap();
+        assert_eq!(low.percent, Some(0.0));
+    }
+
+    #[test]
+    fn test_only_recent_samples_count() {
+        let mut samples = vec![4.0; 100];
+        samples.extend(vec![1.0; RECENT_SAMPLES]);
+        let estimate = Estimate::of(&samples).unwrap();
+        assert_eq!(estimate.samples, 100 + RECENT_SAMPLES);
+        assert_eq!(estimate.percent, Some(1.0));
+    }
+
+    #[test]
+    fn test_learned_per_container() {
+        let with = |sample| Entry {
+            sample: Some(sample),
+            ..Entry::default()
+        };
+        let mut entries: Vec<Entry> = (0..5)
+            .map(|_| with(sample(Container::Mp4, 10_100_000)))
+            .collect();
+        entries.push(with(sample(Container::Mkv, 10_050_000)));
+        entries.push(Entry::default());
+        let learned = Learned::from_history(&entries);
+        let (percent, samples) = learned.percent_for(Container::Mp4).unwrap();
+        assert!((percent - 0.990).abs() < 0.001);
+        assert_eq!(samples, 5);
+        assert_eq!(learned.estimates[&Container::Mkv].samples, 1);
+        assert_eq!(learned.percent_for(Container::Mkv), None);
+        assert_eq!(learned.percent_for(Container::Webm), None);
+    }
+}
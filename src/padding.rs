@@ -0,0 +1,120 @@
+//! `--pad-to-exact`: an output of exactly the target size, for a storage
+//! slot that only takes files of one size, such as a firmware asset.
+//!
+//! The encode aims under the target; once it is verified, [`pad`] appends a
+//! top-level `free` box that fills the rest. Players skip `free` boxes by
+//! type wherever they are, so the file plays as before. Only MP4 and MOV
+//! are made of such boxes ([`pads`]); bytes after the end of other
+//! containers aren't safe to add.
+
+use crate::container::Container;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// The smallest box: a 32-bit size and a type, with nothing in it.
+pub const MIN_BOX_BYTES: u64 = 8;
+
+/// Whether outputs of `container` can be padded.
+pub fn pads(container: Container) -> bool {
+    matches!(container, Container::Mp4 | Container::Mov)
+}
+
+/// The header of a `free` box of `len` bytes in all, header included,
+/// whose body is zeros; `None` when no box is that small. Past 4 GiB the
+/// 32-bit size is 1 and a 64-bit `largesize` follows the type.
+pub fn free_header(len: u64) -> Option<Vec<u8>> {
+    if len < MIN_BOX_BYTES {
+        return None;
+    }
+    let header = match u32::try_from(len) {
+        Ok(size) => [&size.to_be_bytes()[..], b"free"].concat(),
+        Err(_) => [&1u32.to_be_bytes()[..], b"free", &len.to_be_bytes()].concat(),
+    };
+    Some(header)
+}
+
+/// Whether a file of `len` bytes can be padded to `target`: it is no
+/// bigger, and short of it by nothing or by a box at least.
+pub fn can_pad(len: u64, target: u64) -> bool {
+    target
+        .checked_sub(len)
+        .is_some_and(|short| short == 0 || short >= MIN_BOX_BYTES)
+}
+
+/// Appends a `free` box to the file at `path` so it is `target` bytes,
+/// returning the bytes added. Fails when it is over `target` already, or
+/// short of it by less than a box.
+pub fn pad(path: &Path, target: u64) -> io::Result<u64> {
+    let len = fs::metadata(path)?.len();
+    let short = target
+        .checked_sub(len)
+        .ok_or_else(|| io::Error::other(format!("it is {} bytes, over the target already", len)))?;
+    if short == 0 {
+        return Ok(0);
+    }
+    let header = free_header(short).ok_or_else(|| {
+        io::Error::other(format!(
+            "it is {} bytes short, and a box takes at least {}",
+            short, MIN_BOX_BYTES
+        ))
+    })?;
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    file.write_all(&header)?;
+    // The zeros of the body, without writing them out.
+    file.set_len(target)?;
+    let padded = file.metadata()?.len();
+    if padded != target {
+        return Err(io::Error::other(format!(
+            "it came out at {} bytes, not {}",
+            padded, target
+        )));
+    }
+    Ok(short)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_free_headers_are_byte_exact() {
+        assert_eq!(free_header(7), None);
+        assert_eq!(free_header(8).unwrap(), b"\0\0\0\x08free");
+        assert_eq!(free_header(1000).unwrap(), b"\0\0\x03\xe8free");
+        assert_eq!(
+            free_header(u32::MAX as u64).unwrap(),
+            b"\xff\xff\xff\xfffree"
+        );
+        // From 4 GiB on, the 64-bit form.
+        assert_eq!(
+            free_header(1 << 32).unwrap(),
+            b"\0\0\0\x01free\0\0\0\x01\0\0\0\0"
+        );
+        assert_eq!(
+            free_header(5 << 30).unwrap(),
+            b"\0\0\0\x01free\0\0\0\x01\x40\0\0\0"
+        );
+    }
+
+    #[test]
+    fn test_a_file_is_padded_to_the_byte() {
+        let dir = TestDir::new();
+        let path = dir.path().join("out.mp4");
+        fs::write(&path, vec![7; 1000]).unwrap();
+        assert_eq!(pad(&path, 1500).unwrap(), 500);
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 1500);
+        assert_eq!(&data[..1000], &[7; 1000][..]);
+        assert_eq!(&data[1000..1008], b"\0\0\x01\xf4free");
+        assert!(data[1008..].iter().all(|&b| b == 0));
+
+        // Already there.
+        assert_eq!(pad(&path, 1500).unwrap(), 0);
+        for target in [1499, 1507] {
+            assert!(pad(&path, target).is_err(), "{}", target);
+        }
+        assert_eq!(fs::metadata(&path).unwrap().len(), 1500);
+    }
+}
@@ -0,0 +1,278 @@
+//! `--save-stats` and `--stats`: the analysis of a first pass kept for the
+//! runs after it, so trying other trim points or subtitle burns of the
+//! same source only pays for the second pass.
+//!
+//! ffmpeg's `-passlogfile` prefix is where libx264 writes its stats
+//! ([`log_path`]) and their macroblock tree beside them. [`save`] copies
+//! both out of the run directory and writes a [`Header`] next to them
+//! ([`header_path`]) recording what they were made for: the input, as its
+//! path, size and modification time, the encoder, and the frame size and
+//! rate encoded. A later run only [`seed`]s its pass log from them when
+//! [`check`] finds the same.
+//!
+//! The stats describe the frames the first pass saw. A filter that changes
+//! what the frames look like, such as burnt-in subtitles, isn't checked
+//! for, and leaves the second pass spending bits where they were needed
+//! before; one that changes how many there are, such as a different trim,
+//! spreads the bitrate over the wrong frames, and libx264 refuses a second
+//! pass longer than the first.
+
+use crate::encoder::VideoEncoder;
+use crate::probecache::Stamp;
+use crate::url;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How far apart two frame rates may be and still count as the same.
+const FPS_TOLERANCE: f64 = 0.001;
+
+/// What a set of stats was made for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    /// The input's absolute path, or its URL.
+    pub input: String,
+    /// `None` for an input that isn't a file, such as a URL.
+    pub stamp: Option<Stamp>,
+    /// The ffmpeg encoder name.
+    pub encoder: String,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+}
+
+impl Header {
+    /// The header of stats from encoding `input` with `encoder` into
+    /// `width`x`height` at `fps`.
+    pub fn of(input: &str, encoder: VideoEncoder, width: u32, height: u32, fps: f64) -> Self {
+        let path = match std::path::absolute(input) {
+            Ok(path) if !url::is_url(input) => path.to_string_lossy().into_owned(),
+            _ => input.to_string(),
+        };
+        Self {
+            input: path,
+            stamp: Stamp::of(input),
+            encoder: encoder.ffmpeg_name().to_string(),
+            width,
+            height,
+            fps,
+        }
+    }
+}
+
+/// What a run that reuses or keeps its first pass does with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// What this run's stats are, or would be, made for.
+    pub header: Header,
+    /// `--stats`: seed the pass log from here instead of running pass 1.
+    pub reuse: Option<PathBuf>,
+    /// `--save-stats`: copy the pass log here once pass 1 is done.
+    pub save: Option<PathBuf>,
+}
+
+/// The stats file libx264 writes for the pass log prefix `prefix`, for the
+/// video as the output's first stream.
+pub fn log_path(prefix: &Path) -> PathBuf {
+    PathBuf::from(format!("{}-0.log", prefix.display()))
+}
+
+/// The macroblock tree kept beside the stats file `stats`.
+fn mbtree_path(stats: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.mbtree", stats.display()))
+}
+
+/// The header written next to the stats file `stats`.
+pub fn header_path(stats: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.json", stats.display()))
+}
+
+/// Copies `from` to `to` when it is there, and else removes what `to` held
+/// from before, so the pair stays one set.
+fn copy_if_there(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::copy(from, to) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => match fs::remove_file(to) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Saves the stats of the pass log `prefix` to `stats`, with `header` next
+/// to them. The header goes last, and an earlier one first, so stats that
+/// were only partly written are never trusted.
+pub fn save(prefix: &Path, stats: &Path, header: &Header) -> io::Result<()> {
+    match fs::remove_file(header_path(stats)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let log = log_path(prefix);
+    fs::copy(&log, stats)?;
+    copy_if_there(&mbtree_path(&log), &mbtree_path(stats))?;
+    let json = serde_json::to_string_pretty(header).expect("a header always serializes");
+    fs::write(header_path(stats), json + "\n")
+}
+
+/// The header next to the stats file `stats`.
+pub fn load(stats: &Path) -> Result<Header, String> {
+    let path = header_path(stats);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(format!(
+                "{} isn't there to say what they were made for; save them again with --save-stats",
+                path.display()
+            ))
+        }
+        Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+    };
+    serde_json::from_str(&text)
+        .map_err(|e| format!("{} is not a stats header: {}", path.display(), e))
+}
+
+/// Whether stats made for `saved` hold for an encode of `now`; when not,
+/// the first difference.
+pub fn check(saved: &Header, now: &Header) -> Result<(), String> {
+    if saved.input != now.input {
+        return Err(format!("they were made from {}", saved.input));
+    }
+    if saved.stamp != now.stamp {
+        return Err(format!("{} changed since they were made", now.input));
+    }
+    if saved.encoder != now.encoder {
+        return Err(format!(
+            "they were made with {}, not {}",
+            saved.encoder, now.encoder
+        ));
+    }
+    if (saved.width, saved.height) != (now.width, now.height) {
+        return Err(format!(
+            "they were made at {}x{}, not {}x{}",
+            saved.width, saved.height, now.width, now.height
+        ));
+    }
+    if (saved.fps - now.fps).abs() > FPS_TOLERANCE {
+        return Err(format!(
+            "they were made at {} fps, not {}",
+            saved.fps, now.fps
+        ));
+    }
+    Ok(())
+}
+
+/// Puts the stats in `stats` where the pass log `prefix` would have them
+/// after a first pass.
+pub fn seed(stats: &Path, prefix: &Path) -> io::Result<()> {
+    let log = log_path(prefix);
+    fs::copy(stats, &log)?;
+    copy_if_there(&mbtree_path(stats), &mbtree_path(&log))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    fn header() -> Header {
+        Header {
+            input: "/videos/talk.mp4".to_string(),
+            stamp: Some(Stamp {
+                size: 1000,
+                modified_ns: 5,
+            }),
+            encoder: "libx264".to_string(),
+            width: 1280,
+            height: 720,
+            fps: 30.0,
+        }
+    }
+
+    #[test]
+    fn test_stats_only_hold_for_the_same_encode() {
+        let saved = header();
+        assert_eq!(check(&saved, &saved), Ok(()));
+        let close = Header {
+            fps: 30.0004,
+            ..header()
+        };
+        assert_eq!(check(&saved, &close), Ok(()));
+
+        // (changed, difference named)
+        let cases = [
+            (
+                Header {
+                    input: "/videos/other.mp4".to_string(),
+                    ..header()
+                },
+                "they were made from /videos/talk.mp4",
+            ),
+            (
+                Header {
+                    stamp: Some(Stamp {
+                        size: 1000,
+                        modified_ns: 6,
+                    }),
+                    ..header()
+                },
+                "/videos/talk.mp4 changed since they were made",
+            ),
+            (
+                Header {
+                    encoder: "libsvtav1".to_string(),
+                    ..header()
+                },
+                "they were made with libx264, not libsvtav1",
+            ),
+            (
+                Header {
+                    width: 854,
+                    height: 480,
+                    ..header()
+                },
+                "they were made at 1280x720, not 854x480",
+            ),
+            (
+                Header {
+                    fps: 25.0,
+                    ..header()
+                },
+                "they were made at 30 fps, not 25",
+            ),
+        ];
+        for (now, difference) in cases {
+            assert_eq!(check(&saved, &now), Err(difference.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_saved_stats_seed_a_pass_log_with_their_header() {
+        let dir = TestDir::new();
+        let prefix = dir.path().join("run-ffmpeg2pass");
+        fs::write(log_path(&prefix), "#options: 1280x720\n").unwrap();
+        fs::write(mbtree_path(&log_path(&prefix)), [1, 2, 3]).unwrap();
+        let stats = dir.path().join("talk.stats");
+        save(&prefix, &stats, &header()).unwrap();
+        assert_eq!(load(&stats), Ok(header()));
+
+        let next = dir.path().join("next-ffmpeg2pass");
+        seed(&stats, &next).unwrap();
+        assert_eq!(
+            fs::read_to_string(log_path(&next)).unwrap(),
+            "#options: 1280x720\n"
+        );
+        assert_eq!(fs::read(mbtree_path(&log_path(&next))).unwrap(), [1, 2, 3]);
+
+        // Saved again without a tree, the old one doesn't stay behind.
+        fs::remove_file(mbtree_path(&log_path(&prefix))).unwrap();
+        save(&prefix, &stats, &header()).unwrap();
+        assert!(!mbtree_path(&stats).exists());
+
+        fs::remove_file(header_path(&stats)).unwrap();
+        assert!(load(&stats).unwrap_err().contains("--save-stats"));
+        fs::write(header_path(&stats), "{}").unwrap();
+        assert!(load(&stats).unwrap_err().contains("is not a stats header"));
+    }
+}
@@ -0,0 +1,231 @@
+//! `plan`: the bitrate math of a reduce for numbers given on the command
+//! line, with no input file and no ffmpeg.
+//!
+//! The numbers stand in for what probing would report, a source of the
+//! given length, frame size and rate with one stereo audio track, and go
+//! through [`plan_encoding`] as a real run's would. Two things a real run
+//! learns from the file are left out: the source's own bitrate, so nothing
+//! is capped to it, and the overhead learned from earlier runs into the
+//! same container, which `--overhead-percent` stands in for.
+
+use crate::probe::VideoInfo;
+use crate::reduce::{plan_encoding, EncodingPlan, ReduceOptions};
+use crate::sizefmt::SizeFormat;
+use crate::warning::Warning;
+use serde::Serialize;
+
+/// What the planner is told about the input instead of probing it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scenario {
+    /// Length in seconds.
+    pub duration: f64,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+}
+
+impl Scenario {
+    /// What ffprobe would report for such an input.
+    fn source(self) -> VideoInfo {
+        VideoInfo {
+            width: self.width,
+            height: self.height,
+            avg_frame_rate: Some(self.fps.to_string()),
+            r_frame_rate: Some(self.fps.to_string()),
+            duration: Some(self.duration.to_string()),
+            ..VideoInfo::default()
+        }
+    }
+
+    /// The plan a reduce with `opts` would make for such an input.
+    pub fn plan(self, opts: &ReduceOptions) -> EncodingPlan {
+        plan_encoding(self.duration, &self.source(), opts)
+    }
+}
+
+/// Whether the target can hold the encode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Fits,
+    /// It fits, but the bits per pixel predict heavy artifacts.
+    PoorQuality,
+    /// Even the minimum video bitrate won't fit, so the output would come
+    /// out over the target.
+    OverTarget,
+}
+
+impl Verdict {
+    pub fn of(plan: &EncodingPlan) -> Verdict {
+        if plan.clamped() {
+            Verdict::OverTarget
+        } else if plan.poor_quality.is_some() {
+            Verdict::PoorQuality
+        } else {
+            Verdict::Fits
+        }
+    }
+
+    /// One line for the human output.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Verdict::Fits => "fits",
+            Verdict::PoorQuality => "fits, but the quality will be poor",
+            Verdict::OverTarget => "does not fit: even the minimum bitrate is over the target",
+        }
+    }
+}
+
+/// The numbers of a plan, as `--output-format json` prints them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub duration_s: f64,
+    pub target_bytes: u64,
+    /// Bits per second.
+    pub video_bitrate: u64,
+    /// Bits per second, all tracks together.
+    pub audio_bitrate: u64,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+    pub bits_per_pixel: f64,
+    pub quality: String,
+    pub verdict: Verdict,
+    pub predicted_bytes: u64,
+    pub warnings: Vec<Warning>,
+}
+
+impl Summary {
+    /// The summary of `plan`, made for an input `duration` seconds long.
+    pub fn new(duration: f64, plan: &EncodingPlan, opts: &ReduceOptions) -> Summary {
+        Summary {
+            duration_s: duration,
+            target_bytes: opts.target_bytes,
+            video_bitrate: plan.video_bitrate,
+            audio_bitrate: plan.audio_tracks.iter().map(|t| t.bitrate).sum(),
+            width: plan.width,
+            height: plan.height,
+            fps: plan.fps,
+            bits_per_pixel: bits_per_pixel(plan),
+            quality: plan.quality.to_string(),
+            verdict: Verdict::of(plan),
+            predicted_bytes: plan.predicted_bytes,
+            warnings: plan
+                .warnings
+                .iter()
+                .chain(&plan.poor_quality)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// The lines after the plan's own in the human output.
+    pub fn describe(&self, sizes: SizeFormat) -> Vec<String> {
+        vec![
+            format!(
+                "Bits per pixel {:.3} at {}x{}: {} quality",
+                self.bits_per_pixel, self.width, self.height, self.quality
+            ),
+            format!(
+                "Verdict for {}: {}",
+                sizes.size(self.target_bytes),
+                self.verdict.describe()
+            ),
+        ]
+    }
+}
+
+fn bits_per_pixel(plan: &EncodingPlan) -> f64 {
+    crate::estimate::bits_per_pixel(plan.video_bitrate, plan.width, plan.height, plan.fps)
+}
+
+/// Parses a frame size such as `1920x1080`.
+pub fn parse_resolution(text: &str) -> Result<(u32, u32), String> {
+    let invalid = || {
+        format!(
+            "invalid resolution '{}': expected WIDTHxHEIGHT, e.g. 1920x1080",
+            text
+        )
+    };
+    let (width, height) = text.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+    let side = |s: &str| s.trim().parse::<u32>().ok().filter(|&n| n >= 2);
+    Ok((
+        side(width).ok_or_else(invalid)?,
+        side(height).ok_or_else(invalid)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::size::SizeUnits;
+    use crate::testing::mib;
+    use crate::warning::Code;
+
+    const HOUR: Scenario = Scenario {
+        duration: 3600.0,
+        width: 1920,
+        height: 1080,
+        fps: 30.0,
+    };
+
+    #[test]
+    fn test_verdict_follows_the_plan() {
+        // (target, verdict)
+        let cases = [
+            (mib(4000), Verdict::Fits),
+            (mib(200), Verdict::PoorQuality),
+            (mib(10), Verdict::OverTarget),
+        ];
+        for (target, expected) in cases {
+            let opts = ReduceOptions::new(target);
+            let plan = HOUR.plan(&opts);
+            let summary = Summary::new(HOUR.duration, &plan, &opts);
+            assert_eq!(summary.verdict, expected, "{}", target);
+            assert_eq!(summary.video_bitrate, plan.video_bitrate);
+        }
+        let opts = ReduceOptions::new(mib(10));
+        let summary = Summary::new(HOUR.duration, &HOUR.plan(&opts), &opts);
+        assert_eq!(summary.warnings[0].code, Code::BitrateClamped);
+    }
+
+    #[test]
+    fn test_audio_bitrate_comes_off_the_video() {
+        let mut opts = ReduceOptions::new(mib(500));
+        let default = Summary::new(HOUR.duration, &HOUR.plan(&opts), &opts);
+        assert_eq!(default.audio_bitrate, 128_000);
+        opts.audio_bitrate = Some(96_000);
+        let lower = Summary::new(HOUR.duration, &HOUR.plan(&opts), &opts);
+        assert_eq!(lower.audio_bitrate, 96_000);
+        assert_eq!(lower.video_bitrate - default.video_bitrate, 32_000);
+        opts.no_audio = true;
+        assert_eq!(
+            Summary::new(HOUR.duration, &HOUR.plan(&opts), &opts).audio_bitrate,
+            0
+        );
+    }
+
+    #[test]
+    fn test_summary_describes_bits_per_pixel() {
+        let opts = ReduceOptions::new(mib(4000));
+        let plan = HOUR.plan(&opts);
+        let lines = Summary::new(HOUR.duration, &plan, &opts).describe(SizeUnits::Binary.into());
+        assert_eq!(
+            lines[0],
+            format!(
+                "Bits per pixel {:.3} at 1920x1080: good quality",
+                bits_per_pixel(&plan)
+            )
+        );
+        assert_eq!(lines[1], "Verdict for 4,000 MiB: fits");
+    }
+
+    #[test]
+    fn test_parse_resolution() {
+        assert_eq!(parse_resolution("1920x1080"), Ok((1920, 1080)));
+        assert_eq!(parse_resolution(" 1280X720 "), Ok((1280, 720)));
+        for bad in ["1080p", "1920x", "x1080", "0x0", "1920x1080x3"] {
+            assert!(parse_resolution(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Human-readable status output: colors, and the batch summary layout.
+//!
+//! Everything meant for a person goes through [`Presenter`], so color and
+//! width handling live in one place and machine-readable output modes can
+//! bypass it entirely. It is also where credentials in URLs are masked.
+//! What the stream can display is decided in [`crate::terminal`].
+
+use crate::console::Console;
+use crate::terminal::{Capabilities, OutputMode};
+use crate::url::redact_urls;
+use clap::ValueEnum;
+
+/// When to color status output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal, unless `NO_COLOR` is set or
+    /// `CLICOLOR_FORCE` asks for it anyway (see [`crate::terminal::detect`]).
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// The kind of a status line, which decides its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    Success,
+    Warning,
+    Error,
+}
+
+impl Style {
+    fn ansi(self) -> Option<&'static str> {
+        match self {
+            Style::Plain => None,
+            Style::Success => Some("32"),
+            Style::Warning => Some("33"),
+            Style::Error => Some("31"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Presenter {
+    console: Console,
+    caps: Capabilities,
+}
+
+impl Presenter {
+    pub fn new(console: Console, mode: impl Into<OutputMode>) -> Self {
+        Self {
+            console,
+            caps: Capabilities::of(console, mode.into()),
+        }
+    }
+
+    /// A presenter for a run writing to `output`, which moves status to
+    /// stderr when the video itself goes to stdout.
+    pub fn for_output(output: &str, mode: impl Into<OutputMode>) -> Self {
+        Self::new(Console::for_output(output), mode)
+    }
+
+    /// A presenter for messages that always belong on stderr.
+    pub fn stderr(mode: impl Into<OutputMode>) -> Self {
+        Self::new(Console::stderr(), mode)
+    }
+
+    /// Whether progress may be redrawn in place with [`raw`](Self::raw).
+    pub fn redraws(&self) -> bool {
+        self.caps.redraw
+    }
+
+    /// Wraps `text` in the color for `style`, if colors are enabled.
+    pub fn paint(&self, style: Style, text: &str) -> String {
+        match style.ansi().filter(|_| self.caps.color) {
+            Some(code) => format!("\x1b[{}m{}\x1b[0m", code, text),
+            None => text.to_string(),
+        }
+    }
+
+    pub fn line(&self, style: Style, text: &str) {
+        self.console.say(&self.paint(style, &redact_urls(text)));
+    }
+
+    pub fn info(&self, text: &str) {
+        self.line(Style::Plain, text);
+    }
+
+    pub fn success(&self, text: &str) {
+        self.line(Style::Success, text);
+    }
+
+    pub fn warn(&self, text: &str) {
+        self.line(Style::Warning, &format!("Warning: {}", text));
+    }
+
+    pub fn error(&self, text: &str) {
+        self.line(Style::Error, &format!("Error: {}", text));
+    }
+
+    /// Writes `text` without a newline, for prompts and in-place redraws.
+    pub fn raw(&self, text: &str) {
+        self.console.write(&redact_urls(text));
+    }
+
+    /// Columns available for tables: the terminal width, or unlimited when
+    /// the output is not a terminal (logs should keep full paths).
+    pub fn width(&self) -> usize {
+        if !self.console.is_terminal() {
+            return usize::MAX;
+        }
+        terminal_size::terminal_size()
+            .map(|(w, _)| w.0 as usize)
+            .unwrap_or(80)
+    }
+
+    /// `table` laid out for this stream: fitted to its width, with ASCII
+    /// ellipses unless it can show `…`.
+    pub fn table(&self, table: &Table) -> Vec<String> {
+        let ellipsis = if self.caps.unicode { "…" } else { "..." };
+        table.render_with(self.width(), ellipsis)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub header: String,
+    pub align: Align,
+    /// Whether this column gives up space (by truncating its cells) when
+    /// the table is wider than the available width.
+    pub shrink: bool,
+}
+
+impl Column {
+    pub fn new(header: &str, align: Align) -> Self {
+        Self {
+            header: header.to_string(),
+            align,
+            shrink: false,
+        }
+    }
+
+    pub fn shrinking(mut self) -> Self {
+        self.shrink = true;
+        self
+    }
+}
+
+/// Space between columns.
+const GAP: &str = "  ";
+
+/// Narrowest a shrinking column gets, so truncated paths stay recognizable.
+const MIN_SHRUNK_WIDTH: usize = 12;
+
+/// A plain-text table laid out to fit a given width.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    columns: Vec<Column>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self {
+            columns,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        debug_assert_eq!(row.len(), self.columns.len());
+        self.rows.push(row);
+    }
+
+    /// Column widths for rendering within `max_width`, shrinking the
+    /// shrinkable columns (never below a minimum) when the natural widths
+    /// don't fit.
+    pub fn widths(&self, max_width: usize) -> Vec<usize> {
+        let mut widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, col)| {
+                self.rows
+                    .iter()
+                    .map(|row| text_width(&row[i]))
+                    .chain([text_width(&col.header)])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let total = |widths: &[usize]| {
+            widths.iter().sum::<usize>() + GAP.len() * widths.len().saturating_sub(1)
+        };
+        let mut excess = total(&widths).saturating_sub(max_width);
+        for (i, col) in self.columns.iter().enumerate() {
+            if excess == 0 {
+                break;
+            }
+            if col.shrink {
+                let floor = MIN_SHRUNK_WIDTH.max(text_width(&col.header));
+                let give = excess.min(widths[i].saturating_sub(floor));
+                widths[i] -= give;
+                excess -= give;
+            }
+        }
+        widths
+    }
+
+    /// Renders the header followed by one line per row, without trailing
+    /// whitespace.
+    pub fn render(&self, max_width: usize) -> Vec<String> {
+        self.render_with(max_width, "…")
+    }
+
+    /// [`render`](Self::render), marking shortened cells with `ellipsis`.
+    pub fn render_with(&self, max_width: usize, ellipsis: &str) -> Vec<String> {
+        let widths = self.widths(max_width);
+        let header: Vec<String> = self.columns.iter().map(|c| c.header.clone()).collect();
+        std::iter::once(&header)
+            .chain(self.rows.iter())
+            .map(|row| {
+                let cells: Vec<String> = row
+                    .iter()
+                    .zip(&self.columns)
+                    .zip(&widths)
+                    .map(|((cell, col), &width)| {
+                        let cell = shorten_middle(cell, width, ellipsis);
+                        let pad = " ".repeat(width - text_width(&cell));
+                        match col.align {
+                            Align::Left => format!("{}{}", cell, pad),
+                            Align::Right => format!("{}{}", pad, cell),
+                        }
+                    })
+                    .collect();
+                cells.join(GAP).trim_end().to_string()
+            })
+            .collect()
+    }
+}
+
+fn text_width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Shortens `text` to `width` characters by replacing its middle with `…`,
+/// which keeps both the start of a path and the file name visible.
+pub fn truncate_middle(text: &str, width: usize) -> String {
+    shorten_middle(text, width, "…")
+}
+
+/// [`truncate_middle`] with any marker, such as `...` for plain output.
+/// Only as much of the marker as fits is kept.
+fn shorten_middle(text: &str, width: usize, ellipsis: &str) -> String {
+    let len = text_width(text);
+    if len <= width {
+        return text.to_string();
+    }
+    let marker: String = ellipsis.chars().take(width).collect();
+    let keep = width - text_width(&marker);
+    let head = keep / 2;
+    let tail = keep - head;
+    let chars: Vec<char> = text.chars().collect();
+    let mut out: String = chars[..head].iter().collect();
+    out.push_str(&marker);
+    out.extend(&chars[len - tail..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Table {
+        let mut table = Table::new(vec![
+            Column::new("Input", Align::Left).shrinking(),
+            Column::new("Result", Align::Left),
+            Column::new("Size", Align::Right),
+        ]);
+        table.push(vec![
+            "videos/holiday/2024/very-long-name-of-a-clip.mp4".into(),
+            "ok".into(),
+            "48.7 MB".into(),
+        ]);
+        table.push(vec!["b.mp4".into(), "failed".into(), "-".into()]);
+        table
+    }
+
+    #[test]
+    fn test_widths_fit_content_when_there_is_room() {
+        assert_eq!(sample().widths(usize::MAX), vec![48, 6, 7]);
+    }
+
+    #[test]
+    fn test_sizes_are_right_aligned() {
+        let lines = sample().render(usize::MAX);
+        assert_eq!(lines[0], format!("{:48}  Result     Size", "Input"));
+        assert!(lines[1].ends_with("ok      48.7 MB"));
+        assert!(lines[2].ends_with("failed        -"));
+    }
+
+    #[test]
+    fn test_long_paths_are_truncated_to_fit() {
+        let lines = sample().render(40);
+        assert!(lines.iter().all(|l| l.chars().count() <= 40), "{:?}", lines);
+        assert_eq!(sample().widths(40), vec![23, 6, 7]);
+        assert_eq!(lines[1], "videos/holi…-a-clip.mp4  ok      48.7 MB");
+    }
+
+    #[test]
+    fn test_shrinking_stops_at_minimum() {
+        let widths = sample().widths(10);
+        assert_eq!(widths, vec![MIN_SHRUNK_WIDTH, 6, 7]);
+    }
+
+    #[test]
+    fn test_truncate_middle() {
+        assert_eq!(truncate_middle("short", 10), "short");
+        assert_eq!(truncate_middle("abcdefghij", 5), "ab…ij");
+        assert_eq!(truncate_middle("abcdefghij", 1), "…");
+        assert_eq!(truncate_middle("abc", 0), "");
+    }
+
+    #[test]
+    fn test_plain_tables_shorten_with_ascii() {
+        let lines = sample().render_with(40, "...");
+        assert_eq!(lines[1], "videos/hol...a-clip.mp4  ok      48.7 MB");
+        assert_eq!(shorten_middle("abcdefghij", 2, "..."), "..");
+    }
+
+    #[test]
+    fn test_paint_only_when_enabled() {
+        let plain = Presenter::new(Console::stderr(), ColorChoice::Never);
+        assert_eq!(plain.paint(Style::Error, "x"), "x");
+        let colored = Presenter::new(Console::stderr(), ColorChoice::Always);
+        assert_eq!(colored.paint(Style::Success, "x"), "\x1b[32mx\x1b[0m");
+        assert_eq!(colored.paint(Style::Plain, "x"), "x");
+    }
+}
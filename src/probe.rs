@@ -0,0 +1,788 @@
+//! Parsing of ffprobe output into the properties the reducer needs.
+
+use crate::aspect::Ratio;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// Properties of the primary video stream, as reported by ffprobe.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VideoInfo {
+    /// Absolute stream index in the input.
+    #[serde(default)]
+    pub index: u32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub codec_name: Option<String>,
+    /// The container's four-character code for the codec (`avc1`, or
+    /// `encv` for an encrypted stream).
+    #[serde(default)]
+    pub codec_tag_string: Option<String>,
+    /// The codec profile, as ffprobe names it (`High`, `Main 10`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// The codec level; for H.264, ten times its number (`41` for 4.1).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pix_fmt: Option<String>,
+    #[serde(default)]
+    pub disposition: Disposition,
+    #[serde(default)]
+    pub color_primaries: Option<String>,
+    #[serde(default)]
+    pub color_transfer: Option<String>,
+    #[serde(default)]
+    pub color_space: Option<String>,
+    #[serde(default)]
+    pub color_range: Option<String>,
+    /// Average frame rate as a rational string (e.g. `30000/1001`).
+    #[serde(default)]
+    pub avg_frame_rate: Option<String>,
+    /// The lowest rate all timestamps fit on; far off the average for
+    /// variable frame rate streams.
+    #[serde(default)]
+    pub r_frame_rate: Option<String>,
+    /// Stream bitrate in bits per second, as ffprobe's decimal string.
+    /// Many containers (e.g. MKV, TS) don't record it.
+    #[serde(default)]
+    pub bit_rate: Option<String>,
+    /// Shape of one pixel, e.g. `32:27` for a 16:9 NTSC DVD; `0:1` or
+    /// absent when the container doesn't say, which means square.
+    #[serde(default)]
+    pub sample_aspect_ratio: Option<String>,
+    /// The display aspect ratio, e.g. `16:9`.
+    #[serde(default)]
+    pub display_aspect_ratio: Option<String>,
+    /// The stream's own length in seconds, as ffprobe's decimal string;
+    /// absent in Matroska, which only records the file's.
+    #[serde(default)]
+    pub duration: Option<String>,
+    /// The input's audio streams, from a separate probe; `None` when they
+    /// weren't probed, which is treated as one stereo track. ffprobe never
+    /// reports it, but a [saved probe](crate::probecache::Saved) does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_streams: Option<Vec<AudioStream>>,
+    /// Absolute indices of the input's cover art, the video streams
+    /// [`parse_video_info`] passed over; see [`crate::coverart`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cover_art: Vec<u32>,
+    /// The input's data streams, such as camera telemetry, from a separate
+    /// probe; see [`crate::datastream`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub data_streams: Vec<DataStream>,
+    /// When the container says the recording was made, as ffprobe prints
+    /// its `creation_time` tag; see [`crate::archive`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub creation_time: Option<String>,
+    /// The stream's side data; only the display matrix's rotation is
+    /// asked for.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub side_data_list: Vec<SideData>,
+    /// Set when ffprobe couldn't be run and this was read from the input's
+    /// MP4 headers instead: only the durations are known, and the rest is
+    /// assumed (see [`crate::mp4box`]).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub from_headers: bool,
+}
+
+/// Properties of one audio stream, as reported by ffprobe.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AudioStream {
+    /// Absolute stream index in the input.
+    #[serde(default)]
+    pub index: u32,
+    #[serde(default)]
+    pub codec_name: Option<String>,
+    #[serde(default)]
+    pub codec_tag_string: Option<String>,
+    #[serde(default)]
+    pub channels: Option<u32>,
+    /// See [`VideoInfo::duration`].
+    #[serde(default)]
+    pub duration: Option<String>,
+    #[serde(default)]
+    pub disposition: Disposition,
+    #[serde(default)]
+    pub tags: StreamTags,
+}
+
+/// Properties of one data stream, as reported by ffprobe.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DataStream {
+    /// Absolute stream index in the input.
+    #[serde(default)]
+    pub index: u32,
+    /// Absent where ffmpeg has no codec for it, as for a `tmcd` timecode
+    /// track.
+    #[serde(default)]
+    pub codec_name: Option<String>,
+    /// The container's four-character code, such as `gpmd` for GoPro
+    /// telemetry.
+    #[serde(default)]
+    pub codec_tag_string: Option<String>,
+    /// See [`VideoInfo::bit_rate`].
+    #[serde(default)]
+    pub bit_rate: Option<String>,
+    #[serde(default)]
+    pub tags: StreamTags,
+}
+
+/// One entry of a stream's `side_data_list`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SideData {
+    /// Degrees of the display matrix, counterclockwise: a portrait phone
+    /// recording stored landscape has -90.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<f64>,
+}
+
+/// The stream flags ffprobe reports under `disposition`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Disposition {
+    /// Set for cover art stored as a one-frame video stream.
+    #[serde(default)]
+    pub attached_pic: u32,
+    /// Set for the track players should pick first.
+    #[serde(default)]
+    pub default: u32,
+}
+
+/// The stream metadata ffprobe reports under `tags`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamTags {
+    /// ISO 639-2 code such as `eng`; `und` means unknown.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// The track's name in MP4 and MOV, such as `GoPro MET`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handler_name: Option<String>,
+}
+
+/// Audio and video lengths further apart than this, in seconds, are worth
+/// acting on; a frame's worth of audio past the video is normal.
+pub const LENGTH_SLACK_SECONDS: f64 = 1.0;
+
+/// How the lengths of the audio and the video of an input differ; see
+/// [`VideoInfo::length_mismatch`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LengthMismatch {
+    /// The audio runs on after the last frame, which the format duration
+    /// (the longest stream's) counts in.
+    AudioLonger { video: f64, audio: f64 },
+    /// The audio stops before the video does.
+    VideoLonger { video: f64, audio: f64 },
+}
+
+/// Top-level shape of `ffprobe -of json` output.
+#[derive(Debug, Deserialize)]
+struct ProbeOutput<T> {
+    #[serde(default = "Vec::new")]
+    streams: Vec<T>,
+    #[serde(default)]
+    format: FormatSection,
+}
+
+/// The `format` object, with what is asked of it through `format_tags`.
+#[derive(Debug, Default, Deserialize)]
+struct FormatSection {
+    #[serde(default)]
+    tags: FormatTags,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FormatTags {
+    #[serde(default)]
+    creation_time: Option<String>,
+}
+
+/// Parses `ffprobe -select_streams v -of json` output into the properties
+/// of the first video stream that isn't cover art, noting the cover art in
+/// [`VideoInfo::cover_art`] and the container's `creation_time` in
+/// [`VideoInfo::creation_time`]. An input with nothing but cover art gets
+/// its first picture, which [`crate::unsupported`] refuses.
+pub fn parse_video_info(stdout: &str) -> Result<VideoInfo, Box<dyn Error>> {
+    let probe: ProbeOutput<VideoInfo> = serde_json::from_str(stdout)?;
+    let creation_time = probe.format.tags.creation_time;
+    let (pictures, videos): (Vec<VideoInfo>, Vec<VideoInfo>) = probe
+        .streams
+        .into_iter()
+        .partition(|s| s.disposition.attached_pic != 0);
+    let info = match videos.into_iter().next() {
+        Some(info) => VideoInfo {
+            cover_art: pictures.iter().map(|p| p.index).collect(),
+            ..info
+        },
+        None => pictures
+            .into_iter()
+            .next()
+            .ok_or("ffprobe reported no video stream")?,
+    };
+    Ok(VideoInfo {
+        creation_time,
+        ..info
+    })
+}
+
+/// Parses `ffprobe -select_streams a -of json` output into the audio streams.
+pub fn parse_audio_streams(stdout: &str) -> Result<Vec<AudioStream>, Box<dyn Error>> {
+    let probe: ProbeOutput<AudioStream> = serde_json::from_str(stdout)?;
+    Ok(probe.streams)
+}
+
+/// Parses `ffprobe -select_streams d -of json` output into the data
+/// streams.
+pub fn parse_data_streams(stdout: &str) -> Result<Vec<DataStream>, Box<dyn Error>> {
+    let probe: ProbeOutput<DataStream> = serde_json::from_str(stdout)?;
+    Ok(probe.streams)
+}
+
+#[derive(Debug, Deserialize)]
+struct CodecOnly {
+    #[serde(default)]
+    codec_name: Option<String>,
+}
+
+/// Parses `ffprobe -show_entries stream=codec_name -of json` output into
+/// the codec of each stream (`unknown` where ffprobe didn't say).
+pub fn parse_codec_names(stdout: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let probe: ProbeOutput<CodecOnly> = serde_json::from_str(stdout)?;
+    Ok(probe
+        .streams
+        .into_iter()
+        .map(|s| s.codec_name.unwrap_or_else(|| "unknown".to_string()))
+        .collect())
+}
+
+// Values ffprobe reports that ffmpeg also accepts for the matching output option.
+// Anything else ("unknown", "unspecified", "reserved", ...) is left unset.
+const PRIMARIES: &[&str] = &[
+    "bt709",
+    "bt470m",
+    "bt470bg",
+    "smpte170m",
+    "smpte240m",
+    "film",
+    "bt2020",
+    "smpte428",
+    "smpte431",
+    "smpte432",
+    "jedec-p22",
+];
+const TRANSFERS: &[&str] = &[
+    "bt709",
+    "gamma22",
+    "gamma28",
+    "smpte170m",
+    "smpte240m",
+    "linear",
+    "log100",
+    "log316",
+    "iec61966-2-4",
+    "bt1361e",
+    "iec61966-2-1",
+    "bt2020-10",
+    "bt2020-12",
+    "smpte2084",
+    "smpte428",
+    "arib-std-b67",
+];
+const MATRICES: &[&str] = &[
+    "rgb",
+    "bt709",
+    "fcc",
+    "bt470bg",
+    "smpte170m",
+    "smpte240m",
+    "ycgco",
+    "bt2020nc",
+    "bt2020c",
+    "smpte2085",
+    "chroma-derived-nc",
+    "chroma-derived-c",
+    "ictcp",
+];
+const RANGES: &[&str] = &["tv", "pc"];
+
+impl VideoInfo {
+    /// Encoder flags that carry the source's color signaling over to the output.
+    ///
+    /// Only values ffmpeg understands are forwarded; unknown or unspecified
+    /// ones are dropped rather than guessed.
+    pub fn color_args(&self) -> Vec<String> {
+        let fields = [
+            ("-color_primaries", &self.color_primaries, PRIMARIES),
+            ("-color_trc", &self.color_transfer, TRANSFERS),
+            ("-colorspace", &self.color_space, MATRICES),
+            ("-color_range", &self.color_range, RANGES),
+        ];
+        let mut args = Vec::new();
+        for (flag, value, known) in fields {
+            if let Some(value) = value.as_deref().filter(|v| known.contains(v)) {
+                args.push(flag.to_string());
+                args.push(value.to_string());
+            }
+        }
+        args
+    }
+
+    /// The stream bitrate in bits per second, when known.
+    pub fn bit_rate(&self) -> Option<u64> {
+        self.bit_rate
+            .as_deref()
+            .and_then(|b| b.parse().ok())
+            .filter(|&b| b > 0)
+    }
+
+    /// The average frame rate in frames per second, if ffprobe reported a
+    /// usable one. For variable frame rate streams this is the rate that
+    /// matters for bitrate and quality math.
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.avg_frame_rate.as_deref().and_then(parse_rational)
+    }
+
+    /// Degrees clockwise the picture is turned for display, as ffmpeg
+    /// turns it before filtering: 0, 90, 180 or 270.
+    pub fn rotation(&self) -> u32 {
+        let Some(degrees) = self.side_data_list.iter().find_map(|s| s.rotation) else {
+            return 0;
+        };
+        let quarters = (-degrees / 90.0).round() as i64;
+        (quarters.rem_euclid(4) * 90) as u32
+    }
+
+    /// The frame size as displayed, which ffmpeg's filters see: the
+    /// stored size, with its sides swapped by a quarter turn.
+    pub fn displayed_size(&self) -> (u32, u32) {
+        if self.rotation() % 180 == 90 {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+
+    /// The video stream's own length in seconds, when the container
+    /// records one.
+    pub fn stream_duration(&self) -> Option<f64> {
+        parse_seconds(self.duration.as_deref())
+    }
+
+    /// How the video and the longest audio stream differ in length, when
+    /// both are known and more than [`LENGTH_SLACK_SECONDS`] apart.
+    pub fn length_mismatch(&self) -> Option<LengthMismatch> {
+        let video = self.stream_duration()?;
+        let audio = self
+            .audio_streams
+            .as_ref()?
+            .iter()
+            .filter_map(|stream| parse_seconds(stream.duration.as_deref()))
+            .max_by(f64::total_cmp)?;
+        if audio - video > LENGTH_SLACK_SECONDS {
+            Some(LengthMismatch::AudioLonger { video, audio })
+        } else if video - audio > LENGTH_SLACK_SECONDS {
+            Some(LengthMismatch::VideoLonger { video, audio })
+        } else {
+            None
+        }
+    }
+
+    /// The sample aspect ratio, square unless ffprobe reported another.
+    pub fn sar(&self) -> Ratio {
+        self.sample_aspect_ratio
+            .as_deref()
+            .and_then(Ratio::parse)
+            .unwrap_or(Ratio::SQUARE)
+    }
+
+    /// Whether the stream has a variable frame rate, as phone recordings
+    /// usually do: the real and average rates disagree by more than 1%.
+    pub fn is_variable_frame_rate(&self) -> bool {
+        let real = self.r_frame_rate.as_deref().and_then(parse_rational);
+        match (real, self.frame_rate()) {
+            (Some(real), Some(avg)) => (real - avg).abs() / avg > 0.01,
+            _ => false,
+        }
+    }
+
+    /// One-line description of the color signaling for verbose output.
+    pub fn describe_color(&self) -> String {
+        let show = |v: &Option<String>| v.clone().unwrap_or_else(|| "unknown".to_string());
+        format!(
+            "primaries={} transfer={} matrix={} range={}",
+            show(&self.color_primaries),
+            show(&self.color_transfer),
+            show(&self.color_space),
+            show(&self.color_range)
+        )
+    }
+}
+
+/// A duration as ffprobe prints it; `N/A` and zero yield `None`.
+fn parse_seconds(value: Option<&str>) -> Option<f64> {
+    value
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|&d| d.is_finite() && d > 0.0)
+}
+
+/// Parses an ffprobe rational such as `30000/1001` or `25`; `0/0` yields `None`.
+pub fn parse_rational(value: &str) -> Option<f64> {
+    let rate = match value.split_once('/') {
+        Some((num, den)) => {
+            let den: f64 = den.parse().ok()?;
+            if den == 0.0 {
+                return None;
+            }
+            num.parse::<f64>().ok()? / den
+        }
+        None => value.parse().ok()?,
+    };
+    (rate > 0.0 && rate.is_finite()).then_some(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::GOPRO_DATA_STREAMS;
+
+    const BT709_FIXTURE: &str = r#"{
+        "programs": [],
+        "streams": [
+            {
+                "width": 1920,
+                "height": 1080,
+                "color_range": "tv",
+                "color_space": "bt709",
+                "color_transfer": "bt709",
+                "color_primaries": "bt709"
+            }
+        ]
+    }"#;
+
+    const HDR10_FIXTURE: &str = r#"{
+        "streams": [
+            {
+                "width": 3840,
+                "height": 2160,
+                "color_range": "tv",
+                "color_space": "bt2020nc",
+                "color_transfer": "smpte2084",
+                "color_primaries": "bt2020"
+            }
+        ]
+    }"#;
+
+    const UNTAGGED_FIXTURE: &str = r#"{
+        "streams": [
+            {
+                "width": 1280,
+                "height": 720,
+                "color_range": "unknown",
+                "color_space": "unknown",
+                "color_primaries": "unspecified"
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn test_video_info_parsing() {
+        let output = r#"{"programs": [], "streams": [{"width": 1281, "height": 721}]}"#;
+        let info = parse_video_info(output).unwrap();
+        assert_eq!((info.width, info.height), (1281, 721));
+        assert_eq!(info.color_primaries, None);
+        assert_eq!(info.profile, None);
+
+        let output = r#"{"streams": [{"width": 1920, "height": 1080, "codec_name": "h264",
+                         "profile": "High", "level": 41, "pix_fmt": "yuv420p"}]}"#;
+        let info = parse_video_info(output).unwrap();
+        assert_eq!(info.profile.as_deref(), Some("High"));
+        assert_eq!(info.level, Some(41));
+        assert_eq!(info.pix_fmt.as_deref(), Some("yuv420p"));
+
+        // Audio-only files yield no video stream.
+        assert!(parse_video_info(r#"{"streams": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_cover_art_is_passed_over() {
+        // An M4V from a store: the poster comes before the film.
+        let output = r#"{"streams": [
+            {"index": 0, "codec_name": "mjpeg", "width": 600, "height": 900,
+             "disposition": {"attached_pic": 1}},
+            {"index": 1, "codec_name": "h264", "width": 1920, "height": 1080,
+             "duration": "5400.0", "disposition": {"attached_pic": 0}},
+            {"index": 3, "codec_name": "png", "width": 300, "height": 300,
+             "disposition": {"attached_pic": 1}}
+        ]}"#;
+        let info = parse_video_info(output).unwrap();
+        assert_eq!(info.index, 1);
+        assert_eq!(info.codec_name.as_deref(), Some("h264"));
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.duration.as_deref(), Some("5400.0"));
+        assert_eq!(info.cover_art, [0, 3]);
+
+        // Nothing but a picture: the picture, to be refused.
+        let output = r#"{"streams": [
+            {"index": 1, "codec_name": "mjpeg", "width": 600, "height": 600,
+             "disposition": {"attached_pic": 1}}
+        ]}"#;
+        let info = parse_video_info(output).unwrap();
+        assert_eq!(info.disposition.attached_pic, 1);
+        assert!(info.cover_art.is_empty());
+    }
+
+    #[test]
+    fn test_creation_time_comes_from_the_format_tags() {
+        let output = r#"{"streams": [{"width": 1920, "height": 1080}],
+            "format": {"tags": {"creation_time": "2024-05-07T12:34:56.000000Z"}}}"#;
+        let info = parse_video_info(output).unwrap();
+        assert_eq!(
+            info.creation_time.as_deref(),
+            Some("2024-05-07T12:34:56.000000Z")
+        );
+        let output = r#"{"streams": [{"width": 1920, "height": 1080}], "format": {}}"#;
+        assert_eq!(parse_video_info(output).unwrap().creation_time, None);
+    }
+
+    #[test]
+    fn test_rotation_comes_from_the_display_matrix() {
+        let rotated = |side_data: &str| {
+            let output = format!(
+                r#"{{"streams": [{{"width": 1920, "height": 1080, "side_data_list": {}}}]}}"#,
+                side_data
+            );
+            let info = parse_video_info(&output).unwrap();
+            (info.rotation(), info.displayed_size())
+        };
+        // An iPhone held upright.
+        assert_eq!(
+            rotated(r#"[{"side_data_type": "Display Matrix", "rotation": -90}]"#),
+            (90, (1080, 1920))
+        );
+        assert_eq!(rotated(r#"[{"rotation": 90}]"#), (270, (1080, 1920)));
+        assert_eq!(rotated(r#"[{"rotation": 180}]"#), (180, (1920, 1080)));
+        assert_eq!(rotated(r#"[{"rotation": -0.0}]"#), (0, (1920, 1080)));
+        assert_eq!(rotated("[]"), (0, (1920, 1080)));
+    }
+
+    #[test]
+    fn test_gopro_data_streams() {
+        let streams = parse_data_streams(GOPRO_DATA_STREAMS).unwrap();
+        let summary: Vec<_> = streams
+            .iter()
+            .map(|s| {
+                (
+                    s.index,
+                    s.codec_name.as_deref(),
+                    s.codec_tag_string.as_deref(),
+                    s.tags.handler_name.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (2, None, Some("tmcd"), Some("GoPro TCD")),
+                (3, Some("bin_data"), Some("gpmd"), Some("GoPro MET")),
+                (4, None, Some("fdsc"), Some("GoPro SOS")),
+            ]
+        );
+        assert_eq!(streams[1].bit_rate.as_deref(), Some("48063"));
+        assert!(parse_data_streams(r#"{"streams": []}"#).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_audio_stream_parsing() {
+        let output = r#"{"streams": [
+            {"index": 1, "codec_name": "pcm_s16le", "channels": 2,
+             "disposition": {"default": 1}, "tags": {"language": "eng"}},
+            {"index": 2, "codec_name": "aac", "channels": 6}
+        ]}"#;
+        let streams = parse_audio_streams(output).unwrap();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[1].channels, Some(6));
+        assert_eq!(streams[0].tags.language.as_deref(), Some("eng"));
+        assert_eq!(streams[0].disposition.default, 1);
+        assert_eq!(streams[1].tags.language, None);
+        assert!(parse_audio_streams(r#"{"streams": []}"#)
+            .unwrap()
+            .is_empty());
+        assert!(parse_audio_streams("{}").unwrap().is_empty());
+    }
+
+    /// One video stream and the given audio streams, with their lengths.
+    fn with_lengths(video: &str, audio: &[&str]) -> VideoInfo {
+        let mut info = parse_video_info(&format!(
+            r#"{{"streams": [{{"width": 1920, "height": 1080, "duration": "{}"}}]}}"#,
+            video
+        ))
+        .unwrap();
+        let audio: Vec<String> = audio
+            .iter()
+            .enumerate()
+            .map(|(i, d)| {
+                format!(
+                    r#"{{"index": {}, "codec_name": "aac", "duration": "{}"}}"#,
+                    i + 1,
+                    d
+                )
+            })
+            .collect();
+        info.audio_streams =
+            Some(parse_audio_streams(&format!(r#"{{"streams": [{}]}}"#, audio.join(","))).unwrap());
+        info
+    }
+
+    #[test]
+    fn test_audio_running_past_the_video() {
+        let info = with_lengths("60.000000", &["64.512000"]);
+        assert_eq!(info.stream_duration(), Some(60.0));
+        assert_eq!(
+            info.length_mismatch(),
+            Some(LengthMismatch::AudioLonger {
+                video: 60.0,
+                audio: 64.512
+            })
+        );
+        // The longest of several tracks counts.
+        assert!(matches!(
+            with_lengths("60.000000", &["60.010000", "63.000000"]).length_mismatch(),
+            Some(LengthMismatch::AudioLonger { audio, .. }) if audio == 63.0
+        ));
+    }
+
+    #[test]
+    fn test_audio_ending_early_and_close_lengths() {
+        assert_eq!(
+            with_lengths("60.000000", &["45.000000"]).length_mismatch(),
+            Some(LengthMismatch::VideoLonger {
+                video: 60.0,
+                audio: 45.0
+            })
+        );
+        // AAC priming and the last frame's length leave a little over.
+        assert_eq!(
+            with_lengths("60.000000", &["60.046000"]).length_mismatch(),
+            None
+        );
+        assert_eq!(
+            with_lengths("60.000000", &["59.500000"]).length_mismatch(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unknown_lengths_are_no_mismatch() {
+        // Matroska records no stream durations.
+        assert_eq!(with_lengths("N/A", &["64.000000"]).length_mismatch(), None);
+        assert_eq!(with_lengths("60.000000", &["N/A"]).length_mismatch(), None);
+        assert_eq!(with_lengths("60.000000", &[]).length_mismatch(), None);
+        let mut unprobed = with_lengths("60.000000", &["64.000000"]);
+        unprobed.audio_streams = None;
+        assert_eq!(unprobed.length_mismatch(), None);
+    }
+
+    #[test]
+    fn test_codec_names_parsing() {
+        let output = r#"{"streams": [{"index": 2, "codec_name": "subrip"}, {"index": 3}]}"#;
+        assert_eq!(parse_codec_names(output).unwrap(), ["subrip", "unknown"]);
+        assert!(parse_codec_names(r#"{"streams": []}"#).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bit_rate_parsing() {
+        let output = r#"{"streams": [{"width": 640, "height": 360, "bit_rate": "850123"}]}"#;
+        assert_eq!(parse_video_info(output).unwrap().bit_rate(), Some(850_123));
+        let output = r#"{"streams": [{"width": 640, "height": 360, "bit_rate": "N/A"}]}"#;
+        assert_eq!(parse_video_info(output).unwrap().bit_rate(), None);
+    }
+
+    #[test]
+    fn test_frame_rate_parsing() {
+        assert_eq!(parse_rational("25"), Some(25.0));
+        assert!((parse_rational("30000/1001").unwrap() - 29.97).abs() < 0.01);
+        assert_eq!(parse_rational("0/0"), None);
+        assert_eq!(parse_rational("N/A"), None);
+
+        let info = parse_video_info(
+            r#"{"streams": [{"width": 2, "height": 2, "avg_frame_rate": "60/1"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(info.frame_rate(), Some(60.0));
+    }
+
+    #[test]
+    fn test_variable_frame_rate_detection() {
+        // A phone recording: timestamps on a 1/120 grid, ~29.87 fps on average.
+        let info = parse_video_info(
+            r#"{"streams": [{"width": 1920, "height": 1080,
+                "r_frame_rate": "120/1", "avg_frame_rate": "1792000/60000"}]}"#,
+        )
+        .unwrap();
+        assert!(info.is_variable_frame_rate());
+        assert!((info.frame_rate().unwrap() - 29.87).abs() < 0.01);
+
+        // NTSC and plain constant rates agree.
+        for (real, avg) in [("30000/1001", "30000/1001"), ("25/1", "25/1")] {
+            let info = parse_video_info(&format!(
+                r#"{{"streams": [{{"width": 2, "height": 2,
+                    "r_frame_rate": "{}", "avg_frame_rate": "{}"}}]}}"#,
+                real, avg
+            ))
+            .unwrap();
+            assert!(!info.is_variable_frame_rate());
+        }
+        // Without both rates there is nothing to compare.
+        let info = parse_video_info(
+            r#"{"streams": [{"width": 2, "height": 2,
+                "r_frame_rate": "120/1", "avg_frame_rate": "0/0"}]}"#,
+        )
+        .unwrap();
+        assert!(!info.is_variable_frame_rate());
+    }
+
+    #[test]
+    fn test_bt709_color_args() {
+        let info = parse_video_info(BT709_FIXTURE).unwrap();
+        assert_eq!(
+            info.color_args(),
+            vec![
+                "-color_primaries",
+                "bt709",
+                "-color_trc",
+                "bt709",
+                "-colorspace",
+                "bt709",
+                "-color_range",
+                "tv"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hdr10_color_args() {
+        let info = parse_video_info(HDR10_FIXTURE).unwrap();
+        assert_eq!(
+            info.color_args(),
+            vec![
+                "-color_primaries",
+                "bt2020",
+                "-color_trc",
+                "smpte2084",
+                "-colorspace",
+                "bt2020nc",
+                "-color_range",
+                "tv"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_color_values_are_not_guessed() {
+        let info = parse_video_info(UNTAGGED_FIXTURE).unwrap();
+        assert!(info.color_args().is_empty());
+        assert_eq!(
+            info.describe_color(),
+            "primaries=unspecified transfer=unknown matrix=unknown range=unknown"
+        );
+    }
+}
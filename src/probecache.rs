@@ -0,0 +1,426 @@
+//! Probe results reused within a run, and across the runs of a batch.
+//!
+//! Auto-downscaling, audio selection, the remux checks, output naming and
+//! a batch's budget each want to know about the input, and each question
+//! is another ffprobe run, which is slow on network storage. [`Cached`]
+//! asks each question once per file and keeps the answer in its
+//! [`ProbeCache`], keyed by the path along with the file's size and
+//! modification time: a file that changed since is probed afresh. Inputs
+//! that aren't files, such as URLs, are never cached.
+//!
+//! A batch keeps what it learned about its inputs in its state (see
+//! [`crate::resume`]), so a resumed batch doesn't probe them again.
+
+use crate::breakdown::FileStreams;
+use crate::error::ReduceError;
+use crate::filesystem::Filesystem;
+use crate::probe::VideoInfo;
+use crate::progress::Progress;
+use crate::sleep::Inhibitor;
+use crate::tool::VideoTool;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::UNIX_EPOCH;
+
+/// What tells one version of a file from another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub modified_ns: u64,
+}
+
+impl Stamp {
+    /// The stamp of the file at `path`; `None` for anything that isn't a
+    /// file on disk.
+    pub fn of(path: &str) -> Option<Stamp> {
+        let meta = std::fs::metadata(path).ok().filter(|m| m.is_file())?;
+        let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Stamp {
+            size: meta.len(),
+            modified_ns: u64::try_from(modified.as_nanos()).ok()?,
+        })
+    }
+}
+
+/// The answers known about one version of a file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Probed {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded_duration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<VideoInfo>,
+    /// Only kept for the run: cheap to ask again, and rarely asked.
+    #[serde(skip)]
+    pub subtitle_codecs: Option<Vec<String>>,
+    #[serde(skip)]
+    pub streams: Option<FileStreams>,
+}
+
+impl Probed {
+    /// Whether any of it would be saved.
+    fn saves_anything(&self) -> bool {
+        self.duration.is_some() || self.decoded_duration.is_some() || self.info.is_some()
+    }
+}
+
+/// What is known about one file, as a batch state saves it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Saved {
+    pub path: String,
+    pub stamp: Stamp,
+    #[serde(flatten)]
+    pub probed: Probed,
+}
+
+/// The answers of every probe of a run, shared with its clones and between
+/// the threads a `--jobs` batch reduces its files on. A file asked about
+/// from several threads at once is probed by one; the others wait for its
+/// answer.
+#[derive(Debug, Default, Clone)]
+pub struct ProbeCache {
+    entries: Arc<Mutex<HashMap<String, (Stamp, Probed)>>>,
+    /// A lock for each file, held while it is probed.
+    probing: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+}
+
+impl ProbeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes on what an earlier run saved, where this run doesn't know
+    /// better; entries of files that changed since are dropped when they
+    /// are next asked for.
+    pub fn load(&self, saved: &[Saved]) {
+        let mut entries = self.lock();
+        for file in saved {
+            let (stamp, probed) = entries
+                .entry(file.path.clone())
+                .or_insert_with(|| (file.stamp, Probed::default()));
+            if *stamp == file.stamp {
+                let earlier = file.probed.clone();
+                probed.duration = probed.duration.or(earlier.duration);
+                probed.decoded_duration = probed.decoded_duration.or(earlier.decoded_duration);
+                probed.info = probed.info.take().or(earlier.info);
+            }
+        }
+    }
+
+    /// What is known about each of `paths`, to be saved for a later run.
+    pub fn saved(&self, paths: &[String]) -> Vec<Saved> {
+        let entries = self.lock();
+        paths
+            .iter()
+            .filter_map(|path| {
+                let (stamp, probed) = entries.get(path)?;
+                probed.saves_anything().then(|| Saved {
+                    path: path.clone(),
+                    stamp: *stamp,
+                    probed: probed.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// The answer `field` holds for `path` as it is now, else what `probe`
+    /// finds, which is kept when it succeeded. Only the file's own lock is
+    /// held while probing, so the probes of other files aren't held up.
+    fn get_or_probe<V: Clone>(
+        &self,
+        path: &str,
+        field: fn(&mut Probed) -> &mut Option<V>,
+        probe: impl FnOnce() -> Result<V, ReduceError>,
+    ) -> Result<V, ReduceError> {
+        let Some(stamp) = Stamp::of(path) else {
+            return probe();
+        };
+        if let Some(value) = self.known(path, stamp, field) {
+            return Ok(value);
+        }
+        let turn = Arc::clone(
+            self.probing
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(path.to_string())
+                .or_default(),
+        );
+        let _turn = turn.lock().unwrap_or_else(PoisonError::into_inner);
+        // Another thread may have asked while this one waited its turn.
+        if let Some(value) = self.known(path, stamp, field) {
+            return Ok(value);
+        }
+        let value = probe()?;
+        let mut entries = self.lock();
+        let entry = entries
+            .entry(path.to_string())
+            .or_insert_with(|| (stamp, Probed::default()));
+        if entry.0 != stamp {
+            *entry = (stamp, Probed::default());
+        }
+        *field(&mut entry.1) = Some(value.clone());
+        Ok(value)
+    }
+
+    /// The answer `field` holds for `path`, when it is for the file as it
+    /// is now.
+    fn known<V: Clone>(
+        &self,
+        path: &str,
+        stamp: Stamp,
+        field: fn(&mut Probed) -> &mut Option<V>,
+    ) -> Option<V> {
+        let mut entries = self.lock();
+        let (cached, probed) = entries.get_mut(path)?;
+        field(probed).as_ref().filter(|_| *cached == stamp).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, (Stamp, Probed)>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A tool that answers probes from its [`ProbeCache`] where it can and
+/// passes everything else on.
+pub struct Cached<'a, T> {
+    inner: &'a T,
+    cache: ProbeCache,
+}
+
+impl<'a, T: VideoTool> Cached<'a, T> {
+    pub fn new(inner: &'a T) -> Self {
+        Self {
+            inner,
+            cache: ProbeCache::new(),
+        }
+    }
+}
+
+impl<T: VideoTool> VideoTool for Cached<'_, T> {
+    fn get_video_duration(&self, input: &str) -> Result<f64, ReduceError> {
+        self.cache.get_or_probe(
+            input,
+            |p| &mut p.duration,
+            || self.inner.get_video_duration(input),
+        )
+    }
+
+    fn get_video_info(&self, input: &str) -> Result<VideoInfo, ReduceError> {
+        self.cache
+            .get_or_probe(input, |p| &mut p.info, || self.inner.get_video_info(input))
+    }
+
+    fn get_decoded_duration(&self, input: &str) -> Result<f64, ReduceError> {
+        self.cache.get_or_probe(
+            input,
+            |p| &mut p.decoded_duration,
+            || self.inner.get_decoded_duration(input),
+        )
+    }
+
+    fn get_subtitle_codecs(&self, input: &str) -> Result<Vec<String>, ReduceError> {
+        self.cache.get_or_probe(
+            input,
+            |p| &mut p.subtitle_codecs,
+            || self.inner.get_subtitle_codecs(input),
+        )
+    }
+
+    fn get_streams(&self, input: &str) -> Result<FileStreams, ReduceError> {
+        self.cache
+            .get_or_probe(input, |p| &mut p.streams, || self.inner.get_streams(input))
+    }
+
+    fn get_packet_sizes(&self, input: &str) -> Result<HashMap<u32, u64>, ReduceError> {
+        self.inner.get_packet_sizes(input)
+    }
+
+    fn get_packet_times(&self, input: &str) -> Result<Vec<(f64, u64)>, ReduceError> {
+        self.inner.get_packet_times(input)
+    }
+
+    fn list_encoders(&self) -> Result<Vec<String>, ReduceError> {
+        self.inner.list_encoders()
+    }
+
+    fn list_hwaccels(&self) -> Result<Vec<String>, ReduceError> {
+        self.inner.list_hwaccels()
+    }
+
+    fn run_ffmpeg(&self, args: &[&str]) -> Result<(), ReduceError> {
+        self.inner.run_ffmpeg(args)
+    }
+
+    fn run_ffmpeg_with_progress(
+        &self,
+        args: &[&str],
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<(), ReduceError> {
+        self.inner.run_ffmpeg_with_progress(args, on_progress)
+    }
+
+    fn run_ffmpeg_parallel(
+        &self,
+        jobs: &[Vec<&str>],
+        on_progress: &mut dyn FnMut(usize, &Progress),
+    ) -> Result<(), ReduceError> {
+        self.inner.run_ffmpeg_parallel(jobs, on_progress)
+    }
+
+    fn filesystem_of(&self, dir: &Path) -> Option<Filesystem> {
+        self.inner.filesystem_of(dir)
+    }
+
+    fn sleep_inhibitor(&self) -> Box<dyn Inhibitor> {
+        self.inner.sleep_inhibitor()
+    }
+
+    fn rename_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename_file(from, to)
+    }
+
+    fn probe_cache(&self) -> Option<&ProbeCache> {
+        Some(&self.cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockVideoTool, TestDir};
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    fn mock_with_file(dir: &TestDir, name: &str) -> (MockVideoTool, String) {
+        let path = dir.join(name);
+        std::fs::write(&path, b"video").unwrap();
+        (MockVideoTool::new(60.0), path)
+    }
+
+    #[test]
+    fn test_each_question_is_asked_once_per_file() {
+        let dir = TestDir::new();
+        let (mock, a) = mock_with_file(&dir, "a.mp4");
+        let b = dir.join("b.mp4");
+        std::fs::write(&b, b"other").unwrap();
+        let tool = Cached::new(&mock);
+        for _ in 0..3 {
+            tool.get_video_info(&a).unwrap();
+            tool.get_video_duration(&a).unwrap();
+            tool.get_streams(&a).unwrap();
+            tool.get_video_info(&b).unwrap();
+        }
+        assert_eq!(
+            mock.probes(),
+            [
+                format!("info {}", a),
+                format!("duration {}", a),
+                format!("streams {}", a),
+                format!("info {}", b)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_a_changed_file_is_probed_again() {
+        let dir = TestDir::new();
+        let (mock, a) = mock_with_file(&dir, "a.mp4");
+        let tool = Cached::new(&mock);
+        tool.get_video_info(&a).unwrap();
+        std::fs::write(&a, b"a longer video").unwrap();
+        tool.get_video_info(&a).unwrap();
+        tool.get_video_info(&a).unwrap();
+        assert_eq!(mock.probes().len(), 2);
+    }
+
+    #[test]
+    fn test_a_file_asked_about_from_several_threads_is_probed_once() {
+        let dir = TestDir::new();
+        let (mut mock, a) = mock_with_file(&dir, "a.mp4");
+        let b = dir.join("b.mp4");
+        std::fs::write(&b, b"other").unwrap();
+        mock.probe_delay = Duration::from_millis(50);
+        let tool = Cached::new(&mock);
+        let start = Barrier::new(8);
+        thread::scope(|scope| {
+            for i in 0..8 {
+                let (tool, start, info) = (&tool, &start, &mock.info);
+                let input = if i % 2 == 0 { &a } else { &b };
+                scope.spawn(move || {
+                    start.wait();
+                    assert_eq!(&tool.get_video_info(input).unwrap(), info);
+                });
+            }
+        });
+
+        let mut probes = mock.probes();
+        probes.sort();
+        assert_eq!(probes, [format!("info {}", a), format!("info {}", b)]);
+    }
+
+    #[test]
+    fn test_what_isnt_a_file_is_never_cached() {
+        let mock = MockVideoTool::new(60.0);
+        let tool = Cached::new(&mock);
+        for input in ["https://example.com/in.mp4", "missing.mp4"] {
+            tool.get_video_duration(input).unwrap();
+            tool.get_video_duration(input).unwrap();
+        }
+        assert_eq!(mock.probes().len(), 4);
+        assert!(tool.cache.saved(&["missing.mp4".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_failures_are_not_remembered() {
+        let dir = TestDir::new();
+        let (mock, a) = mock_with_file(&dir, "a.mp4");
+        mock.transient_probe_failures.set(1);
+        let tool = Cached::new(&mock);
+        assert!(tool.get_video_info(&a).is_err());
+        assert!(tool.get_video_info(&a).is_ok());
+        tool.get_video_info(&a).unwrap();
+        assert_eq!(mock.probes().len(), 2);
+    }
+
+    #[test]
+    fn test_saved_answers_carry_over_while_the_file_is_unchanged() {
+        let dir = TestDir::new();
+        let (mock, a) = mock_with_file(&dir, "a.mp4");
+        let first = Cached::new(&mock);
+        first.get_video_info(&a).unwrap();
+        first.get_subtitle_codecs(&a).unwrap();
+        let saved = first.cache.saved(&[a.clone(), dir.join("other.mp4")]);
+        assert_eq!(saved.len(), 1);
+        let json = serde_json::to_string(&saved).unwrap();
+        assert!(!json.contains("subtitle"), "{}", json);
+        let saved: Vec<Saved> = serde_json::from_str(&json).unwrap();
+
+        let second = Cached::new(&mock);
+        second.cache.load(&saved);
+        assert_eq!(second.get_video_info(&a).unwrap(), mock.info);
+        assert_eq!(mock.probes().len(), 2);
+        // Only what was saved is known.
+        second.get_subtitle_codecs(&a).unwrap();
+        assert_eq!(mock.probes().len(), 3);
+
+        // What this run asked already is kept alongside.
+        let merged = Cached::new(&mock);
+        merged.get_video_duration(&a).unwrap();
+        merged.cache.load(&saved);
+        merged.get_video_info(&a).unwrap();
+        merged.get_video_duration(&a).unwrap();
+        assert_eq!(mock.probes().len(), 4);
+
+        let third = Cached::new(&mock);
+        third.cache.load(&saved);
+        std::fs::write(&a, b"replaced").unwrap();
+        third.get_video_info(&a).unwrap();
+        assert_eq!(mock.probes().len(), 5);
+    }
+}
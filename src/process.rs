@@ -0,0 +1,485 @@
+//! Async process layer for ffprobe and ffmpeg, built on `tokio::process`.
+//!
+//! Each running ffmpeg is supervised by one `select!` loop that reads
+//! progress, watches the timeout and polls for Ctrl-C, while stderr is
+//! collected by a separate task. [`FfmpegTool`](crate::tool::FfmpegTool)
+//! wraps these functions with [`block_on`] for synchronous callers.
+//! [`ffmpeg_all`] runs several at once, for the chunks of one encode; the
+//! files of a `--jobs` batch run at once from threads of their own (see
+//! [`crate::batch`]).
+//!
+//! Neither stream is held whole: stdout is cut into lines as it is read
+//! and stderr keeps only its [`STDERR_TAIL_BYTES`] last bytes, so memory
+//! stays flat however much ffmpeg prints (see [`crate::capture`]). All of
+//! stderr can go to a log file as it comes instead.
+
+use crate::capture::{Lines, Tail};
+use crate::error::ReduceError;
+use crate::interrupt;
+use crate::progress::{Progress, ProgressParser};
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::process::{ExitStatus, Stdio};
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+
+/// How many trailing stderr lines of a failed ffmpeg run go into the error.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// How much of ffmpeg's stderr is kept in memory for those lines.
+pub const STDERR_TAIL_BYTES: usize = 64 * 1024;
+
+/// The longest line read from ffmpeg; the rest of a longer one is dropped.
+const MAX_LINE_BYTES: usize = 16 * 1024;
+
+/// How much is read from a pipe at a time.
+const READ_CHUNK: usize = 8 * 1024;
+
+/// How often the supervision loop checks for Ctrl-C.
+const INTERRUPT_POLL: Duration = Duration::from_millis(100);
+
+/// Runs `future` to completion on a fresh single-threaded runtime.
+///
+/// This is the bridge used by the blocking API; it must not be called from
+/// inside another tokio runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to start the tokio runtime")
+        .block_on(future)
+}
+
+/// What a successful ffprobe run printed.
+///
+/// A zero exit is what makes a run successful: ffprobe also prints to
+/// stderr about files it reads fine (a damaged frame, an odd timestamp), so
+/// stderr alone says nothing. It still explains a stdout that won't parse,
+/// which is what [`Captured::parse`] uses it for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Captured {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Captured {
+    /// Parses stdout with `parse`; a failure is a probe error starting with
+    /// `context` and ending with whatever ffprobe said on stderr.
+    pub fn parse<T, E: std::fmt::Display>(
+        &self,
+        context: &str,
+        parse: impl FnOnce(&str) -> Result<T, E>,
+    ) -> Result<T, ReduceError> {
+        parse(&self.stdout).map_err(|e| {
+            let mut message = format!("{}: {}", context, e);
+            let stderr = self.stderr.trim();
+            if !stderr.is_empty() {
+                message.push_str(&format!(" (ffprobe said: {})", stderr));
+            }
+            ReduceError::Probe(message)
+        })
+    }
+}
+
+/// Runs ffprobe to completion, classifying launch and exit failures. It is
+/// killed once `timeout` has elapsed, which matters for URL inputs on a
+/// server that stops responding.
+pub async fn ffprobe(args: &[&str], timeout: Option<Duration>) -> Result<Captured, ReduceError> {
+    capture("ffprobe", args, timeout).await
+}
+
+/// Runs a short, non-encoding ffmpeg query (such as `-encoders`) and
+/// returns its stdout; failures count as probe errors.
+pub async fn ffmpeg_query(args: &[&str]) -> Result<String, ReduceError> {
+    Ok(capture("ffmpeg", args, None).await?.stdout)
+}
+
+async fn capture(
+    tool: &'static str,
+    args: &[&str],
+    timeout: Option<Duration>,
+) -> Result<Captured, ReduceError> {
+    let run = Command::new(tool)
+        .args(args)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    // Dropping the timed-out future kills the child.
+    let output = tokio::time::timeout(timeout.unwrap_or(Duration::MAX), run)
+        .await
+        .map_err(|_| ReduceError::Timeout(timeout.unwrap_or_default()))?
+        .map_err(|e| spawn_error(tool, e))?;
+    if cannot_start(output.status) {
+        return Err(ReduceError::ToolNotFound(tool));
+    }
+    if !output.status.success() {
+        return Err(ReduceError::Probe(format!(
+            "{} failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(Captured {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// Runs ffmpeg, reporting each `-progress pipe:1` snapshot to `on_progress`.
+///
+/// The child is killed on Ctrl-C or once `timeout` has elapsed. stdout is
+/// only captured when ffmpeg reports progress there; when the encoded stream
+/// itself goes to stdout it must reach our caller untouched. With `log`,
+/// the command and everything it prints on stderr are appended to that
+/// file.
+pub async fn ffmpeg(
+    args: &[&str],
+    timeout: Option<Duration>,
+    log: Option<&Path>,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<(), ReduceError> {
+    let log = log.map(|path| open_log(path, args)).transpose()?;
+    let stdout = if reports_progress_on_stdout(args) {
+        Stdio::piped()
+    } else {
+        Stdio::inherit()
+    };
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    own_process_group(&mut command);
+    let mut child = command.spawn().map_err(|e| spawn_error("ffmpeg", e))?;
+    let stderr = child.stderr.take().map(|err| collect_tail(err, log));
+
+    let status = supervise(&mut child, timeout, on_progress).await?;
+    if !status.success() {
+        let tail = match stderr {
+            Some(task) => task
+                .await
+                .map(|tail| tail.last_lines(STDERR_TAIL_LINES))
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        let mut message = String::from("ffmpeg failed during encoding");
+        if !tail.is_empty() {
+            message.push_str(":\n");
+            message.push_str(&tail.join("\n"));
+        }
+        return Err(ReduceError::Encode(message));
+    }
+    Ok(())
+}
+
+/// Runs several ffmpeg commands at once, like [`ffmpeg`], reporting each
+/// one's progress along with its index in `jobs`. Returns the first failure
+/// as soon as it happens; the runs still going are dropped, which kills
+/// their children.
+pub async fn ffmpeg_all(
+    jobs: &[Vec<&str>],
+    timeout: Option<Duration>,
+    log: Option<&Path>,
+    on_progress: &mut dyn FnMut(usize, &Progress),
+) -> Result<(), ReduceError> {
+    // Only one run is polled at a time, so the callback is never borrowed
+    // twice.
+    let on_progress = RefCell::new(on_progress);
+    let on_progress = &on_progress;
+    type Run<'a> = Pin<Box<dyn Future<Output = Result<(), ReduceError>> + 'a>>;
+    let mut runs: Vec<Run> = jobs
+        .iter()
+        .enumerate()
+        .map(|(i, args)| {
+            Box::pin(async move {
+                let mut report = |progress: &Progress| (on_progress.borrow_mut())(i, progress);
+                ffmpeg(args, timeout, log, &mut report).await
+            }) as Run
+        })
+        .collect();
+    std::future::poll_fn(|cx| {
+        let mut i = 0;
+        while i < runs.len() {
+            match runs[i].as_mut().poll(cx) {
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(())) => drop(runs.swap_remove(i)),
+                Poll::Pending => i += 1,
+            }
+        }
+        if runs.is_empty() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Waits for `child` while forwarding progress, enforcing the timeout and
+/// reacting to Ctrl-C.
+async fn supervise(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<ExitStatus, ReduceError> {
+    let mut stdout = child.stdout.take();
+    let mut lines = Lines::new(MAX_LINE_BYTES);
+    let mut parser = ProgressParser::default();
+    let mut poll = tokio::time::interval(INTERRUPT_POLL);
+    let deadline = tokio::time::sleep(timeout.unwrap_or(Duration::MAX));
+    tokio::pin!(deadline);
+
+    let status = loop {
+        tokio::select! {
+            chunk = next_chunk(&mut stdout) => match chunk {
+                Some(chunk) => {
+                    for line in lines.feed(&chunk) {
+                        if let Some(progress) = parser.feed(&line) {
+                            on_progress(&progress);
+                        }
+                    }
+                }
+                None => stdout = None,
+            },
+            status = child.wait() => {
+                break status
+                    .map_err(|e| ReduceError::Encode(format!("cannot wait for ffmpeg: {}", e)))?;
+            }
+            _ = poll.tick() => {
+                if interrupt::is_interrupted() {
+                    kill(child).await;
+                    return Err(ReduceError::Interrupted);
+                }
+            }
+            _ = &mut deadline, if timeout.is_some() => {
+                kill(child).await;
+                return Err(ReduceError::Timeout(timeout.unwrap_or_default()));
+            }
+        }
+    };
+    // The last snapshot (progress=end) may still be buffered after exit.
+    let mut rest = Vec::new();
+    if let Some(out) = stdout.as_mut() {
+        // Not bounded by a line: a child that exited has stopped writing.
+        let _ = out.read_to_end(&mut rest).await;
+    }
+    for line in lines.feed(&rest).into_iter().chain(lines.finish()) {
+        if let Some(progress) = parser.feed(&line) {
+            on_progress(&progress);
+        }
+    }
+    Ok(status)
+}
+
+/// The next chunk from an optional reader, `None` at its end or on an
+/// error; pends forever once it is gone so `select!` stops polling it.
+async fn next_chunk<R: AsyncRead + Unpin>(reader: &mut Option<R>) -> Option<Vec<u8>> {
+    let Some(reader) = reader else {
+        return std::future::pending().await;
+    };
+    let mut chunk = vec![0; READ_CHUNK];
+    match reader.read(&mut chunk).await {
+        Ok(0) | Err(_) => None,
+        Ok(n) => {
+            chunk.truncate(n);
+            Some(chunk)
+        }
+    }
+}
+
+/// On Windows, Ctrl-C goes to every process on the console, and ffmpeg
+/// treats it as a request to finish the output gracefully, which can take a
+/// while or stall. Starting it in its own process group keeps the event
+/// away from it: our handler sees the Ctrl-C and [`kill`] terminates the
+/// child outright, as it does on Unix.
+#[cfg(windows)]
+fn own_process_group(command: &mut Command) {
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+}
+
+/// Elsewhere ffmpeg stays in our process group, so a terminal's SIGINT
+/// reaches it too; it is killed either way.
+#[cfg(not(windows))]
+fn own_process_group(_command: &mut Command) {}
+
+async fn kill(child: &mut Child) {
+    let _ = child.kill().await;
+}
+
+/// Opens `path` to append the log of the ffmpeg run with `args` to,
+/// starting with the command.
+fn open_log(path: &Path, args: &[&str]) -> Result<File, ReduceError> {
+    let cannot = |e: io::Error| {
+        ReduceError::Encode(format!(
+            "cannot write the ffmpeg log {}: {}",
+            path.display(),
+            e
+        ))
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(cannot)?;
+    writeln!(file, "$ ffmpeg {}", args.join(" ")).map_err(cannot)?;
+    Ok(file)
+}
+
+/// Reads `reader` to the end on its own task, keeping its last bytes and
+/// appending each of its lines to `log`. A log that can't be written to
+/// any more is given up on, not the run.
+fn collect_tail<R: AsyncRead + Unpin + Send + 'static>(
+    mut reader: R,
+    mut log: Option<File>,
+) -> JoinHandle<Tail> {
+    tokio::spawn(async move {
+        let mut tail = Tail::new(STDERR_TAIL_BYTES);
+        let mut lines = Lines::new(MAX_LINE_BYTES);
+        let mut chunk = vec![0; READ_CHUNK];
+        loop {
+            let n = match reader.read(&mut chunk).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            tail.push(&chunk[..n]);
+            if let Some(file) = log.as_mut() {
+                // Whole lines in one write, so runs logging at once don't
+                // split each other's.
+                let mut text = String::new();
+                for line in lines.feed(&chunk[..n]) {
+                    text.push_str(&line);
+                    text.push('\n');
+                }
+                if !text.is_empty() && file.write_all(text.as_bytes()).is_err() {
+                    log = None;
+                }
+            }
+        }
+        if let (Some(file), Some(line)) = (log.as_mut(), lines.finish()) {
+            let _ = writeln!(file, "{}", line);
+        }
+        tail
+    })
+}
+
+fn reports_progress_on_stdout(args: &[&str]) -> bool {
+    args.windows(2)
+        .any(|pair| pair[0] == "-progress" && pair[1] == "pipe:1")
+}
+
+/// Whether a tool that was started exited as one that isn't installed
+/// properly: 127 is what the dynamic loader gives when a shared library is
+/// missing, 126 an executable that isn't one.
+fn cannot_start(status: ExitStatus) -> bool {
+    matches!(status.code(), Some(126 | 127))
+}
+
+/// Maps a failure to start `tool` onto the matching error category.
+fn spawn_error(tool: &'static str, err: io::Error) -> ReduceError {
+    if err.kind() == io::ErrorKind::NotFound {
+        ReduceError::ToolNotFound(tool)
+    } else {
+        ReduceError::Encode(format!("cannot run {}: {}", tool, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stdout_captured_only_for_progress() {
+        assert!(reports_progress_on_stdout(&[
+            "-y",
+            "-progress",
+            "pipe:1",
+            "-i",
+            "in.mp4"
+        ]));
+        assert!(!reports_progress_on_stdout(&[
+            "-y", "-i", "in.mp4", "-f", "mp4", "pipe:1"
+        ]));
+    }
+
+    #[test]
+    fn test_missing_executable_is_tool_not_found() {
+        let err = spawn_error("ffmpeg", io::Error::from(io::ErrorKind::NotFound));
+        assert!(matches!(err, ReduceError::ToolNotFound("ffmpeg")));
+        let err = spawn_error("ffmpeg", io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(err, ReduceError::Encode(_)));
+    }
+
+    /// ffprobe fixtures as shell scripts: (script, the duration it yields
+    /// or the end of its error).
+    #[cfg(unix)]
+    const PROBE_RUNS: [(&str, Result<f64, &str>); 4] = [
+        (
+            "echo '[h264 @ 0xa] corrupt decoded frame' >&2; echo 12.5",
+            Ok(12.5),
+        ),
+        (
+            "echo '[h264 @ 0xa] corrupt decoded frame' >&2; echo N/A",
+            Err("in.mp4: invalid float literal (ffprobe said: [h264 @ 0xa] corrupt decoded frame)"),
+        ),
+        (
+            "echo 12.5; echo 'in.mp4: Invalid data found when processing input' >&2; exit 1",
+            Err("failed: in.mp4: Invalid data found when processing input"),
+        ),
+        (
+            "echo N/A; echo 'in.mp4: Invalid data found when processing input' >&2; exit 1",
+            Err("failed: in.mp4: Invalid data found when processing input"),
+        ),
+    ];
+
+    #[cfg(unix)]
+    #[test]
+    fn test_the_exit_status_decides_and_stderr_explains() {
+        for (script, expected) in PROBE_RUNS {
+            let duration = block_on(capture("sh", &["-c", script], None)).and_then(|probe| {
+                probe.parse(
+                    "cannot read duration of in.mp4",
+                    crate::tool::FfmpegTool::parse_duration,
+                )
+            });
+            match (duration, expected) {
+                (Ok(duration), Ok(expected)) => assert_eq!(duration, expected, "{}", script),
+                (Err(ReduceError::Probe(message)), Err(end)) => {
+                    assert!(message.ends_with(end), "{}: {}", script, message)
+                }
+                (duration, _) => panic!("{}: {:?}", script, duration),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stderr_of_a_successful_run_is_kept() {
+        let probe = block_on(capture(
+            "sh",
+            &["-c", "echo '{}'; echo 'odd pts' >&2"],
+            None,
+        ))
+        .unwrap();
+        assert_eq!(probe.stdout, "{}\n");
+        assert_eq!(probe.stderr, "odd pts\n");
+        // JSON output is parsed strictly, stderr or not.
+        let err = probe
+            .parse("cannot read in.mp4", crate::probe::parse_video_info)
+            .unwrap_err();
+        assert!(
+            err.to_string().ends_with("(ffprobe said: odd pts)"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! `--profile` and `--auto-profile`: the target size of a place the output
+//! is headed for.
+//!
+//! A profile stands for a destination's upload limit, so `--profile
+//! discord` saves looking up what Discord takes. Without `--size` the
+//! output's folders are also looked at: one named after a destination,
+//! such as `~/Videos/Discord/clip.mp4`, [`infer`]s its profile. That is
+//! only suggested unless `--auto-profile` is given, and never wins over a
+//! size given by hand; [`choose`] has the order.
+
+use crate::ratecontrol::Mode;
+use crate::sizefmt::SizeFormat;
+use clap::ValueEnum;
+use std::fmt;
+use std::path::{Component, Path};
+
+/// The target without `--size`, a profile or an inferred one, in
+/// megabytes of the chosen units.
+pub const DEFAULT_SIZE: &str = "100";
+
+/// A destination with a known upload limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// 10 MB, the limit without Nitro.
+    Discord,
+    /// 16 MB, the limit for videos sent as media.
+    Whatsapp,
+    /// 2 GB.
+    Telegram,
+}
+
+impl Profile {
+    const ALL: [Profile; 3] = [Profile::Discord, Profile::Whatsapp, Profile::Telegram];
+
+    /// The upload limit in bytes, in SI megabytes as the services state
+    /// them, so it holds whichever they mean.
+    pub fn target_bytes(self) -> u64 {
+        match self {
+            Profile::Discord => 10_000_000,
+            Profile::Whatsapp => 16_000_000,
+            Profile::Telegram => 2_000_000_000,
+        }
+    }
+
+    /// The `--mode` the destination pins, if any: chat apps take short
+    /// clips under a hard cap, which a capped constant quality suits best.
+    pub fn rate_mode(self) -> Option<Mode> {
+        match self {
+            Profile::Discord | Profile::Whatsapp => Some(Mode::CrfCapped),
+            Profile::Telegram => None,
+        }
+    }
+
+    /// Folder names that point at the destination, lowercase.
+    fn hints(self) -> &'static [&'static str] {
+        match self {
+            Profile::Discord => &["discord"],
+            Profile::Whatsapp => &["whatsapp"],
+            Profile::Telegram => &["telegram"],
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().expect("no skipped profiles");
+        f.write_str(value.get_name())
+    }
+}
+
+/// A profile read off the output's path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inference {
+    pub profile: Profile,
+    /// The folder that named it, as written.
+    pub folder: String,
+}
+
+impl Inference {
+    /// Why the profile was picked, for the status output.
+    pub fn reason(&self) -> String {
+        format!("the output is in the folder '{}'", self.folder)
+    }
+}
+
+/// The profile the folders of `dir` name, the innermost first: a word of
+/// a folder's name (split at anything but letters and digits) matches a
+/// destination, whatever its case, so `WhatsApp Video` names WhatsApp.
+pub fn infer(dir: &Path) -> Option<Inference> {
+    let folders: Vec<&str> = dir
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+    folders.iter().rev().find_map(|folder| {
+        let words: Vec<String> = folder
+            .split(|c: char| !c.is_alphanumeric())
+            .map(str::to_lowercase)
+            .collect();
+        Profile::ALL
+            .into_iter()
+            .find(|profile| {
+                profile
+                    .hints()
+                    .iter()
+                    .any(|hint| words.iter().any(|word| word == hint))
+            })
+            .map(|profile| Inference {
+                profile,
+                folder: folder.to_string(),
+            })
+    })
+}
+
+/// Where the target size came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// `--size` or `--probe-limit-url`.
+    Explicit,
+    Profile(Profile),
+    Inferred(Inference),
+    Default,
+}
+
+/// The target and where it came from: a size given by hand, else
+/// `profile`, else with `auto` the `inferred` profile, else `default`.
+pub fn choose(
+    explicit: Option<u64>,
+    profile: Option<Profile>,
+    inferred: Option<Inference>,
+    auto: bool,
+    default: u64,
+) -> (u64, Origin) {
+    if let Some(bytes) = explicit {
+        return (bytes, Origin::Explicit);
+    }
+    if let Some(profile) = profile {
+        return (profile.target_bytes(), Origin::Profile(profile));
+    }
+    match inferred {
+        Some(inference) if auto => (
+            inference.profile.target_bytes(),
+            Origin::Inferred(inference),
+        ),
+        _ => (default, Origin::Default),
+    }
+}
+
+/// What to print about `inferred` when the target came from `origin`:
+/// that it was applied, or else what `--auto-profile` would do; nothing
+/// when a size was given by hand.
+pub fn describe(
+    origin: &Origin,
+    inferred: Option<&Inference>,
+    sizes: SizeFormat,
+) -> Option<String> {
+    match (origin, inferred) {
+        (Origin::Inferred(inference), _) => Some(format!(
+            "Targeting {} for {}, because {} (--size overrides it)",
+            sizes.size(inference.profile.target_bytes()),
+            inference.profile,
+            inference.reason()
+        )),
+        (Origin::Default, Some(inference)) => Some(format!(
+            "{}; --auto-profile would target {} for {} instead",
+            capitalize(&inference.reason()),
+            sizes.size(inference.profile.target_bytes()),
+            inference.profile
+        )),
+        _ => None,
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::size::SizeUnits;
+
+    fn inferred(profile: Profile) -> Option<Inference> {
+        Some(Inference {
+            profile,
+            folder: profile.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_folders_name_profiles() {
+        // (directory, profile and folder)
+        let cases = [
+            (
+                "/home/me/Videos/Discord",
+                Some((Profile::Discord, "Discord")),
+            ),
+            (
+                "/sdcard/WhatsApp/Media/WhatsApp Video",
+                Some((Profile::Whatsapp, "WhatsApp Video")),
+            ),
+            (
+                "out/for-telegram/2024",
+                Some((Profile::Telegram, "for-telegram")),
+            ),
+            // The innermost folder wins.
+            ("discord/whatsapp", Some((Profile::Whatsapp, "whatsapp"))),
+            ("/home/me/Videos", None),
+            // Whole words only.
+            ("/home/me/discordant", None),
+            ("", None),
+        ];
+        for (dir, expected) in cases {
+            let found = infer(Path::new(dir));
+            assert_eq!(
+                found.as_ref().map(|i| (i.profile, i.folder.as_str())),
+                expected,
+                "{}",
+                dir
+            );
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let default = 100 << 20;
+        let discord = Profile::Discord.target_bytes();
+        let whatsapp = Profile::Whatsapp.target_bytes();
+        // (explicit, profile, inferred, auto, bytes, origin)
+        let cases = [
+            (None, None, None, false, default, Origin::Default),
+            (None, None, None, true, default, Origin::Default),
+            // Inferred profiles are only suggested without --auto-profile.
+            (
+                None,
+                None,
+                inferred(Profile::Discord),
+                false,
+                default,
+                Origin::Default,
+            ),
+            (
+                None,
+                None,
+                inferred(Profile::Discord),
+                true,
+                discord,
+                Origin::Inferred(inferred(Profile::Discord).unwrap()),
+            ),
+            // --profile wins over the folder.
+            (
+                None,
+                Some(Profile::Whatsapp),
+                inferred(Profile::Discord),
+                true,
+                whatsapp,
+                Origin::Profile(Profile::Whatsapp),
+            ),
+            // And --size over everything.
+            (
+                Some(5),
+                Some(Profile::Whatsapp),
+                inferred(Profile::Discord),
+                true,
+                5,
+                Origin::Explicit,
+            ),
+            (
+                Some(5),
+                None,
+                inferred(Profile::Discord),
+                true,
+                5,
+                Origin::Explicit,
+            ),
+        ];
+        for (explicit, profile, inference, auto, bytes, origin) in cases {
+            let chosen = choose(explicit, profile, inference.clone(), auto, default);
+            assert_eq!(
+                chosen,
+                (bytes, origin),
+                "{:?} {:?} {:?} {}",
+                explicit,
+                profile,
+                inference,
+                auto
+            );
+        }
+    }
+
+    #[test]
+    fn test_inferences_are_always_explained() {
+        let sizes = SizeUnits::Si.into();
+        let inference = inferred(Profile::Discord).unwrap();
+        let applied = describe(&Origin::Inferred(inference.clone()), None, sizes).unwrap();
+        assert_eq!(
+            applied,
+            "Targeting 10 MB for discord, because the output is in the folder 'discord' (--size overrides it)"
+        );
+        let suggested = describe(&Origin::Default, Some(&inference), sizes).unwrap();
+        assert_eq!(
+            suggested,
+            "The output is in the folder 'discord'; --auto-profile would target 10 MB for discord instead"
+        );
+        assert_eq!(describe(&Origin::Explicit, Some(&inference), sizes), None);
+        assert_eq!(
+            describe(&Origin::Profile(Profile::Whatsapp), Some(&inference), sizes),
+            None
+        );
+        assert_eq!(describe(&Origin::Default, None, sizes), None);
+    }
+}
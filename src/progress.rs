@@ -0,0 +1,83 @@
+//! Parsing of ffmpeg's machine-readable `-progress` output.
+
+/// A snapshot of encode progress emitted by ffmpeg once per reporting period.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Progress {
+    /// Position of the encoded output in seconds.
+    pub out_time: f64,
+    /// Encoding speed relative to real time, if ffmpeg reported one.
+    pub speed: Option<f64>,
+    /// Whether this is the final report (`progress=end`).
+    pub done: bool,
+}
+
+/// Accumulates `key=value` lines from `-progress` into [`Progress`] snapshots.
+#[derive(Debug, Default)]
+pub struct ProgressParser {
+    current: Progress,
+}
+
+impl ProgressParser {
+    /// Feeds one line; returns a snapshot when the line closes a report block.
+    pub fn feed(&mut self, line: &str) -> Option<Progress> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+        match key {
+            // Despite the name, out_time_ms is in microseconds, like out_time_us.
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(us) = value.parse::<i64>() {
+                    self.current.out_time = us.max(0) as f64 / 1_000_000.0;
+                }
+            }
+            "speed" => {
+                self.current.speed = value.trim_end_matches('x').trim().parse().ok();
+            }
+            "progress" => {
+                self.current.done = value == "end";
+                return Some(self.current.clone());
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_progress_blocks() {
+        let output = "frame=120\nfps=48.0\nout_time_us=4000000\nout_time_ms=4000000\n\
+                      out_time=00:00:04.000000\nspeed=1.91x\nprogress=continue\n\
+                      frame=240\nout_time_us=8000000\nspeed=2.05x\nprogress=end\n";
+        let mut parser = ProgressParser::default();
+        let reports: Vec<Progress> = output.lines().filter_map(|l| parser.feed(l)).collect();
+        assert_eq!(
+            reports,
+            vec![
+                Progress {
+                    out_time: 4.0,
+                    speed: Some(1.91),
+                    done: false
+                },
+                Progress {
+                    out_time: 8.0,
+                    speed: Some(2.05),
+                    done: true
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreported_speed_and_garbage_lines() {
+        let mut parser = ProgressParser::default();
+        assert_eq!(parser.feed("not a progress line"), None);
+        assert_eq!(parser.feed("out_time_us=N/A"), None);
+        assert_eq!(parser.feed("speed=N/A"), None);
+        let report = parser.feed("progress=continue").unwrap();
+        assert_eq!(report.speed, None);
+        assert_eq!(report.out_time, 0.0);
+    }
+}
@@ -0,0 +1,206 @@
+//! Every question the tool asks goes through a [`Prompter`], which decides
+//! whether to ask at all: `--yes` takes the default answer, and without a
+//! terminal on stdin a question that guards something destructive is an
+//! error rather than a read that blocks (or consumes piped data).
+
+use crate::error::ReduceError;
+use crate::presenter::Presenter;
+use std::io::{self, BufRead, IsTerminal};
+
+/// What to do about one question.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Ask,
+    /// Take the default answer without reading anything.
+    AssumeDefault,
+    /// Nobody can answer, and the default isn't safe to assume.
+    Refuse,
+}
+
+/// The decision for a question, given `--yes`, whether stdin is a terminal
+/// and whether the question guards a destructive action.
+pub fn decide(assume_yes: bool, terminal: bool, destructive: bool) -> Decision {
+    if assume_yes {
+        Decision::AssumeDefault
+    } else if terminal {
+        Decision::Ask
+    } else if destructive {
+        Decision::Refuse
+    } else {
+        Decision::AssumeDefault
+    }
+}
+
+/// Asks questions on `input`, reporting through `out`.
+pub struct Prompter<R> {
+    input: R,
+    out: Presenter,
+    assume_yes: bool,
+    terminal: bool,
+}
+
+impl Prompter<io::StdinLock<'static>> {
+    /// A prompter on the process's stdin.
+    pub fn stdin(out: Presenter, assume_yes: bool) -> Self {
+        let terminal = io::stdin().is_terminal();
+        Self::new(io::stdin().lock(), out, assume_yes, terminal)
+    }
+}
+
+impl<R: BufRead> Prompter<R> {
+    pub fn new(input: R, out: Presenter, assume_yes: bool, terminal: bool) -> Self {
+        Self {
+            input,
+            out,
+            assume_yes,
+            terminal,
+        }
+    }
+
+    /// Fails up front when a destructive question couldn't be answered,
+    /// before any work is done. `what` names the feature that would ask.
+    pub fn check(&self, what: &str) -> Result<(), ReduceError> {
+        match decide(self.assume_yes, self.terminal, true) {
+            Decision::Refuse => Err(ReduceError::Usage(format!(
+                "{} needs a terminal on stdin; pass --yes to accept the defaults",
+                what
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Asks `question` with "yes" as the default. Closed input counts as
+    /// "no".
+    pub fn confirm(&mut self, question: &str, destructive: bool) -> Result<bool, ReduceError> {
+        match decide(self.assume_yes, self.terminal, destructive) {
+            Decision::Ask => {
+                self.out.raw(&format!("{} [Y/n] ", question));
+                Ok(match self.read_answer()? {
+                    Some(answer) => {
+                        matches!(answer.to_ascii_lowercase().as_str(), "" | "y" | "yes")
+                    }
+                    None => false,
+                })
+            }
+            Decision::AssumeDefault => {
+                self.out.info(&format!("{} [Y/n] y", question));
+                Ok(true)
+            }
+            Decision::Refuse => Err(self.refusal(question)),
+        }
+    }
+
+    /// Asks for one of `count` numbered choices, returning its 0-based
+    /// index; `default` is what `--yes` picks. `None` when the input closed.
+    pub fn choose(
+        &mut self,
+        count: usize,
+        default: usize,
+        destructive: bool,
+    ) -> Result<Option<usize>, ReduceError> {
+        let question = format!("Choice [1-{}]:", count);
+        match decide(self.assume_yes, self.terminal, destructive) {
+            Decision::Ask => loop {
+                self.out.raw(&format!("{} ", question));
+                let Some(answer) = self.read_answer()? else {
+                    return Ok(None);
+                };
+                match answer.parse::<usize>() {
+                    Ok(n) if (1..=count).contains(&n) => return Ok(Some(n - 1)),
+                    _ => self.out.info("Please enter one of the numbers above."),
+                }
+            },
+            Decision::AssumeDefault => {
+                self.out.info(&format!("{} {}", question, default + 1));
+                Ok(Some(default))
+            }
+            Decision::Refuse => Err(self.refusal(&question)),
+        }
+    }
+
+    fn refusal(&self, question: &str) -> ReduceError {
+        ReduceError::Usage(format!(
+            "cannot ask \"{}\" without a terminal on stdin; pass --yes to accept the default",
+            question
+        ))
+    }
+
+    /// Reads one trimmed line; `None` at end of input.
+    fn read_answer(&mut self) -> Result<Option<String>, ReduceError> {
+        let mut line = String::new();
+        let read = self
+            .input
+            .read_line(&mut line)
+            .map_err(|e| ReduceError::Usage(format!("cannot read answer: {}", e)))?;
+        Ok((read > 0).then(|| line.trim().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presenter::ColorChoice;
+    use std::io::Cursor;
+
+    fn prompter(input: &str, assume_yes: bool, terminal: bool) -> Prompter<Cursor<String>> {
+        Prompter::new(
+            Cursor::new(input.to_string()),
+            Presenter::stderr(ColorChoice::Never),
+            assume_yes,
+            terminal,
+        )
+    }
+
+    #[test]
+    fn test_decision_matrix() {
+        use Decision::*;
+        // (--yes, terminal, destructive)
+        assert_eq!(decide(true, true, true), AssumeDefault);
+        assert_eq!(decide(true, true, false), AssumeDefault);
+        assert_eq!(decide(true, false, true), AssumeDefault);
+        assert_eq!(decide(true, false, false), AssumeDefault);
+        assert_eq!(decide(false, true, true), Ask);
+        assert_eq!(decide(false, true, false), Ask);
+        assert_eq!(decide(false, false, true), Refuse);
+        assert_eq!(decide(false, false, false), AssumeDefault);
+    }
+
+    #[test]
+    fn test_confirm_reads_answers_on_a_terminal() {
+        assert!(prompter("\n", false, true).confirm("Go?", true).unwrap());
+        assert!(prompter("YES\n", false, true).confirm("Go?", true).unwrap());
+        assert!(!prompter("n\n", false, true).confirm("Go?", true).unwrap());
+        assert!(!prompter("", false, true).confirm("Go?", true).unwrap());
+    }
+
+    #[test]
+    fn test_yes_and_missing_terminal() {
+        // --yes never reads, even where an answer is waiting.
+        assert!(prompter("n\n", true, true).confirm("Go?", true).unwrap());
+        assert!(prompter("", false, false).confirm("Go?", false).unwrap());
+        let err = prompter("y\n", false, false)
+            .confirm("Go?", true)
+            .unwrap_err();
+        assert!(matches!(&err, ReduceError::Usage(m) if m.contains("\"Go?\"")));
+
+        assert!(prompter("", false, false).check("--interactive").is_err());
+        assert!(prompter("", true, false).check("--interactive").is_ok());
+        assert!(prompter("", false, true).check("--interactive").is_ok());
+    }
+
+    #[test]
+    fn test_choose() {
+        assert_eq!(
+            prompter("0\nx\n2\n", false, true)
+                .choose(3, 2, false)
+                .unwrap(),
+            Some(1)
+        );
+        assert_eq!(prompter("", false, true).choose(3, 2, false).unwrap(), None);
+        assert_eq!(
+            prompter("1\n", true, true).choose(3, 2, false).unwrap(),
+            Some(2)
+        );
+        assert!(prompter("", false, false).choose(3, 2, true).is_err());
+    }
+}
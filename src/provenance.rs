@@ -0,0 +1,198 @@
+//! `--tag-metadata`: a note in the output of how it was made.
+//!
+//! The tag goes into the container's `comment` field, e.g.
+//! `mdviqure 0.1.0: target=50MiB codec=h264 preset=slow br=3200k
+//! audio=128k`, and `mdviqure probe` shows it. It describes the settings
+//! only: file names and directories stay out of it, and so do encoder
+//! options whose value looks like a path.
+
+use crate::encoder::{EncoderParams, Preset, VideoEncoder};
+use crate::size::SizeUnits;
+use crate::sizefmt::{Separators, SizeFormat};
+use clap::ValueEnum;
+
+/// What every tag starts with, which is how `probe` tells ours apart from
+/// any other comment.
+pub const PREFIX: &str = "mdviqure ";
+
+/// Longest tag written, in bytes. Players and taggers that cut comments
+/// short do so well above this.
+pub const MAX_TAG_BYTES: usize = 255;
+
+/// The settings an encode ran with.
+#[derive(Debug, Clone, Copy)]
+pub struct Provenance<'a> {
+    pub target_bytes: u64,
+    pub size_units: SizeUnits,
+    pub encoder: VideoEncoder,
+    pub preset: Preset,
+    /// The `-b:v` value, such as `3200k`; `None` for a copied video.
+    pub video_bitrate: Option<&'a str>,
+    /// Total audio bitrate in bits per second; 0 without audio.
+    pub audio_bitrate: u64,
+    pub encoder_params: Option<&'a EncoderParams>,
+}
+
+impl Provenance<'_> {
+    /// The tag, at most [`MAX_TAG_BYTES`] long.
+    pub fn tag(&self) -> String {
+        let codec = self
+            .encoder
+            .to_possible_value()
+            .expect("no skipped encoders");
+        let mut fields = vec![
+            format!(
+                "target={}",
+                SizeFormat::new(self.size_units, Separators::NONE)
+                    .size(self.target_bytes)
+                    .replace(' ', "")
+            ),
+            format!("codec={}", codec.get_name()),
+        ];
+        match self.video_bitrate {
+            Some(bitrate) => {
+                fields.push(format!("preset={}", self.encoder.preset_value(self.preset)));
+                fields.push(format!("br={}", bitrate));
+            }
+            None => fields.push("br=copy".to_string()),
+        }
+        fields.push(match self.audio_bitrate {
+            0 => "audio=none".to_string(),
+            bitrate => format!("audio={}k", bitrate / 1000),
+        });
+        if let Some(params) = self.encoder_params.filter(|_| self.video_bitrate.is_some()) {
+            let shown: Vec<String> = params
+                .to_string()
+                .split(':')
+                .filter(|pair| !pair.contains(['/', '\\']))
+                .map(str::to_string)
+                .collect();
+            if !shown.is_empty() {
+                fields.push(format!(
+                    "{}={}",
+                    params.encoder.params_option(),
+                    shown.join(":")
+                ));
+            }
+        }
+        let tag = format!(
+            "{}{}: {}",
+            PREFIX,
+            env!("CARGO_PKG_VERSION"),
+            fields.join(" ")
+        );
+        truncate(&tag, MAX_TAG_BYTES)
+    }
+
+    /// The ffmpeg options writing the tag.
+    pub fn metadata_args(&self) -> [String; 2] {
+        ["-metadata".to_string(), format!("comment={}", self.tag())]
+    }
+}
+
+/// `text` cut to at most `max` bytes, on a character boundary.
+pub fn truncate(text: &str, max: usize) -> String {
+    if text.len() <= max {
+        return text.to_string();
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+/// Whether a file's comment is a tag this tool wrote.
+pub fn is_tag(comment: &str) -> bool {
+    comment.starts_with(PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mib;
+
+    fn provenance() -> Provenance<'static> {
+        Provenance {
+            target_bytes: mib(50),
+            size_units: SizeUnits::Binary,
+            encoder: VideoEncoder::H264,
+            preset: Preset::Slow,
+            video_bitrate: Some("3200k"),
+            audio_bitrate: 128_000,
+            encoder_params: None,
+        }
+    }
+
+    #[test]
+    fn test_tag_describes_the_settings() {
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(
+            provenance().tag(),
+            format!(
+                "mdviqure {}: target=50MiB codec=h264 preset=slow br=3200k audio=128k",
+                version
+            )
+        );
+        let copied = Provenance {
+            encoder: VideoEncoder::SvtAv1,
+            video_bitrate: None,
+            audio_bitrate: 0,
+            size_units: SizeUnits::Si,
+            target_bytes: 25_000_000,
+            ..provenance()
+        };
+        assert_eq!(
+            copied.tag(),
+            format!(
+                "mdviqure {}: target=25MB codec=svt-av1 br=copy audio=none",
+                version
+            )
+        );
+        assert!(is_tag(&copied.tag()));
+        assert!(!is_tag("Encoded with HandBrake"));
+        let [option, value] = provenance().metadata_args();
+        assert_eq!(option, "-metadata");
+        assert!(value.starts_with("comment=mdviqure "), "{}", value);
+    }
+
+    #[test]
+    fn test_encoder_options_with_paths_stay_out() {
+        let params = EncoderParams::parse(
+            VideoEncoder::H264,
+            "aq-mode=3:zones=/home/me/zones.txt:ref=4",
+        )
+        .unwrap();
+        let tag = Provenance {
+            encoder_params: Some(&params),
+            ..provenance()
+        }
+        .tag();
+        assert!(tag.ends_with(" x264-params=aq-mode=3:ref=4"), "{}", tag);
+        let windows = EncoderParams::parse(VideoEncoder::H264, r"qpfile=C\clip.qp").unwrap();
+        let tag = Provenance {
+            encoder_params: Some(&windows),
+            ..provenance()
+        }
+        .tag();
+        assert!(!tag.contains("x264-params"), "{}", tag);
+    }
+
+    #[test]
+    fn test_long_tags_are_cut_on_a_character_boundary() {
+        let long = format!("ref=4:psy-rd={}", "1.0,".repeat(100));
+        let params = EncoderParams::parse(VideoEncoder::H264, &long).unwrap();
+        let tag = Provenance {
+            encoder_params: Some(&params),
+            ..provenance()
+        }
+        .tag();
+        assert_eq!(tag.len(), MAX_TAG_BYTES);
+        assert!(tag.starts_with("mdviqure "));
+
+        assert_eq!(truncate("short", 10), "short");
+        // 'é' takes two bytes, so the cut falls before it.
+        assert_eq!(truncate("abcé", 4), "abc");
+        assert_eq!(truncate("abcé", 5), "abcé");
+    }
+}
@@ -0,0 +1,226 @@
+//! `--mode`: how the encoder is held to the video bitrate.
+//!
+//! An average bitrate (`abr`, two passes with `--two-pass`) spends the
+//! whole budget and lands near the target, which a long video needs. A
+//! constant quality capped at that bitrate (`crf-capped`) only spends what
+//! the picture needs: easy scenes come out smaller, and the cap still keeps
+//! the file from going over. On a short clip with bits to spare that looks
+//! at least as good and never wastes the budget on noise, but over a long
+//! video the savings pile up into a file far under the target. [`choose`]
+//! decides for `auto`, from the length and the bits per pixel the plan
+//! allows.
+
+use crate::encoder::VideoEncoder;
+use crate::estimate::{self, Codec, Quality};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// The longest encode `auto` gives a constant quality.
+pub const MAX_CRF_LENGTH: f64 = 60.0;
+
+/// The rate-control buffer of a capped constant quality, in seconds at the
+/// cap.
+pub const BUFFER_SECONDS: u64 = 2;
+
+/// The rate control asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Mode {
+    /// Pick from the length and the bits per pixel.
+    #[default]
+    Auto,
+    /// An average bitrate.
+    Abr,
+    /// A constant quality, capped at the bitrate.
+    CrfCapped,
+}
+
+/// The rate control an encode runs with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateControl {
+    #[default]
+    Abr,
+    CrfCapped,
+}
+
+impl RateControl {
+    pub fn is_abr(&self) -> bool {
+        *self == RateControl::Abr
+    }
+}
+
+/// What an encode of `length` seconds at `kbps` into `width`x`height` at
+/// `fps` runs with for `mode`, and why. `two_pass` asks for an average
+/// bitrate, the only one with two passes.
+pub fn choose(
+    mode: Mode,
+    two_pass: bool,
+    length: f64,
+    (width, height, fps): (u32, u32, f64),
+    kbps: u64,
+    codec: Codec,
+) -> (RateControl, String) {
+    match mode {
+        Mode::Abr => return (RateControl::Abr, "as --mode abr asks".to_string()),
+        Mode::CrfCapped => {
+            return (
+                RateControl::CrfCapped,
+                "as --mode crf-capped asks".to_string(),
+            )
+        }
+        Mode::Auto => {}
+    }
+    if two_pass {
+        return (RateControl::Abr, "as --two-pass asks".to_string());
+    }
+    if length > MAX_CRF_LENGTH {
+        return (
+            RateControl::Abr,
+            format!(
+                "the encode is {:.0}s, longer than the {:.0}s a constant quality stays near the target for",
+                length, MAX_CRF_LENGTH
+            ),
+        );
+    }
+    let bpp = estimate::bits_per_pixel(kbps * 1000, width, height, fps);
+    let comfortable = codec.min_bpp(Quality::Good);
+    if bpp < comfortable {
+        return (
+            RateControl::Abr,
+            format!(
+                "{:.3} bits per pixel, under the {:.3} a good picture needs, leave nothing to save",
+                bpp, comfortable
+            ),
+        );
+    }
+    (
+        RateControl::CrfCapped,
+        format!(
+            "a {:.1}s clip with {:.3} bits per pixel, at least the {:.3} a good picture needs",
+            length, bpp, comfortable
+        ),
+    )
+}
+
+/// The constant quality `encoder` is asked for: a good picture, which the
+/// cap lowers where the budget can't pay for it.
+pub fn crf(encoder: VideoEncoder) -> u32 {
+    match encoder {
+        VideoEncoder::H264 => 23,
+        VideoEncoder::SvtAv1 => 35,
+    }
+}
+
+/// The options for a constant quality capped at `kbps`.
+pub fn crf_capped_args(encoder: VideoEncoder, kbps: u64) -> Vec<String> {
+    vec![
+        "-crf".to_string(),
+        crf(encoder).to_string(),
+        "-maxrate".to_string(),
+        format!("{}k", kbps),
+        "-bufsize".to_string(),
+        format!("{}k", kbps * BUFFER_SECONDS),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_picks_by_length_and_bits_per_pixel() {
+        const HD: (u32, u32, f64) = (1920, 1080, 30.0);
+        // (mode, two_pass, length, frame, kbps, chosen, reason contains)
+        let cases = [
+            (
+                Mode::Auto,
+                false,
+                20.0,
+                HD,
+                8000,
+                RateControl::CrfCapped,
+                "a 20.0s clip with 0.129",
+            ),
+            (
+                Mode::Auto,
+                false,
+                20.0,
+                HD,
+                4000,
+                RateControl::Abr,
+                "0.064 bits per pixel",
+            ),
+            (
+                Mode::Auto,
+                false,
+                20.0,
+                (1280, 720, 30.0),
+                4000,
+                RateControl::CrfCapped,
+                "0.145",
+            ),
+            (
+                Mode::Auto,
+                false,
+                60.0,
+                HD,
+                8000,
+                RateControl::CrfCapped,
+                "a 60.0s clip",
+            ),
+            (
+                Mode::Auto,
+                false,
+                600.0,
+                HD,
+                8000,
+                RateControl::Abr,
+                "the encode is 600s",
+            ),
+            (
+                Mode::Auto,
+                true,
+                20.0,
+                HD,
+                8000,
+                RateControl::Abr,
+                "--two-pass",
+            ),
+            (
+                Mode::Abr,
+                false,
+                20.0,
+                HD,
+                8000,
+                RateControl::Abr,
+                "--mode abr",
+            ),
+            (
+                Mode::CrfCapped,
+                false,
+                600.0,
+                HD,
+                500,
+                RateControl::CrfCapped,
+                "--mode crf-capped",
+            ),
+        ];
+        for (mode, two_pass, length, frame, kbps, chosen, reason) in cases {
+            let (control, why) = choose(mode, two_pass, length, frame, kbps, Codec::H264);
+            assert_eq!(control, chosen, "{:?} {}s {}k: {}", mode, length, kbps, why);
+            assert!(why.contains(reason), "{}", why);
+        }
+        // AV1 gets a good picture from fewer bits.
+        let (control, _) = choose(Mode::Auto, false, 20.0, HD, 4000, Codec::Av1);
+        assert_eq!(control, RateControl::CrfCapped);
+    }
+
+    #[test]
+    fn test_crf_capped_args() {
+        assert_eq!(
+            crf_capped_args(VideoEncoder::H264, 800).join(" "),
+            "-crf 23 -maxrate 800k -bufsize 1600k"
+        );
+        assert_eq!(crf_capped_args(VideoEncoder::SvtAv1, 800)[1], "35");
+    }
+}
@@ -0,0 +1,7156 @@
+//! Bitrate planning and the re-encode workflow.
+
+use crate::accuracy::Prediction;
+use crate::aspect::{self, Ratio};
+use crate::audio::{self, AudioCodec, AudioEncoder, AudioSelection, KeptTrack};
+use crate::budget::{Attempt, AttemptKind, Budget, Ledger, Outcome};
+use crate::checksum::{self, Algorithm, Checksums, FileChecksum, Pending};
+use crate::chunked;
+use crate::commit;
+use crate::console::Console;
+use crate::container::{Container, Remux};
+use crate::coverart;
+use crate::datastream;
+use crate::deterministic;
+use crate::device::Compat;
+use crate::diskspace::{self, Needs};
+use crate::effort;
+use crate::encoder::{EncoderParams, Preset, VideoEncoder};
+use crate::error::ReduceError;
+use crate::estimate::{self, estimate_encode_seconds, format_duration, Quality};
+use crate::events::{self, Event, Phase};
+use crate::filename;
+use crate::filetarget::TargetSource;
+use crate::filter::{even_dimensions, EvenMode, FilterChain, FilterGraph};
+use crate::floor::{self, Floor};
+use crate::fonts;
+use crate::hls::{self, Hls};
+use crate::hwdecode::{self, Accel, HwDecode};
+use crate::images::{ImageInput, ImageSource};
+use crate::interrupt;
+use crate::longpath;
+use crate::looping;
+use crate::mp4box;
+use crate::outdir;
+use crate::overhead::{Learned, Sample};
+use crate::padding;
+use crate::passstats::{self, Stats};
+use crate::presenter::Presenter;
+use crate::probe::{LengthMismatch, VideoInfo};
+use crate::progress::Progress;
+use crate::provenance::Provenance;
+use crate::ratecontrol::{self, Mode, RateControl};
+use crate::region::Region;
+use crate::session;
+use crate::sidecar::{self, Recipe, Settings, Sidecar, SourceSummary};
+use crate::size::SizeUnits;
+use crate::sizefmt::SizeFormat;
+use crate::sleep;
+use crate::subtitles;
+use crate::tempdir::RunTempDir;
+use crate::terminal::OutputMode;
+use crate::tool::{Recording, VideoTool};
+use crate::transient::{self, Retries};
+use crate::twopass::{self, Passes};
+use crate::unsupported;
+use crate::url;
+use crate::usage::{Meter, Usage};
+use crate::validate::{self, Capabilities};
+use crate::vertical::{self, Canvas};
+use crate::warning::{self, Code, Warning};
+use crate::window;
+use crate::STDIO_PATH;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Options controlling how a single video is reduced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReduceOptions {
+    /// Target size in bytes.
+    pub target_bytes: u64,
+    /// Unit system sizes given on the command line or in a manifest are
+    /// read in.
+    pub size_units: SizeUnits,
+    /// How sizes are displayed; see [`crate::sizefmt`].
+    pub sizes: SizeFormat,
+    /// How odd frame dimensions are fixed before encoding.
+    pub even_mode: EvenMode,
+    /// Downscale (keeping the aspect ratio) when the source is wider than this.
+    pub max_width: Option<u32>,
+    /// Downscale when the source is taller than this, whatever the budget
+    /// allows; with `max_width` too, the smaller result wins.
+    pub max_height: Option<u32>,
+    /// Lay the output out on a 9:16 portrait canvas (`--vertical`), in
+    /// place of `max_width` and `max_height`.
+    pub vertical: Option<vertical::Fit>,
+    /// Encode only this rectangle of the frame (`--region`), cropped
+    /// before any scaling.
+    pub region: Option<Region>,
+    /// Output frame rate, if it should be changed.
+    pub fps: Option<f64>,
+    /// Highest output frame rate, applied over `fps`, `cfr` and the
+    /// source's own rate.
+    pub max_fps: Option<f64>,
+    /// Normalize to a constant frame rate (`--cfr`): `Some(Some(rate))` for
+    /// an explicit rate, `Some(None)` for the rounded average of the source.
+    pub cfr: Option<Option<f64>>,
+    /// Print probe details and other diagnostics.
+    pub verbose: bool,
+    /// Video encoder for re-encodes.
+    pub encoder: VideoEncoder,
+    /// Encoder speed preset.
+    pub preset: Preset,
+    /// The `--effort` level `preset` came from, if it was given; a different
+    /// `preset` means `--preset` overrode it.
+    pub effort: Option<u8>,
+    /// Options passed through to the encoder library; left out when the
+    /// video is encoded with a different encoder (say, after a fallback).
+    pub encoder_params: Option<EncoderParams>,
+    /// Refuse to start (or switch to a faster preset) when the estimated
+    /// encode time exceeds this many minutes.
+    pub max_encode_minutes: Option<f64>,
+    /// Where to create the per-run temp directory (defaults to the system temp dir).
+    pub temp_dir: Option<PathBuf>,
+    /// Leave the per-run temp directory in place for debugging.
+    pub keep_temp: bool,
+    /// Re-encode at a lower bitrate up to this many times when the output
+    /// comes out larger than the target.
+    pub max_retries: u32,
+    /// How often probing the input and starting an encode are retried
+    /// after a failure that may pass; see [`crate::transient`].
+    pub transient_retries: Retries,
+    /// `--max-attempts` and `--max-total-time`, across all of the above;
+    /// see [`crate::budget`].
+    pub budget: Budget,
+    /// Drop the audio track, leaving its share of the budget to the video.
+    pub no_audio: bool,
+    /// Split into this many equal-length parts, each within the target size.
+    pub parts: u32,
+    /// Encode the video as this many chunks at once and join them; 1
+    /// encodes it in one piece.
+    pub chunks: u32,
+    /// When to color status output, and whether to keep it plain.
+    pub output_mode: OutputMode,
+    /// Input duration in seconds, overriding what ffprobe reports.
+    pub duration: Option<f64>,
+    /// Encode only this many seconds from the start, at the bitrate planned
+    /// for the whole input (`--sample`). The caller picks the output name.
+    pub sample: Option<f64>,
+    /// Encode at most this many seconds, or with `parts` above one, make
+    /// no part longer; see [`crate::window`].
+    pub max_duration: Option<f64>,
+    /// Seconds of the input to skip before the encoded window.
+    pub window_start: Option<f64>,
+    /// Measure the duration by decoding the input even when the container
+    /// reports one.
+    pub trust_decode_duration: bool,
+    /// Read the input as an image sequence or a looped still. Images carry
+    /// no audio, so `no_audio` should be set along with this.
+    pub image: Option<ImageInput>,
+    /// Never encode the video at a higher bitrate than the source uses.
+    pub source_cap: bool,
+    /// Stream-copy the video instead when the source bitrate is already
+    /// below the planned one.
+    pub copy_if_larger: bool,
+    /// Always re-encode the video, even when copying it and re-encoding
+    /// only the audio would fit.
+    pub force_video_reencode: bool,
+    /// Which audio tracks to keep.
+    pub audio_tracks: AudioSelection,
+    /// Bitrate of each kept audio track in bits per second, instead of
+    /// [`audio::AUDIO_BITRATE_PER_CHANNEL`] per channel (`--audio-bitrate`).
+    pub audio_bitrate: Option<u64>,
+    /// Share of the target (in percent) set aside for container overhead.
+    pub overhead_percent: f64,
+    /// Overhead learned from earlier runs, used instead of
+    /// `overhead_percent` for the containers it knows.
+    pub learned_overhead: Option<Learned>,
+    /// Send a desktop notification once the run is over. This is up to the
+    /// caller: a batch notifies once, not after every file.
+    pub notify: bool,
+    /// Refuse to encode when the bits per pixel predict a poor result,
+    /// instead of only warning.
+    pub fail_on_poor_quality: bool,
+    /// Refuse to encode below this quality; see [`crate::floor`].
+    pub min_quality: Option<Floor>,
+    /// Copy the streams into the output's container instead of encoding,
+    /// when they fit it and the input is within the target already.
+    pub remux_only: bool,
+    /// With `remux_only`, fail instead of falling back to a re-encode.
+    pub strict_remux: bool,
+    /// Copy a URL input into the run directory before probing, instead of
+    /// having ffprobe and every encode attempt read it over the network.
+    pub download_first: bool,
+    /// Probe and plan, print the plan, and stop before encoding.
+    pub dry_run: bool,
+    /// Create the output's directory when it doesn't exist, instead of
+    /// failing.
+    pub create_dirs: bool,
+    /// Print status on stderr even for a file output, because stdout
+    /// carries a machine-readable report.
+    pub status_on_stderr: bool,
+    /// Print no status at all, because stdout gets a line of its own
+    /// (`--summary-line`); errors are still the caller's to report.
+    pub quiet: bool,
+    /// When the temp directory is on a filesystem that seeks poorly, encode
+    /// in the system temp directory instead and copy the result over.
+    pub safe_remote_write: bool,
+    /// Write MP4 and MOV fragmented, so muxing never seeks back into the
+    /// file; set for a temp directory on such a filesystem.
+    pub fragment_mp4: bool,
+    /// Note the settings in the output's `comment` metadata; see
+    /// [`crate::provenance`].
+    pub tag_metadata: bool,
+    /// Write the same bytes on every run; see [`crate::deterministic`].
+    pub deterministic: bool,
+    /// Make the output loop seamlessly; see [`crate::looping`].
+    pub loop_safe: bool,
+    /// Keep the files outputs replace until the run has succeeded, and put
+    /// them back when it fails; see [`crate::commit`].
+    pub keep_original_on_failure: bool,
+    /// Write an HLS playlist and its segments into the output directory
+    /// instead of a single file; see [`crate::hls`].
+    pub hls: Option<Hls>,
+    /// Pad a verified MP4 or MOV output to exactly the target size; see
+    /// [`crate::padding`].
+    pub pad_to_exact: bool,
+    /// When the audio runs on past the video, budget for the video's length
+    /// and end the output with it.
+    pub trim_to_video: bool,
+    /// Write legacy containers (AVI), with the codecs they hold.
+    pub allow_legacy_container: bool,
+    /// Profile, pixel format and audio details a `--device` needs.
+    pub compat: Compat,
+    /// How the encoder is held to the bitrate; see [`crate::ratecontrol`].
+    /// An average bitrate unless set, where the command line defaults to
+    /// auto.
+    pub rate_mode: Mode,
+    /// Encode the video in two passes where that pays off; see
+    /// [`crate::twopass`].
+    pub two_pass: bool,
+    /// Two-pass stats to skip the first pass with; see
+    /// [`crate::passstats`].
+    pub stats: Option<PathBuf>,
+    /// Where to keep the stats of the first pass for later runs.
+    pub save_stats: Option<PathBuf>,
+    /// When to keep the system from sleeping during the encode.
+    pub inhibit_sleep: sleep::Policy,
+    /// The (redacted) `--probe-limit-url` the target was read from.
+    pub limit_url: Option<String>,
+    /// Where `batch --target-from` found this file's target.
+    pub target_source: Option<TargetSource>,
+    /// Write a `--sidecar` file next to the output; see [`crate::sidecar`].
+    pub sidecar: bool,
+    /// The arguments the tool was started with, for the sidecar.
+    pub command_line: Vec<String>,
+    /// Write the input's subtitles next to a re-encoded output; see
+    /// [`crate::subtitles`].
+    pub extract_subs: bool,
+    /// Hash the input and the output; see [`crate::checksum`].
+    pub checksum: Option<Algorithm>,
+    /// Copy the input's cover art through; see [`crate::coverart`].
+    pub keep_cover_art: bool,
+    /// Copy the input's data streams through, such as camera telemetry;
+    /// see [`crate::datastream`].
+    pub keep_data_streams: bool,
+    /// Decode the input on the GPU; see [`crate::hwdecode`].
+    pub hwdecode: HwDecode,
+}
+
+impl ReduceOptions {
+    pub fn new(target_bytes: u64) -> Self {
+        Self {
+            target_bytes,
+            size_units: SizeUnits::Binary,
+            sizes: SizeFormat::default(),
+            even_mode: EvenMode::Scale,
+            max_width: None,
+            max_height: None,
+            vertical: None,
+            region: None,
+            fps: None,
+            max_fps: None,
+            cfr: None,
+            verbose: false,
+            encoder: VideoEncoder::H264,
+            preset: Preset::Medium,
+            effort: None,
+            encoder_params: None,
+            max_encode_minutes: None,
+            temp_dir: None,
+            keep_temp: false,
+            max_retries: 2,
+            transient_retries: Retries::NONE,
+            budget: Budget::default(),
+            no_audio: false,
+            parts: 1,
+            chunks: 1,
+            output_mode: OutputMode::default(),
+            duration: None,
+            sample: None,
+            max_duration: None,
+            window_start: None,
+            trust_decode_duration: false,
+            image: None,
+            source_cap: true,
+            copy_if_larger: false,
+            force_video_reencode: false,
+            audio_tracks: AudioSelection::default(),
+            audio_bitrate: None,
+            overhead_percent: 0.0,
+            learned_overhead: None,
+            notify: false,
+            fail_on_poor_quality: false,
+            min_quality: None,
+            remux_only: false,
+            strict_remux: false,
+            download_first: false,
+            dry_run: false,
+            create_dirs: true,
+            status_on_stderr: false,
+            quiet: false,
+            safe_remote_write: false,
+            fragment_mp4: false,
+            tag_metadata: false,
+            deterministic: false,
+            loop_safe: false,
+            keep_original_on_failure: false,
+            pad_to_exact: false,
+            hls: None,
+            trim_to_video: true,
+            allow_legacy_container: false,
+            compat: Compat::default(),
+            rate_mode: Mode::Abr,
+            two_pass: false,
+            stats: None,
+            save_stats: None,
+            inhibit_sleep: sleep::Policy::default(),
+            limit_url: None,
+            target_source: None,
+            sidecar: false,
+            command_line: Vec::new(),
+            extract_subs: false,
+            checksum: None,
+            keep_cover_art: false,
+            keep_data_streams: false,
+            hwdecode: HwDecode::None,
+        }
+    }
+
+    /// Where a run writing to `output` prints its status.
+    pub fn presenter(&self, output: &str) -> Presenter {
+        if self.quiet {
+            Presenter::new(Console::none(), self.output_mode)
+        } else if self.status_on_stderr {
+            Presenter::stderr(self.output_mode)
+        } else {
+            Presenter::for_output(output, self.output_mode)
+        }
+    }
+}
+
+/// Lowest video bitrate (bps) ever requested from the encoder.
+pub const MIN_VIDEO_BITRATE: u64 = 100_000;
+
+/// Computes the video bitrate (in bits per second) needed so that:
+///
+///    (video_bitrate + audio_bitrate) * duration / 8 ≈ target file size in bytes.
+///
+/// If the computed video bitrate is too low, a minimum of 100_000 bps is used.
+pub fn compute_video_bitrate(duration: f64, target_bytes: u64, audio_bitrate: u64) -> u64 {
+    // Total bitrate (in bits per second) needed to hit the target file size.
+    let total_bitrate = (target_bytes * 8) as f64 / duration;
+    // Subtract the (assumed constant) audio bitrate.
+    let video_bitrate = total_bitrate - (audio_bitrate as f64);
+    // Use a minimum value if needed.
+    let min_video_bitrate = MIN_VIDEO_BITRATE as f64;
+    if video_bitrate < min_video_bitrate {
+        min_video_bitrate as u64
+    } else {
+        video_bitrate as u64
+    }
+}
+
+/// Share of the source bitrate used when capping at it, leaving room for
+/// the re-encode being a little less efficient than the original.
+const SOURCE_CAP_FACTOR: f64 = 0.95;
+
+/// The bitrate to use instead of `planned` when that is more than the
+/// source video itself uses; `None` when the plan can stand.
+///
+/// Re-encoding at a higher bitrate than the source only adds bytes: the
+/// quality lost in the original encode can't be recovered.
+pub fn source_cap(planned: u64, source_bitrate: Option<u64>) -> Option<u64> {
+    let source = source_bitrate?;
+    if planned <= source {
+        return None;
+    }
+    let capped = ((source as f64 * SOURCE_CAP_FACTOR) as u64).max(MIN_VIDEO_BITRATE);
+    (capped < planned).then_some(capped)
+}
+
+/// Share of the target a copied video plus re-encoded audio may fill; the
+/// rest is headroom for container overhead and bitrate estimates that are
+/// averages, not exact sizes.
+const AUDIO_ONLY_HEADROOM: f64 = 0.98;
+
+/// Whether copying the video stream (at `video_bitrate`, as probed) and
+/// re-encoding only the audio at `audio_bitrate` lands within the target.
+///
+/// An unknown video bitrate never fits: without it there is nothing to
+/// base the promise on.
+pub fn audio_only_fits(
+    video_bitrate: Option<u64>,
+    duration: f64,
+    audio_bitrate: u64,
+    target_bytes: u64,
+) -> bool {
+    let Some(video_bitrate) = video_bitrate else {
+        return false;
+    };
+    let bytes = (video_bitrate + audio_bitrate) as f64 * duration / 8.0;
+    bytes <= target_bytes as f64 * AUDIO_ONLY_HEADROOM
+}
+
+/// How far (as a fraction) the probed duration may be from `--duration`
+/// before the difference is pointed out.
+const DURATION_MISMATCH: f64 = 0.05;
+
+/// A time range of the input, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub start: f64,
+    pub length: f64,
+}
+
+/// Container bytes that come with every packet: the sample size and
+/// timing entries of an MP4 index, or a Matroska block header.
+pub const MUX_BYTES_PER_PACKET: u64 = 8;
+
+/// Extra index bytes per video frame for codecs that reorder frames
+/// (B-frames), whose presentation offsets MP4 stores frame by frame.
+pub const REORDER_BYTES_PER_FRAME: u64 = 8;
+
+/// AAC frames per second of one 48 kHz track (1024 samples each).
+const AAC_FRAMES_PER_SECOND: f64 = 48_000.0 / 1024.0;
+
+/// Container bytes the packets of `duration` seconds of output add up to,
+/// beyond the payload the bitrates account for.
+pub fn muxing_bytes(duration: f64, fps: f64, audio_tracks: usize, encoder: VideoEncoder) -> u64 {
+    let per_frame = MUX_BYTES_PER_PACKET
+        + if encoder.reorders_frames() {
+            REORDER_BYTES_PER_FRAME
+        } else {
+            0
+        };
+    let video = duration * fps * per_frame as f64;
+    let audio =
+        duration * AAC_FRAMES_PER_SECOND * audio_tracks as f64 * MUX_BYTES_PER_PACKET as f64;
+    (video + audio).round() as u64
+}
+
+/// One step of the bitrate math in [`plan_encoding`], which applies them
+/// in this order:
+///
+/// 1. [`Overhead`](Adjustment::Overhead): the `--overhead` share comes off
+///    the target.
+/// 2. [`Muxing`](Adjustment::Muxing): the per-packet container bytes
+///    ([`muxing_bytes`]) come off what is left.
+/// 3. [`Audio`](Adjustment::Audio): the kept tracks take their bitrates
+///    out of the per-second budget.
+/// 4. [`Data`](Adjustment::Data): so do the data streams copied with
+///    `--keep-data-streams`; the video gets the rest.
+/// 5. [`RaisedToMinimum`](Adjustment::RaisedToMinimum): a video bitrate
+///    below [`MIN_VIDEO_BITRATE`] is raised to it.
+/// 6. [`SourceCap`](Adjustment::SourceCap): a video bitrate above the
+///    source's is lowered to just under it (see [`source_cap`]).
+///
+/// Steps that change nothing are left out of [`EncodingPlan::adjustments`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Adjustment {
+    /// Bytes set aside for container overhead.
+    Overhead(u64),
+    /// Bytes the container's packet index and headers take.
+    Muxing(u64),
+    /// Bits per second the audio tracks take together.
+    Audio(u64),
+    /// Bits per second the copied data streams take together.
+    Data(u64),
+    /// The video bitrate the budget left, in bits per second (zero or below
+    /// when the audio and data alone are over it).
+    RaisedToMinimum(f64),
+    /// The source's video bitrate the plan was capped below.
+    SourceCap(u64),
+}
+
+impl Adjustment {
+    /// One line for the dry-run output.
+    pub fn describe(self, sizes: SizeFormat) -> String {
+        match self {
+            Adjustment::Overhead(bytes) => {
+                format!("minus {} set aside for overhead", sizes.size(bytes))
+            }
+            Adjustment::Muxing(bytes) => format!(
+                "minus {} bytes of container index and packet headers",
+                sizes.count(bytes)
+            ),
+            Adjustment::Audio(bitrate) => format!("minus {}k of audio", bitrate / 1000),
+            Adjustment::Data(bitrate) => {
+                format!("minus {}k of data streams", bitrate.div_ceil(1000))
+            }
+            Adjustment::RaisedToMinimum(bitrate) => format!(
+                "raised from {}k to the {}k minimum",
+                (bitrate.max(0.0) / 1000.0) as u64,
+                MIN_VIDEO_BITRATE / 1000
+            ),
+            Adjustment::SourceCap(source) => {
+                format!("capped below the source's {}k", source / 1000)
+            }
+        }
+    }
+}
+
+/// Everything [`reduce_video`] decides before encoding: the bitrates and
+/// how they were arrived at, the output geometry, the filters and whether
+/// the video is copied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodingPlan {
+    /// Video bitrate in bits per second.
+    pub video_bitrate: u64,
+    /// The audio tracks kept, each with its own bitrate.
+    pub audio_tracks: Vec<KeptTrack>,
+    /// The steps from the target to `video_bitrate`, in the order applied.
+    pub adjustments: Vec<Adjustment>,
+    /// Bytes of each output left for audio and video once overhead and
+    /// muxing are taken off the target.
+    pub payload_budget_bytes: u64,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+    /// Length of each output file in seconds.
+    pub part_duration: f64,
+    pub quality: Quality,
+    /// The video filters, in the order the chain renders them.
+    pub filters: FilterChain,
+    /// The limits that changed the frame size or rate.
+    pub caps: Vec<Cap>,
+    /// Display aspect ratio to mark the video with, for an anamorphic
+    /// source that keeps its stored frame size.
+    pub aspect: Option<Ratio>,
+    /// Constant output frame rate for `--cfr`.
+    pub cfr: Option<f64>,
+    /// Copy the video stream instead of encoding it.
+    pub copy_video: bool,
+    /// How the encoder is held to `video_bitrate`.
+    pub rate_control: RateControl,
+    /// Why that rate control, for the status output.
+    pub rate_reason: String,
+    /// How many passes the video is encoded in; one when it is copied.
+    pub passes: Passes,
+    /// Whether copying the video and re-encoding only the audio fits.
+    pub audio_only: bool,
+    /// Size each output is expected to come out at.
+    pub predicted_bytes: u64,
+    /// What the filter and copy decisions were, for the status output.
+    pub notes: Vec<String>,
+    /// Problems with the plan, such as the minimum-bitrate clamp.
+    pub warnings: Vec<Warning>,
+    /// The bits-per-pixel warning; [`reduce_video`] fails on it with
+    /// `--fail-on-poor-quality`. Never set for a copied video.
+    pub poor_quality: Option<Warning>,
+}
+
+impl EncodingPlan {
+    /// Whether the budget called for less than [`MIN_VIDEO_BITRATE`], so the
+    /// output is likely to end up over the target.
+    pub fn clamped(&self) -> bool {
+        self.adjustments
+            .iter()
+            .any(|a| matches!(a, Adjustment::RaisedToMinimum(_)))
+    }
+
+    /// Whether the bitrate was lowered to the source's, so the output will
+    /// come in under the target.
+    pub fn source_capped(&self) -> bool {
+        self.adjustments
+            .iter()
+            .any(|a| matches!(a, Adjustment::SourceCap(_)))
+    }
+
+    /// The plan as `--dry-run` prints it: the bitrate steps in order, then
+    /// the filters and the predicted size.
+    pub fn describe(&self, opts: &ReduceOptions) -> Vec<String> {
+        let sizes = opts.sizes;
+        let mut lines = vec![format!("Target {}", sizes.size(opts.target_bytes))];
+        for adjustment in &self.adjustments {
+            lines.push(format!("  {}", adjustment.describe(sizes)));
+        }
+        lines.push(format!(
+            "  = video {}k{}",
+            self.video_bitrate / 1000,
+            if self.copy_video { " (copied)" } else { "" }
+        ));
+        lines.push(format!(
+            "Output {}x{} at {} fps, {}",
+            self.width,
+            self.height,
+            (self.fps * 100.0).round() / 100.0,
+            match self.filters.render() {
+                Ok(FilterGraph::Simple(graph)) | Ok(FilterGraph::Complex { graph, .. }) => {
+                    format!("filters {}", graph)
+                }
+                _ => "no filters".to_string(),
+            }
+        ));
+        if let Some(params) = applied_params(opts).filter(|_| !self.copy_video) {
+            let [option, value] = params.args();
+            lines.push(format!("Encoder options {} {}", option, value));
+        }
+        if self.rate_control == RateControl::CrfCapped && !self.copy_video {
+            let args = ratecontrol::crf_capped_args(opts.encoder, self.video_bitrate / 1000);
+            lines.push(format!("Constant quality, {}", args.join(" ")));
+        }
+        match self.passes {
+            Passes::One => {}
+            Passes::Two => lines.push("Two passes".to_string()),
+            Passes::Capped => {
+                let args = twopass::rate_cap_args(self.video_bitrate / 1000);
+                lines.push(format!("One pass, {}", args.join(" ")));
+            }
+        }
+        lines.push(format!(
+            "Predicted size {}{}",
+            sizes.size(self.predicted_bytes),
+            if opts.parts > 1 { " per part" } else { "" }
+        ));
+        lines
+    }
+}
+
+/// Works out bitrates, output geometry, filters and the expected quality
+/// for `opts`; see [`Adjustment`] for the order of the bitrate math.
+pub fn plan_encoding(duration: f64, info: &VideoInfo, opts: &ReduceOptions) -> EncodingPlan {
+    let part_duration = duration / opts.parts.max(1) as f64;
+    let (width, height) = output_size(info, opts);
+    let fps = output_fps(info, opts);
+    let audio_tracks = kept_audio_tracks(info, opts);
+    let mut adjustments = Vec::new();
+
+    let usable = usable_bytes(opts);
+    if usable < opts.target_bytes {
+        adjustments.push(Adjustment::Overhead(opts.target_bytes - usable));
+    }
+    let muxing = muxing_bytes(part_duration, fps, audio_tracks.len(), opts.encoder);
+    let payload_budget_bytes = usable.saturating_sub(muxing);
+    if muxing > 0 {
+        adjustments.push(Adjustment::Muxing(usable - payload_budget_bytes));
+    }
+    let audio_bitrate = audio::total_bitrate(&audio_tracks);
+    if audio_bitrate > 0 {
+        adjustments.push(Adjustment::Audio(audio_bitrate));
+    }
+    let data_bitrate = if opts.keep_data_streams {
+        datastream::bitrate(&info.data_streams, &datastream::copied(&info.data_streams))
+    } else {
+        0
+    };
+    if data_bitrate > 0 {
+        adjustments.push(Adjustment::Data(data_bitrate));
+    }
+    // Whatever isn't the video takes its share off the top.
+    let side_bitrate = audio_bitrate + data_bitrate;
+    let budget_bitrate = (payload_budget_bytes * 8) as f64 / part_duration - side_bitrate as f64;
+    let mut video_bitrate =
+        compute_video_bitrate(part_duration, payload_budget_bytes, side_bitrate);
+    let mut warnings = Vec::new();
+    if budget_bitrate < MIN_VIDEO_BITRATE as f64 {
+        adjustments.push(Adjustment::RaisedToMinimum(budget_bitrate));
+        warnings.push(Warning::new(
+            Code::BitrateClamped,
+            format!(
+                "the target is too small for this duration; the bitrate was raised to the {}k minimum",
+                MIN_VIDEO_BITRATE / 1000
+            ),
+        ));
+    }
+    // A bitrate asked for is not a downgrade.
+    for (n, track) in audio_tracks.iter().enumerate() {
+        let full = track.channels as u64 * audio::AUDIO_BITRATE_PER_CHANNEL;
+        if track.bitrate < full && opts.audio_bitrate.is_none() {
+            warnings.push(Warning::new(
+                Code::AudioDowngraded,
+                format!(
+                    "audio track {} has {} channels; encoding it at {}k instead of {}k",
+                    n + 1,
+                    track.channels,
+                    track.bitrate / 1000,
+                    full / 1000
+                ),
+            ));
+        }
+    }
+    if let Some(capped) = source_cap(video_bitrate, info.bit_rate()).filter(|_| opts.source_cap) {
+        adjustments.push(Adjustment::SourceCap(info.bit_rate().unwrap_or_default()));
+        video_bitrate = capped;
+    }
+
+    let caps = applied_caps(info, opts);
+    let aspect =
+        (!info.sar().is_square() && downscale(info, opts).is_none() && opts.vertical.is_none())
+            .then(|| aspect::display_aspect(info.width, info.height, info.sar()));
+    let (filters, mut notes) = build_filters(info, opts);
+    let cfr = cfr_rate(info, opts);
+    let audio_only = opts.image.is_none()
+        && !opts.force_video_reencode
+        && audio_only_fits(
+            info.bit_rate(),
+            part_duration,
+            side_bitrate,
+            payload_budget_bytes,
+        );
+    let capped = adjustments
+        .iter()
+        .any(|a| matches!(a, Adjustment::SourceCap(_)));
+    // A copy keeps the source's codec, so it has to be the one the encoder
+    // would have written. The container was picked to hold that one (see
+    // `plan_container`), so it holds the copy as well.
+    let source_codec = info.codec_name.as_deref();
+    let same_codec = source_codec == Some(opts.encoder.stream_codec());
+    // `--device`'s frame and frame rate caps come as filters; the stream
+    // details it asks for have to be there already.
+    let unmet = opts.compat.unmet_by(info);
+    let copy_video = (audio_only || (capped && opts.copy_if_larger)) && {
+        // Filters and frame rate conversion need decoded frames, so they
+        // rule out a stream copy.
+        if !filters.is_empty() {
+            notes.push(
+                "Cannot stream-copy the video because it needs filtering; re-encoding".into(),
+            );
+        } else if cfr.is_some() {
+            notes.push("Cannot stream-copy the video with --cfr; re-encoding".into());
+        } else if !same_codec {
+            notes.push(format!(
+                "Cannot stream-copy the {} video into {} output; re-encoding",
+                source_codec.unwrap_or("unknown"),
+                opts.encoder.stream_codec()
+            ));
+        } else if let Some(unmet) = &unmet {
+            notes.push(format!(
+                "Cannot stream-copy the video for --device: {}; re-encoding",
+                unmet
+            ));
+        }
+        filters.is_empty() && cfr.is_none() && same_codec && unmet.is_none()
+    };
+    let source = info.bit_rate().unwrap_or_default() / 1000;
+    if copy_video && audio_only {
+        notes.push(format!(
+            "Video ({}k) already fits: copying the video stream unchanged and re-encoding only the audio",
+            source
+        ));
+    } else if capped && copy_video {
+        notes.push(format!(
+            "Source video is only {}k; copying it unchanged, so the output will come in under the target",
+            source
+        ));
+    } else if capped {
+        notes.push(format!(
+            "Source video is only {}k; capped the bitrate at {}k, so the output will come in under the target",
+            source,
+            video_bitrate / 1000
+        ));
+    }
+
+    // A copied stream keeps the source's quality, whatever the budget says.
+    let poor_quality = (!copy_video)
+        .then(|| {
+            estimate::poor_quality_warning(video_bitrate, width, height, fps, opts.encoder.codec())
+        })
+        .flatten()
+        .map(|message| Warning::new(Code::PoorQuality, message));
+    let planned_video = if copy_video {
+        info.bit_rate().unwrap_or(video_bitrate)
+    } else {
+        video_bitrate
+    };
+    let payload = ((planned_video + side_bitrate) as f64 * part_duration / 8.0) as u64;
+    let length = opts
+        .sample
+        .map_or(part_duration, |sample| sample.min(duration));
+    let chunks = chunked::chunk_count(opts.chunks, duration);
+    let (rate_control, rate_reason) = ratecontrol::choose(
+        opts.rate_mode,
+        opts.two_pass,
+        length,
+        (width, height, fps),
+        video_bitrate / 1000,
+        opts.encoder.codec(),
+    );
+    // A constant quality is one pass whatever was asked.
+    let (passes, no_two_pass) = twopass::plan(
+        opts.two_pass && !copy_video && rate_control.is_abr(),
+        length,
+        opts.encoder,
+        chunks,
+    );
+    if let Some(reason) = no_two_pass {
+        warnings.push(Warning::new(
+            Code::TwoPassSkipped,
+            format!(
+                "not encoding in two passes: {}; using one pass with the peak rate capped",
+                reason
+            ),
+        ));
+    }
+    EncodingPlan {
+        video_bitrate,
+        audio_tracks,
+        adjustments,
+        payload_budget_bytes,
+        width,
+        height,
+        fps,
+        part_duration,
+        quality: opts.encoder.codec().quality(estimate::bits_per_pixel(
+            video_bitrate,
+            width,
+            height,
+            fps,
+        )),
+        filters,
+        caps,
+        aspect,
+        cfr,
+        copy_video,
+        rate_control,
+        rate_reason,
+        passes,
+        audio_only,
+        predicted_bytes: payload + (opts.target_bytes - payload_budget_bytes),
+        notes,
+        warnings,
+        poor_quality,
+    }
+}
+
+/// The target minus the share set aside for container overhead.
+fn usable_bytes(opts: &ReduceOptions) -> u64 {
+    let overhead = opts.overhead_percent.clamp(0.0, 100.0) / 100.0;
+    (opts.target_bytes as f64 * (1.0 - overhead)) as u64
+}
+
+/// The audio tracks that go into the output; none for `--no-audio` and
+/// image inputs.
+pub fn kept_audio_tracks(info: &VideoInfo, opts: &ReduceOptions) -> Vec<KeptTrack> {
+    if opts.no_audio || opts.image.is_some() {
+        return Vec::new();
+    }
+    let mut tracks = audio::kept_tracks(info, opts.audio_tracks);
+    if let Some(most) = opts.compat.audio_channels {
+        // Downmixed tracks get the bitrate of what they become.
+        for track in tracks.iter_mut().filter(|t| t.channels > most) {
+            track.channels = most;
+            track.bitrate = audio::track_bitrate(Some(most));
+        }
+    }
+    if let Some(bitrate) = opts.audio_bitrate {
+        for track in &mut tracks {
+            track.bitrate = bitrate;
+        }
+    }
+    tracks
+}
+
+/// One line itemizing where the bitrate goes.
+pub fn describe_budget(plan: &EncodingPlan, opts: &ReduceOptions) -> String {
+    let mut items = vec![format!("video {} kb/s", plan.video_bitrate / 1000)];
+    for (i, track) in plan.audio_tracks.iter().enumerate() {
+        items.push(format!(
+            "audio track {} {} kb/s",
+            i + 1,
+            track.bitrate / 1000
+        ));
+    }
+    if plan.audio_tracks.is_empty() {
+        items.push("no audio".to_string());
+    }
+    items.push(format!("overhead {}%", opts.overhead_percent));
+    items.join(", ")
+}
+
+/// A limit on the output that the source exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cap {
+    MaxWidth,
+    MaxHeight,
+    MaxFps,
+}
+
+impl Cap {
+    /// The option that sets the limit.
+    pub fn flag(self) -> &'static str {
+        match self {
+            Cap::MaxWidth => "--max-width",
+            Cap::MaxHeight => "--max-height",
+            Cap::MaxFps => "--max-fps",
+        }
+    }
+}
+
+/// A smaller frame size, and the limit that called for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Downscale {
+    pub width: u32,
+    pub height: u32,
+    pub cap: Cap,
+}
+
+/// The frame size `--max-width` and `--max-height` scale the source down
+/// to, keeping its display aspect ratio; with both, the one giving the
+/// smaller frame. The other side is rounded to even, as the scale filter
+/// does. An anamorphic source is measured at its square-pixel size, so the
+/// result has square pixels.
+pub fn downscale(info: &VideoInfo, opts: &ReduceOptions) -> Option<Downscale> {
+    if opts.vertical.is_some() {
+        return None;
+    }
+    let even = |side: f64| ((side / 2.0).round() * 2.0) as u32;
+    let (width, height) = aspect::square_pixel_size(info.width, info.height, info.sar());
+    let by_width = opts.max_width.filter(|&w| w < width).map(|w| Downscale {
+        width: w,
+        height: even(height as f64 * w as f64 / width as f64),
+        cap: Cap::MaxWidth,
+    });
+    let by_height = opts.max_height.filter(|&h| h < height).map(|h| Downscale {
+        width: even(width as f64 * h as f64 / height as f64),
+        height: h,
+        cap: Cap::MaxHeight,
+    });
+    match (by_width, by_height) {
+        (Some(w), Some(h)) if h.height < w.height => Some(h),
+        (w, h) => w.or(h),
+    }
+}
+
+/// Frame size after `--max-width` and `--max-height` (odd-dimension fixes
+/// aside), or the canvas of `--vertical`. Without a downscale an anamorphic
+/// frame keeps its stored size.
+pub fn output_size(info: &VideoInfo, opts: &ReduceOptions) -> (u32, u32) {
+    if let Some(canvas) = vertical_canvas(info, opts) {
+        return (canvas.width, canvas.height);
+    }
+    downscale(info, opts).map_or((info.width, info.height), |d| (d.width, d.height))
+}
+
+/// The portrait canvas of `--vertical`, unless the source already is one.
+fn vertical_canvas(info: &VideoInfo, opts: &ReduceOptions) -> Option<Canvas> {
+    let canvas = Canvas::of(info.width, info.height, info.sar(), opts.vertical?);
+    let unchanged = canvas.crop.is_none()
+        && !canvas.is_boxed()
+        && info.sar().is_square()
+        && (canvas.width, canvas.height) == (info.width, info.height);
+    (!unchanged).then_some(canvas)
+}
+
+/// The rate `--max-fps` holds the output to, when the one it would have
+/// otherwise (from `--fps`, `--cfr` or the source) is faster. `None` when
+/// that rate isn't known.
+pub fn frame_rate_cap(info: &VideoInfo, opts: &ReduceOptions) -> Option<f64> {
+    let max = opts.max_fps?;
+    let rate = opts
+        .fps
+        .or_else(|| requested_cfr_rate(info, opts))
+        .or_else(|| info.frame_rate())?;
+    (rate > max).then_some(max)
+}
+
+/// The caps that change the output of `info`, size first.
+fn applied_caps(info: &VideoInfo, opts: &ReduceOptions) -> Vec<Cap> {
+    let size = downscale(info, opts).map(|d| d.cap);
+    let rate = frame_rate_cap(info, opts).map(|_| Cap::MaxFps);
+    size.into_iter().chain(rate).collect()
+}
+
+fn output_fps(info: &VideoInfo, opts: &ReduceOptions) -> f64 {
+    frame_rate_cap(info, opts)
+        .or(opts.fps)
+        .or_else(|| cfr_rate(info, opts))
+        .or_else(|| info.frame_rate())
+        .unwrap_or(estimate::DEFAULT_FPS)
+}
+
+/// The constant rate `--cfr` normalizes to, if requested, within
+/// `--max-fps`.
+fn cfr_rate(info: &VideoInfo, opts: &ReduceOptions) -> Option<f64> {
+    requested_cfr_rate(info, opts).map(|rate| frame_rate_cap(info, opts).unwrap_or(rate))
+}
+
+fn requested_cfr_rate(info: &VideoInfo, opts: &ReduceOptions) -> Option<f64> {
+    opts.cfr.map(|rate| {
+        rate.unwrap_or_else(|| {
+            info.frame_rate()
+                .map_or(estimate::DEFAULT_FPS, |avg| avg.round().max(1.0))
+        })
+    })
+}
+
+/// Reduces the quality of the input video to hit roughly the target file size (in MB).
+///
+/// This function:
+/// 1. Obtains the video duration (see [`resolve_duration`]).
+/// 2. Computes a target video bitrate, after the kept audio tracks and the
+///    overhead allowance have taken their share.
+/// 3. Probes the frame size and builds the filter chain (downscale, fps,
+///    even dimensions as libx264 requires).
+/// 4. Calls ffmpeg to re‑encode the video into a per-run temp directory and
+///    moves the result into place only once the encode has succeeded.
+/// 5. Re-encodes at a lower bitrate when the result overshoots the target,
+///    up to `max_retries` times.
+///
+/// With `parts` above one, steps 4 and 5 run once per equal-length part and
+/// each part is written next to `output` as `<stem>.partN.<ext>`.
+///
+/// Either path may be `-`: input is then read from stdin, and output is
+/// streamed to stdout as fragmented MP4 with all status text on stderr.
+/// The input may also be an HTTP(S) URL, which ffmpeg reads directly
+/// unless `download_first` is set.
+pub fn reduce_video<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    output: &str,
+    opts: &ReduceOptions,
+) -> Result<ReduceReport, ReduceError> {
+    let meter = Meter::start();
+    // The input is read while it is encoded, so its hash costs no time.
+    let hashing = opts.checksum.filter(|_| !opts.dry_run).map(|algorithm| {
+        let is_file = input != STDIO_PATH && !url::is_url(input);
+        (
+            algorithm,
+            is_file.then(|| Pending::start(algorithm, Path::new(input))),
+        )
+    });
+    // Dropped on any error return below, putting back what was replaced.
+    let held = (opts.keep_original_on_failure && !opts.dry_run).then(commit::hold);
+    let recording = Recording::new(tool);
+    let mut report = reduce(&recording, input, output, opts)?;
+    if let Some(recipe) = &mut report.recipe {
+        recipe.ffmpeg = recording.commands();
+    }
+    if opts.dry_run {
+        return Ok(report);
+    }
+    let usage = meter.finish();
+    let out = opts.presenter(output);
+    out.info(&usage.describe(opts.sizes));
+    let mut report = ReduceReport {
+        usage: Some(usage),
+        ..report
+    };
+    if let Some((algorithm, pending)) = hashing {
+        let checksums = hash_files(algorithm, input, pending, output, opts, &report)?;
+        out.info(&format!("Checksums ({}):", algorithm));
+        for (hash, path) in checksums.lines(input) {
+            out.info(&format!("  {}  {}", hash, path));
+        }
+        report.checksums = Some(checksums);
+    }
+    if opts.sidecar && output != STDIO_PATH {
+        report.sidecar = write_sidecar(input, output, opts, &report)?;
+        if let Some(path) = &report.sidecar {
+            out.info(&format!("How it was made: {}", path));
+        }
+    }
+    if let Some(held) = held {
+        held.release();
+    }
+    Ok(report)
+}
+
+/// The checksums of a run: the input's from `pending`, and those of the
+/// files written to `output`.
+fn hash_files(
+    algorithm: Algorithm,
+    input: &str,
+    pending: Option<Pending>,
+    output: &str,
+    opts: &ReduceOptions,
+    report: &ReduceReport,
+) -> Result<Checksums, ReduceError> {
+    let failed = |path: &str, e: io::Error| {
+        ReduceError::Encode(format!(
+            "cannot compute the {} of {}: {}",
+            algorithm, path, e
+        ))
+    };
+    let input_hash = pending
+        .map(|pending| pending.wait().map_err(|e| failed(input, e)))
+        .transpose()?;
+    // --max-duration may have raised the number of parts.
+    let parts = report
+        .recipe
+        .as_ref()
+        .and_then(|recipe| recipe.settings.as_ref())
+        .map_or(opts.parts, |settings| settings.parts);
+    let paths = match (output, parts) {
+        (STDIO_PATH, _) => Vec::new(),
+        (_, 0 | 1) => vec![output.to_string()],
+        (_, parts) => (1..=parts).map(|n| part_output_path(output, n)).collect(),
+    };
+    let outputs = paths
+        .into_iter()
+        .map(|path| {
+            let hash =
+                checksum::of_file(algorithm, Path::new(&path)).map_err(|e| failed(&path, e))?;
+            Ok(FileChecksum { path, hash })
+        })
+        .collect::<Result<_, ReduceError>>()?;
+    Ok(Checksums {
+        algorithm,
+        input: input_hash,
+        outputs,
+    })
+}
+
+/// Writes the sidecar of `output` from what the run recorded, returning
+/// its path.
+fn write_sidecar(
+    input: &str,
+    output: &str,
+    opts: &ReduceOptions,
+    report: &ReduceReport,
+) -> Result<Option<String>, ReduceError> {
+    let Some(recipe) = report.recipe.clone() else {
+        return Ok(None);
+    };
+    let path = sidecar::path_for(output);
+    let made = Sidecar::new(
+        opts.command_line.clone(),
+        recipe,
+        events::Report::reduced(input, output, opts, report),
+    );
+    made.write(Path::new(&path))
+        .map_err(|e| ReduceError::Encode(format!("cannot write {}: {}", path, e)))?;
+    Ok(Some(path))
+}
+
+/// [`reduce_video`], unmeasured.
+fn reduce<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    output: &str,
+    opts: &ReduceOptions,
+) -> Result<ReduceReport, ReduceError> {
+    // Left over from a run that failed, or from probing for --interactive.
+    warning::take();
+    let out = opts.presenter(output);
+    let ledger = Ledger::new(opts.budget, opts.sizes);
+    let parts = opts.parts.max(1);
+    if parts > 1 && output == STDIO_PATH {
+        return Err(ReduceError::Usage(
+            "splitting into parts needs an output file, not stdout".into(),
+        ));
+    }
+    if opts.chunks > 1 && output == STDIO_PATH {
+        return Err(ReduceError::Usage(
+            "--chunked-encode needs an output file, not stdout".into(),
+        ));
+    }
+    if opts.hls.is_some() {
+        let clash = [
+            (output == STDIO_PATH, "stdout"),
+            (parts > 1, "--split"),
+            (opts.chunks > 1, "--chunked-encode"),
+            (opts.sample.is_some(), "--sample"),
+            (opts.remux_only, "--remux-only"),
+            (opts.loop_safe, "--loop-safe"),
+            (opts.checksum.is_some(), "--checksum"),
+            (opts.extract_subs, "--extract-subs"),
+        ]
+        .into_iter()
+        .find_map(|(clashes, with)| clashes.then_some(with));
+        if let Some(with) = clash {
+            return Err(ReduceError::Usage(format!(
+                "--format hls writes a playlist and its segments into a directory, so it can't be used with {}",
+                with
+            )));
+        }
+    }
+    if opts.pad_to_exact {
+        let clash = [
+            (output == STDIO_PATH, "stdout"),
+            (parts > 1, "--split"),
+            (opts.hls.is_some(), "--format hls"),
+            (opts.sample.is_some(), "--sample"),
+        ]
+        .into_iter()
+        .find_map(|(clashes, with)| clashes.then_some(with));
+        if let Some(with) = clash {
+            return Err(ReduceError::Usage(format!(
+                "--pad-to-exact pads a single output file, so it can't be used with {}",
+                with
+            )));
+        }
+        let container = output_container(output);
+        if !padding::pads(container) {
+            return Err(ReduceError::Usage(format!(
+                "--pad-to-exact pads with an MP4 free box, so it needs an MP4 or MOV output, not {}",
+                container
+            )));
+        }
+    }
+    if opts.stats.is_some() || opts.save_stats.is_some() {
+        let clash = [(parts > 1, "--split"), (opts.sample.is_some(), "--sample")]
+            .into_iter()
+            .find_map(|(clashes, with)| clashes.then_some(with));
+        if let Some(with) = clash {
+            return Err(ReduceError::Usage(format!(
+                "--stats and --save-stats keep the first pass of one whole encode, so they can't be used with {}",
+                with
+            )));
+        }
+    }
+    validate::check(&opts.validate(None, &Capabilities::default()))?;
+    // A dry run writes nothing, so it only fails where a real one would.
+    if output != STDIO_PATH && !(opts.dry_run && opts.create_dirs) {
+        outdir::ensure_parent(Path::new(output), opts.create_dirs)?;
+    }
+    let opts = &plan_container(output, opts, out)?;
+    let opts = &plan_writes(tool, output, opts, out)?;
+    // Partial output (and a spooled copy of stdin) lives in the run directory,
+    // so a failed or interrupted encode never leaves a truncated file at the
+    // destination.
+    let run_dir = RunTempDir::create(opts.temp_dir.as_deref(), opts.keep_temp)
+        .map_err(|e| ReduceError::Encode(e.to_string()))?;
+    if run_dir.is_kept() {
+        out.info(&format!(
+            "Keeping temporary files in {}",
+            run_dir.path().display()
+        ));
+    }
+    // ffprobe and ffmpeg each need to read the input, and the bitrate math
+    // needs the duration up front, so stdin is spooled to a file first.
+    let input = if input == STDIO_PATH {
+        if opts.image.is_some() {
+            return Err(ReduceError::Usage(
+                "image inputs cannot be read from stdin".into(),
+            ));
+        }
+        spool_stdin(&run_dir)?
+    } else if opts.download_first && !opts.dry_run && url::is_url(input) {
+        download(tool, input, &run_dir, out)?
+    } else {
+        input.to_string()
+    };
+    let input = input.as_str();
+
+    events::phase(Phase::Probing);
+    let Source {
+        info,
+        duration,
+        image,
+        shortest,
+    } = transient::retry(
+        opts.transient_retries,
+        "probing the input",
+        None,
+        out,
+        || probe_source(tool, input, opts, out),
+    )?;
+    let mut recipe = Recipe {
+        source: SourceSummary {
+            bytes: std::fs::metadata(input).ok().map(|m| m.len()),
+            ..SourceSummary::of(&info, duration)
+        },
+        run_dir: run_dir.path().display().to_string(),
+        ..Recipe::default()
+    };
+    let window = window::choose(duration, opts.max_duration, opts.window_start, parts)
+        .map_err(ReduceError::Usage)?;
+    let cut = window.cuts(duration);
+    let cover_art = coverart::kept(
+        &info.cover_art,
+        opts.keep_cover_art,
+        output_container(output),
+        opts.fragment_mp4 || output == STDIO_PATH,
+        out,
+    );
+    let data_streams = datastream::kept(
+        &info.data_streams,
+        opts.keep_data_streams,
+        output_container(output),
+        out,
+    );
+    if opts.remux_only {
+        if let Some(report) = try_remux(
+            tool,
+            input,
+            output,
+            &info,
+            &cover_art,
+            &data_streams,
+            duration,
+            cut,
+            opts,
+            &run_dir,
+            &ledger,
+            out,
+        )? {
+            return Ok(ReduceReport {
+                recipe: Some(recipe),
+                attempts: ledger.attempts(),
+                ..report
+            }
+            .with_warnings());
+        }
+    }
+    if cut {
+        let message = format!(
+            "the input is {} long; only {} of it is encoded",
+            format_duration(duration),
+            window.describe()
+        );
+        warning::emit(out, Warning::new(Code::Trimmed, message));
+    }
+    if window.parts > parts {
+        out.info(&format!(
+            "Splitting into {} parts instead of {}, so none is longer than --max-duration",
+            window.parts, parts
+        ));
+    }
+    let (duration, parts) = (window.length, window.parts);
+    let opts = &ReduceOptions {
+        encoder: available_encoder(tool, opts.encoder, out)?,
+        overhead_percent: overhead_percent(opts, output, out),
+        parts,
+        // Only what is copied takes from the budget.
+        keep_data_streams: !data_streams.is_empty(),
+        ..opts.clone()
+    };
+    let container = output_container(output);
+    if !container.video_encoders().contains(&opts.encoder) {
+        return Err(ReduceError::Usage(format!(
+            "{} can't hold {} video, and this ffmpeg has no encoder it can",
+            container, opts.encoder
+        )));
+    }
+    let plan = plan_encoding(duration, &info, opts);
+    let audio_encoder =
+        available_audio_encoder(tool, container.audio_codec(), &plan.audio_tracks, out)?;
+
+    // ffmpeg accepts bitrates in a suffix form (e.g. "500k" for 500 kb/s). We convert bps -> kbps.
+    let video_bitrate_str = format!("{}k", plan.video_bitrate / 1000);
+    out.info(&format!("Video duration: {:.2} seconds", duration));
+    out.info(&format!(
+        "Target size: {} ({} bytes){}",
+        opts.sizes.size(opts.target_bytes),
+        opts.sizes.count(opts.target_bytes),
+        match (&opts.limit_url, opts.target_source) {
+            (Some(url), _) => format!(", the limit {} reported", url),
+            (None, Some(source)) => format!(", {}", source.describe()),
+            (None, None) => String::new(),
+        }
+    ));
+    out.info(&format!(
+        "Using video bitrate: {} ({} bps)",
+        video_bitrate_str, plan.video_bitrate
+    ));
+    out.info(&format!("Bitrate budget: {}", describe_budget(&plan, opts)));
+    for warning in &plan.warnings {
+        warning::emit(out, warning.clone());
+    }
+    if parts > 1 {
+        out.info(&format!(
+            "Splitting into {} parts of {} each",
+            parts,
+            format_duration(plan.part_duration)
+        ));
+    }
+    if opts.verbose {
+        out.info(&format!("Source: {}x{}", info.width, info.height));
+        let sar = info.sar();
+        if !sar.is_square() {
+            let (width, height) = aspect::square_pixel_size(info.width, info.height, sar);
+            out.info(&format!(
+                "Pixel aspect ratio: {} (displayed at {}, {}x{})",
+                sar,
+                aspect::display_aspect(info.width, info.height, sar),
+                width,
+                height
+            ));
+        }
+        out.info(&format!("Source color: {}", info.describe_color()));
+        if info.is_variable_frame_rate() {
+            out.info(&format!(
+                "Variable frame rate: {} fps average (timestamps on a {} grid){}",
+                info.frame_rate()
+                    .map_or(0.0, |avg| (avg * 100.0).round() / 100.0),
+                info.r_frame_rate.as_deref().unwrap_or("?"),
+                if opts.cfr.is_none() {
+                    "; --cfr normalizes it"
+                } else {
+                    ""
+                }
+            ));
+        }
+        for (i, track) in plan.audio_tracks.iter().enumerate() {
+            let input = info
+                .audio_streams
+                .as_ref()
+                .and_then(|streams| streams.get(track.input_position as usize));
+            out.info(&format!(
+                "Audio track {}: input track {} ({}), kept as {}",
+                i + 1,
+                track.input_position + 1,
+                input.map_or("not probed".to_string(), |stream| {
+                    audio::describe_tags(
+                        audio::stream_language(stream),
+                        stream.disposition.default != 0,
+                    )
+                }),
+                track.describe_tags()
+            ));
+        }
+        out.info(&format!("Expected quality: {}", plan.quality));
+    }
+
+    let graph = plan.filters.render()?;
+    let stats = plan_stats(input, &plan, opts)?;
+    // The first pass takes about as long as the second.
+    let encoded = match plan.passes {
+        Passes::Two if stats.as_ref().is_some_and(|s| s.reuse.is_some()) => duration,
+        Passes::Two => 2.0 * duration,
+        Passes::One | Passes::Capped => duration,
+    };
+    // A copied stream keeps the source's quality, whatever the budget says,
+    // so only an encode is held to the floor.
+    if let Some(floor) = opts.min_quality.filter(|_| !plan.copy_video) {
+        let refusal = floor::refusal(
+            floor,
+            opts.encoder.codec(),
+            plan.video_bitrate,
+            plan.width,
+            plan.height,
+            plan.fps,
+        );
+        if let Some(message) = refusal {
+            return Err(ReduceError::BelowQualityFloor(message));
+        }
+    }
+    let preset = plan_preset(encoded, &info, opts, out)?;
+    if let Some(warning) = &plan.poor_quality {
+        if opts.fail_on_poor_quality {
+            return Err(ReduceError::Usage(format!(
+                "{} (stopping because of --fail-on-poor-quality)",
+                warning.message
+            )));
+        }
+        warning::emit(out, warning.clone());
+    }
+    for note in &plan.notes {
+        out.info(note);
+    }
+    if !plan.copy_video {
+        out.info(&format!(
+            "Rate control: {} ({})",
+            match plan.rate_control {
+                RateControl::Abr => "average bitrate".to_string(),
+                RateControl::CrfCapped =>
+                    format!("constant quality capped at {}k", plan.video_bitrate / 1000),
+            },
+            plan.rate_reason
+        ));
+        check_encoder_params(opts, out);
+        check_effort(opts, out);
+        if let Some(message) = deterministic::caveat(opts.encoder).filter(|_| opts.deterministic) {
+            warning::emit(out, Warning::new(Code::NotBitExact, message));
+        }
+    }
+    let accel = match opts.hwdecode {
+        HwDecode::None => None,
+        // Pictures have no GPU decoder to speak of.
+        _ if image.is_some() => None,
+        requested => hwdecode::choose(
+            requested,
+            &tool.list_hwaccels()?,
+            Accel::device_present,
+            out,
+        ),
+    };
+    let hw_graph = match accel {
+        Some(accel) => {
+            out.info(&format!("Decoding on the GPU with {} (--hwdecode)", accel));
+            let mut filters = plan.filters.clone();
+            filters.hwdownload();
+            Some(filters.render()?)
+        }
+        None => None,
+    };
+    let hw_failed = Cell::new(false);
+    let chunks = chunked::chunk_count(opts.chunks, duration);
+    if opts.chunks > 1 {
+        if plan.copy_video {
+            out.info("The video is copied, so there is nothing to encode in chunks");
+        } else if chunks == 1 {
+            out.info("The input is too short to split into chunks; encoding it in one piece");
+        } else {
+            out.info(&format!(
+                "Encoding in {} chunks of about {} at once",
+                chunks,
+                format_duration(duration / chunks as f64)
+            ));
+        }
+    }
+    if opts.dry_run {
+        out.info("");
+        for line in plan.describe(opts) {
+            out.info(&line);
+        }
+        out.info("Dry run: nothing was encoded");
+        return Ok(ReduceReport::default().with_warnings());
+    }
+    if output != STDIO_PATH {
+        check_free_space(opts, output, &run_dir, chunks, out);
+    }
+    // Copying the video takes no time worth staying awake for.
+    let estimate = if plan.copy_video {
+        0.0
+    } else {
+        estimate_encode_seconds(encoded, plan.fps, plan.width, plan.height, preset)
+    };
+    let _awake = keep_awake(tool, opts, Duration::from_secs_f64(estimate), output, out);
+
+    let ctx = EncodeContext {
+        input,
+        duration,
+        part_duration: plan.part_duration,
+        graph: &graph,
+        hwdecode: accel
+            .zip(hw_graph.as_ref())
+            .map(|(accel, graph)| HwDecoding {
+                accel,
+                graph,
+                failed: &hw_failed,
+            }),
+        encoder: opts.encoder,
+        preset,
+        encoder_params: applied_params(opts),
+        audio_encoder,
+        compat: opts.compat,
+        fragment_mp4: opts.fragment_mp4,
+        tag_metadata: opts.tag_metadata,
+        deterministic: opts.deterministic,
+        loop_safe: opts.loop_safe,
+        rate_control: plan.rate_control,
+        hls: opts.hls,
+        pad_to_exact: opts.pad_to_exact,
+        shortest,
+        fps: plan.fps,
+        cfr: plan.cfr,
+        aspect: plan.aspect,
+        chunks,
+        info: &info,
+        cover_art: &cover_art,
+        data_streams: &data_streams,
+        image: image.as_ref(),
+        copy_video: plan.copy_video,
+        audio: &plan.audio_tracks,
+        run_dir: &run_dir,
+        out,
+        target_bytes: opts.target_bytes,
+        overhead_bytes: opts.target_bytes - plan.payload_budget_bytes,
+        sizes: opts.sizes,
+        max_retries: opts.max_retries,
+        transient_retries: opts.transient_retries,
+        ledger: &ledger,
+        passes: plan.passes,
+        stats: stats.as_ref(),
+    };
+    let mut prediction = None;
+    for part in 0..parts {
+        let segment = Segment {
+            start: window.start + part as f64 * plan.part_duration,
+            length: plan.part_duration,
+        };
+        let (segment, part_output) = if let Some(sample) = opts.sample {
+            // The plan above used the whole duration, so the sample shows
+            // the quality of the real job.
+            if sample >= duration {
+                out.info("The input is no longer than the sample; encoding all of it");
+            }
+            let length = sample.min(duration);
+            let start = window.start;
+            (Some(Segment { start, length }), output.to_string())
+        } else if parts == 1 {
+            (cut.then_some(segment), output.to_string())
+        } else {
+            let part_output = part_output_path(output, part + 1);
+            out.info(&format!(
+                "Encoding part {}/{}: {}",
+                part + 1,
+                parts,
+                part_output
+            ));
+            (Some(segment), part_output)
+        };
+        let part_prediction =
+            encode_to_target(tool, &ctx, segment, plan.video_bitrate, &part_output)?;
+        prediction = match (prediction, part_prediction) {
+            (Some(total), Some(p)) => Some(Prediction::combine(total, p)),
+            (total, p) => total.or(p),
+        };
+    }
+    if opts.verbose {
+        if let Some(prediction) = &prediction {
+            out.info(&format!(
+                "Size prediction: {}",
+                prediction.describe(opts.sizes)
+            ));
+        }
+    }
+    if let Some(sample) = opts.sample {
+        warning::emit(
+            out,
+            Warning::new(
+                Code::SampleOnly,
+                format!(
+                    "{} is only a sample of the first {}; run again without --sample for the full output",
+                    output,
+                    format_duration(sample.min(duration))
+                ),
+            ),
+        );
+    }
+    let subtitles = if opts.extract_subs && output != STDIO_PATH && image.is_none() {
+        let written = subtitles::extract(tool, input, output, &run_dir, out)?;
+        for path in &written {
+            out.info(&format!("Subtitles: {}", path));
+        }
+        written
+    } else {
+        Vec::new()
+    };
+    // A sample or a copied video says little about a full encode, and a
+    // constant quality spends less than the plan wherever it can.
+    let learns = opts.sample.is_none() && !plan.copy_video && plan.rate_control.is_abr();
+    let sample = match (prediction, Container::from_path(output)) {
+        (Some(prediction), Some(container)) if learns => {
+            let muxing = muxing_bytes(
+                plan.part_duration,
+                plan.fps,
+                plan.audio_tracks.len(),
+                opts.encoder,
+            );
+            Some(Sample {
+                container,
+                duration,
+                streams: 1 + plan.audio_tracks.len() as u32,
+                payload_bytes: prediction.payload_bytes,
+                muxing_bytes: muxing * parts as u64,
+                actual_bytes: prediction.actual_bytes,
+            })
+        }
+        _ => None,
+    };
+    let encoded_video = !plan.copy_video;
+    recipe.settings = Some(Settings {
+        target_bytes: opts.target_bytes,
+        overhead_percent: opts.overhead_percent,
+        encoder: opts.encoder.to_string(),
+        preset: encoded_video.then(|| opts.encoder.preset_value(preset)),
+        encoder_params: ctx
+            .encoder_params
+            .filter(|_| encoded_video)
+            .map(ToString::to_string),
+        video_bitrate: encoded_video.then_some(plan.video_bitrate),
+        audio_encoder: (!plan.audio_tracks.is_empty()).then(|| ctx.audio_encoder.name.to_string()),
+        audio_bitrates: plan.audio_tracks.iter().map(|t| t.bitrate).collect(),
+        width: plan.width,
+        height: plan.height,
+        fps: plan.fps,
+        rate_control: plan.rate_control,
+        passes: plan.passes,
+        parts,
+        chunks,
+        sample: opts.sample.map(|sample| sample.min(duration)),
+        region: opts.region.map(|region| region.to_string()),
+    });
+    Ok(ReduceReport {
+        prediction,
+        caps: plan.caps,
+        sample,
+        warnings: Vec::new(),
+        usage: None,
+        recipe: Some(recipe),
+        sidecar: None,
+        subtitles,
+        checksums: None,
+        attempts: ledger.attempts(),
+    }
+    .with_warnings())
+}
+
+/// What a successful [`reduce_video`] worked out along the way.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReduceReport {
+    /// Predicted against actual size; `None` when streaming to stdout,
+    /// where the size is never known.
+    pub prediction: Option<Prediction>,
+    /// The `--max-*` limits the source exceeded, which are why the output
+    /// is smaller or slower than it.
+    pub caps: Vec<Cap>,
+    /// What the run showed about the output container's overhead, for the
+    /// history to learn from; only full encodes into a file have one.
+    pub sample: Option<Sample>,
+    /// Everything warned about during the run, in the order printed.
+    pub warnings: Vec<Warning>,
+    /// The compute it took; absent for a dry run.
+    pub usage: Option<Usage>,
+    /// What the sidecar records: the inputs, settings and ffmpeg commands
+    /// of the run; absent for a dry run.
+    pub recipe: Option<Recipe>,
+    /// Where `--sidecar` wrote it.
+    pub sidecar: Option<String>,
+    /// The files `--extract-subs` wrote.
+    pub subtitles: Vec<String>,
+    /// What `--checksum` computed.
+    pub checksums: Option<Checksums>,
+    /// Every try at writing the output, in order; see [`crate::budget`].
+    pub attempts: Vec<Attempt>,
+}
+
+impl ReduceReport {
+    /// The report with the warnings recorded so far moved into it.
+    fn with_warnings(mut self) -> Self {
+        self.warnings = warning::take();
+        self
+    }
+}
+
+/// What is known about the input before encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub info: VideoInfo,
+    /// Length in seconds.
+    pub duration: f64,
+    /// Set for image inputs, which ffmpeg reads differently.
+    pub image: Option<ImageSource>,
+    /// End the output where the video ends, cutting the audio that runs on
+    /// past it; see [`fit_to_video`].
+    pub shortest: bool,
+}
+
+/// Probes `input`, or for image inputs checks the files and probes the
+/// first one, deriving the duration from the frame count or loop length.
+pub fn probe_source<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    opts: &ReduceOptions,
+    out: Presenter,
+) -> Result<Source, ReduceError> {
+    let Some(kind) = opts.image else {
+        let info = tool.get_video_info(input)?;
+        unsupported::check(&info)
+            .map_err(|e| ReduceError::Probe(format!("cannot reduce {}: {}", input, e)))?;
+        if info.from_headers {
+            warn_assumed(input, out);
+        }
+        let info = crop_to_region(info, opts)?;
+        if !opts.no_audio {
+            audio::check_selection(&info, opts.audio_tracks).map_err(ReduceError::Usage)?;
+            if info.audio_streams.as_ref().is_some_and(Vec::is_empty) {
+                out.info("The input has no audio; the whole budget goes to the video");
+            }
+        }
+        let duration = resolve_duration(tool, input, opts, out)?;
+        let (duration, shortest) = fit_to_video(&info, duration, opts, out);
+        return Ok(Source {
+            info,
+            duration,
+            image: None,
+            shortest,
+        });
+    };
+    let image = ImageSource::open(input, kind)?;
+    let mut info = crop_to_region(tool.get_video_info(&image.probe_path)?, opts)?;
+    info.avg_frame_rate = Some(image.fps().to_string());
+    if let ImageInput::Sequence { .. } = kind {
+        out.info(&format!(
+            "Frames: {} from {}",
+            image.frames, image.probe_path
+        ));
+    }
+    Ok(Source {
+        info,
+        duration: image.duration(),
+        image: Some(image),
+        shortest: false,
+    })
+}
+
+/// Warns that only the durations of `input` are known; see
+/// [`VideoInfo::from_headers`].
+fn warn_assumed(input: &str, out: Presenter) {
+    let (width, height) = mp4box::ASSUMED_SIZE;
+    let message = format!(
+        "ffprobe can't be run, so only the durations of {} are known, from its MP4 headers; it is taken to be {}x{} at {} fps, with stereo audio",
+        input,
+        width,
+        height,
+        estimate::DEFAULT_FPS
+    );
+    warning::emit(out, Warning::new(Code::ProbedWithoutFfprobe, message));
+}
+
+/// `info` as the rest of the run sees it with `--region`, which must lie
+/// within its frame.
+fn crop_to_region(info: VideoInfo, opts: &ReduceOptions) -> Result<VideoInfo, ReduceError> {
+    let Some(region) = opts.region else {
+        return Ok(info);
+    };
+    if let Some(problem) = region.misfit(&info) {
+        return Err(ReduceError::Usage(problem));
+    }
+    Ok(region.crop(&info))
+}
+
+/// With `--trim-to-video`, the duration to budget for when the audio of
+/// `info` runs on past the video, and whether to end the output with the
+/// video. The format `duration` is the longest stream's, so encoding it
+/// all would spend bits on seconds of a frozen last frame. An explicit
+/// `--duration` stands as given.
+fn fit_to_video(
+    info: &VideoInfo,
+    duration: f64,
+    opts: &ReduceOptions,
+    out: Presenter,
+) -> (f64, bool) {
+    if opts.duration.is_some() {
+        return (duration, false);
+    }
+    match info.length_mismatch() {
+        Some(LengthMismatch::AudioLonger { video, audio }) if opts.trim_to_video => {
+            warning::emit(
+                out,
+                Warning::new(
+                    Code::AudioPastVideo,
+                    format!(
+                        "the audio runs {} past the end of the video; the output ends with the video (--no-trim-to-video keeps the rest)",
+                        format_duration(audio - video)
+                    ),
+                ),
+            );
+            (duration.min(video), true)
+        }
+        Some(LengthMismatch::AudioLonger { video, audio }) => {
+            out.info(&format!(
+                "The audio runs {} past the end of the video; keeping it",
+                format_duration(audio - video)
+            ));
+            (duration, false)
+        }
+        Some(LengthMismatch::VideoLonger { video, audio }) => {
+            out.info(&format!(
+                "The audio ends {} before the video",
+                format_duration(video - audio)
+            ));
+            (duration, false)
+        }
+        None => (duration, false),
+    }
+}
+
+/// Works out the input duration in seconds: `--duration` if given, else
+/// what ffprobe reports, else (or with `--trust-decode-duration`) what
+/// decoding the whole input measures.
+///
+/// Call after probing the stream info, so that a file ffprobe can't read at
+/// all fails there instead of being decoded.
+pub fn resolve_duration<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    opts: &ReduceOptions,
+    out: Presenter,
+) -> Result<f64, ReduceError> {
+    let usable = |d: f64| d.is_finite() && d > 0.0;
+    if let Some(duration) = opts.duration {
+        // Only for the comparison; a failed probe is the usual reason for
+        // the override.
+        if let Ok(probed) = tool.get_video_duration(input) {
+            if usable(probed) && (probed - duration).abs() / duration > DURATION_MISMATCH {
+                warning::emit(
+                    out,
+                    Warning::new(
+                        Code::DurationMismatch,
+                        format!(
+                            "ffprobe reports a duration of {}, but --duration is {}; using {}",
+                            format_duration(probed),
+                            format_duration(duration),
+                            format_duration(duration)
+                        ),
+                    ),
+                );
+            }
+        }
+        return Ok(duration);
+    }
+    if !opts.trust_decode_duration {
+        match tool.get_video_duration(input) {
+            Ok(duration) if usable(duration) => return Ok(duration),
+            Ok(_) | Err(ReduceError::Probe(_)) => warning::emit(
+                out,
+                Warning::new(
+                    Code::DurationDecoded,
+                    "ffprobe reported no usable duration; decoding the input to measure it",
+                ),
+            ),
+            Err(e) => return Err(e),
+        }
+    }
+    tool.get_decoded_duration(input)
+}
+
+/// Everything an encode attempt needs besides the segment and bitrate.
+struct EncodeContext<'a> {
+    input: &'a str,
+    /// Length of the whole input in seconds.
+    duration: f64,
+    /// Length of output, in seconds, that `target_bytes` and the bitrates
+    /// were planned for: [`EncodingPlan::part_duration`].
+    part_duration: f64,
+    graph: &'a FilterGraph,
+    hwdecode: Option<HwDecoding<'a>>,
+    encoder: VideoEncoder,
+    preset: Preset,
+    /// `--x264-params` and the like, when they are for `encoder`.
+    encoder_params: Option<&'a EncoderParams>,
+    /// The ffmpeg audio encoder, the one the output container takes.
+    audio_encoder: AudioEncoder,
+    compat: Compat,
+    /// Write the output as fragmented MP4.
+    fragment_mp4: bool,
+    /// Write a [`Provenance`] tag into the output.
+    tag_metadata: bool,
+    /// See [`ReduceOptions::deterministic`].
+    deterministic: bool,
+    /// See [`ReduceOptions::loop_safe`].
+    loop_safe: bool,
+    /// See [`EncodingPlan::rate_control`].
+    rate_control: RateControl,
+    /// See [`ReduceOptions::hls`].
+    hls: Option<Hls>,
+    /// See [`ReduceOptions::pad_to_exact`].
+    pad_to_exact: bool,
+    /// See [`Source::shortest`].
+    shortest: bool,
+    /// Output frame rate, which chunk boundaries are aligned to.
+    fps: f64,
+    /// Constant output frame rate for `--cfr`.
+    cfr: Option<f64>,
+    /// See [`EncodingPlan::aspect`].
+    aspect: Option<Ratio>,
+    /// Chunks to encode the video in at once; see [`encode_chunked`].
+    chunks: u32,
+    info: &'a VideoInfo,
+    /// The input's cover art copied into the output; see [`coverart::kept`].
+    cover_art: &'a [u32],
+    /// The input's data streams copied into the output; see
+    /// [`datastream::kept`].
+    data_streams: &'a [u32],
+    image: Option<&'a ImageSource>,
+    /// Stream-copy the video on the first attempt instead of encoding it.
+    copy_video: bool,
+    audio: &'a [KeptTrack],
+    run_dir: &'a RunTempDir,
+    out: Presenter,
+    target_bytes: u64,
+    /// Share of `target_bytes` set aside for container overhead.
+    overhead_bytes: u64,
+    sizes: SizeFormat,
+    max_retries: u32,
+    transient_retries: Retries,
+    /// The attempts so far, which `--max-attempts` and `--max-total-time`
+    /// hold the next one to.
+    ledger: &'a Ledger,
+    passes: Passes,
+    /// `--stats` and `--save-stats`, when the encode runs two passes.
+    stats: Option<&'a Stats>,
+}
+
+/// `--hwdecode`: the decoder an encode starts with, and the filter graph
+/// that downloads its frames.
+#[derive(Clone, Copy)]
+struct HwDecoding<'a> {
+    accel: Accel,
+    graph: &'a FilterGraph,
+    /// Set once it failed to start, so the rest of the run decodes in
+    /// software.
+    failed: &'a Cell<bool>,
+}
+
+impl EncodeContext<'_> {
+    /// The hardware decoder encodes use, unless it failed to start.
+    fn accel(&self) -> Option<Accel> {
+        self.hwdecode
+            .filter(|hw| !hw.failed.get())
+            .map(|hw| hw.accel)
+    }
+
+    /// The filter graph for the frames the decoder gives.
+    fn filters(&self) -> &FilterGraph {
+        match self.hwdecode {
+            Some(hw) if !hw.failed.get() => hw.graph,
+            _ => self.graph,
+        }
+    }
+
+    /// `bytes` planned for a whole part, shared down to `length` seconds of
+    /// it: a `--sample` is held to its share of the target, so it is
+    /// verified and retried like the full run would be.
+    fn share_of(&self, bytes: u64, length: f64) -> u64 {
+        if length >= self.part_duration {
+            bytes
+        } else {
+            (bytes as f64 * length / self.part_duration) as u64
+        }
+    }
+}
+
+/// `--stats` and `--save-stats` for encoding `input` as `plan` says; `None`
+/// when neither was given or the encode runs in one pass, which the
+/// `two_pass_skipped` warning already says. Fails when the stats to reuse
+/// were made for a different encode.
+fn plan_stats(
+    input: &str,
+    plan: &EncodingPlan,
+    opts: &ReduceOptions,
+) -> Result<Option<Stats>, ReduceError> {
+    if plan.passes != Passes::Two || (opts.stats.is_none() && opts.save_stats.is_none()) {
+        return Ok(None);
+    }
+    let header = passstats::Header::of(input, opts.encoder, plan.width, plan.height, plan.fps);
+    if let Some(path) = &opts.stats {
+        passstats::load(path)
+            .and_then(|saved| passstats::check(&saved, &header))
+            .map_err(|e| {
+                ReduceError::Usage(format!(
+                    "cannot reuse the two-pass stats in {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+    }
+    Ok(Some(Stats {
+        header,
+        reuse: opts.stats.clone(),
+        save: opts.save_stats.clone(),
+    }))
+}
+
+/// Keeps the stats of the first pass where `--save-stats` asked. A failure
+/// only costs later runs their first pass, so it is a warning.
+fn save_stats(run_dir: &RunTempDir, stats: &Stats, out: Presenter) {
+    let Some(path) = &stats.save else {
+        return;
+    };
+    match passstats::save(&run_dir.passlog_prefix(), path, &stats.header) {
+        Ok(()) => out.info(&format!("Saved the two-pass stats to {}", path.display())),
+        Err(e) => warning::emit(
+            out,
+            Warning::new(
+                Code::StatsNotSaved,
+                format!(
+                    "cannot save the two-pass stats to {}: {}",
+                    path.display(),
+                    e
+                ),
+            ),
+        ),
+    }
+}
+
+/// Encodes `segment` (the whole input when `None`) into `output`, lowering
+/// the bitrate and retrying while the result is over the target. Returns
+/// how the final attempt compared with its predicted size.
+fn encode_to_target<T: VideoTool>(
+    tool: &T,
+    ctx: &EncodeContext,
+    segment: Option<Segment>,
+    video_bitrate: u64,
+    output: &str,
+) -> Result<Option<Prediction>, ReduceError> {
+    let out = ctx.out;
+    let to_stdout = output == STDIO_PATH;
+    // An HLS output is a directory of the run, holding the playlist.
+    let staging = ctx.hls.map(|_| ctx.run_dir.file("hls"));
+    let partial = match &staging {
+        Some(dir) => dir.join(hls::PLAYLIST),
+        None => partial_output_path(ctx.run_dir, output),
+    };
+    let partial_str = partial.to_string_lossy().into_owned();
+    // Progress, the prediction and the retry math all go by the length
+    // actually encoded.
+    let length = segment.map_or(ctx.duration, |s| s.length);
+    let target_bytes = ctx.share_of(ctx.target_bytes, length);
+    let overhead_bytes = ctx.share_of(ctx.overhead_bytes, length);
+
+    let mut video_bitrate = video_bitrate;
+    let mut first_pass_done = false;
+    if let Some(path) = ctx.stats.and_then(|stats| stats.reuse.as_deref()) {
+        passstats::seed(path, &ctx.run_dir.passlog_prefix()).map_err(|e| {
+            ReduceError::Encode(format!(
+                "cannot reuse the two-pass stats in {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        out.info(&format!(
+            "Pass 1 of 2: reusing the stats in {} (--stats)",
+            path.display()
+        ));
+        out.info("Pass 2 of 2: encoding");
+        first_pass_done = true;
+    }
+    let attempts = ctx.max_retries + 1;
+    for attempt in 1..=attempts {
+        let video_bitrate_str = format!("{}k", video_bitrate / 1000);
+        let destination = if to_stdout {
+            STDIO_PATH
+        } else {
+            partial_str.as_str()
+        };
+        if let Some(dir) = &staging {
+            hls::clear(dir).map_err(|e| {
+                ReduceError::Encode(format!("cannot prepare {}: {}", dir.display(), e))
+            })?;
+        }
+        // Only the first attempt copies; an oversized copy is re-encoded.
+        let copy_video = ctx.copy_video && attempt == 1;
+        match attempt {
+            1 => events::phase(Phase::Encoding),
+            _ => events::emit(&Event::Phase {
+                phase: Phase::Retry,
+                retry: Some(attempt - 1),
+            }),
+        }
+        // Output already streamed can't be taken back, so a stream is
+        // never started again.
+        let retries = if to_stdout {
+            Retries::NONE
+        } else {
+            ctx.transient_retries
+        };
+        let within = Some(transient::STARTUP);
+        let mut run_attempt = || {
+            // The analysis holds for a lower bitrate too, so a retry over the
+            // target only runs the second pass again.
+            if ctx.passes == Passes::Two && !copy_video && !first_pass_done {
+                out.info("Pass 1 of 2: analyzing the video");
+                let mut display = ProgressDisplay::new(out, length);
+                let args = first_pass_args(ctx, segment, &video_bitrate_str);
+                let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+                let result =
+                    tool.run_ffmpeg_with_progress(&args, &mut |progress| display.update(progress));
+                display.finish();
+                if interrupt::is_interrupted() {
+                    return Err(ReduceError::Interrupted);
+                }
+                result?;
+                first_pass_done = true;
+                if let Some(stats) = ctx.stats {
+                    save_stats(ctx.run_dir, stats, out);
+                }
+                out.info("Pass 2 of 2: encoding");
+            }
+            let mut display = ProgressDisplay::new(out, length);
+            let result = if ctx.chunks > 1 && !copy_video {
+                encode_chunked(tool, ctx, &video_bitrate_str, destination, &mut display)
+            } else {
+                let args = encode_args(ctx, segment, copy_video, &video_bitrate_str, destination);
+                let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+                tool.run_ffmpeg_with_progress(&args, &mut |progress| display.update(progress))
+            };
+            display.finish();
+            if interrupt::is_interrupted() {
+                return Err(ReduceError::Interrupted);
+            }
+            result
+        };
+        let planned_bitrate = (!copy_video).then_some(video_bitrate);
+        let mut encode = |mut kind: AttemptKind| {
+            transient::retry(retries, "starting the encode", within, out, || {
+                ctx.ledger.begin(kind, planned_bitrate, target_bytes)?;
+                // Another go at this same encode is a retry of it.
+                kind = AttemptKind::TransientRetry;
+                let result = run_attempt();
+                if let Err(e) = &result {
+                    ctx.ledger.fail(e);
+                }
+                result
+            })
+        };
+        let mut result = encode(match attempt {
+            1 => AttemptKind::Encode,
+            _ => AttemptKind::OverTargetRetry,
+        });
+        if let Some(hw) = ctx.hwdecode.filter(|hw| !hw.failed.get()) {
+            if result.as_ref().is_err_and(hwdecode::failed_to_start) {
+                hwdecode::warn_fallback(hw.accel, out);
+                hw.failed.set(true);
+                result = encode(AttemptKind::SoftwareDecode);
+            }
+        }
+        result.map_err(|e| out_of_space(e, ctx.run_dir))?;
+
+        // A stream that has already been written can't be checked or redone.
+        if to_stdout {
+            ctx.ledger.end(Outcome::Streamed);
+            return Ok(None);
+        }
+        events::phase(Phase::Verifying);
+        let actual_bytes = match &staging {
+            Some(dir) => hls::total_bytes(dir),
+            None => std::fs::metadata(&partial).map(|m| m.len()),
+        }
+        .map_err(|e| ReduceError::Encode(format!("cannot read encoded file: {}", e)))
+        .inspect_err(|e| ctx.ledger.fail(e))?;
+        let planned_video = if copy_video {
+            ctx.info.bit_rate().unwrap_or(video_bitrate)
+        } else {
+            video_bitrate
+        };
+        let prediction = Prediction {
+            payload_bytes: ((planned_video
+                + audio::total_bitrate(ctx.audio)
+                + datastream::bitrate(&ctx.info.data_streams, ctx.data_streams))
+                as f64
+                * length
+                / 8.0) as u64,
+            overhead_bytes,
+            actual_bytes,
+        };
+        // Too close under the target for `--pad-to-exact` to fill with a box,
+        // the output is retried a box lower.
+        let unpaddable = ctx.pad_to_exact
+            && staging.is_none()
+            && actual_bytes <= target_bytes
+            && !padding::can_pad(actual_bytes, target_bytes);
+        if actual_bytes <= target_bytes && !unpaddable {
+            ctx.ledger.end(Outcome::Kept {
+                bytes: actual_bytes,
+            });
+            if ctx.loop_safe {
+                looping::verify(tool, &partial_str, ctx.fps, out);
+            }
+            let bytes = match &staging {
+                Some(dir) => {
+                    move_into_place(tool, dir, actual_bytes, output, ctx.run_dir, "HLS")?;
+                    actual_bytes
+                }
+                None => {
+                    let bytes =
+                        pad_to_target(&partial, actual_bytes, target_bytes, ctx.pad_to_exact, out)?;
+                    move_into_place(tool, &partial, bytes, output, ctx.run_dir, "encoded")?;
+                    bytes
+                }
+            };
+            warning::recap(out);
+            out.success(&format!("Done: {} ({})", output, ctx.sizes.size(bytes)));
+            return Ok(Some(prediction));
+        }
+
+        let short = target_bytes.saturating_sub(actual_bytes);
+        let retry_bitrate = if unpaddable {
+            ctx.ledger.end(Outcome::Failed {
+                error: unpaddable_error(short),
+            });
+            shrink_bitrate(
+                video_bitrate,
+                actual_bytes,
+                target_bytes - padding::MIN_BOX_BYTES,
+            )
+        } else {
+            ctx.ledger.end(Outcome::OverTarget {
+                bytes: actual_bytes,
+            });
+            shrink_bitrate(video_bitrate, actual_bytes, target_bytes)
+        };
+        if attempt == attempts || retry_bitrate >= video_bitrate {
+            if unpaddable {
+                return Err(ReduceError::Encode(format!(
+                    "cannot pad the output to {} bytes: {}",
+                    target_bytes,
+                    unpaddable_error(short)
+                )));
+            }
+            return Err(ReduceError::OverTarget {
+                actual_bytes,
+                target_bytes,
+                attempts: attempt,
+            });
+        }
+        // Said before the warning that would promise another attempt.
+        ctx.ledger.check()?;
+        let message = if unpaddable {
+            format!(
+                "output was {} bytes short of the {} target, too close to pad; retrying at {}k",
+                short,
+                ctx.sizes.size(target_bytes),
+                retry_bitrate / 1000
+            )
+        } else {
+            format!(
+                "output was {}, over the {} target; retrying at {}k",
+                ctx.sizes.size_against(actual_bytes, target_bytes),
+                ctx.sizes.size(target_bytes),
+                retry_bitrate / 1000
+            )
+        };
+        warning::emit(out, Warning::new(Code::OverTargetRetry, message));
+        video_bitrate = retry_bitrate;
+    }
+    unreachable!("the last attempt always returns")
+}
+
+/// The settings of an attempt encoding the video at `video_bitrate`, or
+/// copying it when `None`.
+fn provenance<'a>(ctx: &'a EncodeContext, video_bitrate: Option<&'a str>) -> Provenance<'a> {
+    Provenance {
+        target_bytes: ctx.target_bytes,
+        size_units: ctx.sizes.units,
+        encoder: ctx.encoder,
+        preset: ctx.preset,
+        video_bitrate,
+        audio_bitrate: audio::total_bitrate(ctx.audio),
+        encoder_params: ctx.encoder_params,
+    }
+}
+
+/// `err`, as [`ReduceError::DiskFull`] when ffmpeg ran out of space in the
+/// run directory, which is then cleared to give back what the run took.
+fn out_of_space(err: ReduceError, run_dir: &RunTempDir) -> ReduceError {
+    let err = diskspace::classify(err, run_dir.path());
+    if matches!(err, ReduceError::DiskFull { .. }) {
+        run_dir.clear();
+    }
+    err
+}
+
+/// MP4 flags for muxing without seeking back: each keyframe starts a
+/// fragment, and the index up front is empty.
+const FRAGMENTED_MOVFLAGS: &str = "frag_keyframe+empty_moov";
+
+/// Refuses an output whose extension names no container the tool writes,
+/// before anything is probed. WebM only holds AV1, so an H.264 encode into
+/// it fails here too; a legacy AVI output is written with H.264 whatever
+/// the codec asked for.
+fn plan_container(
+    output: &str,
+    opts: &ReduceOptions,
+    out: Presenter,
+) -> Result<ReduceOptions, ReduceError> {
+    // stdout is always fragmented MP4, and so are HLS segments.
+    if output == STDIO_PATH || opts.hls.is_some() {
+        return Ok(opts.clone());
+    }
+    let container =
+        Container::for_output(output, opts.allow_legacy_container).map_err(ReduceError::Usage)?;
+    let encoders = container.video_encoders();
+    if encoders.contains(&opts.encoder) {
+        return Ok(opts.clone());
+    }
+    if !container.is_legacy() {
+        let codec = |encoder: VideoEncoder| {
+            let value = encoder.to_possible_value().expect("no skipped encoders");
+            value.get_name().to_string()
+        };
+        return Err(ReduceError::Usage(format!(
+            "{} can't hold {} video; use --codec {} or another extension",
+            container,
+            codec(opts.encoder),
+            codec(encoders[0])
+        )));
+    }
+    out.info(&format!(
+        "{} output: encoding with {} instead of {}",
+        container, encoders[0], opts.encoder
+    ));
+    Ok(ReduceOptions {
+        encoder: encoders[0],
+        encoder_params: None,
+        ..opts.clone()
+    })
+}
+
+/// The container `output` is written as; see [`plan_container`].
+fn output_container(output: &str) -> Container {
+    Container::from_path(output).unwrap_or(Container::Mp4)
+}
+
+/// Checks the filesystems the run writes to. A target larger than one of
+/// them can hold fails up front. When the temp directory, where ffmpeg
+/// muxes, seeks poorly, the run moves to the system temp directory with
+/// `--safe-remote-write`, or else writes MP4 fragmented.
+fn plan_writes<T: VideoTool>(
+    tool: &T,
+    output: &str,
+    opts: &ReduceOptions,
+    out: Presenter,
+) -> Result<ReduceOptions, ReduceError> {
+    let temp = opts.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let mut dirs = vec![temp.as_path()];
+    if output != STDIO_PATH {
+        dirs.push(output_dir(output));
+    }
+    for dir in dirs {
+        let Some(fs) = tool.filesystem_of(dir) else {
+            continue;
+        };
+        if let Some(max) = fs.max_file_bytes().filter(|&max| opts.target_bytes > max) {
+            return Err(ReduceError::Usage(format!(
+                "{} is on {}, which can't hold files over {}, but the target is {}; choose a smaller --size (--split keeps the total) or write elsewhere",
+                dir.display(),
+                fs.name(),
+                opts.sizes.size(max),
+                opts.sizes.size(opts.target_bytes)
+            )));
+        }
+    }
+    let mut opts = opts.clone();
+    // Streamed output is muxed into the pipe, not the temp directory.
+    let Some(fs) = tool
+        .filesystem_of(&temp)
+        .filter(|fs| fs.seeks_poorly() && output != STDIO_PATH)
+    else {
+        return Ok(opts);
+    };
+    let local = std::env::temp_dir();
+    let local_is_safe = tool
+        .filesystem_of(&local)
+        .is_none_or(|fs| !fs.seeks_poorly());
+    if opts.safe_remote_write && local_is_safe && local != temp {
+        out.info(&format!(
+            "{} is on an {} share; encoding in {} and copying the result over",
+            temp.display(),
+            fs.name(),
+            local.display()
+        ));
+        opts.temp_dir = Some(local);
+    } else if Container::from_path(output).is_some_and(Container::wants_faststart) {
+        out.info(&format!(
+            "{} is on an {} share, which MP4 muxing can't seek in reliably; writing fragmented MP4",
+            temp.display(),
+            fs.name()
+        ));
+        opts.fragment_mp4 = true;
+    } else {
+        warning::emit(
+            out,
+            Warning::new(
+                Code::UnseekableTempDir,
+                format!(
+                    "{} is on an {} share, which muxing may not seek in reliably; --safe-remote-write encodes on a local disk instead",
+                    temp.display(),
+                    fs.name()
+                ),
+            ),
+        );
+    }
+    Ok(opts)
+}
+
+/// Commits the finished `partial` (the `what` file), measured at `bytes`,
+/// to `output`; see [`commit::commit`]. A directory `partial` is an HLS
+/// playlist with its segments, committed as a set by [`hls::commit`]. A
+/// disk that fills up during a copy across filesystems leaves nothing
+/// behind at either end.
+fn move_into_place<T: VideoTool>(
+    tool: &T,
+    partial: &Path,
+    bytes: u64,
+    output: &str,
+    run_dir: &RunTempDir,
+    what: &str,
+) -> Result<(), ReduceError> {
+    let committed = if partial.is_dir() {
+        hls::commit(tool, partial, Path::new(output))
+    } else {
+        commit::commit(tool, partial, Path::new(output), Some(bytes))
+    };
+    committed.map_err(|e| {
+        if diskspace::is_storage_full(&e) {
+            let err = diskspace::full(output_dir(output));
+            run_dir.clear();
+            return err;
+        }
+        ReduceError::Encode(format!("cannot move {} file to {}: {}", what, output, e))
+    })
+}
+
+/// With `--pad-to-exact`, pads `partial`, verified at `bytes`, to
+/// `target_bytes`; returns the size it then has.
+fn pad_to_target(
+    partial: &Path,
+    bytes: u64,
+    target_bytes: u64,
+    pad: bool,
+    out: Presenter,
+) -> Result<u64, ReduceError> {
+    if !pad {
+        return Ok(bytes);
+    }
+    let added = padding::pad(partial, target_bytes).map_err(|e| {
+        ReduceError::Encode(format!(
+            "cannot pad the output to {} bytes: {}",
+            target_bytes, e
+        ))
+    })?;
+    if added > 0 {
+        out.info(&format!(
+            "Padded to exactly {} bytes with a {}-byte free box",
+            target_bytes, added
+        ));
+    }
+    Ok(target_bytes)
+}
+
+/// Why an output `short` bytes under the target can't be padded to it.
+fn unpaddable_error(short: u64) -> String {
+    format!(
+        "it is {} bytes short, and a box takes at least {}",
+        short,
+        padding::MIN_BOX_BYTES
+    )
+}
+
+/// The directory `output` goes into.
+fn output_dir(output: &str) -> &Path {
+    match Path::new(output).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Warns when a disk this run writes to has little more room than the run
+/// may take; see [`Needs::of_run`].
+fn check_free_space(
+    opts: &ReduceOptions,
+    output: &str,
+    run_dir: &RunTempDir,
+    chunks: u32,
+    out: Presenter,
+) {
+    let output_dir = output_dir(output);
+    let needs = Needs::of_run(
+        opts.target_bytes,
+        opts.parts,
+        chunks > 1,
+        run_dir.path(),
+        output_dir,
+        diskspace::same_disk(run_dir.path(), output_dir),
+    );
+    for (dir, needed) in needs.dirs {
+        let Some(free) = diskspace::free_bytes(&dir) else {
+            continue;
+        };
+        if diskspace::is_tight(needed, free) {
+            warning::emit(
+                out,
+                Warning::new(
+                    Code::LowDiskSpace,
+                    format!(
+                        "only {} free on the disk holding {}, and this run may write up to {} there",
+                        opts.sizes.size(free),
+                        dir.display(),
+                        opts.sizes.size(needed)
+                    ),
+                ),
+            );
+        }
+    }
+}
+
+/// Builds the ffmpeg command line for one encode attempt writing to
+/// `destination` (`-` for stdout).
+fn encode_args(
+    ctx: &EncodeContext,
+    segment: Option<Segment>,
+    copy_video: bool,
+    video_bitrate: &str,
+    destination: &str,
+) -> Vec<String> {
+    let to_stdout = destination == STDIO_PATH;
+    // The command-line below tells ffmpeg to overwrite the output file (-y),
+    // use libx264 for video encoding with our computed bitrate, and
+    // encode audio using AAC at 128k.
+    // -progress pipe:1 gives us machine-readable progress for the ETA display;
+    // it is dropped when stdout carries the video itself.
+    let mut args: Vec<String> = vec!["-y".into()];
+    if !to_stdout {
+        args.extend(
+            ["-progress", "pipe:1", "-nostats"]
+                .iter()
+                .map(|s| s.to_string()),
+        );
+    }
+    args.extend(input_args(ctx, segment, !copy_video));
+    if copy_video {
+        args.extend(["-c:v".to_string(), "copy".to_string()]);
+    } else {
+        args.extend(video_encode_args(ctx, video_bitrate));
+        if ctx.passes == Passes::Two {
+            args.extend(["-pass".to_string(), "2".to_string()]);
+        }
+    }
+    // Explicit maps keep exactly the planned tracks, in the planned order.
+    if !ctx.filters().maps_video() {
+        args.extend(["-map".to_string(), coverart::VIDEO.to_string()]);
+    }
+    args.extend(coverart::args(ctx.cover_art, 0));
+    args.extend(audio_args(ctx));
+    args.extend(datastream::args(ctx.data_streams, 0));
+    if let Some(hls) = ctx.hls.filter(|_| !copy_video) {
+        args.extend(hls::keyframe_args(hls));
+    }
+    if ctx.loop_safe && !ctx.audio.is_empty() {
+        args.extend(looping::audio_args(
+            segment.map_or(ctx.duration, |s| s.length),
+        ));
+    }
+    if (ctx.shortest || ctx.loop_safe) && !ctx.audio.is_empty() {
+        args.push("-shortest".to_string());
+    }
+    if ctx.tag_metadata {
+        args.extend(provenance(ctx, (!copy_video).then_some(video_bitrate)).metadata_args());
+    }
+    if ctx.deterministic {
+        args.extend(deterministic::encoder_args());
+        args.extend(deterministic::muxer_args());
+    }
+    let mp4 =
+        to_stdout || Container::from_path(destination).is_some_and(Container::wants_faststart);
+    if ctx.loop_safe && mp4 {
+        args.extend(looping::muxer_args());
+    }
+    if to_stdout {
+        // A pipe is not seekable, so MP4 has to be written fragmented with
+        // the index up front instead of patched in at the end.
+        args.extend(["-f".to_string(), "mp4".to_string()]);
+        args.extend(with_loop_flags(ctx, FRAGMENTED_MOVFLAGS.to_string()));
+        args.push("pipe:1".to_string());
+    } else if let Some(hls) = ctx.hls {
+        args.extend(hls::muxer_args(hls, destination));
+        args.push(longpath::for_tool(destination));
+    } else {
+        args.extend(movflags(ctx, destination));
+        args.push(longpath::for_tool(destination));
+    }
+    args
+}
+
+/// The input of an encode of `segment`, or of the whole input when `None`;
+/// `decode` when the video is decoded rather than copied.
+fn input_args(ctx: &EncodeContext, segment: Option<Segment>, decode: bool) -> Vec<String> {
+    // Seeking as an input option is fast and makes the output start at zero,
+    // which keeps the progress display relative to the segment.
+    if let Some(image) = ctx.image {
+        return image.input_args(segment);
+    }
+    let mut args = Vec::new();
+    if let Some(accel) = ctx.accel().filter(|_| decode) {
+        args.extend(accel.input_args());
+    }
+    if let Some(segment) = segment {
+        if segment.start > 0.0 {
+            args.extend(["-ss".to_string(), format!("{:.3}", segment.start)]);
+        }
+        args.extend(["-t".to_string(), format!("{:.3}", segment.length)]);
+    }
+    args.extend(["-i".to_string(), longpath::for_tool(ctx.input)]);
+    args
+}
+
+/// The first of two passes: the video only, analyzed into the pass log
+/// and encoded into nothing.
+fn first_pass_args(
+    ctx: &EncodeContext,
+    segment: Option<Segment>,
+    video_bitrate: &str,
+) -> Vec<String> {
+    let mut args: Vec<String> = ["-y", "-progress", "pipe:1", "-nostats"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    args.extend(input_args(ctx, segment, true));
+    args.extend(video_encode_args(ctx, video_bitrate));
+    if let Some(hls) = ctx.hls {
+        args.extend(hls::keyframe_args(hls));
+    }
+    if !ctx.filters().maps_video() {
+        args.extend(["-map".to_string(), coverart::VIDEO.to_string()]);
+    }
+    if ctx.deterministic {
+        // The second pass reads what this one decided, so it runs alike.
+        args.extend(deterministic::encoder_args());
+    }
+    args.extend(
+        ["-pass", "1", "-an", "-f", "null", twopass::NULL_OUTPUT]
+            .iter()
+            .map(|s| s.to_string()),
+    );
+    args
+}
+
+/// `-movflags` for writing `destination`: fragmented when asked to be,
+/// else with the index up front where the device wants it.
+fn movflags(ctx: &EncodeContext, destination: &str) -> Vec<String> {
+    let mp4 = Container::from_path(destination).is_some_and(Container::wants_faststart);
+    let flags = if ctx.fragment_mp4 {
+        FRAGMENTED_MOVFLAGS.to_string()
+    } else if ctx.compat.faststart && mp4 {
+        "+faststart".to_string()
+    } else if ctx.loop_safe && mp4 {
+        String::new()
+    } else {
+        return Vec::new();
+    };
+    with_loop_flags(ctx, flags)
+}
+
+/// `-movflags` with `flags`, plus [`looping::MOVFLAGS`] for `--loop-safe`.
+fn with_loop_flags(ctx: &EncodeContext, flags: String) -> Vec<String> {
+    let flags = match (ctx.loop_safe, flags.is_empty()) {
+        (false, _) => flags,
+        (true, true) => format!("+{}", looping::MOVFLAGS),
+        (true, false) => format!("{}+{}", flags, looping::MOVFLAGS),
+    };
+    vec!["-movflags".to_string(), flags]
+}
+
+/// Maps and encoder options for the kept audio tracks, or `-an` for none.
+fn audio_args(ctx: &EncodeContext) -> Vec<String> {
+    let audio = ctx.audio;
+    let mut args = Vec::new();
+    for track in audio {
+        args.extend(["-map".to_string(), format!("0:a:{}?", track.input_position)]);
+    }
+    if audio.is_empty() {
+        args.push("-an".to_string());
+    } else {
+        args.extend(ctx.audio_encoder.args());
+        for (i, track) in audio.iter().enumerate() {
+            args.extend([format!("-b:a:{}", i), format!("{}k", track.bitrate / 1000)]);
+        }
+        if let Some(rate) = ctx.compat.audio_sample_rate {
+            args.extend(["-ar".to_string(), rate.to_string()]);
+        }
+        if let Some(channels) = ctx.compat.audio_channels {
+            // Only tracks with more channels are downmixed.
+            for (i, track) in audio.iter().enumerate() {
+                args.extend([
+                    format!("-ac:a:{}", i),
+                    track.channels.min(channels).to_string(),
+                ]);
+            }
+        }
+        args.extend(audio::stream_args(audio));
+    }
+    args
+}
+
+/// `--chunked-encode`: encodes the video as [`chunked::chunk_bounds`]
+/// pieces at once, with the audio of the whole input alongside them, then
+/// joins it all into `destination` without re-encoding.
+///
+/// The joined file is probed afterwards: a chunk that came out short would
+/// otherwise only show as a jump in the picture.
+fn encode_chunked<T: VideoTool>(
+    tool: &T,
+    ctx: &EncodeContext,
+    video_bitrate: &str,
+    destination: &str,
+    display: &mut ProgressDisplay,
+) -> Result<(), ReduceError> {
+    let chunks = chunked::chunk_bounds(ctx.duration, ctx.chunks, ctx.fps);
+    let parts: Vec<PathBuf> = (1..=chunks.len())
+        .map(|i| ctx.run_dir.artifact(&format!("chunk{}", i), Some("mkv")))
+        .collect();
+    // The tag, the cover art and the data streams go on at the join.
+    let video_only = EncodeContext {
+        audio: &[],
+        cover_art: &[],
+        data_streams: &[],
+        tag_metadata: false,
+        ..*ctx
+    };
+    let mut jobs: Vec<Vec<String>> = chunks
+        .iter()
+        .zip(&parts)
+        .map(|(chunk, part)| {
+            encode_args(
+                &video_only,
+                Some(*chunk),
+                false,
+                video_bitrate,
+                &part.to_string_lossy(),
+            )
+        })
+        .collect();
+    let audio = (!ctx.audio.is_empty()).then(|| ctx.run_dir.artifact("audio", Some("mka")));
+    if let Some(audio) = &audio {
+        // The audio doesn't depend on the video bitrate, so a retry reuses
+        // what the first attempt encoded.
+        if !audio.exists() {
+            let mut args = vec![
+                "-y".to_string(),
+                "-i".to_string(),
+                longpath::for_tool(ctx.input),
+                "-vn".to_string(),
+            ];
+            args.extend(audio_args(ctx));
+            if ctx.deterministic {
+                args.extend(deterministic::encoder_args());
+                args.extend(deterministic::muxer_args());
+            }
+            args.push(longpath::for_tool(&audio.to_string_lossy()));
+            jobs.push(args);
+        }
+    }
+    let jobs: Vec<Vec<&str>> = jobs
+        .iter()
+        .map(|args| args.iter().map(|s| s.as_str()).collect())
+        .collect();
+    let mut positions = vec![0.0; chunks.len()];
+    let mut speeds = vec![None; chunks.len()];
+    tool.run_ffmpeg_parallel(&jobs, &mut |i, progress| {
+        // Only the chunks report progress; the audio job runs without it.
+        if i >= positions.len() {
+            return;
+        }
+        positions[i] = progress.out_time;
+        speeds[i] = progress.speed;
+        // The chunks run side by side, so their speeds add up.
+        let speed: f64 = speeds.iter().flatten().sum();
+        display.update(&Progress {
+            out_time: positions.iter().sum(),
+            speed: (speed > 0.0).then_some(speed),
+            done: false,
+        });
+    })?;
+
+    let list = ctx.run_dir.artifact("chunks", Some("txt"));
+    std::fs::write(&list, chunked::concat_list(&parts))
+        .map_err(|e| ReduceError::Encode(format!("cannot write the chunk list: {}", e)))?;
+    let mut args = vec![
+        "-y".to_string(),
+        "-f".to_string(),
+        "concat".to_string(),
+        "-safe".to_string(),
+        "0".to_string(),
+        "-i".to_string(),
+        longpath::for_tool(&list.to_string_lossy()),
+    ];
+    if let Some(audio) = &audio {
+        args.extend([
+            "-i".to_string(),
+            longpath::for_tool(&audio.to_string_lossy()),
+        ]);
+    }
+    if !ctx.cover_art.is_empty() || !ctx.data_streams.is_empty() {
+        args.extend(["-i".to_string(), longpath::for_tool(ctx.input)]);
+    }
+    args.extend(["-map".to_string(), "0:v".to_string()]);
+    let source_input = if audio.is_some() { 2 } else { 1 };
+    args.extend(coverart::args(ctx.cover_art, source_input));
+    if audio.is_some() {
+        args.extend(["-map".to_string(), "1:a".to_string()]);
+        // Stream copy carries the tags, but not always the dispositions.
+        args.extend(audio::stream_args(ctx.audio));
+    }
+    args.extend(datastream::args(ctx.data_streams, source_input));
+    args.extend(["-c".to_string(), "copy".to_string()]);
+    // The audio was encoded whole, tail and all.
+    if ctx.shortest && audio.is_some() {
+        args.push("-shortest".to_string());
+    }
+    if ctx.tag_metadata {
+        args.extend(provenance(ctx, Some(video_bitrate)).metadata_args());
+    }
+    if ctx.deterministic {
+        args.extend(deterministic::muxer_args());
+    }
+    args.extend(movflags(ctx, destination));
+    args.push(longpath::for_tool(destination));
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    tool.run_ffmpeg(&args)?;
+
+    // Each join may gain or lose a frame; past that a chunk went missing.
+    let joined = tool.get_video_duration(destination)?;
+    let tolerance = chunks.len() as f64 / ctx.fps + 0.1;
+    if (joined - ctx.duration).abs() > tolerance {
+        return Err(ReduceError::Encode(format!(
+            "the joined chunks last {} but the input lasts {}; try again without --chunked-encode",
+            format_duration(joined),
+            format_duration(ctx.duration)
+        )));
+    }
+    Ok(())
+}
+
+/// Filters and encoder options for encoding (rather than copying) the video.
+fn video_encode_args(ctx: &EncodeContext, video_bitrate: &str) -> Vec<String> {
+    let graph = ctx.filters();
+    let mut args = graph.input_args();
+    let mut filter = graph.filter_args();
+    if !ctx.cover_art.is_empty() && matches!(graph, FilterGraph::Simple(_)) {
+        // A copied stream can't be filtered, so `-vf` would fail on the
+        // cover art.
+        filter[0] = "-filter:v:0".to_string();
+    }
+    args.extend(filter);
+    args.extend([
+        "-c:v".to_string(),
+        ctx.encoder.ffmpeg_name().to_string(),
+        "-preset".to_string(),
+        ctx.encoder.preset_value(ctx.preset),
+    ]);
+    let kbps = video_bitrate.trim_end_matches('k').parse().unwrap_or(0);
+    match ctx.rate_control {
+        RateControl::Abr => args.extend([
+            "-b:v".to_string(),
+            video_bitrate.to_string(),
+            "-passlogfile".to_string(),
+            longpath::for_tool(&ctx.run_dir.passlog_prefix().to_string_lossy()),
+        ]),
+        RateControl::CrfCapped => args.extend(ratecontrol::crf_capped_args(ctx.encoder, kbps)),
+    }
+    if ctx.passes == Passes::Capped {
+        args.extend(twopass::rate_cap_args(kbps));
+    }
+    if let Some(params) = ctx.encoder_params {
+        args.extend(params.args());
+    }
+    if ctx.loop_safe {
+        args.extend(looping::video_args());
+    }
+    // Carry the color signaling over so players don't fall back to guessing (e.g. BT.601).
+    args.extend(ctx.info.color_args());
+    if let Some(aspect) = ctx.aspect {
+        // Stated rather than left to the encoder, so players stretch an
+        // anamorphic frame back to its shape.
+        args.extend(["-aspect".to_string(), aspect.to_string()]);
+    }
+    if let Some(rate) = ctx.cfr {
+        // Duplicates and drops frames onto an even grid, so the audio stays
+        // in sync where a bare `-r` would let it drift on VFR sources.
+        args.extend([
+            "-vsync".to_string(),
+            "cfr".to_string(),
+            "-r".to_string(),
+            rate.to_string(),
+        ]);
+    }
+    if ctx.encoder == VideoEncoder::H264 {
+        if let Some(profile) = ctx.compat.h264_profile {
+            args.extend(["-profile:v".to_string(), profile.to_string()]);
+        }
+        if let Some(level) = ctx.compat.h264_level {
+            args.extend(["-level:v".to_string(), level.to_string()]);
+        }
+    }
+    // RGB frames would otherwise become 4:4:4 H.264, which most players
+    // can't decode.
+    let pix_fmt = ctx
+        .compat
+        .pix_fmt
+        .or(ctx.image.is_some().then_some("yuv420p"));
+    if let Some(pix_fmt) = pix_fmt {
+        args.extend(["-pix_fmt".to_string(), pix_fmt.to_string()]);
+    }
+    args
+}
+
+/// `--remux-only`: copies the streams into the output's container without
+/// encoding anything. `None` when that isn't possible and a normal re-encode
+/// should follow; with `--strict-remux` that is an error instead. `cut` is
+/// set when the encoded window leaves some of the input out.
+#[allow(clippy::too_many_arguments)]
+fn try_remux<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    output: &str,
+    info: &VideoInfo,
+    cover_art: &[u32],
+    data_streams: &[u32],
+    duration: f64,
+    cut: bool,
+    opts: &ReduceOptions,
+    run_dir: &RunTempDir,
+    ledger: &Ledger,
+    out: Presenter,
+) -> Result<Option<ReduceReport>, ReduceError> {
+    let give_up = |reason: String| {
+        if opts.strict_remux {
+            Err(ReduceError::Usage(format!(
+                "cannot remux: {} (stopping because of --strict-remux)",
+                reason
+            )))
+        } else {
+            let message = format!("cannot remux: {}; re-encoding instead", reason);
+            warning::emit(out, Warning::new(Code::RemuxFallback, message));
+            Ok(None)
+        }
+    };
+    if output == STDIO_PATH {
+        return give_up("remuxing needs an output file, not stdout".into());
+    }
+    if cut {
+        return give_up("a copy can't leave out part of the input".into());
+    }
+    let Some(container) = Container::from_path(output) else {
+        return give_up(format!("no known container for {}", output));
+    };
+    // A URL's size isn't known up front; the remuxed file is checked below.
+    let input_bytes = std::fs::metadata(input).ok().map(|m| m.len());
+    if let Some(bytes) = input_bytes.filter(|&bytes| bytes > opts.target_bytes) {
+        return give_up(format!(
+            "the input is {}, over the target",
+            opts.sizes.size(bytes)
+        ));
+    }
+    let video = info.codec_name.as_deref().unwrap_or("unknown");
+    let audio: Vec<&str> = match (&info.audio_streams, opts.no_audio) {
+        (Some(streams), false) => streams
+            .iter()
+            .map(|s| s.codec_name.as_deref().unwrap_or("unknown"))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let subtitles = tool.get_subtitle_codecs(input)?;
+    let subtitles: Vec<&str> = subtitles.iter().map(String::as_str).collect();
+    let remux = match container.check(video, &audio, &subtitles) {
+        Ok(remux) => remux,
+        Err(reason) => return give_up(reason),
+    };
+    let attachments = fonts::attachments(&tool.get_streams(input)?).len();
+    if attachments > 0 && !container.carries_attachments() {
+        // Not a reason to re-encode: no output of this container could keep
+        // them.
+        warning::emit(
+            out,
+            Warning::new(
+                Code::AttachmentsDropped,
+                format!(
+                    "{} attachment{} of the input (usually subtitle fonts) can't go into {} and {} left out",
+                    attachments,
+                    if attachments == 1 { "" } else { "s" },
+                    container,
+                    if attachments == 1 { "is" } else { "are" }
+                ),
+            ),
+        );
+    }
+
+    match input_bytes {
+        Some(bytes) => out.info(&format!(
+            "Remuxing into {} without re-encoding (the input is {})",
+            container,
+            opts.sizes.size(bytes)
+        )),
+        None => out.info(&format!("Remuxing into {} without re-encoding", container)),
+    }
+    let partial = partial_output_path(run_dir, output);
+    let args = remux_args(
+        input,
+        &remux,
+        cover_art,
+        data_streams,
+        opts.no_audio,
+        attachments > 0 && container.carries_attachments(),
+        opts.fragment_mp4,
+        opts.deterministic,
+        &partial.to_string_lossy(),
+    );
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    ledger.begin(AttemptKind::Remux, None, opts.target_bytes)?;
+    events::phase(Phase::Remuxing);
+    let mut display = ProgressDisplay::new(out, duration);
+    let result = tool.run_ffmpeg_with_progress(&args, &mut |progress| display.update(progress));
+    display.finish();
+    let result = match result {
+        _ if interrupt::is_interrupted() => Err(ReduceError::Interrupted),
+        result => result.map_err(|e| out_of_space(e, run_dir)),
+    };
+    let actual_bytes = result
+        .and_then(|_| {
+            events::phase(Phase::Verifying);
+            std::fs::metadata(&partial)
+                .map(|m| m.len())
+                .map_err(|e| ReduceError::Encode(format!("cannot read remuxed file: {}", e)))
+        })
+        .inspect_err(|e| ledger.fail(e))?;
+    if opts.pad_to_exact
+        && actual_bytes <= opts.target_bytes
+        && !padding::can_pad(actual_bytes, opts.target_bytes)
+    {
+        let short = opts.target_bytes - actual_bytes;
+        ledger.end(Outcome::Failed {
+            error: unpaddable_error(short),
+        });
+        if opts.strict_remux {
+            return Err(ReduceError::Encode(format!(
+                "cannot pad the output to {} bytes: {}",
+                opts.target_bytes,
+                unpaddable_error(short)
+            )));
+        }
+        let message = format!(
+            "the remuxed file came out {} bytes short of the {} target, too close to pad; re-encoding instead",
+            short,
+            opts.sizes.size(opts.target_bytes)
+        );
+        warning::emit(out, Warning::new(Code::RemuxFallback, message));
+        return Ok(None);
+    }
+    if actual_bytes > opts.target_bytes {
+        ledger.end(Outcome::OverTarget {
+            bytes: actual_bytes,
+        });
+        if opts.strict_remux {
+            return Err(ReduceError::OverTarget {
+                actual_bytes,
+                target_bytes: opts.target_bytes,
+                attempts: 1,
+            });
+        }
+        let message = format!(
+            "the remuxed file came out at {}, over the {} target; re-encoding instead",
+            opts.sizes.size_against(actual_bytes, opts.target_bytes),
+            opts.sizes.size(opts.target_bytes)
+        );
+        warning::emit(out, Warning::new(Code::RemuxFallback, message));
+        return Ok(None);
+    }
+    ledger.end(Outcome::Kept {
+        bytes: actual_bytes,
+    });
+    let bytes = pad_to_target(
+        &partial,
+        actual_bytes,
+        opts.target_bytes,
+        opts.pad_to_exact,
+        out,
+    )?;
+    move_into_place(tool, &partial, bytes, output, run_dir, "remuxed")?;
+    warning::recap(out);
+    out.success(&format!("Done: {} ({})", output, opts.sizes.size(bytes)));
+    Ok(Some(ReduceReport::default()))
+}
+
+/// The ffmpeg arguments for copying the first video stream, the audio and
+/// the subtitles of `input` into `destination`, and with `attachments` the
+/// attached files too. `cover_art` and `data_streams` are what
+/// [`coverart::kept`] and [`datastream::kept`] kept.
+/// `fragment_mp4` writes MP4 and MOV fragmented.
+#[allow(clippy::too_many_arguments)]
+fn remux_args(
+    input: &str,
+    remux: &Remux,
+    cover_art: &[u32],
+    data_streams: &[u32],
+    no_audio: bool,
+    attachments: bool,
+    fragment_mp4: bool,
+    deterministic: bool,
+    destination: &str,
+) -> Vec<String> {
+    let mut args: Vec<String> = ["-y", "-progress", "pipe:1", "-nostats", "-i"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    args.push(longpath::for_tool(input));
+    args.extend(["-map".to_string(), coverart::VIDEO.to_string()]);
+    args.extend(coverart::args(cover_art, 0));
+    if !no_audio {
+        args.extend(["-map".to_string(), "0:a?".to_string()]);
+    }
+    args.extend(["-map".to_string(), "0:s?".to_string()]);
+    args.extend(datastream::args(data_streams, 0));
+    if attachments {
+        // Without its fonts, ASS subtitles fall back to whatever the player
+        // has and lose their typesetting.
+        args.extend(["-map".to_string(), "0:t?".to_string()]);
+    }
+    args.extend(["-c".to_string(), "copy".to_string()]);
+    if let Some(codec) = remux.subtitle_codec {
+        args.extend(["-c:s".to_string(), codec.to_string()]);
+    }
+    if fragment_mp4 {
+        // Moving the index to the front rewrites the file, which is what
+        // fragmenting avoids.
+        args.extend(["-movflags".to_string(), FRAGMENTED_MOVFLAGS.to_string()]);
+    } else if remux.container.wants_faststart() {
+        args.extend(["-movflags".to_string(), "+faststart".to_string()]);
+    }
+    if deterministic {
+        args.extend(deterministic::muxer_args());
+    }
+    args.push(longpath::for_tool(destination));
+    args
+}
+
+/// `--overhead-percent`, or the overhead learned for the container of
+/// `output` once the history has enough samples of it.
+fn overhead_percent(opts: &ReduceOptions, output: &str, out: Presenter) -> f64 {
+    let container = Container::from_path(output);
+    let learned = opts
+        .learned_overhead
+        .as_ref()
+        .zip(container)
+        .and_then(|(learned, container)| learned.percent_for(container));
+    match (learned, container) {
+        (Some((percent, samples)), Some(container)) => {
+            let percent = (percent * 100.0).round() / 100.0;
+            out.info(&format!(
+                "Overhead allowance {}%, learned from {} earlier {} outputs",
+                percent, samples, container
+            ));
+            percent
+        }
+        _ => opts.overhead_percent,
+    }
+}
+
+/// The `--x264-params` (or the like) of `opts` if they are for the encoder
+/// it uses.
+fn applied_params(opts: &ReduceOptions) -> Option<&EncoderParams> {
+    opts.encoder_params
+        .as_ref()
+        .filter(|params| params.encoder == opts.encoder)
+}
+
+/// Says when the encoder pass-through options are left out, and warns when
+/// they set the bitrate or rate control the size targeting relies on.
+fn check_encoder_params(opts: &ReduceOptions, out: Presenter) {
+    let Some(params) = &opts.encoder_params else {
+        return;
+    };
+    if params.encoder != opts.encoder {
+        out.info(&format!(
+            "Encoding with {}, so --{} is left out",
+            opts.encoder,
+            params.encoder.params_option()
+        ));
+        return;
+    }
+    let keys = params.rate_control_keys();
+    if !keys.is_empty() {
+        let message = format!(
+            "--{} sets {}, which fights the bitrate the tool picks to hit the target size",
+            params.encoder.params_option(),
+            keys.join(", ")
+        );
+        warning::emit(out, Warning::new(Code::EncoderParamsRateControl, message));
+    }
+}
+
+/// Warns when an explicit preset wins over `--effort`: `--preset`, or a
+/// `preset` in the encoder's pass-through options.
+fn check_effort(opts: &ReduceOptions, out: Presenter) {
+    let Some(effort) = opts.effort else {
+        return;
+    };
+    let mapped = effort::native(opts.encoder, effort);
+    let overridden_by = match applied_params(opts).and_then(|p| p.get("preset")) {
+        Some(value) => Some(format!(
+            "--{} preset={}",
+            opts.encoder.params_option(),
+            value
+        )),
+        None => {
+            (opts.preset != effort::preset(effort)).then(|| format!("--preset {}", opts.preset))
+        }
+    };
+    if let Some(flag) = overridden_by {
+        let message = format!(
+            "{} overrides --effort {} (preset {} for {})",
+            flag, effort, mapped, opts.encoder
+        );
+        warning::emit(out, Warning::new(Code::EffortOverridden, message));
+    }
+}
+
+/// `requested` when this ffmpeg build has it, otherwise H.264 (which every
+/// build the tool supports has, so it isn't checked).
+fn available_encoder<T: VideoTool>(
+    tool: &T,
+    requested: VideoEncoder,
+    out: Presenter,
+) -> Result<VideoEncoder, ReduceError> {
+    if requested == VideoEncoder::H264 {
+        return Ok(requested);
+    }
+    if tool
+        .list_encoders()?
+        .iter()
+        .any(|name| name == requested.ffmpeg_name())
+    {
+        return Ok(requested);
+    }
+    let message = format!(
+        "this ffmpeg has no {} encoder (check `ffmpeg -encoders`); falling back to H.264 with {}",
+        requested,
+        VideoEncoder::H264
+    );
+    warning::emit(out, Warning::new(Code::EncoderFallback, message));
+    Ok(VideoEncoder::H264)
+}
+
+/// The best encoder this ffmpeg has for `codec`, said in the status
+/// output; without `tracks` to encode, nothing is asked of ffmpeg.
+fn available_audio_encoder<T: VideoTool>(
+    tool: &T,
+    codec: AudioCodec,
+    tracks: &[KeptTrack],
+    out: Presenter,
+) -> Result<AudioEncoder, ReduceError> {
+    if tracks.is_empty() {
+        return Ok(codec.encoders()[0]);
+    }
+    let encoder =
+        audio::select_encoder(codec, &tool.list_encoders()?).map_err(ReduceError::Usage)?;
+    out.info(&format!(
+        "Audio encoder: {}{}",
+        encoder,
+        if encoder.experimental {
+            " (experimental)"
+        } else {
+            ""
+        }
+    ));
+    Ok(encoder)
+}
+
+/// Video bitrate for a retry after an attempt came out at `actual_bytes`.
+///
+/// Scales by how far over the target the attempt landed, plus a 5% safety
+/// margin, but never below the usual minimum.
+pub fn shrink_bitrate(video_bitrate: u64, actual_bytes: u64, target_bytes: u64) -> u64 {
+    let scaled = video_bitrate as f64 * target_bytes as f64 / actual_bytes as f64 * 0.95;
+    (scaled as u64).max(MIN_VIDEO_BITRATE)
+}
+
+/// Copies standard input into the run directory and returns the copy's path.
+fn spool_stdin(run_dir: &RunTempDir) -> Result<String, ReduceError> {
+    let path = run_dir.artifact("stdin-input", None);
+    File::create(&path)
+        .and_then(|mut file| io::copy(&mut io::stdin().lock(), &mut file))
+        .map_err(|e| {
+            if diskspace::is_storage_full(&e) {
+                let err = diskspace::full(run_dir.path());
+                run_dir.clear();
+                return err;
+            }
+            ReduceError::Probe(format!("cannot read input from stdin: {}", e))
+        })?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Copies the streams of the URL `input` into the run directory with
+/// ffmpeg, which reads it front to back like any download, and returns the
+/// copy's path.
+fn download<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    run_dir: &RunTempDir,
+    out: Presenter,
+) -> Result<String, ReduceError> {
+    // Keep the source's container when it is a known one; Matroska holds
+    // whatever else the streams are.
+    let ext = url::file_name(input)
+        .filter(|name| Container::from_path(name).is_some())
+        .and_then(|name| Path::new(name).extension())
+        .map_or("mkv".to_string(), |ext| {
+            ext.to_string_lossy().to_lowercase()
+        });
+    let path = run_dir.artifact("download", Some(&ext));
+    let path = path.to_string_lossy().into_owned();
+    out.info(&format!("Downloading {} first", input));
+    let args = download_args(input, &path);
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    let result = tool.run_ffmpeg(&args);
+    if interrupt::is_interrupted() {
+        return Err(ReduceError::Interrupted);
+    }
+    result.map_err(|e| match e {
+        ReduceError::Encode(message) => {
+            ReduceError::Probe(format!("cannot download {}: {}", input, message))
+        }
+        other => other,
+    })?;
+    Ok(path)
+}
+
+/// The ffmpeg arguments copying every video, audio and subtitle stream of
+/// `url` into `destination`, and the attachments when it is Matroska.
+fn download_args(url: &str, destination: &str) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "-y", "-nostats", "-i", url, "-map", "0:v", "-map", "0:a?", "-map", "0:s?",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    if Container::from_path(destination).is_some_and(Container::carries_attachments) {
+        args.extend(["-map".to_string(), "0:t?".to_string()]);
+    }
+    args.extend(["-c".to_string(), "copy".to_string()]);
+    args.push(longpath::for_tool(destination));
+    args
+}
+
+/// Path of part `part` (1-based) of a split output: `out.mp4` becomes `out.part1.mp4`.
+pub fn part_output_path(output: &str, part: u32) -> String {
+    with_infix(output, &format!("part{}", part))
+}
+
+/// Path a `--sample` of `output` goes to: `out.mp4` becomes `out.sample.mp4`.
+pub fn sample_output_path(output: &str) -> String {
+    with_infix(output, "sample")
+}
+
+/// `output` with `.infix` inserted before its extension.
+fn with_infix(output: &str, infix: &str) -> String {
+    let path = Path::new(output);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, infix, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, infix),
+    };
+    let name = filename::fit_length(&name, filename::MAX_BYTES);
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Path of the in-progress output inside the run directory. The output's
+/// extension is kept because ffmpeg picks the muxer from it.
+fn partial_output_path(run_dir: &RunTempDir, output: &str) -> PathBuf {
+    let ext = Path::new(output)
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned());
+    run_dir.artifact("partial", ext.as_deref())
+}
+
+/// Keeps the system awake through an encode predicted to take `estimate`,
+/// as `--inhibit-sleep` says, until the hold is dropped.
+fn keep_awake<T: VideoTool>(
+    tool: &T,
+    opts: &ReduceOptions,
+    estimate: Duration,
+    output: &str,
+    out: Presenter,
+) -> Option<sleep::Hold> {
+    if !opts.inhibit_sleep.applies(estimate) {
+        return None;
+    }
+    let name = Path::new(output)
+        .file_name()
+        .map_or_else(|| output.into(), |name| name.to_string_lossy());
+    match sleep::Hold::take(tool.sleep_inhibitor(), &format!("Encoding {}", name)) {
+        Ok(hold) => {
+            if opts.verbose {
+                out.info(&format!(
+                    "Keeping the system awake until the encode ends ({})",
+                    hold.name()
+                ));
+            }
+            Some(hold)
+        }
+        Err(e) if opts.inhibit_sleep == sleep::Policy::Always => {
+            warning::emit(
+                out,
+                Warning::new(
+                    Code::SleepNotInhibited,
+                    format!(
+                        "cannot keep the system awake ({}); it may sleep mid-encode",
+                        e
+                    ),
+                ),
+            );
+            None
+        }
+        Err(e) => {
+            if opts.verbose {
+                out.info(&format!("Not keeping the system awake ({})", e));
+            }
+            None
+        }
+    }
+}
+
+/// Prints the up-front encode time estimate and applies `--max-encode-time`.
+///
+/// Returns the preset to encode with, which is faster than requested when
+/// that is the only way to stay within the time budget.
+fn plan_preset(
+    duration: f64,
+    info: &VideoInfo,
+    opts: &ReduceOptions,
+    out: Presenter,
+) -> Result<Preset, ReduceError> {
+    let fps = output_fps(info, opts);
+    let (width, height) = output_size(info, opts);
+    let estimate = estimate_encode_seconds(duration, fps, width, height, opts.preset);
+    out.info(&format!(
+        "Estimated encode time: {} (preset {})",
+        format_duration(estimate),
+        opts.preset
+    ));
+
+    let Some(budget_minutes) = opts.max_encode_minutes else {
+        return Ok(opts.preset);
+    };
+    let budget = budget_minutes * 60.0;
+    if estimate <= budget {
+        return Ok(opts.preset);
+    }
+    match estimate::fit_preset(duration, fps, width, height, opts.preset, budget) {
+        Some(preset) => {
+            let message = format!(
+                "switching to preset {} to fit the {} budget (estimated {})",
+                preset,
+                format_duration(budget),
+                format_duration(estimate_encode_seconds(
+                    duration, fps, width, height, preset
+                ))
+            );
+            warning::emit(out, Warning::new(Code::PresetSwitched, message));
+            Ok(preset)
+        }
+        None => Err(ReduceError::Usage(format!(
+            "Estimated encode time {} exceeds the {} budget even with the fastest preset",
+            format_duration(estimate),
+            format_duration(budget)
+        ))),
+    }
+}
+
+/// Percentage points between progress lines in plain output.
+const PLAIN_PROGRESS_STEP: f64 = 10.0;
+
+/// The progress of one ffmpeg run: a single line redrawn in place where
+/// the output allows it, else a new line every [`PLAIN_PROGRESS_STEP`].
+struct ProgressDisplay {
+    out: Presenter,
+    /// Length of the input being processed, in seconds.
+    duration: f64,
+    /// Length of the redrawn line so far.
+    line_len: usize,
+    /// The last step a plain line was printed for.
+    printed_step: Option<u32>,
+}
+
+impl ProgressDisplay {
+    fn new(out: Presenter, duration: f64) -> Self {
+        Self {
+            out,
+            duration,
+            line_len: 0,
+            printed_step: None,
+        }
+    }
+
+    fn update(&mut self, progress: &Progress) {
+        session::report(progress, self.duration);
+        let percent = (progress.out_time / self.duration * 100.0).clamp(0.0, 100.0);
+        let eta = progress
+            .speed
+            .and_then(|speed| estimate::eta_seconds(progress.out_time, self.duration, speed));
+        events::emit(&Event::Progress {
+            out_time_ms: (progress.out_time * 1000.0).round() as u64,
+            percent: (percent * 10.0).round() / 10.0,
+            speed: progress.speed,
+            eta_s: eta.map(|eta| eta.round()),
+        });
+        if !self.out.redraws() {
+            if let Some(line) = self.plain_line(percent, eta) {
+                self.out.info(&line);
+            }
+            return;
+        }
+        let line = match (progress.speed, eta) {
+            (Some(speed), Some(eta)) => format!(
+                "Encoding: {:5.1}% at {:.2}x, ETA {}",
+                percent,
+                speed,
+                format_duration(eta)
+            ),
+            _ => format!("Encoding: {:5.1}%", percent),
+        };
+        // Pad with spaces so a shorter line fully overwrites the previous one.
+        self.out
+            .raw(&format!("\r{:width$}", line, width = self.line_len));
+        self.line_len = line.len().max(self.line_len);
+    }
+
+    /// The plain line for `percent`, when it starts a new step.
+    fn plain_line(&mut self, percent: f64, eta: Option<f64>) -> Option<String> {
+        let step = (percent / PLAIN_PROGRESS_STEP) as u32;
+        if self.printed_step.is_some_and(|printed| printed >= step) {
+            return None;
+        }
+        self.printed_step = Some(step);
+        Some(match eta {
+            Some(eta) => format!("{:.0}% done, ETA {}", percent, format_duration(eta)),
+            None => format!("{:.0}% done", percent),
+        })
+    }
+
+    /// Ends the redrawn line, so the next message starts on its own.
+    fn finish(self) {
+        if self.line_len > 0 {
+            self.out.info("");
+        }
+    }
+}
+
+/// Builds the filter chain for the probed source, with a note on each
+/// geometry adjustment.
+fn build_filters(info: &VideoInfo, opts: &ReduceOptions) -> (FilterChain, Vec<String>) {
+    let mut filters = FilterChain::new();
+    let mut notes = Vec::new();
+    let mut width = info.width;
+    let mut height = info.height;
+
+    // `info` already has the region's size.
+    if let Some(region) = opts.region {
+        filters.crop(
+            &region.width.to_string(),
+            &region.height.to_string(),
+            &region.x.to_string(),
+            &region.y.to_string(),
+        );
+        notes.push(format!("Region: {} of the source frame (--region)", region));
+    }
+    if let Some(downscale) = downscale(info, opts) {
+        let limit = match downscale.cap {
+            Cap::MaxHeight => downscale.height,
+            _ => downscale.width,
+        };
+        if !info.sar().is_square() {
+            // "-2" would keep the stored shape, not the displayed one.
+            filters.scale_to_square_pixels(downscale.width, downscale.height);
+        } else if downscale.cap == Cap::MaxHeight {
+            // "-2" keeps the aspect ratio and makes the other side even.
+            filters.scale("-2", &downscale.height.to_string());
+        } else {
+            filters.scale(&downscale.width.to_string(), "-2");
+        }
+        width = downscale.width;
+        height = downscale.height;
+        notes.push(format!(
+            "Downscaling: {}x{} to {}x{} ({} {})",
+            info.width,
+            info.height,
+            width,
+            height,
+            downscale.cap.flag(),
+            limit
+        ));
+    }
+    if let Some(canvas) = vertical_canvas(info, opts) {
+        let how = match canvas.crop {
+            Some(crop) => {
+                filters.crop(
+                    &crop.width.to_string(),
+                    &crop.height.to_string(),
+                    &crop.x.to_string(),
+                    &crop.y.to_string(),
+                );
+                format!("center-cropped to {}x{}", crop.width, crop.height)
+            }
+            None => {
+                let bars = if !canvas.is_boxed() {
+                    ""
+                } else if canvas.picture.0 < canvas.width {
+                    ", pillarboxed"
+                } else {
+                    ", letterboxed"
+                };
+                format!(
+                    "scaled to {}x{}{}",
+                    canvas.picture.0, canvas.picture.1, bars
+                )
+            }
+        };
+        filters.scale_onto_canvas(canvas.picture, (canvas.width, canvas.height));
+        width = canvas.width;
+        height = canvas.height;
+        notes.push(format!(
+            "Vertical canvas: {}x{} ({}x{} {})",
+            width, height, info.width, info.height, how
+        ));
+    }
+    // --cfr sets its rate on the encoder instead, already within the cap.
+    match frame_rate_cap(info, opts).filter(|_| opts.cfr.is_none()) {
+        Some(max) => {
+            filters.fps(&max.to_string());
+            notes.push(format!("Output frame rate: {} fps (--max-fps)", max));
+        }
+        None => {
+            if let Some(fps) = opts.fps {
+                filters.fps(&fps.to_string());
+                notes.push(format!("Output frame rate: {} fps", fps));
+            }
+        }
+    }
+
+    if !width.is_multiple_of(2) || !height.is_multiple_of(2) {
+        filters.force_even(opts.even_mode);
+        let (even_width, even_height) = even_dimensions(width, height, opts.even_mode);
+        let how = match opts.even_mode {
+            EvenMode::Scale => "scaled",
+            EvenMode::Pad => "padded",
+        };
+        notes.push(format!(
+            "Adjusted odd dimensions: {}x{} {} to {}x{}",
+            width, height, how, even_width, even_height
+        ));
+    }
+    (filters, notes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Entry;
+    use crate::interrupt::CancelToken;
+    use crate::overhead;
+    use crate::presenter::ColorChoice;
+    use crate::probe::{parse_data_streams, AudioStream};
+    use crate::region;
+    use crate::testing::{arg_value, mib, EventLog, MockVideoTool, TestDir, GOPRO_DATA_STREAMS};
+
+    /// Options writing their temp files under `dir`.
+    fn opts_in(dir: &TestDir, target_mb: u64) -> ReduceOptions {
+        let mut opts = ReduceOptions::new(mib(target_mb));
+        opts.temp_dir = Some(dir.path().to_path_buf());
+        opts
+    }
+
+    #[test]
+    fn test_compute_video_bitrate() {
+        // For a 100-second video and a target size of 100MB:
+        // 100 MB = 104857600 bytes; total bits = 104857600 * 8 = 838860800.
+        // Total bitrate = 838860800 / 100 = 8,388,608 bps.
+        // Expected video bitrate = 8,388,608 - 128,000 = 8,260,608 bps (approximately).
+        let duration = 100.0;
+        let target_bytes = 100 * 1024 * 1024;
+        let audio_bitrate = 128_000;
+        let video_bitrate = compute_video_bitrate(duration, target_bytes, audio_bitrate);
+        let expected = 8_388_608.0 - 128_000.0;
+        assert!((video_bitrate as f64 - expected).abs() < 1_000.0);
+    }
+
+    #[test]
+    fn test_compute_video_bitrate_si_megabytes() {
+        // 100 MB (SI) = 100,000,000 bytes; 800,000,000 bits over 100 s is
+        // 8,000,000 bps, about 4.6% below the binary figure above.
+        let target_bytes = 100 * SizeUnits::Si.megabyte();
+        let video_bitrate = compute_video_bitrate(100.0, target_bytes, 128_000);
+        assert_eq!(video_bitrate, 8_000_000 - 128_000);
+    }
+
+    #[test]
+    fn test_minimum_video_bitrate() {
+        // Create a scenario where the computed video bitrate would fall below the minimum.
+        // With a very long duration, the computed video bitrate could be negative.
+        let duration = 10_000.0; // very long video
+        let target_bytes = 50 * 1024 * 1024; // 50 MB target
+        let audio_bitrate = 128_000;
+        let video_bitrate = compute_video_bitrate(duration, target_bytes, audio_bitrate);
+        // In this case the computed video bitrate should be clamped to the minimum of 100_000.
+        assert_eq!(video_bitrate, 100_000);
+    }
+
+    #[test]
+    fn test_reduce_video_workflow() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let input = "input.mp4";
+        let output = dir.join("output.mp4");
+        let opts = opts_in(&dir, 100);
+
+        reduce_video(&tool, input, &output, &opts).expect("reduce_video failed");
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        let args = &calls[0];
+
+        // Verify key arguments
+        assert!(args.contains(&"-c:v".to_string()));
+        assert!(args.contains(&"libx264".to_string()));
+        assert!(args.contains(&"-b:v".to_string()));
+
+        // Check bitrate (should be around 8253k)
+        // From test_compute_video_bitrate, expected is ~8260608, less
+        // 85,500 bytes of muxing over 100 s (6,840 bps): 8253768
+        assert!(args.contains(&"8253k".to_string()));
+        // Even dimensions need no filter.
+        assert!(!args.contains(&"-vf".to_string()));
+
+        // The encode went to a temp file that was moved into place.
+        assert_ne!(args.last().unwrap(), &output);
+        assert!(Path::new(&output).exists());
+        assert_eq!(dir.entries(), vec!["output.mp4"]);
+    }
+
+    #[test]
+    fn test_duration_override_replaces_probed_value() {
+        let dir = TestDir::new();
+        // The container claims 10 s, which would give a far higher bitrate.
+        let tool = MockVideoTool::new(10.0);
+        let mut opts = opts_in(&dir, 100);
+        opts.duration = Some(100.0);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        assert_eq!(arg_value(&tool.single_call(), "-b:v"), Some("8253k"));
+        assert_eq!(tool.decode_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_unusable_probed_duration_falls_back_to_decoding() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(f64::NAN);
+        tool.decoded_duration = Some(100.0);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts_in(&dir, 100)).unwrap();
+
+        assert_eq!(tool.decode_calls.get(), 1);
+        assert_eq!(arg_value(&tool.single_call(), "-b:v"), Some("8253k"));
+    }
+
+    #[test]
+    fn test_trust_decode_duration_ignores_the_header() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(10.0);
+        tool.decoded_duration = Some(100.0);
+        let mut opts = opts_in(&dir, 100);
+        opts.trust_decode_duration = true;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        assert_eq!(tool.decode_calls.get(), 1);
+        assert_eq!(arg_value(&tool.single_call(), "-b:v"), Some("8253k"));
+    }
+
+    #[test]
+    fn test_image_sequence_duration_comes_from_frame_count() {
+        let dir = TestDir::new();
+        for n in 1..=300 {
+            std::fs::write(dir.join(&format!("{:04}.png", n)), b"png").unwrap();
+        }
+        let tool = MockVideoTool::new(f64::NAN);
+        let mut opts = opts_in(&dir, 100);
+        opts.image = Some(ImageInput::Sequence { fps: 3.0 });
+        opts.no_audio = true;
+        reduce_video(&tool, &dir.join("%04d.png"), &dir.join("out.mp4"), &opts).unwrap();
+
+        // 300 frames at 3 fps is 100 s, all of it for video; 300 frames
+        // barely cost any muxing.
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-b:v"), Some("8388k"));
+        assert_eq!(arg_value(&args, "-framerate"), Some("3"));
+        assert_eq!(arg_value(&args, "-start_number"), Some("1"));
+        assert_eq!(arg_value(&args, "-pix_fmt"), Some("yuv420p"));
+        assert!(args.contains(&"-an".to_string()));
+        assert_eq!(tool.decode_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_still_image_is_looped_for_the_requested_length() {
+        let dir = TestDir::new();
+        let image = dir.join("title.png");
+        std::fs::write(&image, b"png").unwrap();
+        let tool = MockVideoTool::new(f64::NAN);
+        let mut opts = opts_in(&dir, 100);
+        opts.image = Some(ImageInput::Still {
+            seconds: 10.0,
+            fps: 30.0,
+        });
+        opts.no_audio = true;
+        reduce_video(&tool, &image, &dir.join("out.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-loop"), Some("1"));
+        assert_eq!(arg_value(&args, "-t"), Some("10.000"));
+    }
+
+    #[test]
+    fn test_source_cap() {
+        assert_eq!(source_cap(8_000_000, None), None);
+        assert_eq!(source_cap(8_000_000, Some(10_000_000)), None);
+        assert_eq!(source_cap(8_000_000, Some(8_000_000)), None);
+        assert_eq!(source_cap(8_000_000, Some(2_000_000)), Some(1_900_000));
+        // Never below the usual minimum, and never a "cap" that isn't lower.
+        assert_eq!(source_cap(500_000, Some(50_000)), Some(MIN_VIDEO_BITRATE));
+        assert_eq!(source_cap(MIN_VIDEO_BITRATE, Some(50_000)), None);
+    }
+
+    fn tool_with_source_bitrate(bit_rate: &str) -> MockVideoTool {
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.bit_rate = Some(bit_rate.into());
+        tool
+    }
+
+    #[test]
+    fn test_bitrate_is_capped_at_the_source() {
+        let dir = TestDir::new();
+        let tool = tool_with_source_bitrate("2000000");
+        let mut opts = opts_in(&dir, 100);
+        opts.force_video_reencode = true;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-b:v"), Some("1900k"));
+
+        let tool = tool_with_source_bitrate("2000000");
+        opts.source_cap = false;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-b:v"), Some("8253k"));
+    }
+
+    #[test]
+    fn test_copy_if_larger_copies_the_video_stream() {
+        let dir = TestDir::new();
+        // Just under the planned 8260k: capped, but too close to the target
+        // for the audio-only shortcut.
+        let tool = tool_with_source_bitrate("8200000");
+        let mut opts = opts_in(&dir, 100);
+        opts.copy_if_larger = true;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-c:v"), Some("copy"));
+        assert_eq!(arg_value(&args, "-b:v"), None);
+        assert_eq!(arg_value(&args, "-c:a"), Some("aac"));
+    }
+
+    #[test]
+    fn test_oversized_copy_is_re_encoded() {
+        let dir = TestDir::new();
+        let mut tool = tool_with_source_bitrate("2000000");
+        tool.output_bytes = vec![mib(120), mib(10)];
+        let mut opts = opts_in(&dir, 100);
+        opts.copy_if_larger = true;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(arg_value(&calls[0], "-c:v"), Some("copy"));
+        assert_eq!(arg_value(&calls[1], "-c:v"), Some("libx264"));
+    }
+
+    #[test]
+    fn test_a_copy_keeps_to_the_codec_asked_for() {
+        let dir = TestDir::new();
+        let ios = crate::device::Device::Ios.constraints();
+        // (source codec, output, encoder, compat, video codec written)
+        let cases = [
+            (
+                "hevc",
+                "out.webm",
+                VideoEncoder::SvtAv1,
+                Compat::default(),
+                "libsvtav1",
+            ),
+            (
+                "hevc",
+                "out.mp4",
+                VideoEncoder::SvtAv1,
+                Compat::default(),
+                "libsvtav1",
+            ),
+            (
+                "hevc",
+                "out.mkv",
+                VideoEncoder::H264,
+                Compat::default(),
+                "libx264",
+            ),
+            (
+                "hevc",
+                "out.mp4",
+                ios.encoder.unwrap(),
+                ios.compat,
+                "libx264",
+            ),
+            (
+                "av1",
+                "out.webm",
+                VideoEncoder::SvtAv1,
+                Compat::default(),
+                "copy",
+            ),
+            (
+                "h264",
+                "out.mkv",
+                VideoEncoder::H264,
+                Compat::default(),
+                "copy",
+            ),
+        ];
+        for (codec, output, encoder, compat, written) in cases {
+            // Far under the target, so the audio-only shortcut would copy.
+            let mut tool = tool_with_source_bitrate("2000000");
+            tool.info.codec_name = Some(codec.to_string());
+            let mut opts = opts_in(&dir, 50);
+            opts.encoder = encoder;
+            opts.compat = compat;
+            reduce_video(&tool, "in.mp4", &dir.join(output), &opts).unwrap();
+            let args = tool.single_call();
+            assert_eq!(
+                arg_value(&args, "-c:v"),
+                Some(written),
+                "{} into {} with {:?}",
+                codec,
+                output,
+                encoder
+            );
+        }
+
+        let mut tool = tool_with_source_bitrate("2000000");
+        tool.info.codec_name = Some("hevc".to_string());
+        let plan = plan_encoding(100.0, &tool.info, &ReduceOptions::new(mib(50)));
+        assert!(!plan.copy_video);
+        assert!(
+            plan.notes.contains(
+                &"Cannot stream-copy the hevc video into h264 output; re-encoding".to_string()
+            ),
+            "{:?}",
+            plan.notes
+        );
+    }
+
+    #[test]
+    fn test_copy_if_larger_still_encodes_when_filtering() {
+        let dir = TestDir::new();
+        let tool = tool_with_source_bitrate("2000000");
+        let mut opts = opts_in(&dir, 100);
+        opts.copy_if_larger = true;
+        opts.max_width = Some(1280);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-c:v"), Some("libx264"));
+        assert_eq!(arg_value(&args, "-b:v"), Some("1900k"));
+    }
+
+    #[test]
+    fn test_audio_only_fits() {
+        let target = 100 * 1_000_000;
+        // 60 s of 8 Mb/s video is 60 MB; 128k audio adds 0.96 MB.
+        assert!(audio_only_fits(Some(8_000_000), 60.0, 128_000, target));
+        // 12.9 Mb/s video is 96.75 MB, 97.71 MB with audio: inside the 2%
+        // headroom. At 13 Mb/s (98.46 MB in total) it is not.
+        assert!(audio_only_fits(Some(12_900_000), 60.0, 128_000, target));
+        assert!(!audio_only_fits(Some(13_000_000), 60.0, 128_000, target));
+        // Dropping the audio leaves the whole budget to the copied video.
+        assert!(audio_only_fits(Some(13_000_000), 60.0, 0, target));
+        // Video alone over the target, or of unknown size, never fits.
+        assert!(!audio_only_fits(Some(20_000_000), 60.0, 0, target));
+        assert!(!audio_only_fits(None, 60.0, 128_000, target));
+        // Longer inputs scale both streams.
+        assert!(!audio_only_fits(Some(8_000_000), 600.0, 128_000, target));
+    }
+
+    #[test]
+    fn test_fitting_video_is_copied_with_audio_re_encoded() {
+        let dir = TestDir::new();
+        let tool = tool_with_source_bitrate("2000000");
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts_in(&dir, 100)).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-c:v"), Some("copy"));
+        assert_eq!(arg_value(&args, "-c:a"), Some("aac"));
+
+        let tool = tool_with_source_bitrate("2000000");
+        let mut opts = opts_in(&dir, 100);
+        opts.force_video_reencode = true;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-c:v"), Some("libx264"));
+    }
+
+    fn tool_with_audio(channels: &[u32]) -> MockVideoTool {
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.audio_streams = Some(
+            channels
+                .iter()
+                .enumerate()
+                .map(|(i, &c)| crate::probe::AudioStream {
+                    index: i as u32 + 1,
+                    codec_name: Some("pcm_s24le".into()),
+                    channels: Some(c),
+                    ..Default::default()
+                })
+                .collect(),
+        );
+        tool
+    }
+
+    #[test]
+    fn test_all_audio_tracks_share_the_budget() {
+        let dir = TestDir::new();
+        let tool = tool_with_audio(&[2, 1]);
+        let mut opts = opts_in(&dir, 100);
+        opts.audio_tracks = AudioSelection::All;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        // 8,388,608 bps in total, minus muxing (7,440 bps with two tracks)
+        // and 128k and 64k of audio.
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-b:v"), Some("8186k"));
+        assert_eq!(arg_value(&args, "-b:a:0"), Some("128k"));
+        assert_eq!(arg_value(&args, "-b:a:1"), Some("64k"));
+        let maps: Vec<&str> = args
+            .windows(2)
+            .filter(|w| w[0] == "-map")
+            .map(|w| w[1].as_str())
+            .collect();
+        assert_eq!(maps, ["0:V:0", "0:a:0?", "0:a:1?"]);
+    }
+
+    #[test]
+    fn test_selected_audio_track_must_exist() {
+        let dir = TestDir::new();
+        let tool = tool_with_audio(&[2, 2]);
+        let mut opts = opts_in(&dir, 100);
+        opts.audio_tracks = AudioSelection::Track(2);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert!(tool.single_call().contains(&"0:a:1?".to_string()));
+
+        opts.audio_tracks = AudioSelection::Track(3);
+        let err = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_kept_tracks_carry_their_tags_to_the_output_numbering() {
+        let dir = TestDir::new();
+        let mut tool = tool_with_audio(&[2, 2]);
+        let streams = tool.info.audio_streams.as_mut().unwrap();
+        streams[0].tags.language = Some("eng".into());
+        streams[0].disposition.default = 1;
+        streams[1].tags.language = Some("jpn".into());
+        let mut opts = opts_in(&dir, 100);
+        opts.audio_tracks = AudioSelection::Track(2);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-metadata:s:a:0"), Some("language=jpn"));
+        assert_eq!(arg_value(&args, "-disposition:a:0"), Some("default"));
+        assert_eq!(arg_value(&args, "-metadata:s:a:1"), None);
+
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        opts.audio_tracks = AudioSelection::All;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-metadata:s:a:0"), Some("language=eng"));
+        assert_eq!(arg_value(&args, "-disposition:a:0"), Some("default"));
+        assert_eq!(arg_value(&args, "-metadata:s:a:1"), Some("language=jpn"));
+        assert_eq!(arg_value(&args, "-disposition:a:1"), Some("0"));
+    }
+
+    #[test]
+    fn test_silent_input_gives_all_bits_to_video() {
+        let dir = TestDir::new();
+        let tool = tool_with_audio(&[]);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts_in(&dir, 100)).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-b:v"), Some("8384k"));
+        assert!(args.contains(&"-an".to_string()));
+    }
+
+    #[test]
+    fn test_learned_overhead_stands_in_for_the_default() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let history: Vec<Entry> = (0..overhead::MIN_SAMPLES)
+            .map(|_| Entry {
+                // 5% of the output unaccounted for.
+                sample: Some(Sample {
+                    container: Container::Mp4,
+                    duration: 60.0,
+                    streams: 2,
+                    payload_bytes: 9_400_000,
+                    muxing_bytes: 100_000,
+                    actual_bytes: 10_000_000,
+                }),
+                ..Entry::default()
+            })
+            .collect();
+        let mut opts = opts_in(&dir, 100);
+        opts.learned_overhead = Some(Learned::from_history(&history));
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        // As with --overhead-percent 5.
+        assert_eq!(arg_value(&tool.single_call(), "-b:v"), Some("7834k"));
+
+        // Nothing is known about Matroska yet.
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        reduce_video(&tool, "in.mp4", &dir.join("out.mkv"), &opts).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-b:v"), Some("8253k"));
+    }
+
+    #[test]
+    fn test_full_encodes_report_an_overhead_sample() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.output_bytes = vec![mib(90)];
+        let mut opts = opts_in(&dir, 100);
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let sample = report.sample.unwrap();
+        assert_eq!(sample.container, Container::Mp4);
+        assert_eq!(sample.streams, 2);
+        assert_eq!(sample.actual_bytes, mib(90));
+        assert_eq!(
+            sample.payload_bytes,
+            report.prediction.unwrap().payload_bytes
+        );
+        assert_eq!(sample.muxing_bytes, 85_500);
+
+        // A sample covers too little of the input to learn from.
+        tool.output_bytes = vec![mib(9)];
+        opts.sample = Some(10.0);
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(report.sample, None);
+    }
+
+    #[test]
+    fn test_overhead_is_taken_off_the_target() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 100);
+        opts.overhead_percent = 5.0;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        // 95% of 8,388,608 bps is 7,969,177, minus 6,840 bps of muxing and
+        // 128k of audio.
+        assert_eq!(arg_value(&tool.single_call(), "-b:v"), Some("7834k"));
+
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert_eq!(
+            describe_budget(&plan, &opts),
+            "video 7834 kb/s, audio track 1 128 kb/s, overhead 5%"
+        );
+    }
+
+    #[test]
+    fn test_report_carries_the_size_prediction() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.output_bytes = vec![mib(98)];
+        let mut opts = opts_in(&dir, 100);
+        opts.overhead_percent = 5.0;
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let prediction = report.prediction.unwrap();
+        // The payload is what the bitrate math planned to fill: 95%, less
+        // the 85,500 bytes of muxing, which count as overhead.
+        let muxing = 85_500;
+        assert!(
+            prediction
+                .payload_bytes
+                .abs_diff(mib(100) * 95 / 100 - muxing)
+                < 100
+        );
+        assert_eq!(
+            prediction.overhead_bytes,
+            mib(100) - mib(100) * 95 / 100 + muxing
+        );
+        assert_eq!(prediction.actual_bytes, mib(98));
+        assert!((prediction.error_percent() + 2.0).abs() < 0.01);
+
+        // Parts add up; a stream has no size to compare.
+        opts.parts = 2;
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(report.prediction.unwrap().actual_bytes, 2 * mib(98));
+        let report = reduce_video(&tool, "in.mp4", "-", &opts_in(&dir, 100)).unwrap();
+        assert_eq!(report.prediction, None);
+    }
+
+    #[test]
+    fn test_stdout_output_streams_fragmented_mp4() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let opts = opts_in(&dir, 100);
+
+        reduce_video(&tool, "input.mp4", "-", &opts).expect("reduce_video failed");
+
+        let args = tool.single_call();
+        assert_eq!(args.last().unwrap(), "pipe:1");
+        assert_eq!(arg_value(&args, "-f"), Some("mp4"));
+        assert_eq!(
+            arg_value(&args, "-movflags"),
+            Some("frag_keyframe+empty_moov")
+        );
+        // Progress would interleave with the video on stdout.
+        assert!(!args.contains(&"-progress".to_string()));
+        assert!(dir.entries().is_empty());
+    }
+
+    #[test]
+    fn test_oversized_output_is_retried_at_lower_bitrate() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.output_bytes = vec![60 * 1024 * 1024, 40 * 1024 * 1024];
+        let output = dir.join("output.mp4");
+
+        reduce_video(&tool, "input.mp4", &output, &opts_in(&dir, 50)).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        let first: u64 = arg_value(&calls[0], "-b:v")
+            .unwrap()
+            .trim_end_matches('k')
+            .parse()
+            .unwrap();
+        let second: u64 = arg_value(&calls[1], "-b:v")
+            .unwrap()
+            .trim_end_matches('k')
+            .parse()
+            .unwrap();
+        assert!(second < first * 50 / 60, "{} -> {}", first, second);
+        assert_eq!(dir.entries(), vec!["output.mp4"]);
+    }
+
+    #[test]
+    fn test_a_retried_encode_as_events() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.output_bytes = vec![60 * 1024 * 1024, 40 * 1024 * 1024];
+        tool.progress = vec![Progress {
+            out_time: 25.0,
+            speed: Some(5.0),
+            done: false,
+        }];
+        let log = EventLog::install();
+
+        reduce_video(
+            &tool,
+            "input.mp4",
+            &dir.join("output.mp4"),
+            &opts_in(&dir, 50),
+        )
+        .unwrap();
+
+        let events = log.events();
+        let phases: Vec<(Phase, Option<u32>)> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Phase { phase, retry } => Some((*phase, *retry)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            phases,
+            [
+                (Phase::Probing, None),
+                (Phase::Encoding, None),
+                (Phase::Verifying, None),
+                (Phase::Retry, Some(1)),
+                (Phase::Verifying, None)
+            ]
+        );
+        assert!(events.contains(&Event::Progress {
+            out_time_ms: 25_000,
+            percent: 25.0,
+            speed: Some(5.0),
+            eta_s: Some(15.0),
+        }));
+        let retry_warning = events.iter().position(
+            |event| matches!(event, Event::Warning(w) if w.code == Code::OverTargetRetry),
+        );
+        let retry = events.iter().position(|event| {
+            matches!(
+                event,
+                Event::Phase {
+                    phase: Phase::Retry,
+                    ..
+                }
+            )
+        });
+        assert!(retry_warning.unwrap() < retry.unwrap());
+    }
+
+    #[test]
+    fn test_a_disk_without_room_for_the_target_is_warned_about() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        // A petabyte fits on no disk this test runs on.
+        let opts = opts_in(&dir, 1 << 30);
+        let report = reduce_video(&tool, "input.mp4", &dir.join("output.mp4"), &opts).unwrap();
+        let codes: Vec<Code> = report.warnings.iter().map(|w| w.code).collect();
+        assert!(codes.contains(&Code::LowDiskSpace), "{:?}", report.warnings);
+
+        let report = reduce_video(
+            &tool,
+            "input.mp4",
+            &dir.join("output.mp4"),
+            &opts_in(&dir, 1),
+        )
+        .unwrap();
+        assert!(report.warnings.iter().all(|w| w.code != Code::LowDiskSpace));
+    }
+
+    #[test]
+    fn test_targets_over_what_fat32_holds_fail_up_front() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        let stick = dir.path().join("stick");
+        tool.mounts = vec![(stick.clone(), "vfat".to_string())];
+        let output = stick.join("film.mp4").to_string_lossy().into_owned();
+
+        let err = reduce_video(&tool, "input.mp4", &output, &opts_in(&dir, 5 * 1024)).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)));
+        assert!(
+            err.to_string()
+                .contains("is on FAT32, which can't hold files over"),
+            "{}",
+            err
+        );
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+        // Up to the limit is fine.
+        reduce_video(&tool, "input.mp4", &output, &opts_in(&dir, 4000)).unwrap();
+    }
+
+    #[test]
+    fn test_a_temp_dir_on_smb_gets_fragmented_mp4() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.mounts = vec![(dir.path().to_path_buf(), "cifs".to_string())];
+
+        reduce_video(&tool, "input.mp4", &dir.join("out.mp4"), &opts_in(&dir, 50)).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-movflags"), Some(FRAGMENTED_MOVFLAGS));
+        assert!(args.last().unwrap().starts_with(&dir.join("")));
+
+        // Matroska has no fragmented form to fall back to.
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        let report =
+            reduce_video(&tool, "input.mp4", &dir.join("out.mkv"), &opts_in(&dir, 50)).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-movflags"), None);
+        assert_eq!(report.warnings[0].code, Code::UnseekableTempDir);
+    }
+
+    #[test]
+    fn test_safe_remote_write_encodes_locally() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.mounts = vec![(dir.path().to_path_buf(), "cifs".to_string())];
+        let opts = ReduceOptions {
+            safe_remote_write: true,
+            ..opts_in(&dir, 50)
+        };
+
+        reduce_video(&tool, "input.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-movflags"), None);
+        let destination = args.last().unwrap();
+        assert!(!destination.starts_with(&dir.join("")), "{}", destination);
+        assert!(Path::new(destination).starts_with(std::env::temp_dir()));
+        assert_eq!(dir.entries(), ["out.mp4"]);
+    }
+
+    #[test]
+    fn test_output_over_target_after_retries_fails() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.output_bytes = vec![60 * 1024 * 1024];
+        let mut opts = opts_in(&dir, 50);
+        opts.max_retries = 1;
+
+        let err = reduce_video(&tool, "input.mp4", &dir.join("output.mp4"), &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::OverTarget { attempts: 2, .. }));
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 2);
+        assert!(dir.entries().is_empty());
+    }
+
+    #[test]
+    fn test_just_over_target_never_reads_as_the_target() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.output_bytes = vec![50 * 1024 * 1024 + 1, 1024];
+        let opts = opts_in(&dir, 50);
+        let report = reduce_video(&tool, "input.mp4", &dir.join("output.mp4"), &opts).unwrap();
+        let retry = report
+            .warnings
+            .iter()
+            .find(|w| w.code == Code::OverTargetRetry)
+            .unwrap();
+        assert!(
+            retry
+                .message
+                .starts_with("output was 50.01 MiB, over the 50 MiB target"),
+            "{}",
+            retry.message
+        );
+    }
+
+    #[test]
+    fn test_shrink_bitrate_respects_minimum() {
+        assert_eq!(shrink_bitrate(1_000_000, 200, 100), 475_000);
+        assert_eq!(shrink_bitrate(150_000, 300, 100), MIN_VIDEO_BITRATE);
+    }
+
+    #[test]
+    fn test_split_encodes_each_part_to_the_target() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 100);
+        opts.parts = 2;
+
+        reduce_video(&tool, "input.mp4", &dir.join("output.mp4"), &opts).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(arg_value(&calls[0], "-ss"), None);
+        assert_eq!(arg_value(&calls[0], "-t"), Some("50.000"));
+        assert_eq!(arg_value(&calls[1], "-ss"), Some("50.000"));
+        // Each half gets the whole budget, so roughly double the bitrate.
+        assert_eq!(arg_value(&calls[0], "-b:v"), Some("16642k"));
+        assert_eq!(dir.entries(), vec!["output.part1.mp4", "output.part2.mp4"]);
+    }
+
+    #[test]
+    fn test_max_duration_sets_the_encode_window() {
+        // (--max-duration, --window-start, --split, --sample) on a 120 s
+        // input, and -ss and -t of each encode.
+        type Case = (Option<f64>, Option<f64>, u32, Option<f64>);
+        type Encode<'a> = (Option<&'a str>, Option<&'a str>);
+        let cases: [(Case, &[Encode], bool); 7] = [
+            ((Some(30.0), None, 1, None), &[(None, Some("30.000"))], true),
+            (
+                (Some(30.0), Some(10.0), 1, None),
+                &[(Some("10.000"), Some("30.000"))],
+                true,
+            ),
+            // Already short enough.
+            ((Some(300.0), None, 1, None), &[(None, None)], false),
+            // Only the start skipped.
+            (
+                (None, Some(100.0), 1, None),
+                &[(Some("100.000"), Some("20.000"))],
+                true,
+            ),
+            // With --split it caps the parts, which become three.
+            (
+                (Some(40.0), None, 2, None),
+                &[
+                    (None, Some("40.000")),
+                    (Some("40.000"), Some("40.000")),
+                    (Some("80.000"), Some("40.000")),
+                ],
+                false,
+            ),
+            (
+                (Some(40.0), Some(60.0), 2, None),
+                &[
+                    (Some("60.000"), Some("30.000")),
+                    (Some("90.000"), Some("30.000")),
+                ],
+                true,
+            ),
+            // A sample comes from the start of the window.
+            (
+                (Some(30.0), Some(10.0), 1, Some(5.0)),
+                &[(Some("10.000"), Some("5.000"))],
+                true,
+            ),
+        ];
+        for ((max_duration, window_start, parts, sample), encodes, trimmed) in cases {
+            let dir = TestDir::new();
+            let tool = MockVideoTool::new(120.0);
+            let mut opts = opts_in(&dir, 100);
+            opts.max_duration = max_duration;
+            opts.window_start = window_start;
+            opts.parts = parts;
+            opts.sample = sample;
+            let report = reduce_video(&tool, "input.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+            let calls = tool.ffmpeg_calls.lock().unwrap();
+            let window: Vec<Encode> = calls
+                .iter()
+                .map(|call| (arg_value(call, "-ss"), arg_value(call, "-t")))
+                .collect();
+            let case = (max_duration, window_start, parts, sample);
+            assert_eq!(window, encodes, "{:?}", case);
+            let warned = report.warnings.iter().any(|w| w.code == Code::Trimmed);
+            assert_eq!(warned, trimmed, "{:?}", case);
+        }
+    }
+
+    #[test]
+    fn test_max_duration_budgets_for_what_is_kept() {
+        let dir = TestDir::new();
+        let bitrate = |duration, max_duration| {
+            let tool = MockVideoTool::new(duration);
+            let mut opts = opts_in(&dir, 10);
+            opts.max_duration = max_duration;
+            reduce_video(&tool, "input.mp4", &dir.join("out.mp4"), &opts).unwrap();
+            let calls = tool.ffmpeg_calls.lock().unwrap();
+            arg_value(&calls[0], "-b:v").unwrap().to_string()
+        };
+        assert_eq!(bitrate(600.0, Some(30.0)), bitrate(30.0, None));
+        assert_ne!(bitrate(600.0, Some(30.0)), bitrate(600.0, None));
+
+        // A window past the end of the input, or one a chunked encode
+        // can't keep to.
+        let tool = MockVideoTool::new(120.0);
+        let mut opts = opts_in(&dir, 10);
+        opts.window_start = Some(120.0);
+        let err = reduce_video(&tool, "input.mp4", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(
+            err.to_string().contains("past the end of the input"),
+            "{}",
+            err
+        );
+        opts.window_start = None;
+        opts.max_duration = Some(30.0);
+        opts.chunks = 4;
+        let err = reduce_video(&tool, "input.mp4", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(err.to_string().contains("not --max-duration"), "{}", err);
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_split_to_stdout_is_rejected() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 100);
+        opts.parts = 2;
+        let err = reduce_video(&tool, "input.mp4", "-", &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)));
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_chunked_encode_joins_the_chunks_and_the_audio() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.avg_frame_rate = Some("30/1".into());
+        // Only the join of the first attempt comes out over the target.
+        tool.output_bytes = vec![1024, 1024, 1024, 1024, mib(200), 1024];
+        let mut opts = opts_in(&dir, 100);
+        opts.chunks = 3;
+
+        reduce_video(&tool, "input.mp4", &dir.join("output.mp4"), &opts).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        // Three chunks, the audio and the join, then the chunks and the
+        // join again; the audio is reused.
+        assert_eq!(calls.len(), 9);
+        for chunk in &calls[..3] {
+            assert!(chunk.contains(&"-an".to_string()));
+            assert!(chunk.last().unwrap().ends_with(".mkv"));
+        }
+        assert_eq!(arg_value(&calls[0], "-ss"), None);
+        assert_eq!(arg_value(&calls[0], "-t"), Some("33.317"));
+        assert_eq!(arg_value(&calls[1], "-ss"), Some("33.317"));
+        assert_eq!(arg_value(&calls[2], "-ss"), Some("66.650"));
+        assert_eq!(arg_value(&calls[0], "-b:v"), arg_value(&calls[2], "-b:v"));
+        assert!(calls[3].contains(&"-vn".to_string()));
+        assert_eq!(arg_value(&calls[3], "-c:a"), Some("aac"));
+        assert_eq!(arg_value(&calls[4], "-f"), Some("concat"));
+        assert_eq!(arg_value(&calls[4], "-c"), Some("copy"));
+        assert_eq!(arg_value(&calls[8], "-f"), Some("concat"));
+        assert!(calls[5..8].iter().all(|c| c.contains(&"-an".to_string())));
+        assert_eq!(dir.entries(), vec!["output.mp4"]);
+    }
+
+    #[test]
+    fn test_tag_metadata_records_each_attempt() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.output_bytes = vec![mib(60), mib(40)];
+        let mut opts = opts_in(&dir, 50);
+        let output = dir.join("archive.mp4");
+        reduce_video(&tool, "input.mp4", &output, &opts).unwrap();
+        assert_eq!(
+            arg_value(&tool.ffmpeg_calls.lock().unwrap()[0], "-metadata"),
+            None
+        );
+
+        opts.tag_metadata = true;
+        let tool = MockVideoTool {
+            output_bytes: vec![mib(60), mib(40)],
+            ..MockVideoTool::new(100.0)
+        };
+        reduce_video(&tool, "input.mp4", &output, &opts).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        // The retry's tag has the bitrate it was actually encoded at.
+        for call in calls.iter() {
+            let tag = arg_value(call, "-metadata").unwrap();
+            let bitrate = arg_value(call, "-b:v").unwrap();
+            assert!(tag.starts_with("comment=mdviqure "), "{}", tag);
+            assert!(tag.contains(&format!(" br={} ", bitrate)), "{}", tag);
+            assert!(
+                !tag.contains("input") && !tag.contains("archive"),
+                "{}",
+                tag
+            );
+        }
+
+        // Chunks are untagged; the joined file carries it.
+        let tool = MockVideoTool::new(100.0);
+        opts.chunks = 2;
+        reduce_video(&tool, "input.mp4", &output, &opts).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        let (join, rest) = calls.split_last().unwrap();
+        assert!(rest.iter().all(|c| arg_value(c, "-metadata").is_none()));
+        assert!(arg_value(join, "-metadata").unwrap().contains(" br="));
+    }
+
+    #[test]
+    fn test_deterministic_pins_every_command() {
+        let dir = TestDir::new();
+        let output = dir.join("out.mp4");
+        let mut opts = opts_in(&dir, 50);
+        opts.deterministic = true;
+        opts.tag_metadata = true;
+        let pinned = |calls: &[Vec<String>]| {
+            for call in calls {
+                assert_eq!(arg_value(call, "-fflags"), Some("+bitexact"), "{:?}", call);
+                assert_eq!(arg_value(call, "-map_metadata"), Some("-1"), "{:?}", call);
+            }
+        };
+        let tool = MockVideoTool::new(100.0);
+        let report = reduce_video(&tool, "input.mp4", &output, &opts).unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        pinned(&calls);
+        assert_eq!(arg_value(&calls[0], "-threads"), Some("1"));
+        assert_eq!(arg_value(&calls[0], "-flags"), Some("+bitexact"));
+        // The fixed time and the tag are both kept.
+        let metadata: Vec<&String> = calls[0]
+            .iter()
+            .zip(&calls[0][1..])
+            .filter(|(flag, _)| *flag == "-metadata")
+            .map(|(_, value)| value)
+            .collect();
+        assert!(
+            metadata[0].starts_with("comment=mdviqure "),
+            "{:?}",
+            metadata
+        );
+        assert_eq!(
+            metadata[1],
+            &format!("creation_time={}", deterministic::CREATION_TIME)
+        );
+
+        // Both passes run on one thread.
+        opts.two_pass = true;
+        let tool = MockVideoTool::new(100.0);
+        reduce_video(&tool, "input.mp4", &output, &opts).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert!(calls.iter().all(|c| arg_value(c, "-threads") == Some("1")));
+        pinned(&calls[1..]);
+
+        // So do the chunks and the audio, and the join is pinned too.
+        opts.two_pass = false;
+        opts.chunks = 2;
+        let tool = MockVideoTool::new(100.0);
+        reduce_video(&tool, "input.mp4", &output, &opts).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        let (join, encodes) = calls.split_last().unwrap();
+        assert_eq!(encodes.len(), 3);
+        assert!(encodes
+            .iter()
+            .all(|c| arg_value(c, "-threads") == Some("1")));
+        pinned(&calls);
+        assert_eq!(arg_value(join, "-threads"), None);
+
+        // Without the option nothing is pinned.
+        opts.deterministic = false;
+        opts.chunks = 1;
+        let tool = MockVideoTool::new(100.0);
+        reduce_video(&tool, "input.mp4", &output, &opts).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(arg_value(&calls[0], "-threads"), None);
+        assert_eq!(arg_value(&calls[0], "-fflags"), None);
+    }
+
+    #[test]
+    fn test_deterministic_warns_about_svt_av1() {
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.deterministic = true;
+        opts.encoder = VideoEncoder::SvtAv1;
+        let tool = MockVideoTool::new(100.0);
+        let report = reduce_video(&tool, "input.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let codes: Vec<Code> = report.warnings.iter().map(|w| w.code).collect();
+        assert_eq!(codes, [Code::NotBitExact]);
+        // It is still run as deterministically as it can be.
+        assert_eq!(
+            arg_value(&tool.ffmpeg_calls.lock().unwrap()[0], "-threads"),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn test_loop_safe_starts_on_a_keyframe_and_ends_with_the_video() {
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.loop_safe = true;
+        let mut tool = MockVideoTool::new(12.0);
+        tool.info.duration = Some("12.000000".into());
+        let report = reduce_video(&tool, "loop.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-force_key_frames"), Some("0"));
+        assert_eq!(
+            arg_value(&args, "-filter:a"),
+            Some("apad,atrim=end=12.000000")
+        );
+        assert!(args.contains(&"-shortest".to_string()));
+        assert_eq!(arg_value(&args, "-use_editlist"), Some("0"));
+        assert_eq!(arg_value(&args, "-movflags"), Some("+negative_cts_offsets"));
+        // The output was probed to check it.
+        assert!(
+            tool.probes().iter().any(|p| p.contains("partial-")),
+            "{:?}",
+            tool.probes()
+        );
+
+        // Matroska has no edit lists, and a sample is cut to its own length.
+        let tool = MockVideoTool::new(12.0);
+        opts.sample = Some(5.0);
+        reduce_video(&tool, "loop.mp4", &dir.join("out.mkv"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(
+            arg_value(&args, "-filter:a"),
+            Some("apad,atrim=end=5.000000")
+        );
+        assert_eq!(arg_value(&args, "-use_editlist"), None);
+        assert_eq!(arg_value(&args, "-movflags"), None);
+
+        // Without it, none of that.
+        let tool = MockVideoTool::new(12.0);
+        let opts = opts_in(&dir, 50);
+        reduce_video(&tool, "loop.mp4", &dir.join("plain.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        for flag in [
+            "-force_key_frames",
+            "-filter:a",
+            "-use_editlist",
+            "-shortest",
+        ] {
+            assert!(!args.contains(&flag.to_string()), "{}", flag);
+        }
+    }
+
+    #[test]
+    fn test_loop_safe_output_with_audio_stopping_short_is_warned_about() {
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.loop_safe = true;
+        let mut tool = MockVideoTool::new(12.0);
+        tool.info.duration = Some("12.000000".into());
+        tool.info.audio_streams = Some(vec![AudioStream {
+            duration: Some("11.500000".into()),
+            ..AudioStream::default()
+        }]);
+        let report = reduce_video(&tool, "loop.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let warning = report
+            .warnings
+            .iter()
+            .find(|w| w.code == Code::LoopNotSeamless)
+            .expect("a loop warning");
+        assert_eq!(
+            warning.message,
+            "audio track 1 lasts 11.500s but the video 12.000s, so it won't loop seamlessly"
+        );
+    }
+
+    #[test]
+    fn test_chunked_encode_checks_the_joined_duration() {
+        let dir = TestDir::new();
+        // The chunks are planned for 200 s, but the join probes at 100 s.
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 100);
+        opts.chunks = 2;
+        opts.duration = Some(200.0);
+        let err = reduce_video(&tool, "input.mp4", &dir.join("output.mp4"), &opts).unwrap_err();
+        assert!(matches!(&err, ReduceError::Encode(m) if m.contains("joined chunks")));
+        assert!(dir.entries().is_empty());
+    }
+
+    #[test]
+    fn test_chunked_encode_needs_a_whole_file_output() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 100);
+        opts.chunks = 2;
+        let err = reduce_video(&tool, "input.mp4", "-", &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)));
+        opts.parts = 2;
+        let err = reduce_video(&tool, "input.mp4", &dir.join("output.mp4"), &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)));
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+
+        // Too short to be worth splitting.
+        let tool = MockVideoTool::new(15.0);
+        opts.parts = 1;
+        reduce_video(&tool, "input.mp4", &dir.join("output.mp4"), &opts).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-f"), None);
+    }
+
+    /// A 60 s video whose audio runs on to `audio` seconds, which is what
+    /// the format duration reports.
+    fn tool_with_audio_lasting(audio: f64) -> MockVideoTool {
+        let mut tool = MockVideoTool::new(audio.max(60.0));
+        tool.info.duration = Some("60.000000".into());
+        tool.info.audio_streams = Some(vec![AudioStream {
+            index: 1,
+            codec_name: Some("aac".into()),
+            channels: Some(2),
+            duration: Some(format!("{:.6}", audio)),
+            ..AudioStream::default()
+        }]);
+        tool
+    }
+
+    #[test]
+    fn test_audio_past_the_video_is_cut_off() {
+        let dir = TestDir::new();
+        let bitrate_for = |tool: &MockVideoTool, opts: &ReduceOptions| {
+            reduce_video(tool, "in.mp4", &dir.join("out.mp4"), opts).unwrap();
+            let call = tool.ffmpeg_calls.lock().unwrap().last().unwrap().clone();
+            (
+                arg_value(&call, "-b:v").unwrap().to_string(),
+                call.contains(&"-shortest".to_string()),
+            )
+        };
+        let opts = opts_in(&dir, 50);
+        let (even, shortest) = bitrate_for(&tool_with_audio_lasting(60.0), &opts);
+        assert!(!shortest);
+
+        let tool = tool_with_audio_lasting(65.0);
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let call = tool.single_call();
+        // Budgeted for the 60 s of video, not the 65 s of the format.
+        assert_eq!(arg_value(&call, "-b:v"), Some(even.as_str()));
+        assert!(call.contains(&"-shortest".to_string()));
+        let warning = &report.warnings[0];
+        assert_eq!(warning.code, Code::AudioPastVideo);
+        assert!(
+            warning.message.contains("runs 0:05 past"),
+            "{}",
+            warning.message
+        );
+
+        let mut keep = opts_in(&dir, 50);
+        keep.trim_to_video = false;
+        let (bitrate, shortest) = bitrate_for(&tool_with_audio_lasting(65.0), &keep);
+        assert_ne!(bitrate, even);
+        assert!(!shortest);
+    }
+
+    #[test]
+    fn test_audio_ending_early_is_only_reported() {
+        let dir = TestDir::new();
+        let tool = tool_with_audio_lasting(40.0);
+        let report =
+            reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts_in(&dir, 50)).unwrap();
+        assert!(!tool.single_call().contains(&"-shortest".to_string()));
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_no_audio_gives_audio_budget_to_video() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 100);
+        opts.no_audio = true;
+
+        reduce_video(&tool, "input.mp4", &dir.join("output.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert!(args.contains(&"-an".to_string()));
+        assert!(!args.contains(&"aac".to_string()));
+        assert_eq!(arg_value(&args, "-b:v"), Some("8384k"));
+    }
+
+    #[test]
+    fn test_part_output_path() {
+        assert_eq!(part_output_path("out/clip.mp4", 2), "out/clip.part2.mp4");
+        assert_eq!(part_output_path("clip", 1), "clip.part1");
+        assert_eq!(sample_output_path("out/clip.mp4"), "out/clip.sample.mp4");
+    }
+
+    #[test]
+    fn test_sample_encodes_the_start_at_the_full_run_bitrate() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        reduce_video(
+            &tool,
+            "input.mp4",
+            &dir.join("full.mp4"),
+            &opts_in(&dir, 50),
+        )
+        .unwrap();
+        let full = tool.single_call();
+
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 50);
+        opts.sample = Some(30.0);
+        reduce_video(&tool, "input.mp4", &dir.join("sample.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-ss"), None);
+        assert_eq!(arg_value(&args, "-t"), Some("30.000"));
+        // Planned over the whole 100 s, not budgeted for 30 s.
+        assert_eq!(arg_value(&args, "-b:v"), arg_value(&full, "-b:v"));
+        assert_eq!(arg_value(&args, "-b:a"), arg_value(&full, "-b:a"));
+    }
+
+    #[test]
+    fn test_url_input_goes_to_ffmpeg_as_is() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let url = "https://example.com/clip.mp4?token=abc";
+        reduce_video(&tool, url, &dir.join("out.mp4"), &opts_in(&dir, 50)).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-i"), Some(url));
+    }
+
+    #[test]
+    fn test_download_first_encodes_the_local_copy() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 50);
+        opts.download_first = true;
+        let url = "https://example.com/v/clip.webm?token=abc";
+        reduce_video(&tool, url, &dir.join("out.mp4"), &opts).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(arg_value(&calls[0], "-i"), Some(url));
+        assert_eq!(arg_value(&calls[0], "-c"), Some("copy"));
+        let copy = calls[0].last().unwrap();
+        assert!(
+            copy.contains("download-") && copy.ends_with(".webm"),
+            "{}",
+            copy
+        );
+        assert_eq!(arg_value(&calls[1], "-i"), Some(copy.as_str()));
+        // WebM has no room for attachments.
+        assert!(!calls[0].contains(&"0:t?".to_string()));
+        assert!(download_args(url, "copy.mkv").contains(&"0:t?".to_string()));
+        // The copy went away with the run directory.
+        assert_eq!(dir.entries(), vec!["out.mp4"]);
+    }
+
+    #[test]
+    fn test_failed_download_is_a_probe_error() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.fail_ffmpeg = true;
+        let mut opts = opts_in(&dir, 50);
+        opts.download_first = true;
+        let err =
+            reduce_video(&tool, "http://example.com/a", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(matches!(&err, ReduceError::Probe(m) if m.starts_with("cannot download")));
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 1);
+        assert!(tool.ffmpeg_calls.lock().unwrap()[0]
+            .last()
+            .unwrap()
+            .ends_with(".mkv"));
+    }
+
+    #[test]
+    fn test_encrypted_input_fails_before_encoding() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.codec_name = Some("h264".into());
+        tool.info.codec_tag_string = Some("encv".into());
+        let err =
+            reduce_video(&tool, "drm.mp4", &dir.join("out.mp4"), &opts_in(&dir, 50)).unwrap_err();
+        assert!(
+            matches!(&err, ReduceError::Probe(m) if m.contains("stream 0 (video, h264 'encv')"))
+        );
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+        assert_eq!(tool.decode_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_sample_is_held_to_its_share_of_the_target() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        // Well under the 50 MiB target, but over the 15 MiB share of 30 s.
+        tool.output_bytes = vec![mib(20), mib(14)];
+        let mut opts = opts_in(&dir, 50);
+        opts.sample = Some(30.0);
+        let report = reduce_video(&tool, "input.mp4", &dir.join("sample.mp4"), &opts).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        let bitrate = |args: &[String]| {
+            arg_value(args, "-b:v")
+                .unwrap()
+                .trim_end_matches('k')
+                .parse::<u64>()
+                .unwrap()
+        };
+        // Scaled by 15/20 with the safety margin, as for the full run.
+        let expected = (bitrate(&calls[0]) as f64 * 0.75 * 0.95) as u64;
+        assert!(bitrate(&calls[1]).abs_diff(expected) <= 1, "{:?}", calls[1]);
+        // The overhead predicted is the sample's share too.
+        let prediction = report.prediction.unwrap();
+        let full = reduce_video(
+            &MockVideoTool::new(100.0),
+            "input.mp4",
+            &dir.join("full.mp4"),
+            &opts_in(&dir, 50),
+        )
+        .unwrap()
+        .prediction
+        .unwrap();
+        assert_eq!(
+            prediction.overhead_bytes,
+            (full.overhead_bytes as f64 * 0.3) as u64
+        );
+    }
+
+    #[test]
+    fn test_split_parts_are_predicted_for_their_own_length() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        // The first part comes out over its 50 MiB and is redone.
+        tool.output_bytes = vec![mib(55), mib(45), mib(48)];
+        let mut opts = opts_in(&dir, 50);
+        opts.parts = 2;
+        let report = reduce_video(&tool, "input.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        assert!(calls.iter().all(|c| arg_value(c, "-t") == Some("50.000")));
+        let prediction = report.prediction.unwrap();
+        assert_eq!(prediction.actual_bytes, mib(45) + mib(48));
+        // Each part's payload is its bitrate over its 50 s, not the input's
+        // 100 s, so the two parts add up to about the whole target.
+        let predicted = prediction.predicted_bytes();
+        assert!(
+            predicted <= mib(100) && predicted > mib(90),
+            "{}",
+            predicted
+        );
+    }
+
+    #[test]
+    fn test_sample_longer_than_input_covers_all_of_it() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(20.0);
+        let mut opts = opts_in(&dir, 50);
+        opts.sample = Some(30.0);
+        reduce_video(&tool, "input.mp4", &dir.join("output.mp4"), &opts).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-t"), Some("20.000"));
+    }
+
+    #[test]
+    fn test_failed_encode_leaves_no_partial_files() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.fail_ffmpeg = true;
+        let output = dir.join("output.mp4");
+        assert!(reduce_video(&tool, "in.mp4", &output, &opts_in(&dir, 50)).is_err());
+        assert!(dir.entries().is_empty());
+    }
+
+    #[test]
+    fn test_keep_temp_leaves_run_dir() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 50);
+        opts.keep_temp = true;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let entries = dir.entries();
+        assert_eq!(entries.len(), 2);
+        assert!(entries
+            .iter()
+            .any(|e| e.starts_with(crate::tempdir::PREFIX)));
+    }
+
+    #[test]
+    fn test_reduce_video_fixes_odd_dimensions() {
+        let tool = MockVideoTool::new(100.0).with_dimensions(1281, 721);
+        let dir = TestDir::new();
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts_in(&dir, 50)).unwrap();
+
+        let args = tool.single_call();
+        let vf = args.iter().position(|a| a == "-vf").expect("missing -vf");
+        assert_eq!(args[vf + 1], "scale=trunc(iw/2)*2:trunc(ih/2)*2");
+        // The filter must come after the input and only appear once.
+        assert!(vf > args.iter().position(|a| a == "-i").unwrap());
+        assert_eq!(args.iter().filter(|a| *a == "-vf").count(), 1);
+    }
+
+    #[test]
+    fn test_reduce_video_merges_downscale_and_fps() {
+        let tool = MockVideoTool::new(100.0).with_dimensions(1921, 1081);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.max_width = Some(1281);
+        opts.fps = Some(30.0);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-vf"), Some("scale=1280:-2,fps=30"));
+    }
+
+    #[test]
+    fn test_cfr_normalizes_to_the_rounded_average_rate() {
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.r_frame_rate = Some("120/1".into());
+        tool.info.avg_frame_rate = Some("1792000/60000".into());
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert!(!tool.single_call().iter().any(|a| a == "-vsync"));
+
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        opts.cfr = Some(None);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-vsync"), Some("cfr"));
+        assert_eq!(arg_value(&args, "-r"), Some("30"));
+
+        // An explicit rate wins, and drives the quality estimate.
+        opts.cfr = Some(Some(24.0));
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert_eq!(plan.fps, 24.0);
+    }
+
+    fn remux_tool() -> MockVideoTool {
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.codec_name = Some("h264".into());
+        tool.info.audio_streams = Some(vec![AudioStream {
+            index: 1,
+            codec_name: Some("aac".into()),
+            channels: Some(2),
+            ..AudioStream::default()
+        }]);
+        tool.subtitle_codecs = vec!["subrip".into()];
+        tool
+    }
+
+    #[test]
+    fn test_remux_only_copies_compatible_streams() {
+        let tool = remux_tool();
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.remux_only = true;
+        let output = dir.join("out.mp4");
+        reduce_video(&tool, "in.mkv", &output, &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-c"), Some("copy"));
+        assert_eq!(arg_value(&args, "-c:s"), Some("mov_text"));
+        assert_eq!(arg_value(&args, "-movflags"), Some("+faststart"));
+        assert!(!args.iter().any(|a| a == "-b:v"));
+        assert!(Path::new(&output).exists());
+    }
+
+    #[test]
+    fn test_remux_only_keeps_fonts_in_matroska() {
+        let mut tool = remux_tool();
+        tool.streams = crate::breakdown::parse_streams(
+            r#"{"streams": [
+                {"index": 0, "codec_name": "h264", "codec_type": "video"},
+                {"index": 1, "codec_name": "ass", "codec_type": "subtitle"},
+                {"index": 2, "codec_name": "ttf", "codec_type": "attachment"}
+            ]}"#,
+        )
+        .unwrap();
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.remux_only = true;
+
+        warning::take();
+        reduce_video(&tool, "in.mkv", &dir.join("out.mkv"), &opts).unwrap();
+        let maps_attachments = |args: &[String]| args.windows(2).any(|w| w == ["-map", "0:t?"]);
+        assert!(maps_attachments(&tool.single_call()));
+        assert!(warning::take().is_empty());
+
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        let report = reduce_video(&tool, "in.mkv", &dir.join("out.mp4"), &opts).unwrap();
+        assert!(!maps_attachments(&tool.single_call()));
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].code, Code::AttachmentsDropped);
+        assert!(report.warnings[0]
+            .message
+            .starts_with("1 attachment of the input"));
+    }
+
+    #[test]
+    fn test_remux_only_falls_back_to_encoding() {
+        let mut tool = remux_tool();
+        tool.info.audio_streams.as_mut().unwrap()[0].codec_name = Some("vorbis".into());
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.remux_only = true;
+        reduce_video(&tool, "in.mkv", &dir.join("out.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        assert!(arg_value(&args, "-b:v").is_some());
+        assert_eq!(arg_value(&args, "-c"), None);
+
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        opts.strict_remux = true;
+        let err = reduce_video(&tool, "in.mkv", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(
+            err.to_string().contains("vorbis audio can't go into MP4"),
+            "{}",
+            err
+        );
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remux_only_needs_an_input_within_the_target() {
+        let tool = remux_tool();
+        let dir = TestDir::new();
+        let input = dir.join("in.mkv");
+        std::fs::File::create(&input)
+            .and_then(|f| f.set_len(mib(60)))
+            .unwrap();
+        let mut opts = opts_in(&dir, 50);
+        opts.remux_only = true;
+        opts.strict_remux = true;
+        let err = reduce_video(&tool, &input, &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(err.to_string().contains("over the target"), "{}", err);
+    }
+
+    #[test]
+    fn test_reduce_video_keeps_color_signaling() {
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.color_primaries = Some("bt709".into());
+        tool.info.color_transfer = Some("unknown".into());
+        tool.info.color_range = Some("tv".into());
+        let dir = TestDir::new();
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts_in(&dir, 50)).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-color_primaries"), Some("bt709"));
+        assert_eq!(arg_value(&args, "-color_range"), Some("tv"));
+        assert_eq!(arg_value(&args, "-color_trc"), None);
+        assert_eq!(arg_value(&args, "-colorspace"), None);
+        // Output options must precede the output path.
+        assert!(args.last().unwrap().ends_with(".mp4"));
+    }
+
+    #[test]
+    fn test_reduce_video_passes_preset_and_progress() {
+        let mut tool = MockVideoTool::new(100.0);
+        tool.progress = vec![Progress {
+            out_time: 50.0,
+            speed: Some(2.0),
+            done: false,
+        }];
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.preset = Preset::Slow;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-preset"), Some("slow"));
+        assert_eq!(arg_value(&args, "-progress"), Some("pipe:1"));
+    }
+
+    #[test]
+    fn test_explicit_presets_win_over_effort_with_a_warning() {
+        let tool = MockVideoTool::new(100.0);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.effort = Some(8);
+        opts.preset = effort::preset(8);
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-preset"), Some("slower"));
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        opts.preset = Preset::Fast;
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-preset"), Some("fast"));
+        assert_eq!(report.warnings[0].code, Code::EffortOverridden);
+        assert_eq!(
+            report.warnings[0].message,
+            "--preset fast overrides --effort 8 (preset slower for libx264)"
+        );
+
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        opts.encoder = VideoEncoder::SvtAv1;
+        opts.preset = effort::preset(8);
+        opts.encoder_params = Some(EncoderParams::parse(VideoEncoder::SvtAv1, "preset=2").unwrap());
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(
+            report.warnings[0].message,
+            "--svtav1-params preset=2 overrides --effort 8 (preset 5 for libsvtav1)"
+        );
+    }
+
+    #[test]
+    fn test_svt_av1_maps_the_preset() {
+        let tool = MockVideoTool::new(100.0);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.encoder = VideoEncoder::SvtAv1;
+        opts.preset = Preset::Veryslow;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-c:v"), Some("libsvtav1"));
+        assert_eq!(arg_value(&args, "-preset"), Some("4"));
+        assert_eq!(arg_value(&args, "-b:v"), Some("4061k"));
+    }
+
+    #[test]
+    fn test_encoder_params_are_passed_through() {
+        let tool = MockVideoTool::new(100.0);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.encoder_params =
+            Some(EncoderParams::parse(VideoEncoder::H264, "aq-mode=3:vbv-maxrate=900").unwrap());
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(
+            arg_value(&args, "-x264-params"),
+            Some("aq-mode=3:vbv-maxrate=900")
+        );
+        // The tool's own bitrate still goes in; the user's key only warns.
+        assert_eq!(arg_value(&args, "-b:v"), Some("4059k"));
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].code, Code::EncoderParamsRateControl);
+
+        opts.dry_run = true;
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert!(plan
+            .describe(&opts)
+            .contains(&"Encoder options -x264-params aq-mode=3:vbv-maxrate=900".to_string()));
+    }
+
+    #[test]
+    fn test_a_device_only_takes_a_copy_that_keeps_to_all_its_limits() {
+        let device = |device: crate::device::Device| {
+            let c = device.constraints();
+            let mut opts = ReduceOptions::new(mib(50));
+            opts.encoder = c.encoder.unwrap();
+            opts.max_width = c.max_width;
+            opts.max_height = c.max_height;
+            opts.max_fps = c.max_fps;
+            opts.compat = c.compat;
+            opts
+        };
+        let ios = device(crate::device::Device::Ios);
+        let android = device(crate::device::Device::Android);
+        let fitting = |edit: &dyn Fn(&mut VideoInfo)| {
+            let mut tool = tool_with_source_bitrate("2000000");
+            tool.info.avg_frame_rate = Some("30/1".to_string());
+            tool.info.profile = Some("High".to_string());
+            tool.info.level = Some(41);
+            tool.info.pix_fmt = Some("yuv420p".to_string());
+            edit(&mut tool.info);
+            tool.info
+        };
+        // (what the source is, its stream, the device, the note when not
+        // copied)
+        let cases: [(&str, VideoInfo, &ReduceOptions, Option<&str>); 9] = [
+            ("within every limit", fitting(&|_| {}), &ios, None),
+            (
+                "main profile at a lower level",
+                fitting(&|i| {
+                    i.profile = Some("Main".to_string());
+                    i.level = Some(31);
+                }),
+                &ios,
+                None,
+            ),
+            (
+                "hevc",
+                fitting(&|i| i.codec_name = Some("hevc".to_string())),
+                &ios,
+                Some("Cannot stream-copy the hevc video into h264 output; re-encoding"),
+            ),
+            (
+                "over the level",
+                fitting(&|i| i.level = Some(51)),
+                &ios,
+                Some("Cannot stream-copy the video for --device: its level is 5.1, over 4.2; re-encoding"),
+            ),
+            (
+                "high 10",
+                fitting(&|i| i.profile = Some("High 10".to_string())),
+                &ios,
+                Some("Cannot stream-copy the video for --device: its profile is High 10, not high; re-encoding"),
+            ),
+            (
+                "4:4:4",
+                fitting(&|i| i.pix_fmt = Some("yuv444p".to_string())),
+                &ios,
+                Some("Cannot stream-copy the video for --device: it is yuv444p, not yuv420p; re-encoding"),
+            ),
+            (
+                "not probed",
+                fitting(&|i| i.level = None),
+                &ios,
+                Some("Cannot stream-copy the video for --device: its level is unknown, over 4.2; re-encoding"),
+            ),
+            (
+                "larger than the frame",
+                fitting(&|i| {
+                    i.width = 3840;
+                    i.height = 2160;
+                }),
+                &ios,
+                Some("Cannot stream-copy the video because it needs filtering; re-encoding"),
+            ),
+            (
+                "faster than the frame rate",
+                fitting(&|i| i.avg_frame_rate = Some("60/1".to_string())),
+                &android,
+                Some("Cannot stream-copy the video because it needs filtering; re-encoding"),
+            ),
+        ];
+        for (name, info, opts, note) in cases {
+            let plan = plan_encoding(100.0, &info, opts);
+            assert_eq!(plan.copy_video, note.is_none(), "{}", name);
+            if let Some(note) = note {
+                assert!(
+                    plan.notes.contains(&note.to_string()),
+                    "{}: {:?}",
+                    name,
+                    plan.notes
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_device_compat_reaches_the_encode() {
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.audio_streams = Some(vec![AudioStream {
+            index: 1,
+            codec_name: Some("ac3".into()),
+            channels: Some(6),
+            ..AudioStream::default()
+        }]);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.compat = crate::device::Device::OldTv.constraints().compat;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-profile:v"), Some("main"));
+        assert_eq!(arg_value(&args, "-level:v"), Some("3.1"));
+        assert_eq!(arg_value(&args, "-pix_fmt"), Some("yuv420p"));
+        assert_eq!(arg_value(&args, "-ar"), Some("48000"));
+        // The 5.1 track is downmixed, and budgeted as stereo.
+        assert_eq!(arg_value(&args, "-ac:a:0"), Some("2"));
+        assert_eq!(arg_value(&args, "-b:a:0"), Some("128k"));
+        assert_eq!(arg_value(&args, "-movflags"), Some("+faststart"));
+
+        // No profile for other encoders, nor faststart for Matroska.
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        opts.encoder = VideoEncoder::SvtAv1;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mkv"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-profile:v"), None);
+        assert_eq!(arg_value(&args, "-movflags"), None);
+        assert_eq!(arg_value(&args, "-pix_fmt"), Some("yuv420p"));
+    }
+
+    #[test]
+    fn test_transient_failures_are_retried_with_a_warning() {
+        let tool = MockVideoTool::new(100.0);
+        let dir = TestDir::new();
+        let output = dir.join("out.mp4");
+        let mut opts = opts_in(&dir, 50);
+        tool.transient_probe_failures.set(1);
+        let err = reduce_video(&tool, "in.mp4", &output, &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::Probe(_)), "{:?}", err);
+
+        opts.transient_retries = Retries {
+            count: 2,
+            first_delay: std::time::Duration::ZERO,
+        };
+        tool.transient_probe_failures.set(1);
+        let report = reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].code, Code::TransientRetry);
+        assert_eq!(
+            report.warnings[0].message,
+            "probing the input failed (I/O error); retrying in 0s (1 of 2)"
+        );
+
+        // The encode is run again as it was.
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        tool.transient_ffmpeg_failures.set(2);
+        let report = reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap().clone();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0], calls[2]);
+        assert_eq!(report.warnings.len(), 2);
+        assert!(report.warnings[1]
+            .message
+            .starts_with("starting the encode failed (connection reset)"));
+
+        tool.transient_ffmpeg_failures.set(3);
+        let err = reduce_video(&tool, "in.mp4", &output, &opts).unwrap_err();
+        assert!(err.to_string().contains("Connection reset"), "{}", err);
+    }
+
+    #[test]
+    fn test_the_budget_holds_every_kind_of_retry() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        // A failure to start, then over the target, then within it.
+        tool.output_bytes = vec![0, mib(60), mib(40)];
+        let mut opts = opts_in(&dir, 50);
+        opts.transient_retries = Retries {
+            count: 1,
+            first_delay: std::time::Duration::ZERO,
+        };
+        let report = {
+            tool.transient_ffmpeg_failures.set(1);
+            reduce_video(&tool, "in.mp4", &dir.join("a.mp4"), &opts).unwrap()
+        };
+        let attempts: Vec<(AttemptKind, &Outcome)> = report
+            .attempts
+            .iter()
+            .map(|attempt| (attempt.kind, &attempt.outcome))
+            .collect();
+        assert!(
+            matches!(
+                attempts[..],
+                [
+                    (AttemptKind::Encode, Outcome::Failed { .. }),
+                    (AttemptKind::TransientRetry, Outcome::OverTarget { .. }),
+                    (AttemptKind::OverTargetRetry, Outcome::Kept { .. }),
+                ]
+            ),
+            "{:?}",
+            attempts
+        );
+
+        // Two attempts in all leave no room for the retry at a lower
+        // bitrate, which isn't promised either.
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        opts.budget.max_attempts = Some(2);
+        tool.transient_ffmpeg_failures.set(1);
+        let output = dir.join("b.mp4");
+        let err = reduce_video(&tool, "in.mp4", &output, &opts).unwrap_err();
+        assert_eq!(err.exit_code(), 12, "{}", err);
+        assert!(
+            err.to_string()
+                .contains("the 2 attempts --max-attempts allows"),
+            "{}",
+            err
+        );
+        let ReduceError::BudgetExhausted { attempts, .. } = &err else {
+            unreachable!()
+        };
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 2);
+        assert!(!warning::take()
+            .iter()
+            .any(|warning| warning.code == Code::OverTargetRetry));
+        assert!(!Path::new(&output).exists());
+
+        // Out of time, only the first attempt runs.
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        opts.budget = Budget {
+            max_attempts: None,
+            max_total_time: Some(std::time::Duration::ZERO),
+        };
+        tool.transient_ffmpeg_failures.set(1);
+        let err = reduce_video(&tool, "in.mp4", &output, &opts).unwrap_err();
+        assert!(err.to_string().contains("--max-total-time"), "{}", err);
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_unsupported_outputs_fail_before_probing() {
+        let tool = MockVideoTool::new(100.0);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        for output in ["out.mp3", "out.avi", "out"] {
+            let err = reduce_video(&tool, "in.mp4", &dir.join(output), &opts).unwrap_err();
+            assert!(
+                matches!(err, ReduceError::Usage(_)),
+                "{}: {:?}",
+                output,
+                err
+            );
+        }
+        let err = reduce_video(&tool, "in.mp4", &dir.join("out.webm"), &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "WebM can't hold h264 video; use --codec svt-av1 or another extension"
+        );
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+
+        opts.encoder = VideoEncoder::SvtAv1;
+        reduce_video(&tool, "in.mp4", &dir.join("out.webm"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-c:v"), Some("libsvtav1"));
+        assert_eq!(arg_value(&args, "-c:a"), Some("libopus"));
+    }
+
+    #[test]
+    fn test_legacy_avi_outputs_get_compatible_codecs() {
+        let tool = MockVideoTool::new(100.0);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.allow_legacy_container = true;
+        opts.encoder = VideoEncoder::SvtAv1;
+        reduce_video(&tool, "in.mp4", &dir.join("out.avi"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-c:v"), Some("libx264"));
+        assert_eq!(arg_value(&args, "-c:a"), Some("libmp3lame"));
+    }
+
+    #[test]
+    fn test_audio_encoder_follows_what_the_build_has() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 50);
+        opts.encoder = VideoEncoder::SvtAv1;
+        tool.encoders = vec!["libsvtav1".into(), "libfdk_aac".into(), "opus".into()];
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-c:a"), Some("libfdk_aac"));
+
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        reduce_video(&tool, "in.mp4", &dir.join("out.webm"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-c:a"), Some("opus"));
+        assert_eq!(arg_value(&args, "-strict"), Some("experimental"));
+
+        // Nothing for MP3 fails before encoding, unless there is no audio.
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        opts.allow_legacy_container = true;
+        opts.encoder = VideoEncoder::H264;
+        tool.encoders = vec!["libx264".into(), "aac".into()];
+        let err = reduce_video(&tool, "in.mp4", &dir.join("out.avi"), &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)), "{:?}", err);
+        assert!(err.to_string().contains("--enable-libmp3lame"), "{}", err);
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+        opts.no_audio = true;
+        reduce_video(&tool, "in.mp4", &dir.join("out.avi"), &opts).unwrap();
+        assert!(tool.single_call().contains(&"-an".to_string()));
+    }
+
+    #[test]
+    fn test_encoder_params_are_left_out_after_a_fallback() {
+        let mut tool = MockVideoTool::new(100.0);
+        tool.encoders = vec!["libx264".into(), "aac".into()];
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.encoder = VideoEncoder::SvtAv1;
+        opts.encoder_params = Some(EncoderParams::parse(VideoEncoder::SvtAv1, "tbr=900").unwrap());
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-c:v"), Some("libx264"));
+        assert_eq!(arg_value(&args, "-svtav1-params"), None);
+        assert_eq!(arg_value(&args, "-x264-params"), None);
+        let codes: Vec<Code> = report.warnings.iter().map(|w| w.code).collect();
+        assert_eq!(codes, [Code::EncoderFallback]);
+    }
+
+    #[test]
+    fn test_missing_svt_av1_falls_back_to_h264() {
+        let mut tool = MockVideoTool::new(100.0);
+        tool.encoders = vec!["libx264".into(), "aac".into()];
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.encoder = VideoEncoder::SvtAv1;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-c:v"), Some("libx264"));
+        assert_eq!(arg_value(&args, "-preset"), Some("medium"));
+    }
+
+    #[test]
+    fn test_fail_on_poor_quality_stops_before_encoding() {
+        // An hour of 1080p30 in 100 MiB leaves about 100k for the video.
+        let tool = MockVideoTool::new(3600.0);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 100);
+        opts.fail_on_poor_quality = true;
+        let err = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)), "{:?}", err);
+        assert!(err.to_string().contains("--max-width 1280"), "{}", err);
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+
+        // Without the flag it is only a warning.
+        opts.fail_on_poor_quality = false;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_min_quality_refuses_and_names_what_would_reach_it() {
+        // Ten minutes of 1080p30 in 100 MiB leaves about 1.2 Mb/s for the
+        // video, half of what the floor needs at that size.
+        let tool = MockVideoTool::new(600.0);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 100);
+        opts.min_quality = Some(Floor::Bpp(0.04));
+        let err = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(
+            matches!(err, ReduceError::BelowQualityFloor(_)),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.exit_code(), 10);
+        assert!(err.to_string().contains("(--max-height 720)"), "{}", err);
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+
+        // Following the advice gets it encoded.
+        opts.max_height = Some(720);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 1);
+
+        // A level floor is the codec's threshold for it.
+        opts.max_height = None;
+        opts.min_quality = Some(Floor::Level(Quality::Good));
+        let err = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(
+            err.to_string().contains("--min-quality good (0.080 bpp)"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_max_encode_time_switches_to_faster_preset() {
+        // One hour of 1080p30 at medium is estimated at 40 minutes.
+        let tool = MockVideoTool::new(3600.0);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 100);
+        opts.max_encode_minutes = Some(20.0);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(arg_value(&tool.single_call(), "-preset"), Some("veryfast"));
+    }
+
+    #[test]
+    fn test_max_encode_time_refuses_impossible_budget() {
+        let tool = MockVideoTool::new(3600.0).with_dimensions(3840, 2160);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 100);
+        opts.max_encode_minutes = Some(1.0);
+        let err = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(err.to_string().contains("exceeds the 1:00 budget"));
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_max_width_ignored_for_narrower_sources() {
+        let tool = MockVideoTool::new(100.0).with_dimensions(640, 360);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.max_width = Some(1280);
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        assert_eq!(arg_value(&tool.single_call(), "-vf"), None);
+    }
+
+    /// A phone's 4K recording at 60 fps.
+    const UHD_60_FIXTURE: &str = r#"{"streams": [{"width": 3840, "height": 2160,
+        "r_frame_rate": "60/1", "avg_frame_rate": "60/1"}]}"#;
+
+    /// A portrait clip, taller than it is wide, at 30 fps.
+    const PORTRAIT_FIXTURE: &str = r#"{"streams": [{"width": 1080, "height": 1920,
+        "r_frame_rate": "30/1", "avg_frame_rate": "30/1"}]}"#;
+
+    #[test]
+    fn test_hard_caps_decide_size_and_rate() {
+        let uhd = crate::probe::parse_video_info(UHD_60_FIXTURE).unwrap();
+        let portrait = crate::probe::parse_video_info(PORTRAIT_FIXTURE).unwrap();
+        let opts = |max_width, max_height, max_fps| ReduceOptions {
+            max_width,
+            max_height,
+            max_fps,
+            ..ReduceOptions::new(mib(50))
+        };
+        let scaled = |width, height, cap| Some(Downscale { width, height, cap });
+        // (source, --max-width, --max-height, --max-fps) -> (downscale, rate cap)
+        let cases = [
+            (
+                &uhd,
+                None,
+                Some(1080),
+                Some(30.0),
+                scaled(1920, 1080, Cap::MaxHeight),
+                Some(30.0),
+            ),
+            (&uhd, None, None, Some(60.0), None, None),
+            (&uhd, None, Some(2160), None, None, None),
+            // The stricter limit wins: 1280 wide is only 720 tall.
+            (
+                &uhd,
+                Some(1280),
+                Some(1080),
+                None,
+                scaled(1280, 720, Cap::MaxWidth),
+                None,
+            ),
+            (
+                &uhd,
+                Some(1920),
+                Some(720),
+                None,
+                scaled(1280, 720, Cap::MaxHeight),
+                None,
+            ),
+            (
+                &portrait,
+                None,
+                Some(1080),
+                None,
+                scaled(608, 1080, Cap::MaxHeight),
+                None,
+            ),
+            (
+                &portrait,
+                Some(720),
+                Some(1920),
+                Some(24.0),
+                scaled(720, 1280, Cap::MaxWidth),
+                Some(24.0),
+            ),
+        ];
+        for (i, (info, w, h, fps, expected_scale, expected_rate)) in cases.into_iter().enumerate() {
+            let opts = opts(w, h, fps);
+            assert_eq!(downscale(info, &opts), expected_scale, "case {}", i);
+            assert_eq!(frame_rate_cap(info, &opts), expected_rate, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn test_max_fps_caps_explicit_rates_too() {
+        let uhd = crate::probe::parse_video_info(UHD_60_FIXTURE).unwrap();
+        let mut opts = ReduceOptions::new(mib(50));
+        opts.max_fps = Some(30.0);
+        opts.fps = Some(50.0);
+        assert_eq!(frame_rate_cap(&uhd, &opts), Some(30.0));
+        opts.fps = Some(24.0);
+        assert_eq!(frame_rate_cap(&uhd, &opts), None);
+
+        // --cfr keeps its own mechanism, at the capped rate.
+        opts.fps = None;
+        opts.cfr = Some(None);
+        let plan = plan_encoding(100.0, &uhd, &opts);
+        assert_eq!(plan.cfr, Some(30.0));
+        assert_eq!(plan.fps, 30.0);
+        assert!(plan.filters.is_empty());
+        assert_eq!(plan.caps, [Cap::MaxFps]);
+    }
+
+    #[test]
+    fn test_caps_filter_the_output_and_are_reported() {
+        let mut tool = MockVideoTool::new(100.0).with_dimensions(3840, 2160);
+        tool.info.avg_frame_rate = Some("60/1".into());
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.max_height = Some(1080);
+        opts.max_fps = Some(30.0);
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert_eq!(
+            plan.notes,
+            [
+                "Downscaling: 3840x2160 to 1920x1080 (--max-height 1080)",
+                "Output frame rate: 30 fps (--max-fps)",
+            ]
+        );
+
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(report.caps, [Cap::MaxHeight, Cap::MaxFps]);
+        let vf = arg_value(&tool.single_call(), "-vf").unwrap().to_string();
+        assert!(vf.contains("scale=-2:1080"), "{}", vf);
+        assert!(vf.contains("fps=30"), "{}", vf);
+    }
+
+    #[test]
+    fn test_vertical_letterboxes_or_crops_landscape_sources() {
+        let tool = MockVideoTool::new(100.0).with_dimensions(1920, 1080);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.vertical = Some(vertical::Fit::Pad);
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert_eq!(
+            plan.notes,
+            ["Vertical canvas: 1080x1920 (1920x1080 scaled to 1080x608, letterboxed)"]
+        );
+        assert_eq!(output_size(&tool.info, &opts), (1080, 1920));
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(
+            arg_value(&tool.single_call(), "-vf"),
+            Some("scale=1080:608,setsar=1,pad=1080:1920:(ow-iw)/2:(oh-ih)/2")
+        );
+
+        opts.vertical = Some(vertical::Fit::Crop);
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert_eq!(
+            plan.notes,
+            ["Vertical canvas: 608x1080 (1920x1080 center-cropped to 608x1080)"]
+        );
+        assert_eq!(
+            plan.filters.render().unwrap(),
+            FilterGraph::Simple("crop=608:1080:656:0,scale=608:1080,setsar=1".into())
+        );
+    }
+
+    #[test]
+    fn test_region_is_cropped_before_scaling_and_planned_at_its_size() {
+        let tool = MockVideoTool::new(100.0).with_dimensions(3840, 2160);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 20);
+        opts.region = region::parse("1280x720+2560+0").ok();
+        let full = plan_encoding(100.0, &tool.info, &opts);
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(
+            arg_value(&tool.single_call(), "-vf"),
+            Some("crop=1280:720:2560:0")
+        );
+        let settings = report.recipe.unwrap().settings.unwrap();
+        assert_eq!((settings.width, settings.height), (1280, 720));
+        assert_eq!(settings.region.as_deref(), Some("1280x720+2560+0"));
+        // A ninth of the pixels gets the same bits.
+        let cropped = opts.region.unwrap().crop(&tool.info);
+        let plan = plan_encoding(100.0, &cropped, &opts);
+        assert!(
+            plan.quality > full.quality,
+            "{} {}",
+            plan.quality,
+            full.quality
+        );
+        assert_eq!(
+            plan.notes,
+            ["Region: 1280x720+2560+0 of the source frame (--region)"]
+        );
+
+        // --max-width applies to the region, not the frame.
+        opts.max_width = Some(1920);
+        assert_eq!(output_size(&cropped, &opts), (1280, 720));
+        opts.max_width = Some(640);
+        let plan = plan_encoding(100.0, &cropped, &opts);
+        assert_eq!(
+            plan.filters.render().unwrap(),
+            FilterGraph::Simple("crop=1280:720:2560:0,scale=640:-2".into())
+        );
+        assert_eq!(
+            plan.notes[1],
+            "Downscaling: 1280x720 to 640x360 (--max-width 640)"
+        );
+    }
+
+    #[test]
+    fn test_region_off_the_frame_is_refused_before_encoding() {
+        let tool = MockVideoTool::new(100.0).with_dimensions(1920, 1080);
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 20);
+        opts.region = region::parse("1280x720+1280+0").ok();
+        let error = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert_eq!(error.exit_code(), 2);
+        assert!(
+            error
+                .to_string()
+                .contains("at 1280x720 its offsets can be +0+0 to +640+360"),
+            "{}",
+            error
+        );
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_vertical_sources_are_left_alone_or_capped() {
+        let mut opts = ReduceOptions::new(mib(50));
+        opts.vertical = Some(vertical::Fit::Crop);
+        let phone = MockVideoTool::new(100.0).with_dimensions(1080, 1920);
+        let plan = plan_encoding(100.0, &phone.info, &opts);
+        assert!(plan.filters.is_empty());
+        assert!(plan.notes.is_empty(), "{:?}", plan.notes);
+
+        let tall = MockVideoTool::new(100.0).with_dimensions(2160, 3840);
+        let plan = plan_encoding(100.0, &tall.info, &opts);
+        assert_eq!(
+            plan.filters.render().unwrap(),
+            FilterGraph::Simple("scale=1080:1920,setsar=1".into())
+        );
+        // --vertical replaces the --max-* caps.
+        assert!(plan.caps.is_empty());
+    }
+
+    /// A 16:9 NTSC DVD: 720x480 stored, with pixels 32:27 wide.
+    const NTSC_WIDESCREEN_FIXTURE: &str = r#"{"streams": [{"width": 720, "height": 480,
+        "sample_aspect_ratio": "32:27", "display_aspect_ratio": "16:9",
+        "avg_frame_rate": "30000/1001"}]}"#;
+
+    #[test]
+    fn test_anamorphic_sources_scale_by_their_displayed_size() {
+        let ntsc = crate::probe::parse_video_info(NTSC_WIDESCREEN_FIXTURE).unwrap();
+        let mut ntsc_4_3 = ntsc.clone();
+        ntsc_4_3.sample_aspect_ratio = Some("8:9".into());
+        let square = crate::probe::parse_video_info(
+            r#"{"streams": [{"width": 720, "height": 480, "sample_aspect_ratio": "1:1"}]}"#,
+        )
+        .unwrap();
+        let opts = |max_width, max_height| ReduceOptions {
+            max_width,
+            max_height,
+            ..ReduceOptions::new(mib(50))
+        };
+        // (source, --max-width, --max-height) -> output size
+        let cases = [
+            // Displayed at 854x480, so 640 wide is 360 tall, not 426.
+            (&ntsc, Some(640), None, Some((640, 360, Cap::MaxWidth))),
+            (&ntsc, Some(800), None, Some((800, 450, Cap::MaxWidth))),
+            (&ntsc, None, Some(360), Some((640, 360, Cap::MaxHeight))),
+            // Wider than 720 once stretched, so --max-width 720 applies.
+            (&ntsc, Some(720), None, Some((720, 404, Cap::MaxWidth))),
+            (&ntsc, Some(1280), None, None),
+            (&ntsc_4_3, Some(320), None, Some((320, 240, Cap::MaxWidth))),
+            (&ntsc_4_3, Some(720), None, None),
+            (&square, Some(640), None, Some((640, 426, Cap::MaxWidth))),
+        ];
+        for (i, (info, w, h, expected)) in cases.into_iter().enumerate() {
+            let expected = expected.map(|(width, height, cap)| Downscale { width, height, cap });
+            assert_eq!(downscale(info, &opts(w, h)), expected, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn test_anamorphic_frames_get_square_pixels_or_keep_their_aspect() {
+        let dvd = || {
+            let mut tool = MockVideoTool::new(100.0).with_dimensions(720, 480);
+            tool.info.sample_aspect_ratio = Some("32:27".into());
+            tool
+        };
+        let tool = dvd();
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 50);
+        opts.max_width = Some(640);
+        reduce_video(&tool, "in.mp4", &dir.join("small.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-vf"), Some("scale=640:360,setsar=1"));
+        assert_eq!(arg_value(&args, "-aspect"), None);
+
+        // Kept at 720x480, the frame is marked 16:9 explicitly.
+        let tool = dvd();
+        opts.max_width = None;
+        reduce_video(&tool, "in.mp4", &dir.join("kept.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-vf"), None);
+        assert_eq!(arg_value(&args, "-aspect"), Some("16:9"));
+    }
+
+    #[test]
+    fn test_muxing_bytes() {
+        // 3,000 frames of 16 bytes (8 of them for B-frame offsets), and
+        // 4,687.5 AAC frames per track of 8.
+        assert_eq!(muxing_bytes(100.0, 30.0, 1, VideoEncoder::H264), 85_500);
+        assert_eq!(muxing_bytes(100.0, 30.0, 0, VideoEncoder::SvtAv1), 24_000);
+        assert_eq!(muxing_bytes(100.0, 30.0, 2, VideoEncoder::SvtAv1), 99_000);
+    }
+
+    /// The kind of each adjustment, for comparing plans without their
+    /// exact figures.
+    fn adjustment_kinds(plan: &EncodingPlan) -> Vec<&'static str> {
+        plan.adjustments
+            .iter()
+            .map(|a| match a {
+                Adjustment::Overhead(_) => "overhead",
+                Adjustment::Muxing(_) => "muxing",
+                Adjustment::Audio(_) => "audio",
+                Adjustment::Data(_) => "data",
+                Adjustment::RaisedToMinimum(_) => "minimum",
+                Adjustment::SourceCap(_) => "source cap",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_adjustment_interactions() {
+        struct Case {
+            name: &'static str,
+            duration: f64,
+            target_mib: u64,
+            overhead_percent: f64,
+            /// Channels of each audio track; `None` for `--no-audio`.
+            channels: Option<&'static [u32]>,
+            source_bitrate: Option<&'static str>,
+            encoder: VideoEncoder,
+            video_k: u64,
+            adjustments: &'static [&'static str],
+            copy_video: bool,
+        }
+        let cases = [
+            Case {
+                name: "overhead and stereo audio",
+                duration: 100.0,
+                target_mib: 100,
+                overhead_percent: 5.0,
+                channels: Some(&[2]),
+                source_bitrate: None,
+                encoder: VideoEncoder::H264,
+                video_k: 7834,
+                adjustments: &["overhead", "muxing", "audio"],
+                copy_video: false,
+            },
+            Case {
+                // The source cap applies to what overhead, muxing and both
+                // tracks left, and the capped video plus audio fits.
+                name: "overhead, source cap and two-track ladder",
+                duration: 100.0,
+                target_mib: 100,
+                overhead_percent: 5.0,
+                channels: Some(&[6, 2]),
+                source_bitrate: Some("3000000"),
+                encoder: VideoEncoder::H264,
+                video_k: 2850,
+                adjustments: &["overhead", "muxing", "audio", "source cap"],
+                copy_video: true,
+            },
+            Case {
+                name: "audio over the budget",
+                duration: 10_000.0,
+                target_mib: 50,
+                overhead_percent: 0.0,
+                channels: Some(&[2]),
+                source_bitrate: None,
+                encoder: VideoEncoder::H264,
+                video_k: 100,
+                adjustments: &["muxing", "audio", "minimum"],
+                copy_video: false,
+            },
+            Case {
+                // Capping can't go below the minimum the clamp raised to.
+                name: "minimum clamp above a tiny source",
+                duration: 10_000.0,
+                target_mib: 50,
+                overhead_percent: 0.0,
+                channels: Some(&[2]),
+                source_bitrate: Some("80000"),
+                encoder: VideoEncoder::H264,
+                video_k: 100,
+                adjustments: &["muxing", "audio", "minimum"],
+                copy_video: false,
+            },
+            Case {
+                name: "no audio, no frame reordering",
+                duration: 100.0,
+                target_mib: 100,
+                overhead_percent: 0.0,
+                channels: None,
+                source_bitrate: None,
+                encoder: VideoEncoder::SvtAv1,
+                video_k: 8386,
+                adjustments: &["muxing"],
+                copy_video: false,
+            },
+        ];
+        for case in cases {
+            let mut tool = tool_with_audio(case.channels.unwrap_or(&[]));
+            tool.info.bit_rate = case.source_bitrate.map(str::to_string);
+            let mut opts = ReduceOptions::new(mib(case.target_mib));
+            opts.overhead_percent = case.overhead_percent;
+            opts.no_audio = case.channels.is_none();
+            opts.audio_tracks = AudioSelection::All;
+            opts.encoder = case.encoder;
+            let plan = plan_encoding(case.duration, &tool.info, &opts);
+            assert_eq!(plan.video_bitrate / 1000, case.video_k, "{}", case.name);
+            assert_eq!(adjustment_kinds(&plan), case.adjustments, "{}", case.name);
+            assert_eq!(plan.copy_video, case.copy_video, "{}", case.name);
+            assert_eq!(plan.clamped(), case.adjustments.contains(&"minimum"));
+            assert_eq!(
+                plan.source_capped(),
+                case.adjustments.contains(&"source cap")
+            );
+        }
+    }
+
+    #[test]
+    fn test_plan_predicts_the_target_it_fills() {
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = ReduceOptions::new(mib(100));
+        opts.overhead_percent = 5.0;
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        // Overhead and muxing are set aside and the rest is filled, so the
+        // prediction is the target, give or take the rounding to whole bits.
+        assert!(plan.predicted_bytes <= mib(100));
+        assert!(mib(100) - plan.predicted_bytes < 100);
+        assert_eq!(plan.payload_budget_bytes, mib(100) * 95 / 100 - 85_500);
+        assert!(plan.warnings.is_empty());
+
+        // A clamped plan predicts going over.
+        let plan = plan_encoding(10_000.0, &tool.info, &ReduceOptions::new(mib(50)));
+        assert!(plan.predicted_bytes > mib(50));
+        let codes: Vec<Code> = plan.warnings.iter().map(|w| w.code).collect();
+        assert_eq!(codes, [Code::BitrateClamped]);
+    }
+
+    #[test]
+    fn test_surround_audio_over_the_track_cap_is_a_warning() {
+        let mut tool = MockVideoTool::new(100.0);
+        let track = |index, channels| AudioStream {
+            index,
+            channels: Some(channels),
+            ..AudioStream::default()
+        };
+        tool.info.audio_streams = Some(vec![track(1, 2), track(2, 6)]);
+        let mut opts = ReduceOptions::new(mib(100));
+        opts.audio_tracks = AudioSelection::All;
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert_eq!(
+            plan.warnings,
+            [Warning::new(
+                Code::AudioDowngraded,
+                "audio track 2 has 6 channels; encoding it at 256k instead of 384k"
+            )]
+        );
+
+        // Stereo gets its full 64k per channel.
+        opts.audio_tracks = AudioSelection::Track(1);
+        assert!(plan_encoding(100.0, &tool.info, &opts).warnings.is_empty());
+    }
+
+    #[test]
+    fn test_report_lists_the_warnings_of_the_run() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(10_000.0);
+        let mut opts = opts_in(&dir, 50);
+        opts.sample = Some(30.0);
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let codes: Vec<Code> = report.warnings.iter().map(|w| w.code).collect();
+        assert_eq!(
+            codes,
+            [Code::BitrateClamped, Code::PoorQuality, Code::SampleOnly]
+        );
+        // Taken into the report, so the next run starts empty.
+        assert!(warning::take().is_empty());
+    }
+
+    #[test]
+    fn test_plan_notes_filter_and_copy_decisions() {
+        let mut tool = tool_with_source_bitrate("2000000").with_dimensions(1921, 1080);
+        tool.info.audio_streams = Some(Vec::new());
+        let mut opts = ReduceOptions::new(mib(100));
+        opts.max_width = Some(1280);
+        opts.copy_if_larger = true;
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert!(!plan.filters.is_empty());
+        assert!(!plan.copy_video);
+        assert_eq!(
+            plan.notes,
+            [
+                "Downscaling: 1921x1080 to 1280x720 (--max-width 1280)",
+                "Cannot stream-copy the video because it needs filtering; re-encoding",
+                "Source video is only 2000k; capped the bitrate at 1900k, so the output will come in under the target",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_output_directory_is_created_or_reported() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let output = dir.join("out/result.mp4");
+        let mut opts = opts_in(&dir, 100);
+        opts.create_dirs = false;
+        let err = reduce_video(&tool, "in.mp4", &output, &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)));
+        assert!(err
+            .to_string()
+            .starts_with("output directory does not exist: "));
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+
+        // A dry run would create it, so it doesn't have to.
+        opts.create_dirs = true;
+        opts.dry_run = true;
+        reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+        assert!(!dir.path().join("out").exists());
+
+        opts.dry_run = false;
+        reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+        assert!(Path::new(&output).exists());
+    }
+
+    #[test]
+    fn test_dry_run_encodes_nothing() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 100);
+        opts.dry_run = true;
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        assert_eq!(report, ReduceReport::default());
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+        assert!(!Path::new(&dir.join("out.mp4")).exists());
+
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert_eq!(
+            plan.describe(&opts),
+            [
+                "Target 100 MiB",
+                "  minus 85,500 bytes of container index and packet headers",
+                "  minus 128k of audio",
+                "  = video 8253k",
+                "Output 1920x1080 at 30 fps, no filters",
+                "Predicted size 100 MiB",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_two_passes_share_the_pass_log() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        // Over the target once, so the second pass runs again.
+        tool.output_bytes = vec![0, mib(120), mib(90)];
+        let mut opts = opts_in(&dir, 100);
+        opts.two_pass = true;
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        let pass = |args: &[String]| arg_value(args, "-pass").map(str::to_string);
+        assert_eq!(pass(&calls[0]).as_deref(), Some("1"));
+        assert!(calls[0].contains(&"-an".to_string()));
+        assert_eq!(
+            calls[0].last().map(String::as_str),
+            Some(twopass::NULL_OUTPUT)
+        );
+        assert_eq!(pass(&calls[1]).as_deref(), Some("2"));
+        assert_eq!(pass(&calls[2]).as_deref(), Some("2"));
+        assert_eq!(
+            arg_value(&calls[0], "-passlogfile"),
+            arg_value(&calls[2], "-passlogfile")
+        );
+        assert_eq!(arg_value(&calls[1], "-maxrate"), None);
+    }
+
+    #[test]
+    fn test_reused_stats_skip_the_first_pass() {
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 100);
+        opts.two_pass = true;
+        let tool = MockVideoTool::new(100.0);
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        let header =
+            passstats::Header::of("in.mp4", opts.encoder, plan.width, plan.height, plan.fps);
+        let prefix = dir.path().join("earlier-ffmpeg2pass");
+        std::fs::write(passstats::log_path(&prefix), "#options: stats\n").unwrap();
+        let stats = dir.path().join("in.stats");
+        passstats::save(&prefix, &stats, &header).unwrap();
+        opts.stats = Some(stats.clone());
+
+        reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(arg_value(&calls[0], "-pass"), Some("2"));
+
+        // Stats of another frame size are refused before anything runs.
+        opts.max_width = Some(plan.width / 2);
+        let tool = MockVideoTool::new(100.0);
+        let err = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::Usage(_)), "{:?}", err);
+        assert!(
+            err.to_string().starts_with(&format!(
+                "cannot reuse the two-pass stats in {}: they were made at {}x{}",
+                stats.display(),
+                plan.width,
+                plan.height
+            )),
+            "{}",
+            err
+        );
+        assert!(tool.ffmpeg_calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_crf_capped_caps_the_peak_at_the_planned_bitrate() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(20.0);
+        tool.info.avg_frame_rate = Some("30/1".into());
+        // Over the target once, so the retry lowers the cap.
+        tool.output_bytes = vec![mib(120), mib(90)];
+        let mut opts = opts_in(&dir, 100);
+        opts.rate_mode = Mode::Auto;
+        let plan = plan_encoding(20.0, &tool.info, &opts);
+        assert_eq!(
+            plan.rate_control,
+            RateControl::CrfCapped,
+            "{}",
+            plan.rate_reason
+        );
+        let kbps = plan.video_bitrate / 1000;
+        assert!(plan.describe(&opts).contains(&format!(
+            "Constant quality, -crf 23 -maxrate {}k -bufsize {}k",
+            kbps,
+            2 * kbps
+        )));
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        for call in calls.iter() {
+            assert_eq!(arg_value(call, "-crf"), Some("23"));
+            assert_eq!(arg_value(call, "-b:v"), None);
+            assert_eq!(arg_value(call, "-passlogfile"), None);
+        }
+        let cap = |call: &[String]| -> u64 {
+            arg_value(call, "-maxrate")
+                .unwrap()
+                .trim_end_matches('k')
+                .parse()
+                .unwrap()
+        };
+        assert_eq!(cap(&calls[0]), kbps);
+        assert!(cap(&calls[1]) < kbps);
+        // Under the plan wherever it can be, so nothing for the history.
+        assert!(report.sample.is_none());
+        let settings = report.recipe.unwrap().settings.unwrap();
+        assert_eq!(settings.rate_control, RateControl::CrfCapped);
+
+        // Long enough that only an average bitrate lands near the target.
+        opts.target_bytes = mib(2000);
+        let plan = plan_encoding(600.0, &tool.info, &opts);
+        assert_eq!(plan.rate_control, RateControl::Abr, "{}", plan.rate_reason);
+    }
+
+    #[test]
+    fn test_sidecar_records_every_command() {
+        let dir = TestDir::new();
+        let output = dir.join("out.mp4");
+        let input = "https://cdn.example.com/in.mp4?sig=secret";
+        let mut tool = MockVideoTool::new(100.0);
+        tool.output_bytes = vec![0, mib(120), mib(90)];
+        let mut opts = opts_in(&dir, 100);
+        opts.two_pass = true;
+        opts.sidecar = true;
+        opts.command_line = ["mdviqure", input, &output, "--sidecar", "--two-pass"]
+            .map(String::from)
+            .to_vec();
+        let report = reduce_video(&tool, input, &output, &opts).unwrap();
+
+        let path = sidecar::path_for(&output);
+        assert_eq!(report.sidecar.as_deref(), Some(path.as_str()));
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(!json.contains("secret"), "{}", json);
+        let made: Sidecar = serde_json::from_str(&json).unwrap();
+        assert_eq!(made.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(made.command_line[1], url::redact(input));
+        // Both passes of the first attempt and the retry's second pass.
+        assert_eq!(made.recipe.ffmpeg.len(), 3);
+        assert_eq!(arg_value(&made.recipe.ffmpeg[0], "-pass"), Some("1"));
+        let settings = made.recipe.settings.unwrap();
+        assert_eq!(settings.passes, Passes::Two);
+        assert_eq!(settings.encoder, "libx264");
+        assert_eq!(made.recipe.source.duration_s, 100.0);
+        assert_eq!(made.report.output_bytes, Some(mib(90)));
+        assert_eq!(
+            made.report.predicted_bytes,
+            report.prediction.map(|p| p.predicted_bytes())
+        );
+
+        // A dry run makes nothing to record.
+        std::fs::remove_file(&path).unwrap();
+        opts.dry_run = true;
+        let report = reduce_video(&tool, input, &output, &opts).unwrap();
+        assert_eq!(report.sidecar, None);
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_extract_subs_writes_them_next_to_the_output() {
+        let dir = TestDir::new();
+        let output = dir.join("movie.mp4");
+        let mut tool = MockVideoTool::new(100.0);
+        tool.streams = crate::breakdown::parse_streams(
+            r#"{"streams": [
+                {"index": 0, "codec_name": "h264", "codec_type": "video"},
+                {"index": 1, "codec_name": "ass", "codec_type": "subtitle", "tags": {"language": "eng"}},
+                {"index": 2, "codec_name": "subrip", "codec_type": "subtitle", "tags": {"language": "fre"}}
+            ]}"#,
+        )
+        .unwrap();
+        let mut opts = opts_in(&dir, 100);
+        let report = reduce_video(&tool, "in.mkv", &output, &opts).unwrap();
+        assert!(report.subtitles.is_empty());
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 1);
+
+        opts.extract_subs = true;
+        let report = reduce_video(&tool, "in.mkv", &output, &opts).unwrap();
+        assert_eq!(
+            report.subtitles,
+            [dir.join("movie.eng.ass"), dir.join("movie.fre.srt")]
+        );
+        assert_eq!(
+            dir.entries(),
+            ["movie.eng.ass", "movie.fre.srt", "movie.mp4"]
+        );
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(arg_value(&calls[3], "-map"), Some("0:2"));
+        assert_eq!(arg_value(&calls[3], "-c:s"), Some("srt"));
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn test_checksum_hashes_the_input_and_each_part() {
+        let dir = TestDir::new();
+        let input = dir.join("in.mp4");
+        std::fs::write(&input, "abc").unwrap();
+        let output = dir.join("out.mp4");
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 100);
+        let report = reduce_video(&tool, &input, &output, &opts).unwrap();
+        assert_eq!(report.checksums, None);
+
+        opts.checksum = Some(Algorithm::Sha256);
+        opts.parts = 2;
+        opts.sidecar = true;
+        let report = reduce_video(&tool, &input, &output, &opts).unwrap();
+        let checksums = report.checksums.unwrap();
+        assert_eq!(checksums.algorithm, Algorithm::Sha256);
+        assert_eq!(
+            checksums.input.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        let paths: Vec<&str> = checksums.outputs.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [dir.join("out.part1.mp4"), dir.join("out.part2.mp4")]
+        );
+        for file in &checksums.outputs {
+            let hash = checksum::of_file(Algorithm::Sha256, Path::new(&file.path)).unwrap();
+            assert_eq!(file.hash, hash);
+        }
+        let json = std::fs::read_to_string(report.sidecar.unwrap()).unwrap();
+        let made: Sidecar = serde_json::from_str(&json).unwrap();
+        assert_eq!(made.report.checksums, Some(checksums));
+
+        // Nothing to hash on a dry run.
+        opts.dry_run = true;
+        let report = reduce_video(&tool, &input, &output, &opts).unwrap();
+        assert_eq!(report.checksums, None);
+    }
+
+    #[test]
+    fn test_cover_art_is_left_out_unless_kept() {
+        let dir = TestDir::new();
+        let output = dir.join("film.mp4");
+        let mut tool = MockVideoTool::new(100.0);
+        // The poster is stream 0, before the film.
+        tool.info.index = 1;
+        tool.info.cover_art = vec![0];
+        let maps = |args: &[String]| -> Vec<String> {
+            args.windows(2)
+                .filter(|w| w[0] == "-map")
+                .map(|w| w[1].clone())
+                .collect()
+        };
+        let mut opts = opts_in(&dir, 100);
+        reduce_video(&tool, "in.m4v", &output, &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(maps(&args), ["0:V:0", "0:a:0?"]);
+        assert!(!args.iter().any(|a| a == "attached_pic"));
+
+        opts.keep_cover_art = true;
+        opts.max_width = Some(1280);
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        let report = reduce_video(&tool, "in.m4v", &output, &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(maps(&args), ["0:V:0", "0:0", "0:a:0?"]);
+        assert_eq!(arg_value(&args, "-c:v"), Some("libx264"));
+        assert_eq!(arg_value(&args, "-c:v:1"), Some("copy"));
+        assert_eq!(arg_value(&args, "-disposition:v:1"), Some("attached_pic"));
+        // The scale is for the film alone.
+        assert_eq!(arg_value(&args, "-vf"), None);
+        assert!(arg_value(&args, "-filter:v:0")
+            .unwrap()
+            .starts_with("scale="));
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        // Fragmented MP4 has no place for it.
+        opts.fragment_mp4 = true;
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        let report = reduce_video(&tool, "in.m4v", &output, &opts).unwrap();
+        assert_eq!(maps(&tool.single_call()), ["0:V:0", "0:a:0?"]);
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].code, Code::CoverArtDropped);
+    }
+
+    #[test]
+    fn test_kept_cover_art_is_copied_by_remuxes_and_joins() {
+        let dir = TestDir::new();
+        let mut tool = remux_tool();
+        tool.info.cover_art = vec![3];
+        let mut opts = opts_in(&dir, 50);
+        opts.keep_cover_art = true;
+        opts.remux_only = true;
+        reduce_video(&tool, "in.mkv", &dir.join("out.mp4"), &opts).unwrap();
+        let args = tool.single_call().join(" ");
+        assert!(
+            args.contains("-map 0:V:0 -map 0:3 -c:v:1 copy -disposition:v:1 attached_pic "),
+            "{}",
+            args
+        );
+
+        // A chunked encode leaves it to the join, which reads it from the
+        // input after the chunks and the audio.
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        tool.info.avg_frame_rate = Some("30/1".into());
+        opts.remux_only = false;
+        opts.chunks = 2;
+        reduce_video(&tool, "in.mkv", &dir.join("out.mkv"), &opts).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 4);
+        assert!(calls[..3]
+            .iter()
+            .all(|c| !c.contains(&"-c:v:1".to_string())));
+        let join = calls[3].join(" ");
+        assert!(join.ends_with(".mkv"), "{}", join);
+        assert!(
+            join.contains(" -i in.mkv -map 0:v -map 2:3 -c:v:1 copy "),
+            "{}",
+            join
+        );
+    }
+
+    #[test]
+    fn test_gopro_telemetry_is_copied_only_when_kept() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.data_streams = parse_data_streams(GOPRO_DATA_STREAMS).unwrap();
+        let maps = |args: &[String]| -> Vec<String> {
+            args.windows(2)
+                .filter(|w| w[0] == "-map")
+                .map(|w| w[1].clone())
+                .collect()
+        };
+        let mut opts = opts_in(&dir, 100);
+        let report = reduce_video(&tool, "GX010042.MP4", &dir.join("out.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(maps(&args), ["0:V:0", "0:a:0?"]);
+        assert_eq!(arg_value(&args, "-c:d"), None);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        // Only the telemetry: the timecode and recovery tracks have no codec
+        // ffmpeg could copy them with.
+        opts.keep_data_streams = true;
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        let report = reduce_video(&tool, "GX010042.MP4", &dir.join("out.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(maps(&args), ["0:V:0", "0:a:0?", "0:3"]);
+        assert_eq!(arg_value(&args, "-c:d"), Some("copy"));
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        // Matroska has no place for it.
+        tool.ffmpeg_calls.lock().unwrap().clear();
+        let report = reduce_video(&tool, "GX010042.MP4", &dir.join("out.mkv"), &opts).unwrap();
+        let args = tool.single_call();
+        assert_eq!(maps(&args), ["0:V:0", "0:a:0?"]);
+        assert_eq!(arg_value(&args, "-c:d"), None);
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].code, Code::DataStreamsDropped);
+    }
+
+    #[test]
+    fn test_kept_data_streams_come_out_of_the_video_budget() {
+        let mut tool = MockVideoTool::new(100.0);
+        tool.info.data_streams = parse_data_streams(GOPRO_DATA_STREAMS).unwrap();
+        let mut opts = ReduceOptions::new(mib(50));
+        let without = plan_encoding(100.0, &tool.info, &opts);
+        assert!(!without
+            .adjustments
+            .iter()
+            .any(|a| matches!(a, Adjustment::Data(_))));
+
+        opts.keep_data_streams = true;
+        let with = plan_encoding(100.0, &tool.info, &opts);
+        assert!(with.adjustments.contains(&Adjustment::Data(48_063)));
+        assert_eq!(
+            Adjustment::Data(48_063).describe(SizeFormat::default()),
+            "minus 49k of data streams"
+        );
+        let taken = without.video_bitrate - with.video_bitrate;
+        assert!((48_062..=48_064).contains(&taken), "{}", taken);
+
+        // Nothing copyable takes nothing.
+        tool.info.data_streams.remove(1);
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert_eq!(plan.video_bitrate, without.video_bitrate);
+    }
+
+    #[test]
+    fn test_hwdecode_decodes_on_the_gpu_and_downloads_for_the_filters() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.hwaccels = vec!["vdpau".into(), "cuda".into()];
+        let mut opts = opts_in(&dir, 100);
+        opts.hwdecode = HwDecode::Cuda;
+        opts.max_width = Some(1280);
+        let report = reduce_video(&tool, "in.mkv", &dir.join("out.mp4"), &opts).unwrap();
+        let args = tool.single_call();
+        let joined = args.join(" ");
+        assert!(
+            joined.contains(" -hwaccel cuda -hwaccel_output_format cuda -i in.mkv "),
+            "{}",
+            joined
+        );
+        assert_eq!(arg_value(&args, "-c:v"), Some("libx264"));
+        assert_eq!(
+            arg_value(&args, "-vf"),
+            Some("hwdownload,format=nv12|p010le,scale=1280:-2")
+        );
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        // A copied video isn't decoded at all.
+        let mut tool = remux_tool();
+        tool.hwaccels = vec!["cuda".into()];
+        opts.max_width = None;
+        opts.remux_only = true;
+        reduce_video(&tool, "in.mkv", &dir.join("out.mp4"), &opts).unwrap();
+        assert!(!tool.single_call().contains(&"-hwaccel".to_string()));
+    }
+
+    #[test]
+    fn test_hwdecode_falls_back_to_software_when_it_fails_to_start() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.hwaccels = vec!["cuda".into()];
+        tool.broken_hwaccel = true;
+        // The software encode comes out over the target once, and its retry
+        // decodes in software too.
+        tool.output_bytes = vec![1024, 200 * 1024 * 1024, 1024];
+        let mut opts = opts_in(&dir, 100);
+        opts.hwdecode = HwDecode::Cuda;
+        let report = reduce_video(&tool, "in.mkv", &dir.join("out.mp4"), &opts).unwrap();
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        assert!(calls[0].contains(&"-hwaccel".to_string()));
+        for call in &calls[1..] {
+            assert!(!call.contains(&"-hwaccel".to_string()), "{:?}", call);
+            assert_eq!(arg_value(call, "-vf"), None);
+        }
+        let codes: Vec<Code> = report.warnings.iter().map(|w| w.code).collect();
+        assert_eq!(codes, [Code::HwDecodeFallback, Code::OverTargetRetry]);
+        assert!(report.warnings[0]
+            .message
+            .starts_with("cuda decoding failed to start"));
+    }
+
+    #[test]
+    fn test_hwdecode_this_ffmpeg_lacks_decodes_in_software() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(100.0);
+        let mut opts = opts_in(&dir, 100);
+        opts.hwdecode = HwDecode::Vaapi;
+        let report = reduce_video(&tool, "in.mkv", &dir.join("out.mp4"), &opts).unwrap();
+        assert!(!tool.single_call().contains(&"-hwaccel".to_string()));
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].code, Code::HwDecodeFallback);
+
+        // Other failures are not the decoder's, and aren't retried.
+        let mut tool = MockVideoTool::new(100.0);
+        tool.hwaccels = vec!["vaapi".into()];
+        tool.fail_ffmpeg = true;
+        let err = reduce_video(&tool, "in.mkv", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(matches!(err, ReduceError::Encode(_)));
+        assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_long_encodes_keep_the_system_awake_until_done() {
+        let dir = TestDir::new();
+        let output = dir.join("out.mp4");
+        // An hour of 1080p takes far longer than the automatic threshold.
+        let tool = MockVideoTool::new(3600.0);
+        reduce_video(&tool, "in.mp4", &output, &opts_in(&dir, 100)).unwrap();
+        assert_eq!(tool.sleep.events(), ["acquire Encoding out.mp4", "release"]);
+
+        let tool = MockVideoTool::new(10.0);
+        let mut opts = opts_in(&dir, 100);
+        reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+        assert!(tool.sleep.events().is_empty());
+        opts.inhibit_sleep = sleep::Policy::Always;
+        reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+        assert_eq!(tool.sleep.events().len(), 2);
+
+        let tool = MockVideoTool::new(3600.0);
+        opts.inhibit_sleep = sleep::Policy::Never;
+        reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+        assert!(tool.sleep.events().is_empty());
+
+        // Without an inhibitor the encode goes ahead, with a warning only
+        // when staying awake was asked for.
+        let mut tool = MockVideoTool::new(3600.0);
+        tool.sleep.unavailable = true;
+        opts.inhibit_sleep = sleep::Policy::Auto;
+        let report = reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+        assert!(!report
+            .warnings
+            .iter()
+            .any(|w| w.code == Code::SleepNotInhibited));
+        opts.inhibit_sleep = sleep::Policy::Always;
+        let report = reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+        let warning = report
+            .warnings
+            .iter()
+            .find(|w| w.code == Code::SleepNotInhibited)
+            .unwrap();
+        assert!(
+            warning.message.contains("mock: no inhibitor running"),
+            "{}",
+            warning.message
+        );
+        assert!(!tool.sleep.events().contains(&"release".to_string()));
+    }
+
+    #[test]
+    fn test_failed_and_interrupted_encodes_let_the_system_sleep() {
+        let dir = TestDir::new();
+        let output = dir.join("out.mp4");
+        let mut opts = opts_in(&dir, 100);
+        opts.inhibit_sleep = sleep::Policy::Always;
+
+        let mut tool = MockVideoTool::new(100.0);
+        tool.fail_ffmpeg = true;
+        assert!(reduce_video(&tool, "in.mp4", &output, &opts).is_err());
+        assert_eq!(tool.sleep.events(), ["acquire Encoding out.mp4", "release"]);
+
+        let token = CancelToken::new();
+        interrupt::watch(token.clone());
+        let mut tool = MockVideoTool::new(100.0);
+        tool.interrupt_ffmpeg = Some(token);
+        let err = reduce_video(&tool, "in.mp4", &output, &opts).unwrap_err();
+        interrupt::watch(CancelToken::new());
+        assert!(matches!(err, ReduceError::Interrupted), "{:?}", err);
+        assert_eq!(tool.sleep.events(), ["acquire Encoding out.mp4", "release"]);
+    }
+
+    #[test]
+    fn test_hls_is_verified_and_committed_as_a_whole() {
+        let dir = TestDir::new();
+        let output = dir.join("stream");
+        let mut opts = opts_in(&dir, 10);
+        opts.hls = Some(Hls {
+            segment_seconds: 4.0,
+        });
+        let mut tool = MockVideoTool::new(100.0);
+        // The segments of the first attempt are over the target together.
+        tool.output_bytes = vec![mib(12), mib(9)];
+        reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        let args = &calls[1];
+        assert_eq!(arg_value(args, "-f"), Some("hls"));
+        assert_eq!(arg_value(args, "-hls_time"), Some("4"));
+        assert_eq!(
+            arg_value(args, "-force_key_frames"),
+            Some("expr:gte(t,n_forced*4)")
+        );
+        let playlist = args.last().unwrap();
+        assert!(playlist.ends_with("index.m3u8") && !playlist.starts_with(&output));
+        let mut written: Vec<String> = std::fs::read_dir(&output)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        written.sort();
+        assert_eq!(written, ["index.m3u8", "segment00000.m4s"]);
+        assert_eq!(dir.entries(), ["stream"]);
+
+        opts.parts = 2;
+        let err = reduce_video(&tool, "in.mp4", &output, &opts).unwrap_err();
+        assert!(
+            err.to_string().ends_with("can't be used with --split"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_pad_to_exact_lands_on_the_target() {
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 10);
+        opts.pad_to_exact = true;
+        let mut tool = MockVideoTool::new(100.0);
+        tool.output_bytes = vec![mib(9)];
+        let output = dir.join("out.mp4");
+        let report = reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+        assert_eq!(std::fs::metadata(&output).unwrap().len(), opts.target_bytes);
+        // What the encode made, for the history to learn from.
+        assert_eq!(report.prediction.unwrap().actual_bytes, mib(9));
+
+        let err = reduce_video(&tool, "in.mp4", &dir.join("out.mkv"), &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--pad-to-exact pads with an MP4 free box, so it needs an MP4 or MOV output, not Matroska"
+        );
+        opts.parts = 2;
+        let err = reduce_video(&tool, "in.mp4", &output, &opts).unwrap_err();
+        assert!(
+            err.to_string().ends_with("can't be used with --split"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_pad_to_exact_retries_an_output_too_close_to_pad() {
+        let dir = TestDir::new();
+        let mut opts = opts_in(&dir, 10);
+        opts.pad_to_exact = true;
+        for short in 1..padding::MIN_BOX_BYTES {
+            let mut tool = MockVideoTool::new(100.0);
+            tool.output_bytes = vec![mib(10) - short, mib(9)];
+            let output = dir.join("out.mp4");
+            let report = reduce_video(&tool, "in.mp4", &output, &opts).unwrap();
+            assert_eq!(std::fs::metadata(&output).unwrap().len(), opts.target_bytes);
+            assert_eq!(tool.ffmpeg_calls.lock().unwrap().len(), 2);
+            let outcomes: Vec<&Outcome> = report.attempts.iter().map(|a| &a.outcome).collect();
+            assert!(matches!(
+                outcomes[..],
+                [Outcome::Failed { .. }, Outcome::Kept { .. }]
+            ));
+            let retry = report
+                .warnings
+                .iter()
+                .find(|w| w.code == Code::OverTargetRetry)
+                .unwrap();
+            assert!(retry
+                .message
+                .starts_with(&format!("output was {} bytes short", short)));
+        }
+
+        // Out of retries, it still fails, saying why.
+        opts.max_retries = 0;
+        let mut tool = MockVideoTool::new(100.0);
+        tool.output_bytes = vec![mib(10) - 3];
+        let err = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("it is 3 bytes short, and a box takes at least 8"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_an_input_reduced_in_place_survives_every_failure() {
+        let dir = TestDir::new();
+        let input = dir.join("clip.mp4");
+        let hash = || checksum::of_file(Algorithm::Sha256, Path::new(&input)).unwrap();
+        std::fs::write(&input, b"the original").unwrap();
+        let original = hash();
+        let mut opts = opts_in(&dir, 10);
+        opts.keep_original_on_failure = true;
+
+        let mut failing = MockVideoTool::new(100.0);
+        failing.fail_ffmpeg = true;
+        let mut oversized = MockVideoTool::new(100.0);
+        oversized.output_bytes = vec![mib(20)];
+        let mut unrenamable = MockVideoTool::new(100.0);
+        unrenamable.rename_error = Some(io::ErrorKind::PermissionDenied);
+        let mut full = MockVideoTool::new(100.0);
+        full.rename_error = Some(io::ErrorKind::StorageFull);
+        let token = CancelToken::new();
+        let mut interrupted = MockVideoTool::new(100.0);
+        interrupted.interrupt_ffmpeg = Some(token.clone());
+
+        interrupt::watch(token);
+        for (tool, expected) in [
+            (&failing, "ffmpeg"),
+            (&oversized, "over the 10485760-byte target"),
+            (&unrenamable, "cannot move encoded file"),
+            (&full, "out of disk space"),
+            (&interrupted, "interrupted"),
+        ] {
+            let err = reduce_video(tool, &input, &input, &opts).unwrap_err();
+            assert!(
+                err.to_string().to_lowercase().contains(expected),
+                "{}: {}",
+                expected,
+                err
+            );
+            assert_eq!(hash(), original, "{}", expected);
+            assert_eq!(dir.entries(), ["clip.mp4"], "{}", expected);
+        }
+        interrupt::watch(CancelToken::new());
+
+        reduce_video(&MockVideoTool::new(100.0), &input, &input, &opts).unwrap();
+        assert_ne!(hash(), original);
+        assert_eq!(dir.entries(), ["clip.mp4"]);
+    }
+
+    #[test]
+    fn test_short_clip_gets_one_capped_pass() {
+        let dir = TestDir::new();
+        let tool = MockVideoTool::new(5.0);
+        let mut opts = opts_in(&dir, 10);
+        opts.two_pass = true;
+        let report = reduce_video(&tool, "in.mp4", &dir.join("out.mp4"), &opts).unwrap();
+
+        let args = tool.single_call();
+        assert_eq!(arg_value(&args, "-pass"), None);
+        let kbps: u64 = arg_value(&args, "-b:v")
+            .unwrap()
+            .trim_end_matches('k')
+            .parse()
+            .unwrap();
+        assert_eq!(
+            arg_value(&args, "-maxrate"),
+            Some(format!("{}k", 2 * kbps).as_str())
+        );
+        let skipped: Vec<&Warning> = report
+            .warnings
+            .iter()
+            .filter(|w| w.code == Code::TwoPassSkipped)
+            .collect();
+        assert_eq!(skipped.len(), 1);
+        assert!(
+            skipped[0].message.contains("only 5.0s"),
+            "{}",
+            skipped[0].message
+        );
+
+        // The dry run says the same.
+        opts.dry_run = true;
+        let plan = plan_encoding(5.0, &tool.info, &opts);
+        assert_eq!(plan.passes, Passes::Capped);
+        let expected = format!("One pass, -maxrate {}k -bufsize {}k", 2 * kbps, 2 * kbps);
+        assert!(plan.describe(&opts).contains(&expected));
+        opts.encoder = VideoEncoder::SvtAv1;
+        assert_eq!(
+            plan_encoding(100.0, &tool.info, &opts).passes,
+            Passes::Capped
+        );
+        opts.encoder = VideoEncoder::H264;
+        let plan = plan_encoding(100.0, &tool.info, &opts);
+        assert_eq!(plan.passes, Passes::Two);
+        assert!(plan.describe(&opts).contains(&"Two passes".to_string()));
+    }
+
+    #[test]
+    fn test_plain_progress_prints_a_line_per_step() {
+        let mut display = ProgressDisplay::new(Presenter::stderr(ColorChoice::Never), 100.0);
+        let lines: Vec<String> = [(0.0, None), (4.0, None), (45.0, Some(151.0)), (47.0, None)]
+            .into_iter()
+            .filter_map(|(percent, eta)| display.plain_line(percent, eta))
+            .collect();
+        assert_eq!(lines, ["0% done", "45% done, ETA 2:31"]);
+        assert_eq!(
+            display.plain_line(100.0, None).as_deref(),
+            Some("100% done")
+        );
+    }
+}
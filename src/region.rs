@@ -0,0 +1,219 @@
+//! `--region`: encoding one rectangle of the frame, such as a window of a
+//! desktop capture.
+//!
+//! The rectangle is measured on the picture as it is displayed, turned
+//! upright the way ffmpeg turns a phone recording before filtering it, in
+//! pixels of the source before any scaling. The crop is applied the moment
+//! the input is probed ([`Region::crop`]), so the rest of the run plans
+//! with the region as the source frame: `--max-width`, `--vertical`, the
+//! bitrate math and the quality estimate all go by its size.
+
+use crate::probe::VideoInfo;
+use std::fmt;
+
+/// A rectangle of the displayed frame, `WxH+X+Y`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub width: u32,
+    pub height: u32,
+    /// Columns left of the region.
+    pub x: u32,
+    /// Rows above the region.
+    pub y: u32,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// Parses `--region`, such as `1280x720+2560+0`. Offsets count from the
+/// top left corner; X11's negative ones, from the other edges, are
+/// refused rather than guessed at.
+pub fn parse(text: &str) -> Result<Region, String> {
+    let invalid = || {
+        format!(
+            "invalid region '{}': expected WIDTHxHEIGHT+X+Y, e.g. 1280x720+0+0",
+            text
+        )
+    };
+    let text = text.trim();
+    let at = text.find(['+', '-']).ok_or_else(invalid)?;
+    let (size, offsets) = text.split_at(at);
+    let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let number = |s: &str| {
+        (!s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| s.parse::<u32>().ok())
+            .flatten()
+    };
+    let (width, height) = (
+        number(width).ok_or_else(invalid)?,
+        number(height).ok_or_else(invalid)?,
+    );
+    if offsets.contains('-') {
+        return Err(format!(
+            "invalid region '{}': offsets count from the top left corner and can't be negative",
+            text
+        ));
+    }
+    let Some((x, y)) = offsets[1..].split_once('+') else {
+        return Err(invalid());
+    };
+    let (x, y) = (
+        number(x).ok_or_else(invalid)?,
+        number(y).ok_or_else(invalid)?,
+    );
+    if width == 0 || height == 0 {
+        return Err(format!(
+            "invalid region '{}': the width and height must be at least 1",
+            text
+        ));
+    }
+    Ok(Region {
+        width,
+        height,
+        x,
+        y,
+    })
+}
+
+impl Region {
+    /// Why the region doesn't lie within the displayed frame of `info`,
+    /// with the sizes and offsets that would; `None` when it does.
+    pub fn misfit(self, info: &VideoInfo) -> Option<String> {
+        let (width, height) = info.displayed_size();
+        let frame = match info.rotation() {
+            0 => format!("the {}x{} frame", width, height),
+            turn => format!("the {}x{} frame (turned {}°)", width, height, turn),
+        };
+        if self.width > width || self.height > height {
+            return Some(format!(
+                "--region {} is larger than {}; its size can be 1x1 to {}x{}",
+                self, frame, width, height
+            ));
+        }
+        let (max_x, max_y) = (width - self.width, height - self.height);
+        (self.x > max_x || self.y > max_y).then(|| {
+            format!(
+                "--region {} runs off {}; at {}x{} its offsets can be +0+0 to +{}+{}",
+                self, frame, self.width, self.height, max_x, max_y
+            )
+        })
+    }
+
+    /// `info` with the region as its frame. A quarter turn stays in the
+    /// stored size, so the region's sides swap there.
+    pub fn crop(self, info: &VideoInfo) -> VideoInfo {
+        let (width, height) = if info.rotation() % 180 == 90 {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+        VideoInfo {
+            width,
+            height,
+            ..info.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::SideData;
+
+    fn frame(width: u32, height: u32, rotation: Option<f64>) -> VideoInfo {
+        VideoInfo {
+            width,
+            height,
+            side_data_list: vec![SideData { rotation }],
+            ..VideoInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_regions_parse_as_geometry() {
+        let region = |width, height, x, y| {
+            Ok(Region {
+                width,
+                height,
+                x,
+                y,
+            })
+        };
+        assert_eq!(parse("1280x720+2560+0"), region(1280, 720, 2560, 0));
+        assert_eq!(parse(" 641X361+1+3 "), region(641, 361, 1, 3));
+        assert_eq!(parse("1280x720+0+0").unwrap().to_string(), "1280x720+0+0");
+        for text in [
+            "1280x720",
+            "1280x720+0",
+            "1280x720+0+0+0",
+            "1280x+0+0",
+            "x720+0+0",
+            "1280*720+0+0",
+            "1280x720++0+0",
+            "1280x720+0x+0",
+            "",
+        ] {
+            let error = parse(text).unwrap_err();
+            assert!(
+                error.contains("expected WIDTHxHEIGHT+X+Y"),
+                "{}: {}",
+                text,
+                error
+            );
+        }
+        for text in ["1280x720-10+0", "1280x720+0-10", "1280x720+-10+0"] {
+            let error = parse(text).unwrap_err();
+            assert!(error.contains("can't be negative"), "{}: {}", text, error);
+        }
+        assert!(parse("0x720+0+0").unwrap_err().contains("at least 1"));
+    }
+
+    #[test]
+    fn test_regions_outside_the_frame_say_what_would_fit() {
+        let desktop = frame(3840, 2160, None);
+        assert_eq!(parse("1280x720+2560+1440").unwrap().misfit(&desktop), None);
+        assert_eq!(
+            parse("1280x720+2600+0").unwrap().misfit(&desktop).unwrap(),
+            "--region 1280x720+2600+0 runs off the 3840x2160 frame; at 1280x720 its offsets can be +0+0 to +2560+1440"
+        );
+        assert_eq!(
+            parse("4096x720+0+0").unwrap().misfit(&desktop).unwrap(),
+            "--region 4096x720+0+0 is larger than the 3840x2160 frame; its size can be 1x1 to 3840x2160"
+        );
+
+        // A portrait phone recording, stored landscape.
+        let phone = frame(1920, 1080, Some(-90.0));
+        assert_eq!(parse("1080x1000+0+920").unwrap().misfit(&phone), None);
+        assert_eq!(
+            parse("1280x720+0+0").unwrap().misfit(&phone).unwrap(),
+            "--region 1280x720+0+0 is larger than the 1080x1920 frame (turned 90°); its size can be 1x1 to 1080x1920"
+        );
+        // Upside down is the same size.
+        assert_eq!(
+            parse("1280x720+640+360")
+                .unwrap()
+                .misfit(&frame(1920, 1080, Some(180.0))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_the_cropped_frame_keeps_everything_but_its_size() {
+        let source = VideoInfo {
+            avg_frame_rate: Some("30/1".to_string()),
+            ..frame(3840, 2160, None)
+        };
+        let cropped = parse("1280x720+10+10").unwrap().crop(&source);
+        assert_eq!((cropped.width, cropped.height), (1280, 720));
+        assert_eq!(cropped.frame_rate(), Some(30.0));
+
+        let cropped = parse("1080x1000+0+0")
+            .unwrap()
+            .crop(&frame(1920, 1080, Some(90.0)));
+        assert_eq!((cropped.width, cropped.height), (1000, 1080));
+        assert_eq!(cropped.displayed_size(), (1080, 1000));
+    }
+}
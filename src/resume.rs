@@ -0,0 +1,227 @@
+//! Batch progress saved after every file, so a batch that died part-way
+//! can continue where it stopped.
+//!
+//! The state file lists the batch's inputs, its target and how far each
+//! item got. It is rewritten through a temp file and a rename, so a crash
+//! leaves either the previous state or the new one, never a torn file.
+
+use crate::batch::written_bytes;
+use crate::probecache::Saved;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the state file a batch keeps in its output directory.
+pub const STATE_FILE_NAME: &str = ".mdviqure-batch.json";
+
+/// How far one item of a batch got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Not finished; started items that were cut off stay pending too.
+    Pending,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemState {
+    pub input: String,
+    pub output: String,
+    pub status: Status,
+    /// Total size written, for finished items.
+    pub bytes: Option<u64>,
+    /// The item's own target, where a manifest gives it one other than the
+    /// batch's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_bytes: Option<u64>,
+}
+
+/// Progress of one batch invocation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchState {
+    pub target_bytes: u64,
+    pub parts: u32,
+    /// Directory holding the intermediate files of the running item;
+    /// whatever is left there after a crash is partial.
+    pub temp_dir: Option<PathBuf>,
+    pub items: Vec<ItemState>,
+    /// What probing the inputs found, so a resumed batch needn't ask again.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub probes: Vec<Saved>,
+}
+
+impl BatchState {
+    /// A fresh state with every item pending.
+    pub fn new(inputs: &[String], outputs: &[String], target_bytes: u64, parts: u32) -> Self {
+        Self {
+            target_bytes,
+            parts,
+            temp_dir: None,
+            items: inputs
+                .iter()
+                .zip(outputs)
+                .map(|(input, output)| ItemState {
+                    input: input.clone(),
+                    output: output.clone(),
+                    status: Status::Pending,
+                    bytes: None,
+                    target_bytes: None,
+                })
+                .collect(),
+            probes: Vec::new(),
+        }
+    }
+
+    /// Where a batch into `output_dir` keeps its state by default.
+    pub fn default_path(output_dir: &Path) -> PathBuf {
+        output_dir.join(STATE_FILE_NAME)
+    }
+
+    /// Reads the state at `path`; `None` when there is none.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Replaces the state at `path` in one rename.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        std::fs::write(&temp, text)?;
+        std::fs::rename(&temp, path)
+    }
+
+    /// Whether this state belongs to a batch of `fresh`'s inputs, outputs,
+    /// target and split.
+    pub fn is_same_batch(&self, fresh: &BatchState) -> bool {
+        self.target_bytes == fresh.target_bytes
+            && self.parts == fresh.parts
+            && self.items.len() == fresh.items.len()
+            && self.items.iter().zip(&fresh.items).all(|(a, b)| {
+                a.input == b.input && a.output == b.output && a.target_bytes == b.target_bytes
+            })
+    }
+
+    /// The bytes of item `index` when it may be skipped: it finished, and
+    /// its output is still on disk at the recorded size, within the target.
+    pub fn completed(&self, index: usize) -> Option<u64> {
+        let item = self.items.get(index)?;
+        let bytes = item.bytes.filter(|_| item.status == Status::Done)?;
+        let target = item.target_bytes.unwrap_or(self.target_bytes);
+        let limit = target * u64::from(self.parts.max(1));
+        let on_disk = written_bytes(&item.output, self.parts);
+        (on_disk == bytes && bytes > 0 && bytes <= limit).then_some(bytes)
+    }
+
+    /// Number of items that finished successfully.
+    pub fn done_count(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|i| i.status == Status::Done)
+            .count()
+    }
+
+    pub fn record(&mut self, index: usize, status: Status, bytes: Option<u64>) {
+        if let Some(item) = self.items.get_mut(index) {
+            item.status = status;
+            item.bytes = bytes;
+        }
+    }
+
+    /// Removes what a crashed run left in its temp directory.
+    pub fn remove_leftovers(&self) -> io::Result<()> {
+        match &self.temp_dir {
+            Some(dir) => match std::fs::remove_dir_all(dir) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    fn state_in(dir: &TestDir) -> BatchState {
+        let inputs = vec!["a.mp4".to_string(), "b.mp4".to_string()];
+        let outputs = vec![dir.join("a.mp4"), dir.join("b.mp4")];
+        BatchState::new(&inputs, &outputs, 1000, 1)
+    }
+
+    #[test]
+    fn test_state_round_trips() {
+        let dir = TestDir::new();
+        let path = BatchState::default_path(dir.path());
+        assert_eq!(BatchState::load(&path).unwrap(), None);
+
+        let mut state = state_in(&dir);
+        state.temp_dir = Some(dir.path().join("tmp"));
+        state.record(0, Status::Done, Some(900));
+        state.record(1, Status::Failed, None);
+        state.save(&path).unwrap();
+        assert_eq!(BatchState::load(&path).unwrap(), Some(state));
+
+        std::fs::write(&path, "{\"target_bytes\":").unwrap();
+        assert!(BatchState::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_only_verified_outputs_count_as_completed() {
+        let dir = TestDir::new();
+        let mut state = state_in(&dir);
+        std::fs::write(dir.join("a.mp4"), vec![0; 900]).unwrap();
+        state.record(0, Status::Done, Some(900));
+        assert_eq!(state.completed(0), Some(900));
+        assert_eq!(state.done_count(), 1);
+
+        // Changed since, or never finished.
+        std::fs::write(dir.join("a.mp4"), vec![0; 800]).unwrap();
+        assert_eq!(state.completed(0), None);
+        assert_eq!(state.completed(1), None);
+        std::fs::write(dir.join("b.mp4"), vec![0; 800]).unwrap();
+        state.record(1, Status::Failed, Some(800));
+        assert_eq!(state.completed(1), None);
+        // Over the target.
+        std::fs::write(dir.join("a.mp4"), vec![0; 1200]).unwrap();
+        state.record(0, Status::Done, Some(1200));
+        assert_eq!(state.completed(0), None);
+    }
+
+    #[test]
+    fn test_same_batch_needs_same_items_and_target() {
+        let dir = TestDir::new();
+        let state = state_in(&dir);
+        assert!(state.is_same_batch(&state_in(&dir)));
+        let mut other = state_in(&dir);
+        other.target_bytes = 2000;
+        assert!(!state.is_same_batch(&other));
+        let mut other = state_in(&dir);
+        other.items.pop();
+        assert!(!state.is_same_batch(&other));
+    }
+
+    #[test]
+    fn test_leftovers_are_removed() {
+        let dir = TestDir::new();
+        let mut state = state_in(&dir);
+        let temp = dir.path().join("batch-tmp");
+        std::fs::create_dir_all(temp.join("mdviqure-1-abc")).unwrap();
+        std::fs::write(temp.join("mdviqure-1-abc").join("partial.mp4"), b"x").unwrap();
+        state.temp_dir = Some(temp.clone());
+        state.remove_leftovers().unwrap();
+        assert!(!temp.exists());
+        state.remove_leftovers().unwrap();
+    }
+}
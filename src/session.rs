@@ -0,0 +1,217 @@
+//! A reduce running in the background, for programs embedding the library.
+//!
+//! [`EncodeSession`] owns its run the way a file handle owns a descriptor:
+//! dropping it without [`EncodeSession::wait`] cancels the encode, and the
+//! drop returns only once ffmpeg is dead and the run's temp directory,
+//! partial output included, is gone. A GUI that panics or closes its window
+//! mid-encode leaks neither processes nor files.
+
+use crate::error::ReduceError;
+use crate::interrupt::{self, CancelToken};
+use crate::progress::Progress;
+use crate::reduce::{reduce_video, ReduceOptions, ReduceReport};
+use crate::tool::VideoTool;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+
+/// Where the encode of a session stands.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SessionProgress {
+    /// Seconds of output the current attempt has encoded.
+    pub out_time: f64,
+    /// Seconds the current attempt encodes in all.
+    pub duration: f64,
+    /// Encoding speed relative to real time, if ffmpeg reported one.
+    pub speed: Option<f64>,
+}
+
+impl SessionProgress {
+    /// How much of the current attempt is done, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        if self.duration > 0.0 {
+            (self.out_time / self.duration).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+type Latest = Arc<Mutex<Option<SessionProgress>>>;
+
+thread_local! {
+    /// The session the current thread encodes for, if any.
+    static LATEST: RefCell<Option<Latest>> = const { RefCell::new(None) };
+}
+
+/// Records `progress` through an encode of `duration` seconds for the
+/// session running on this thread; does nothing on other threads.
+pub(crate) fn report(progress: &Progress, duration: f64) {
+    LATEST.with(|latest| {
+        if let Some(latest) = latest.borrow().as_ref() {
+            *latest.lock().unwrap_or_else(PoisonError::into_inner) = Some(SessionProgress {
+                out_time: progress.out_time,
+                duration,
+                speed: progress.speed,
+            });
+        }
+    });
+}
+
+/// A [`reduce_video`] run on a thread of its own.
+pub struct EncodeSession {
+    cancel: CancelToken,
+    latest: Latest,
+    /// Taken by [`EncodeSession::wait`], so that dropping afterwards has
+    /// nothing left to clean up.
+    worker: Option<JoinHandle<Result<ReduceReport, ReduceError>>>,
+}
+
+impl EncodeSession {
+    /// Starts reducing `input` into `output` with `opts`, probing and then
+    /// running ffmpeg on a new thread.
+    pub fn start<T: VideoTool + Send + 'static>(
+        tool: T,
+        input: &str,
+        output: &str,
+        opts: &ReduceOptions,
+    ) -> Result<Self, ReduceError> {
+        let cancel = CancelToken::new();
+        let latest = Latest::default();
+        let run = {
+            let (cancel, latest) = (cancel.clone(), latest.clone());
+            let (input, output, opts) = (input.to_string(), output.to_string(), opts.clone());
+            move || {
+                interrupt::watch(cancel);
+                LATEST.with(|slot| *slot.borrow_mut() = Some(latest));
+                reduce_video(&tool, &input, &output, &opts)
+            }
+        };
+        let worker = thread::Builder::new()
+            .name("mdviqure-encode".into())
+            .spawn(run)
+            .map_err(|e| ReduceError::Encode(format!("cannot start the encode thread: {}", e)))?;
+        Ok(Self {
+            cancel,
+            latest,
+            worker: Some(worker),
+        })
+    }
+
+    /// The latest progress report; `None` until ffmpeg sends the first one.
+    pub fn progress(&self) -> Option<SessionProgress> {
+        *self.latest.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether the run is over, so that [`EncodeSession::wait`] won't block.
+    pub fn is_finished(&self) -> bool {
+        self.worker.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    /// Asks the run to stop. ffmpeg is killed within a tenth of a second,
+    /// after which [`EncodeSession::wait`] returns
+    /// [`ReduceError::Interrupted`]. Cancelling again, or after the run is
+    /// over, does nothing.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Blocks until the run is over and returns its report. A panic on the
+    /// encode thread is resumed here.
+    pub fn wait(mut self) -> Result<ReduceReport, ReduceError> {
+        let worker = self.worker.take().expect("only wait() takes the worker");
+        worker
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+impl Drop for EncodeSession {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            self.cancel.cancel();
+            // The thread cleans up as it unwinds; a panic there has been
+            // printed already and must not turn into a second one here.
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{mib, MockVideoTool, TestDir};
+
+    fn opts_in(dir: &TestDir) -> ReduceOptions {
+        let mut opts = ReduceOptions::new(mib(100));
+        opts.temp_dir = Some(dir.path().to_path_buf());
+        opts
+    }
+
+    #[test]
+    fn test_wait_returns_the_report() {
+        let dir = TestDir::new();
+        let mut tool = MockVideoTool::new(100.0);
+        tool.progress = vec![Progress {
+            out_time: 25.0,
+            speed: Some(2.0),
+            done: false,
+        }];
+        let output = dir.join("output.mp4");
+        let session = EncodeSession::start(tool, "input.mp4", &output, &opts_in(&dir)).unwrap();
+        while !session.is_finished() {
+            thread::yield_now();
+        }
+        let progress = session.progress().unwrap();
+        assert_eq!(progress.fraction(), 0.25);
+        assert_eq!(progress.speed, Some(2.0));
+
+        let report = session.wait().unwrap();
+        assert!(report.prediction.is_some());
+        assert_eq!(dir.entries(), vec!["output.mp4"]);
+    }
+
+    #[test]
+    fn test_a_cancelled_session_reports_the_interruption() {
+        let dir = TestDir::new();
+        let output = dir.join("output.mp4");
+        let session = EncodeSession::start(
+            MockVideoTool::new(100.0),
+            "input.mp4",
+            &output,
+            &opts_in(&dir),
+        )
+        .unwrap();
+        session.cancel();
+        session.cancel();
+        // The mock may finish before it notices; either way nothing is
+        // half-written.
+        match session.wait() {
+            Ok(_) => assert_eq!(dir.entries(), vec!["output.mp4"]),
+            Err(e) => {
+                assert!(matches!(e, ReduceError::Interrupted), "{}", e);
+                assert!(dir.entries().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_dropping_a_finished_session_is_harmless() {
+        let dir = TestDir::new();
+        let output = dir.join("output.mp4");
+        let session = EncodeSession::start(
+            MockVideoTool::new(100.0),
+            "input.mp4",
+            &output,
+            &opts_in(&dir),
+        )
+        .unwrap();
+        while !session.is_finished() {
+            thread::yield_now();
+        }
+        drop(session);
+        // Cancelling after the fact must not remove the finished output.
+        assert_eq!(dir.entries(), vec!["output.mp4"]);
+        assert!(!interrupt::is_interrupted());
+    }
+}
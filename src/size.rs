@@ -0,0 +1,160 @@
+//! Size strings ("25", "25MB", "1.5GiB") and the unit system they are read
+//! in; [`crate::sizefmt`] writes sizes back out.
+
+use clap::ValueEnum;
+
+/// Whether "MB" means 1,000,000 bytes (SI, as upload limits usually do) or
+/// 1,048,576 bytes (binary).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SizeUnits {
+    Si,
+    Binary,
+}
+
+impl SizeUnits {
+    /// Bytes in one kilobyte.
+    pub fn kilo(self) -> u64 {
+        match self {
+            SizeUnits::Si => 1000,
+            SizeUnits::Binary => 1024,
+        }
+    }
+
+    /// Bytes in one megabyte.
+    pub fn megabyte(self) -> u64 {
+        self.kilo() * self.kilo()
+    }
+
+    /// What [`kilo`](Self::kilo) bytes to the power `power` are called,
+    /// from 1 to 3: `KB`, `MB` and `GB`, or `KiB`, `MiB` and `GiB`.
+    pub fn name(self, power: u32) -> &'static str {
+        match (self, power) {
+            (SizeUnits::Si, 1) => "KB",
+            (SizeUnits::Si, 2) => "MB",
+            (SizeUnits::Si, _) => "GB",
+            (SizeUnits::Binary, 1) => "KiB",
+            (SizeUnits::Binary, 2) => "MiB",
+            (SizeUnits::Binary, _) => "GiB",
+        }
+    }
+}
+
+/// Parses a size such as `25`, `25MB`, `500k` or `1.5GiB` into bytes.
+///
+/// A bare number is megabytes. `K`/`M`/`G` (optionally followed by `B`)
+/// follow `units`; `KiB`/`MiB`/`GiB` are always binary and `B` is bytes.
+pub fn parse_size(text: &str, units: SizeUnits) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "invalid size '{}': expected e.g. 25, 25MB, 500KB or 1.5GiB",
+            text
+        )
+    };
+    let trimmed = text.trim();
+    let split = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+    let (number, suffix) = trimmed.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let kilo = units.kilo();
+    let multiplier = match suffix.trim().to_ascii_lowercase().as_str() {
+        "" | "m" | "mb" => kilo * kilo,
+        "b" => 1,
+        "k" | "kb" => kilo,
+        "g" | "gb" => kilo * kilo * kilo,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    let bytes = (number * multiplier as f64).round();
+    if !bytes.is_finite() || bytes < 1.0 {
+        return Err(format!(
+            "invalid size '{}': must be greater than zero",
+            text
+        ));
+    }
+    Ok(bytes as u64)
+}
+
+/// Parses a bitrate such as `96k`, `1.5M` or `96000` into bits per second.
+///
+/// `k` and `M` are always powers of 1000, as ffmpeg reads them; a trailing
+/// `b/s` or `bps` is allowed, and a bare number is bits per second.
+pub fn parse_bitrate(text: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "invalid bitrate '{}': expected e.g. 96k, 1.5M or 96000",
+            text
+        )
+    };
+    let trimmed = text.trim();
+    let split = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+    let (number, suffix) = trimmed.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let suffix = suffix.trim().to_ascii_lowercase();
+    let unit = suffix
+        .strip_suffix("b/s")
+        .or_else(|| suffix.strip_suffix("bps"))
+        .unwrap_or(&suffix);
+    let multiplier = match unit {
+        "" => 1.0,
+        "k" => 1e3,
+        "m" => 1e6,
+        _ => return Err(invalid()),
+    };
+    let bits = (number * multiplier).round();
+    if !bits.is_finite() || bits < 1.0 {
+        return Err(format!(
+            "invalid bitrate '{}': must be greater than zero",
+            text
+        ));
+    }
+    Ok(bits as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size_follows_unit_system() {
+        assert_eq!(parse_size("25", SizeUnits::Si), Ok(25_000_000));
+        assert_eq!(parse_size("25MB", SizeUnits::Si), Ok(25_000_000));
+        assert_eq!(parse_size("25", SizeUnits::Binary), Ok(26_214_400));
+        assert_eq!(parse_size("25 mb", SizeUnits::Binary), Ok(26_214_400));
+        assert_eq!(parse_size("500k", SizeUnits::Si), Ok(500_000));
+        assert_eq!(parse_size("1.5G", SizeUnits::Si), Ok(1_500_000_000));
+    }
+
+    #[test]
+    fn test_parse_size_explicit_binary_and_bytes() {
+        assert_eq!(parse_size("8MiB", SizeUnits::Si), Ok(8 * 1024 * 1024));
+        assert_eq!(parse_size("1GiB", SizeUnits::Si), Ok(1 << 30));
+        assert_eq!(parse_size("1234B", SizeUnits::Binary), Ok(1234));
+    }
+
+    #[test]
+    fn test_parse_size_rejects_garbage() {
+        assert!(parse_size("", SizeUnits::Si).is_err());
+        assert!(parse_size("MB", SizeUnits::Si).is_err());
+        assert!(parse_size("25TB", SizeUnits::Si).is_err());
+        assert!(parse_size("-5", SizeUnits::Si).is_err());
+        assert!(parse_size("0", SizeUnits::Si).is_err());
+        assert!(parse_size("1.2.3", SizeUnits::Si).is_err());
+    }
+
+    #[test]
+    fn test_parse_bitrate() {
+        assert_eq!(parse_bitrate("96k"), Ok(96_000));
+        assert_eq!(parse_bitrate("96 kb/s"), Ok(96_000));
+        assert_eq!(parse_bitrate("1.5M"), Ok(1_500_000));
+        assert_eq!(parse_bitrate("128kbps"), Ok(128_000));
+        assert_eq!(parse_bitrate("64000"), Ok(64_000));
+        assert!(parse_bitrate("0k").is_err());
+        assert!(parse_bitrate("96KiB").is_err());
+        assert!(parse_bitrate("fast").is_err());
+    }
+}
@@ -0,0 +1,375 @@
+//! How sizes and counts read on screen, apart from how they are counted.
+//!
+//! Bytes stay exact everywhere else and only become text here. A
+//! [`SizeFormat`] names sizes in MB or MiB per `--display-units` (or
+//! `--si`), whatever `--size-units` read `--size` in, and separates
+//! thousands and decimals the way the locale does, or in a fixed
+//! [`Grouping`]. Sizes show two decimals at most, rounded to the nearest,
+//! except that a size over its limit is rounded up
+//! ([`SizeFormat::size_against`]): an output that missed a 25 MB target by
+//! a few bytes reads `25.01 MB`, never `25 MB`. A size too small to show
+//! in megabytes is shown in kilobytes, or in bytes, rather than as `0 MB`.
+
+use crate::size::SizeUnits;
+use clap::ValueEnum;
+
+/// How digits are grouped and decimals marked, for `--digit-grouping`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Grouping {
+    /// As the locale in `LC_ALL`, `LC_NUMERIC` or `LANG` does.
+    Locale,
+    /// `1,234.5`
+    Comma,
+    /// `1.234,5`
+    Period,
+    /// `1 234,5`
+    Space,
+    /// `1234.5`
+    None,
+}
+
+/// The characters between groups of thousands and before decimals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Separators {
+    pub group: Option<char>,
+    pub decimal: char,
+}
+
+impl Separators {
+    pub const COMMA: Separators = Separators {
+        group: Some(','),
+        decimal: '.',
+    };
+    pub const PERIOD: Separators = Separators {
+        group: Some('.'),
+        decimal: ',',
+    };
+    pub const SPACE: Separators = Separators {
+        group: Some(' '),
+        decimal: ',',
+    };
+    /// For text read by programs, such as the provenance tag.
+    pub const NONE: Separators = Separators {
+        group: None,
+        decimal: '.',
+    };
+
+    /// The separators of `grouping`, with `locale` (such as `de_DE.UTF-8`)
+    /// deciding for [`Grouping::Locale`].
+    pub fn of(grouping: Grouping, locale: Option<&str>) -> Separators {
+        match grouping {
+            Grouping::Locale => locale.map_or(Separators::COMMA, Separators::for_locale),
+            Grouping::Comma => Separators::COMMA,
+            Grouping::Period => Separators::PERIOD,
+            Grouping::Space => Separators::SPACE,
+            Grouping::None => Separators::NONE,
+        }
+    }
+
+    /// The separators a locale name such as `fr_FR.UTF-8` or `de_CH` uses;
+    /// English ones for `C`, `POSIX` and languages not listed.
+    pub fn for_locale(locale: &str) -> Separators {
+        let name = locale.split(['.', '@']).next().unwrap_or_default();
+        let (language, region) = name.split_once(['_', '-']).unwrap_or((name, ""));
+        match (language.to_ascii_lowercase().as_str(), region) {
+            ("de" | "it", "CH") => Separators {
+                group: Some('\''),
+                decimal: '.',
+            },
+            ("es", "MX" | "US") => Separators::COMMA,
+            (
+                "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "hr" | "sl"
+                | "sr" | "vi",
+                _,
+            ) => Separators::PERIOD,
+            (
+                "fr" | "ru" | "sv" | "pl" | "cs" | "sk" | "fi" | "nb" | "nn" | "no" | "uk" | "hu"
+                | "bg" | "lt" | "lv" | "et",
+                _,
+            ) => Separators::SPACE,
+            _ => Separators::COMMA,
+        }
+    }
+}
+
+/// The locale numbers follow: the first of `LC_ALL`, `LC_NUMERIC` and
+/// `LANG` that is set.
+pub fn system_locale() -> Option<String> {
+    ["LC_ALL", "LC_NUMERIC", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Which way a size is rounded to two decimals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rounding {
+    Nearest,
+    Up,
+}
+
+/// How sizes and counts are written for people.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeFormat {
+    pub units: SizeUnits,
+    pub separators: Separators,
+}
+
+impl Default for SizeFormat {
+    fn default() -> Self {
+        SizeUnits::Binary.into()
+    }
+}
+
+/// `units` with [`Separators::COMMA`].
+impl From<SizeUnits> for SizeFormat {
+    fn from(units: SizeUnits) -> Self {
+        SizeFormat::new(units, Separators::COMMA)
+    }
+}
+
+impl SizeFormat {
+    pub fn new(units: SizeUnits, separators: Separators) -> Self {
+        SizeFormat { units, separators }
+    }
+
+    /// `bytes` in megabytes, naming the unit: `25 MB` or `23.84 MiB`.
+    pub fn size(self, bytes: u64) -> String {
+        self.megabytes(bytes, Rounding::Nearest)
+    }
+
+    /// `bytes` as [`size`](Self::size) writes it, but rounded up when it is
+    /// over `limit`, so it never reads as within it.
+    pub fn size_against(self, bytes: u64, limit: u64) -> String {
+        let rounding = if bytes > limit {
+            Rounding::Up
+        } else {
+            Rounding::Nearest
+        };
+        self.megabytes(bytes, rounding)
+    }
+
+    /// `bytes` with exactly one decimal and no separators, in the largest
+    /// of kilo-, mega- and gigabytes it is at least one of: `412.3 MB`,
+    /// `2.5 GB`. For text whose form must not change, such as
+    /// `--summary-line`; over `limit` it is rounded up, as
+    /// [`size_against`](Self::size_against) does.
+    pub fn fixed(self, bytes: u64, limit: Option<u64>) -> String {
+        let power = (2..=3)
+            .rev()
+            .find(|&power| bytes >= self.units.kilo().pow(power))
+            .unwrap_or(1);
+        let rounding = match limit {
+            Some(limit) if bytes > limit => Rounding::Up,
+            _ => Rounding::Nearest,
+        };
+        let tenths = scaled(bytes, self.units.kilo().pow(power), 10, rounding);
+        format!("{}.{} {}", tenths / 10, tenths % 10, self.units.name(power))
+    }
+
+    /// `n` with its thousands grouped: `25,000,000`.
+    pub fn count(self, n: u64) -> String {
+        let digits = n.to_string();
+        let Some(group) = self.separators.group else {
+            return digits;
+        };
+        let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                out.push(group);
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    fn megabytes(self, bytes: u64, rounding: Rounding) -> String {
+        let (kilobyte, megabyte) = (self.units.kilo(), self.units.megabyte());
+        // The largest unit the size shows as at least a hundredth of.
+        let shows_in = |unit: u64| u128::from(bytes) * 200 >= u128::from(unit);
+        let (unit, name) = match self.units {
+            _ if bytes == 0 || shows_in(megabyte) => (megabyte, self.units.name(2)),
+            _ if shows_in(kilobyte) => (kilobyte, self.units.name(1)),
+            _ => return format!("{} B", bytes),
+        };
+        let hundredths = scaled(bytes, unit, 100, rounding);
+        let whole = self.count((hundredths / 100) as u64);
+        let decimals = match hundredths % 100 {
+            0 => String::new(),
+            n if n % 10 == 0 => format!("{}{}", self.separators.decimal, n / 10),
+            n => format!("{}{:02}", self.separators.decimal, n),
+        };
+        format!("{}{} {}", whole, decimals, name)
+    }
+}
+
+/// `bytes` in `unit`s, times `scale`, rounded to a whole number. With
+/// integers, so no value rounds on a float's error.
+fn scaled(bytes: u64, unit: u64, scale: u128, rounding: Rounding) -> u128 {
+    let unit = u128::from(unit);
+    let scaled = u128::from(bytes) * scale;
+    match rounding {
+        Rounding::Nearest => (scaled + unit / 2) / unit,
+        Rounding::Up => scaled.div_ceil(unit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+    const SI: SizeFormat = SizeFormat {
+        units: SizeUnits::Si,
+        separators: Separators::COMMA,
+    };
+    const BINARY: SizeFormat = SizeFormat {
+        units: SizeUnits::Binary,
+        separators: Separators::COMMA,
+    };
+
+    #[test]
+    fn test_size_names_the_unit() {
+        assert_eq!(SI.size(25_000_000), "25 MB");
+        assert_eq!(BINARY.size(100 * MIB), "100 MiB");
+        assert_eq!(BINARY.size(25_000_000), "23.84 MiB");
+        assert_eq!(SI.size(1_500_000), "1.5 MB");
+        assert_eq!(SI.size(0), "0 MB");
+        assert_eq!(SI.size(2_500_000_000), "2,500 MB");
+    }
+
+    #[test]
+    fn test_small_sizes_fall_back_to_smaller_units() {
+        // The stub ffmpeg's 1000-byte outputs.
+        assert_eq!(BINARY.size(1000), "0.98 KiB");
+        assert_eq!(SI.size(1000), "1 KB");
+        // The smallest size megabytes show, and a byte under it.
+        assert_eq!(SI.size(5_000), "0.01 MB");
+        assert_eq!(SI.size(4_999), "5 KB");
+        assert_eq!(BINARY.size(MIB / 200 + 1), "0.01 MiB");
+        assert_eq!(BINARY.size(MIB / 200), "5.12 KiB");
+        // Likewise for kilobytes, below which only bytes are left.
+        assert_eq!(SI.size(5), "0.01 KB");
+        assert_eq!(SI.size(4), "4 B");
+        assert_eq!(BINARY.size(1), "1 B");
+        // A small size over its limit still rounds up, in its own unit.
+        assert_eq!(SI.size_against(1_001, 1_000), "1.01 KB");
+    }
+
+    #[test]
+    fn test_size_rounds_to_the_nearest_hundredth() {
+        // 25.004999 and 25.005 MB.
+        assert_eq!(SI.size(25_004_999), "25 MB");
+        assert_eq!(SI.size(25_005_000), "25.01 MB");
+        // Just under a whole megabyte reads as it.
+        assert_eq!(BINARY.size(MIB - 1), "1 MiB");
+        assert_eq!(SI.size(9_999_999_999), "10,000 MB");
+        // Too large for a float to hold to the byte.
+        assert_eq!(SI.size(u64::MAX), "18,446,744,073,709.55 MB");
+    }
+
+    #[test]
+    fn test_over_the_limit_never_reads_as_within_it() {
+        let limit = 25_000_000;
+        // One byte over is a hundredth over, not "25 MB".
+        assert_eq!(SI.size_against(limit + 1, limit), "25.01 MB");
+        assert_eq!(SI.size_against(limit, limit), "25 MB");
+        assert_eq!(SI.size_against(limit - 1, limit), "25 MB");
+        // A limit that isn't round in the units shown: 8 MiB is 8.39 MB,
+        // and a byte over it still doesn't read less.
+        let limit = 8 * MIB;
+        assert_eq!(SI.size(limit), "8.39 MB");
+        assert_eq!(SI.size_against(limit + 1, limit), "8.39 MB");
+        assert_eq!(SI.size_against(8_390_001, limit), "8.4 MB");
+        // Whatever the sizes, over never shows below the limit's own text,
+        // in whichever units each is shown.
+        for limit in [1, 999_999, 25_004_999, 25_005_000, 8 * MIB, 49_995_000] {
+            for over in [1, 2, 4_999, 5_000, 5_001, 9_999] {
+                for format in [SI, BINARY] {
+                    let shown = format.size_against(limit + over, limit);
+                    let value = |s: &str| {
+                        let (number, unit) = s.split_once(' ').unwrap();
+                        let unit = match unit {
+                            "B" => 1,
+                            "KB" | "KiB" => format.units.kilo(),
+                            _ => format.units.megabyte(),
+                        };
+                        number.replace(',', "").parse::<f64>().unwrap() * unit as f64
+                    };
+                    assert!(
+                        value(&shown) >= value(&format.size(limit)),
+                        "{} over {}",
+                        shown,
+                        limit
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fixed_sizes_keep_one_decimal() {
+        let german = SizeFormat::new(SizeUnits::Si, Separators::PERIOD);
+        // (bytes, limit, SI, binary)
+        let cases = [
+            (412_300_000, None, "412.3 MB", "393.2 MiB"),
+            (2_500_000_000, None, "2.5 GB", "2.3 GiB"),
+            (25_000_000, None, "25.0 MB", "23.8 MiB"),
+            (900_000, None, "900.0 KB", "878.9 KiB"),
+            (0, None, "0.0 KB", "0.0 KiB"),
+            (1_234_567_890_000, None, "1234.6 GB", "1149.8 GiB"),
+            // 25.04 MB over a 25 MB limit isn't 25.0 MB.
+            (25_040_000, Some(25_000_000), "25.1 MB", "23.9 MiB"),
+            (25_040_000, Some(26_000_000), "25.0 MB", "23.9 MiB"),
+            (25_000_001, Some(25_000_000), "25.1 MB", "23.9 MiB"),
+        ];
+        for (bytes, limit, si, binary) in cases {
+            assert_eq!(SI.fixed(bytes, limit), si, "{}", bytes);
+            assert_eq!(BINARY.fixed(bytes, limit), binary, "{}", bytes);
+            // The locale has no say in it.
+            assert_eq!(german.fixed(bytes, limit), si, "{}", bytes);
+        }
+    }
+
+    #[test]
+    fn test_separators_follow_the_locale() {
+        let cases = [
+            ("en_US.UTF-8", "2,500.5 MB"),
+            ("C", "2,500.5 MB"),
+            ("de_DE.UTF-8", "2.500,5 MB"),
+            ("pt_BR", "2.500,5 MB"),
+            ("fr_FR.UTF-8@euro", "2 500,5 MB"),
+            ("sv-SE", "2 500,5 MB"),
+            ("de_CH.UTF-8", "2'500.5 MB"),
+            ("es_MX", "2,500.5 MB"),
+            ("ja_JP.UTF-8", "2,500.5 MB"),
+        ];
+        for (locale, expected) in cases {
+            let format = SizeFormat::new(
+                SizeUnits::Si,
+                Separators::of(Grouping::Locale, Some(locale)),
+            );
+            assert_eq!(format.size(2_500_500_000), expected, "{}", locale);
+        }
+        let fixed = |grouping| {
+            SizeFormat::new(SizeUnits::Si, Separators::of(grouping, Some("de_DE")))
+                .size(2_500_500_000)
+        };
+        assert_eq!(fixed(Grouping::Comma), "2,500.5 MB");
+        assert_eq!(fixed(Grouping::Space), "2 500,5 MB");
+        assert_eq!(fixed(Grouping::None), "2500.5 MB");
+        assert_eq!(Separators::of(Grouping::Locale, None), Separators::COMMA);
+    }
+
+    #[test]
+    fn test_count_groups_thousands() {
+        assert_eq!(SI.count(0), "0");
+        assert_eq!(SI.count(999), "999");
+        assert_eq!(SI.count(25_000_000), "25,000,000");
+        assert_eq!(SI.count(104_857_600), "104,857,600");
+        let period = SizeFormat::new(SizeUnits::Si, Separators::PERIOD);
+        assert_eq!(period.count(104_857_600), "104.857.600");
+        let none = SizeFormat::new(SizeUnits::Si, Separators::NONE);
+        assert_eq!(none.count(104_857_600), "104857600");
+    }
+}
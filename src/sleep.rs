@@ -0,0 +1,258 @@
+//! `--inhibit-sleep`: keep the computer awake while an encode runs.
+//!
+//! A laptop that suspends mid-encode wakes up to a dead ffmpeg and a
+//! partial output. While a [`Hold`] is alive the system is asked not to
+//! sleep or idle-suspend: through `systemd-inhibit` on Linux, `caffeinate`
+//! on macOS and `SetThreadExecutionState` on Windows, each an [`Inhibitor`]
+//! so tests can pretend. Dropping the hold releases it, so a finished,
+//! failed or interrupted encode all let the system sleep again. The helper
+//! processes also let go by themselves if the tool dies without `Drop`
+//! running: `systemd-inhibit` holds the lock only as long as its `cat`
+//! reads our end of a pipe, and `caffeinate -w` watches our process.
+
+use clap::ValueEnum;
+use std::time::Duration;
+
+/// Encodes predicted to take longer than this keep the system awake
+/// without `--inhibit-sleep`.
+pub const AUTO_AFTER: Duration = Duration::from_secs(3 * 60);
+
+/// When to keep the system awake (`--inhibit-sleep`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Policy {
+    /// For encodes predicted to take longer than [`AUTO_AFTER`].
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Policy {
+    /// Whether an encode predicted to take `estimate` keeps the system
+    /// awake.
+    pub fn applies(self, estimate: Duration) -> bool {
+        match self {
+            Policy::Auto => estimate > AUTO_AFTER,
+            Policy::Always => true,
+            Policy::Never => false,
+        }
+    }
+}
+
+/// A way of asking the system not to sleep.
+pub trait Inhibitor {
+    /// What it is called, for the status output.
+    fn name(&self) -> &'static str;
+    /// Asks the system to stay awake, giving `why`.
+    fn acquire(&mut self, why: &str) -> Result<(), String>;
+    /// Lets the system sleep again. Called once, after a successful
+    /// [`acquire`](Inhibitor::acquire).
+    fn release(&mut self);
+}
+
+/// The system kept awake; dropping it lets the system sleep again.
+pub struct Hold {
+    inhibitor: Box<dyn Inhibitor>,
+}
+
+impl Hold {
+    /// Acquires `inhibitor` with `why`; the error says why it couldn't.
+    pub fn take(mut inhibitor: Box<dyn Inhibitor>, why: &str) -> Result<Hold, String> {
+        inhibitor
+            .acquire(why)
+            .map_err(|e| format!("{}: {}", inhibitor.name(), e))?;
+        Ok(Hold { inhibitor })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.inhibitor.name()
+    }
+}
+
+impl Drop for Hold {
+    fn drop(&mut self) {
+        self.inhibitor.release();
+    }
+}
+
+/// The inhibitor of the platform the tool runs on.
+pub fn platform() -> Box<dyn Inhibitor> {
+    if cfg!(target_os = "macos") {
+        let pid = std::process::id().to_string();
+        Box::new(Helper::new(
+            "caffeinate",
+            &["-i", "-w", &pid],
+            HelperWhy::Ignored,
+        ))
+    } else if cfg!(windows) {
+        Box::new(ExecutionState)
+    } else if cfg!(target_os = "linux") {
+        Box::new(Helper::new(
+            "systemd-inhibit",
+            &["--what=sleep:idle", "--who=mdviqure", "--mode=block", "cat"],
+            HelperWhy::Argument("--why="),
+        ))
+    } else {
+        Box::new(Unsupported)
+    }
+}
+
+/// Whether a helper command is told why.
+#[derive(Debug, Clone, Copy)]
+enum HelperWhy {
+    Ignored,
+    /// As this option, glued to the reason and put first.
+    Argument(&'static str),
+}
+
+/// A program that keeps the system awake until it is killed.
+struct Helper {
+    program: &'static str,
+    args: Vec<String>,
+    why: HelperWhy,
+    child: Option<std::process::Child>,
+}
+
+impl Helper {
+    fn new(program: &'static str, args: &[&str], why: HelperWhy) -> Self {
+        Self {
+            program,
+            args: args.iter().map(|a| a.to_string()).collect(),
+            why,
+            child: None,
+        }
+    }
+}
+
+impl Inhibitor for Helper {
+    fn name(&self) -> &'static str {
+        self.program
+    }
+
+    fn acquire(&mut self, why: &str) -> Result<(), String> {
+        use std::process::{Command, Stdio};
+        let mut command = Command::new(self.program);
+        if let HelperWhy::Argument(option) = self.why {
+            command.arg(format!("{}{}", option, why));
+        }
+        let mut child = command
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        // One that can't take the lock (no logind, say) gives up at once.
+        std::thread::sleep(Duration::from_millis(50));
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(format!("exited with {}", status));
+        }
+        self.child = Some(child);
+        Ok(())
+    }
+
+    fn release(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// `SetThreadExecutionState`, which holds as long as the thread that
+/// called it keeps the state; [`Hold`] is released on the thread that took
+/// it, since it isn't `Send`.
+struct ExecutionState;
+
+#[cfg(windows)]
+mod win32 {
+    pub const ES_CONTINUOUS: u32 = 0x8000_0000;
+    pub const ES_SYSTEM_REQUIRED: u32 = 0x0000_0001;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn SetThreadExecutionState(flags: u32) -> u32;
+    }
+}
+
+impl Inhibitor for ExecutionState {
+    fn name(&self) -> &'static str {
+        "SetThreadExecutionState"
+    }
+
+    #[cfg(windows)]
+    fn acquire(&mut self, _why: &str) -> Result<(), String> {
+        // SAFETY: takes and returns plain flags.
+        let previous = unsafe {
+            win32::SetThreadExecutionState(win32::ES_CONTINUOUS | win32::ES_SYSTEM_REQUIRED)
+        };
+        if previous == 0 {
+            return Err("the call failed".into());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    fn acquire(&mut self, _why: &str) -> Result<(), String> {
+        Err("only on Windows".into())
+    }
+
+    fn release(&mut self) {
+        // SAFETY: as above.
+        #[cfg(windows)]
+        let _ = unsafe { win32::SetThreadExecutionState(win32::ES_CONTINUOUS) };
+    }
+}
+
+/// Platforms without a known way to stay awake.
+struct Unsupported;
+
+impl Inhibitor for Unsupported {
+    fn name(&self) -> &'static str {
+        "no inhibitor"
+    }
+
+    fn acquire(&mut self, _why: &str) -> Result<(), String> {
+        Err("not supported on this platform".into())
+    }
+
+    fn release(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockInhibitor;
+
+    #[test]
+    fn test_policy_follows_the_estimate() {
+        let short = Duration::from_secs(60);
+        let long = AUTO_AFTER + Duration::from_secs(1);
+        assert!(!Policy::Auto.applies(short));
+        assert!(!Policy::Auto.applies(AUTO_AFTER));
+        assert!(Policy::Auto.applies(long));
+        assert!(Policy::Always.applies(short));
+        assert!(!Policy::Never.applies(long));
+    }
+
+    #[test]
+    fn test_hold_releases_once_when_dropped() {
+        let mock = MockInhibitor::default();
+        let hold = Hold::take(Box::new(mock.clone()), "Encoding a.mp4").unwrap();
+        assert_eq!(hold.name(), "mock");
+        assert_eq!(mock.events(), ["acquire Encoding a.mp4"]);
+        drop(hold);
+        assert_eq!(mock.events(), ["acquire Encoding a.mp4", "release"]);
+    }
+
+    #[test]
+    fn test_a_failed_acquire_holds_nothing() {
+        let mut mock = MockInhibitor::default();
+        mock.unavailable = true;
+        let err = Hold::take(Box::new(mock.clone()), "Encoding")
+            .err()
+            .unwrap();
+        assert_eq!(err, "mock: no inhibitor running");
+        assert_eq!(mock.events(), ["acquire Encoding"]);
+    }
+}
@@ -0,0 +1,338 @@
+//! `--extract-subs`: the input's subtitle streams as files next to the
+//! output.
+//!
+//! A re-encode keeps only video and audio, so an MKV's subtitles would
+//! otherwise be lost on the way to MP4. Each subtitle stream is written on
+//! its own, as `<output stem>.<language>.<ext>`, in the [`Format`] its
+//! codec fits: plain text becomes SubRip, which every player reads; ASS
+//! stays ASS, since SubRip has no room for its styling and positioning;
+//! bitmap subtitles are copied into the one container each has. A stream
+//! that doesn't fit any of them, or fails to extract, is warned about and
+//! the run goes on.
+
+use crate::breakdown::{FileStreams, StreamEntry, StreamKind};
+use crate::commit;
+use crate::error::ReduceError;
+use crate::interrupt;
+use crate::longpath;
+use crate::presenter::Presenter;
+use crate::tempdir::RunTempDir;
+use crate::tool::VideoTool;
+use crate::warning::{self, Code, Warning};
+use std::path::Path;
+
+/// How a subtitle stream is written as a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    /// The `-c:s` value: an encoder, or `copy`.
+    pub codec: &'static str,
+    /// The `-f` muxer.
+    pub muxer: &'static str,
+    pub extension: &'static str,
+}
+
+const SRT: Format = Format {
+    codec: "srt",
+    muxer: "srt",
+    extension: "srt",
+};
+
+/// The format a subtitle stream of `codec` is written in; `None` for
+/// codecs no sidecar file holds, such as teletext.
+pub fn format_for(codec: &str) -> Option<Format> {
+    let format = match codec {
+        "subrip" | "mov_text" | "webvtt" | "text" => SRT,
+        "ass" => Format {
+            codec: "copy",
+            muxer: "ass",
+            extension: "ass",
+        },
+        // ffmpeg reads SSA as ASS, which the muxer writes.
+        "ssa" => Format {
+            codec: "ass",
+            muxer: "ass",
+            extension: "ass",
+        },
+        "hdmv_pgs_subtitle" => Format {
+            codec: "copy",
+            muxer: "sup",
+            extension: "sup",
+        },
+        // DVD and DVB bitmaps have no file format of their own.
+        "dvd_subtitle" | "dvb_subtitle" => Format {
+            codec: "copy",
+            muxer: "matroska",
+            extension: "mks",
+        },
+        _ => return None,
+    };
+    Some(format)
+}
+
+/// A subtitle stream and the file it goes into.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extraction<'a> {
+    pub stream: &'a StreamEntry,
+    pub format: Format,
+    pub path: String,
+}
+
+/// What each subtitle stream of `streams` becomes next to `output`, in
+/// stream order; streams without a [`format_for`] their codec are the
+/// `Err`s. The name is `<stem>.<language>.<ext>`, without the language
+/// when it is unknown, and with `.2`, `.3` and on before the extension for
+/// later streams that would take the same name.
+pub fn plan<'a>(
+    output: &str,
+    streams: &'a FileStreams,
+) -> Vec<Result<Extraction<'a>, &'a StreamEntry>> {
+    let stem = Path::new(output).with_extension("");
+    let stem = stem.to_string_lossy();
+    let mut taken: Vec<String> = Vec::new();
+    streams
+        .streams
+        .iter()
+        .filter(|s| s.kind == StreamKind::Subtitle)
+        .map(|stream| {
+            let format = format_for(&stream.codec).ok_or(stream)?;
+            let mut base = stem.to_string();
+            if let Some(language) = language(stream) {
+                base = format!("{}.{}", base, language);
+            }
+            let path = (1..)
+                .map(|n| match n {
+                    1 => format!("{}.{}", base, format.extension),
+                    n => format!("{}.{}.{}", base, n, format.extension),
+                })
+                .find(|path| !taken.contains(path))
+                .expect("some number is free");
+            taken.push(path.clone());
+            Ok(Extraction {
+                stream,
+                format,
+                path,
+            })
+        })
+        .collect()
+}
+
+/// The stream's language tag as it goes into a file name; `None` when
+/// missing or `und`.
+fn language(stream: &StreamEntry) -> Option<String> {
+    let language: String = stream
+        .language
+        .as_deref()?
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect::<String>()
+        .to_ascii_lowercase();
+    (!language.is_empty() && language != "und").then_some(language)
+}
+
+/// The ffmpeg arguments writing stream `index` of `input` to
+/// `destination` in `format`.
+pub fn extract_args(input: &str, index: u32, format: Format, destination: &str) -> Vec<String> {
+    let mut args: Vec<String> = ["-y", "-v", "error", "-i"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    args.push(longpath::for_tool(input));
+    args.extend([
+        "-map".to_string(),
+        format!("0:{}", index),
+        "-c:s".to_string(),
+        format.codec.to_string(),
+        "-f".to_string(),
+        format.muxer.to_string(),
+    ]);
+    args.push(longpath::for_tool(destination));
+    args
+}
+
+/// Writes the subtitle streams of `input` next to `output` as [`plan`]
+/// names them, each into `run_dir` first, and returns the paths written.
+/// Only an error that would stop any ffmpeg run, such as a missing ffmpeg
+/// or an interrupt, fails it; anything else is warned about per stream.
+pub fn extract<T: VideoTool>(
+    tool: &T,
+    input: &str,
+    output: &str,
+    run_dir: &RunTempDir,
+    out: Presenter,
+) -> Result<Vec<String>, ReduceError> {
+    let streams = tool.get_streams(input)?;
+    let mut written = Vec::new();
+    for planned in plan(output, &streams) {
+        let extraction = match planned {
+            Ok(extraction) => extraction,
+            Err(stream) => {
+                let message = format!(
+                    "subtitle stream #{} ({}) has no file format to extract it into and is left out",
+                    stream.index, stream.codec
+                );
+                warning::emit(out, Warning::new(Code::SubtitleNotExtracted, message));
+                continue;
+            }
+        };
+        let stream = extraction.stream;
+        let partial = run_dir.artifact(
+            &format!("subtitle{}", stream.index),
+            Some(extraction.format.extension),
+        );
+        let args = extract_args(
+            input,
+            stream.index,
+            extraction.format,
+            &partial.to_string_lossy(),
+        );
+        let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        let result = tool.run_ffmpeg(&args);
+        if interrupt::is_interrupted() {
+            return Err(ReduceError::Interrupted);
+        }
+        let failure = match result {
+            Ok(()) => commit::commit(tool, &partial, Path::new(&extraction.path), None)
+                .err()
+                .map(|e| e.to_string()),
+            Err(ReduceError::Encode(message)) => Some(message),
+            Err(other) => return Err(other),
+        };
+        match failure {
+            None => written.push(extraction.path),
+            Some(reason) => {
+                let message = format!(
+                    "cannot extract subtitle stream #{} ({}) to {}: {}",
+                    stream.index, stream.codec, extraction.path, reason
+                );
+                warning::emit(out, Warning::new(Code::SubtitleNotExtracted, message));
+            }
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::breakdown::parse_streams;
+    use crate::presenter::ColorChoice;
+    use crate::testing::{arg_value, MockVideoTool, TestDir};
+
+    /// An MKV with subtitles in five codecs, two of them in English.
+    const SUBTITLED_MKV: &str = r#"{
+        "format": {"size": "50000000", "duration": "1440.0"},
+        "streams": [
+            {"index": 0, "codec_name": "h264", "codec_type": "video"},
+            {"index": 1, "codec_name": "aac", "codec_type": "audio", "tags": {"language": "jpn"}},
+            {"index": 2, "codec_name": "ass", "codec_type": "subtitle", "tags": {"language": "eng"}},
+            {"index": 3, "codec_name": "subrip", "codec_type": "subtitle", "tags": {"language": "eng"}},
+            {"index": 4, "codec_name": "subrip", "codec_type": "subtitle", "tags": {"language": "ENG"}},
+            {"index": 5, "codec_name": "hdmv_pgs_subtitle", "codec_type": "subtitle", "tags": {"language": "und"}},
+            {"index": 6, "codec_name": "dvb_teletext", "codec_type": "subtitle", "tags": {"language": "ger"}},
+            {"index": 7, "codec_name": "webvtt", "codec_type": "subtitle"},
+            {"index": 8, "codec_name": "ttf", "codec_type": "attachment", "extradata_size": 250000}
+        ]
+    }"#;
+
+    #[test]
+    fn test_formats_follow_the_codec() {
+        let cases = [
+            ("subrip", Some(("srt", "srt", "srt"))),
+            ("mov_text", Some(("srt", "srt", "srt"))),
+            ("webvtt", Some(("srt", "srt", "srt"))),
+            ("ass", Some(("copy", "ass", "ass"))),
+            ("ssa", Some(("ass", "ass", "ass"))),
+            ("hdmv_pgs_subtitle", Some(("copy", "sup", "sup"))),
+            ("dvd_subtitle", Some(("copy", "matroska", "mks"))),
+            ("dvb_subtitle", Some(("copy", "matroska", "mks"))),
+            ("dvb_teletext", None),
+            ("eia_608", None),
+        ];
+        for (codec, expected) in cases {
+            let format = format_for(codec).map(|f| (f.codec, f.muxer, f.extension));
+            assert_eq!(format, expected, "{}", codec);
+        }
+    }
+
+    #[test]
+    fn test_names_carry_the_language() {
+        let streams = parse_streams(SUBTITLED_MKV).unwrap();
+        let planned: Vec<Result<(u32, String), u32>> = plan("dir/movie.v2.mp4", &streams)
+            .into_iter()
+            .map(|p| p.map(|e| (e.stream.index, e.path)).map_err(|s| s.index))
+            .collect();
+        assert_eq!(
+            planned,
+            [
+                Ok((2, "dir/movie.v2.eng.ass".to_string())),
+                Ok((3, "dir/movie.v2.eng.srt".to_string())),
+                // Same language and format as the one before.
+                Ok((4, "dir/movie.v2.eng.2.srt".to_string())),
+                Ok((5, "dir/movie.v2.sup".to_string())),
+                Err(6),
+                Ok((7, "dir/movie.v2.srt".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_args_map_one_stream() {
+        let args = extract_args("in.mkv", 3, SRT, "/tmp/run/subtitle3.srt");
+        assert_eq!(
+            args.join(" "),
+            "-y -v error -i in.mkv -map 0:3 -c:s srt -f srt /tmp/run/subtitle3.srt"
+        );
+    }
+
+    #[test]
+    fn test_extract_warns_per_stream_and_goes_on() {
+        let dir = TestDir::new();
+        let run_dir = RunTempDir::create(Some(dir.path()), false).unwrap();
+        let mut tool = MockVideoTool::new(1440.0);
+        tool.streams = parse_streams(SUBTITLED_MKV).unwrap();
+        // The first stream, the ASS one, fails.
+        tool.transient_ffmpeg_failures.set(1);
+        let output = dir.join("movie.mp4");
+        warning::take();
+
+        let written = extract(
+            &tool,
+            "in.mkv",
+            &output,
+            &run_dir,
+            Presenter::stderr(ColorChoice::Never),
+        )
+        .unwrap();
+        assert_eq!(
+            written,
+            [
+                dir.join("movie.eng.srt"),
+                dir.join("movie.eng.2.srt"),
+                dir.join("movie.sup"),
+                dir.join("movie.srt"),
+            ]
+        );
+        for path in &written {
+            assert!(Path::new(path).is_file(), "{}", path);
+        }
+        let calls = tool.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 5);
+        assert_eq!(arg_value(&calls[0], "-map"), Some("0:2"));
+        assert!(calls.iter().all(|call| call
+            .last()
+            .unwrap()
+            .starts_with(run_dir.path().to_str().unwrap())));
+
+        let warnings = warning::take();
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings[0]
+            .message
+            .starts_with("cannot extract subtitle stream #2 (ass)"));
+        assert!(warnings[1]
+            .message
+            .starts_with("subtitle stream #6 (dvb_teletext) has no file format"));
+        assert!(warnings
+            .iter()
+            .all(|w| w.code == Code::SubtitleNotExtracted));
+    }
+}
@@ -0,0 +1,365 @@
+//! `--summary-line`: how a run went, in one line to paste into a commit
+//! message or a chat.
+//!
+//! ```text
+//! demo.mp4: 412.3 MB → 49.6 MB (-88%) h264 1080p→720p 2:13 elapsed
+//! demo.mp4: 412.3 MB → failed (over target, exit 6)
+//! ```
+//!
+//! The format is stable, so [`line()`] writes it the same way wherever it
+//! goes, whatever the terminal could show: the input's file name, shortened
+//! in the middle past [`NAME_WIDTH`] characters; its size and the size
+//! written, with one decimal in the run's units as [`SizeFormat::fixed`]
+//! writes them (an output over its target rounded up, so that it never
+//! reads as the target) and `?` for an input that isn't a file; the change
+//! in percent, except for a sample; then the codec (`copy` for a copied
+//! video, `remux` when nothing was encoded), the frame as its shorter side
+//! and, when it changed, the frame rate, with `→` to what the output got;
+//! `in N parts`, `sample M:SS` and the time taken, when there are any. A
+//! failure gives what went wrong and the exit code instead.
+
+use crate::error::ReduceError;
+use crate::estimate::format_duration;
+use crate::presenter::truncate_middle;
+use crate::reduce::ReduceReport;
+use crate::sidecar::{Settings, SourceSummary};
+use crate::sizefmt::SizeFormat;
+use crate::url;
+use std::path::Path;
+
+/// The longest file name the line shows whole.
+pub const NAME_WIDTH: usize = 40;
+
+/// How far apart two frame rates may be and still count as the same.
+const FPS_TOLERANCE: f64 = 0.01;
+
+/// The line for a run of `input`, which was `source_bytes` when it started,
+/// that ended with `result`, having written `output_bytes` of the
+/// `target_bytes` all of its output was to come within; sizes are shown in
+/// the units of `sizes`.
+pub fn line(
+    input: &str,
+    source_bytes: Option<u64>,
+    output_bytes: Option<u64>,
+    result: Result<&ReduceReport, &ReduceError>,
+    sizes: SizeFormat,
+    target_bytes: u64,
+) -> String {
+    let source = source_bytes.map_or("?".to_string(), |bytes| sizes.fixed(bytes, None));
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            return format!(
+                "{}: {} → failed ({}, exit {})",
+                name(input),
+                source,
+                e.label(),
+                e.exit_code()
+            )
+        }
+    };
+    let settings = report.recipe.as_ref().and_then(|r| r.settings.as_ref());
+    // A sample is no measure of what the whole input comes to.
+    let sample = settings.and_then(|s| s.sample);
+    let limit = Some(target_bytes).filter(|_| sample.is_none());
+    let mut line = match output_bytes {
+        Some(written) => format!(
+            "{}: {} → {}",
+            name(input),
+            source,
+            sizes.fixed(written, limit)
+        ),
+        None => return format!("{}: {} → nothing written (dry run)", name(input), source),
+    };
+    if let (Some(from), Some(to), None) = (source_bytes.filter(|&b| b > 0), output_bytes, sample) {
+        let change = (to as f64 / from as f64 - 1.0) * 100.0;
+        line += &format!(" ({:+}%)", change.round() as i64);
+    }
+    if let Some(recipe) = &report.recipe {
+        line += &format!(" {} {}", codec(settings), frame(&recipe.source, settings));
+        if let Some(settings) = settings {
+            if settings.parts > 1 {
+                line += &format!(" in {} parts", settings.parts);
+            }
+            if let Some(sample) = sample {
+                line += &format!(" sample {}", format_duration(sample));
+            }
+        }
+    }
+    if let Some(usage) = &report.usage {
+        line += &format!(" {} elapsed", format_duration(usage.wall_s));
+    }
+    line
+}
+
+/// The input as the line names it: its file name, shortened to fit.
+fn name(input: &str) -> String {
+    let name = if input == crate::STDIO_PATH {
+        "stdin"
+    } else if url::is_url(input) {
+        url::file_name(input).unwrap_or(input)
+    } else {
+        Path::new(input)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(input)
+    };
+    truncate_middle(name, NAME_WIDTH)
+}
+
+/// The codec the video was written with.
+fn codec(settings: Option<&Settings>) -> &str {
+    match settings {
+        None => "remux",
+        Some(settings) if settings.video_bitrate.is_none() => "copy",
+        Some(settings) => match settings.encoder.as_str() {
+            "libx264" => "h264",
+            "libsvtav1" => "av1",
+            other => other,
+        },
+    }
+}
+
+/// The source frame, and what the output made of it when that differs.
+fn frame(source: &SourceSummary, settings: Option<&Settings>) -> String {
+    let from = (source.width.min(source.height), source.fps);
+    let Some(settings) = settings.filter(|s| s.video_bitrate.is_some()) else {
+        return frame_label(from.0, from.1, false);
+    };
+    let to = (settings.width.min(settings.height), Some(settings.fps));
+    let fps_changed = matches!(
+        (from.1, to.1),
+        (Some(a), Some(b)) if (a - b).abs() > FPS_TOLERANCE
+    );
+    if from.0 == to.0 && !fps_changed {
+        return frame_label(from.0, from.1, false);
+    }
+    format!(
+        "{}→{}",
+        frame_label(from.0, from.1, fps_changed),
+        frame_label(to.0, to.1, fps_changed)
+    )
+}
+
+/// `720p`, or `720p30` with the frame rate.
+fn frame_label(lines: u32, fps: Option<f64>, with_fps: bool) -> String {
+    match fps.filter(|_| with_fps) {
+        Some(fps) if (fps - fps.round()).abs() < FPS_TOLERANCE => {
+            format!("{}p{}", lines, fps.round())
+        }
+        Some(fps) => format!("{}p{}", lines, format!("{:.2}", fps).trim_end_matches('0')),
+        None => format!("{}p", lines),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sidecar::Recipe;
+    use crate::size::SizeUnits;
+    use crate::sizefmt::Separators;
+    use crate::usage::Usage;
+
+    const MB: u64 = 1_000_000;
+    const SI: SizeFormat = SizeFormat {
+        units: SizeUnits::Si,
+        separators: Separators::COMMA,
+    };
+    const BINARY: SizeFormat = SizeFormat {
+        units: SizeUnits::Binary,
+        separators: Separators::COMMA,
+    };
+
+    fn reduced(source: (u32, u32, f64), settings: Option<Settings>) -> ReduceReport {
+        ReduceReport {
+            recipe: Some(Recipe {
+                source: SourceSummary {
+                    width: source.0,
+                    height: source.1,
+                    fps: Some(source.2),
+                    ..SourceSummary::default()
+                },
+                settings,
+                ..Recipe::default()
+            }),
+            usage: Some(Usage {
+                wall_s: 133.4,
+                cpu_s: None,
+                peak_rss_bytes: None,
+            }),
+            ..ReduceReport::default()
+        }
+    }
+
+    fn encoded(width: u32, height: u32, fps: f64) -> Settings {
+        Settings {
+            encoder: "libx264".to_string(),
+            video_bitrate: Some(2_000_000),
+            width,
+            height,
+            fps,
+            parts: 1,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn test_lines_in_their_documented_form() {
+        let downscaled = reduced((1920, 1080, 30.0), Some(encoded(1280, 720, 30.0)));
+        let slowed = reduced((1920, 1080, 59.94), Some(encoded(1280, 720, 30.0)));
+        let kept = reduced((1280, 720, 29.97), Some(encoded(1280, 720, 29.97)));
+        let av1 = reduced(
+            (1080, 1920, 30.0),
+            Some(Settings {
+                encoder: "libsvtav1".to_string(),
+                parts: 3,
+                ..encoded(720, 1280, 24.0)
+            }),
+        );
+        let sampled = reduced(
+            (1280, 720, 30.0),
+            Some(Settings {
+                sample: Some(10.0),
+                ..encoded(1280, 720, 30.0)
+            }),
+        );
+        let copied = reduced(
+            (1280, 720, 30.0),
+            Some(Settings {
+                video_bitrate: None,
+                ..encoded(1280, 720, 30.0)
+            }),
+        );
+        let remuxed = reduced((1280, 720, 30.0), None);
+        let source = Some(412_300_000);
+        // (input, source bytes, written, report, line)
+        let cases = [
+            (
+                "videos/demo.mp4",
+                source,
+                Some(49_600_000),
+                &downscaled,
+                "demo.mp4: 412.3 MB → 49.6 MB (-88%) h264 1080p→720p 2:13 elapsed",
+            ),
+            (
+                "demo.mp4",
+                source,
+                Some(49_600_000),
+                &slowed,
+                "demo.mp4: 412.3 MB → 49.6 MB (-88%) h264 1080p59.94→720p30 2:13 elapsed",
+            ),
+            (
+                "demo.mp4",
+                Some(8 * MB),
+                Some(9 * MB),
+                &kept,
+                "demo.mp4: 8.0 MB → 9.0 MB (+13%) h264 720p 2:13 elapsed",
+            ),
+            (
+                "https://cdn.example.com/v/phone.mov?sig=secret",
+                None,
+                Some(2_500_000_000),
+                &av1,
+                "phone.mov: ? → 2.5 GB av1 1080p30→720p24 in 3 parts 2:13 elapsed",
+            ),
+            (
+                "demo.mp4",
+                source,
+                Some(1_200_000),
+                &sampled,
+                "demo.mp4: 412.3 MB → 1.2 MB h264 720p sample 0:10 2:13 elapsed",
+            ),
+            (
+                "demo.mp4",
+                Some(20 * MB),
+                Some(19 * MB),
+                &copied,
+                "demo.mp4: 20.0 MB → 19.0 MB (-5%) copy 720p 2:13 elapsed",
+            ),
+            (
+                "demo.mp4",
+                Some(20 * MB),
+                Some(19 * MB),
+                &remuxed,
+                "demo.mp4: 20.0 MB → 19.0 MB (-5%) remux 720p 2:13 elapsed",
+            ),
+            (
+                "demo.mp4",
+                source,
+                None,
+                &ReduceReport::default(),
+                "demo.mp4: 412.3 MB → nothing written (dry run)",
+            ),
+            (
+                "a holiday video with a name far too long to show whole.mp4",
+                Some(900_000),
+                Some(400_000),
+                &ReduceReport::default(),
+                "a holiday video wit…ng to show whole.mp4: 900.0 KB → 400.0 KB (-56%)",
+            ),
+        ];
+        for (input, source, written, report, expected) in cases {
+            assert_eq!(
+                line(input, source, written, Ok(report), SI, 500 * MB),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_an_output_just_over_the_target_never_reads_as_the_target() {
+        let report = reduced((1920, 1080, 30.0), Some(encoded(1280, 720, 30.0)));
+        assert_eq!(
+            line(
+                "demo.mp4",
+                Some(412_300_000),
+                Some(25_040_000),
+                Ok(&report),
+                SI,
+                25 * MB
+            ),
+            "demo.mp4: 412.3 MB → 25.1 MB (-94%) h264 1080p→720p 2:13 elapsed"
+        );
+        // Within the target, it is rounded as any other size.
+        assert_eq!(
+            line(
+                "demo.mp4",
+                Some(412_300_000),
+                Some(24_960_000),
+                Ok(&report),
+                SI,
+                25 * MB
+            ),
+            "demo.mp4: 412.3 MB → 25.0 MB (-94%) h264 1080p→720p 2:13 elapsed"
+        );
+    }
+
+    #[test]
+    fn test_failures_name_what_went_wrong() {
+        let over = ReduceError::OverTarget {
+            actual_bytes: 60 * MB,
+            target_bytes: 50 * MB,
+            attempts: 3,
+        };
+        assert_eq!(
+            line("demo.mp4", Some(412_300_000), None, Err(&over), SI, 50 * MB),
+            "demo.mp4: 412.3 MB → failed (over target, exit 6)"
+        );
+        let probe = ReduceError::Probe("moov atom not found".to_string());
+        assert_eq!(
+            line("-", None, None, Err(&probe), BINARY, 50 * MB),
+            "stdin: ? → failed (probe failed, exit 4)"
+        );
+        assert_eq!(
+            line(
+                "demo.mp4",
+                Some(52_428_800),
+                None,
+                Err(&ReduceError::Interrupted),
+                BINARY,
+                50 * MB
+            ),
+            "demo.mp4: 50.0 MiB → failed (interrupted, exit 7)"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Per-run directory for intermediate artifacts (partial outputs, pass logs, ...).
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Prefix shared by every run directory, so stale ones are easy to spot.
+pub const PREFIX: &str = "mdviqure-";
+
+/// A uniquely named directory that is removed when dropped, unless kept.
+///
+/// Dropping covers success, error returns and unwinding alike, so every exit
+/// path of a run cleans up after itself.
+#[derive(Debug)]
+pub struct RunTempDir {
+    path: PathBuf,
+    /// The run's unique token, also embedded in the artifact names so files
+    /// of concurrent runs never collide, even when moved side by side.
+    token: String,
+    keep: bool,
+}
+
+impl RunTempDir {
+    /// Creates a new run directory under `base`, or the system temp dir
+    /// (which honors `TMPDIR`) when `base` is `None`.
+    pub fn create(base: Option<&Path>, keep: bool) -> io::Result<Self> {
+        let base = base
+            .map(Path::to_path_buf)
+            .unwrap_or_else(std::env::temp_dir);
+        // Retry on the (unlikely) event that another instance picked the same name.
+        for _ in 0..16 {
+            let token = unique_token();
+            let path = base.join(format!("{}{}-{}", PREFIX, std::process::id(), token));
+            match std::fs::create_dir(&path) {
+                Ok(()) => return Ok(Self { path, token, keep }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("cannot create temp dir in {}: {}", base.display(), e),
+                    ))
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "could not find an unused temp dir name",
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path for an artifact named `name` inside the run directory.
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// Path for the artifact `stem`, tagged with the run's token, with the
+    /// extension `ext` if any.
+    pub fn artifact(&self, stem: &str, ext: Option<&str>) -> PathBuf {
+        match ext {
+            Some(ext) => self.file(&format!("{}-{}.{}", stem, self.token, ext)),
+            None => self.file(&format!("{}-{}", stem, self.token)),
+        }
+    }
+
+    /// Prefix for ffmpeg's `-passlogfile`. Passing it explicitly keeps the
+    /// pass logs out of the working directory, where ffmpeg's default
+    /// `ffmpeg2pass-0.log` would be shared by every run started there.
+    pub fn passlog_prefix(&self) -> PathBuf {
+        self.artifact("ffmpeg2pass", None)
+    }
+
+    pub fn is_kept(&self) -> bool {
+        self.keep
+    }
+
+    /// Deletes the directory and everything in it now, even with
+    /// `--keep-temp`, to give back the space of a run that ran out of it.
+    pub fn clear(&self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+impl Drop for RunTempDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Returns a short random hex token, distinct across calls and concurrent processes.
+pub fn unique_token() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    // RandomState is seeded from OS randomness, so two processes started in
+    // the same nanosecond still diverge.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(nanos);
+    hasher.write_u32(std::process::id());
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    format!("{:08x}", hasher.finish() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_run_dir_is_removed_on_drop() {
+        let base = TestDir::new();
+        let dir = RunTempDir::create(Some(base.path()), false).unwrap();
+        let path = dir.path().to_path_buf();
+        std::fs::write(dir.file("partial.mp4"), b"data").unwrap();
+        assert!(path.starts_with(base.path()));
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_kept_run_dir_survives_drop() {
+        let base = TestDir::new();
+        let dir = RunTempDir::create(Some(base.path()), true).unwrap();
+        let path = dir.path().to_path_buf();
+        drop(dir);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_run_dirs_for_same_process_do_not_collide() {
+        let base = TestDir::new();
+        let a = RunTempDir::create(Some(base.path()), false).unwrap();
+        let b = RunTempDir::create(Some(base.path()), false).unwrap();
+        assert_ne!(a.path(), b.path());
+        let name = a.path().file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with(PREFIX));
+        assert!(name.contains(&std::process::id().to_string()));
+    }
+
+    #[test]
+    fn test_artifacts_carry_the_run_token() {
+        let base = TestDir::new();
+        let a = RunTempDir::create(Some(base.path()), false).unwrap();
+        let b = RunTempDir::create(Some(base.path()), false).unwrap();
+        let partial = a.artifact("partial", Some("mp4"));
+        let name = partial.file_name().unwrap().to_string_lossy().to_string();
+        assert!(partial.starts_with(a.path()));
+        assert!(name.starts_with("partial-") && name.ends_with(".mp4"));
+        assert_ne!(name, "partial-.mp4");
+        assert_ne!(
+            name,
+            b.artifact("partial", Some("mp4"))
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+        );
+        assert!(a.passlog_prefix().starts_with(a.path()));
+        assert_ne!(a.passlog_prefix(), b.passlog_prefix());
+    }
+
+    #[test]
+    fn test_missing_base_dir_is_reported() {
+        let base = TestDir::new();
+        let err = RunTempDir::create(Some(&base.path().join("missing")), false).unwrap_err();
+        assert!(err.to_string().contains("cannot create temp dir"));
+    }
+}
@@ -0,0 +1,233 @@
+//! Output naming templates for batch mode (`--name-template` and
+//! placeholders in `--output-dir`), e.g. `{date}_{stem}_{size}MB.{ext}`.
+//!
+//! `{{` and `}}` stand for literal braces.
+
+/// A value a template can refer to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// Input file name without its extension.
+    Stem,
+    /// Input extension, without the dot.
+    Ext,
+    /// Target size in megabytes.
+    Size,
+    /// Input modification date, `YYYY-MM-DD`.
+    Date,
+    Year,
+    Month,
+    Day,
+    /// Recording date, `YYYY-MM-DD`; see [`crate::archive`].
+    Recorded,
+    /// Source frame size in pixels.
+    Width,
+    Height,
+}
+
+const FIELDS: [(&str, Field); 10] = [
+    ("stem", Field::Stem),
+    ("ext", Field::Ext),
+    ("size", Field::Size),
+    ("date", Field::Date),
+    ("year", Field::Year),
+    ("month", Field::Month),
+    ("day", Field::Day),
+    ("recorded", Field::Recorded),
+    ("width", Field::Width),
+    ("height", Field::Height),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Field(Field),
+}
+
+/// A parsed naming template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+/// What the placeholders of one input render to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Values {
+    pub stem: String,
+    pub ext: String,
+    pub size: String,
+    /// Modification date as (year, month, day).
+    pub date: (i64, u32, u32),
+    /// Recording date as (year, month, day).
+    pub recorded: (i64, u32, u32),
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed '{{{}' in '{}'", name, text)),
+                        }
+                    }
+                    let field = FIELDS
+                        .iter()
+                        .find(|(n, _)| *n == name)
+                        .map(|&(_, field)| field)
+                        .ok_or_else(|| {
+                            format!(
+                                "unknown placeholder {{{}}} in '{}'; valid ones are {}",
+                                name,
+                                text,
+                                valid_placeholders()
+                            )
+                        })?;
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(Piece::Field(field));
+                }
+                '}' => {
+                    return Err(format!(
+                        "unmatched '}}' in '{}' (write }}}} for a brace)",
+                        text
+                    ))
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Text(literal));
+        }
+        Ok(Self { pieces })
+    }
+
+    /// Whether rendering needs `field`, so values that cost a probe can be
+    /// left out otherwise.
+    pub fn uses(&self, field: Field) -> bool {
+        self.pieces.contains(&Piece::Field(field))
+    }
+
+    /// Whether the template has no placeholders at all.
+    pub fn is_literal(&self) -> bool {
+        self.pieces.iter().all(|p| matches!(p, Piece::Text(_)))
+    }
+
+    /// The text before the first placeholder.
+    pub fn fixed_prefix(&self) -> &str {
+        match self.pieces.first() {
+            Some(Piece::Text(text)) => text,
+            _ => "",
+        }
+    }
+
+    pub fn render(&self, values: &Values) -> String {
+        let mut rendered = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => rendered.push_str(text),
+                Piece::Field(field) => rendered.push_str(&values.get(*field)),
+            }
+        }
+        rendered
+    }
+}
+
+impl Values {
+    fn get(&self, field: Field) -> String {
+        let (year, month, day) = self.date;
+        match field {
+            Field::Stem => self.stem.clone(),
+            Field::Ext => self.ext.clone(),
+            Field::Size => self.size.clone(),
+            Field::Date => format!("{:04}-{:02}-{:02}", year, month, day),
+            Field::Year => format!("{:04}", year),
+            Field::Month => format!("{:02}", month),
+            Field::Day => format!("{:02}", day),
+            Field::Recorded => {
+                let (year, month, day) = self.recorded;
+                format!("{:04}-{:02}-{:02}", year, month, day)
+            }
+            Field::Width => self.width.to_string(),
+            Field::Height => self.height.to_string(),
+        }
+    }
+}
+
+/// `{stem}, {ext}, ...` for error messages.
+fn valid_placeholders() -> String {
+    FIELDS
+        .iter()
+        .map(|(name, _)| format!("{{{}}}", name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> Values {
+        Values {
+            stem: "holiday".into(),
+            ext: "mp4".into(),
+            size: "25".into(),
+            date: (2024, 5, 7),
+            recorded: (2023, 12, 31),
+            width: 1920,
+            height: 1080,
+        }
+    }
+
+    #[test]
+    fn test_placeholders_render() {
+        let template = Template::parse("{date}_{stem}_{size}MB.{ext}").unwrap();
+        assert_eq!(template.render(&values()), "2024-05-07_holiday_25MB.mp4");
+        let template = Template::parse("archive/{year}/{month}/{day}-{width}x{height}").unwrap();
+        assert_eq!(template.render(&values()), "archive/2024/05/07-1920x1080");
+        assert_eq!(template.fixed_prefix(), "archive/");
+        assert!(template.uses(Field::Width));
+        assert!(!template.uses(Field::Stem));
+        let template = Template::parse("{recorded}_{stem}.{ext}").unwrap();
+        assert_eq!(template.render(&values()), "2023-12-31_holiday.mp4");
+    }
+
+    #[test]
+    fn test_literal_braces_and_plain_text() {
+        let template = Template::parse("{{raw}}_{stem}").unwrap();
+        assert_eq!(template.render(&values()), "{raw}_holiday");
+        let template = Template::parse("out/clips").unwrap();
+        assert!(template.is_literal());
+        assert_eq!(template.fixed_prefix(), "out/clips");
+    }
+
+    #[test]
+    fn test_invalid_templates_are_explained() {
+        let err = Template::parse("{stem}_{resolution}").unwrap_err();
+        assert!(err.contains("unknown placeholder {resolution}"), "{}", err);
+        assert!(err.contains("{stem}, {ext}, {size}, {date}"), "{}", err);
+        assert!(Template::parse("{stem")
+            .unwrap_err()
+            .contains("unclosed '{stem'"));
+        assert!(Template::parse("a}b")
+            .unwrap_err()
+            .contains("unmatched '}'"));
+    }
+}
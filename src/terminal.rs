@@ -0,0 +1,147 @@
+//! What the status stream can display: color, in-place progress redraws and
+//! non-ASCII characters.
+//!
+//! CI logs and dumb terminals show carriage returns and escape codes as
+//! garbage, so anything written for a person asks [`Capabilities`] first.
+//! The presenter and the progress display are its only consumers.
+
+use crate::console::Console;
+use crate::presenter::ColorChoice;
+
+/// `--color` and `--plain` together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OutputMode {
+    pub color: ColorChoice,
+    /// No color, no redraws and ASCII only, whatever the terminal could do.
+    pub plain: bool,
+}
+
+impl From<ColorChoice> for OutputMode {
+    fn from(color: ColorChoice) -> Self {
+        Self {
+            color,
+            plain: false,
+        }
+    }
+}
+
+/// The environment variables that bear on styling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Env {
+    /// `NO_COLOR` is set to anything non-empty (<https://no-color.org>).
+    pub no_color: bool,
+    /// `CLICOLOR_FORCE` is set to anything but empty or `0`.
+    pub clicolor_force: bool,
+    /// `TERM` is `dumb`.
+    pub dumb_term: bool,
+}
+
+impl Env {
+    pub fn current() -> Self {
+        let var = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty());
+        Self {
+            no_color: var("NO_COLOR").is_some(),
+            clicolor_force: var("CLICOLOR_FORCE").is_some_and(|v| v != "0"),
+            dumb_term: var("TERM").is_some_and(|v| v == "dumb"),
+        }
+    }
+}
+
+/// What a status stream gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub color: bool,
+    /// Redraw the progress line in place with `\r`; otherwise progress is
+    /// printed as a new line every so often.
+    pub redraw: bool,
+    /// Use characters outside ASCII, such as `…` in shortened paths.
+    pub unicode: bool,
+}
+
+impl Capabilities {
+    /// The capabilities of `console` in the current environment.
+    pub fn of(console: Console, mode: OutputMode) -> Self {
+        detect(mode, console.is_terminal(), Env::current())
+    }
+}
+
+/// Decides the capabilities of a stream. Output that isn't a terminal, or
+/// goes to `TERM=dumb`, is plain as if `--plain` had been given.
+///
+/// For color an explicit `--color always` or `never` wins. Otherwise
+/// `NO_COLOR` turns it off, then `CLICOLOR_FORCE` turns it on even for plain
+/// output, and else it follows whether the output is plain.
+pub fn detect(mode: OutputMode, terminal: bool, env: Env) -> Capabilities {
+    let plain = mode.plain || !terminal || env.dumb_term;
+    let color = match mode.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto if env.no_color => false,
+        ColorChoice::Auto if env.clicolor_force => true,
+        ColorChoice::Auto => !plain,
+    };
+    Capabilities {
+        color,
+        redraw: !plain,
+        unicode: !plain,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTY: bool = true;
+    const PIPE: bool = false;
+
+    fn env(no_color: bool, clicolor_force: bool, dumb_term: bool) -> Env {
+        Env {
+            no_color,
+            clicolor_force,
+            dumb_term,
+        }
+    }
+
+    fn caps(color: bool, redraw: bool) -> Capabilities {
+        Capabilities {
+            color,
+            redraw,
+            unicode: redraw,
+        }
+    }
+
+    #[test]
+    fn test_detection_matrix() {
+        let auto = OutputMode::default();
+        let plain = OutputMode {
+            plain: true,
+            ..auto
+        };
+        // (mode, terminal, NO_COLOR, CLICOLOR_FORCE, TERM=dumb) -> (color, redraw)
+        let cases = [
+            (auto, TTY, env(false, false, false), caps(true, true)),
+            (auto, PIPE, env(false, false, false), caps(false, false)),
+            (auto, TTY, env(false, false, true), caps(false, false)),
+            (auto, TTY, env(true, false, false), caps(false, true)),
+            (auto, PIPE, env(false, true, false), caps(true, false)),
+            (auto, TTY, env(false, true, true), caps(true, false)),
+            // NO_COLOR beats CLICOLOR_FORCE.
+            (auto, PIPE, env(true, true, false), caps(false, false)),
+            (plain, TTY, env(false, false, false), caps(false, false)),
+            (plain, TTY, env(false, true, false), caps(true, false)),
+        ];
+        for (i, (mode, terminal, env, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(detect(mode, terminal, env), expected, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn test_explicit_color_wins_over_the_environment() {
+        let always = OutputMode::from(ColorChoice::Always);
+        let never = OutputMode::from(ColorChoice::Never);
+        assert!(detect(always, PIPE, env(true, false, true)).color);
+        assert!(!detect(never, TTY, env(false, true, false)).color);
+        // Color doesn't bring back redraws on a pipe.
+        assert!(!detect(always, PIPE, Env::default()).redraw);
+    }
+}
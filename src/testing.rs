@@ -0,0 +1,469 @@
+//! Test doubles shared by the unit tests of several modules.
+
+use crate::breakdown::FileStreams;
+use crate::error::ReduceError;
+use crate::events::{self, Event};
+use crate::filesystem::{self, Filesystem};
+use crate::interrupt::CancelToken;
+use crate::probe::VideoInfo;
+use crate::progress::Progress;
+use crate::sleep::Inhibitor;
+use crate::tool::VideoTool;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[path = "../tests/common/mp4.rs"]
+pub mod mp4;
+
+/// `ffprobe -select_streams d` of a GoPro HERO9 clip: the timecode,
+/// the GPMF telemetry and the "SOS" recovery track.
+pub const GOPRO_DATA_STREAMS: &str = r#"{
+    "programs": [
+
+    ],
+    "streams": [
+        {
+            "index": 2,
+            "codec_tag_string": "tmcd",
+            "tags": {
+                "language": "eng",
+                "handler_name": "GoPro TCD"
+            }
+        },
+        {
+            "index": 3,
+            "codec_name": "bin_data",
+            "codec_tag_string": "gpmd",
+            "bit_rate": "48063",
+            "tags": {
+                "language": "eng",
+                "handler_name": "GoPro MET"
+            }
+        },
+        {
+            "index": 4,
+            "codec_tag_string": "fdsc",
+            "bit_rate": "14413",
+            "tags": {
+                "language": "eng",
+                "handler_name": "GoPro SOS"
+            }
+        }
+    ]
+}"#;
+
+pub struct MockVideoTool {
+    pub duration: f64,
+    pub info: VideoInfo,
+    pub ffmpeg_calls: Mutex<Vec<Vec<String>>>,
+    /// Progress snapshots replayed to every progress-aware ffmpeg call.
+    pub progress: Vec<Progress>,
+    /// Size of the file written to the output path (the last argument, unless
+    /// it is stdout or the null muxer's `-`) of each call, in call order; the last entry repeats.
+    pub output_bytes: Vec<u64>,
+    /// Make every ffmpeg call fail after writing its output.
+    pub fail_ffmpeg: bool,
+    /// Cancel this token during every ffmpeg call, as Ctrl-C mid-encode
+    /// would; the test thread should watch it.
+    pub interrupt_ffmpeg: Option<CancelToken>,
+    /// The sleep inhibitor handed out, recording what it was asked.
+    pub sleep: MockInhibitor,
+    /// Make this many more probes fail with an I/O error, as on a flaky
+    /// network mount.
+    pub transient_probe_failures: Count,
+    /// Make this many more ffmpeg calls fail with a reset connection.
+    pub transient_ffmpeg_failures: Count,
+    /// What decoding the whole input measures; `duration` when `None`.
+    pub decoded_duration: Option<f64>,
+    /// Number of decode-through duration measurements made.
+    pub decode_calls: Count,
+    /// What `ffmpeg -encoders` lists.
+    pub encoders: Vec<String>,
+    /// What `ffmpeg -hwaccels` lists.
+    pub hwaccels: Vec<String>,
+    /// Make every ffmpeg call that decodes with `-hwaccel` fail, as where
+    /// the driver is missing.
+    pub broken_hwaccel: bool,
+    /// Codecs of the input's subtitle streams.
+    pub subtitle_codecs: Vec<String>,
+    /// What the stream listing reports.
+    pub streams: FileStreams,
+    /// Bytes per stream index that reading the packets sums to.
+    pub packet_sizes: HashMap<u32, u64>,
+    /// Number of packet reads made.
+    pub packet_calls: Count,
+    /// What reading the video packets' times and sizes returns.
+    pub packet_times: Vec<(f64, u64)>,
+    /// Filesystems mounted at these directories; anywhere else the type is
+    /// unknown.
+    pub mounts: Vec<(PathBuf, String)>,
+    /// When set, every rename fails with this error, as on a disk that
+    /// refuses it.
+    pub rename_error: Option<io::ErrorKind>,
+    /// Each probe made, as what was asked and of which input, such as
+    /// `info in.mp4`.
+    pub probe_calls: Mutex<Vec<String>>,
+    /// Hold every probe this long, so that the probes of several threads
+    /// overlap.
+    pub probe_delay: Duration,
+    /// Hold every ffmpeg call this long, so that the calls of a batch's
+    /// files running at once overlap.
+    pub ffmpeg_delay: Duration,
+    /// The most ffmpeg calls that were running at once.
+    pub most_at_once: Count,
+    /// How many ffmpeg calls are running now.
+    pub running: Count,
+}
+
+impl MockVideoTool {
+    pub fn new(duration: f64) -> Self {
+        Self {
+            duration,
+            info: VideoInfo {
+                width: 1920,
+                height: 1080,
+                codec_name: Some("h264".to_string()),
+                ..VideoInfo::default()
+            },
+            ffmpeg_calls: Mutex::new(Vec::new()),
+            progress: Vec::new(),
+            output_bytes: vec![1024],
+            fail_ffmpeg: false,
+            interrupt_ffmpeg: None,
+            sleep: MockInhibitor::default(),
+            transient_probe_failures: Count::default(),
+            transient_ffmpeg_failures: Count::default(),
+            decoded_duration: None,
+            decode_calls: Count::default(),
+            encoders: ["libx264", "libsvtav1", "aac", "libopus", "libmp3lame"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            hwaccels: Vec::new(),
+            broken_hwaccel: false,
+            subtitle_codecs: Vec::new(),
+            streams: FileStreams::default(),
+            packet_sizes: HashMap::new(),
+            packet_calls: Count::default(),
+            packet_times: Vec::new(),
+            mounts: Vec::new(),
+            rename_error: None,
+            probe_calls: Mutex::new(Vec::new()),
+            probe_delay: Duration::ZERO,
+            ffmpeg_delay: Duration::ZERO,
+            most_at_once: Count::default(),
+            running: Count::default(),
+        }
+    }
+
+    pub fn with_dimensions(mut self, width: u32, height: u32) -> Self {
+        self.info.width = width;
+        self.info.height = height;
+        self
+    }
+
+    /// The probes made so far, in order.
+    pub fn probes(&self) -> Vec<String> {
+        self.probe_calls.lock().unwrap().clone()
+    }
+
+    fn probed(&self, what: &str, input: &str) {
+        std::thread::sleep(self.probe_delay);
+        self.probe_calls
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", what, input));
+    }
+
+    /// Returns the arguments of the only ffmpeg invocation, panicking otherwise.
+    pub fn single_call(&self) -> Vec<String> {
+        let calls = self.ffmpeg_calls.lock().unwrap();
+        assert_eq!(calls.len(), 1, "expected exactly one ffmpeg call");
+        calls[0].clone()
+    }
+}
+
+impl VideoTool for MockVideoTool {
+    fn get_video_duration(&self, input: &str) -> Result<f64, ReduceError> {
+        self.probed("duration", input);
+        Ok(self.duration)
+    }
+
+    fn get_video_info(&self, input: &str) -> Result<VideoInfo, ReduceError> {
+        self.probed("info", input);
+        if countdown(&self.transient_probe_failures) {
+            return Err(ReduceError::Probe(format!(
+                "ffprobe failed: {}: Input/output error",
+                input
+            )));
+        }
+        Ok(self.info.clone())
+    }
+
+    fn get_decoded_duration(&self, input: &str) -> Result<f64, ReduceError> {
+        self.probed("decoded", input);
+        self.decode_calls.set(self.decode_calls.get() + 1);
+        Ok(self.decoded_duration.unwrap_or(self.duration))
+    }
+
+    fn get_subtitle_codecs(&self, input: &str) -> Result<Vec<String>, ReduceError> {
+        self.probed("subtitles", input);
+        Ok(self.subtitle_codecs.clone())
+    }
+
+    fn get_streams(&self, input: &str) -> Result<FileStreams, ReduceError> {
+        self.probed("streams", input);
+        Ok(self.streams.clone())
+    }
+
+    fn get_packet_sizes(&self, _input: &str) -> Result<HashMap<u32, u64>, ReduceError> {
+        self.packet_calls.set(self.packet_calls.get() + 1);
+        Ok(self.packet_sizes.clone())
+    }
+
+    fn get_packet_times(&self, _input: &str) -> Result<Vec<(f64, u64)>, ReduceError> {
+        Ok(self.packet_times.clone())
+    }
+
+    fn list_encoders(&self) -> Result<Vec<String>, ReduceError> {
+        Ok(self.encoders.clone())
+    }
+
+    fn list_hwaccels(&self) -> Result<Vec<String>, ReduceError> {
+        Ok(self.hwaccels.clone())
+    }
+
+    fn filesystem_of(&self, dir: &Path) -> Option<Filesystem> {
+        filesystem::mount_of(&self.mounts, dir)
+    }
+
+    fn sleep_inhibitor(&self) -> Box<dyn Inhibitor> {
+        Box::new(self.sleep.clone())
+    }
+
+    fn rename_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+        match self.rename_error {
+            Some(kind) => Err(io::Error::from(kind)),
+            None => std::fs::rename(from, to),
+        }
+    }
+
+    fn run_ffmpeg(&self, args: &[&str]) -> Result<(), ReduceError> {
+        let now = self.running.0.fetch_add(1, Ordering::SeqCst) + 1;
+        self.most_at_once.0.fetch_max(now, Ordering::SeqCst);
+        std::thread::sleep(self.ffmpeg_delay);
+        self.running.0.fetch_sub(1, Ordering::SeqCst);
+        let args_vec: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        let call = {
+            let mut calls = self.ffmpeg_calls.lock().unwrap();
+            calls.push(args_vec);
+            calls.len() - 1
+        };
+        // Like ffmpeg, leave an output file behind (a partial one on failure).
+        // Sparse files keep large sizes cheap.
+        if let Some(output) = args.last().filter(|&&a| a != "pipe:1" && a != "-") {
+            let bytes = self.output_bytes[call.min(self.output_bytes.len() - 1)];
+            // An HLS playlist lists one segment holding all of it.
+            let file = match output.strip_suffix("index.m3u8") {
+                Some(dir) => {
+                    let _ = std::fs::write(output, "#EXTM3U\n#EXTINF:10.0,\nsegment00000.m4s\n");
+                    format!("{}segment00000.m4s", dir)
+                }
+                None => output.to_string(),
+            };
+            let _ = std::fs::File::create(file).and_then(|f| f.set_len(bytes));
+        }
+        if countdown(&self.transient_ffmpeg_failures) {
+            return Err(ReduceError::Encode(
+                "ffmpeg failed during encoding:\nConnection reset by peer".into(),
+            ));
+        }
+        if self.broken_hwaccel && args.contains(&"-hwaccel") {
+            return Err(ReduceError::Encode(
+                "ffmpeg failed during encoding:\n\
+                 [hevc @ 0x5581] Failed setup for format cuda: hwaccel initialisation returned error."
+                    .into(),
+            ));
+        }
+        if let Some(token) = &self.interrupt_ffmpeg {
+            token.cancel();
+            return Err(ReduceError::Encode("ffmpeg was killed".into()));
+        }
+        if self.fail_ffmpeg {
+            return Err(ReduceError::Encode("ffmpeg failed during encoding".into()));
+        }
+        Ok(())
+    }
+
+    fn run_ffmpeg_with_progress(
+        &self,
+        args: &[&str],
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<(), ReduceError> {
+        for progress in &self.progress {
+            on_progress(progress);
+        }
+        self.run_ffmpeg(args)
+    }
+}
+
+/// An inhibitor that only records `acquire <why>` and `release`; clones
+/// share the record.
+#[derive(Debug, Clone, Default)]
+pub struct MockInhibitor {
+    /// Fail every acquire, as where no inhibitor service runs.
+    pub unavailable: bool,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockInhibitor {
+    pub fn events(&self) -> Vec<String> {
+        self.log.lock().unwrap().clone()
+    }
+}
+
+impl Inhibitor for MockInhibitor {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn acquire(&mut self, why: &str) -> Result<(), String> {
+        self.log.lock().unwrap().push(format!("acquire {}", why));
+        if self.unavailable {
+            return Err("no inhibitor running".into());
+        }
+        Ok(())
+    }
+
+    fn release(&mut self) {
+        self.log.lock().unwrap().push("release".into());
+    }
+}
+
+/// A number the mock keeps, read and set as with a `Cell` but shareable
+/// between the threads of a batch's jobs.
+#[derive(Debug, Default)]
+pub struct Count(AtomicU32);
+
+impl Count {
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn set(&self, n: u32) {
+        self.0.store(n, Ordering::SeqCst);
+    }
+}
+
+/// Counts `left` down, saying whether it was above zero.
+fn countdown(left: &Count) -> bool {
+    let was = left.get();
+    left.set(was.saturating_sub(1));
+    was > 0
+}
+
+/// `n` binary megabytes in bytes.
+pub fn mib(n: u64) -> u64 {
+    n * 1024 * 1024
+}
+
+/// Returns the value following `flag` in an ffmpeg argument list.
+pub fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(|s| s.as_str())
+}
+
+/// A scratch directory removed when dropped.
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "mdviqure-test-{}-{}",
+            std::process::id(),
+            crate::tempdir::unique_token()
+        ));
+        std::fs::create_dir_all(&path).expect("create test dir");
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of `name` inside the directory, as a string for the reducer APIs.
+    pub fn join(&self, name: &str) -> String {
+        self.path.join(name).to_string_lossy().into_owned()
+    }
+
+    /// Names of the entries currently in the directory, sorted.
+    pub fn entries(&self) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(&self.path)
+            .map(|rd| {
+                rd.filter_map(Result::ok)
+                    .map(|e| e.file_name().to_string_lossy().into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Collects the events emitted on this thread until dropped.
+pub struct EventLog {
+    lines: Rc<RefCell<Vec<u8>>>,
+}
+
+/// The writing end of an [`EventLog`].
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl EventLog {
+    pub fn install() -> Self {
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        events::install(Box::new(SharedBuffer(Rc::clone(&lines))));
+        Self { lines }
+    }
+
+    /// The events so far, each checked to be one line of JSON.
+    pub fn events(&self) -> Vec<Event> {
+        String::from_utf8(self.lines.borrow().clone())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        events::uninstall();
+    }
+}
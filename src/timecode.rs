@@ -0,0 +1,83 @@
+//! Time strings given on the command line: plain seconds (`90`, `12.5`) or
+//! clock time (`1:30`, `01:02:03.250`).
+
+/// Parses `text` into seconds.
+///
+/// Clock time is `[[hh:]mm:]ss[.fff]`; hours may exceed 24 and the leading
+/// field isn't limited to 59, so `90:00` is an hour and a half.
+pub fn parse_time(text: &str) -> Result<f64, String> {
+    let invalid = || {
+        format!(
+            "invalid time '{}': expected seconds or hh:mm:ss[.fff]",
+            text
+        )
+    };
+    let fields: Vec<&str> = text.trim().split(':').collect();
+    if fields.len() > 3 {
+        return Err(invalid());
+    }
+    let (last, leading) = fields.split_last().ok_or_else(invalid)?;
+    let seconds: f64 = parse_field(last).ok_or_else(invalid)?;
+    let mut total = 0.0;
+    for (i, field) in leading.iter().enumerate() {
+        if field.contains('.') {
+            return Err(invalid());
+        }
+        let value = parse_field(field).ok_or_else(invalid)?;
+        // Only the first field may overflow its unit.
+        if i > 0 && value >= 60.0 {
+            return Err(invalid());
+        }
+        total = total * 60.0 + value;
+    }
+    if !leading.is_empty() && seconds >= 60.0 {
+        return Err(invalid());
+    }
+    Ok(total * 60.0 + seconds)
+}
+
+/// A non-negative decimal number made of digits and at most one dot.
+fn parse_field(field: &str) -> Option<f64> {
+    let field = field.trim();
+    if field.is_empty() || !field.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    field.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_seconds() {
+        assert_eq!(parse_time("90"), Ok(90.0));
+        assert_eq!(parse_time("12.5"), Ok(12.5));
+        assert_eq!(parse_time(" 0 "), Ok(0.0));
+    }
+
+    #[test]
+    fn test_clock_time_keeps_milliseconds() {
+        assert_eq!(parse_time("1:30"), Ok(90.0));
+        let t = parse_time("01:02:03.250").unwrap();
+        assert!((t - 3723.25).abs() < 1e-9);
+        let t = parse_time("00:00:00.001").unwrap();
+        assert!((t - 0.001).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_more_than_a_day() {
+        assert_eq!(parse_time("25:00:00"), Ok(90_000.0));
+        assert_eq!(parse_time("100:30:15"), Ok(361_815.0));
+        assert_eq!(parse_time("90:00"), Ok(5_400.0));
+    }
+
+    #[test]
+    fn test_rejects_malformed_times() {
+        for text in [
+            "", "abc", "1:2:3:4", "-5", "1:60", "1:75:00", "1.5:00", "1::2", "+3",
+        ] {
+            assert!(parse_time(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,594 @@
+//! Abstraction over the external ffmpeg/ffprobe executables.
+
+use crate::breakdown::{self, FileStreams};
+use crate::compare;
+use crate::coverart;
+use crate::encoder;
+use crate::error::ReduceError;
+use crate::filesystem::{self, Filesystem};
+use crate::hwdecode;
+use crate::longpath;
+use crate::mp4box::{self, Movie};
+use crate::presenter::Presenter;
+use crate::probe::{self, VideoInfo};
+use crate::probecache::ProbeCache;
+use crate::process::{self, Captured};
+use crate::progress::Progress;
+use crate::sleep::{self, Inhibitor};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Trait to abstract external video processing tools (ffprobe, ffmpeg).
+pub trait VideoTool {
+    fn get_video_duration(&self, input: &str) -> Result<f64, ReduceError>;
+    fn get_video_info(&self, input: &str) -> Result<VideoInfo, ReduceError>;
+    /// Measures the duration by decoding the whole video stream, for inputs
+    /// whose container reports no (or a wrong) duration.
+    fn get_decoded_duration(&self, input: &str) -> Result<f64, ReduceError>;
+    /// Codecs of the input's subtitle streams.
+    fn get_subtitle_codecs(&self, input: &str) -> Result<Vec<String>, ReduceError>;
+    /// Every stream of the input, with the container's size and duration.
+    fn get_streams(&self, input: &str) -> Result<FileStreams, ReduceError>;
+    /// Bytes per stream index, summed over every packet; reads the whole
+    /// input.
+    fn get_packet_sizes(&self, input: &str) -> Result<HashMap<u32, u64>, ReduceError>;
+    /// Time and size of every packet of the first video stream, in file
+    /// order; reads the whole input.
+    fn get_packet_times(&self, input: &str) -> Result<Vec<(f64, u64)>, ReduceError>;
+    /// Names of the encoders this ffmpeg build provides.
+    fn list_encoders(&self) -> Result<Vec<String>, ReduceError>;
+    /// Names of the hardware decoders this ffmpeg build provides.
+    fn list_hwaccels(&self) -> Result<Vec<String>, ReduceError>;
+    fn run_ffmpeg(&self, args: &[&str]) -> Result<(), ReduceError>;
+
+    /// Runs ffmpeg, reporting each `-progress pipe:1` snapshot to `on_progress`.
+    ///
+    /// The default implementation reports nothing, which suits tools that
+    /// can't observe the child's output.
+    fn run_ffmpeg_with_progress(
+        &self,
+        args: &[&str],
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<(), ReduceError> {
+        let _ = on_progress;
+        self.run_ffmpeg(args)
+    }
+
+    /// Runs several ffmpeg commands, reporting progress along with each
+    /// command's index in `jobs`, and stops at the first failure.
+    ///
+    /// The default implementation runs them one after another;
+    /// [`FfmpegTool`] runs them all at once.
+    fn run_ffmpeg_parallel(
+        &self,
+        jobs: &[Vec<&str>],
+        on_progress: &mut dyn FnMut(usize, &Progress),
+    ) -> Result<(), ReduceError> {
+        for (i, args) in jobs.iter().enumerate() {
+            self.run_ffmpeg_with_progress(args, &mut |progress| on_progress(i, progress))?;
+        }
+        Ok(())
+    }
+
+    /// The filesystem holding the directory `dir`, when the platform can
+    /// tell.
+    fn filesystem_of(&self, dir: &Path) -> Option<Filesystem> {
+        filesystem::detect(dir)
+    }
+
+    /// What keeps the system awake during an encode.
+    fn sleep_inhibitor(&self) -> Box<dyn Inhibitor> {
+        sleep::platform()
+    }
+
+    /// Renames `from` over `to`, the step of [`crate::commit::commit`] that
+    /// puts an output in place.
+    fn rename_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    /// Where the tool keeps the probes it already made, when it does; see
+    /// [`crate::probecache`].
+    fn probe_cache(&self) -> Option<&ProbeCache> {
+        None
+    }
+}
+
+/// A tool that passes everything on to another, noting the arguments of
+/// each ffmpeg command it runs, for `--sidecar`.
+pub struct Recording<'a, T> {
+    inner: &'a T,
+    commands: RefCell<Vec<Vec<String>>>,
+}
+
+impl<'a, T: VideoTool> Recording<'a, T> {
+    pub fn new(inner: &'a T) -> Self {
+        Self {
+            inner,
+            commands: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The arguments of every ffmpeg command run so far, in order.
+    pub fn commands(&self) -> Vec<Vec<String>> {
+        self.commands.borrow().clone()
+    }
+
+    fn record(&self, args: &[&str]) {
+        self.commands
+            .borrow_mut()
+            .push(args.iter().map(|arg| arg.to_string()).collect());
+    }
+}
+
+impl<T: VideoTool> VideoTool for Recording<'_, T> {
+    fn get_video_duration(&self, input: &str) -> Result<f64, ReduceError> {
+        self.inner.get_video_duration(input)
+    }
+
+    fn get_video_info(&self, input: &str) -> Result<VideoInfo, ReduceError> {
+        self.inner.get_video_info(input)
+    }
+
+    fn get_decoded_duration(&self, input: &str) -> Result<f64, ReduceError> {
+        self.inner.get_decoded_duration(input)
+    }
+
+    fn get_subtitle_codecs(&self, input: &str) -> Result<Vec<String>, ReduceError> {
+        self.inner.get_subtitle_codecs(input)
+    }
+
+    fn get_streams(&self, input: &str) -> Result<FileStreams, ReduceError> {
+        self.inner.get_streams(input)
+    }
+
+    fn get_packet_sizes(&self, input: &str) -> Result<HashMap<u32, u64>, ReduceError> {
+        self.inner.get_packet_sizes(input)
+    }
+
+    fn get_packet_times(&self, input: &str) -> Result<Vec<(f64, u64)>, ReduceError> {
+        self.inner.get_packet_times(input)
+    }
+
+    fn list_encoders(&self) -> Result<Vec<String>, ReduceError> {
+        self.inner.list_encoders()
+    }
+
+    fn list_hwaccels(&self) -> Result<Vec<String>, ReduceError> {
+        self.inner.list_hwaccels()
+    }
+
+    fn run_ffmpeg(&self, args: &[&str]) -> Result<(), ReduceError> {
+        self.record(args);
+        self.inner.run_ffmpeg(args)
+    }
+
+    fn run_ffmpeg_with_progress(
+        &self,
+        args: &[&str],
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<(), ReduceError> {
+        self.record(args);
+        self.inner.run_ffmpeg_with_progress(args, on_progress)
+    }
+
+    fn run_ffmpeg_parallel(
+        &self,
+        jobs: &[Vec<&str>],
+        on_progress: &mut dyn FnMut(usize, &Progress),
+    ) -> Result<(), ReduceError> {
+        for args in jobs {
+            self.record(args);
+        }
+        self.inner.run_ffmpeg_parallel(jobs, on_progress)
+    }
+
+    fn filesystem_of(&self, dir: &Path) -> Option<Filesystem> {
+        self.inner.filesystem_of(dir)
+    }
+
+    fn sleep_inhibitor(&self) -> Box<dyn Inhibitor> {
+        self.inner.sleep_inhibitor()
+    }
+
+    fn rename_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename_file(from, to)
+    }
+
+    fn probe_cache(&self) -> Option<&ProbeCache> {
+        self.inner.probe_cache()
+    }
+}
+
+/// Real implementation running the ffmpeg and ffprobe executables.
+///
+/// Its methods are blocking wrappers over the async functions in
+/// [`crate::process`], which async callers can use directly.
+#[derive(Debug, Clone, Default)]
+pub struct FfmpegTool {
+    /// Kill any single ffmpeg or ffprobe run that takes longer than this.
+    pub timeout: Option<Duration>,
+    /// Where to show what ffprobe printed on stderr about files it read
+    /// anyway; `None` keeps it quiet.
+    pub diagnostics: Option<Presenter>,
+    /// Where the stderr of every ffmpeg run goes in full, as `--ffmpeg-log`
+    /// asks; only its end is kept otherwise.
+    pub ffmpeg_log: Option<PathBuf>,
+    /// When ffprobe can't be run, read the durations of an MP4 input from
+    /// its own headers (see [`crate::mp4box`]) instead of failing.
+    pub read_mp4_headers: bool,
+}
+
+impl FfmpegTool {
+    /// Parses the output from ffprobe to extract duration.
+    /// Separated for unit testing.
+    pub fn parse_duration(stdout: &str) -> Result<f64, Box<dyn Error>> {
+        let duration: f64 = stdout.trim().parse()?;
+        Ok(duration)
+    }
+
+    fn log(&self) -> Option<&Path> {
+        self.ffmpeg_log.as_deref()
+    }
+
+    /// Runs ffprobe, passing its stderr of a successful run on to
+    /// [`FfmpegTool::diagnostics`].
+    async fn ffprobe(&self, args: &[&str]) -> Result<Captured, ReduceError> {
+        let probe = process::ffprobe(args, self.timeout).await?;
+        if let Some(out) = &self.diagnostics {
+            for line in probe
+                .stderr
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+            {
+                out.info(&format!("ffprobe: {}", line));
+            }
+        }
+        Ok(probe)
+    }
+
+    /// What the headers of `input` tell in place of ffprobe, which failed
+    /// with `error`: that error itself unless ffprobe couldn't be run at
+    /// all, [`FfmpegTool::read_mp4_headers`] is set, and `input` is an MP4
+    /// that can be read.
+    fn read_headers(&self, input: &str, error: ReduceError) -> Result<Movie, ReduceError> {
+        let fallback = matches!(error, ReduceError::ToolNotFound("ffprobe"))
+            && self.read_mp4_headers
+            && mp4box::reads(input);
+        if !fallback {
+            return Err(error);
+        }
+        mp4box::read_file(Path::new(input)).map_err(|e| {
+            if let Some(out) = &self.diagnostics {
+                out.info(&format!("{} can't be read without ffprobe: {}", input, e));
+            }
+            error
+        })
+    }
+
+    pub async fn video_duration(&self, input: &str) -> Result<f64, ReduceError> {
+        let input_arg = longpath::for_tool(input);
+        let probe = self
+            .ffprobe(&[
+                "-v",
+                "error",
+                "-select_streams",
+                "v:0",
+                "-show_entries",
+                "format=duration",
+                "-of",
+                "default=noprint_wrappers=1:nokey=1",
+                &input_arg,
+            ])
+            .await;
+        let probe = match probe {
+            Ok(probe) => probe,
+            // The movie header has the length ffprobe would print.
+            Err(e) => return self.read_headers(input, e).map(|movie| movie.duration),
+        };
+        probe.parse(
+            &format!("cannot read duration of {}", input),
+            Self::parse_duration,
+        )
+    }
+
+    pub async fn video_info(&self, input: &str) -> Result<VideoInfo, ReduceError> {
+        let input_arg = longpath::for_tool(input);
+        let probe = self.ffprobe(
+            &[
+            "-v",
+            "error",
+            "-select_streams",
+            "v",
+            "-show_entries",
+            "stream=index,width,height,codec_name,codec_tag_string,profile,level,pix_fmt,avg_frame_rate,r_frame_rate,bit_rate,sample_aspect_ratio,display_aspect_ratio,duration,color_primaries,color_transfer,color_space,color_range:stream_disposition=attached_pic:stream_side_data=rotation:format_tags=creation_time",
+            "-of",
+            "json",
+            &input_arg,
+            ],
+        )
+        .await;
+        let probe = match probe {
+            Ok(probe) => probe,
+            Err(e) => return self.assumed_info(input, e),
+        };
+        let mut info = probe.parse(
+            &format!("cannot read the video stream of {}", input),
+            probe::parse_video_info,
+        )?;
+        let probe = self
+            .ffprobe(&[
+                "-v",
+                "error",
+                "-select_streams",
+                "a",
+                "-show_entries",
+                "stream=index,codec_name,codec_tag_string,channels,duration:stream_tags=language:stream_disposition=default",
+                "-of",
+                "json",
+                &input_arg,
+            ])
+            .await?;
+        info.audio_streams = Some(probe.parse(
+            &format!("cannot read the audio streams of {}", input),
+            probe::parse_audio_streams,
+        )?);
+        let probe = self
+            .ffprobe(&[
+                "-v",
+                "error",
+                "-select_streams",
+                "d",
+                "-show_entries",
+                "stream=index,codec_name,codec_tag_string,bit_rate:stream_tags=handler_name",
+                "-of",
+                "json",
+                &input_arg,
+            ])
+            .await?;
+        info.data_streams = probe.parse(
+            &format!("cannot read the data streams of {}", input),
+            probe::parse_data_streams,
+        )?;
+        Ok(info)
+    }
+
+    /// The stream details of `input` when ffprobe failed with `error`, from
+    /// its headers (see [`FfmpegTool::read_headers`]) and assumptions.
+    fn assumed_info(&self, input: &str, error: ReduceError) -> Result<VideoInfo, ReduceError> {
+        self.read_headers(input, error)?
+            .video_info()
+            .map_err(|e| ReduceError::Probe(format!("cannot reduce {}: {}", input, e)))
+    }
+
+    pub async fn decoded_duration(&self, input: &str) -> Result<f64, ReduceError> {
+        let input_arg = longpath::for_tool(input);
+        let mut duration = 0.0_f64;
+        let args = [
+            "-v",
+            "error",
+            "-progress",
+            "pipe:1",
+            "-nostats",
+            "-i",
+            &input_arg,
+            "-map",
+            coverart::VIDEO,
+            "-f",
+            "null",
+            "-",
+        ];
+        process::ffmpeg(&args, self.timeout, self.log(), &mut |progress| {
+            duration = duration.max(progress.out_time);
+        })
+        .await
+        .map_err(|e| match e {
+            ReduceError::Encode(message) => ReduceError::Probe(format!(
+                "cannot decode {} to measure its duration: {}",
+                input, message
+            )),
+            other => other,
+        })?;
+        if duration > 0.0 {
+            Ok(duration)
+        } else {
+            Err(ReduceError::Probe(format!(
+                "decoding {} produced no video frames",
+                input
+            )))
+        }
+    }
+
+    pub async fn subtitle_codecs(&self, input: &str) -> Result<Vec<String>, ReduceError> {
+        let input_arg = longpath::for_tool(input);
+        let probe = self
+            .ffprobe(&[
+                "-v",
+                "error",
+                "-select_streams",
+                "s",
+                "-show_entries",
+                "stream=codec_name",
+                "-of",
+                "json",
+                &input_arg,
+            ])
+            .await?;
+        probe.parse(
+            &format!("cannot read the subtitle streams of {}", input),
+            probe::parse_codec_names,
+        )
+    }
+
+    pub async fn streams(&self, input: &str) -> Result<FileStreams, ReduceError> {
+        let input_arg = longpath::for_tool(input);
+        let probe = self.ffprobe(
+            &[
+                "-v",
+                "error",
+                "-show_entries",
+                "format=size,duration,bit_rate:format_tags=comment:stream=index,codec_type,codec_name,bit_rate,duration,extradata_size:stream_tags=language",
+                "-of",
+                "json",
+                &input_arg,
+            ],
+        )
+        .await?;
+        probe.parse(
+            &format!("cannot read the streams of {}", input),
+            breakdown::parse_streams,
+        )
+    }
+
+    pub async fn packet_sizes(&self, input: &str) -> Result<HashMap<u32, u64>, ReduceError> {
+        let input_arg = longpath::for_tool(input);
+        let probe = self
+            .ffprobe(&[
+                "-v",
+                "error",
+                "-show_entries",
+                "packet=stream_index,size",
+                "-of",
+                "csv=p=0",
+                &input_arg,
+            ])
+            .await?;
+        Ok(breakdown::parse_packet_sizes(&probe.stdout))
+    }
+
+    pub async fn packet_times(&self, input: &str) -> Result<Vec<(f64, u64)>, ReduceError> {
+        let input_arg = longpath::for_tool(input);
+        let probe = self
+            .ffprobe(&[
+                "-v",
+                "error",
+                "-select_streams",
+                "v:0",
+                "-show_entries",
+                "packet=pts_time,size",
+                "-of",
+                "csv=p=0",
+                &input_arg,
+            ])
+            .await?;
+        Ok(compare::parse_packet_times(&probe.stdout))
+    }
+
+    pub async fn encoders(&self) -> Result<Vec<String>, ReduceError> {
+        let stdout = process::ffmpeg_query(&["-hide_banner", "-encoders"]).await?;
+        Ok(encoder::parse_encoders(&stdout))
+    }
+
+    pub async fn hwaccels(&self) -> Result<Vec<String>, ReduceError> {
+        let stdout = process::ffmpeg_query(&["-hide_banner", "-hwaccels"]).await?;
+        Ok(hwdecode::parse_hwaccels(&stdout))
+    }
+
+    pub async fn ffmpeg(
+        &self,
+        args: &[&str],
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<(), ReduceError> {
+        process::ffmpeg(args, self.timeout, self.log(), on_progress).await
+    }
+
+    pub async fn ffmpeg_all(
+        &self,
+        jobs: &[Vec<&str>],
+        on_progress: &mut dyn FnMut(usize, &Progress),
+    ) -> Result<(), ReduceError> {
+        process::ffmpeg_all(jobs, self.timeout, self.log(), on_progress).await
+    }
+}
+
+impl VideoTool for FfmpegTool {
+    fn get_video_duration(&self, input: &str) -> Result<f64, ReduceError> {
+        process::block_on(self.video_duration(input))
+    }
+
+    fn get_video_info(&self, input: &str) -> Result<VideoInfo, ReduceError> {
+        process::block_on(self.video_info(input))
+    }
+
+    fn get_decoded_duration(&self, input: &str) -> Result<f64, ReduceError> {
+        process::block_on(self.decoded_duration(input))
+    }
+
+    fn get_subtitle_codecs(&self, input: &str) -> Result<Vec<String>, ReduceError> {
+        process::block_on(self.subtitle_codecs(input))
+    }
+
+    fn get_streams(&self, input: &str) -> Result<FileStreams, ReduceError> {
+        process::block_on(self.streams(input))
+    }
+
+    fn get_packet_sizes(&self, input: &str) -> Result<HashMap<u32, u64>, ReduceError> {
+        process::block_on(self.packet_sizes(input))
+    }
+
+    fn get_packet_times(&self, input: &str) -> Result<Vec<(f64, u64)>, ReduceError> {
+        process::block_on(self.packet_times(input))
+    }
+
+    fn list_encoders(&self) -> Result<Vec<String>, ReduceError> {
+        process::block_on(self.encoders())
+    }
+
+    fn list_hwaccels(&self) -> Result<Vec<String>, ReduceError> {
+        process::block_on(self.hwaccels())
+    }
+
+    fn run_ffmpeg(&self, args: &[&str]) -> Result<(), ReduceError> {
+        self.run_ffmpeg_with_progress(args, &mut |_| {})
+    }
+
+    fn run_ffmpeg_with_progress(
+        &self,
+        args: &[&str],
+        on_progress: &mut dyn FnMut(&Progress),
+    ) -> Result<(), ReduceError> {
+        process::block_on(self.ffmpeg(args, on_progress))
+    }
+
+    fn run_ffmpeg_parallel(
+        &self,
+        jobs: &[Vec<&str>],
+        on_progress: &mut dyn FnMut(usize, &Progress),
+    ) -> Result<(), ReduceError> {
+        process::block_on(self.ffmpeg_all(jobs, on_progress))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffmpeg_tool_parsing_logic() {
+        let output = "123.456\n";
+        let duration = FfmpegTool::parse_duration(output).unwrap();
+        assert!((duration - 123.456).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_ffmpeg_tool_failure_modes() {
+        let tool = FfmpegTool::default();
+        // This is expected to fail because "nonexistent.mp4" doesn't exist
+        // or ffprobe/ffmpeg might not be installed.
+        // We just want to ensure it returns an error, covering the error path.
+
+        let result = tool.get_video_duration("nonexistent_file_for_test.mp4");
+        assert!(result.is_err());
+
+        // Similarly for run_ffmpeg, passing invalid args should fail (either at spawn or execution)
+        let result = tool.run_ffmpeg(&["-invalid-flag"]);
+        assert!(result.is_err());
+        let result = tool.run_ffmpeg_with_progress(&["-invalid-flag"], &mut |_| {});
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! `--retries`: another go at reads that fail for a moment.
+//!
+//! Inputs on an SMB or NFS mount now and then fail to open, or fail in the
+//! first seconds of an encode, with errors that are gone a moment later: a
+//! reset connection, a stale file handle, a bare I/O error. A failure whose
+//! message carries one of [`SIGNATURES`] is retried after a pause that
+//! doubles each time. This is separate from the re-encodes of an output
+//! that came out over the target (`--max-retries`).
+
+use crate::error::ReduceError;
+use crate::interrupt;
+use crate::presenter::Presenter;
+use crate::warning::{self, Code, Warning};
+use std::time::{Duration, Instant};
+
+/// What ffmpeg, ffprobe and the OS say for failures that may pass, in
+/// lower case, each with the reason shown when retrying.
+pub const SIGNATURES: &[(&str, &str)] = &[
+    ("connection reset", "connection reset"),
+    ("stale file handle", "stale file handle"),
+    ("input/output error", "I/O error"),
+    (
+        "resource temporarily unavailable",
+        "resource temporarily unavailable",
+    ),
+    ("connection timed out", "connection timed out"),
+];
+
+/// The longest pause between two tries.
+pub const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How long into an encode a failure still counts as failing to start;
+/// one that fails later has written too much to be a passing hiccup.
+pub const STARTUP: Duration = Duration::from_secs(20);
+
+/// How often a failure may be retried, and how long to wait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retries {
+    pub count: u32,
+    /// The pause before the first retry; each later one is twice as long,
+    /// up to [`MAX_DELAY`].
+    pub first_delay: Duration,
+}
+
+impl Retries {
+    /// No retries.
+    pub const NONE: Retries = Retries {
+        count: 0,
+        first_delay: Duration::from_secs(1),
+    };
+
+    /// The pause before retry number `retry`, from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.first_delay.saturating_mul(factor).min(MAX_DELAY)
+    }
+}
+
+/// Why `err` may pass on another try, if it matches a signature. Only
+/// failures of ffprobe and ffmpeg qualify; a missing file or a bad option
+/// stays what it is.
+pub fn reason(err: &ReduceError) -> Option<&'static str> {
+    let message = match err {
+        ReduceError::Probe(message) | ReduceError::Encode(message) => message.to_lowercase(),
+        _ => return None,
+    };
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| message.contains(signature))
+        .map(|&(_, reason)| reason)
+}
+
+/// Runs `run`, and again after a transient failure, up to `retries.count`
+/// more times. With `within`, only failures that come that soon after the
+/// try started are retried. Each retry is warned about with its reason.
+pub fn retry<T>(
+    retries: Retries,
+    what: &str,
+    within: Option<Duration>,
+    out: Presenter,
+    mut run: impl FnMut() -> Result<T, ReduceError>,
+) -> Result<T, ReduceError> {
+    for retry in 1.. {
+        let started = Instant::now();
+        let err = match run() {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        let early = within.is_none_or(|within| started.elapsed() <= within);
+        let reason = match reason(&err) {
+            Some(reason) if early && retry <= retries.count => reason,
+            _ => return Err(err),
+        };
+        let delay = retries.delay(retry);
+        warning::emit(
+            out,
+            Warning::new(
+                Code::TransientRetry,
+                format!(
+                    "{} failed ({}); retrying in {}s ({} of {})",
+                    what,
+                    reason,
+                    delay.as_secs_f64(),
+                    retry,
+                    retries.count
+                ),
+            ),
+        );
+        pause(delay)?;
+    }
+    unreachable!("the retries run out first")
+}
+
+/// Sleeps for `delay`, cut short by Ctrl-C.
+fn pause(delay: Duration) -> Result<(), ReduceError> {
+    let until = Instant::now() + delay;
+    loop {
+        if interrupt::is_interrupted() {
+            return Err(ReduceError::Interrupted);
+        }
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(());
+        }
+        std::thread::sleep(left.min(Duration::from_millis(100)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presenter::ColorChoice;
+    use std::cell::Cell;
+
+    fn quick(count: u32) -> Retries {
+        Retries {
+            count,
+            first_delay: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_transient_failures_are_told_apart() {
+        let cases = [
+            (
+                ReduceError::Probe("ffprobe failed: /mnt/nas/a.mp4: Input/output error".into()),
+                Some("I/O error"),
+            ),
+            (
+                ReduceError::Encode(
+                    "ffmpeg failed during encoding:\n[tcp] Connection reset by peer".into(),
+                ),
+                Some("connection reset"),
+            ),
+            (
+                ReduceError::Probe("ffprobe failed: a.mp4: Stale file handle".into()),
+                Some("stale file handle"),
+            ),
+            (
+                ReduceError::Probe("ffprobe failed: a.mp4: No such file or directory".into()),
+                None,
+            ),
+            (
+                ReduceError::Encode("ffmpeg failed during encoding:\nInvalid argument".into()),
+                None,
+            ),
+            (ReduceError::Usage("Input/output error".into()), None),
+            (ReduceError::Interrupted, None),
+        ];
+        for (err, expected) in cases {
+            assert_eq!(reason(&err), expected, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let retries = Retries {
+            count: 8,
+            first_delay: Duration::from_secs(1),
+        };
+        let delays: Vec<u64> = (1..=7).map(|r| retries.delay(r).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(retries.delay(u32::MAX), MAX_DELAY);
+    }
+
+    #[test]
+    fn test_retry_runs_again_until_it_works_or_runs_out() {
+        let out = Presenter::stderr(ColorChoice::Never);
+        warning::take();
+        let calls = Cell::new(0);
+        let flaky = || {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 {
+                Err(ReduceError::Probe(
+                    "ffprobe failed: Input/output error".into(),
+                ))
+            } else {
+                Ok(calls.get())
+            }
+        };
+        assert_eq!(retry(quick(2), "probing", None, out, flaky).unwrap(), 3);
+        let warnings = warning::take();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].code, Code::TransientRetry);
+        assert_eq!(
+            warnings[0].message,
+            "probing failed (I/O error); retrying in 0s (1 of 2)"
+        );
+
+        calls.set(0);
+        assert!(retry(quick(1), "probing", None, out, flaky).is_err());
+        assert_eq!(calls.get(), 2);
+
+        // Anything else fails at once.
+        calls.set(0);
+        let broken = || -> Result<(), ReduceError> {
+            calls.set(calls.get() + 1);
+            Err(ReduceError::Probe("ffprobe failed: Invalid data".into()))
+        };
+        assert!(retry(quick(5), "probing", None, out, broken).is_err());
+        assert_eq!(calls.get(), 1);
+
+        // So does a failure later than `within`.
+        calls.set(0);
+        let late = || -> Result<(), ReduceError> {
+            calls.set(calls.get() + 1);
+            std::thread::sleep(Duration::from_millis(5));
+            Err(ReduceError::Encode("Input/output error".into()))
+        };
+        let within = Some(Duration::from_millis(1));
+        assert!(retry(quick(5), "the encode", within, out, late).is_err());
+        assert_eq!(calls.get(), 1);
+        warning::take();
+    }
+}
@@ -0,0 +1,137 @@
+//! `--two-pass`: a first pass that only analyzes the video, so the encode
+//! proper knows where the bitrate is needed and lands closer to it.
+//!
+//! The first pass takes about as long as the second. On a clip of a few
+//! seconds that buys next to nothing, and some encoders can't run two
+//! passes through ffmpeg at all. [`plan`] says when an encode falls back
+//! to one pass, which then has its peak rate capped (`-maxrate` and
+//! `-bufsize`) so it can't stray as far from the average.
+
+use crate::encoder::VideoEncoder;
+use serde::{Deserialize, Serialize};
+
+/// Shortest encode that gets two passes.
+pub const MIN_LENGTH: f64 = 15.0;
+
+/// The peak rate of a capped pass, as a multiple of the average bitrate.
+pub const MAXRATE_FACTOR: u64 = 2;
+
+/// The rate-control buffer of a capped pass, in seconds at the average
+/// bitrate.
+pub const BUFFER_SECONDS: u64 = 2;
+
+/// Where the first pass writes the output it throws away.
+pub const NULL_OUTPUT: &str = if cfg!(windows) { "NUL" } else { "/dev/null" };
+
+/// How many passes an encode runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Passes {
+    #[default]
+    One,
+    Two,
+    /// One where two were asked for, with the rate capped.
+    Capped,
+}
+
+/// The passes of an encode `length` seconds long with `encoder`, split
+/// into `chunks`, and with `--two-pass` when `requested`; when two passes
+/// were asked for but won't run, also why not.
+pub fn plan(
+    requested: bool,
+    length: f64,
+    encoder: VideoEncoder,
+    chunks: u32,
+) -> (Passes, Option<String>) {
+    if !requested {
+        return (Passes::One, None);
+    }
+    let reason = if !encoder.supports_two_pass() {
+        format!("{} can't run two passes through ffmpeg", encoder)
+    } else if chunks > 1 {
+        "--chunked-encode encodes each chunk in one pass".to_string()
+    } else if length < MIN_LENGTH {
+        format!(
+            "the encode is only {:.1}s, under the {}s where a first pass pays off",
+            length, MIN_LENGTH
+        )
+    } else {
+        return (Passes::Two, None);
+    };
+    (Passes::Capped, Some(reason))
+}
+
+/// `-maxrate` and `-bufsize` for a capped pass at `kbps`.
+pub fn rate_cap_args(kbps: u64) -> Vec<String> {
+    vec![
+        "-maxrate".to_string(),
+        format!("{}k", kbps * MAXRATE_FACTOR),
+        "-bufsize".to_string(),
+        format!("{}k", kbps * BUFFER_SECONDS),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fallbacks_in_order() {
+        // (requested, length, encoder, chunks, passes, reason contains)
+        let cases = [
+            (false, 600.0, VideoEncoder::H264, 1, Passes::One, None),
+            (false, 5.0, VideoEncoder::SvtAv1, 1, Passes::One, None),
+            (true, 600.0, VideoEncoder::H264, 1, Passes::Two, None),
+            (true, MIN_LENGTH, VideoEncoder::H264, 1, Passes::Two, None),
+            (
+                true,
+                5.0,
+                VideoEncoder::H264,
+                1,
+                Passes::Capped,
+                Some("only 5.0s, under the 15s"),
+            ),
+            (
+                true,
+                600.0,
+                VideoEncoder::H264,
+                4,
+                Passes::Capped,
+                Some("--chunked-encode"),
+            ),
+            // The encoder is the first reason given.
+            (
+                true,
+                5.0,
+                VideoEncoder::SvtAv1,
+                4,
+                Passes::Capped,
+                Some("libsvtav1 can't run two passes"),
+            ),
+        ];
+        for (requested, length, encoder, chunks, passes, reason) in cases {
+            let (planned, why) = plan(requested, length, encoder, chunks);
+            assert_eq!(planned, passes, "{} {} {}", length, encoder, chunks);
+            match reason {
+                Some(reason) => assert!(why.as_deref().unwrap().contains(reason), "{:?}", why),
+                None => assert_eq!(why, None),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoder_capabilities() {
+        let expected = [(VideoEncoder::H264, true), (VideoEncoder::SvtAv1, false)];
+        for (encoder, two_pass) in expected {
+            assert_eq!(encoder.supports_two_pass(), two_pass, "{}", encoder);
+        }
+    }
+
+    #[test]
+    fn test_rate_cap_follows_the_bitrate() {
+        assert_eq!(
+            rate_cap_args(1000),
+            ["-maxrate", "2000k", "-bufsize", "2000k"]
+        );
+    }
+}
@@ -0,0 +1,353 @@
+//! Inputs ffmpeg can probe but not transcode: encrypted streams, codecs it
+//! has no decoder for, and files that hold a picture instead of video.
+//!
+//! Without this check they fail deep inside the encode with pages of
+//! decoder errors. Each case is one entry in [`RULES`]; add new ones there.
+
+use crate::probe::VideoInfo;
+
+/// What a rule looks at in a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Match {
+    /// The container's codec tag (`codec_tag_string`).
+    Tag(&'static str),
+    /// ffprobe's codec name.
+    Codec(&'static str),
+    /// The stream is cover art (`disposition.attached_pic`).
+    AttachedPic,
+}
+
+/// Which streams a rule applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Video,
+    Audio,
+    Any,
+}
+
+struct Rule {
+    kind: Kind,
+    matches: Match,
+    /// Completes "stream N (video, codec) ...".
+    problem: &'static str,
+}
+
+const ENCRYPTED: &str = "is encrypted (Common Encryption); ffmpeg cannot decode it without the key";
+const FAIRPLAY: &str = "is protected by Apple FairPlay DRM, which ffmpeg cannot decode";
+const UNKNOWN_CODEC: &str =
+    "uses a codec ffmpeg has no decoder for; convert it with the software that made it first";
+const STILL: &str = "is a single image, not a video; use --loop-duration to turn it into one";
+
+const RULES: &[Rule] = &[
+    Rule {
+        kind: Kind::Video,
+        matches: Match::Tag("encv"),
+        problem: ENCRYPTED,
+    },
+    Rule {
+        kind: Kind::Audio,
+        matches: Match::Tag("enca"),
+        problem: ENCRYPTED,
+    },
+    Rule {
+        kind: Kind::Video,
+        matches: Match::Tag("drmi"),
+        problem: FAIRPLAY,
+    },
+    Rule {
+        kind: Kind::Audio,
+        matches: Match::Tag("drms"),
+        problem: FAIRPLAY,
+    },
+    Rule {
+        kind: Kind::Any,
+        matches: Match::Codec("none"),
+        problem: UNKNOWN_CODEC,
+    },
+    Rule {
+        kind: Kind::Any,
+        matches: Match::Codec("unknown"),
+        problem: UNKNOWN_CODEC,
+    },
+    Rule {
+        kind: Kind::Video,
+        matches: Match::AttachedPic,
+        problem: "is cover art; the file is audio only and has no video to reduce",
+    },
+    Rule {
+        kind: Kind::Video,
+        matches: Match::Codec("png"),
+        problem: STILL,
+    },
+    Rule {
+        kind: Kind::Video,
+        matches: Match::Codec("bmp"),
+        problem: STILL,
+    },
+    Rule {
+        kind: Kind::Video,
+        matches: Match::Codec("tiff"),
+        problem: STILL,
+    },
+    Rule {
+        kind: Kind::Video,
+        matches: Match::Codec("jpegxl"),
+        problem: STILL,
+    },
+];
+
+/// What the rules need to know about one stream.
+struct Stream<'a> {
+    index: u32,
+    kind: Kind,
+    codec: Option<&'a str>,
+    tag: Option<&'a str>,
+    attached_pic: bool,
+}
+
+impl Stream<'_> {
+    fn matches(&self, rule: &Rule) -> bool {
+        if rule.kind != Kind::Any && rule.kind != self.kind {
+            return false;
+        }
+        match rule.matches {
+            Match::Tag(tag) => self.tag.is_some_and(|t| t.eq_ignore_ascii_case(tag)),
+            Match::Codec(codec) => self.codec == Some(codec),
+            Match::AttachedPic => self.attached_pic,
+        }
+    }
+
+    fn describe(&self, problem: &str) -> String {
+        let kind = if self.kind == Kind::Video {
+            "video"
+        } else {
+            "audio"
+        };
+        let codec = self.codec.unwrap_or("unknown");
+        match self.tag {
+            Some(tag) if !tag.is_empty() && !tag.starts_with("[0]") => format!(
+                "stream {} ({}, {} '{}') {}",
+                self.index, kind, codec, tag, problem
+            ),
+            _ => format!("stream {} ({}, {}) {}", self.index, kind, codec, problem),
+        }
+    }
+}
+
+/// Fails with a message naming the stream and its codec when the probed
+/// input is one ffmpeg is known not to transcode.
+pub fn check(info: &VideoInfo) -> Result<(), String> {
+    let video = Stream {
+        index: info.index,
+        kind: Kind::Video,
+        codec: info.codec_name.as_deref(),
+        tag: info.codec_tag_string.as_deref(),
+        attached_pic: info.disposition.attached_pic != 0,
+    };
+    let audio = info.audio_streams.iter().flatten().map(|a| Stream {
+        index: a.index,
+        kind: Kind::Audio,
+        codec: a.codec_name.as_deref(),
+        tag: a.codec_tag_string.as_deref(),
+        attached_pic: false,
+    });
+    for stream in std::iter::once(video).chain(audio) {
+        if let Some(rule) = RULES.iter().find(|rule| stream.matches(rule)) {
+            return Err(stream.describe(rule.problem));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{parse_audio_streams, parse_video_info};
+
+    /// `check` on the video and audio probes of one file.
+    fn check_fixture(video: &str, audio: &str) -> Result<(), String> {
+        let mut info = parse_video_info(video).unwrap();
+        info.audio_streams = Some(parse_audio_streams(audio).unwrap());
+        check(&info)
+    }
+
+    const PLAIN_VIDEO: &str = r#"{
+        "programs": [],
+        "streams": [
+            {
+                "index": 0,
+                "codec_name": "h264",
+                "codec_tag_string": "avc1",
+                "width": 1920,
+                "height": 1080,
+                "r_frame_rate": "30/1",
+                "avg_frame_rate": "30/1",
+                "disposition": {"attached_pic": 0}
+            }
+        ]
+    }"#;
+
+    const PLAIN_AUDIO: &str = r#"{
+        "programs": [],
+        "streams": [
+            {"index": 1, "codec_name": "aac", "codec_tag_string": "mp4a", "channels": 2}
+        ]
+    }"#;
+
+    /// A DASH segment protected with CENC: the sample entries are `encv`
+    /// and `enca` instead of `avc1` and `mp4a`.
+    const CENC_VIDEO: &str = r#"{
+        "programs": [],
+        "streams": [
+            {
+                "index": 0,
+                "codec_name": "h264",
+                "codec_tag_string": "encv",
+                "width": 1280,
+                "height": 720,
+                "r_frame_rate": "25/1",
+                "avg_frame_rate": "25/1",
+                "disposition": {"attached_pic": 0}
+            }
+        ]
+    }"#;
+
+    const CENC_AUDIO: &str = r#"{
+        "programs": [],
+        "streams": [
+            {"index": 1, "codec_name": "aac", "codec_tag_string": "enca", "channels": 2}
+        ]
+    }"#;
+
+    /// An iTunes rental: the video is `drmi`, the audio `drms`.
+    const FAIRPLAY_VIDEO: &str = r#"{
+        "programs": [],
+        "streams": [
+            {
+                "index": 0,
+                "codec_name": "h264",
+                "codec_tag_string": "drmi",
+                "width": 1920,
+                "height": 800,
+                "disposition": {"attached_pic": 0}
+            }
+        ]
+    }"#;
+
+    const FAIRPLAY_AUDIO: &str = r#"{
+        "programs": [],
+        "streams": [
+            {"index": 1, "codec_name": "aac", "codec_tag_string": "drms", "channels": 2}
+        ]
+    }"#;
+
+    /// An MP3 with embedded artwork: the only video stream is the cover.
+    const COVER_ART_VIDEO: &str = r#"{
+        "programs": [],
+        "streams": [
+            {
+                "index": 1,
+                "codec_name": "mjpeg",
+                "codec_tag_string": "[0][0][0][0]",
+                "width": 600,
+                "height": 600,
+                "r_frame_rate": "90000/1",
+                "avg_frame_rate": "0/0",
+                "disposition": {"attached_pic": 1}
+            }
+        ]
+    }"#;
+
+    const PNG_STILL_VIDEO: &str = r#"{
+        "programs": [],
+        "streams": [
+            {
+                "index": 0,
+                "codec_name": "png",
+                "codec_tag_string": "[0][0][0][0]",
+                "width": 1024,
+                "height": 768,
+                "r_frame_rate": "25/1",
+                "avg_frame_rate": "0/0",
+                "disposition": {"attached_pic": 0}
+            }
+        ]
+    }"#;
+
+    /// An AVI with a FourCC ffmpeg doesn't know, which ffprobe reports as
+    /// codec `none`.
+    const UNKNOWN_FOURCC_VIDEO: &str = r#"{
+        "programs": [],
+        "streams": [
+            {
+                "index": 0,
+                "codec_name": "none",
+                "codec_tag_string": "XVD3",
+                "width": 720,
+                "height": 576,
+                "disposition": {"attached_pic": 0}
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn test_ordinary_input_passes() {
+        assert_eq!(check_fixture(PLAIN_VIDEO, PLAIN_AUDIO), Ok(()));
+        assert_eq!(check_fixture(PLAIN_VIDEO, r#"{"streams": []}"#), Ok(()));
+        // The mock and older probes leave everything out.
+        assert_eq!(check(&VideoInfo::default()), Ok(()));
+    }
+
+    #[test]
+    fn test_encrypted_streams_are_named() {
+        assert_eq!(
+            check_fixture(CENC_VIDEO, CENC_AUDIO).unwrap_err(),
+            "stream 0 (video, h264 'encv') is encrypted (Common Encryption); \
+             ffmpeg cannot decode it without the key"
+        );
+        let err = check_fixture(PLAIN_VIDEO, CENC_AUDIO).unwrap_err();
+        assert!(
+            err.starts_with("stream 1 (audio, aac 'enca') is encrypted"),
+            "{}",
+            err
+        );
+        let err = check_fixture(FAIRPLAY_VIDEO, FAIRPLAY_AUDIO).unwrap_err();
+        assert!(err.starts_with("stream 0 (video, h264 'drmi')"), "{}", err);
+        assert!(err.contains("FairPlay"), "{}", err);
+        let err = check_fixture(PLAIN_VIDEO, FAIRPLAY_AUDIO).unwrap_err();
+        assert!(err.starts_with("stream 1 (audio, aac 'drms')"), "{}", err);
+    }
+
+    #[test]
+    fn test_pictures_are_not_video() {
+        let err = check_fixture(COVER_ART_VIDEO, PLAIN_AUDIO).unwrap_err();
+        assert_eq!(
+            err,
+            "stream 1 (video, mjpeg) is cover art; the file is audio only and has no video to reduce"
+        );
+        let err = check_fixture(PNG_STILL_VIDEO, r#"{"streams": []}"#).unwrap_err();
+        assert!(
+            err.starts_with("stream 0 (video, png) is a single image"),
+            "{}",
+            err
+        );
+        assert!(err.contains("--loop-duration"), "{}", err);
+    }
+
+    #[test]
+    fn test_unknown_codecs_are_named() {
+        let err = check_fixture(UNKNOWN_FOURCC_VIDEO, PLAIN_AUDIO).unwrap_err();
+        assert!(
+            err.starts_with("stream 0 (video, none 'XVD3') uses a codec"),
+            "{}",
+            err
+        );
+        let audio = r#"{"streams": [{"index": 2, "codec_name": "unknown", "channels": 2}]}"#;
+        let err = check_fixture(PLAIN_VIDEO, audio).unwrap_err();
+        assert!(
+            err.starts_with("stream 2 (audio, unknown) uses a codec"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! What a run cost in compute: the CPU time its ffmpeg and ffprobe
+//! processes used, the most memory one of them held, and the wall time.
+//!
+//! On Unix the numbers come from `getrusage(RUSAGE_CHILDREN)`, which adds
+//! up every child process once it has been waited for, so a run reads it
+//! before and after and keeps the difference. That needs no change to how
+//! children are started or awaited, and covers the parallel encodes of
+//! `--chunked-encode` too. The peak memory can't be split the same way:
+//! it is the largest child this process ever waited for, so in a batch a
+//! file shows at least the peak of the ones before it. Elsewhere only the
+//! wall time is measured.
+
+use crate::estimate::format_duration;
+use crate::sizefmt::SizeFormat;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// The compute a run, or a whole batch, took.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Usage {
+    /// Seconds from start to finish.
+    pub wall_s: f64,
+    /// Seconds of CPU time, user and system, of the child processes; absent
+    /// where the platform doesn't tell.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_s: Option<f64>,
+    /// The most memory one child process had resident, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_rss_bytes: Option<u64>,
+}
+
+impl Usage {
+    /// `self` and `other` together: times add up, the peak is the larger.
+    pub fn plus(self, other: Usage) -> Usage {
+        let sum = |a: Option<f64>, b: Option<f64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        Usage {
+            wall_s: self.wall_s + other.wall_s,
+            cpu_s: sum(self.cpu_s, other.cpu_s),
+            peak_rss_bytes: self.peak_rss_bytes.max(other.peak_rss_bytes),
+        }
+    }
+
+    /// One line for the status output, e.g. `CPU time 12:40 over 3:10 of
+    /// wall time (4.0 cores), peak memory 412 MiB`.
+    pub fn describe(&self, sizes: SizeFormat) -> String {
+        let wall = format_duration(self.wall_s);
+        let mut line = match self.cpu_s {
+            Some(cpu) if self.wall_s > 0.0 => format!(
+                "CPU time {} over {} of wall time ({:.1} cores)",
+                format_duration(cpu),
+                wall,
+                cpu / self.wall_s
+            ),
+            _ => format!("Wall time {}", wall),
+        };
+        if let Some(bytes) = self.peak_rss_bytes {
+            line.push_str(&format!(", peak memory {}", sizes.size(bytes)));
+        }
+        line
+    }
+}
+
+/// Measures from when it is started.
+#[derive(Debug)]
+pub struct Meter {
+    started: Instant,
+    children_before: Option<Children>,
+}
+
+impl Meter {
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            children_before: children(),
+        }
+    }
+
+    /// What was used since [`Meter::start`].
+    pub fn finish(&self) -> Usage {
+        let after = children();
+        let cpu_s = match (self.children_before, after) {
+            (Some(before), Some(after)) => Some((after.cpu_s - before.cpu_s).max(0.0)),
+            _ => None,
+        };
+        Usage {
+            wall_s: self.started.elapsed().as_secs_f64(),
+            cpu_s,
+            // Zero until a child has been waited for.
+            peak_rss_bytes: after.map(|a| a.peak_rss_bytes).filter(|&bytes| bytes > 0),
+        }
+    }
+}
+
+/// The totals over every child process waited for so far.
+#[derive(Debug, Clone, Copy)]
+struct Children {
+    cpu_s: f64,
+    peak_rss_bytes: u64,
+}
+
+#[cfg(unix)]
+fn children() -> Option<Children> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    // SAFETY: getrusage only writes the struct it is given, which is
+    // zeroed and of the right type.
+    if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: the call succeeded, so it filled the struct in.
+    let usage = unsafe { usage.assume_init() };
+    let seconds = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1_000_000.0;
+    // Linux and the BSDs report kilobytes, macOS bytes.
+    let rss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    Some(Children {
+        cpu_s: seconds(usage.ru_utime) + seconds(usage.ru_stime),
+        peak_rss_bytes: (usage.ru_maxrss.max(0) as u64).saturating_mul(rss_unit),
+    })
+}
+
+#[cfg(not(unix))]
+fn children() -> Option<Children> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::size::SizeUnits;
+
+    #[test]
+    fn test_usage_adds_up_and_describes_itself() {
+        let a = Usage {
+            wall_s: 190.0,
+            cpu_s: Some(760.0),
+            peak_rss_bytes: Some(412 * 1024 * 1024),
+        };
+        let b = Usage {
+            wall_s: 10.0,
+            cpu_s: None,
+            peak_rss_bytes: Some(1024),
+        };
+        assert_eq!(
+            a.describe(SizeUnits::Binary.into()),
+            "CPU time 12:40 over 3:10 of wall time (4.0 cores), peak memory 412 MiB"
+        );
+        let total = a.plus(b);
+        assert_eq!(total.wall_s, 200.0);
+        assert_eq!(total.cpu_s, Some(760.0));
+        assert_eq!(total.peak_rss_bytes, Some(412 * 1024 * 1024));
+        assert_eq!(
+            Usage::default().describe(SizeUnits::Binary.into()),
+            "Wall time 0:00"
+        );
+        assert_eq!(
+            serde_json::to_string(&Usage::default()).unwrap(),
+            r#"{"wall_s":0.0}"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_a_real_child_is_measured() {
+        let meter = Meter::start();
+        let status = std::process::Command::new("sh")
+            .args(["-c", "i=0; while [ $i -lt 200000 ]; do i=$((i+1)); done"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let usage = meter.finish();
+        assert!(usage.wall_s > 0.0);
+        assert!(usage.cpu_s.unwrap() > 0.0, "{:?}", usage);
+        assert!(usage.peak_rss_bytes.unwrap() > 0, "{:?}", usage);
+    }
+}
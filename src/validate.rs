@@ -0,0 +1,703 @@
+//! Checking a configuration before anything runs, with every problem at
+//! once.
+//!
+//! [`ReduceOptions::validate`] finds what would stop a run or change it
+//! under the user: options that contradict each other or are out of
+//! range, and given a probe of the input and what this ffmpeg can do,
+//! targets the input can't be reduced to and encoders it lacks. A wrapper
+//! shows them all before starting; the command line prints the errors in
+//! one go instead of stopping at the first. Each [`ValidationIssue`] names
+//! a stable [`Code`], the same one the run's warning would carry for those
+//! that are only warnings.
+
+use crate::audio;
+use crate::encoder::VideoEncoder;
+use crate::error::ReduceError;
+use crate::floor;
+use crate::probe::VideoInfo;
+use crate::reduce::{plan_encoding, ReduceOptions};
+use crate::tool::VideoTool;
+use crate::unsupported;
+use crate::warning;
+use crate::window;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Whether a [`ValidationIssue`] stops the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The run goes ahead, differently from what was asked.
+    Warning,
+    /// The run would fail.
+    Error,
+}
+
+/// What a [`ValidationIssue`] is about. The `snake_case` names are part of the JSON
+/// output, so existing ones must not change; those of warnings are the
+/// names of the [`crate::warning::Code`] the run would warn with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Code {
+    /// A number option is zero or below, or not a number.
+    InvalidValue,
+    /// `--x264-params` or `--svtav1-params` without its encoder.
+    EncoderParamsMismatch,
+    /// `--chunked-encode` with an option it can't be combined with.
+    ChunkedEncodeConflict,
+    /// `--window-start` is past the end of the input.
+    WindowOutsideInput,
+    /// The input is of a kind the tool can't reduce.
+    UnsupportedInput,
+    /// `--audio-track` names a track the input doesn't have.
+    NoSuchAudioTrack,
+    /// `--region` doesn't lie within the input's frame.
+    RegionOutsideFrame,
+    /// The target can't reach `--min-quality`.
+    BelowQualityFloor,
+    /// The planned bits per pixel predict heavy artifacts; an error with
+    /// `--fail-on-poor-quality`.
+    PoorQuality,
+    /// The target is too small for the input's length.
+    BitrateClamped,
+    /// This ffmpeg lacks the video encoder asked for.
+    EncoderFallback,
+    /// This ffmpeg lacks the hardware decoder asked for.
+    #[serde(rename = "hwdecode_fallback")]
+    HwDecodeFallback,
+}
+
+/// One problem with a configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationIssue {
+    pub code: Code,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl ValidationIssue {
+    fn error(code: Code, message: impl Into<String>) -> Self {
+        ValidationIssue {
+            code,
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(code: Code, message: impl Into<String>) -> Self {
+        ValidationIssue {
+            code,
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+/// What this ffmpeg can do, as far as it is known; what isn't known isn't
+/// checked.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Capabilities {
+    /// The encoders `ffmpeg -encoders` lists.
+    pub encoders: Option<Vec<String>>,
+    /// The hardware decoders `ffmpeg -hwaccels` lists.
+    pub hwaccels: Option<Vec<String>>,
+}
+
+impl Capabilities {
+    /// What `tool` can do that `opts` depends on; ffmpeg is only asked
+    /// about encoders for another than H.264, which every build has, and
+    /// about hardware decoders when `--hwdecode` wants one.
+    pub fn of<T: VideoTool>(tool: &T, opts: &ReduceOptions) -> Self {
+        Capabilities {
+            encoders: (opts.encoder != VideoEncoder::H264)
+                .then(|| tool.list_encoders().ok())
+                .flatten(),
+            hwaccels: opts
+                .hwdecode
+                .accel()
+                .and_then(|_| tool.list_hwaccels().ok()),
+        }
+    }
+}
+
+/// Why `--chunked-encode` can't be used with `opts`, as what it needs
+/// instead; `None` when it can. An output on stdout is the caller's to
+/// check.
+pub fn chunked_conflict(opts: &ReduceOptions) -> Option<&'static str> {
+    if opts.chunks <= 1 {
+        None
+    } else if opts.parts > 1 {
+        Some("a single output, not --split")
+    } else if opts.sample.is_some() {
+        Some("the whole input, not --sample")
+    } else if opts.image.is_some() {
+        Some("a video input, not images")
+    } else if opts.max_duration.is_some() || opts.window_start.is_some() {
+        Some("the whole input, not --max-duration or --window-start")
+    } else if opts.loop_safe {
+        Some("a plain encode, not --loop-safe")
+    } else {
+        None
+    }
+}
+
+impl ReduceOptions {
+    /// Every problem with these options, errors first; with `probe`, also
+    /// those with reducing that input, and with `caps`, those with this
+    /// ffmpeg. Nothing is run.
+    pub fn validate(&self, probe: Option<&VideoInfo>, caps: &Capabilities) -> Vec<ValidationIssue> {
+        let mut issues = self.option_issues();
+        if let Some(info) = probe {
+            issues.extend(self.input_issues(info));
+        }
+        issues.extend(self.capability_issues(caps));
+        // Stable, so each severity keeps the order found.
+        issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity));
+        issues
+    }
+
+    fn option_issues(&self) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        let positive = |value: f64| value.is_finite() && value > 0.0;
+        let numbers = [
+            ("--max-fps", self.max_fps),
+            ("--cfr", self.cfr.flatten()),
+            ("--duration", self.duration),
+            ("--sample", self.sample),
+            ("--max-duration", self.max_duration),
+        ];
+        for (flag, value) in numbers {
+            if value.is_some_and(|value| !positive(value)) {
+                issues.push(ValidationIssue::error(
+                    Code::InvalidValue,
+                    format!("{} must be greater than zero", flag),
+                ));
+            }
+        }
+        if let Some(params) = &self.encoder_params {
+            if params.encoder != self.encoder {
+                issues.push(ValidationIssue::error(
+                    Code::EncoderParamsMismatch,
+                    format!(
+                        "--{} needs --codec {}",
+                        params.encoder.params_option(),
+                        params
+                            .encoder
+                            .to_possible_value()
+                            .expect("no skipped encoders")
+                            .get_name()
+                    ),
+                ));
+            }
+        }
+        if let Some(conflict) = chunked_conflict(self) {
+            issues.push(ValidationIssue::error(
+                Code::ChunkedEncodeConflict,
+                format!("--chunked-encode needs {}", conflict),
+            ));
+        }
+        issues
+    }
+
+    fn input_issues(&self, info: &VideoInfo) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        if let Err(e) = unsupported::check(info) {
+            // Nothing else about an input that can't be read matters.
+            return vec![ValidationIssue::error(
+                Code::UnsupportedInput,
+                format!("cannot reduce the input: {}", e),
+            )];
+        }
+        if !self.no_audio {
+            if let Err(e) = audio::check_selection(info, self.audio_tracks) {
+                issues.push(ValidationIssue::error(Code::NoSuchAudioTrack, e));
+            }
+        }
+        let cropped;
+        let info = match self.region {
+            Some(region) => {
+                if let Some(problem) = region.misfit(info) {
+                    // What the plan would crop to isn't known.
+                    issues.push(ValidationIssue::error(Code::RegionOutsideFrame, problem));
+                    return issues;
+                }
+                cropped = region.crop(info);
+                &cropped
+            }
+            None => info,
+        };
+        let Some(duration) = self.duration.or_else(|| info.stream_duration()) else {
+            return issues;
+        };
+        let window = match window::choose(
+            duration,
+            self.max_duration,
+            self.window_start,
+            self.parts.max(1),
+        ) {
+            Ok(window) => window,
+            Err(e) => {
+                issues.push(ValidationIssue::error(Code::WindowOutsideInput, e));
+                return issues;
+            }
+        };
+        let opts = ReduceOptions {
+            parts: window.parts,
+            ..self.clone()
+        };
+        let plan = plan_encoding(window.length, info, &opts);
+        let clamped = plan
+            .warnings
+            .iter()
+            .find(|w| w.code == warning::Code::BitrateClamped);
+        if let Some(clamped) = clamped {
+            issues.push(ValidationIssue::warning(
+                Code::BitrateClamped,
+                &clamped.message,
+            ));
+        }
+        if let Some(floor) = self.min_quality.filter(|_| !plan.copy_video) {
+            let refusal = floor::refusal(
+                floor,
+                self.encoder.codec(),
+                plan.video_bitrate,
+                plan.width,
+                plan.height,
+                plan.fps,
+            );
+            if let Some(message) = refusal {
+                issues.push(ValidationIssue::error(Code::BelowQualityFloor, message));
+            }
+        }
+        if let Some(warning) = &plan.poor_quality {
+            issues.push(if self.fail_on_poor_quality {
+                ValidationIssue::error(
+                    Code::PoorQuality,
+                    format!(
+                        "{} (stopping because of --fail-on-poor-quality)",
+                        warning.message
+                    ),
+                )
+            } else {
+                ValidationIssue::warning(Code::PoorQuality, &warning.message)
+            });
+        }
+        issues
+    }
+
+    fn capability_issues(&self, caps: &Capabilities) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        let listed = |names: &[String], name: &str| names.iter().any(|n| n == name);
+        if let Some(encoders) = &caps.encoders {
+            if self.encoder != VideoEncoder::H264 && !listed(encoders, self.encoder.ffmpeg_name()) {
+                issues.push(ValidationIssue::warning(
+                    Code::EncoderFallback,
+                    format!(
+                        "this ffmpeg has no {} encoder (check `ffmpeg -encoders`); H.264 would be used instead",
+                        self.encoder
+                    ),
+                ));
+            }
+        }
+        if let (Some(hwaccels), Some(accel)) = (&caps.hwaccels, self.hwdecode.accel()) {
+            if !listed(hwaccels, accel.name()) {
+                issues.push(ValidationIssue::warning(
+                    Code::HwDecodeFallback,
+                    format!(
+                        "this ffmpeg has no {} decoding (check `ffmpeg -hwaccels`); the input would be decoded in software",
+                        accel
+                    ),
+                ));
+            }
+        }
+        issues
+    }
+}
+
+/// The errors among `issues` as one [`ReduceError`] listing them all, or
+/// `Ok` when there are none. It is of the kind the run would fail with at
+/// the first of them, so the exit code doesn't change with validating.
+pub fn check(issues: &[ValidationIssue]) -> Result<(), ReduceError> {
+    let errors: Vec<&ValidationIssue> = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .collect();
+    let Some(first) = errors.first() else {
+        return Ok(());
+    };
+    let message = match errors.as_slice() {
+        [only] => only.message.clone(),
+        all => format!(
+            "{} problems stop the run:\n  {}",
+            all.len(),
+            all.iter()
+                .map(|issue| issue.message.as_str())
+                .collect::<Vec<_>>()
+                .join("\n  ")
+        ),
+    };
+    Err(match first.code {
+        Code::UnsupportedInput => ReduceError::Probe(message),
+        Code::BelowQualityFloor => ReduceError::BelowQualityFloor(message),
+        _ => ReduceError::Usage(message),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioSelection;
+    use crate::encoder::EncoderParams;
+    use crate::floor::Floor;
+    use crate::hwdecode::HwDecode;
+    use crate::images::ImageInput;
+    use crate::probe::AudioStream;
+    use crate::region;
+    use crate::testing::{mib, MockVideoTool};
+    use Severity::{Error, Warning};
+
+    /// A configuration and what validating it should find, in order.
+    struct Case {
+        name: &'static str,
+        /// Changes from 100 MiB of a 100-second 1080p input with one audio
+        /// track, on an ffmpeg of unknown abilities.
+        setup: fn(&mut ReduceOptions, &mut VideoInfo, &mut Capabilities),
+        probed: bool,
+        found: &'static [(Code, Severity)],
+    }
+
+    const CASES: &[Case] = &[
+        Case {
+            name: "nothing wrong",
+            setup: |_, _, _| {},
+            probed: true,
+            found: &[],
+        },
+        Case {
+            name: "zero duration",
+            setup: |opts, _, _| opts.duration = Some(0.0),
+            probed: false,
+            found: &[(Code::InvalidValue, Error)],
+        },
+        Case {
+            name: "negative fps cap and a NaN frame rate",
+            setup: |opts, _, _| {
+                opts.max_fps = Some(-1.0);
+                opts.cfr = Some(Some(f64::NAN));
+            },
+            probed: false,
+            found: &[(Code::InvalidValue, Error), (Code::InvalidValue, Error)],
+        },
+        Case {
+            name: "--cfr without a rate",
+            setup: |opts, _, _| opts.cfr = Some(None),
+            probed: false,
+            found: &[],
+        },
+        Case {
+            name: "zero sample and window",
+            setup: |opts, _, _| {
+                opts.sample = Some(0.0);
+                opts.max_duration = Some(0.0);
+            },
+            probed: false,
+            found: &[(Code::InvalidValue, Error), (Code::InvalidValue, Error)],
+        },
+        Case {
+            name: "svt-av1 params for x264",
+            setup: |opts, _, _| {
+                opts.encoder_params =
+                    Some(EncoderParams::parse(VideoEncoder::SvtAv1, "tune=0").unwrap());
+            },
+            probed: false,
+            found: &[(Code::EncoderParamsMismatch, Error)],
+        },
+        Case {
+            name: "chunks of a split",
+            setup: |opts, _, _| {
+                opts.chunks = 4;
+                opts.parts = 2;
+            },
+            probed: false,
+            found: &[(Code::ChunkedEncodeConflict, Error)],
+        },
+        Case {
+            name: "chunks of a sample",
+            setup: |opts, _, _| {
+                opts.chunks = 4;
+                opts.sample = Some(30.0);
+            },
+            probed: false,
+            found: &[(Code::ChunkedEncodeConflict, Error)],
+        },
+        Case {
+            name: "chunks of images",
+            setup: |opts, _, _| {
+                opts.chunks = 4;
+                opts.image = Some(ImageInput::Sequence { fps: 24.0 });
+            },
+            probed: false,
+            found: &[(Code::ChunkedEncodeConflict, Error)],
+        },
+        Case {
+            name: "chunks of a loop",
+            setup: |opts, _, _| {
+                opts.chunks = 4;
+                opts.loop_safe = true;
+            },
+            probed: false,
+            found: &[(Code::ChunkedEncodeConflict, Error)],
+        },
+        Case {
+            name: "chunks of a window",
+            setup: |opts, _, _| {
+                opts.chunks = 4;
+                opts.window_start = Some(10.0);
+            },
+            probed: false,
+            found: &[(Code::ChunkedEncodeConflict, Error)],
+        },
+        Case {
+            name: "an encrypted input hides everything else about it",
+            setup: |opts, info, _| {
+                info.codec_tag_string = Some("encv".into());
+                opts.audio_tracks = AudioSelection::Track(3);
+                opts.duration = Some(0.0);
+            },
+            probed: true,
+            found: &[(Code::InvalidValue, Error), (Code::UnsupportedInput, Error)],
+        },
+        Case {
+            name: "a third audio track of one",
+            setup: |opts, _, _| opts.audio_tracks = AudioSelection::Track(3),
+            probed: true,
+            found: &[(Code::NoSuchAudioTrack, Error)],
+        },
+        Case {
+            name: "an audio track with --no-audio",
+            setup: |opts, _, _| {
+                opts.audio_tracks = AudioSelection::Track(3);
+                opts.no_audio = true;
+            },
+            probed: true,
+            found: &[],
+        },
+        Case {
+            name: "a region off the frame",
+            setup: |opts, _, _| {
+                opts.region = region::parse("1280x720+1280+0").ok();
+                opts.target_bytes = mib(5);
+            },
+            probed: true,
+            found: &[(Code::RegionOutsideFrame, Error)],
+        },
+        Case {
+            name: "a region off the frame found without a probe",
+            setup: |opts, _, _| opts.region = region::parse("1280x720+1280+0").ok(),
+            probed: false,
+            found: &[],
+        },
+        Case {
+            name: "a small region in a target too small for the frame",
+            setup: |opts, _, _| {
+                opts.region = region::parse("480x270+0+0").ok();
+                opts.target_bytes = mib(5);
+            },
+            probed: true,
+            found: &[],
+        },
+        Case {
+            name: "a window past the end",
+            setup: |opts, _, _| {
+                opts.max_duration = Some(10.0);
+                opts.window_start = Some(200.0);
+            },
+            probed: true,
+            found: &[(Code::WindowOutsideInput, Error)],
+        },
+        Case {
+            name: "a window past the end found without a probe",
+            setup: |opts, _, _| {
+                opts.max_duration = Some(10.0);
+                opts.window_start = Some(200.0);
+            },
+            probed: false,
+            found: &[],
+        },
+        Case {
+            name: "a long input in a small target",
+            setup: |opts, info, _| {
+                opts.target_bytes = mib(50);
+                info.duration = Some("10000".into());
+            },
+            probed: true,
+            found: &[
+                (Code::BitrateClamped, Warning),
+                (Code::PoorQuality, Warning),
+            ],
+        },
+        Case {
+            name: "poor quality with --fail-on-poor-quality",
+            setup: |opts, _, _| {
+                opts.target_bytes = mib(5);
+                opts.fail_on_poor_quality = true;
+            },
+            probed: true,
+            found: &[(Code::PoorQuality, Error)],
+        },
+        Case {
+            name: "a quality floor the target can't reach, errors first",
+            setup: |opts, info, _| {
+                opts.target_bytes = mib(50);
+                opts.min_quality = Some(Floor::Bpp(0.5));
+                info.duration = Some("10000".into());
+            },
+            probed: true,
+            found: &[
+                (Code::BelowQualityFloor, Error),
+                (Code::BitrateClamped, Warning),
+                (Code::PoorQuality, Warning),
+            ],
+        },
+        Case {
+            name: "the duration option outranks the probe",
+            setup: |opts, info, _| {
+                opts.duration = Some(100.0);
+                info.duration = Some("10000".into());
+            },
+            probed: true,
+            found: &[],
+        },
+        Case {
+            name: "an ffmpeg without SVT-AV1",
+            setup: |opts, _, caps| {
+                opts.encoder = VideoEncoder::SvtAv1;
+                caps.encoders = Some(vec!["libx264".into()]);
+            },
+            probed: false,
+            found: &[(Code::EncoderFallback, Warning)],
+        },
+        Case {
+            name: "an ffmpeg without CUDA",
+            setup: |opts, _, caps| {
+                opts.hwdecode = HwDecode::Cuda;
+                caps.hwaccels = Some(vec!["vaapi".into()]);
+            },
+            probed: false,
+            found: &[(Code::HwDecodeFallback, Warning)],
+        },
+        Case {
+            name: "an ffmpeg with CUDA and SVT-AV1",
+            setup: |opts, _, caps| {
+                opts.encoder = VideoEncoder::SvtAv1;
+                opts.hwdecode = HwDecode::Cuda;
+                caps.encoders = Some(vec!["libsvtav1".into()]);
+                caps.hwaccels = Some(vec!["cuda".into()]);
+            },
+            probed: false,
+            found: &[],
+        },
+        Case {
+            name: "an ffmpeg of unknown abilities",
+            setup: |opts, _, _| {
+                opts.encoder = VideoEncoder::SvtAv1;
+                opts.hwdecode = HwDecode::Cuda;
+            },
+            probed: false,
+            found: &[],
+        },
+    ];
+
+    #[test]
+    fn test_validation_finds_each_known_problem() {
+        for case in CASES {
+            let mut opts = ReduceOptions::new(mib(100));
+            let mut info = MockVideoTool::new(100.0).info;
+            info.duration = Some("100".into());
+            info.audio_streams = Some(vec![AudioStream::default()]);
+            let mut caps = Capabilities::default();
+            (case.setup)(&mut opts, &mut info, &mut caps);
+            let probe = case.probed.then_some(&info);
+            let found: Vec<(Code, Severity)> = opts
+                .validate(probe, &caps)
+                .iter()
+                .map(|issue| (issue.code, issue.severity))
+                .collect();
+            assert_eq!(found, case.found, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn test_messages_say_what_to_change() {
+        let mut opts = ReduceOptions::new(mib(100));
+        opts.encoder_params = Some(EncoderParams::parse(VideoEncoder::SvtAv1, "tune=0").unwrap());
+        opts.chunks = 4;
+        opts.sample = Some(30.0);
+        let messages: Vec<String> = opts
+            .validate(None, &Capabilities::default())
+            .into_iter()
+            .map(|issue| issue.message)
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "--svtav1-params needs --codec svt-av1",
+                "--chunked-encode needs the whole input, not --sample",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_fails_with_every_error_and_no_warning() {
+        let issues = [
+            ValidationIssue::warning(Code::BitrateClamped, "clamped"),
+            ValidationIssue::error(Code::InvalidValue, "--duration must be greater than zero"),
+        ];
+        assert_eq!(
+            check(&issues).unwrap_err().to_string(),
+            "--duration must be greater than zero"
+        );
+        let issues = [
+            ValidationIssue::error(Code::InvalidValue, "--duration must be greater than zero"),
+            ValidationIssue::error(
+                Code::NoSuchAudioTrack,
+                "--audio-track 3: the input has 1 audio track",
+            ),
+        ];
+        assert_eq!(
+            check(&issues).unwrap_err().to_string(),
+            "2 problems stop the run:\n  --duration must be greater than zero\n  --audio-track 3: the input has 1 audio track"
+        );
+        assert!(check(&issues[..0]).is_ok());
+        let refused = [ValidationIssue::error(
+            Code::BelowQualityFloor,
+            "needs 80 MB",
+        )];
+        assert_eq!(check(&refused).unwrap_err().exit_code(), 10);
+        assert!(check(&[ValidationIssue::warning(Code::PoorQuality, "poor")]).is_ok());
+    }
+
+    #[test]
+    fn test_issues_serialize_with_stable_names() {
+        let issue = ValidationIssue::warning(Code::HwDecodeFallback, "software");
+        assert_eq!(
+            serde_json::to_string(&issue).unwrap(),
+            r#"{"code":"hwdecode_fallback","severity":"warning","message":"software"}"#
+        );
+        let shared = [
+            (Code::BitrateClamped, warning::Code::BitrateClamped),
+            (Code::PoorQuality, warning::Code::PoorQuality),
+            (Code::EncoderFallback, warning::Code::EncoderFallback),
+            (Code::HwDecodeFallback, warning::Code::HwDecodeFallback),
+        ];
+        for (code, warned) in shared {
+            assert_eq!(
+                serde_json::to_value(code).unwrap(),
+                warned.as_str(),
+                "{:?}",
+                code
+            );
+            assert_eq!(serde_json::to_value(warned).unwrap(), warned.as_str());
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! `--vertical`: a 9:16 frame for Shorts, Reels and TikTok.
+//!
+//! The output is a portrait canvas of at most 1080x1920. A source that is
+//! wider than 9:16 (any landscape video) is scaled to the canvas width and
+//! letterboxed, or with `--vertical-crop` cut down to its centered 9:16
+//! part; a narrower one is scaled to the canvas height and pillarboxed.
+//! Sizes are worked out with square pixels, so an anamorphic source is
+//! measured as it is displayed, and every side is even.
+
+use crate::aspect::{self, Ratio};
+
+/// Widest canvas, the 1080p portrait frame the platforms recommend.
+pub const MAX_WIDTH: u32 = 1080;
+
+/// Tallest canvas.
+pub const MAX_HEIGHT: u32 = 1920;
+
+/// What happens to a source wider than 9:16.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    /// Keep the whole picture, with black bars above and below.
+    Pad,
+    /// Cut the sides off, keeping the center.
+    Crop,
+}
+
+/// A rectangle of the stored (not displayed) source frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Where the picture goes on the portrait frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    /// The part of the source kept, with [`Fit::Crop`].
+    pub crop: Option<Crop>,
+    /// The size the picture is scaled to, centered on the canvas; the
+    /// canvas size when it fills it.
+    pub picture: (u32, u32),
+}
+
+impl Canvas {
+    /// Lays a stored `width`x`height` frame with `sar` out on a portrait
+    /// canvas.
+    pub fn of(width: u32, height: u32, sar: Ratio, fit: Fit) -> Self {
+        let (display_width, display_height) = aspect::square_pixel_size(width, height, sar);
+        let wider = display_width as u64 * 16 > display_height as u64 * 9;
+        if wider && fit == Fit::Crop {
+            // 9:16 of the displayed height, in stored columns.
+            let display_crop = display_height as f64 * 9.0 / 16.0;
+            let crop_width = even(display_crop * sar.den as f64 / sar.num as f64).min(width);
+            let height_out = display_height.min(MAX_HEIGHT) & !1;
+            let canvas = (even(height_out as f64 * 9.0 / 16.0), height_out);
+            return Self {
+                width: canvas.0,
+                height: canvas.1,
+                crop: Some(Crop {
+                    width: crop_width,
+                    height,
+                    x: ((width - crop_width) / 2) & !1,
+                    y: 0,
+                }),
+                picture: canvas,
+            };
+        }
+        let (canvas, picture) = if wider {
+            let width_out = display_width.min(MAX_WIDTH) & !1;
+            let picture_height =
+                even(display_height as f64 * width_out as f64 / display_width as f64);
+            (
+                (width_out, even(width_out as f64 * 16.0 / 9.0)),
+                (width_out, picture_height),
+            )
+        } else {
+            let height_out = display_height.min(MAX_HEIGHT) & !1;
+            let picture_width =
+                even(display_width as f64 * height_out as f64 / display_height as f64);
+            (
+                (even(height_out as f64 * 9.0 / 16.0), height_out),
+                (
+                    picture_width.min(even(height_out as f64 * 9.0 / 16.0)),
+                    height_out,
+                ),
+            )
+        };
+        Self {
+            width: canvas.0,
+            height: canvas.1,
+            crop: None,
+            picture,
+        }
+    }
+
+    /// Whether the picture leaves bars on the canvas.
+    pub fn is_boxed(&self) -> bool {
+        self.picture != (self.width, self.height)
+    }
+}
+
+/// `side` rounded to the nearest even size, at least 2.
+fn even(side: f64) -> u32 {
+    (((side / 2.0).round() * 2.0) as u32).max(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_landscape_is_letterboxed_or_cropped() {
+        let padded = Canvas::of(1920, 1080, Ratio::SQUARE, Fit::Pad);
+        assert_eq!((padded.width, padded.height), (1080, 1920));
+        assert_eq!(padded.picture, (1080, 608));
+        assert_eq!(padded.crop, None);
+        assert!(padded.is_boxed());
+
+        let cropped = Canvas::of(1920, 1080, Ratio::SQUARE, Fit::Crop);
+        assert_eq!((cropped.width, cropped.height), (608, 1080));
+        assert_eq!(
+            cropped.crop,
+            Some(Crop {
+                width: 608,
+                height: 1080,
+                x: 656,
+                y: 0
+            })
+        );
+        assert!(!cropped.is_boxed());
+
+        // A 4K source is cropped at full height, then capped.
+        let cropped = Canvas::of(3840, 2160, Ratio::SQUARE, Fit::Crop);
+        assert_eq!((cropped.width, cropped.height), (1080, 1920));
+        assert_eq!(cropped.crop.unwrap().width, 1216);
+    }
+
+    #[test]
+    fn test_vertical_sources_are_only_capped() {
+        let canvas = Canvas::of(1080, 1920, Ratio::SQUARE, Fit::Crop);
+        assert_eq!((canvas.width, canvas.height), (1080, 1920));
+        assert_eq!(canvas.crop, None);
+        assert!(!canvas.is_boxed());
+
+        let canvas = Canvas::of(2160, 3840, Ratio::SQUARE, Fit::Pad);
+        assert_eq!((canvas.width, canvas.height), (1080, 1920));
+        assert!(!canvas.is_boxed());
+
+        // A phone screen recording, taller than 9:16, is pillarboxed.
+        let canvas = Canvas::of(1080, 2340, Ratio::SQUARE, Fit::Crop);
+        assert_eq!((canvas.width, canvas.height), (1080, 1920));
+        assert_eq!(canvas.picture, (886, 1920));
+        assert_eq!(canvas.crop, None);
+    }
+
+    #[test]
+    fn test_portrait_wider_than_nine_sixteenths() {
+        // 3:4 is still wider than 9:16, and never scaled up.
+        let canvas = Canvas::of(960, 1280, Ratio::SQUARE, Fit::Pad);
+        assert_eq!((canvas.width, canvas.height), (960, 1706));
+        assert_eq!(canvas.picture, (960, 1280));
+        let canvas = Canvas::of(720, 960, Ratio::SQUARE, Fit::Crop);
+        assert_eq!((canvas.width, canvas.height), (540, 960));
+        assert_eq!(canvas.crop.unwrap().x, 90);
+    }
+
+    #[test]
+    fn test_anamorphic_sources_are_measured_as_displayed() {
+        // A 16:9 PAL DVD: 720x576 stored, 1024x576 displayed.
+        let sar = Ratio { num: 64, den: 45 };
+        let padded = Canvas::of(720, 576, sar, Fit::Pad);
+        assert_eq!((padded.width, padded.height), (1024, 1820));
+        assert_eq!(padded.picture, (1024, 576));
+
+        // The 324 displayed columns kept are 228 stored ones.
+        let cropped = Canvas::of(720, 576, sar, Fit::Crop);
+        assert_eq!((cropped.width, cropped.height), (324, 576));
+        let crop = cropped.crop.unwrap();
+        assert_eq!((crop.width, crop.x), (228, 246));
+    }
+
+    #[test]
+    fn test_every_side_is_even() {
+        for (width, height) in [(1279, 719), (641, 361), (333, 999), (1919, 1081)] {
+            for fit in [Fit::Pad, Fit::Crop] {
+                let canvas = Canvas::of(width, height, Ratio::SQUARE, fit);
+                let crop = canvas.crop.map_or((0, 0), |c| (c.width, c.x));
+                for side in [
+                    canvas.width,
+                    canvas.height,
+                    canvas.picture.0,
+                    canvas.picture.1,
+                    crop.0,
+                    crop.1,
+                ] {
+                    assert_eq!(side % 2, 0, "{}x{} {:?}: {:?}", width, height, fit, canvas);
+                }
+            }
+        }
+    }
+}
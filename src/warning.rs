@@ -0,0 +1,221 @@
+//! Warnings raised during a run, kept for the final report.
+//!
+//! A warning is printed where it happens, like any status line, and also
+//! recorded with a stable [`Code`] so `--output-format json` and batch
+//! summaries can list them afterwards. A single-file run holds them back
+//! instead, and prints them together ahead of its `Done:` line. Recording goes to a per-thread list
+//! rather than through every function's signature; [`reduce_video`] clears
+//! it when it starts and moves it into its report at the end.
+//!
+//! [`reduce_video`]: crate::reduce::reduce_video
+
+use crate::events::{self, Event};
+use crate::presenter::{Presenter, Style};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fmt;
+
+/// What a warning is about. The `snake_case` names are part of the JSON
+/// output, so existing ones must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Code {
+    /// The budget called for less than the minimum video bitrate.
+    BitrateClamped,
+    /// The planned bits per pixel predict heavy artifacts.
+    PoorQuality,
+    /// A surround track got less than its per-channel bitrate.
+    AudioDowngraded,
+    /// An attempt came out over the target and is being re-encoded.
+    OverTargetRetry,
+    /// `--duration` disagrees with what ffprobe reports.
+    DurationMismatch,
+    /// ffprobe had no duration, so the input is decoded to measure it.
+    DurationDecoded,
+    /// `--remux-only` couldn't remux and re-encoded instead.
+    RemuxFallback,
+    /// The requested encoder is missing from this ffmpeg.
+    EncoderFallback,
+    /// `--hwdecode` asked for a hardware decoder this ffmpeg lacks, or
+    /// that failed to start, so the input is decoded in software.
+    #[serde(rename = "hwdecode_fallback")]
+    HwDecodeFallback,
+    /// A faster preset was picked to fit `--max-encode-time`.
+    PresetSwitched,
+    /// `--preset` or a `preset` encoder option was given with `--effort`,
+    /// and wins over it.
+    EffortOverridden,
+    /// The output is only a `--sample`.
+    SampleOnly,
+    /// The input's attachments (usually subtitle fonts) don't fit the
+    /// output's container.
+    AttachmentsDropped,
+    /// `--keep-cover-art` was given, but the output has no place for the
+    /// input's cover art.
+    CoverArtDropped,
+    /// `--keep-data-streams` was given, but the output has no place for the
+    /// input's data streams.
+    DataStreamsDropped,
+    /// `--x264-params` or `--svtav1-params` set the bitrate or rate
+    /// control themselves.
+    EncoderParamsRateControl,
+    /// A disk the run writes to has little more room than the output needs.
+    LowDiskSpace,
+    /// The temp directory is on a filesystem that seeks poorly, and the
+    /// output can't be written fragmented.
+    UnseekableTempDir,
+    /// The audio runs past the end of the video, and the output is cut
+    /// where the video ends.
+    AudioPastVideo,
+    /// Probing or starting the encode failed in a way that may pass, and
+    /// is being retried (`--retries`).
+    TransientRetry,
+    /// `--two-pass` was asked for, but the encode runs in one pass.
+    TwoPassSkipped,
+    /// `--inhibit-sleep always` couldn't keep the system awake.
+    SleepNotInhibited,
+    /// `--deterministic` was asked for with an encoder that may not give
+    /// the same bytes twice.
+    NotBitExact,
+    /// A subtitle stream `--extract-subs` couldn't write to a file.
+    SubtitleNotExtracted,
+    /// `--max-duration` or `--window-start` left some of the input out.
+    Trimmed,
+    /// A `--loop-safe` output has a track that doesn't end with the video.
+    LoopNotSeamless,
+    /// ffprobe couldn't be run, so the input's durations were read from
+    /// its MP4 headers and the rest assumed.
+    ProbedWithoutFfprobe,
+    /// `--save-stats` couldn't keep the stats of the first pass.
+    StatsNotSaved,
+}
+
+impl Code {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Code::BitrateClamped => "bitrate_clamped",
+            Code::PoorQuality => "poor_quality",
+            Code::AudioDowngraded => "audio_downgraded",
+            Code::OverTargetRetry => "over_target_retry",
+            Code::DurationMismatch => "duration_mismatch",
+            Code::DurationDecoded => "duration_decoded",
+            Code::RemuxFallback => "remux_fallback",
+            Code::EncoderFallback => "encoder_fallback",
+            Code::HwDecodeFallback => "hwdecode_fallback",
+            Code::PresetSwitched => "preset_switched",
+            Code::EffortOverridden => "effort_overridden",
+            Code::SampleOnly => "sample_only",
+            Code::AttachmentsDropped => "attachments_dropped",
+            Code::CoverArtDropped => "cover_art_dropped",
+            Code::DataStreamsDropped => "data_streams_dropped",
+            Code::EncoderParamsRateControl => "encoder_params_rate_control",
+            Code::LowDiskSpace => "low_disk_space",
+            Code::UnseekableTempDir => "unseekable_temp_dir",
+            Code::AudioPastVideo => "audio_past_video",
+            Code::TransientRetry => "transient_retry",
+            Code::TwoPassSkipped => "two_pass_skipped",
+            Code::SleepNotInhibited => "sleep_not_inhibited",
+            Code::NotBitExact => "not_bit_exact",
+            Code::SubtitleNotExtracted => "subtitle_not_extracted",
+            Code::Trimmed => "trimmed",
+            Code::LoopNotSeamless => "loop_not_seamless",
+            Code::ProbedWithoutFfprobe => "probed_without_ffprobe",
+            Code::StatsNotSaved => "stats_not_saved",
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Warning {
+    pub code: Code,
+    /// The text printed after "Warning: ".
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+thread_local! {
+    static RECORDED: RefCell<Vec<Warning>> = const { RefCell::new(Vec::new()) };
+    /// With [`hold_back`], the warnings left for [`recap`] to print.
+    static HELD: RefCell<Option<Vec<Warning>>> = const { RefCell::new(None) };
+}
+
+/// Prints `warning` on `out`, unless warnings are held back for [`recap`],
+/// sends it as an event and records it.
+pub fn emit(out: Presenter, warning: Warning) {
+    HELD.with(|held| match held.borrow_mut().as_mut() {
+        Some(held) => held.push(warning.clone()),
+        None => out.warn(&warning.message),
+    });
+    if events::is_enabled() {
+        events::emit(&Event::Warning(warning.clone()));
+    }
+    RECORDED.with(|recorded| recorded.borrow_mut().push(warning));
+}
+
+/// Holds back the warnings of this thread from now on, as a single-file
+/// run does: they are only printed together by [`recap`].
+pub fn hold_back() {
+    HELD.with(|held| held.borrow_mut().get_or_insert_with(Vec::new).clear());
+}
+
+/// Prints the warnings held back since the last recap under a `Warnings:`
+/// heading, as a run does before its summary line.
+pub fn recap(out: Presenter) {
+    let held = HELD.with(|held| held.borrow_mut().as_mut().map(std::mem::take));
+    let Some(held) = held.filter(|held| !held.is_empty()) else {
+        return;
+    };
+    out.line(Style::Warning, "Warnings:");
+    for warning in held {
+        out.info(&format!("  {}", warning.message));
+    }
+}
+
+/// The warnings recorded on this thread since the last call, oldest first.
+pub fn take() -> Vec<Warning> {
+    RECORDED.with(|recorded| recorded.take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presenter::ColorChoice;
+
+    #[test]
+    fn test_emitted_warnings_are_taken_once() {
+        take();
+        let out = Presenter::stderr(ColorChoice::Never);
+        emit(out, Warning::new(Code::SampleOnly, "first"));
+        emit(out, Warning::new(Code::PoorQuality, "second"));
+        let codes: Vec<Code> = take().iter().map(|w| w.code).collect();
+        assert_eq!(codes, [Code::SampleOnly, Code::PoorQuality]);
+        assert!(take().is_empty());
+    }
+
+    #[test]
+    fn test_json_uses_the_code_names() {
+        let warning = Warning::new(Code::BitrateClamped, "too small");
+        assert_eq!(
+            serde_json::to_string(&warning).unwrap(),
+            r#"{"code":"bitrate_clamped","message":"too small"}"#
+        );
+        assert_eq!(
+            serde_json::to_value(Code::AudioDowngraded).unwrap(),
+            Code::AudioDowngraded.as_str()
+        );
+    }
+}
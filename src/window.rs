@@ -0,0 +1,158 @@
+//! `--max-duration` and `--window-start`: the stretch of the input that is
+//! encoded, for destinations that cap the length as well as the size.
+//!
+//! [`choose`] settles it once the input is probed, and everything after
+//! plans for the [`Window`] as if it were the whole input. The rules, in
+//! order:
+//!
+//! 1. `--window-start` skips that much of the input; it must start before
+//!    the end.
+//! 2. Without `--split`, `--max-duration` cuts what follows to at most
+//!    that long. An input that is already short enough is left whole.
+//! 3. With `--split`, nothing is cut: `--max-duration` caps each part
+//!    instead, raising the number of parts if the ones asked for would be
+//!    longer.
+//!
+//! `--sample` then encodes the start of the window.
+
+use crate::estimate::format_duration;
+
+/// What of the input is encoded, and in how many parts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    /// Seconds into the input.
+    pub start: f64,
+    /// Seconds from `start`, for all parts together.
+    pub length: f64,
+    pub parts: u32,
+}
+
+impl Window {
+    /// Whether it leaves out some of an input of `duration` seconds.
+    pub fn cuts(&self, duration: f64) -> bool {
+        self.start > 0.0 || self.start + self.length < duration
+    }
+
+    /// The stretch encoded, e.g. `0:10-0:40`.
+    pub fn describe(&self) -> String {
+        format!(
+            "{}-{}",
+            format_duration(self.start),
+            format_duration(self.start + self.length)
+        )
+    }
+}
+
+/// The window of an input of `duration` seconds for `max_duration`,
+/// `start` and `parts` (`--split`), by the rules above; the error says why
+/// the start is out of range.
+pub fn choose(
+    duration: f64,
+    max_duration: Option<f64>,
+    start: Option<f64>,
+    parts: u32,
+) -> Result<Window, String> {
+    let start = start.unwrap_or(0.0);
+    if start >= duration {
+        return Err(format!(
+            "--window-start {} is past the end of the input, which is {} long",
+            format_duration(start),
+            format_duration(duration)
+        ));
+    }
+    let rest = duration - start;
+    let parts = parts.max(1);
+    let Some(max) = max_duration else {
+        return Ok(Window {
+            start,
+            length: rest,
+            parts,
+        });
+    };
+    if parts == 1 {
+        return Ok(Window {
+            start,
+            length: rest.min(max),
+            parts,
+        });
+    }
+    // A hair over the cap from rounding isn't worth another part.
+    let needed = ((rest / max) - 1e-9).ceil().max(1.0) as u32;
+    Ok(Window {
+        start,
+        length: rest,
+        parts: parts.max(needed),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose() {
+        // (duration, --max-duration, --window-start, --split) and the
+        // window as (start, length, parts).
+        let cases = [
+            // Nothing asked for: all of it.
+            (120.0, None, None, 1, (0.0, 120.0, 1)),
+            // The tail is cut.
+            (120.0, Some(30.0), None, 1, (0.0, 30.0, 1)),
+            // A short input stays whole.
+            (20.0, Some(30.0), None, 1, (0.0, 20.0, 1)),
+            (30.0, Some(30.0), None, 1, (0.0, 30.0, 1)),
+            // The window moves, and ends with the input if it must.
+            (120.0, Some(30.0), Some(10.0), 1, (10.0, 30.0, 1)),
+            (120.0, Some(30.0), Some(100.0), 1, (100.0, 20.0, 1)),
+            // Only the start skipped.
+            (120.0, None, Some(100.0), 1, (100.0, 20.0, 1)),
+            // With --split it caps the parts instead: four parts of 30 s.
+            (120.0, Some(30.0), None, 2, (0.0, 120.0, 4)),
+            // More parts than the cap needs stay.
+            (120.0, Some(30.0), None, 6, (0.0, 120.0, 6)),
+            // Just over a multiple from rounding needs no extra part.
+            (
+                90.000_000_000_1,
+                Some(30.0),
+                None,
+                2,
+                (0.0, 90.000_000_000_1, 3),
+            ),
+            // The skipped start isn't split.
+            (120.0, Some(30.0), Some(60.0), 2, (60.0, 60.0, 2)),
+        ];
+        for (duration, max, start, parts, (want_start, want_length, want_parts)) in cases {
+            let window = choose(duration, max, start, parts).unwrap();
+            assert_eq!(
+                window,
+                Window {
+                    start: want_start,
+                    length: want_length,
+                    parts: want_parts,
+                },
+                "{:?}",
+                (duration, max, start, parts)
+            );
+        }
+    }
+
+    #[test]
+    fn test_start_must_be_inside_the_input() {
+        for start in [120.0, 500.0] {
+            let err = choose(120.0, Some(30.0), Some(start), 1).unwrap_err();
+            assert!(err.contains("which is 2:00 long"), "{}", err);
+        }
+        assert!(choose(120.0, None, Some(119.5), 1).is_ok());
+    }
+
+    #[test]
+    fn test_cuts() {
+        assert!(!choose(120.0, None, None, 1).unwrap().cuts(120.0));
+        assert!(!choose(20.0, Some(30.0), None, 1).unwrap().cuts(20.0));
+        assert!(choose(120.0, Some(30.0), None, 1).unwrap().cuts(120.0));
+        assert!(choose(120.0, None, Some(5.0), 1).unwrap().cuts(120.0));
+        assert!(!choose(120.0, Some(30.0), None, 2).unwrap().cuts(120.0));
+        let window = choose(120.0, Some(30.0), Some(10.0), 1).unwrap();
+        assert_eq!(window.describe(), "0:10-0:40");
+    }
+}
@@ -0,0 +1,274 @@
+//! `mdviqure batch` and colored status output.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn batch_reduces_into_output_dir_and_summarizes() {
+    let sb = Sandbox::new();
+    let a = sb.input("a.mp4");
+    let b = sb.input("b.mp4");
+    let out_dir = sb.work().join("reduced");
+    let output = sb
+        .command()
+        .arg("batch")
+        .arg(&a)
+        .arg(&b)
+        .arg("--output-dir")
+        .arg(&out_dir)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(out_dir.join("a.mp4").exists());
+    assert!(out_dir.join("b.mp4").exists());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Reduced 2 of 2 files"), "{}", stdout);
+    // Not a terminal, so no color codes.
+    assert!(!stdout.contains('\x1b'));
+}
+
+#[test]
+fn batch_jobs_reduce_several_files_at_once() {
+    let sb = Sandbox::new();
+    let inputs: Vec<_> = ["a.mp4", "b.mp4", "c.mp4"]
+        .iter()
+        .map(|name| sb.input(name))
+        .collect();
+    let out_dir = sb.work().join("reduced");
+    let output = sb
+        .command()
+        .arg("batch")
+        .args(&inputs)
+        .arg("--output-dir")
+        .arg(&out_dir)
+        .arg("--jobs")
+        .arg("2")
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Reduced 3 of 3 files"), "{}", stdout);
+    for name in ["a.mp4", "b.mp4", "c.mp4"] {
+        assert!(out_dir.join(name).exists(), "{}", name);
+    }
+
+    let output = sb
+        .command()
+        .arg("batch")
+        .args(&inputs)
+        .arg("--output-dir")
+        .arg(&out_dir)
+        .arg("--jobs")
+        .arg("0")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn batch_summary_lists_each_output_checksum() {
+    let sb = Sandbox::new();
+    let a = sb.input("a.mp4");
+    let out_dir = sb.work().join("reduced");
+    let output = sb
+        .command()
+        .arg("batch")
+        .arg(&a)
+        .arg("--output-dir")
+        .arg(&out_dir)
+        .arg("--checksum")
+        .arg("sha256")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let written = out_dir.join("a.mp4").display().to_string();
+    let hash = stdout
+        .lines()
+        .find_map(|line| line.strip_suffix(&format!("  {}", written)))
+        .unwrap_or_else(|| panic!("{}", stdout))
+        .trim_start();
+    assert_eq!(hash.len(), 64, "{}", stdout);
+    let row = stdout
+        .lines()
+        .find(|line| line.contains("a.mp4  ok "))
+        .unwrap_or_else(|| panic!("{}", stdout));
+    assert!(row.ends_with(hash), "{}", stdout);
+}
+
+#[test]
+fn batch_failure_sets_exit_code_and_continues() {
+    let sb = Sandbox::new();
+    let a = sb.input("a.mp4");
+    let missing = sb.work().join("missing.mp4");
+    let output = sb
+        .command()
+        .arg("batch")
+        .arg(&missing)
+        .arg(&a)
+        .arg("-o")
+        .arg(sb.work().join("reduced"))
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(4));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("probe failed"), "{}", stdout);
+    assert!(stdout.contains("Reduced 1 of 2 files"), "{}", stdout);
+}
+
+#[test]
+fn batch_runs_the_jobs_of_a_manifest() {
+    let sb = Sandbox::new();
+    sb.input("a.mp4");
+    sb.input("b.mp4");
+    let manifest = sb.work().join("jobs.toml");
+    std::fs::write(
+        &manifest,
+        r#"
+            [defaults]
+            target = "10MB"
+
+            [[jobs]]
+            name = "opening"
+            input = "a.mp4"
+            output = "out/first.mp4"
+
+            [[jobs]]
+            input = "b.mp4"
+            output = "out/second.mp4"
+            codec = "svt-av1"
+        "#,
+    )
+    .unwrap();
+    let output = sb
+        .command()
+        .arg("batch")
+        .arg("--manifest")
+        .arg(&manifest)
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(sb.work().join("out/first.mp4").exists());
+    assert!(sb.work().join("out/second.mp4").exists());
+    assert!(stdout.contains("[1/2] opening"), "{}", stdout);
+    assert!(stdout.contains("Reduced 2 of 2 files"), "{}", stdout);
+}
+
+#[test]
+fn manifest_mistakes_stop_the_batch_before_encoding() {
+    let sb = Sandbox::new();
+    sb.input("a.mp4");
+    let manifest = sb.work().join("jobs.json");
+    std::fs::write(
+        &manifest,
+        r#"{"jobs": [
+            {"input": "a.mp4", "output": "out.mp4"},
+            {"name": "gone", "input": "missing.mp4", "output": "out.mp4"}
+        ]}"#,
+    )
+    .unwrap();
+    let output = sb
+        .command()
+        .arg("batch")
+        .arg("--manifest")
+        .arg(&manifest)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("job 2 (gone): no such input"), "{}", stderr);
+    assert!(
+        stderr.contains("job 2 (gone): job 1 writes to"),
+        "{}",
+        stderr
+    );
+    assert!(!sb.work().join("out.mp4").exists());
+}
+
+#[test]
+fn color_always_colors_status_lines() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .args(["--color", "always"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("\x1b[32mDone: "), "{}", stdout);
+}
+
+#[test]
+fn errors_are_red_only_when_colored() {
+    let sb = Sandbox::new();
+    let missing = sb.work().join("missing.mp4");
+    let out = sb.work().join("out.mp4");
+    let colored = sb
+        .command()
+        .arg(&missing)
+        .arg(&out)
+        .args(["--color", "always"])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&colored.stderr).contains("\x1b[31mError: "));
+
+    let plain = sb
+        .command()
+        .arg(&missing)
+        .arg(&out)
+        .args(["--color", "never"])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&plain.stderr).starts_with("Error: "));
+}
+
+#[test]
+fn archive_layout_files_by_the_recording_date_in_the_chosen_zone() {
+    let sb = Sandbox::new();
+    let clip = sb.input("clip.mov");
+    // Half past eleven at night in UTC is the next morning in Tokyo.
+    let probe = r#"{"streams":[{"width":640,"height":360,"avg_frame_rate":"30/1"}],
+        "format":{"tags":{"creation_time":"2024-03-31T23:30:00.000000Z"}}}"#;
+    for (zone, month) in [("utc", "2024/03"), ("local", "2024/04")] {
+        let archive = sb.work().join(zone);
+        let output = sb
+            .command()
+            .arg("batch")
+            .arg(&clip)
+            .arg("-o")
+            .arg(&archive)
+            .args(["--archive-layout", "date", "--archive-tz", zone])
+            .args(["--name-template", "{recorded}_{stem}.mp4"])
+            .env("STUB_PROBE_JSON", probe)
+            .env("TZ", "JST-9")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let day = if zone == "utc" {
+            "2024-03-31"
+        } else {
+            "2024-04-01"
+        };
+        assert!(archive
+            .join(month)
+            .join(format!("{}_clip.mp4", day))
+            .exists());
+    }
+}
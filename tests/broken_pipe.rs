@@ -0,0 +1,94 @@
+//! Runs whose status or events go to a pipe nobody reads any more, as when
+//! piped into `head`.
+#![cfg(unix)]
+
+mod common;
+
+use common::{entries, Sandbox};
+use std::process::{Command, Output};
+use std::time::{Duration, Instant};
+
+/// Runs `command` with stdout a pipe whose read end is already closed.
+fn run_into_closed_pipe(mut command: Command) -> Output {
+    let (reader, writer) = std::io::pipe().unwrap();
+    drop(reader);
+    command.stdout(writer).output().unwrap()
+}
+
+/// Whether a `sleep` of `seconds`, as the stub ffmpeg runs it, is still
+/// running anywhere.
+fn sleeping(seconds: &str) -> bool {
+    let wanted = format!("sleep\0{}\0", seconds);
+    std::fs::read_dir("/proc")
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter_map(|entry| std::fs::read(entry.path().join("cmdline")).ok())
+        .any(|cmdline| cmdline == wanted.as_bytes())
+}
+
+#[test]
+fn a_closed_stdout_only_drops_the_status() {
+    let sb = Sandbox::new();
+    let output = sb.work().join("out.mp4");
+    let mut command = sb.command();
+    command.arg(sb.input("in.mp4")).arg(&output);
+    let result = run_into_closed_pipe(command);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert_eq!(result.status.code(), Some(0), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert_eq!(std::fs::metadata(&output).unwrap().len(), 1000);
+    assert!(entries(&sb.tmp()).is_empty());
+}
+
+#[test]
+fn abort_on_broken_pipe_cancels_the_encode_and_cleans_up() {
+    let sb = Sandbox::new();
+    let output = sb.work().join("out.mp4");
+    // A length no other test sleeps for, to look for it afterwards.
+    let sleep = "47";
+    let mut command = sb.command();
+    command
+        .arg(sb.input("in.mp4"))
+        .arg(&output)
+        .arg("--abort-on-broken-pipe")
+        .env("STUB_FFMPEG_SLEEP", sleep);
+    let started = Instant::now();
+    let result = run_into_closed_pipe(command);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert_eq!(result.status.code(), Some(11), "{}", stderr);
+    assert!(stderr.contains("--abort-on-broken-pipe"), "{}", stderr);
+    // ffmpeg was killed, not waited for or left behind.
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert!(!sleeping(sleep));
+    assert!(!output.exists());
+    assert_eq!(entries(&sb.work()), ["in.mp4"]);
+    assert!(entries(&sb.tmp()).is_empty());
+}
+
+#[test]
+fn closed_events_stop_a_batch_with_abort_on_broken_pipe() {
+    let sb = Sandbox::new();
+    let mut command = sb.command();
+    command
+        .arg("batch")
+        .arg(sb.input("a.mp4"))
+        .arg(sb.input("b.mp4"))
+        .arg("--output-dir")
+        .arg(sb.work().join("out"))
+        .args(["--progress-json=1", "--abort-on-broken-pipe"]);
+    let result = run_into_closed_pipe(command);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert_eq!(result.status.code(), Some(11), "{}", stderr);
+    let written = sb.work().join("out");
+    let names = if written.exists() {
+        entries(&written)
+    } else {
+        Vec::new()
+    };
+    assert!(
+        names.iter().all(|name| !name.ends_with(".mp4")),
+        "{:?}",
+        names
+    );
+    assert!(entries(&sb.tmp()).is_empty());
+}
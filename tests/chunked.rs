@@ -0,0 +1,76 @@
+//! `--chunked-encode` running its chunks as parallel ffmpeg processes.
+#![cfg(unix)]
+
+mod common;
+
+use common::{entries, Sandbox};
+use std::time::{Duration, Instant};
+
+#[test]
+fn chunks_are_encoded_at_once_and_joined() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let log = sb.work().join("ffmpeg.log");
+    let started = Instant::now();
+    let output = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .args(["--chunked-encode=3", "--keep-temp"])
+        .env("STUB_DURATION", "60.0")
+        .env("STUB_FFMPEG_LOG", &log)
+        .env("STUB_FFMPEG_SLEEP", "1")
+        .output()
+        .unwrap();
+    let elapsed = started.elapsed();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("Encoding in 3 chunks of about 0:20 at once"),
+        "{}",
+        stdout
+    );
+    let logged = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = logged.lines().collect();
+    assert_eq!(lines.len(), 5, "{}", logged);
+    assert_eq!(lines.iter().filter(|l| l.contains(" -an ")).count(), 3);
+    assert_eq!(lines.iter().filter(|l| l.contains(" -vn ")).count(), 1);
+    assert!(lines[4].starts_with("-y -f concat"), "{}", lines[4]);
+    // Four one-second jobs side by side, then the one-second join.
+    assert!(elapsed < Duration::from_secs(4), "took {:?}", elapsed);
+
+    let run_dir = sb.tmp().join(&entries(&sb.tmp())[0]);
+    let list = entries(&run_dir)
+        .into_iter()
+        .find(|name| name.starts_with("chunks-"))
+        .unwrap();
+    let list = std::fs::read_to_string(run_dir.join(list)).unwrap();
+    assert_eq!(list.lines().count(), 3);
+    assert!(list
+        .lines()
+        .all(|l| l.starts_with("file '") && l.ends_with(".mkv'")));
+}
+
+#[test]
+fn a_join_shorter_than_the_input_fails() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .args(["--chunked-encode=2", "--duration", "120"])
+        .env("STUB_DURATION", "60.0")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("the joined chunks last 1:00 but the input lasts 2:00"),
+        "{}",
+        stderr
+    );
+    assert!(!sb.work().join("out.mp4").exists());
+}
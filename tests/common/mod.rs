@@ -0,0 +1,272 @@
+//! Helpers for running the real binary against stub ffmpeg/ffprobe scripts.
+//!
+//! The stubs are small POSIX shell scripts placed first on `PATH`, so these
+//! tests need neither ffmpeg nor real media files. Their behavior is tuned
+//! through `STUB_*` environment variables on the spawned command. Inputs
+//! given as `http://` URLs are fetched with curl, so a test server sees
+//! every read.
+
+#![allow(dead_code)]
+
+pub mod mp4;
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const FFPROBE: &str = r#"#!/bin/sh
+for last; do :; done
+case "$last" in
+  http://*)
+    if ! curl -sf -o /dev/null "$last"; then
+      echo "$last: Server returned 404 Not Found" >&2
+      exit 1
+    fi
+    ;;
+  *)
+    if [ ! -e "$last" ]; then echo "$last: No such file or directory" >&2; exit 1; fi
+    ;;
+esac
+case "$*" in
+  *format=duration*) echo "${STUB_DURATION:-10.0}" ;;
+  *format=size*)
+    if [ -n "$STUB_STREAMS_JSON" ]; then
+      echo "$STUB_STREAMS_JSON"
+    elif [ -n "$STUB_PROBE_JSON" ]; then
+      echo "$STUB_PROBE_JSON"
+    else
+      echo '{"streams":[{"width":640,"height":360,"avg_frame_rate":"30/1"}]}'
+    fi
+    ;;
+  *"-select_streams s "*)
+    if [ -n "$STUB_SUBTITLE_JSON" ]; then
+      echo "$STUB_SUBTITLE_JSON"
+    else
+      echo '{"streams":[]}'
+    fi
+    ;;
+  *"-select_streams d "*)
+    if [ -n "$STUB_DATA_JSON" ]; then
+      echo "$STUB_DATA_JSON"
+    else
+      echo '{"streams":[]}'
+    fi
+    ;;
+  *"-select_streams a "*)
+    if [ -n "$STUB_AUDIO_JSON" ]; then
+      echo "$STUB_AUDIO_JSON"
+    else
+      echo '{"streams":[{"index":1,"codec_name":"aac","channels":2}]}'
+    fi
+    ;;
+  *)
+    if [ -n "$STUB_PROBE_JSON" ]; then
+      echo "$STUB_PROBE_JSON"
+    else
+      echo '{"streams":[{"width":640,"height":360,"avg_frame_rate":"30/1"}]}'
+    fi
+    ;;
+esac
+if [ -n "$STUB_FFPROBE_STDERR" ]; then echo "$STUB_FFPROBE_STDERR" >&2; fi
+exit "${STUB_FFPROBE_EXIT:-0}"
+"#;
+
+const FFMPEG: &str = r#"#!/bin/sh
+input=
+passlog=
+prev=
+for last; do
+  if [ "$prev" = "-i" ]; then input=$last; fi
+  if [ "$prev" = "-passlogfile" ]; then passlog=$last; fi
+  prev=$last
+done
+if [ "$last" = "-encoders" ]; then
+  echo " ------"
+  for encoder in ${STUB_ENCODERS:-libx264 libsvtav1 aac libopus libmp3lame}; do
+    echo " V..... $encoder $encoder"
+  done
+  exit 0
+fi
+if [ "$last" = "-hwaccels" ]; then
+  echo "Hardware acceleration methods:"
+  for accel in $STUB_HWACCELS; do echo "$accel"; done
+  exit 0
+fi
+if [ -n "$STUB_FFMPEG_LOG" ]; then echo "$*" >> "$STUB_FFMPEG_LOG"; fi
+# A first pass leaves its stats where libx264 would.
+case " $* " in
+  *" -pass 1 "*) echo "stub stats" > "$passlog-0.log" ;;
+esac
+if [ -n "$STUB_BROKEN_HWACCEL" ]; then
+  case " $* " in
+    *" -hwaccel "*)
+      echo "[hevc @ 0x5581] Failed setup for format cuda: hwaccel initialisation returned error." >&2
+      exit 1 ;;
+  esac
+fi
+case "$input" in
+  http://*) curl -sf -o /dev/null "$input" || exit 1 ;;
+esac
+if [ -n "$STUB_FFMPEG_STDERR" ]; then echo "$STUB_FFMPEG_STDERR" >&2; fi
+if [ -n "$STUB_FFMPEG_CHATTER" ]; then
+  i=1
+  while [ "$i" -le "$STUB_FFMPEG_CHATTER" ]; do
+    echo "[debug] chatter line $i of many, as -loglevel debug prints them" >&2
+    i=$((i + 1))
+  done
+fi
+if [ "$last" = "pipe:1" ]; then
+  head -c "${STUB_OUTPUT_BYTES:-1000}" /dev/zero
+  exit "${STUB_FFMPEG_EXIT:-0}"
+fi
+# An HLS playlist: a header and two segments beside it, the bytes split
+# between them.
+case "$last" in
+  *.m3u8)
+    dir=$(dirname "$last")
+    bytes=${STUB_OUTPUT_BYTES:-1000}
+    truncate -s 100 "$dir/init.mp4"
+    truncate -s $((bytes / 2)) "$dir/segment00000.m4s" "$dir/segment00001.m4s"
+    printf '#EXTM3U\n#EXT-X-MAP:URI="init.mp4"\n#EXTINF:6.0,\nsegment00000.m4s\n#EXTINF:4.0,\nsegment00001.m4s\n#EXT-X-ENDLIST\n' > "$last"
+    echo "progress=end"
+    exit "${STUB_FFMPEG_EXIT:-0}" ;;
+esac
+# "-" is the null muxer of a decode-through duration measurement.
+if [ "$last" != "-" ]; then truncate -s "${STUB_OUTPUT_BYTES:-1000}" "$last"; fi
+if [ -n "$STUB_FFMPEG_SLEEP" ]; then exec sleep "$STUB_FFMPEG_SLEEP"; fi
+if [ -n "$STUB_PROGRESS_NOISE" ]; then printf 'not \377\376 UTF-8\r\nframe=  150 fps=30\n'; fi
+echo "out_time_us=5000000"
+echo "speed=2.0x"
+echo "progress=end"
+exit "${STUB_FFMPEG_EXIT:-0}"
+"#;
+
+/// A scratch directory holding the stub `bin/` and a `work/` area.
+pub struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "mdviqure-it-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::create_dir_all(root.join("work")).unwrap();
+        std::fs::create_dir_all(root.join("tmp")).unwrap();
+        let sandbox = Self { root };
+        sandbox.write_script("ffprobe", FFPROBE);
+        sandbox.write_script("ffmpeg", FFMPEG);
+        sandbox
+    }
+
+    /// Replaces (or adds) an executable in the stub `bin/` directory.
+    pub fn write_script(&self, name: &str, body: &str) {
+        let path = self.root.join("bin").join(name);
+        std::fs::write(&path, body).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    /// Removes a stub so the tool can't find it on `PATH`.
+    pub fn remove_script(&self, name: &str) {
+        let _ = std::fs::remove_file(self.root.join("bin").join(name));
+    }
+
+    /// Directory holding the stubs, for tests that call the library and
+    /// so need it on their own `PATH`.
+    pub fn bin(&self) -> PathBuf {
+        self.root.join("bin")
+    }
+
+    /// Directory for inputs and outputs.
+    pub fn work(&self) -> PathBuf {
+        self.root.join("work")
+    }
+
+    /// The default history file of the sandboxed runs.
+    pub fn history(&self) -> PathBuf {
+        self.root
+            .join("config")
+            .join("mdviqure")
+            .join("history.jsonl")
+    }
+
+    /// Directory passed as `--temp-dir`.
+    pub fn tmp(&self) -> PathBuf {
+        self.root.join("tmp")
+    }
+
+    /// Creates an input file in the work directory and returns its path.
+    pub fn input(&self, name: &str) -> PathBuf {
+        let path = self.work().join(name);
+        std::fs::write(&path, b"not really a video").unwrap();
+        path
+    }
+
+    /// A command running the real binary with only the stubs (plus /bin for
+    /// the shell utilities they use) on `PATH`.
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_mdviqure"));
+        self.sandboxed(&mut cmd);
+        cmd
+    }
+
+    /// A command running `script` with `sh -c` in the environment of
+    /// [`command`](Self::command), with the binary as `$MDVIQURE`. Further
+    /// arguments become `$1` and on.
+    pub fn shell(&self, script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        self.sandboxed(&mut cmd);
+        cmd.env("MDVIQURE", env!("CARGO_BIN_EXE_mdviqure"))
+            .args(["-c", script, "sh"]);
+        cmd
+    }
+
+    fn sandboxed(&self, cmd: &mut Command) {
+        cmd.env(
+            "PATH",
+            format!("{}:/bin:/usr/bin", self.root.join("bin").display()),
+        );
+        cmd.env("TMPDIR", self.tmp());
+        cmd.env("XDG_CONFIG_HOME", self.root.join("config"));
+        cmd.env_remove("NO_COLOR");
+        cmd.env_remove("CLICOLOR_FORCE");
+        // Sizes are grouped as the locale does; keep it the same everywhere.
+        cmd.env("LC_ALL", "C");
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Names of the entries in `dir`, sorted.
+pub fn entries(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Polls `condition` until it holds or `timeout` elapses.
+pub fn wait_until(timeout: Duration, mut condition: impl FnMut() -> bool) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if condition() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    condition()
+}
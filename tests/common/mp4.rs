@@ -0,0 +1,38 @@
+//! Builders for the MP4 boxes [`mdviqure::mp4box`] reads, shared by its
+//! unit tests (through `crate::testing`) and the integration tests.
+
+/// A box of `kind` around `body`, with a 32-bit size.
+pub fn boxed(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut data = (8 + body.len() as u32).to_be_bytes().to_vec();
+    data.extend(kind);
+    data.extend(body);
+    data
+}
+
+/// An `mvhd` or `mdhd` body of version 0, which lay out timescale and
+/// duration alike, padded as `mvhd` is.
+pub fn header(timescale: u32, duration: u32) -> Vec<u8> {
+    let mut body = vec![0; 12];
+    body.extend(timescale.to_be_bytes());
+    body.extend(duration.to_be_bytes());
+    body.resize(100, 0);
+    body
+}
+
+/// The same of version 1, with a 64-bit duration.
+pub fn header_v1(timescale: u32, duration: u64) -> Vec<u8> {
+    let mut body = vec![1, 0, 0, 0];
+    body.extend([0; 16]);
+    body.extend(timescale.to_be_bytes());
+    body.extend(duration.to_be_bytes());
+    body
+}
+
+/// A track with a `handler` such as `vide` and the `mdhd` body given.
+pub fn trak(handler: &[u8; 4], mdhd: &[u8]) -> Vec<u8> {
+    let mut hdlr = vec![0; 8];
+    hdlr.extend(handler);
+    hdlr.resize(25, 0);
+    let mdia = [boxed(b"mdhd", mdhd), boxed(b"hdlr", &hdlr)].concat();
+    boxed(b"trak", &boxed(b"mdia", &mdia))
+}
@@ -0,0 +1,62 @@
+//! An M4V whose cover art comes before the film.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+
+/// What the stubbed ffprobe says about the video streams: the poster
+/// first, then the film.
+const POSTER_FIRST: &str = r#"{"streams": [
+    {"index": 0, "codec_name": "mjpeg", "width": 600, "height": 900,
+     "disposition": {"attached_pic": 1}},
+    {"index": 1, "codec_name": "h264", "width": 1280, "height": 720,
+     "avg_frame_rate": "24/1", "disposition": {"attached_pic": 0}}
+]}"#;
+
+/// Runs a reduction of `film.m4v` with `extra` and returns the ffmpeg
+/// command it ran and what it printed.
+fn run(extra: &[&str]) -> (String, String) {
+    let sb = Sandbox::new();
+    let log = sb.work().join("ffmpeg.log");
+    let result = sb
+        .command()
+        .arg(sb.input("film.m4v"))
+        .arg(sb.work().join("film.mp4"))
+        .args(extra)
+        .args(["--verbose"])
+        .env("STUB_PROBE_JSON", POSTER_FIRST)
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+    let printed = format!(
+        "{}{}",
+        String::from_utf8_lossy(&result.stdout),
+        String::from_utf8_lossy(&result.stderr)
+    );
+    assert!(result.status.success(), "{}", printed);
+    (std::fs::read_to_string(&log).unwrap(), printed)
+}
+
+#[test]
+fn the_film_is_reduced_and_the_poster_left_out() {
+    let (command, printed) = run(&[]);
+    assert!(printed.contains("Source: 1280x720"), "{}", printed);
+    assert!(
+        printed.contains("Leaving out the input's cover art"),
+        "{}",
+        printed
+    );
+    assert!(command.contains(" -map 0:V:0 "), "{}", command);
+    assert!(!command.contains("attached_pic"), "{}", command);
+}
+
+#[test]
+fn keep_cover_art_copies_the_poster() {
+    let (command, _) = run(&["--keep-cover-art"]);
+    assert!(
+        command.contains(" -map 0:V:0 -map 0:0 -c:v:1 copy -disposition:v:1 attached_pic "),
+        "{}",
+        command
+    );
+}
@@ -0,0 +1,77 @@
+//! A GoPro clip, with its timecode, telemetry and recovery tracks.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+
+/// What the stubbed ffprobe says about the data streams of a HERO clip.
+const GOPRO: &str = r#"{"streams": [
+    {"index": 2, "codec_tag_string": "tmcd", "tags": {"handler_name": "GoPro TCD"}},
+    {"index": 3, "codec_name": "bin_data", "codec_tag_string": "gpmd",
+     "bit_rate": "48063", "tags": {"handler_name": "GoPro MET"}},
+    {"index": 4, "codec_tag_string": "fdsc", "bit_rate": "14413",
+     "tags": {"handler_name": "GoPro SOS"}}
+]}"#;
+
+/// Runs a reduction of `GX010042.MP4` into `output` with `extra` and
+/// returns the ffmpeg command it ran and what it printed.
+fn run(output: &str, extra: &[&str]) -> (String, String) {
+    let sb = Sandbox::new();
+    let log = sb.work().join("ffmpeg.log");
+    let result = sb
+        .command()
+        .arg(sb.input("GX010042.MP4"))
+        .arg(sb.work().join(output))
+        .args(extra)
+        .args(["--verbose"])
+        .env("STUB_DATA_JSON", GOPRO)
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+    let printed = format!(
+        "{}{}",
+        String::from_utf8_lossy(&result.stdout),
+        String::from_utf8_lossy(&result.stderr)
+    );
+    assert!(result.status.success(), "{}", printed);
+    (std::fs::read_to_string(&log).unwrap(), printed)
+}
+
+#[test]
+fn the_telemetry_is_left_out_by_default() {
+    let (command, printed) = run("out.mp4", &[]);
+    assert!(
+        printed.contains("Leaving out the input's data streams"),
+        "{}",
+        printed
+    );
+    assert!(!command.contains("-c:d"), "{}", command);
+}
+
+#[test]
+fn keep_data_streams_copies_the_telemetry_alone() {
+    let (command, printed) = run("out.mp4", &["--keep-data-streams"]);
+    assert!(command.contains(" -map 0:3 -c:d copy "), "{}", command);
+    assert!(
+        !command.contains("0:2") && !command.contains("0:4"),
+        "{}",
+        command
+    );
+    assert!(
+        printed.contains("Leaving out data streams #2 (tmcd, GoPro TCD), #4 (fdsc, GoPro SOS)"),
+        "{}",
+        printed
+    );
+}
+
+#[test]
+fn keep_data_streams_into_matroska_warns() {
+    let (command, printed) = run("out.mkv", &["--keep-data-streams"]);
+    assert!(!command.contains("-c:d"), "{}", command);
+    assert!(
+        printed.contains("the input's data streams are left out: Matroska has no place for them"),
+        "{}",
+        printed
+    );
+}
@@ -0,0 +1,95 @@
+//! `--deterministic` writing the same bytes on every run.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::process::Command;
+
+/// A hash of the file's bytes that is the same in every process.
+fn hash(path: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    std::fs::read(path).unwrap().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn every_command_is_pinned() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let log = sb.work().join("ffmpeg.log");
+    let mut hashes = Vec::new();
+    for run in ["first.mp4", "second.mp4"] {
+        let output = sb.work().join(run);
+        let status = sb
+            .command()
+            .arg(&input)
+            .arg(&output)
+            .args(["--deterministic", "--two-pass"])
+            .env("STUB_DURATION", "60.0")
+            .env("STUB_FFMPEG_LOG", &log)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        hashes.push(hash(&output));
+    }
+    assert_eq!(hashes[0], hashes[1]);
+
+    let logged = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = logged.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", logged);
+    for line in &lines {
+        assert!(line.contains(" -threads 1 -flags +bitexact"), "{}", line);
+    }
+    for second_pass in [lines[1], lines[3]] {
+        assert!(
+            second_pass.contains(" -map_metadata -1 -metadata creation_time=1970-01-01T00:00:00.000000Z -fflags +bitexact "),
+            "{}",
+            second_pass
+        );
+    }
+}
+
+/// Encodes a generated clip twice with the real ffmpeg. Run with `cargo
+/// test -- --ignored` where ffmpeg has libx264.
+#[test]
+#[ignore = "needs a real ffmpeg with libx264 on PATH"]
+fn a_real_encode_repeats_byte_for_byte() {
+    let sb = Sandbox::new();
+    // The sandbox's stubs would shadow the real tools.
+    sb.remove_script("ffmpeg");
+    sb.remove_script("ffprobe");
+    let fixture = sb.work().join("fixture.mp4");
+    let status = Command::new("ffmpeg")
+        .args(["-v", "error", "-y", "-f", "lavfi", "-i"])
+        .arg("testsrc2=duration=2:size=320x240:rate=30")
+        .args(["-f", "lavfi", "-i", "sine=duration=2"])
+        .args(["-c:v", "libx264", "-c:a", "aac"])
+        .arg(&fixture)
+        .status()
+        .expect("ffmpeg should be on PATH");
+    assert!(status.success());
+
+    let mut hashes = Vec::new();
+    for run in ["first.mp4", "second.mp4"] {
+        let output = sb.work().join(run);
+        let result = sb
+            .command()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .arg(&fixture)
+            .arg(&output)
+            .args(["--size", "0.2", "--deterministic"])
+            .output()
+            .unwrap();
+        assert!(
+            result.status.success(),
+            "{}",
+            String::from_utf8_lossy(&result.stderr)
+        );
+        hashes.push(hash(&output));
+    }
+    assert_eq!(hashes[0], hashes[1]);
+}
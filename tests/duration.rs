@@ -0,0 +1,63 @@
+//! `--duration` and the decode-through duration fallback.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn missing_duration_is_measured_by_decoding() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let log = sb.work().join("ffmpeg.log");
+    let output = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .env("STUB_DURATION", "N/A")
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("decoding the input to measure it"),
+        "{}",
+        stdout
+    );
+    // The stub reports 5 s of progress, which became the duration.
+    assert!(
+        stdout.contains("Video duration: 5.00 seconds"),
+        "{}",
+        stdout
+    );
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.lines().next().unwrap().ends_with("-f null -"));
+}
+
+#[test]
+fn duration_override_warns_about_a_mismatch() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .args(["--duration", "00:01:00.000"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Video duration: 60.00 seconds"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("  ffprobe reports a duration of 0:10, but --duration is 1:00"),
+        "{}",
+        stdout
+    );
+}
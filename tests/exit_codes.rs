@@ -0,0 +1,250 @@
+//! The documented exit code for each failure category.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+
+fn code(sb: &Sandbox, extra: &[&str], env: &[(&str, &str)]) -> Option<i32> {
+    let input = sb.input("in.mp4");
+    let output = sb.work().join("out.mp4");
+    let mut cmd = sb.command();
+    cmd.arg(&input).arg(&output).args(extra);
+    for (key, value) in env {
+        cmd.env(key, value);
+    }
+    cmd.output().unwrap().status.code()
+}
+
+#[test]
+fn success_exits_zero() {
+    let sb = Sandbox::new();
+    assert_eq!(code(&sb, &[], &[]), Some(0));
+}
+
+#[test]
+fn bad_arguments_exit_two() {
+    let sb = Sandbox::new();
+    assert_eq!(code(&sb, &["--size", "lots"], &[]), Some(2));
+    assert_eq!(code(&sb, &["--no-such-flag"], &[]), Some(2));
+}
+
+#[test]
+fn missing_tools_exit_three() {
+    let sb = Sandbox::new();
+    sb.remove_script("ffprobe");
+    assert_eq!(code(&sb, &[], &[]), Some(3));
+
+    let sb = Sandbox::new();
+    sb.remove_script("ffmpeg");
+    assert_eq!(code(&sb, &[], &[]), Some(3));
+}
+
+#[test]
+fn missing_input_exits_four() {
+    let sb = Sandbox::new();
+    let out = sb
+        .command()
+        .arg(sb.work().join("missing.mp4"))
+        .arg(sb.work().join("out.mp4"))
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("No such file"), "{}", stderr);
+}
+
+#[test]
+fn unreadable_probe_output_exits_four() {
+    let sb = Sandbox::new();
+    // Without a duration the input is decoded to measure it instead.
+    assert_eq!(
+        code(
+            &sb,
+            &[],
+            &[("STUB_DURATION", "N/A"), ("STUB_FFMPEG_EXIT", "1")]
+        ),
+        Some(4)
+    );
+    assert_eq!(code(&sb, &[], &[("STUB_PROBE_JSON", "{}")]), Some(4));
+}
+
+#[test]
+fn ffprobe_warnings_only_show_with_verbose() {
+    let sb = Sandbox::new();
+    let run = |extra: &[&str]| {
+        sb.command()
+            .arg(sb.input("in.mp4"))
+            .arg(sb.work().join("out.mp4"))
+            .args(extra)
+            .env(
+                "STUB_FFPROBE_STDERR",
+                "[h264 @ 0x55] mmco: unref short failure",
+            )
+            .output()
+            .unwrap()
+    };
+    let out = run(&[]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(
+        out.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let out = run(&["--verbose"]);
+    assert_eq!(out.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("ffprobe: [h264 @ 0x55] mmco: unref short failure"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn ffprobe_failures_report_its_stderr() {
+    let sb = Sandbox::new();
+    let run = |env: &[(&str, &str)]| {
+        let mut cmd = sb.command();
+        cmd.arg(sb.input("in.mp4"))
+            .arg(sb.work().join("out.mp4"))
+            .env("STUB_FFPROBE_STDERR", "moov atom not found");
+        for (key, value) in env {
+            cmd.env(key, value);
+        }
+        let out = cmd.output().unwrap();
+        assert_eq!(out.status.code(), Some(4));
+        String::from_utf8_lossy(&out.stderr).into_owned()
+    };
+    // Output that parses doesn't rescue a failed run.
+    let stderr = run(&[("STUB_FFPROBE_EXIT", "1")]);
+    assert!(
+        stderr.contains("ffprobe failed: moov atom not found"),
+        "{}",
+        stderr
+    );
+    // A clean exit with output that doesn't parse says what ffprobe said.
+    let stderr = run(&[("STUB_PROBE_JSON", "not json")]);
+    assert!(
+        stderr.contains("(ffprobe said: moov atom not found)"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn encode_failure_exits_five() {
+    let sb = Sandbox::new();
+    assert_eq!(code(&sb, &[], &[("STUB_FFMPEG_EXIT", "1")]), Some(5));
+}
+
+#[test]
+fn encode_failure_reports_ffmpeg_stderr() {
+    let sb = Sandbox::new();
+    let out = sb
+        .command()
+        .arg(sb.input("in.mp4"))
+        .arg(sb.work().join("out.mp4"))
+        .env("STUB_FFMPEG_EXIT", "1")
+        .env("STUB_FFMPEG_STDERR", "Unknown encoder 'libx264'")
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(5));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Unknown encoder 'libx264'"), "{}", stderr);
+}
+
+#[test]
+fn oversized_output_exits_six_after_retries() {
+    let sb = Sandbox::new();
+    let log = sb.work().join("ffmpeg.log");
+    let log_str = log.to_string_lossy().into_owned();
+    let env = [
+        ("STUB_OUTPUT_BYTES", "60000000"),
+        ("STUB_FFMPEG_LOG", log_str.as_str()),
+    ];
+    assert_eq!(
+        code(&sb, &["--size", "50", "--max-retries", "1"], &env),
+        Some(6)
+    );
+    let attempts = std::fs::read_to_string(&log).unwrap().lines().count();
+    assert_eq!(attempts, 2);
+    assert!(!sb.work().join("out.mp4").exists());
+}
+
+#[test]
+fn timeout_exits_eight() {
+    let sb = Sandbox::new();
+    let env = [("STUB_FFMPEG_SLEEP", "30")];
+    assert_eq!(code(&sb, &["--timeout", "1"], &env), Some(8));
+}
+
+#[test]
+fn full_disk_exits_nine_and_frees_the_run_dir() {
+    let sb = Sandbox::new();
+    let out = sb
+        .command()
+        .arg(sb.input("in.mp4"))
+        .arg(sb.work().join("out.mp4"))
+        .arg("--keep-temp")
+        .env("STUB_FFMPEG_EXIT", "1")
+        .env(
+            "STUB_FFMPEG_STDERR",
+            "[mp4 @ 0x5581] Error writing trailer: No space left on device",
+        )
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(9));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "out of disk space writing to {}",
+            sb.tmp().display()
+        )),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("bytes free)"), "{}", stderr);
+    // Kept or not, the partial output is deleted to give the space back.
+    assert!(common::entries(&sb.tmp()).is_empty());
+    assert!(!sb.work().join("out.mp4").exists());
+}
+
+#[test]
+fn exhausted_budget_exits_twelve_and_lists_the_attempts() {
+    let sb = Sandbox::new();
+    let out = sb
+        .command()
+        .arg(sb.input("in.mp4"))
+        .arg(sb.work().join("out.mp4"))
+        .args([
+            "--size",
+            "50",
+            "--max-attempts",
+            "2",
+            "--output-format",
+            "json",
+        ])
+        .env("STUB_OUTPUT_BYTES", "60000000")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(12), "{}", stderr);
+    assert!(
+        stderr.contains("the 2 attempts --max-attempts allows are used"),
+        "{}",
+        stderr
+    );
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let attempts = json["attempts"].as_array().unwrap();
+    let kinds: Vec<&str> = attempts
+        .iter()
+        .map(|attempt| attempt["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["encode", "over_target_retry"]);
+    assert!(attempts
+        .iter()
+        .all(|attempt| attempt["outcome"] == "over_target" && attempt["bytes"] == 60000000));
+    assert!(!sb.work().join("out.mp4").exists());
+}
@@ -0,0 +1,75 @@
+//! ffmpeg printing far more than is worth keeping: only the end goes into
+//! an error, and `--ffmpeg-log` gets all of it.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+use mdviqure::events::Event;
+
+/// Lines the chatty stub prints, about 1.3 MB: far past what is kept.
+const CHATTER: usize = 20_000;
+
+#[test]
+fn a_failure_reports_only_the_last_lines_and_the_log_has_them_all() {
+    let sb = Sandbox::new();
+    let log = sb.work().join("ffmpeg.txt");
+    let result = sb
+        .command()
+        .arg(sb.input("in.mp4"))
+        .arg(sb.work().join("out.mp4"))
+        .arg("--ffmpeg-log")
+        .arg(&log)
+        .env("STUB_FFMPEG_CHATTER", CHATTER.to_string())
+        .env("STUB_FFMPEG_EXIT", "1")
+        .output()
+        .unwrap();
+    assert_eq!(result.status.code(), Some(5));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains(&format!("chatter line {} of many", CHATTER)),
+        "{}",
+        stderr
+    );
+    assert!(
+        !stderr.contains(&format!("chatter line {} of many", CHATTER - 20)),
+        "{}",
+        stderr
+    );
+
+    let logged = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = logged.lines().collect();
+    assert!(lines[0].starts_with("$ ffmpeg -y "), "{}", lines[0]);
+    assert_eq!(
+        lines.iter().filter(|l| l.contains("chatter line")).count(),
+        CHATTER
+    );
+    assert!(lines.contains(&"[debug] chatter line 1 of many, as -loglevel debug prints them"));
+}
+
+#[test]
+fn progress_survives_noise_on_stdout() {
+    let sb = Sandbox::new();
+    let result = sb
+        .command()
+        .arg(sb.input("in.mp4"))
+        .arg(sb.work().join("out.mp4"))
+        .arg("--progress-json")
+        .env("STUB_PROGRESS_NOISE", "1")
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    let progressed = String::from_utf8_lossy(&result.stderr)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Event>(line).ok())
+        .any(|event| {
+            matches!(
+                event,
+                Event::Progress {
+                    out_time_ms: 5000,
+                    ..
+                }
+            )
+        });
+    assert!(progressed);
+}
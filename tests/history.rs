@@ -0,0 +1,137 @@
+//! The batch history: restarted batches skip finished files,
+//! `mdviqure history` lists and clears them, and `mdviqure stats` shows the
+//! container overhead learned from them.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+use std::path::Path;
+use std::process::Output;
+
+fn batch(sb: &Sandbox, input: &Path, extra: &[&str]) -> Output {
+    sb.command()
+        .arg("batch")
+        .arg(input)
+        .arg("--output-dir")
+        .arg(sb.work().join("reduced"))
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn restarted_batch_skips_finished_files() {
+    let sb = Sandbox::new();
+    let a = sb.input("a.mp4");
+    assert!(batch(&sb, &a, &[]).status.success());
+    assert!(sb.history().exists());
+
+    let again = batch(&sb, &a, &[]);
+    assert!(again.status.success());
+    let stdout = String::from_utf8_lossy(&again.stdout);
+    assert!(
+        stdout.contains("Already reduced for this target"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("(1 already done)"), "{}", stdout);
+
+    let redo = batch(&sb, &a, &["--redo"]);
+    let stdout = String::from_utf8_lossy(&redo.stdout);
+    assert!(!stdout.contains("already done"), "{}", stdout);
+}
+
+#[test]
+fn history_lists_and_clears() {
+    let sb = Sandbox::new();
+    let a = sb.input("a.mp4");
+    let file = sb.work().join("ledger.jsonl");
+    let file_arg = file.to_str().unwrap();
+    assert!(batch(&sb, &a, &["--history-file", file_arg])
+        .status
+        .success());
+    assert!(!sb.history().exists());
+
+    let list = sb
+        .command()
+        .args(["history", "--history-file", file_arg])
+        .output()
+        .unwrap();
+    assert!(list.status.success());
+    let stdout = String::from_utf8_lossy(&list.stdout);
+    assert!(stdout.contains("a.mp4"), "{}", stdout);
+    assert!(stdout.contains("100 MiB"), "{}", stdout);
+
+    let clear = sb
+        .command()
+        .args(["history", "clear", "--history-file", file_arg])
+        .status()
+        .unwrap();
+    assert!(clear.success());
+    assert!(!file.exists());
+}
+
+/// A ledger line for an earlier MP4 encode that came out `percent` over
+/// its payload and muxing model.
+fn mp4_sample_line(percent: f64) -> String {
+    let actual = 10_000_000u64;
+    let modelled = (actual as f64 * (1.0 - percent / 100.0)) as u64;
+    format!(
+        concat!(
+            r#"{{"input":"/videos/old.mp4","input_size":1,"input_modified":1,"target_bytes":1,"#,
+            r#""output":"/videos/old_small.mp4","result":"ok","output_bytes":{},"finished":1,"#,
+            r#""sample":{{"container":"mp4","duration":60.0,"streams":2,"payload_bytes":{},"#,
+            r#""muxing_bytes":0,"actual_bytes":{}}}}}"#
+        ),
+        actual, modelled, actual
+    )
+}
+
+#[test]
+fn learned_overhead_is_used_and_shown() {
+    let sb = Sandbox::new();
+    std::fs::create_dir_all(sb.history().parent().unwrap()).unwrap();
+    let lines: Vec<String> = [1.0, 1.5, 2.0, 1.5, 1.5].map(mp4_sample_line).to_vec();
+    std::fs::write(sb.history(), lines.join("\n") + "\n").unwrap();
+
+    // A constant quality spends less than planned, so only an average
+    // bitrate teaches the overhead.
+    let run = sb
+        .command()
+        .arg(sb.input("in.mp4"))
+        .arg(sb.work().join("out.mp4"))
+        .args(["--mode", "abr"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(run.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("Overhead allowance 1.5%, learned from 5 earlier MP4 outputs"),
+        "{}",
+        stdout
+    );
+    // The run adds a sample of its own.
+    let history = std::fs::read_to_string(sb.history()).unwrap();
+    let last = history.lines().last().unwrap();
+    assert!(last.contains(r#""sample":{"container":"mp4""#), "{}", last);
+
+    let stats = sb.command().arg("stats").output().unwrap();
+    let stdout = String::from_utf8_lossy(&stats.stdout);
+    assert!(stats.status.success(), "{}", stdout);
+    let row = stdout.lines().find(|l| l.starts_with("MP4")).unwrap();
+    assert!(row.contains(" 5 ") && row.ends_with("1.50%"), "{}", row);
+
+    // An explicit percentage wins, and --no-learn leaves the ledger alone.
+    let run = sb
+        .command()
+        .arg(sb.input("in.mp4"))
+        .arg(sb.work().join("out.mp4"))
+        .args(["--overhead-percent", "3", "--no-learn"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(!stdout.contains("learned"), "{}", stdout);
+    assert!(stdout.contains("overhead 3%"), "{}", stdout);
+    assert_eq!(std::fs::read_to_string(sb.history()).unwrap(), history);
+}
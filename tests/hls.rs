@@ -0,0 +1,64 @@
+//! `--format hls`: a playlist and its segments, within the target together.
+#![cfg(unix)]
+
+mod common;
+
+use common::{entries, Sandbox};
+
+#[test]
+fn an_hls_output_lists_only_files_it_wrote() {
+    let sb = Sandbox::new();
+    let input = sb.input("clip.mp4");
+    let output = sb.work().join("stream");
+    let log = sb.work().join("ffmpeg.log");
+    let result = sb
+        .command()
+        .arg(&input)
+        .arg(&output)
+        .args(["--size", "10", "--format", "hls", "--hls-time", "4"])
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(result.status.success(), "{}", stderr);
+
+    let playlist = std::fs::read_to_string(output.join("index.m3u8")).unwrap();
+    let listed: Vec<&str> = playlist
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .chain(["init.mp4"])
+        .collect();
+    for name in &listed {
+        assert!(output.join(name).is_file(), "{} is missing", name);
+    }
+    assert_eq!(entries(&output).len(), listed.len() + 1);
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("-f hls -hls_time 4 "), "{}", logged);
+    assert!(entries(&sb.tmp()).is_empty());
+}
+
+#[test]
+fn a_failed_hls_run_leaves_no_segments() {
+    let sb = Sandbox::new();
+    let input = sb.input("clip.mp4");
+    let output = sb.work().join("stream");
+    // ffmpeg failing after writing its segments, and segments over the
+    // target together.
+    for (name, value, code) in [
+        ("STUB_FFMPEG_EXIT", "1", 5),
+        ("STUB_OUTPUT_BYTES", "20000000", 6),
+    ] {
+        let result = sb
+            .command()
+            .arg(&input)
+            .arg(&output)
+            .args(["--size", "10", "--format", "hls"])
+            .env(name, value)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert_eq!(result.status.code(), Some(code), "{}: {}", name, stderr);
+        assert!(!output.exists(), "{}", name);
+        assert!(entries(&sb.tmp()).is_empty(), "{}", name);
+    }
+}
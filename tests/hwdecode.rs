@@ -0,0 +1,70 @@
+//! `--hwdecode` starting on the GPU, and falling back when it can't.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn a_decoder_that_fails_to_start_falls_back_to_software() {
+    let sb = Sandbox::new();
+    let input = sb.input("film.mkv");
+    let output = sb.work().join("film.mp4");
+    let log = sb.work().join("ffmpeg.log");
+    let result = sb
+        .command()
+        .arg(&input)
+        .arg(&output)
+        .args(["--hwdecode", "cuda"])
+        .env("STUB_HWACCELS", "vdpau cuda")
+        .env("STUB_BROKEN_HWACCEL", "1")
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(result.status.success(), "{}{}", stdout, stderr);
+    let printed = format!("{}{}", stdout, stderr);
+    assert!(
+        printed.contains("cuda decoding failed to start; decoding in software instead"),
+        "{}",
+        printed
+    );
+
+    let logged = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = logged.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", logged);
+    assert!(lines[0].contains("-hwaccel cuda "), "{}", lines[0]);
+    assert!(
+        lines[0].contains("-vf hwdownload,format=nv12|p010le "),
+        "{}",
+        lines[0]
+    );
+    assert!(!lines[1].contains("-hwaccel"), "{}", lines[1]);
+    assert!(!lines[1].contains("hwdownload"), "{}", lines[1]);
+}
+
+#[test]
+fn auto_without_a_decoder_stays_in_software() {
+    let sb = Sandbox::new();
+    let input = sb.input("film.mkv");
+    let log = sb.work().join("ffmpeg.log");
+    let result = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("film.mp4"))
+        .args(["--hwdecode", "auto"])
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    let printed = format!(
+        "{}{}",
+        String::from_utf8_lossy(&result.stdout),
+        String::from_utf8_lossy(&result.stderr)
+    );
+    assert!(printed.contains("No hardware decoder found"), "{}", printed);
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(logged.lines().count(), 1);
+    assert!(!logged.contains("-hwaccel"), "{}", logged);
+}
@@ -0,0 +1,75 @@
+//! `--input-pattern` frame sequences and `--loop-duration` stills.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn frame_sequence_is_encoded_without_audio() {
+    let sb = Sandbox::new();
+    let frames = sb.work().join("frames");
+    std::fs::create_dir(&frames).unwrap();
+    for n in 1..=60 {
+        std::fs::write(frames.join(format!("{:05}.png", n)), b"png").unwrap();
+    }
+    let log = sb.work().join("ffmpeg.log");
+    let output = sb
+        .command()
+        .arg("--input-pattern")
+        .arg(frames.join("%05d.png"))
+        .args(["--input-fps", "30"])
+        .arg(sb.work().join("out.mp4"))
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Video duration: 2.00 seconds"),
+        "{}",
+        stdout
+    );
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("-framerate 30 -start_number 1 -i"));
+    assert!(logged.contains(" -an "));
+}
+
+#[test]
+fn pattern_without_matches_fails_before_ffmpeg() {
+    let sb = Sandbox::new();
+    let log = sb.work().join("ffmpeg.log");
+    let output = sb
+        .command()
+        .arg("--input-pattern")
+        .arg(sb.work().join("%05d.png"))
+        .args(["--input-fps", "30"])
+        .arg(sb.work().join("out.mp4"))
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("matches no files"));
+    assert!(!log.exists());
+}
+
+#[test]
+fn still_image_is_looped() {
+    let sb = Sandbox::new();
+    let image = sb.input("title.png");
+    let log = sb.work().join("ffmpeg.log");
+    let output = sb
+        .command()
+        .arg(&image)
+        .arg(sb.work().join("out.mp4"))
+        .args(["--loop-duration", "10"])
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{:?}", output);
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("-loop 1 -framerate 30 -t 10.000 -i"));
+}
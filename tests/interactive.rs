@@ -0,0 +1,51 @@
+//! `--interactive` outside a terminal, with and without `--yes`.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+use std::process::Stdio;
+
+#[test]
+fn interactive_without_terminal_fails_instead_of_waiting() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let log = sb.work().join("ffmpeg.log");
+    let out = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .arg("--interactive")
+        .env("STUB_FFMPEG_LOG", &log)
+        .stdin(Stdio::piped())
+        .output()
+        .unwrap();
+
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("needs a terminal"), "{}", stderr);
+    assert!(!log.exists());
+}
+
+#[test]
+fn interactive_with_yes_shows_the_plan_and_encodes() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let log = sb.work().join("ffmpeg.log");
+    let out = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .args(["--interactive", "--yes"])
+        .env("STUB_FFMPEG_LOG", &log)
+        .stdin(Stdio::piped())
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(0), "{}", stderr);
+    assert!(stdout.contains("Target:"), "{}", stdout);
+    assert!(stdout.contains("Proceed? [Y/n] y"), "{}", stdout);
+    assert!(log.exists());
+}
@@ -0,0 +1,79 @@
+//! Outputs that replace their input, or an earlier output, leaving it whole
+//! whenever the run fails.
+#![cfg(unix)]
+
+mod common;
+
+use common::{entries, Sandbox};
+
+const ORIGINAL: &[u8] = b"not really a video";
+
+/// Files a run left beside its outputs.
+fn strays(names: &[String]) -> Vec<&String> {
+    names
+        .iter()
+        .filter(|name| name.ends_with(".part") || name.ends_with(".orig"))
+        .collect()
+}
+
+#[test]
+fn a_failed_in_place_batch_leaves_every_original() {
+    let sb = Sandbox::new();
+    let a = sb.input("a.mp4");
+    let b = sb.input("b.mp4");
+    // ffmpeg failing after writing part of the file, and an output that
+    // stays over the target however often it is retried.
+    for (name, value, code) in [
+        ("STUB_FFMPEG_EXIT", "1", 5),
+        ("STUB_OUTPUT_BYTES", "5000000", 6),
+    ] {
+        let result = sb
+            .command()
+            .arg("batch")
+            .arg(&a)
+            .arg(&b)
+            .arg("--output-dir")
+            .arg(sb.work())
+            .args(["--size", "1", "--redo"])
+            .env(name, value)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&result.stdout);
+        assert_eq!(result.status.code(), Some(code), "{}: {}", name, stdout);
+        assert!(stdout.contains("Reduced 0 of 2 files"), "{}", stdout);
+        assert_eq!(std::fs::read(&a).unwrap(), ORIGINAL, "{}", name);
+        assert_eq!(std::fs::read(&b).unwrap(), ORIGINAL, "{}", name);
+        let names = entries(&sb.work());
+        assert!(strays(&names).is_empty(), "{:?}", names);
+        assert!(entries(&sb.tmp()).is_empty());
+    }
+}
+
+#[test]
+fn a_failure_after_the_encode_puts_the_original_back() {
+    let sb = Sandbox::new();
+    let input = sb.input("clip.mp4");
+    // The sidecar can't be written where a directory is in the way.
+    std::fs::create_dir(sb.work().join("clip.mp4.mdviqure.json")).unwrap();
+    let run = |keep: bool| {
+        let mut command = sb.command();
+        command.arg(&input).arg(&input).arg("--sidecar");
+        if keep {
+            command.arg("--keep-original-on-failure");
+        }
+        command.output().unwrap()
+    };
+
+    let result = run(true);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert_eq!(result.status.code(), Some(5), "{}", stderr);
+    assert!(stderr.contains("cannot write"), "{}", stderr);
+    assert_eq!(std::fs::read(&input).unwrap(), ORIGINAL);
+    assert_eq!(entries(&sb.work()), ["clip.mp4", "clip.mp4.mdviqure.json"]);
+
+    // Without it the output stays, complete, in place of the input.
+    let result = run(false);
+    assert_eq!(result.status.code(), Some(5));
+    assert_eq!(std::fs::metadata(&input).unwrap().len(), 1000);
+    assert!(strays(&entries(&sb.work())).is_empty());
+}
@@ -0,0 +1,54 @@
+//! `--open` and `--reveal` with a stub `xdg-open`.
+#![cfg(all(unix, not(target_os = "macos")))]
+
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn open_and_reveal_launch_the_result() {
+    let sb = Sandbox::new();
+    let log = sb.work().join("xdg-open.log");
+    sb.write_script(
+        "xdg-open",
+        &format!("#!/bin/sh\necho \"$*\" >> '{}'\n", log.display()),
+    );
+    let input = sb.input("in.mp4");
+    let output = sb.work().join("out.mp4");
+    let status = sb
+        .command()
+        .arg(&input)
+        .arg(&output)
+        .args(["--open", "--reveal"])
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let logged = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = logged.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            output.to_string_lossy().as_ref(),
+            sb.work().to_string_lossy().as_ref()
+        ]
+    );
+}
+
+#[test]
+fn launch_failure_keeps_the_exit_code() {
+    let sb = Sandbox::new();
+    sb.write_script("xdg-open", "#!/bin/sh\nexit 3\n");
+    let input = sb.input("in.mp4");
+    let output = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .arg("--open")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Warning: could not open"), "{}", stdout);
+}
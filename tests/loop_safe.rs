@@ -0,0 +1,72 @@
+//! `--loop-safe` asking ffmpeg for an output that loops, and checking it.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn the_encode_starts_on_a_keyframe_without_an_edit_list() {
+    let sb = Sandbox::new();
+    let input = sb.input("clip.mp4");
+    let log = sb.work().join("ffmpeg.log");
+    let result = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("loop.mp4"))
+        .arg("--loop-safe")
+        .env("STUB_DURATION", "12.0")
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+    let printed = format!(
+        "{}{}",
+        String::from_utf8_lossy(&result.stdout),
+        String::from_utf8_lossy(&result.stderr)
+    );
+    assert!(result.status.success(), "{}", printed);
+    assert!(!printed.contains("loop seamlessly"), "{}", printed);
+
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(logged.lines().count(), 1, "{}", logged);
+    for expected in [
+        " -force_key_frames 0 ",
+        " -filter:a apad,atrim=end=12.000000 ",
+        " -shortest ",
+        " -use_editlist 0 ",
+        "+negative_cts_offsets",
+    ] {
+        assert!(logged.contains(expected), "{} in {}", expected, logged);
+    }
+}
+
+#[test]
+fn an_output_running_past_its_video_is_warned_about() {
+    let sb = Sandbox::new();
+    let input = sb.input("clip.mp4");
+    let result = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("loop.mp4"))
+        .arg("--loop-safe")
+        .env(
+            "STUB_PROBE_JSON",
+            r#"{"streams":[{"width":640,"height":360,"avg_frame_rate":"30/1","duration":"12.000000"}]}"#,
+        )
+        .env("STUB_DURATION", "12.5")
+        .output()
+        .unwrap();
+    let printed = format!(
+        "{}{}",
+        String::from_utf8_lossy(&result.stdout),
+        String::from_utf8_lossy(&result.stderr)
+    );
+    assert!(result.status.success(), "{}", printed);
+    assert!(
+        printed.contains(
+            "the output lasts 12.500s but its video 12.000s, so it won't loop seamlessly"
+        ),
+        "{}",
+        printed
+    );
+}
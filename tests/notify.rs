@@ -0,0 +1,47 @@
+//! `--notify` falls back to the terminal bell in builds without the
+//! `notify` feature.
+#![cfg(not(feature = "notify"))]
+
+mod common;
+
+use common::Sandbox;
+
+fn bells(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&b| b == 0x07).count()
+}
+
+#[test]
+fn notify_rings_the_bell_when_done() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .arg("--notify")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(bells(&output.stdout), 1);
+}
+
+#[test]
+fn batch_notifies_once_for_all_files() {
+    let sb = Sandbox::new();
+    let a = sb.input("a.mp4");
+    let b = sb.input("b.mp4");
+    let output = sb
+        .command()
+        .arg("batch")
+        .arg(&a)
+        .arg(&b)
+        .arg("-o")
+        .arg(sb.work().join("out"))
+        .arg("--notify")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(bells(&output.stdout), 1);
+}
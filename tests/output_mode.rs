@@ -0,0 +1,134 @@
+//! `--plain` and the environment variables that decide color and redraws.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn piped_progress_is_printed_as_lines() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("% done"), "{}", stdout);
+    assert!(!stdout.contains('\r'), "{:?}", stdout);
+    assert!(!stdout.contains('\x1b'), "{:?}", stdout);
+}
+
+#[test]
+fn warnings_are_listed_together_just_before_done() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .args(["--duration", "60"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    let warned = "ffprobe reports a duration of 0:10, but --duration is 1:00; using 1:00";
+    assert_eq!(
+        lines.iter().filter(|line| line.contains(warned)).count(),
+        1,
+        "{}",
+        stdout
+    );
+    let done = lines
+        .iter()
+        .position(|line| line.starts_with("Done: "))
+        .unwrap_or_else(|| panic!("{}", stdout));
+    assert_eq!(lines[done - 2], "Warnings:", "{}", stdout);
+    assert_eq!(lines[done - 1], format!("  {}", warned), "{}", stdout);
+}
+
+#[test]
+fn clicolor_force_colors_piped_output_unless_no_color() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let run = |envs: &[(&str, &str)]| {
+        let output = sb
+            .command()
+            .arg(&input)
+            .arg(sb.work().join("out.mp4"))
+            .envs(envs.iter().copied())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let forced = run(&[("CLICOLOR_FORCE", "1")]);
+    assert!(forced.contains("\x1b[32mDone: "), "{}", forced);
+    // Forced color is still no reason to redraw a log.
+    assert!(!forced.contains('\r'), "{:?}", forced);
+    assert!(!run(&[("CLICOLOR_FORCE", "0")]).contains('\x1b'));
+    assert!(!run(&[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")]).contains('\x1b'));
+}
+
+#[test]
+fn plain_conflicts_with_an_explicit_color() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .args(["--plain", "--color", "always"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn summary_line_is_all_that_is_printed() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let run = |args: &[&str], envs: &[(&str, &str)]| {
+        sb.command()
+            .arg(&input)
+            .arg(sb.work().join("out.mp4"))
+            .args(args)
+            .envs(envs.iter().copied())
+            .output()
+            .unwrap()
+    };
+    let line = "in.mp4: 0.0 KiB → 1.0 KiB (+5456%) h264 360p 0:00 elapsed";
+
+    let output = run(&["--summary-line"], &[]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{}\n", line)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    let output = run(&["--output-format", "json"], &[]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["summary_line"], line);
+
+    let output = run(&["--summary-line"], &[("STUB_FFMPEG_EXIT", "1")]);
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "in.mp4: 0.0 KiB → failed (encode failed, exit 5)\n"
+    );
+    // Only the error itself goes to stderr.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.lines().count(), 1, "{}", stderr);
+    assert!(stderr.starts_with("Error: "), "{}", stderr);
+
+    let output = run(&["--summary-line", "--output-format", "json"], &[]);
+    assert_eq!(output.status.code(), Some(2));
+}
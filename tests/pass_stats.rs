@@ -0,0 +1,94 @@
+//! `--save-stats` and `--stats`: a first pass kept by one run and reused by
+//! the next.
+#![cfg(unix)]
+
+mod common;
+
+use common::{entries, Sandbox};
+use std::path::Path;
+use std::process::Output;
+
+/// Runs a 60-second two-pass encode of `input` with `extra`, logging the
+/// ffmpeg calls to `log`.
+fn run(sb: &Sandbox, input: &Path, output: &str, log: &Path, extra: &[&str]) -> Output {
+    sb.command()
+        .arg(input)
+        .arg(sb.work().join(output))
+        .arg("--two-pass")
+        .args(extra)
+        .env("STUB_DURATION", "60.0")
+        .env("STUB_FFMPEG_LOG", log)
+        .output()
+        .unwrap()
+}
+
+/// The `-pass` of each encode in `log`.
+fn passes(log: &Path) -> Vec<String> {
+    std::fs::read_to_string(log)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split(" -pass ").nth(1))
+        .map(|rest| rest.split(' ').next().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn saved_stats_skip_the_first_pass_of_the_next_run() {
+    let sb = Sandbox::new();
+    let input = sb.input("talk.mp4");
+    let stats = sb.work().join("talk.stats");
+    let stats_arg = stats.to_str().unwrap();
+
+    let log = sb.work().join("first.log");
+    let result = run(&sb, &input, "a.mp4", &log, &["--save-stats", stats_arg]);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(result.status.success(), "{}", stdout);
+    assert_eq!(passes(&log), ["1", "2"]);
+    assert!(stdout.contains("Saved the two-pass stats to"), "{}", stdout);
+    assert_eq!(std::fs::read_to_string(&stats).unwrap(), "stub stats\n");
+    let header = std::fs::read_to_string(sb.work().join("talk.stats.json")).unwrap();
+    assert!(header.contains(r#""width": 640"#), "{}", header);
+
+    let log = sb.work().join("second.log");
+    let result = run(&sb, &input, "b.mp4", &log, &["--stats", stats_arg]);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(result.status.success(), "{}", stdout);
+    assert_eq!(passes(&log), ["2"]);
+    assert!(
+        stdout.contains("Pass 1 of 2: reusing the stats in"),
+        "{}",
+        stdout
+    );
+    assert!(sb.work().join("b.mp4").exists());
+    assert!(entries(&sb.tmp()).is_empty());
+}
+
+#[test]
+fn stats_of_another_encode_are_refused() {
+    let sb = Sandbox::new();
+    let input = sb.input("talk.mp4");
+    let stats = sb.work().join("talk.stats");
+    let stats_arg = stats.to_str().unwrap();
+    let log = sb.work().join("ffmpeg.log");
+    let saved = run(&sb, &input, "a.mp4", &log, &["--save-stats", stats_arg]);
+    assert!(saved.status.success());
+
+    let refused = |extra: &[&str], difference: &str| {
+        let log = sb.work().join("refused.log");
+        let result = run(&sb, &input, "b.mp4", &log, extra);
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert_eq!(result.status.code(), Some(2), "{}", stderr);
+        assert!(stderr.contains(difference), "{}", stderr);
+        assert!(passes(&log).is_empty());
+        assert!(!sb.work().join("b.mp4").exists());
+    };
+    refused(
+        &["--stats", stats_arg, "--max-width", "320"],
+        "they were made at 640x360, not 320x180",
+    );
+    std::fs::write(&input, b"a different video").unwrap();
+    refused(
+        &["--stats", stats_arg],
+        &format!("{} changed since they were made", input.display()),
+    );
+}
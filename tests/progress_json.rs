@@ -0,0 +1,136 @@
+//! `--progress-json`: every line of the event stream parses as an event,
+//! and no status text gets mixed into it.
+#![cfg(unix)]
+
+mod common;
+
+use common::Sandbox;
+use mdviqure::events::{Event, Phase};
+use std::process::Output;
+
+fn events(stream: &[u8]) -> Vec<Event> {
+    String::from_utf8_lossy(stream)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {:?}", e, line)))
+        .collect()
+}
+
+fn phases(events: &[Event]) -> Vec<Phase> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Phase { phase, .. } => Some(*phase),
+            _ => None,
+        })
+        .collect()
+}
+
+fn reduce(sb: &Sandbox, output: &str, extra: &[&str]) -> Output {
+    sb.command()
+        .arg(sb.input("in.mp4"))
+        .arg(output)
+        .args(["--size", "1"])
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn events_go_to_stderr_and_status_stays_on_stdout() {
+    let sb = Sandbox::new();
+    let output = sb.work().join("out.mp4");
+    let run = reduce(&sb, output.to_str().unwrap(), &["--progress-json"]);
+    assert!(run.status.success());
+
+    let events = events(&run.stderr);
+    assert_eq!(
+        phases(&events),
+        [Phase::Probing, Phase::Encoding, Phase::Verifying]
+    );
+    // The stub reports 5 of the input's 10 seconds at twice real time.
+    assert!(events.contains(&Event::Progress {
+        out_time_ms: 5000,
+        percent: 50.0,
+        speed: Some(2.0),
+        eta_s: Some(3.0),
+    }));
+    let Some(Event::Report(report)) = events.last() else {
+        panic!("no report at the end: {:?}", events);
+    };
+    assert_eq!(report.output_bytes, Some(1000));
+    assert_eq!(report.error, None);
+    assert!(String::from_utf8_lossy(&run.stdout).contains("Done:"));
+}
+
+#[test]
+fn streamed_video_leaves_stderr_to_the_events() {
+    let sb = Sandbox::new();
+    let run = reduce(&sb, "-", &["--progress-json"]);
+    assert!(run.status.success());
+    assert_eq!(run.stdout.len(), 1000);
+    // Only events: the status that would have moved to stderr is dropped.
+    let events = events(&run.stderr);
+    assert_eq!(phases(&events), [Phase::Probing, Phase::Encoding]);
+    assert!(matches!(events.last(), Some(Event::Report(_))));
+}
+
+#[test]
+fn events_go_to_an_inherited_descriptor() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb.work().join("out.mp4");
+    let log = sb.work().join("events.jsonl");
+    let run = sb
+        .shell(r#"log=$1; shift; exec "$MDVIQURE" "$@" 3>"$log""#)
+        .arg(&log)
+        .arg(&input)
+        .arg(&output)
+        .args(["--size", "1", "--progress-json=3"])
+        .output()
+        .unwrap();
+    assert!(run.status.success(), "{:?}", run);
+    assert!(
+        run.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    let events = events(&std::fs::read(&log).unwrap());
+    assert_eq!(
+        phases(&events),
+        [Phase::Probing, Phase::Encoding, Phase::Verifying]
+    );
+}
+
+#[test]
+fn failures_end_the_stream_with_an_error() {
+    let sb = Sandbox::new();
+    let output = sb.work().join("out.mp4");
+    let run = sb
+        .command()
+        .arg(sb.work().join("missing.mp4"))
+        .arg(&output)
+        .arg("--progress-json")
+        .output()
+        .unwrap();
+    assert_eq!(run.status.code(), Some(4));
+    let events = events(&run.stderr);
+    let Some(Event::Error { message, exit_code }) = events.last() else {
+        panic!("no error at the end: {:?}", events);
+    };
+    assert_eq!(*exit_code, 4);
+    assert!(message.contains("missing.mp4"), "{}", message);
+    assert!(matches!(&events[events.len() - 2], Event::Report(r) if r.error.is_some()));
+}
+
+#[test]
+fn stdout_events_need_stdout_to_themselves() {
+    let sb = Sandbox::new();
+    let output = sb.work().join("out.mp4");
+    let run = reduce(
+        &sb,
+        output.to_str().unwrap(),
+        &["--progress-json=1", "--output-format", "json"],
+    );
+    assert_eq!(run.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("--progress-json=1"));
+}
@@ -0,0 +1,64 @@
+//! `EncodeSession` cleaning up after itself when dropped mid-encode.
+#![cfg(unix)]
+
+mod common;
+
+use common::{entries, wait_until, Sandbox};
+use mdviqure::reduce::ReduceOptions;
+use mdviqure::session::EncodeSession;
+use mdviqure::tool::FfmpegTool;
+use std::process::Command;
+use std::time::Duration;
+
+/// Writes its pid and the output, then hangs like a long encode.
+const SLOW_FFMPEG: &str = r#"#!/bin/sh
+for last; do :; done
+truncate -s 1000 "$last"
+echo $$ > "$STUB_PID_FILE"
+exec sleep 30
+"#;
+
+fn is_running(pid: &str) -> bool {
+    Command::new("sh")
+        .args(["-c", &format!("kill -0 {} 2>/dev/null", pid)])
+        .status()
+        .unwrap()
+        .success()
+}
+
+// The only test in this binary, as it sets the process environment.
+#[test]
+fn dropping_a_session_mid_encode_kills_ffmpeg_and_removes_its_files() {
+    let sb = Sandbox::new();
+    sb.write_script("ffmpeg", SLOW_FFMPEG);
+    let pid_file = sb.work().join("ffmpeg.pid");
+    std::env::set_var("PATH", format!("{}:/bin:/usr/bin", sb.bin().display()));
+    std::env::set_var("STUB_PID_FILE", &pid_file);
+    let input = sb.input("in.mp4");
+    let output = sb.work().join("out.mp4");
+    let mut opts = ReduceOptions::new(10_000_000);
+    opts.temp_dir = Some(sb.tmp());
+
+    let session = EncodeSession::start(
+        FfmpegTool::default(),
+        &input.to_string_lossy(),
+        &output.to_string_lossy(),
+        &opts,
+    )
+    .unwrap();
+    assert!(wait_until(Duration::from_secs(10), || {
+        std::fs::read_to_string(&pid_file).is_ok_and(|pid| !pid.trim().is_empty())
+    }));
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    let pid = pid.trim();
+    assert!(is_running(pid));
+    // The partial output sits in the run directory.
+    assert_eq!(entries(&sb.tmp()).len(), 1);
+    assert!(!session.is_finished());
+
+    drop(session);
+
+    assert!(!is_running(pid), "ffmpeg {} outlived its session", pid);
+    assert!(entries(&sb.tmp()).is_empty(), "{:?}", entries(&sb.tmp()));
+    assert!(!output.exists());
+}
@@ -0,0 +1,51 @@
+//! Using `-` for stdin input and stdout output.
+#![cfg(unix)]
+
+mod common;
+
+use common::{entries, Sandbox};
+use std::io::Write;
+use std::process::Stdio;
+
+#[test]
+fn reads_input_from_stdin() {
+    let sb = Sandbox::new();
+    let output = sb.work().join("out.mp4");
+    let log = sb.work().join("ffmpeg.log");
+    let mut child = sb
+        .command()
+        .arg("-")
+        .arg(&output)
+        .env("STUB_FFMPEG_LOG", &log)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"fake video bytes")
+        .unwrap();
+    let status = child.wait().unwrap();
+
+    assert!(status.success());
+    assert_eq!(std::fs::metadata(&output).unwrap().len(), 1000);
+    // ffmpeg read the spooled copy, which went away with the run directory.
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("stdin-input"));
+    assert!(entries(&sb.tmp()).is_empty());
+}
+
+#[test]
+fn stdout_carries_only_video_bytes() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb.command().arg(&input).arg("-").output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, vec![0u8; 1000]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Using video bitrate"));
+    assert!(entries(&sb.tmp()).is_empty());
+}
@@ -0,0 +1,84 @@
+//! `--extract-subs` writing an MKV's subtitles next to an MP4 output.
+#![cfg(unix)]
+
+mod common;
+
+use common::{entries, Sandbox};
+
+/// What the stubbed ffprobe says about a fansub MKV: ASS and SubRip
+/// tracks, a PGS track without a language and a teletext one.
+const SUBTITLED_MKV: &str = r#"{"streams": [
+    {"index": 0, "codec_name": "h264", "codec_type": "video"},
+    {"index": 1, "codec_name": "aac", "codec_type": "audio"},
+    {"index": 2, "codec_name": "ass", "codec_type": "subtitle", "tags": {"language": "eng"}},
+    {"index": 3, "codec_name": "subrip", "codec_type": "subtitle", "tags": {"language": "spa"}},
+    {"index": 4, "codec_name": "hdmv_pgs_subtitle", "codec_type": "subtitle"},
+    {"index": 5, "codec_name": "dvb_teletext", "codec_type": "subtitle", "tags": {"language": "ger"}}
+]}"#;
+
+#[test]
+fn each_stream_gets_a_file() {
+    let sb = Sandbox::new();
+    let input = sb.input("show.mkv");
+    let output = sb.work().join("show.mp4");
+    let log = sb.work().join("ffmpeg.log");
+    let result = sb
+        .command()
+        .arg(&input)
+        .arg(&output)
+        .arg("--extract-subs")
+        .env("STUB_STREAMS_JSON", SUBTITLED_MKV)
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(result.status.success(), "{}{}", stdout, stderr);
+
+    assert_eq!(
+        entries(&sb.work()),
+        [
+            "ffmpeg.log",
+            "show.eng.ass",
+            "show.mkv",
+            "show.mp4",
+            "show.spa.srt",
+            "show.sup"
+        ]
+    );
+    let printed = format!("{}{}", stdout, stderr);
+    for name in ["show.eng.ass", "show.spa.srt", "show.sup"] {
+        let line = format!("Subtitles: {}", sb.work().join(name).display());
+        assert!(printed.contains(&line), "{}", printed);
+    }
+    assert!(
+        printed.contains("subtitle stream #5 (dvb_teletext) has no file format"),
+        "{}",
+        printed
+    );
+
+    // The encode, then one command per extracted stream.
+    let logged = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = logged.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", logged);
+    assert!(
+        lines[2].contains(" -map 0:3 -c:s srt -f srt "),
+        "{}",
+        lines[2]
+    );
+}
+
+#[test]
+fn stdout_has_nowhere_to_put_them() {
+    let sb = Sandbox::new();
+    let input = sb.input("show.mkv");
+    let result = sb
+        .command()
+        .arg(&input)
+        .arg("-")
+        .arg("--extract-subs")
+        .output()
+        .unwrap();
+    assert_eq!(result.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("--extract-subs needs an output file"));
+}
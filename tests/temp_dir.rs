@@ -0,0 +1,164 @@
+//! Cleanup behavior of the per-run temp directory.
+#![cfg(unix)]
+
+mod common;
+
+use common::{entries, wait_until, Sandbox};
+use std::process::Command;
+use std::time::Duration;
+
+#[test]
+fn temp_dir_is_removed_after_success() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb.work().join("out.mp4");
+    let status = sb
+        .command()
+        .arg(&input)
+        .arg(&output)
+        .arg("--temp-dir")
+        .arg(sb.tmp())
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    assert_eq!(std::fs::metadata(&output).unwrap().len(), 1000);
+    assert!(entries(&sb.tmp()).is_empty());
+}
+
+#[test]
+fn temp_dir_is_removed_after_failure() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb.work().join("out.mp4");
+    let status = sb
+        .command()
+        .arg(&input)
+        .arg(&output)
+        .env("STUB_FFMPEG_EXIT", "1")
+        .status()
+        .unwrap();
+
+    assert!(!status.success());
+    assert!(!output.exists());
+    // TMPDIR points at the sandbox, so the default location is checked too.
+    assert!(entries(&sb.tmp()).is_empty());
+}
+
+#[test]
+fn keep_temp_leaves_directory_and_prints_path() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb.work().join("out.mp4");
+    let out = sb
+        .command()
+        .arg(&input)
+        .arg(&output)
+        .arg("--keep-temp")
+        .output()
+        .unwrap();
+
+    assert!(out.status.success());
+    let kept = entries(&sb.tmp());
+    assert_eq!(kept.len(), 1);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains(&kept[0]), "path not printed: {}", stdout);
+}
+
+#[test]
+fn temp_dir_is_removed_on_interrupt() {
+    let sb = Sandbox::new();
+    let input = sb.input("in.mp4");
+    let output = sb.work().join("out.mp4");
+    let mut child = sb
+        .command()
+        .arg(&input)
+        .arg(&output)
+        .env("STUB_FFMPEG_SLEEP", "30")
+        .spawn()
+        .unwrap();
+
+    // Wait for the encode to start writing its partial output.
+    let tmp = sb.tmp();
+    let started = wait_until(Duration::from_secs(10), || {
+        entries(&tmp)
+            .first()
+            .is_some_and(|run| !entries(&tmp.join(run)).is_empty())
+    });
+    assert!(started, "encode never started");
+
+    let kill = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+
+    let mut status = None;
+    assert!(wait_until(Duration::from_secs(10), || {
+        status = child.try_wait().unwrap();
+        status.is_some()
+    }));
+    assert_eq!(status.unwrap().code(), Some(7));
+    assert!(entries(&sb.tmp()).is_empty());
+    assert!(!output.exists());
+}
+
+#[test]
+fn concurrent_runs_in_one_directory_keep_their_artifacts_apart() {
+    let sb = Sandbox::new();
+    let log = sb.work().join("ffmpeg.log");
+    let children: Vec<_> = ["a", "b"]
+        .iter()
+        .map(|name| {
+            let input = sb.input(&format!("{}.mp4", name));
+            sb.command()
+                .current_dir(sb.work())
+                .arg(&input)
+                .arg(sb.work().join(format!("{}-small.mp4", name)))
+                // An average bitrate, which names a pass log.
+                .args(["--mode", "abr"])
+                .env(
+                    "STUB_OUTPUT_BYTES",
+                    if *name == "a" { "1000" } else { "2000" },
+                )
+                .env("STUB_FFMPEG_SLEEP", "0.3")
+                .env("STUB_FFMPEG_LOG", &log)
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    // Each output is the one its own encode produced.
+    assert_eq!(
+        std::fs::metadata(sb.work().join("a-small.mp4"))
+            .unwrap()
+            .len(),
+        1000
+    );
+    assert_eq!(
+        std::fs::metadata(sb.work().join("b-small.mp4"))
+            .unwrap()
+            .len(),
+        2000
+    );
+    let logged = std::fs::read_to_string(&log).unwrap();
+    let passlogs: Vec<&str> = logged
+        .lines()
+        .filter_map(|line| line.split(" -passlogfile ").nth(1))
+        .map(|rest| rest.split(' ').next().unwrap())
+        .collect();
+    assert_eq!(passlogs.len(), 2);
+    assert_ne!(passlogs[0], passlogs[1]);
+    assert!(passlogs
+        .iter()
+        .all(|p| p.starts_with(&*sb.tmp().to_string_lossy())));
+    // Nothing intermediate lands next to the outputs.
+    assert_eq!(
+        entries(&sb.work()),
+        ["a-small.mp4", "a.mp4", "b-small.mp4", "b.mp4", "ffmpeg.log"]
+    );
+    assert!(entries(&sb.tmp()).is_empty());
+}
@@ -0,0 +1,179 @@
+//! HTTP inputs, read from a local server.
+#![cfg(unix)]
+
+mod common;
+
+use common::{entries, Sandbox};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The "video" the server hands out.
+const FIXTURE: &[u8] = b"not really a video";
+
+/// A server answering `GET /clip.mp4` (with any query) and 404 otherwise,
+/// recording the target of every request.
+struct Server {
+    base: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl Server {
+    fn start() -> Self {
+        Self::with_delay(None)
+    }
+
+    /// A server that accepts connections but only answers after `delay`.
+    fn with_delay(delay: Option<Duration>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let seen = Arc::clone(&seen);
+                std::thread::spawn(move || answer(stream, &seen, delay));
+            }
+        });
+        Self { base, requests }
+    }
+
+    fn url(&self, target: &str) -> String {
+        format!("{}{}", self.base, target)
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn answer(mut stream: TcpStream, seen: &Mutex<Vec<String>>, delay: Option<Duration>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+        header.clear();
+    }
+    let target = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+    seen.lock().unwrap().push(target.clone());
+    if let Some(delay) = delay {
+        std::thread::sleep(delay);
+    }
+    let response = if target.starts_with("/clip.mp4") {
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            FIXTURE.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(FIXTURE);
+        response
+    } else {
+        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+    };
+    let _ = stream.write_all(&response);
+}
+
+#[test]
+fn probes_and_encodes_straight_from_the_url() {
+    let sb = Sandbox::new();
+    let server = Server::start();
+    let url = server.url("/clip.mp4?token=s3cret");
+    let log = sb.work().join("ffmpeg.log");
+    let out = sb
+        .command()
+        .arg(&url)
+        .arg(sb.work().join("out.mp4"))
+        .args(["--size", "50"])
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(0), "{}", stderr);
+    assert_eq!(
+        std::fs::metadata(sb.work().join("out.mp4")).unwrap().len(),
+        1000
+    );
+    // Four probes (duration, video, audio, data) and the encode itself.
+    let requests = server.requests();
+    assert_eq!(requests.len(), 5, "{:?}", requests);
+    assert!(requests.iter().all(|r| r == "/clip.mp4?token=s3cret"));
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.contains(&format!("-i {} ", url)), "{}", logged);
+}
+
+#[test]
+fn failed_url_is_reported_without_its_credentials() {
+    let sb = Sandbox::new();
+    let server = Server::start();
+    let out = sb
+        .command()
+        .arg(server.url("/missing.mp4?token=s3cret"))
+        .arg(sb.work().join("out.mp4"))
+        .output()
+        .unwrap();
+
+    assert_eq!(out.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("/missing.mp4?token=***"), "{}", stderr);
+    assert!(!stderr.contains("s3cret"), "{}", stderr);
+    assert!(!String::from_utf8_lossy(&out.stdout).contains("s3cret"));
+}
+
+#[test]
+fn download_first_reads_the_url_once() {
+    let sb = Sandbox::new();
+    let server = Server::start();
+    let log = sb.work().join("ffmpeg.log");
+    let out = sb
+        .command()
+        .arg(server.url("/clip.mp4?token=s3cret"))
+        .arg(sb.work().join("out.mp4"))
+        .arg("--download-first")
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert_eq!(
+        out.status.code(),
+        Some(0),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(stdout.contains("Downloading http://"), "{}", stdout);
+    assert!(!stdout.contains("s3cret"), "{}", stdout);
+    assert_eq!(server.requests().len(), 1);
+    let logged = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = logged.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", logged);
+    assert!(lines[0].contains("-c copy"), "{}", lines[0]);
+    assert!(lines[1].contains("download-"), "{}", lines[1]);
+    assert!(entries(&sb.tmp()).is_empty());
+}
+
+#[test]
+fn stalled_server_hits_the_timeout() {
+    let sb = Sandbox::new();
+    let server = Server::with_delay(Some(Duration::from_secs(30)));
+    let started = Instant::now();
+    let out = sb
+        .command()
+        .arg(server.url("/clip.mp4"))
+        .arg(sb.work().join("out.mp4"))
+        .args(["--timeout", "1"])
+        .output()
+        .unwrap();
+
+    assert_eq!(out.status.code(), Some(8));
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("timed out after 1 seconds"));
+}
@@ -0,0 +1,99 @@
+//! An MP4's own headers standing in for an ffprobe that can't be run.
+#![cfg(unix)]
+
+mod common;
+
+use common::mp4::{boxed, header, trak};
+use common::Sandbox;
+use std::path::{Path, PathBuf};
+
+/// An ffprobe whose shared libraries are missing.
+const BROKEN_FFPROBE: &str = "#!/bin/sh
+echo 'ffprobe: error while loading shared libraries: libavdevice.so.60' >&2
+exit 127
+";
+
+/// Writes a 40-second MP4 with a video and an audio track.
+fn mp4(dir: &Path, name: &str, fragmented: bool) -> PathBuf {
+    let mut moov = [
+        boxed(b"mvhd", &header(1000, 40_000)),
+        trak(b"vide", &header(90_000, 3_600_000)),
+        trak(b"soun", &header(48_000, 1_920_000)),
+    ]
+    .concat();
+    if fragmented {
+        moov.extend(boxed(b"mvex", &[]));
+    }
+    let data = [
+        boxed(b"ftyp", b"isom"),
+        boxed(b"moov", &moov),
+        boxed(b"mdat", &[0; 1024]),
+    ]
+    .concat();
+    let path = dir.join(name);
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[test]
+fn an_mp4_is_reduced_from_its_headers() {
+    let sb = Sandbox::new();
+    sb.write_script("ffprobe", BROKEN_FFPROBE);
+    let input = mp4(&sb.work(), "clip.mp4", false);
+    let log = sb.work().join("ffmpeg.log");
+    let result = sb
+        .command()
+        .arg(&input)
+        .arg(sb.work().join("out.mp4"))
+        .args(["--size", "10", "--output-format", "json"])
+        .env("STUB_FFMPEG_LOG", &log)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(result.status.success(), "{}", stderr);
+    assert!(
+        stderr.contains("Video duration: 40.00 seconds"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("it is taken to be 1920x1080 at 30 fps, with stereo audio"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stdout.contains(r#""code":"probed_without_ffprobe""#),
+        "{}",
+        stdout
+    );
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(logged.lines().count(), 1, "{}", logged);
+}
+
+#[test]
+fn other_files_still_need_ffprobe() {
+    let sb = Sandbox::new();
+    sb.write_script("ffprobe", BROKEN_FFPROBE);
+    // The same bytes, in a file that doesn't claim to be an MP4.
+    let mkv = mp4(&sb.work(), "clip.mkv", false);
+    let fragmented = mp4(&sb.work(), "live.mp4", true);
+    for (input, printed) in [(mkv, ""), (fragmented, "it is a fragmented MP4")] {
+        let result = sb
+            .command()
+            .arg(&input)
+            .arg(sb.work().join("out.mp4"))
+            .arg("--verbose")
+            .output()
+            .unwrap();
+        let printed_all = format!(
+            "{}{}",
+            String::from_utf8_lossy(&result.stdout),
+            String::from_utf8_lossy(&result.stderr)
+        );
+        assert_eq!(result.status.code(), Some(3), "{}", printed_all);
+        assert!(printed_all.contains("ffprobe not found"), "{}", printed_all);
+        assert!(printed_all.contains(printed), "{}", printed_all);
+        assert!(!printed_all.contains("probed_without_ffprobe"));
+    }
+}